@@ -6,6 +6,10 @@ REPO_NAME="${REPO_NAME:-constitute-nvr}"
 INSTALL_PREFIX="${INSTALL_PREFIX:-/opt/constitute-nvr}"
 SERVICE_NAME="${SERVICE_NAME:-constitute-nvr}"
 TRY_RESTART=0
+# Set by the service's in-process poller: install now, restart later with --restart-only.
+DEFER_RESTART="${DEFER_RESTART:-0}"
+RESTART_ONLY=0
+RESTART_NEEDED_EXIT=10
 FORCE=0
 MODE="${MODE:-release_artifact}"
 BUILD_USER="${BUILD_USER:-}"
@@ -35,6 +39,9 @@ Options:
   --mode <name>              Update mode hint (default: release_artifact)
   --build-user <name>        Compatibility no-op
   --try-restart              Restart service when binary changed
+  --defer-restart            With --try-restart: keep the previous binary and exit 10
+                             instead of restarting (env DEFER_RESTART=1 does the same)
+  --restart-only             Restart now, rolling back to the kept binary on failure
   --force                    Reinstall even if binary hash is unchanged
   --proxy-url <url>          Use HTTP(S) proxy for release fetches
   --tor                      Use Tor SOCKS egress for release fetches
@@ -143,6 +150,14 @@ while [[ $# -gt 0 ]]; do
       TRY_RESTART=1
       shift
       ;;
+    --defer-restart)
+      DEFER_RESTART=1
+      shift
+      ;;
+    --restart-only)
+      RESTART_ONLY=1
+      shift
+      ;;
     --force)
       FORCE=1
       shift
@@ -192,6 +207,34 @@ while [[ $# -gt 0 ]]; do
   esac
 done
 
+installed_bin="${INSTALL_PREFIX}/bin/constitute-nvr"
+kept_bin="${installed_bin}.prev"
+
+restart_or_rollback() {
+  local rollback_bin="$1"
+  if ! run_sudo systemctl restart "$SERVICE_NAME"; then
+    if [[ -f "$rollback_bin" ]]; then
+      echo "[self-update] restart failed; rolling back binary" >&2
+      run_sudo install -m 0755 "$rollback_bin" "$installed_bin" || true
+      run_sudo systemctl restart "$SERVICE_NAME" || true
+    fi
+    exit 1
+  fi
+  if ! run_sudo systemctl is-active --quiet "$SERVICE_NAME"; then
+    if [[ -f "$rollback_bin" ]]; then
+      echo "[self-update] service unhealthy after update; rolling back binary" >&2
+      run_sudo install -m 0755 "$rollback_bin" "$installed_bin" || true
+      run_sudo systemctl restart "$SERVICE_NAME" || true
+    fi
+    exit 1
+  fi
+}
+
+if [[ "$RESTART_ONLY" -eq 1 ]]; then
+  restart_or_rollback "$kept_bin"
+  exit 0
+fi
+
 if [[ -n "$MODE" && "$MODE" != "release_artifact" ]]; then
   echo "[self-update] mode=${MODE}; release updater is a no-op"
   exit 0
@@ -236,7 +279,6 @@ if [[ ! -f "$incoming_bin" ]]; then
   exit 1
 fi
 
-installed_bin="${INSTALL_PREFIX}/bin/constitute-nvr"
 needs_install=1
 if [[ "$FORCE" -eq 0 && -f "$installed_bin" ]]; then
   current_hash="$(sha256sum "$installed_bin" | awk '{print $1}')"
@@ -262,22 +304,14 @@ if [[ "$needs_install" -eq 1 ]]; then
   fi
 
   echo "[self-update] installed latest release"
-  if [[ "$TRY_RESTART" -eq 1 ]]; then
-    if ! run_sudo systemctl restart "$SERVICE_NAME"; then
-      if [[ -f "$rollback_bin" ]]; then
-        echo "[self-update] restart failed; rolling back binary" >&2
-        run_sudo install -m 0755 "$rollback_bin" "$installed_bin" || true
-        run_sudo systemctl restart "$SERVICE_NAME" || true
-      fi
-      exit 1
-    fi
-    if ! run_sudo systemctl is-active --quiet "$SERVICE_NAME"; then
-      if [[ -f "$rollback_bin" ]]; then
-        echo "[self-update] service unhealthy after update; rolling back binary" >&2
-        run_sudo install -m 0755 "$rollback_bin" "$installed_bin" || true
-        run_sudo systemctl restart "$SERVICE_NAME" || true
-      fi
-      exit 1
+  if [[ "$TRY_RESTART" -eq 1 && "$DEFER_RESTART" -eq 1 ]]; then
+    if [[ -f "$rollback_bin" ]]; then
+      run_sudo install -m 0755 "$rollback_bin" "$kept_bin"
     fi
+    echo "[self-update] restart deferred to the service"
+    exit "$RESTART_NEEDED_EXIT"
+  fi
+  if [[ "$TRY_RESTART" -eq 1 ]]; then
+    restart_or_rollback "$rollback_bin"
   fi
 fi
@@ -0,0 +1,408 @@
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::{crypto, util};
+
+const TOKEN_PREFIX: &str = "cnt1";
+pub const MAX_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+const LEDGER_FILE: &str = "access_tokens.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenOp {
+    Download,
+    Snapshot,
+    Live,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TokenScope {
+    Segment {
+        #[serde(rename = "sourceId")]
+        source_id: String,
+        name: String,
+    },
+    Source {
+        #[serde(rename = "sourceId")]
+        source_id: String,
+    },
+    Zone {
+        zone: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenClaims {
+    pub tid: String,
+    pub scope: TokenScope,
+    pub ops: Vec<TokenOp>,
+    pub iat: u64,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+}
+
+impl TokenClaims {
+    pub fn new(
+        scope: TokenScope,
+        ops: Vec<TokenOp>,
+        ttl_secs: u64,
+        max_bytes: Option<u64>,
+    ) -> Self {
+        let mut tid = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut tid);
+        let iat = util::now_unix_seconds();
+        Self {
+            tid: hex::encode(tid),
+            scope,
+            ops,
+            iat,
+            exp: iat.saturating_add(ttl_secs.min(MAX_TTL_SECS)),
+            max_bytes,
+        }
+    }
+
+    pub fn allows(&self, op: TokenOp) -> bool {
+        self.ops.contains(&op)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MintRequest {
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub zone: Option<String>,
+    pub ops: Vec<TokenOp>,
+    pub ttl_secs: u64,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+impl MintRequest {
+    pub fn scope(&self) -> Option<TokenScope> {
+        match (self.source_id.clone(), self.name.clone(), self.zone.clone()) {
+            (Some(source_id), Some(name), None) => Some(TokenScope::Segment { source_id, name }),
+            (Some(source_id), None, None) => Some(TokenScope::Source { source_id }),
+            (None, None, Some(zone)) => Some(TokenScope::Zone { zone }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    Malformed,
+    Signature,
+    Expired,
+    Revoked,
+    Exhausted,
+}
+
+impl Refusal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Malformed => "token_malformed",
+            Self::Signature => "token_signature",
+            Self::Expired => "token_expired",
+            Self::Revoked => "token_revoked",
+            Self::Exhausted => "token_bytes",
+        }
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::Malformed => "token is malformed",
+            Self::Signature => "token signature is invalid",
+            Self::Expired => "token has expired",
+            Self::Revoked => "token was revoked",
+            Self::Exhausted => "token byte budget is used up",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+pub fn sign(server_secret_hex: &str, claims: &TokenClaims) -> Result<String> {
+    let payload =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signed = format!("{TOKEN_PREFIX}.{payload}");
+    let tag = token_mac(server_secret_hex, &signed)?
+        .finalize()
+        .into_bytes();
+    Ok(format!(
+        "{signed}.{}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(tag)
+    ))
+}
+
+pub fn verify(server_secret_hex: &str, token: &str) -> Result<TokenClaims, Refusal> {
+    let (signed, tag) = token.trim().rsplit_once('.').ok_or(Refusal::Malformed)?;
+    let payload = signed
+        .strip_prefix(TOKEN_PREFIX)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .ok_or(Refusal::Malformed)?;
+    let tag = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(tag)
+        .map_err(|_| Refusal::Malformed)?;
+    let mac = token_mac(server_secret_hex, signed).map_err(|_| Refusal::Signature)?;
+    // Compared in constant time, so timing does not reveal how much of a forgery matched.
+    mac.verify_slice(&tag).map_err(|_| Refusal::Signature)?;
+    let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| Refusal::Malformed)?;
+    serde_json::from_slice(&claims).map_err(|_| Refusal::Malformed)
+}
+
+pub fn session_secret_hex(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+fn token_mac(server_secret_hex: &str, signed: &str) -> Result<Hmac<Sha256>> {
+    let key = crypto::parse_hex_exact(server_secret_hex, 32)?;
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).map_err(|_| anyhow!("hmac key"))?;
+    mac.update(signed.as_bytes());
+    Ok(mac)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LedgerEntry {
+    #[serde(default)]
+    revoked: bool,
+    #[serde(default)]
+    bytes_served: u64,
+    forget_unix: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct AccessTokens {
+    path: Option<PathBuf>,
+    entries: Arc<Mutex<BTreeMap<String, LedgerEntry>>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenStatus {
+    pub revoked: bool,
+    pub bytes_served: u64,
+}
+
+impl AccessTokens {
+    pub fn load(storage_root: &Path) -> Self {
+        let path = storage_root.join(LEDGER_FILE);
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            path: Some(path),
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+
+    pub fn check(&self, claims: &TokenClaims) -> Result<(), Refusal> {
+        if claims.exp <= util::now_unix_seconds() {
+            return Err(Refusal::Expired);
+        }
+        let entries = self.lock();
+        let Some(entry) = entries.get(&claims.tid) else {
+            return Ok(());
+        };
+        if entry.revoked {
+            return Err(Refusal::Revoked);
+        }
+        match claims.max_bytes {
+            Some(max) if entry.bytes_served >= max => Err(Refusal::Exhausted),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn charge(&self, claims: &TokenClaims, bytes: u64) -> Result<(), Refusal> {
+        self.check(claims)?;
+        let Some(max) = claims.max_bytes else {
+            return Ok(());
+        };
+        let mut entries = self.lock();
+        let entry = entries
+            .entry(claims.tid.clone())
+            .or_insert_with(|| LedgerEntry {
+                forget_unix: claims.exp,
+                ..LedgerEntry::default()
+            });
+        let total = entry.bytes_served.saturating_add(bytes);
+        if total > max {
+            return Err(Refusal::Exhausted);
+        }
+        entry.bytes_served = total;
+        self.save(&mut entries);
+        Ok(())
+    }
+
+    pub fn revoke(&self, tid: &str) -> bool {
+        let mut entries = self.lock();
+        let entry = entries.entry(tid.to_string()).or_default();
+        if entry.revoked {
+            return false;
+        }
+        entry.revoked = true;
+        entry.forget_unix = util::now_unix_seconds().saturating_add(MAX_TTL_SECS);
+        self.save(&mut entries);
+        true
+    }
+
+    pub fn status(&self, tid: &str) -> TokenStatus {
+        self.lock()
+            .get(tid)
+            .map(|entry| TokenStatus {
+                revoked: entry.revoked,
+                bytes_served: entry.bytes_served,
+            })
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, LedgerEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn save(&self, entries: &mut BTreeMap<String, LedgerEntry>) {
+        let now = util::now_unix_seconds();
+        entries.retain(|_, entry| entry.forget_unix > now);
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Err(err) = write_ledger(path, entries) {
+            warn!(error = %err, "access token ledger not saved");
+        }
+    }
+}
+
+fn write_ledger(path: &Path, entries: &BTreeMap<String, LedgerEntry>) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)
+        .with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::clock::TestClock;
+    use std::time::Duration;
+
+    const SECRET: &str = "11111111111111111111111111111111111111111111111111111111111111aa";
+
+    fn claims(max_bytes: Option<u64>) -> TokenClaims {
+        TokenClaims::new(
+            TokenScope::Source {
+                source_id: "front".to_string(),
+            },
+            vec![TokenOp::Download],
+            3600,
+            max_bytes,
+        )
+    }
+
+    #[test]
+    fn tokens_verify_only_unaltered_under_the_signing_secret() {
+        let claims = claims(None);
+        let token = sign(SECRET, &claims).unwrap();
+        assert_eq!(verify(SECRET, &token), Ok(claims.clone()));
+
+        let other = "22".repeat(32);
+        assert_eq!(verify(&other, &token), Err(Refusal::Signature));
+        let (signed, tag) = token.rsplit_once('.').unwrap();
+        let payload = signed.strip_prefix("cnt1.").unwrap();
+        let mut widened = claims.clone();
+        widened.scope = TokenScope::Zone {
+            zone: "all".to_string(),
+        };
+        let forged_payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&widened).unwrap());
+        assert_ne!(payload, forged_payload);
+        assert_eq!(
+            verify(SECRET, &format!("cnt1.{forged_payload}.{tag}")),
+            Err(Refusal::Signature)
+        );
+        assert_eq!(verify(SECRET, "cnt1.only-two"), Err(Refusal::Malformed));
+        assert_eq!(
+            verify(SECRET, &token.replacen("cnt1", "cnt2", 1)),
+            Err(Refusal::Malformed)
+        );
+        assert_ne!(session_secret_hex(&token), session_secret_hex(&other));
+    }
+
+    #[test]
+    fn ledger_enforces_expiry_revocation_and_byte_budgets_across_restarts() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-access-token-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+        let tokens = AccessTokens::load(&root);
+
+        let mut expired = claims(None);
+        expired.exp = expired.iat.saturating_sub(1);
+        assert_eq!(tokens.check(&expired), Err(Refusal::Expired));
+
+        let budgeted = claims(Some(100));
+        assert_eq!(tokens.charge(&budgeted, 60), Ok(()));
+        assert_eq!(tokens.charge(&budgeted, 60), Err(Refusal::Exhausted));
+        assert_eq!(tokens.charge(&budgeted, 40), Ok(()));
+        assert_eq!(tokens.check(&budgeted), Err(Refusal::Exhausted));
+
+        let open = claims(None);
+        assert_eq!(tokens.charge(&open, u64::MAX), Ok(()));
+        assert!(tokens.revoke(&open.tid));
+        assert!(!tokens.revoke(&open.tid));
+
+        let reloaded = AccessTokens::load(&root);
+        assert_eq!(reloaded.check(&open), Err(Refusal::Revoked));
+        assert_eq!(
+            reloaded.status(&budgeted.tid),
+            TokenStatus {
+                revoked: false,
+                bytes_served: 100,
+            }
+        );
+        assert_eq!(reloaded.status("unknown"), TokenStatus::default());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn expiry_and_forgotten_revocations_follow_the_clock() {
+        let clock = TestClock::at_secs(1_700_000_000);
+        let tokens = AccessTokens::default();
+        let fresh = claims(None);
+        clock.advance(Duration::from_secs(3599));
+        assert_eq!(tokens.check(&fresh), Ok(()));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(tokens.check(&fresh), Err(Refusal::Expired));
+
+        // A revocation is kept until every token that could carry the id has expired.
+        let revoked = claims(None);
+        assert!(tokens.revoke(&revoked.tid));
+        clock.advance(Duration::from_secs(MAX_TTL_SECS));
+        assert!(tokens.revoke(&fresh.tid));
+        assert!(!tokens.status(&revoked.tid).revoked);
+        assert!(tokens.status(&fresh.tid).revoked);
+    }
+}
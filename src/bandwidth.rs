@@ -0,0 +1,227 @@
+use crate::storage::{IoClass, IoPriority};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant, sleep};
+
+const THROUGHPUT_WINDOW_SECS: u64 = 10;
+const MAX_CONCURRENT_REMUXES: usize = 2;
+
+pub(crate) struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub(crate) fn set_rate(&mut self, rate: u64) {
+        self.refill(Instant::now());
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as f64);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled_at = now;
+    }
+
+    pub(crate) fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill(now);
+        // Overdraw is allowed: the debt becomes the caller's wait, so large chunks are delayed
+        // rather than rejected.
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(rate))),
+        }
+    }
+
+    pub async fn rate(&self) -> u64 {
+        self.bucket.lock().await.rate
+    }
+
+    pub async fn set_rate(&self, rate: u64) {
+        self.bucket.lock().await.set_rate(rate);
+    }
+
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = self.bucket.lock().await.take(bytes, Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ThroughputMeter {
+    total: Arc<AtomicU64>,
+    recent: Arc<Mutex<VecDeque<(u64, u64)>>>,
+}
+
+impl ThroughputMeter {
+    pub async fn record(&self, bytes: u64) {
+        self.total.fetch_add(bytes, Ordering::Relaxed);
+        let now = monotonic_secs();
+        let mut recent = self.recent.lock().await;
+        match recent.back_mut() {
+            Some((second, sent)) if *second == now => *sent += bytes,
+            _ => recent.push_back((now, bytes)),
+        }
+        while recent
+            .front()
+            .is_some_and(|(second, _)| now.saturating_sub(*second) >= THROUGHPUT_WINDOW_SECS)
+        {
+            recent.pop_front();
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub async fn bytes_per_sec(&self) -> u64 {
+        let now = monotonic_secs();
+        let recent = self.recent.lock().await;
+        let sent = recent
+            .iter()
+            .filter(|(second, _)| now.saturating_sub(*second) < THROUGHPUT_WINDOW_SECS)
+            .map(|(_, sent)| *sent)
+            .sum::<u64>();
+        sent / THROUGHPUT_WINDOW_SECS
+    }
+}
+
+fn monotonic_secs() -> u64 {
+    static START: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs()
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressView {
+    pub max_bytes_per_sec: u64,
+    pub bytes_per_sec: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Clone)]
+pub struct ConsumerShaper {
+    pub limiter: RateLimiter,
+    pub meter: ThroughputMeter,
+}
+
+impl ConsumerShaper {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            limiter: RateLimiter::new(rate),
+            meter: ThroughputMeter::default(),
+        }
+    }
+
+    pub async fn view(&self) -> EgressView {
+        EgressView {
+            max_bytes_per_sec: self.limiter.rate().await,
+            bytes_per_sec: self.meter.bytes_per_sec().await,
+            total_bytes: self.meter.total_bytes(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EgressShaper {
+    global: ConsumerShaper,
+    remuxes: Arc<Semaphore>,
+    io: IoPriority,
+}
+
+impl EgressShaper {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            global: ConsumerShaper::new(rate),
+            remuxes: Arc::new(Semaphore::new(MAX_CONCURRENT_REMUXES)),
+            io: IoPriority::default(),
+        }
+    }
+
+    pub fn with_io_priority(mut self, io: IoPriority) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub async fn remux_slot(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.remuxes)
+            .acquire_owned()
+            .await
+            .expect("remux semaphore is never closed")
+    }
+
+    pub async fn set_rate(&self, rate: u64) {
+        self.global.limiter.set_rate(rate).await;
+    }
+
+    pub async fn throttle(&self, consumer: &ConsumerShaper, bytes: usize) {
+        let bytes = bytes as u64;
+        consumer.limiter.acquire(bytes).await;
+        self.global.limiter.acquire(bytes).await;
+        self.io.acquire(IoClass::Serving, bytes).await;
+        consumer.meter.record(bytes).await;
+        self.global.meter.record(bytes).await;
+    }
+
+    pub async fn view(&self) -> EgressView {
+        self.global.view().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_turns_overdraft_into_wait() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000);
+        assert_eq!(bucket.take(1000, start), Duration::ZERO);
+        assert_eq!(bucket.take(500, start), Duration::from_millis(500));
+        // Half a second later the debt is repaid and the next chunk waits its own share.
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(250, later), Duration::from_millis(250));
+
+        bucket.set_rate(0);
+        assert_eq!(bucket.take(1_000_000, later), Duration::ZERO);
+    }
+}
@@ -0,0 +1,228 @@
+use super::protocol::onvif;
+use crate::config::{CameraDeviceConfig, Config};
+use crate::util;
+use constitute_protocol::{LogCategory, LogOutcome, LogSeverity, LogSubjectRef};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraClockStatus {
+    pub source_id: String,
+    pub status: String,
+    pub offset_secs: Option<i64>,
+    pub threshold_secs: u64,
+    pub camera_utc: String,
+    pub camera_timezone: String,
+    pub daylight_savings: bool,
+    pub time_mode: String,
+    pub reason: String,
+    pub checked_at: u64,
+}
+
+impl CameraClockStatus {
+    fn exceeds_threshold(&self) -> bool {
+        matches!(self.status.as_str(), "drift" | "corrected")
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CameraClockMonitor {
+    inner: Arc<Mutex<HashMap<String, CameraClockStatus>>>,
+}
+
+impl CameraClockMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn list(&self) -> Vec<CameraClockStatus> {
+        let mut out = self
+            .inner
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        out.sort_by(|left, right| left.source_id.cmp(&right.source_id));
+        out
+    }
+
+    pub async fn check_all(&self, cfg: &Config) {
+        self.inner.lock().await.retain(|source_id, _| {
+            cfg.camera_devices
+                .iter()
+                .any(|camera| &camera.source_id == source_id)
+        });
+        let onvif_cameras = cfg
+            .camera_devices
+            .iter()
+            .filter(|camera| camera.enabled && camera.has_onvif());
+        for camera in onvif_cameras {
+            self.check_camera(cfg, camera).await;
+        }
+    }
+
+    pub async fn check_camera(
+        &self,
+        cfg: &Config,
+        camera: &CameraDeviceConfig,
+    ) -> CameraClockStatus {
+        let status = check_camera_clock(camera, cfg.camera_network.time_drift_threshold_secs).await;
+        let previous = self
+            .inner
+            .lock()
+            .await
+            .insert(camera.source_id.clone(), status.clone());
+        let newly_exceeded = status.exceeds_threshold()
+            && !previous.is_some_and(|previous| previous.exceeds_threshold());
+        if newly_exceeded || status.status == "corrected" {
+            report_clock_drift(camera, &status).await;
+        }
+        status
+    }
+}
+
+pub async fn check_camera_clock(
+    camera: &CameraDeviceConfig,
+    threshold_secs: u64,
+) -> CameraClockStatus {
+    let mut status = CameraClockStatus {
+        source_id: camera.source_id.clone(),
+        status: "unknown".to_string(),
+        threshold_secs,
+        checked_at: util::now_ms(),
+        ..CameraClockStatus::default()
+    };
+    if camera.onvif_host.trim().is_empty() {
+        status.reason = "no ONVIF host configured".to_string();
+        return status;
+    }
+    if camera.username.trim().is_empty() {
+        status.reason = "no camera credentials configured".to_string();
+        return status;
+    }
+
+    let clock = match onvif::read_system_clock(
+        &camera.onvif_host,
+        camera.onvif_port.max(1),
+        &camera.username,
+        &camera.password,
+    )
+    .await
+    {
+        Ok(clock) => clock,
+        Err(err) => {
+            status.reason = format!("{err:#}");
+            return status;
+        }
+    };
+    status.camera_utc = clock.utc_date_time.clone();
+    status.camera_timezone = clock.timezone_raw.clone();
+    status.daylight_savings = clock.daylight_savings;
+    status.time_mode = clock.time_mode.clone();
+    let Some(camera_unix) = clock.utc_unix else {
+        status.reason = "camera did not report UTCDateTime".to_string();
+        return status;
+    };
+
+    let now = util::now_unix_seconds();
+    let offset = camera_unix - now as i64;
+    status.offset_secs = Some(offset);
+    if offset.unsigned_abs() <= threshold_secs {
+        status.status = "ok".to_string();
+        return status;
+    }
+
+    status.status = "drift".to_string();
+    if camera.set_camera_time {
+        match onvif::set_system_clock_utc(
+            &camera.onvif_host,
+            camera.onvif_port.max(1),
+            &camera.username,
+            &camera.password,
+            &clock,
+            util::now_unix_seconds(),
+        )
+        .await
+        {
+            Ok(()) => status.status = "corrected".to_string(),
+            Err(err) => status.reason = format!("{err:#}"),
+        }
+    }
+    status
+}
+
+async fn report_clock_drift(camera: &CameraDeviceConfig, status: &CameraClockStatus) {
+    warn!(
+        source = %camera.source_id,
+        offset_secs = ?status.offset_secs,
+        threshold_secs = status.threshold_secs,
+        corrected = status.status == "corrected",
+        "camera clock drift exceeds threshold"
+    );
+    crate::logging_surface::submit_safe_event(
+        "camera",
+        LogCategory::ServiceAccess,
+        LogSeverity::Info,
+        LogOutcome::Observed,
+        LogSubjectRef {
+            kind: "camera".to_string(),
+            id: Some(camera.source_id.clone()),
+            display: Some(camera.name.clone()),
+        },
+        &["nvr", "camera_time", "drift"],
+        json!({
+            "sourceId": camera.source_id,
+            "offsetSecs": status.offset_secs,
+            "thresholdSecs": status.threshold_secs,
+            "timeMode": status.time_mode,
+            "corrected": status.status == "corrected",
+            "error": status.reason,
+        }),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cameras_without_credentials_report_unknown() {
+        let camera = CameraDeviceConfig {
+            source_id: "cam-a".to_string(),
+            name: "Cam A".to_string(),
+            onvif_host: "192.0.2.10".to_string(),
+            onvif_port: 80,
+            rtsp_url: "rtsp://192.0.2.10/stream".to_string(),
+            username: String::new(),
+            password: String::new(),
+            driver_id: String::new(),
+            vendor: String::new(),
+            model: String::new(),
+            mac_address: String::new(),
+            rtsp_port: 554,
+            ptz_capable: false,
+            enabled: true,
+            segment_secs: 10,
+            desired: Default::default(),
+            credentials: Default::default(),
+            privacy: false,
+            set_camera_time: true,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
+        };
+        let status = check_camera_clock(&camera, 5).await;
+        assert_eq!(status.status, "unknown");
+        assert!(status.offset_secs.is_none());
+        assert!(!status.reason.is_empty());
+    }
+}
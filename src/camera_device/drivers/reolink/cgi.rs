@@ -586,6 +586,8 @@ impl ReolinkHttpSession {
             .body(encrypted_body)
             .send()
             .await
+            // The request URL carries the session token.
+            .map_err(reqwest::Error::without_url)
             .with_context(|| format!("failed sending Reolink CGI command {cmd}"))?;
 
         if !response.status().is_success() {
@@ -27,7 +27,12 @@ pub async fn read_mounted_camera_device(
     camera: &CameraDeviceConfig,
 ) -> MountedCamera {
     let base_capabilities = driver_capabilities(camera);
-    match read_observed_state(camera).await {
+    let observed = if camera.has_onvif() {
+        read_observed_state(camera).await
+    } else {
+        Ok(stream_only_observed_state(camera))
+    };
+    match observed {
         Ok(observed) => {
             let capabilities =
                 capabilities_for_observed(camera, base_capabilities.clone(), &observed);
@@ -0,0 +1,142 @@
+use super::*;
+use zeroize::Zeroizing;
+
+const VERIFY_ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraMaintenanceRequest {
+    pub source_id: String,
+    #[serde(default)]
+    pub brightness: Option<f32>,
+    #[serde(default)]
+    pub contrast: Option<f32>,
+    #[serde(default)]
+    pub ir_cut_mode: Option<String>,
+}
+
+pub async fn reboot_camera(cfg: &Config, source_id: &str) -> Result<Value> {
+    let camera = maintenance_camera(cfg, source_id)?;
+    let rebooted = onvif::system_reboot(
+        &camera.onvif_host,
+        camera.onvif_port.max(1),
+        &camera.username,
+        &camera.password,
+    )
+    .await?;
+    Ok(match rebooted {
+        Some(message) => json!({
+            "sourceId": camera.source_id,
+            "status": "rebooting",
+            "message": message,
+        }),
+        None => unsupported(&camera.source_id),
+    })
+}
+
+pub async fn get_imaging_settings(cfg: &Config, source_id: &str) -> Result<Value> {
+    let camera = maintenance_camera(cfg, source_id)?;
+    let settings = onvif::read_imaging_settings(
+        &camera.onvif_host,
+        camera.onvif_port.max(1),
+        &camera.username,
+        &camera.password,
+    )
+    .await?;
+    Ok(imaging_response(&camera.source_id, settings))
+}
+
+pub async fn set_imaging_settings(
+    cfg: &Config,
+    request: &CameraMaintenanceRequest,
+) -> Result<Value> {
+    let camera = maintenance_camera(cfg, &request.source_id)?;
+    let settings = onvif::set_imaging_settings(
+        &camera.onvif_host,
+        camera.onvif_port.max(1),
+        &camera.username,
+        &camera.password,
+        &onvif::OnvifImagingUpdate {
+            brightness: request.brightness,
+            contrast: request.contrast,
+            ir_cut_mode: request.ir_cut_mode.clone(),
+        },
+    )
+    .await?;
+    Ok(imaging_response(&camera.source_id, settings))
+}
+
+pub async fn set_camera_password(
+    camera: &CameraDeviceConfig,
+    current: &str,
+    next: &str,
+) -> Result<bool> {
+    onvif::set_user_password(
+        &camera.onvif_host,
+        camera.onvif_port.max(1),
+        &camera.username,
+        current,
+        &camera.username,
+        next,
+    )
+    .await
+}
+
+pub async fn verify_camera_password(camera: &CameraDeviceConfig, password: &str) -> Result<()> {
+    let url = Zeroizing::new(camera.rtsp_input_url_with_password(&camera.rtsp_url, password));
+    let mut attempt = 1;
+    loop {
+        match ffprobe_rtsp_stream(&url).await {
+            Ok(_) => return Ok(()),
+            Err(_) if attempt < VERIFY_ATTEMPTS => {
+                attempt += 1;
+                sleep(Duration::from_secs(2)).await;
+            }
+            Err(err) => {
+                let mut message = Zeroizing::new(err.to_string());
+                if let Ok(parsed) = Url::parse(&url)
+                    && let Some(encoded) = parsed.password()
+                {
+                    *message = message.replace(encoded, "***");
+                }
+                if !password.trim().is_empty() {
+                    *message = message.replace(password.trim(), "***");
+                }
+                return Err(anyhow!(
+                    "stream did not open with the new password: {}",
+                    *message
+                ));
+            }
+        }
+    }
+}
+
+fn maintenance_camera<'a>(cfg: &'a Config, source_id: &str) -> Result<&'a CameraDeviceConfig> {
+    let camera = cfg
+        .camera_devices
+        .iter()
+        .find(|camera| camera.source_id.trim() == source_id.trim())
+        .ok_or_else(|| anyhow!("camera source is not configured"))?;
+    if camera.onvif_host.trim().is_empty() {
+        return Err(anyhow!("camera source has no ONVIF host"));
+    }
+    Ok(camera)
+}
+
+fn imaging_response(source_id: &str, settings: Option<onvif::OnvifImagingSettings>) -> Value {
+    match settings {
+        Some(settings) => json!({
+            "sourceId": source_id,
+            "status": "ok",
+            "settings": settings,
+        }),
+        None => unsupported(source_id),
+    }
+}
+
+fn unsupported(source_id: &str) -> Value {
+    json!({
+        "sourceId": source_id,
+        "status": "unsupported",
+    })
+}
@@ -1,15 +1,20 @@
 pub mod apply;
+pub mod clock;
 pub mod control;
 pub mod discovery;
 pub mod drivers;
+pub mod error;
 pub mod identification;
 pub mod inventory;
+pub mod maintenance;
 pub mod mount;
 pub mod protocol;
 pub mod reconcile;
 pub mod registry;
 pub mod types;
 
+pub use error::CameraError;
+
 use crate::config::{
     CameraDeviceConfig, CameraDeviceDesiredConfig, Config, adopt_camera_active_password,
     camera_device_credential_candidates as camera_credential_candidates,
@@ -1575,6 +1580,12 @@ fn match_candidate(candidate: &DiscoveredCameraCandidate) -> DriverMatch {
 }
 
 fn driver_capabilities(camera: &CameraDeviceConfig) -> CameraCapabilitySet {
+    if !camera.has_onvif() {
+        return CameraCapabilitySet {
+            live_view: camera.has_rtsp(),
+            ..Default::default()
+        };
+    }
     if camera.driver_id.trim() == DRIVER_ID_REOLINK {
         ReolinkDriver.capabilities(camera)
     } else if driver_is_xm(&camera.driver_id) {
@@ -1596,6 +1607,9 @@ fn capabilities_for_observed(
     mut capabilities: CameraCapabilitySet,
     observed: &ObservedCameraState,
 ) -> CameraCapabilitySet {
+    if !camera.has_onvif() {
+        return capabilities;
+    }
     if camera.driver_id.trim() == DRIVER_ID_REOLINK {
         capabilities = reolink_runtime_capabilities(capabilities, observed);
     } else if camera.driver_id.trim() == DRIVER_ID_GENERIC_ONVIF_RTSP {
@@ -2568,6 +2582,13 @@ async fn probe_reolink_candidate(
             segment_secs: 10,
             desired: CameraDeviceDesiredConfig::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let presentation = read_reolink_presentation_via_onvif_bridge(&temp_camera, &onvif_state)
             .await
@@ -2894,6 +2915,29 @@ async fn fallback_observed_state(camera: &CameraDeviceConfig) -> ObservedCameraS
     }
 }
 
+fn stream_only_observed_state(camera: &CameraDeviceConfig) -> ObservedCameraState {
+    ObservedCameraState {
+        display_name: camera_display_name(camera),
+        driver_id: camera.driver_id.clone(),
+        vendor: camera.vendor.clone(),
+        model: camera.model.clone(),
+        ip: camera.onvif_host.clone(),
+        mac_address: camera.mac_address.clone(),
+        time_mode: String::new(),
+        ntp_server: String::new(),
+        manual_time: String::new(),
+        timezone: String::new(),
+        overlay_text: String::new(),
+        overlay_timestamp: None,
+        ptz_capable: false,
+        current_pose: CameraPose::default(),
+        pose_status: String::new(),
+        pose_source: String::new(),
+        services: json!({ "managementPlane": "none" }),
+        raw: json!({ "managementPlane": "none" }),
+    }
+}
+
 fn verification_for_observed(
     cfg: &Config,
     camera: &CameraDeviceConfig,
@@ -3803,6 +3847,13 @@ mod tests {
             segment_secs: 10,
             desired: Default::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let base = CameraCapabilitySet {
             live_view: true,
@@ -3817,6 +3868,44 @@ mod tests {
         assert!(promoted.ptz);
     }
 
+    #[test]
+    fn rtsp_sources_expose_live_view_only() {
+        let camera = CameraDeviceConfig {
+            source_id: "relay".to_string(),
+            name: "Relay".to_string(),
+            onvif_host: String::new(),
+            onvif_port: 0,
+            rtsp_url: "rtsp://10.0.0.9:8554/stream".to_string(),
+            username: String::new(),
+            password: String::new(),
+            driver_id: DRIVER_ID_GENERIC_ONVIF_RTSP.to_string(),
+            vendor: String::new(),
+            model: String::new(),
+            mac_address: String::new(),
+            rtsp_port: 8554,
+            ptz_capable: true,
+            enabled: true,
+            segment_secs: 10,
+            desired: Default::default(),
+            credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: crate::config::CameraSourceType::Rtsp,
+            retention: Default::default(),
+        };
+        let observed = stream_only_observed_state(&camera);
+        assert!(!observed.ptz_capable);
+        let capabilities =
+            capabilities_for_observed(&camera, driver_capabilities(&camera), &observed);
+        assert!(capabilities.live_view);
+        assert!(!capabilities.ptz);
+        assert!(!capabilities.time_sync);
+        assert!(!capabilities.raw_probe);
+    }
+
     #[test]
     fn reolink_onvif_plane_gates_capabilities_to_supported_fields() {
         let camera = CameraDeviceConfig {
@@ -3837,6 +3926,13 @@ mod tests {
             segment_secs: 10,
             desired: Default::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let observed = ObservedCameraState {
             ptz_capable: true,
@@ -3874,6 +3970,13 @@ mod tests {
             segment_secs: 10,
             desired: Default::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let observed = ObservedCameraState {
             raw: json!({ "managementPlane": "transport_only" }),
@@ -3910,6 +4013,13 @@ mod tests {
             segment_secs: 10,
             desired: Default::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let profile = reolink_native_ptz_profile(&camera).expect("native PTZ profile");
         let requested = RequestedPose {
@@ -4089,6 +4199,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let mut next = existing.clone();
         next.desired.display_name = "Driveway".to_string();
@@ -4124,6 +4241,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let mut next = existing.clone();
         next.desired.display_name = "Driveway".to_string();
@@ -4160,6 +4284,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let mut next = existing.clone();
         next.desired.display_name = "Driveway".to_string();
@@ -4260,6 +4391,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let observed = ObservedCameraState {
             display_name: "Carport".to_string(),
@@ -4353,6 +4491,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let mut next = existing.clone();
         next.desired.display_name = "Carport".to_string();
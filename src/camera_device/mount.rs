@@ -59,6 +59,13 @@ pub async fn mount_camera_device(
             ..Default::default()
         },
         credentials: Default::default(),
+        privacy: false,
+        set_camera_time: false,
+        snapshot_interval_secs: 0,
+        zones: Vec::new(),
+        push: Default::default(),
+        source_type: Default::default(),
+        retention: Default::default(),
     };
     normalize_camera_defaults(cfg, &mut camera);
     camera = apply_driver_mount(cfg, &camera).await?;
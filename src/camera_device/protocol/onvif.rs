@@ -9,12 +9,16 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use sha1::{Digest, Sha1};
 use std::net::IpAddr;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use tokio::time::sleep;
 
+use crate::util::clock;
+
 const DEVICE_WSDL: &str = "http://www.onvif.org/ver10/device/wsdl";
 const MEDIA_WSDL: &str = "http://www.onvif.org/ver10/media/wsdl";
 const PTZ_WSDL: &str = "http://www.onvif.org/ver20/ptz/wsdl";
+const IMAGING_WSDL: &str = "http://www.onvif.org/ver20/imaging/wsdl";
+const IR_CUT_MODES: [&str; 3] = ["ON", "OFF", "AUTO"];
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -56,6 +60,33 @@ pub struct OnvifPtzPose {
     pub zoom: Option<f32>,
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnvifSystemClock {
+    pub time_mode: String,
+    pub daylight_savings: bool,
+    pub timezone_raw: String,
+    pub utc_date_time: String,
+    pub utc_unix: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnvifImagingSettings {
+    pub brightness: Option<f32>,
+    pub contrast: Option<f32>,
+    pub color_saturation: Option<f32>,
+    pub sharpness: Option<f32>,
+    pub ir_cut_mode: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OnvifImagingUpdate {
+    pub brightness: Option<f32>,
+    pub contrast: Option<f32>,
+    pub ir_cut_mode: Option<String>,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct OnvifSiteTimePolicy {
     pub ntp_enabled: bool,
@@ -234,6 +265,263 @@ pub async fn read_state(ip: &str, port: u16, username: &str, password: &str) ->
     })
 }
 
+pub async fn read_system_clock(
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+) -> Result<OnvifSystemClock> {
+    let client = http_client()?;
+    let device_service_url = format!("http://{}:{}/onvif/device_service", ip.trim(), port.max(1));
+    let xml = soap_call(
+        &client,
+        &device_service_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/GetSystemDateAndTime"),
+        "<tds:GetSystemDateAndTime/>",
+    )
+    .await
+    .context("ONVIF GetSystemDateAndTime failed")?;
+    let doc = parse_doc(&xml)?;
+    Ok(parse_system_clock(&doc))
+}
+
+pub async fn set_system_clock_utc(
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+    current: &OnvifSystemClock,
+    utc_unix: u64,
+) -> Result<()> {
+    let client = http_client()?;
+    let device_service_url = format!("http://{}:{}/onvif/device_service", ip.trim(), port.max(1));
+    let timezone_xml = if current.timezone_raw.trim().is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<tds:TimeZone><tt:TZ>{}</tt:TZ></tds:TimeZone>",
+            escape_xml(current.timezone_raw.trim())
+        )
+    };
+    let utc_xml = build_manual_datetime_xml(clock::rfc3339_utc(utc_unix).trim_end_matches('Z'))?;
+    soap_call(
+        &client,
+        &device_service_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/SetSystemDateAndTime"),
+        &format!(
+            "<tds:SetSystemDateAndTime><tds:DateTimeType>Manual</tds:DateTimeType><tds:DaylightSavings>{}</tds:DaylightSavings>{timezone_xml}<tds:UTCDateTime>{utc_xml}</tds:UTCDateTime></tds:SetSystemDateAndTime>",
+            current.daylight_savings
+        ),
+    )
+    .await
+    .context("ONVIF SetSystemDateAndTime (manual) failed")
+    .map(|_| ())
+}
+
+pub async fn system_reboot(
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+) -> Result<Option<String>> {
+    let client = http_client()?;
+    let device_service_url = format!("http://{}:{}/onvif/device_service", ip.trim(), port.max(1));
+    match soap_call(
+        &client,
+        &device_service_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/SystemReboot"),
+        "<tds:SystemReboot/>",
+    )
+    .await
+    {
+        Ok(xml) => Ok(Some(
+            parse_doc(&xml)
+                .map(|doc| descendant_text(&doc, "Message"))
+                .unwrap_or_default(),
+        )),
+        Err(err) if is_unsupported_fault(&err) => Ok(None),
+        Err(err) => Err(err.context("ONVIF SystemReboot failed")),
+    }
+}
+
+pub async fn set_user_password(
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+    target_user: &str,
+    new_password: &str,
+) -> Result<bool> {
+    let client = http_client()?;
+    let device_service_url = format!("http://{}:{}/onvif/device_service", ip.trim(), port.max(1));
+    let users = match soap_call(
+        &client,
+        &device_service_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/GetUsers"),
+        "<tds:GetUsers/>",
+    )
+    .await
+    {
+        Ok(xml) => xml,
+        Err(err) if is_unsupported_fault(&err) => return Ok(false),
+        Err(err) => return Err(err.context("ONVIF GetUsers failed")),
+    };
+    let level = parse_user_level(&parse_doc(&users)?, target_user)
+        .unwrap_or_else(|| "Administrator".to_string());
+    match soap_call(
+        &client,
+        &device_service_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/SetUser"),
+        &format!(
+            "<tds:SetUser><tds:User><tt:Username>{}</tt:Username><tt:Password>{}</tt:Password><tt:UserLevel>{}</tt:UserLevel></tds:User></tds:SetUser>",
+            escape_xml(target_user.trim()),
+            escape_xml(new_password.trim()),
+            escape_xml(&level)
+        ),
+    )
+    .await
+    {
+        Ok(_) => Ok(true),
+        Err(err) if is_unsupported_fault(&err) => Ok(false),
+        Err(err) => Err(err.context("ONVIF SetUser failed")),
+    }
+}
+
+pub async fn read_imaging_settings(
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+) -> Result<Option<OnvifImagingSettings>> {
+    let client = http_client()?;
+    let Some((imaging_url, source_token)) =
+        resolve_imaging_target(&client, ip, port, username, password).await?
+    else {
+        return Ok(None);
+    };
+    let xml = soap_call(
+        &client,
+        &imaging_url,
+        username,
+        password,
+        &format!("{IMAGING_WSDL}/GetImagingSettings"),
+        &format!(
+            "<timg:GetImagingSettings><timg:VideoSourceToken>{}</timg:VideoSourceToken></timg:GetImagingSettings>",
+            escape_xml(&source_token)
+        ),
+    )
+    .await;
+    match xml {
+        Ok(xml) => Ok(Some(parse_imaging_settings(&parse_doc(&xml)?))),
+        Err(err) if is_unsupported_fault(&err) => Ok(None),
+        Err(err) => Err(err.context("ONVIF GetImagingSettings failed")),
+    }
+}
+
+pub async fn set_imaging_settings(
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+    update: &OnvifImagingUpdate,
+) -> Result<Option<OnvifImagingSettings>> {
+    let ir_cut_mode = update
+        .ir_cut_mode
+        .as_deref()
+        .map(|mode| mode.trim().to_ascii_uppercase())
+        .filter(|mode| !mode.is_empty());
+    if let Some(mode) = &ir_cut_mode
+        && !IR_CUT_MODES.contains(&mode.as_str())
+    {
+        return Err(anyhow!("irCutMode must be one of ON, OFF, AUTO"));
+    }
+    let client = http_client()?;
+    let Some((imaging_url, source_token)) =
+        resolve_imaging_target(&client, ip, port, username, password).await?
+    else {
+        return Ok(None);
+    };
+
+    let mut settings_xml = String::new();
+    if let Some(brightness) = update.brightness {
+        settings_xml.push_str(&format!("<tt:Brightness>{brightness}</tt:Brightness>"));
+    }
+    if let Some(contrast) = update.contrast {
+        settings_xml.push_str(&format!("<tt:Contrast>{contrast}</tt:Contrast>"));
+    }
+    if let Some(mode) = &ir_cut_mode {
+        settings_xml.push_str(&format!("<tt:IrCutFilter>{mode}</tt:IrCutFilter>"));
+    }
+    if settings_xml.is_empty() {
+        return Err(anyhow!("no imaging settings requested"));
+    }
+    let result = soap_call(
+        &client,
+        &imaging_url,
+        username,
+        password,
+        &format!("{IMAGING_WSDL}/SetImagingSettings"),
+        &format!(
+            "<timg:SetImagingSettings><timg:VideoSourceToken>{}</timg:VideoSourceToken><timg:ImagingSettings>{settings_xml}</timg:ImagingSettings><timg:ForcePersistence>true</timg:ForcePersistence></timg:SetImagingSettings>",
+            escape_xml(&source_token)
+        ),
+    )
+    .await;
+    match result {
+        Ok(_) => read_imaging_settings(ip, port, username, password).await,
+        Err(err) if is_unsupported_fault(&err) => Ok(None),
+        Err(err) => Err(err.context("ONVIF SetImagingSettings failed")),
+    }
+}
+
+async fn resolve_imaging_target(
+    client: &Client,
+    ip: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+) -> Result<Option<(String, String)>> {
+    let device_service_url = format!("http://{}:{}/onvif/device_service", ip.trim(), port.max(1));
+    let capabilities_xml = soap_call(
+        client,
+        &device_service_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/GetCapabilities"),
+        "<tds:GetCapabilities><tds:Category>All</tds:Category></tds:GetCapabilities>",
+    )
+    .await
+    .context("ONVIF GetCapabilities failed")?;
+    let caps_doc = parse_doc(&capabilities_xml)?;
+    let Some(imaging_url) = capability_xaddr(&caps_doc, "Imaging") else {
+        return Ok(None);
+    };
+    let media_service_url = capability_xaddr(&caps_doc, "Media")
+        .unwrap_or_else(|| format!("http://{}:{}/onvif/media_service", ip.trim(), port.max(1)));
+    let sources_xml = soap_call(
+        client,
+        &media_service_url,
+        username,
+        password,
+        &format!("{MEDIA_WSDL}/GetVideoSources"),
+        "<trt:GetVideoSources/>",
+    )
+    .await
+    .context("ONVIF GetVideoSources failed")?;
+    let sources_doc = parse_doc(&sources_xml)?;
+    Ok(first_video_source_token(&sources_doc).map(|token| (imaging_url, token)))
+}
+
 pub async fn read_network_protocols(
     ip: &str,
     port: u16,
@@ -484,6 +772,222 @@ pub async fn ptz_step_pose(
     ptz_set_pose(ip, port, username, password, next_pan, next_tilt, next_zoom).await
 }
 
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnvifSourceDraft {
+    pub source_id: String,
+    pub name: String,
+    pub onvif_host: String,
+    pub onvif_port: u16,
+    pub rtsp_url: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub serial_number: String,
+    pub missing: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+pub async fn draft_source(
+    endpoint: &str,
+    username: &str,
+    password: &str,
+) -> Result<OnvifSourceDraft> {
+    let url = reqwest::Url::parse(endpoint.trim()).context("invalid ONVIF endpoint")?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("ONVIF endpoint must be http or https"));
+    }
+    let host = url
+        .host_str()
+        .map(|host| host.trim_matches(['[', ']']).to_string())
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| anyhow!("ONVIF endpoint has no host"))?;
+    let mut draft = OnvifSourceDraft {
+        onvif_host: host,
+        onvif_port: url.port_or_known_default().unwrap_or(80),
+        ..OnvifSourceDraft::default()
+    };
+    let client = http_client()?;
+    let device_url = url.as_str();
+
+    match soap_call(
+        &client,
+        device_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/GetScopes"),
+        "<tds:GetScopes/>",
+    )
+    .await
+    .context("GetScopes")
+    {
+        Ok(xml) => {
+            draft.name = parse_doc(&xml)
+                .map(|doc| scope_name(&doc))
+                .unwrap_or_default()
+        }
+        Err(err) => draft.errors.push(stage_error(&err)),
+    }
+
+    match soap_call(
+        &client,
+        device_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/GetDeviceInformation"),
+        "<tds:GetDeviceInformation/>",
+    )
+    .await
+    .context("GetDeviceInformation")
+    {
+        Ok(xml) => {
+            if let Ok(doc) = parse_doc(&xml) {
+                draft.manufacturer = descendant_text(&doc, "Manufacturer");
+                draft.model = descendant_text(&doc, "Model");
+                draft.serial_number = descendant_text(&doc, "SerialNumber");
+            }
+        }
+        Err(err) => draft.errors.push(stage_error(&err)),
+    }
+    draft.source_id = draft_source_id(&draft.model, &draft.serial_number);
+    if draft.name.is_empty() {
+        draft.name = draft.model.clone();
+    }
+
+    match resolve_stream_uri(&client, device_url, username, password).await {
+        // Credentials stay in the draft's own fields; the recorder adds them to the URL.
+        Ok(uri) => draft.rtsp_url = uri.trim().to_string(),
+        Err(err) => draft.errors.push(stage_error(&err)),
+    }
+
+    for (field, value) in [
+        ("sourceId", &draft.source_id),
+        ("name", &draft.name),
+        ("rtspUrl", &draft.rtsp_url),
+    ] {
+        if value.is_empty() {
+            draft.missing.push(field.to_string());
+        }
+    }
+    Ok(draft)
+}
+
+async fn resolve_stream_uri(
+    client: &Client,
+    device_url: &str,
+    username: &str,
+    password: &str,
+) -> Result<String> {
+    let capabilities_xml = soap_call(
+        client,
+        device_url,
+        username,
+        password,
+        &format!("{DEVICE_WSDL}/GetCapabilities"),
+        "<tds:GetCapabilities><tds:Category>Media</tds:Category></tds:GetCapabilities>",
+    )
+    .await
+    .context("GetCapabilities")?;
+    let media_url = parse_doc(&capabilities_xml)
+        .ok()
+        .and_then(|doc| capability_xaddr(&doc, "Media"))
+        .unwrap_or_else(|| device_url.to_string());
+    let profiles_xml = soap_call(
+        client,
+        &media_url,
+        username,
+        password,
+        &format!("{MEDIA_WSDL}/GetProfiles"),
+        "<trt:GetProfiles/>",
+    )
+    .await
+    .context("GetProfiles")?;
+    let token = parse_doc(&profiles_xml)
+        .ok()
+        .and_then(|doc| first_profile_token(&doc))
+        .ok_or_else(|| anyhow!("no media profile"))
+        .context("GetProfiles")?;
+    let stream_xml = soap_call(
+        client,
+        &media_url,
+        username,
+        password,
+        &format!("{MEDIA_WSDL}/GetStreamUri"),
+        &format!(
+            concat!(
+                "<trt:GetStreamUri><trt:StreamSetup><tt:Stream>RTP-Unicast</tt:Stream>",
+                "<tt:Transport><tt:Protocol>RTSP</tt:Protocol></tt:Transport>",
+                "</trt:StreamSetup><trt:ProfileToken>{}</trt:ProfileToken></trt:GetStreamUri>"
+            ),
+            escape_xml(&token)
+        ),
+    )
+    .await
+    .context("GetStreamUri")?;
+    parse_doc(&stream_xml)
+        .ok()
+        .map(|doc| descendant_text(&doc, "Uri"))
+        .filter(|uri| !uri.is_empty())
+        .ok_or_else(|| anyhow!("no stream uri"))
+        .context("GetStreamUri")
+}
+
+fn stage_error(err: &anyhow::Error) -> String {
+    let root = err.root_cause().to_string();
+    let reason = root
+        .split_once(" failed with ")
+        .map(|(_, status)| status.split(':').next().unwrap_or(status).to_string())
+        .unwrap_or(root);
+    format!("{err}: {reason}")
+}
+
+fn scope_name(doc: &Document<'_>) -> String {
+    doc.descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "ScopeItem")
+        .filter_map(|node| node.text())
+        .find_map(|scope| scope.trim().strip_prefix("onvif://www.onvif.org/name/"))
+        .map(|name| percent_decode(name).trim().to_string())
+        .unwrap_or_default()
+}
+
+fn first_profile_token(doc: &Document<'_>) -> Option<String> {
+    doc.descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "Profiles")
+        .filter_map(|node| node.attribute("token"))
+        .map(|value| value.trim().to_string())
+        .find(|value| !value.is_empty())
+}
+
+fn draft_source_id(model: &str, serial_number: &str) -> String {
+    if model.trim().is_empty() || serial_number.trim().is_empty() {
+        return String::new();
+    }
+    crate::util::source_dir_name(&crate::util::normalize_source_id(&format!(
+        "{}-{}",
+        model.trim(),
+        serial_number.trim()
+    )))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%'
+            && let Some(byte) = value
+                .get(idx + 1..idx + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            idx += 3;
+            continue;
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 fn http_client() -> Result<Client> {
     Client::builder()
         .danger_accept_invalid_certs(true)
@@ -533,7 +1037,7 @@ async fn soap_call(
 fn build_envelope(username: &str, password: &str, body_xml: &str) -> String {
     let mut nonce = [0u8; 16];
     rand::thread_rng().fill_bytes(&mut nonce);
-    let created = iso8601_now_utc();
+    let created = clock::rfc3339_utc(clock::now_unix_seconds());
     let mut digest = Sha1::new();
     digest.update(nonce);
     digest.update(created.as_bytes());
@@ -547,6 +1051,7 @@ fn build_envelope(username: &str, password: &str, body_xml: &str) -> String {
             "xmlns:tds=\"http://www.onvif.org/ver10/device/wsdl\" ",
             "xmlns:trt=\"http://www.onvif.org/ver10/media/wsdl\" ",
             "xmlns:tptz=\"http://www.onvif.org/ver20/ptz/wsdl\" ",
+            "xmlns:timg=\"http://www.onvif.org/ver20/imaging/wsdl\" ",
             "xmlns:tt=\"http://www.onvif.org/ver10/schema\" ",
             "xmlns:wsse=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd\" ",
             "xmlns:wsu=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd\">",
@@ -699,6 +1204,76 @@ fn datetime_from_node(node: Node<'_, '_>) -> String {
     }
 }
 
+fn parse_imaging_settings(doc: &Document<'_>) -> OnvifImagingSettings {
+    let numeric = |name: &str| descendant_text(doc, name).parse::<f32>().ok();
+    OnvifImagingSettings {
+        brightness: numeric("Brightness"),
+        contrast: numeric("Contrast"),
+        color_saturation: numeric("ColorSaturation"),
+        sharpness: numeric("Sharpness"),
+        ir_cut_mode: descendant_text(doc, "IrCutFilter").to_ascii_uppercase(),
+    }
+}
+
+fn first_video_source_token(doc: &Document<'_>) -> Option<String> {
+    doc.descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "VideoSources")
+        .filter_map(|node| node.attribute("token"))
+        .map(|token| token.trim().to_string())
+        .find(|token| !token.is_empty())
+}
+
+fn parse_user_level(doc: &Document<'_>, username: &str) -> Option<String> {
+    let child_text = |node: Node<'_, '_>, name: &str| {
+        node.children()
+            .find(|child| child.is_element() && child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(|text| text.trim().to_string())
+    };
+    doc.descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "User")
+        .find(|user| child_text(*user, "Username").as_deref() == Some(username.trim()))
+        .and_then(|user| child_text(user, "UserLevel"))
+        .filter(|level| !level.is_empty())
+}
+
+fn is_unsupported_fault(err: &anyhow::Error) -> bool {
+    let text = format!("{err:#}");
+    text.contains("ActionNotSupported") || text.contains("NotSupported")
+}
+
+fn parse_system_clock(doc: &Document<'_>) -> OnvifSystemClock {
+    let utc = doc
+        .descendants()
+        .find(|node| node.is_element() && node.tag_name().name() == "UTCDateTime");
+    OnvifSystemClock {
+        time_mode: descendant_text(doc, "DateTimeType").to_ascii_lowercase(),
+        daylight_savings: matches!(
+            descendant_text(doc, "DaylightSavings")
+                .to_ascii_lowercase()
+                .as_str(),
+            "true" | "1"
+        ),
+        timezone_raw: descendant_text(doc, "TZ"),
+        utc_date_time: utc.map(datetime_from_node).unwrap_or_default(),
+        utc_unix: utc.and_then(unix_from_datetime_node),
+    }
+}
+
+fn unix_from_datetime_node(node: Node<'_, '_>) -> Option<i64> {
+    let date = chrono::NaiveDate::from_ymd_opt(
+        i32::try_from(child_numeric(node, "Year")?).ok()?,
+        child_numeric(node, "Month")?,
+        child_numeric(node, "Day")?,
+    )?;
+    let datetime = date.and_hms_opt(
+        child_numeric(node, "Hour")?,
+        child_numeric(node, "Minute")?,
+        child_numeric(node, "Second")?,
+    )?;
+    Some(datetime.and_utc().timestamp())
+}
+
 fn child_numeric(node: Node<'_, '_>, name: &str) -> Option<u32> {
     node.descendants()
         .find(|child| child.is_element() && child.tag_name().name() == name)
@@ -856,37 +1431,6 @@ fn build_manual_datetime_xml(value: &str) -> Result<String> {
     ))
 }
 
-fn iso8601_now_utc() -> String {
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    unix_to_iso8601(now)
-}
-
-fn unix_to_iso8601(ts: u64) -> String {
-    // Howard Hinnant civil-from-days adaptation, enough for ONVIF WSSE timestamps.
-    let days = (ts / 86_400) as i64;
-    let secs_of_day = (ts % 86_400) as u32;
-    let z = days + 719_468;
-    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
-    let doe = z - era * 146_097;
-    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
-    let y = yoe + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = mp + if mp < 10 { 3 } else { -9 };
-    let year = y + if m <= 2 { 1 } else { 0 };
-    let hour = secs_of_day / 3600;
-    let minute = (secs_of_day % 3600) / 60;
-    let second = secs_of_day % 60;
-    format!(
-        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
-        year, m, d, hour, minute, second,
-    )
-}
-
 fn escape_xml(value: &str) -> String {
     value
         .replace('&', "&amp;")
@@ -907,6 +1451,65 @@ mod tests {
         assert!(xml.contains("<tt:Second>0</tt:Second>"));
     }
 
+    #[test]
+    fn system_clock_parses_utc_and_dst_fields() {
+        let xml = concat!(
+            "<Envelope><Body><GetSystemDateAndTimeResponse><SystemDateAndTime>",
+            "<DateTimeType>Manual</DateTimeType><DaylightSavings>true</DaylightSavings>",
+            "<TimeZone><TZ>CST6CDT,M3.2.0,M11.1.0</TZ></TimeZone>",
+            "<UTCDateTime><Time><Hour>12</Hour><Minute>0</Minute><Second>5</Second></Time>",
+            "<Date><Year>2026</Year><Month>4</Month><Day>5</Day></Date></UTCDateTime>",
+            "<LocalDateTime><Time><Hour>7</Hour><Minute>0</Minute><Second>5</Second></Time>",
+            "<Date><Year>2026</Year><Month>4</Month><Day>5</Day></Date></LocalDateTime>",
+            "</SystemDateAndTime></GetSystemDateAndTimeResponse></Body></Envelope>"
+        );
+        let clock = parse_system_clock(&parse_doc(xml).unwrap());
+        assert_eq!(clock.time_mode, "manual");
+        assert!(clock.daylight_savings);
+        assert_eq!(clock.timezone_raw, "CST6CDT,M3.2.0,M11.1.0");
+        assert_eq!(clock.utc_date_time, "2026-04-05T12:00:05");
+        assert_eq!(clock.utc_unix, Some(1_775_390_405));
+    }
+
+    #[test]
+    fn user_level_comes_from_the_matching_user() {
+        let doc = parse_doc(concat!(
+            "<Envelope><Body><GetUsersResponse>",
+            "<User><Username>viewer</Username><UserLevel>User</UserLevel></User>",
+            "<User><Username>admin</Username><UserLevel>Administrator</UserLevel></User>",
+            "</GetUsersResponse></Body></Envelope>"
+        ))
+        .unwrap();
+        assert_eq!(parse_user_level(&doc, "viewer").as_deref(), Some("User"));
+        assert_eq!(
+            parse_user_level(&doc, " admin ").as_deref(),
+            Some("Administrator")
+        );
+        assert_eq!(parse_user_level(&doc, "other"), None);
+    }
+
+    #[test]
+    fn imaging_settings_and_video_source_parse() {
+        let settings = parse_doc(concat!(
+            "<Envelope><Body><GetImagingSettingsResponse><ImagingSettings>",
+            "<Brightness>55</Brightness><ColorSaturation>50</ColorSaturation>",
+            "<Contrast>48.5</Contrast><IrCutFilter>Auto</IrCutFilter>",
+            "</ImagingSettings></GetImagingSettingsResponse></Body></Envelope>"
+        ))
+        .unwrap();
+        let parsed = parse_imaging_settings(&settings);
+        assert_eq!(parsed.brightness, Some(55.0));
+        assert_eq!(parsed.contrast, Some(48.5));
+        assert_eq!(parsed.sharpness, None);
+        assert_eq!(parsed.ir_cut_mode, "AUTO");
+
+        let sources = parse_doc(
+            "<Envelope><Body><GetVideoSourcesResponse><VideoSources token=\"vs0\"/></GetVideoSourcesResponse></Body></Envelope>",
+        )
+        .unwrap();
+        assert_eq!(first_video_source_token(&sources).as_deref(), Some("vs0"));
+    }
+
     #[test]
     fn ntp_manual_entry_uses_ipv4_or_dns() {
         assert!(ntp_manual_entry("192.168.250.1").contains("IPv4Address"));
@@ -920,4 +1523,23 @@ mod tests {
         assert_eq!(normalize_onvif_timezone("GMT+07:00:00"), "America/Phoenix");
         assert_eq!(normalize_onvif_timezone("MST7"), "America/Phoenix");
     }
+
+    #[test]
+    fn draft_fields_come_from_scopes_and_device_information() {
+        let xml = concat!(
+            "<Envelope><Body><GetScopesResponse>",
+            "<Scopes><ScopeItem>onvif://www.onvif.org/type/video_encoder</ScopeItem></Scopes>",
+            "<Scopes><ScopeItem>onvif://www.onvif.org/name/Front%20Door</ScopeItem></Scopes>",
+            "</GetScopesResponse></Body></Envelope>"
+        );
+        let doc = parse_doc(xml).unwrap();
+        assert_eq!(scope_name(&doc), "Front Door");
+        assert_eq!(draft_source_id("RLC-810A", "00:11 AB"), "rlc-810a-00_11_ab");
+        assert_eq!(draft_source_id("RLC-810A", ""), "");
+        let fault = anyhow!("ONVIF action x failed with 401 Unauthorized: <html/>");
+        assert_eq!(
+            stage_error(&fault.context("GetProfiles")),
+            "GetProfiles: 401 Unauthorized"
+        );
+    }
 }
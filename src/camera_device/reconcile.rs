@@ -117,6 +117,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         }
     }
 
@@ -0,0 +1,167 @@
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+const ENV_DEFAULTS: &[(&str, &str)] = &[("camera_network.timezone", "TZ")];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigOrigin {
+    File,
+    Default,
+    Env,
+    Derived,
+    Api,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConfigOrigins {
+    loaded: BTreeMap<String, (ConfigOrigin, [u8; 32])>,
+}
+
+impl ConfigOrigins {
+    pub fn parsed(document: &Value, parsed: &Value) -> Self {
+        let mut named = BTreeMap::new();
+        leaves(document, &mut named);
+        let mut settings = BTreeMap::new();
+        leaves(parsed, &mut settings);
+        let loaded = settings
+            .into_iter()
+            .map(|(path, value)| {
+                let origin = if named.contains_key(&path) {
+                    ConfigOrigin::File
+                } else if env_default(&path).is_some() {
+                    ConfigOrigin::Env
+                } else {
+                    ConfigOrigin::Default
+                };
+                let digest = digest(value);
+                (path, (origin, digest))
+            })
+            .collect();
+        Self { loaded }
+    }
+
+    pub fn record_derived(&mut self, loaded: &Value) {
+        let mut settings = BTreeMap::new();
+        leaves(loaded, &mut settings);
+        for (path, value) in settings {
+            let digest = digest(value);
+            match self.loaded.get_mut(&path) {
+                Some((_, before)) if *before == digest => {}
+                Some(entry) => *entry = (ConfigOrigin::Derived, digest),
+                None => {
+                    self.loaded.insert(path, (ConfigOrigin::Derived, digest));
+                }
+            }
+        }
+    }
+
+    pub fn origin(&self, path: &str, current: &Value) -> Option<ConfigOrigin> {
+        let (origin, loaded) = self.loaded.get(path)?;
+        Some(match *loaded == digest(current) {
+            true => *origin,
+            false => ConfigOrigin::Api,
+        })
+    }
+}
+
+fn leaves<'a>(document: &'a Value, out: &mut BTreeMap<String, &'a Value>) {
+    let Some(fields) = document.as_object() else {
+        return;
+    };
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("camera_devices", Value::Array(cameras)) => {
+                for camera in cameras {
+                    if let Some(source_id) = camera.get("source_id").and_then(Value::as_str) {
+                        walk(&format!("camera_devices.{source_id}"), camera, out);
+                    }
+                }
+            }
+            _ => walk(key, value, out),
+        }
+    }
+}
+
+pub fn camera_settings(camera: &Value) -> BTreeMap<String, &Value> {
+    let mut out = BTreeMap::new();
+    for (key, value) in camera.as_object().into_iter().flatten() {
+        walk(key, value, &mut out);
+    }
+    out
+}
+
+fn walk<'a>(path: &str, value: &'a Value, out: &mut BTreeMap<String, &'a Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                walk(&format!("{path}.{key}"), value, out);
+            }
+        }
+        _ => {
+            out.insert(path.to_string(), value);
+        }
+    }
+}
+
+fn env_default(path: &str) -> Option<&'static str> {
+    ENV_DEFAULTS
+        .iter()
+        .find(|(setting, _)| *setting == path)
+        .map(|(_, var)| *var)
+        .filter(|var| std::env::var(var).is_ok_and(|value| !value.trim().is_empty()))
+}
+
+fn digest(value: &Value) -> [u8; 32] {
+    Sha256::digest(value.to_string().as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn origins_follow_the_file_loading_and_later_changes() {
+        let document = json!({
+            "node_id": "nvr-1",
+            "camera_devices": [{ "source_id": "front", "name": "Front" }],
+        });
+        let parsed = json!({
+            "node_id": "nvr-1",
+            "storage": { "opaque_names": false, "encryption_key_hex": "" },
+            "camera_devices": [{ "source_id": "front", "name": "Front", "push": { "port": 0 } }],
+        });
+        let mut origins = ConfigOrigins::parsed(&document, &parsed);
+        let mut loaded = parsed.clone();
+        loaded["storage"]["encryption_key_hex"] = json!("ab".repeat(32));
+        loaded["camera_devices"][0]["push"]["port"] = json!(1935);
+        origins.record_derived(&loaded);
+
+        let origin = |path: &str, value: Value| origins.origin(path, &value);
+        assert_eq!(origin("node_id", json!("nvr-1")), Some(ConfigOrigin::File));
+        assert_eq!(
+            origin("camera_devices.front.name", json!("Front")),
+            Some(ConfigOrigin::File)
+        );
+        assert_eq!(
+            origin("storage.opaque_names", json!(false)),
+            Some(ConfigOrigin::Default)
+        );
+        assert_eq!(
+            origin("storage.encryption_key_hex", json!("ab".repeat(32))),
+            Some(ConfigOrigin::Derived)
+        );
+        assert_eq!(
+            origin("camera_devices.front.push.port", json!(1935)),
+            Some(ConfigOrigin::Derived)
+        );
+        assert_eq!(
+            origin("camera_devices.front.name", json!("Porch")),
+            Some(ConfigOrigin::Api)
+        );
+        assert_eq!(origin("camera_devices.back.name", json!("Back")), None);
+    }
+}
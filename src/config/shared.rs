@@ -0,0 +1,84 @@
+use super::Config;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard, watch};
+
+#[derive(Clone)]
+pub struct SharedConfig {
+    inner: Arc<Mutex<Config>>,
+    published: Arc<watch::Sender<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(cfg: Config) -> Self {
+        let (published, _) = watch::channel(Arc::new(cfg.clone()));
+        Self {
+            inner: Arc::new(Mutex::new(cfg)),
+            published: Arc::new(published),
+        }
+    }
+
+    pub async fn lock(&self) -> ConfigGuard<'_> {
+        ConfigGuard {
+            guard: self.inner.lock().await,
+            published: &self.published,
+            written: false,
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<Config> {
+        Arc::clone(&self.published.borrow())
+    }
+}
+
+pub struct ConfigGuard<'a> {
+    guard: MutexGuard<'a, Config>,
+    published: &'a watch::Sender<Arc<Config>>,
+    written: bool,
+}
+
+impl Deref for ConfigGuard<'_> {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.guard
+    }
+}
+
+impl DerefMut for ConfigGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Config {
+        self.written = true;
+        &mut self.guard
+    }
+}
+
+impl Drop for ConfigGuard<'_> {
+    fn drop(&mut self) {
+        if self.written {
+            self.published.send_replace(Arc::new(self.guard.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn snapshots_follow_released_writes_only() {
+        let shared = SharedConfig::new(Config::default_generated());
+        let before = shared.snapshot();
+        {
+            let guard = shared.lock().await;
+            assert_eq!(guard.node_id, before.node_id);
+        }
+        assert!(Arc::ptr_eq(&before, &shared.snapshot()));
+
+        {
+            let mut guard = shared.lock().await;
+            guard.node_id = "nvr-renamed".to_string();
+            assert_eq!(shared.snapshot().node_id, before.node_id);
+        }
+        assert_eq!(shared.snapshot().node_id, "nvr-renamed");
+    }
+}
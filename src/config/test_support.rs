@@ -0,0 +1,12 @@
+use super::CameraDeviceConfig;
+
+pub(crate) fn camera(source_id: &str) -> CameraDeviceConfig {
+    serde_json::from_value(serde_json::json!({
+        "source_id": source_id,
+        "name": source_id,
+        "source_type": "rtsp",
+        "onvif_host": "",
+        "rtsp_url": "rtsp://10.0.0.9:554/live",
+    }))
+    .expect("camera fixture parses")
+}
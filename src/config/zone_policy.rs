@@ -0,0 +1,176 @@
+use super::{CameraDeviceConfig, ZoneConfig};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZonePolicy {
+    #[serde(default)]
+    pub min_retention_days: u64,
+    #[serde(default)]
+    pub max_retention_days: u64,
+    #[serde(default)]
+    pub export_requires_reason: bool,
+}
+
+impl ZonePolicy {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectivePolicy {
+    pub min_retention_days: u64,
+    pub max_retention_days: u64,
+    pub export_requires_reason: bool,
+    pub zones: Vec<String>,
+}
+
+impl EffectivePolicy {
+    pub fn resolve<'a>(zones: impl IntoIterator<Item = &'a ZoneConfig>) -> Self {
+        let mut out = Self::default();
+        for zone in zones {
+            let policy = &zone.policy;
+            if policy.is_empty() {
+                continue;
+            }
+            out.min_retention_days = out.min_retention_days.max(policy.min_retention_days);
+            out.max_retention_days = match (out.max_retention_days, policy.max_retention_days) {
+                (0, days) | (days, 0) => days,
+                (current, days) => current.min(days),
+            };
+            out.export_requires_reason |= policy.export_requires_reason;
+            out.zones.push(zone.key.clone());
+        }
+        out
+    }
+
+    pub fn conflict(&self) -> Option<String> {
+        (self.max_retention_days > 0 && self.min_retention_days > self.max_retention_days).then(
+            || {
+                format!(
+                    "min_retention_days {} exceeds max_retention_days {} (zones {})",
+                    self.min_retention_days,
+                    self.max_retention_days,
+                    self.zones.join(", ")
+                )
+            },
+        )
+    }
+}
+
+pub fn camera_policy(zones: &[ZoneConfig], camera: &CameraDeviceConfig) -> EffectivePolicy {
+    EffectivePolicy::resolve(
+        camera
+            .zones
+            .iter()
+            .filter_map(|key| zones.iter().find(|zone| &zone.key == key)),
+    )
+}
+
+pub fn conflicts(zones: &[ZoneConfig], cameras: &[CameraDeviceConfig]) -> Vec<String> {
+    let mut out = zones
+        .iter()
+        .filter_map(|zone| {
+            EffectivePolicy::resolve([zone])
+                .conflict()
+                .map(|conflict| format!("swarm.zones.{}.policy: {conflict}", zone.key))
+        })
+        .collect::<Vec<_>>();
+    if !out.is_empty() {
+        return out;
+    }
+    for camera in cameras {
+        if let Some(conflict) = camera_policy(zones, camera).conflict() {
+            out.push(format!("camera_devices.{}: {conflict}", camera.source_id));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    fn zone(key: &str, min: u64, max: u64, reason: bool) -> ZoneConfig {
+        ZoneConfig {
+            key: key.to_string(),
+            name: key.to_string(),
+            zone_secret_hex: String::new(),
+            policy: ZonePolicy {
+                min_retention_days: min,
+                max_retention_days: max,
+                export_requires_reason: reason,
+            },
+        }
+    }
+
+    fn camera(source_id: &str, zones: &[&str]) -> CameraDeviceConfig {
+        CameraDeviceConfig {
+            zones: zones.iter().map(|zone| zone.to_string()).collect(),
+            ..test_support::camera(source_id)
+        }
+    }
+
+    #[test]
+    fn strictest_rule_of_each_zone_wins() {
+        let zones = [
+            zone("lobby", 7, 90, false),
+            zone("vault", 30, 0, true),
+            zone("yard", 0, 60, false),
+            zone("open", 0, 0, false),
+        ];
+        let policy = camera_policy(&zones, &camera("cam", &["lobby", "vault", "yard", "open"]));
+        assert_eq!(
+            policy,
+            EffectivePolicy {
+                min_retention_days: 30,
+                max_retention_days: 60,
+                export_requires_reason: true,
+                zones: vec!["lobby".into(), "vault".into(), "yard".into()],
+            }
+        );
+        assert_eq!(policy.conflict(), None);
+    }
+
+    #[test]
+    fn cameras_without_policy_zones_have_no_bounds() {
+        let zones = [zone("open", 0, 0, false), zone("vault", 30, 0, true)];
+        assert_eq!(
+            camera_policy(&zones, &camera("cam", &[])),
+            EffectivePolicy::default()
+        );
+        assert_eq!(
+            camera_policy(&zones, &camera("cam", &["open", "missing"])),
+            EffectivePolicy::default()
+        );
+        let unbounded = camera_policy(&zones, &camera("cam", &["vault"]));
+        assert_eq!(unbounded.max_retention_days, 0);
+        assert_eq!(unbounded.conflict(), None);
+    }
+
+    #[test]
+    fn impossible_combinations_are_rejected() {
+        let zones = [zone("bad", 10, 5, false)];
+        let found = conflicts(&zones, &[camera("cam", &["bad"])]);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("swarm.zones.bad.policy:"), "{found:?}");
+
+        // Each zone is possible alone; a camera in both cannot be.
+        let zones = [zone("hold", 30, 0, false), zone("purge", 0, 14, false)];
+        let cameras = [
+            camera("both", &["hold", "purge"]),
+            camera("hold-only", &["hold"]),
+            camera("purge-only", &["purge"]),
+        ];
+        let found = conflicts(&zones, &cameras);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("camera_devices.both:"), "{found:?}");
+        assert!(found[0].contains("hold, purge"), "{found:?}");
+
+        // Equal bounds keep footage exactly that long.
+        let zones = [zone("exact", 14, 14, false)];
+        assert!(conflicts(&zones, &[camera("cam", &["exact"])]).is_empty());
+    }
+}
@@ -1,15 +1,33 @@
-use anyhow::{Result, anyhow};
 use base64::Engine;
-use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
 use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
 use hkdf::Hkdf;
 use hmac::{Hmac, Mac};
 use rand::RngCore;
 use sha2::Sha256;
 use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+// Relative to this file's directory, as the integration tests include this file by path.
+#[path = "crypto/testvectors.rs"]
+pub mod testvectors;
 
 pub const SESSION_KEY_LEN: usize = 32;
 
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("expected {expected} bytes, got {actual}")]
+    BadKeyLength { expected: usize, actual: usize },
+    #[error("decrypt failed")]
+    DecryptFailed,
+    #[error("encrypt failed")]
+    EncryptFailed,
+    #[error("invalid {0}")]
+    BadEncoding(&'static str),
+    #[error("{0}")]
+    InvalidParameter(&'static str),
+}
+
 pub fn random_nonce_24() -> [u8; 24] {
     let mut nonce = [0u8; 24];
     rand::thread_rng().fill_bytes(&mut nonce);
@@ -22,10 +40,10 @@ pub fn compute_hello_proof(
     device_pk: &str,
     client_key_b64: &str,
     ts: u64,
-) -> Result<String> {
+) -> Result<String, CryptoError> {
     let key = parse_hex_exact(identity_secret_hex, 32)?;
-    let mut mac: Hmac<Sha256> =
-        <Hmac<Sha256> as Mac>::new_from_slice(&key).map_err(|_| anyhow!("hmac key"))?;
+    let mut mac: Hmac<Sha256> = <Hmac<Sha256> as Mac>::new_from_slice(&key)
+        .map_err(|_| CryptoError::InvalidParameter("hmac key"))?;
     let material = format!("{}|{}|{}|{}", identity_id, device_pk, client_key_b64, ts);
     mac.update(material.as_bytes());
     Ok(hex::encode(mac.finalize().into_bytes()))
@@ -38,15 +56,17 @@ pub fn verify_hello_proof(
     client_key_b64: &str,
     ts: u64,
     proof_hex: &str,
-) -> Result<bool> {
-    let expected = compute_hello_proof(
-        identity_secret_hex,
-        identity_id,
-        device_pk,
-        client_key_b64,
-        ts,
-    )?;
-    Ok(expected.eq_ignore_ascii_case(proof_hex))
+) -> Result<bool, CryptoError> {
+    let key = parse_hex_exact(identity_secret_hex, 32)?;
+    let Ok(proof) = hex::decode(proof_hex) else {
+        return Ok(false);
+    };
+    let mut mac: Hmac<Sha256> = <Hmac<Sha256> as Mac>::new_from_slice(&key)
+        .map_err(|_| CryptoError::InvalidParameter("hmac key"))?;
+    let material = format!("{}|{}|{}|{}", identity_id, device_pk, client_key_b64, ts);
+    mac.update(material.as_bytes());
+    // Compared in constant time, so timing does not reveal how much of a guess matched.
+    Ok(mac.verify_slice(&proof).is_ok())
 }
 
 pub fn derive_session_key(
@@ -54,7 +74,7 @@ pub fn derive_session_key(
     identity_secret_hex: &str,
     client_key_b64: &str,
     context: &str,
-) -> Result<(Vec<u8>, String)> {
+) -> Result<(Zeroizing<Vec<u8>>, String), CryptoError> {
     let server_secret_bytes = parse_hex_exact(server_secret_hex, 32)?;
     let identity_secret = parse_hex_exact(identity_secret_hex, 32)?;
     let client_key = decode_b64_exact(client_key_b64, 32)?;
@@ -62,6 +82,7 @@ pub fn derive_session_key(
     let mut ss = [0u8; 32];
     ss.copy_from_slice(&server_secret_bytes);
     let server_secret = StaticSecret::from(ss);
+    ss.zeroize();
     let server_pub = PublicKey::from(&server_secret);
 
     let mut cp = [0u8; 32];
@@ -71,60 +92,126 @@ pub fn derive_session_key(
     let shared = server_secret.diffie_hellman(&client_pub);
     let hk = Hkdf::<Sha256>::new(Some(&identity_secret), shared.as_bytes());
 
-    let mut out = [0u8; SESSION_KEY_LEN];
+    let mut out = Zeroizing::new(vec![0u8; SESSION_KEY_LEN]);
     hk.expand(context.as_bytes(), &mut out)
-        .map_err(|_| anyhow!("hkdf expand failed"))?;
+        .map_err(|_| CryptoError::InvalidParameter("hkdf expand failed"))?;
 
     let server_pub_b64 = base64::engine::general_purpose::STANDARD.encode(server_pub.as_bytes());
-    Ok((out.to_vec(), server_pub_b64))
+    Ok((out, server_pub_b64))
+}
+
+pub fn encrypt_payload(
+    session_key: &[u8],
+    nonce: &[u8; 24],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    encrypt_payload_with_aad(session_key, nonce, &[], plaintext)
 }
 
-pub fn encrypt_payload(session_key: &[u8], nonce: &[u8; 24], plaintext: &[u8]) -> Result<Vec<u8>> {
+pub fn decrypt_payload(
+    session_key: &[u8],
+    nonce: &[u8; 24],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    decrypt_payload_with_aad(session_key, nonce, &[], ciphertext)
+}
+
+pub fn encrypt_payload_with_aad(
+    session_key: &[u8],
+    nonce: &[u8; 24],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    session_cipher(session_key)?
+        .encrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::EncryptFailed)
+}
+
+pub fn decrypt_payload_with_aad(
+    session_key: &[u8],
+    nonce: &[u8; 24],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    session_cipher(session_key)?
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::DecryptFailed)
+}
+
+fn session_cipher(session_key: &[u8]) -> Result<XChaCha20Poly1305, CryptoError> {
     if session_key.len() != SESSION_KEY_LEN {
-        return Err(anyhow!("invalid session key length"));
+        return Err(CryptoError::BadKeyLength {
+            expected: SESSION_KEY_LEN,
+            actual: session_key.len(),
+        });
     }
-    let key = Key::from_slice(session_key);
-    let cipher = XChaCha20Poly1305::new(key);
-    let nonce = XNonce::from_slice(nonce);
-    cipher
-        .encrypt(nonce, plaintext)
-        .map_err(|_| anyhow!("encrypt failed"))
+    Ok(XChaCha20Poly1305::new(Key::from_slice(session_key)))
 }
 
-pub fn decrypt_payload(session_key: &[u8], nonce: &[u8; 24], ciphertext: &[u8]) -> Result<Vec<u8>> {
-    if session_key.len() != SESSION_KEY_LEN {
-        return Err(anyhow!("invalid session key length"));
+pub fn derive_passphrase_key(
+    passphrase: &str,
+    salt: &[u8],
+    iterations: u32,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    if iterations == 0 {
+        return Err(CryptoError::InvalidParameter(
+            "kdf iterations must be at least 1",
+        ));
+    }
+    let keyed: Hmac<Sha256> = <Hmac<Sha256> as Mac>::new_from_slice(passphrase.as_bytes())
+        .map_err(|_| CryptoError::InvalidParameter("hmac key"))?;
+    let mut mac = keyed.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block = mac.finalize().into_bytes();
+    let mut out = Zeroizing::new(block.to_vec());
+    for _ in 1..iterations {
+        let mut mac = keyed.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes();
+        for (acc, byte) in out.iter_mut().zip(block.iter()) {
+            *acc ^= byte;
+        }
     }
-    let key = Key::from_slice(session_key);
-    let cipher = XChaCha20Poly1305::new(key);
-    let nonce = XNonce::from_slice(nonce);
-    cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|_| anyhow!("decrypt failed"))
+    Ok(out)
 }
 
-pub fn parse_hex_exact(hex_in: &str, expected_len: usize) -> Result<Vec<u8>> {
-    let bytes = hex::decode(hex_in.trim()).map_err(|_| anyhow!("invalid hex"))?;
+pub fn parse_hex_exact(
+    hex_in: &str,
+    expected_len: usize,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let bytes =
+        Zeroizing::new(hex::decode(hex_in.trim()).map_err(|_| CryptoError::BadEncoding("hex"))?);
     if bytes.len() != expected_len {
-        return Err(anyhow!(
-            "expected {} bytes, got {}",
-            expected_len,
-            bytes.len()
-        ));
+        return Err(CryptoError::BadKeyLength {
+            expected: expected_len,
+            actual: bytes.len(),
+        });
     }
     Ok(bytes)
 }
 
-pub fn decode_b64_exact(b64: &str, expected_len: usize) -> Result<Vec<u8>> {
+pub fn decode_b64_exact(b64: &str, expected_len: usize) -> Result<Vec<u8>, CryptoError> {
     let bytes = base64::engine::general_purpose::STANDARD
         .decode(b64.trim())
-        .map_err(|_| anyhow!("invalid base64"))?;
+        .map_err(|_| CryptoError::BadEncoding("base64"))?;
     if bytes.len() != expected_len {
-        return Err(anyhow!(
-            "expected {} bytes, got {}",
-            expected_len,
-            bytes.len()
-        ));
+        return Err(CryptoError::BadKeyLength {
+            expected: expected_len,
+            actual: bytes.len(),
+        });
     }
     Ok(bytes)
 }
@@ -148,5 +235,71 @@ mod tests {
         let enc = encrypt_payload(&key, &nonce, input).unwrap();
         let dec = decrypt_payload(&key, &nonce, &enc).unwrap();
         assert_eq!(input.to_vec(), dec);
+
+        let enc = encrypt_payload_with_aad(&key, &nonce, b"header", input).unwrap();
+        assert_eq!(
+            decrypt_payload_with_aad(&key, &nonce, b"header", &enc).unwrap(),
+            input
+        );
+        assert!(decrypt_payload_with_aad(&key, &nonce, b"h3ader", &enc).is_err());
+    }
+
+    #[test]
+    fn passphrase_key_matches_pbkdf2_vectors() {
+        let one = derive_passphrase_key("password", b"salt", 1).unwrap();
+        assert_eq!(
+            hex::encode(&*one),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        let two = derive_passphrase_key("password", b"salt", 2).unwrap();
+        assert_eq!(
+            hex::encode(&*two),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+        assert!(derive_passphrase_key("password", b"salt", 0).is_err());
+    }
+
+    #[test]
+    fn failures_map_to_specific_variants() {
+        let nonce = random_nonce_24();
+        let sealed = encrypt_payload(&[7u8; 32], &nonce, b"frame").unwrap();
+        assert!(matches!(
+            decrypt_payload(&[8u8; 32], &nonce, &sealed),
+            Err(CryptoError::DecryptFailed)
+        ));
+        assert!(matches!(
+            decrypt_payload(&[7u8; 16], &nonce, &sealed),
+            Err(CryptoError::BadKeyLength {
+                expected: 32,
+                actual: 16
+            })
+        ));
+        assert!(matches!(
+            parse_hex_exact("zz", 1),
+            Err(CryptoError::BadEncoding("hex"))
+        ));
+        assert!(matches!(
+            decode_b64_exact("AAAA", 32),
+            Err(CryptoError::BadKeyLength {
+                expected: 32,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn key_errors_never_echo_secret_input() {
+        let secret = "c0ffee".repeat(10);
+        let errors = [
+            parse_hex_exact(&secret, 32).unwrap_err(),
+            parse_hex_exact(&format!("{secret}zz"), 32).unwrap_err(),
+            derive_session_key(&secret, &"11".repeat(32), "AAAA", "ctx").unwrap_err(),
+            derive_session_key(&"22".repeat(32), &secret, "AAAA", "ctx").unwrap_err(),
+            compute_hello_proof(&secret, "id", "dev", "abcd", 10).unwrap_err(),
+        ];
+        for err in errors {
+            let rendered = format!("{err:#} {err:?}");
+            assert!(!rendered.contains("c0ffee"), "{rendered}");
+        }
     }
 }
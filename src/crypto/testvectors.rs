@@ -0,0 +1,209 @@
+use super::{compute_hello_proof, derive_session_key, encrypt_payload};
+use base64::Engine;
+use serde_json::{Value, json};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const IDENTITY_ID: &str = "test-identity";
+const IDENTITY_SECRET_HEX: &str =
+    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const ZONE_SECRET_HEX: &str = "a0a1a2a3a4a5a6a7a8a9aaabacadaeafb0b1b2b3b4b5b6b7b8b9babbbcbdbebf";
+const SERVER_SECRET_HEX: &str = "404142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f";
+const CLIENT_SECRET_HEX: &str = "606162636465666768696a6b6c6d6e6f707172737475767778797a7b7c7d7e7f";
+const DEVICE_PK: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const SESSION_ID: &str = "00000000-0000-4000-8000-000000000001";
+const HELLO_TS: u64 = 1_700_000_000;
+const NONCES_HEX: [&str; 2] = [
+    "0f0e0d0c0b0a09080706050403020100f0e1d2c3b4a59687",
+    "1f1e1d1c1b1a19181716151413121110e0d1c2b3a4958677",
+];
+
+fn b64(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn secret(hex_in: &str) -> StaticSecret {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&hex::decode(hex_in).expect("vector hex"));
+    StaticSecret::from(bytes)
+}
+
+fn client_key() -> String {
+    b64(PublicKey::from(&secret(CLIENT_SECRET_HEX)).as_bytes())
+}
+
+fn hello_vector(secret_hex: &str, zone: Option<&str>, protocol_version: u32) -> Value {
+    let client_key = client_key();
+    let proof = compute_hello_proof(secret_hex, IDENTITY_ID, DEVICE_PK, &client_key, HELLO_TS)
+        .expect("vector proof");
+    let mut hello = json!({
+        "type": "hello",
+        "identityId": IDENTITY_ID,
+        "devicePk": DEVICE_PK,
+        "clientKey": client_key,
+        "ts": HELLO_TS,
+        "proof": proof,
+        "protocolVersion": protocol_version,
+    });
+    if let Some(zone) = zone {
+        hello["zone"] = json!(zone);
+    }
+    json!({
+        "secretHex": secret_hex,
+        "material": format!("{IDENTITY_ID}|{DEVICE_PK}|{client_key}|{HELLO_TS}"),
+        "proofHex": proof,
+        "hello": hello,
+    })
+}
+
+fn session_key_vector(secret_hex: &str) -> (Value, Vec<u8>) {
+    let client_key = client_key();
+    let context = format!("constitute-nvr:{IDENTITY_ID}:{SESSION_ID}");
+    let (key, server_key) =
+        derive_session_key(SERVER_SECRET_HEX, secret_hex, &client_key, &context)
+            .expect("vector session key");
+    let server_pub = PublicKey::from(&secret(SERVER_SECRET_HEX));
+    let shared = secret(CLIENT_SECRET_HEX).diffie_hellman(&server_pub);
+    let vector = json!({
+        "serverSecretHex": SERVER_SECRET_HEX,
+        "saltSecretHex": secret_hex,
+        "clientSecretHex": CLIENT_SECRET_HEX,
+        "clientKey": client_key,
+        "sessionId": SESSION_ID,
+        "context": context,
+        "serverKey": server_key,
+        "sharedSecretHex": hex::encode(shared.as_bytes()),
+        "sessionKeyHex": hex::encode(&*key),
+    });
+    (vector, key.to_vec())
+}
+
+fn envelope_vector(session_key: &[u8], nonce_hex: &str, plaintext: &Value) -> Value {
+    let mut nonce = [0u8; 24];
+    nonce.copy_from_slice(&hex::decode(nonce_hex).expect("vector nonce"));
+    let plain = plaintext.to_string();
+    let cipher = encrypt_payload(session_key, &nonce, plain.as_bytes()).expect("vector seal");
+    json!({
+        "sessionKeyHex": hex::encode(session_key),
+        "nonceHex": nonce_hex,
+        "plaintext": plain,
+        "frame": {
+            "type": "cipher",
+            "nonce": b64(&nonce),
+            "data": b64(&cipher),
+        },
+    })
+}
+
+pub fn document(protocol_versions: &[u32]) -> Value {
+    let newest = protocol_versions.iter().copied().max().unwrap_or(1);
+    let (admin_key, admin_session) = session_key_vector(IDENTITY_SECRET_HEX);
+    let (zone_key, _) = session_key_vector(ZONE_SECRET_HEX);
+    json!({
+        "description": "Fixed-input vectors for the /session handshake and cipher envelope; \
+                        see docs/PROTOCOL.md, Direct Debug Session Negotiation.",
+        "protocolVersions": protocol_versions,
+        "algorithms": {
+            "helloProof": "hex(HMAC-SHA256(key = secret, message = material))",
+            "sessionKey": "HKDF-SHA256(salt = secret, ikm = X25519(serverSecret, clientKey), \
+                           info = context, length = 32)",
+            "envelope": "XChaCha20-Poly1305(key = sessionKey, nonce, plaintext = UTF-8 JSON); \
+                         data is the ciphertext with its 16-byte tag appended",
+        },
+        "helloProof": [
+            hello_vector(IDENTITY_SECRET_HEX, None, newest),
+            hello_vector(ZONE_SECRET_HEX, Some("front-yard"), newest),
+        ],
+        "sessionKey": [admin_key, zone_key],
+        "envelope": [
+            envelope_vector(
+                &admin_session,
+                NONCES_HEX[0],
+                &json!({ "cmd": "list_sources" }),
+            ),
+            envelope_vector(
+                &admin_session,
+                NONCES_HEX[1],
+                &json!({ "ok": true, "cmd": "list_sources", "sources": [] }),
+            ),
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{decrypt_payload, verify_hello_proof};
+    use super::*;
+    use hkdf::Hkdf;
+    use sha2::Sha256;
+
+    fn checked_in() -> Value {
+        serde_json::from_str(include_str!("../../docs/crypto-test-vectors.json"))
+            .expect("parse docs/crypto-test-vectors.json")
+    }
+
+    fn checked_in_versions() -> Vec<u32> {
+        serde_json::from_value(checked_in()["protocolVersions"].clone()).unwrap()
+    }
+
+    #[test]
+    fn checked_in_vectors_are_current() {
+        assert!(
+            checked_in() == document(&checked_in_versions()),
+            "docs/crypto-test-vectors.json is stale; regenerate it with \
+             `constitute-nvr --dump-test-vectors`"
+        );
+    }
+
+    #[test]
+    fn vectors_check_out_from_the_client_side() {
+        let document = document(&checked_in_versions());
+        for vector in document["helloProof"].as_array().unwrap() {
+            let hello = &vector["hello"];
+            assert!(
+                verify_hello_proof(
+                    vector["secretHex"].as_str().unwrap(),
+                    hello["identityId"].as_str().unwrap(),
+                    hello["devicePk"].as_str().unwrap(),
+                    hello["clientKey"].as_str().unwrap(),
+                    hello["ts"].as_u64().unwrap(),
+                    vector["proofHex"].as_str().unwrap(),
+                )
+                .unwrap()
+            );
+        }
+
+        for vector in document["sessionKey"].as_array().unwrap() {
+            let field = |name: &str| vector[name].as_str().unwrap().to_string();
+            let mut server_key = [0u8; 32];
+            server_key.copy_from_slice(
+                &base64::engine::general_purpose::STANDARD
+                    .decode(field("serverKey"))
+                    .unwrap(),
+            );
+            let shared =
+                secret(&field("clientSecretHex")).diffie_hellman(&PublicKey::from(server_key));
+            assert_eq!(hex::encode(shared.as_bytes()), field("sharedSecretHex"));
+            let salt = hex::decode(field("saltSecretHex")).unwrap();
+            let mut key = [0u8; 32];
+            Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+                .expand(field("context").as_bytes(), &mut key)
+                .unwrap();
+            assert_eq!(hex::encode(key), field("sessionKeyHex"));
+        }
+        assert_ne!(
+            document["sessionKey"][0]["sessionKeyHex"],
+            document["sessionKey"][1]["sessionKeyHex"]
+        );
+
+        for vector in document["envelope"].as_array().unwrap() {
+            let key = hex::decode(vector["sessionKeyHex"].as_str().unwrap()).unwrap();
+            let mut nonce = [0u8; 24];
+            nonce.copy_from_slice(&hex::decode(vector["nonceHex"].as_str().unwrap()).unwrap());
+            let data = base64::engine::general_purpose::STANDARD
+                .decode(vector["frame"]["data"].as_str().unwrap())
+                .unwrap();
+            let plain = decrypt_payload(&key, &nonce, &data).unwrap();
+            assert_eq!(plain, vector["plaintext"].as_str().unwrap().as_bytes());
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::notifications::OpsEvent;
+use serde_json::{Map, Value, json};
+use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant, sleep_until};
+
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const SLOW_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+const SLOW_SEND: Duration = Duration::from_secs(1);
+
+pub struct DashboardFeed {
+    events: broadcast::Receiver<OpsEvent>,
+    last: Map<String, Value>,
+    last_sent: Instant,
+    changed: bool,
+    slow: bool,
+}
+
+impl DashboardFeed {
+    pub fn new(events: broadcast::Receiver<OpsEvent>, snapshot: Map<String, Value>) -> Self {
+        Self {
+            events,
+            last: snapshot,
+            last_sent: Instant::now(),
+            changed: false,
+            slow: false,
+        }
+    }
+
+    fn due_at(&self) -> Instant {
+        let wait = if self.slow {
+            SLOW_SNAPSHOT_INTERVAL
+        } else if self.changed {
+            MIN_INTERVAL
+        } else {
+            REFRESH_INTERVAL
+        };
+        self.last_sent + wait
+    }
+
+    pub async fn ready(&mut self) {
+        loop {
+            tokio::select! {
+                _ = sleep_until(self.due_at()) => return,
+                event = self.events.recv() => {
+                    if !self.note(event) {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }
+        }
+    }
+
+    fn note(&mut self, event: Result<OpsEvent, broadcast::error::RecvError>) -> bool {
+        match event {
+            Ok(_) => self.changed = true,
+            Err(broadcast::error::RecvError::Lagged(_)) => self.slow = true,
+            Err(broadcast::error::RecvError::Closed) => return false,
+        }
+        true
+    }
+
+    pub fn frame(&mut self, snapshot: Map<String, Value>) -> Option<Value> {
+        self.changed = false;
+        self.last_sent = Instant::now();
+        if self.slow {
+            let frame = json!({ "cmd": "dashboard_snapshot", "dashboard": snapshot });
+            self.last = snapshot;
+            return Some(frame);
+        }
+        let changed = changed_parts(&self.last, &snapshot);
+        self.last = snapshot;
+        if changed.is_empty() {
+            return None;
+        }
+        Some(json!({ "cmd": "dashboard_delta", "changed": changed }))
+    }
+
+    pub fn sent(&mut self, took: Duration) {
+        self.slow = took > SLOW_SEND;
+    }
+}
+
+fn changed_parts(previous: &Map<String, Value>, next: &Map<String, Value>) -> Map<String, Value> {
+    let mut changed = next
+        .iter()
+        .filter(|(key, value)| previous.get(*key) != Some(value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<Map<_, _>>();
+    for key in previous.keys() {
+        if !next.contains_key(key) {
+            changed.insert(key.clone(), Value::Null);
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NotificationSeverity;
+    use crate::notifications::EventBus;
+
+    fn parts(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn feed_sends_changed_parts_coalesced_and_snapshots_when_slow() {
+        let bus = EventBus::new();
+        let initial = parts(json!({ "cameras": [1], "sessions": [], "storage": { "used": 1 } }));
+        let mut feed = DashboardFeed::new(bus.subscribe(), initial.clone());
+        // Without events the feed only wakes for the periodic recheck.
+        assert_eq!(feed.due_at(), feed.last_sent + REFRESH_INTERVAL);
+        assert_eq!(feed.frame(initial), None);
+
+        // A burst of events makes one frame due a second after the previous one.
+        for _ in 0..5 {
+            let event = OpsEvent::new(
+                "camera_down",
+                NotificationSeverity::Warning,
+                "camera front is backoff".to_string(),
+            );
+            assert!(feed.note(Ok(event)));
+        }
+        assert_eq!(feed.due_at(), feed.last_sent + MIN_INTERVAL);
+        let next = parts(json!({ "cameras": [2], "sessions": [] }));
+        let frame = feed.frame(next.clone()).unwrap();
+        assert_eq!(frame["cmd"], "dashboard_delta");
+        assert_eq!(frame["changed"], json!({ "cameras": [2], "storage": null }));
+        assert_eq!(feed.due_at(), feed.last_sent + REFRESH_INTERVAL);
+
+        // A slow send, or missed events, turn the deltas into periodic full snapshots.
+        feed.sent(SLOW_SEND * 2);
+        assert_eq!(feed.due_at(), feed.last_sent + SLOW_SNAPSHOT_INTERVAL);
+        let frame = feed.frame(next.clone()).unwrap();
+        assert_eq!(frame["cmd"], "dashboard_snapshot");
+        assert_eq!(frame["dashboard"], Value::Object(next.clone()));
+        feed.sent(Duration::from_millis(5));
+        assert_eq!(feed.frame(next.clone()), None);
+        assert!(feed.note(Err(broadcast::error::RecvError::Lagged(3))));
+        assert_eq!(feed.frame(next).unwrap()["cmd"], "dashboard_snapshot");
+    }
+}
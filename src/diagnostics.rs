@@ -0,0 +1,328 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, sleep};
+
+use crate::util;
+
+pub const CAPTURE_TARGET: &str = "constitute_nvr::diagnostics";
+pub const MAX_DURATION_SECS: u64 = 24 * 3_600;
+pub const MAX_TRACE_SECS: u64 = 3_600;
+const CRATE_TARGET: &str = "constitute_nvr";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl DiagnosticLevel {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+
+    pub fn max_duration_secs(self) -> u64 {
+        match self {
+            Self::Trace => MAX_TRACE_SECS,
+            _ => MAX_DURATION_SECS,
+        }
+    }
+}
+
+pub fn module_target(module: &str) -> Option<String> {
+    let module = module.trim();
+    let valid = !module.is_empty()
+        && module.split("::").all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_')
+        });
+    if !valid {
+        return None;
+    }
+    Some(
+        match module == CRATE_TARGET || module.starts_with("constitute_nvr::") {
+            true => module.to_string(),
+            false => format!("{CRATE_TARGET}::{module}"),
+        },
+    )
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticOverride {
+    pub id: u64,
+    pub module: Option<String>,
+    pub source_id: Option<String>,
+    pub level: DiagnosticLevel,
+    pub set_unix: u64,
+    pub expires_unix: u64,
+    pub actor_device_pk: String,
+}
+
+impl DiagnosticOverride {
+    fn directives(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        match (&self.module, &self.source_id) {
+            (Some(module), _) => out.push(format!("{module}={}", self.level.as_str())),
+            (None, None) => out.push(format!("{CRATE_TARGET}={}", self.level.as_str())),
+            (None, Some(_)) => {}
+        }
+        if self.source_id.is_some() {
+            let level = self.level.max(DiagnosticLevel::Debug);
+            out.push(format!("{CAPTURE_TARGET}={}", level.as_str()));
+        }
+        out
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsStatus {
+    pub base_filter: String,
+    pub filter: String,
+    pub overrides: Vec<DiagnosticOverride>,
+    pub max_duration_secs: u64,
+    pub max_trace_secs: u64,
+}
+
+type ApplyFilter = Arc<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
+#[derive(Default)]
+struct Inner {
+    base: String,
+    next_id: u64,
+    overrides: Vec<DiagnosticOverride>,
+}
+
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    inner: Arc<Mutex<Inner>>,
+    apply: Option<ApplyFilter>,
+}
+
+impl fmt::Debug for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Diagnostics")
+            .field("overrides", &self.lock().overrides.len())
+            .finish()
+    }
+}
+
+impl Diagnostics {
+    pub fn new(base: &str, apply: impl Fn(&str) -> Result<()> + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                base: base.to_string(),
+                ..Inner::default()
+            })),
+            apply: Some(Arc::new(apply)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn set(
+        &self,
+        module: Option<String>,
+        source_id: Option<String>,
+        level: DiagnosticLevel,
+        duration_secs: u64,
+        actor_device_pk: &str,
+    ) -> Result<DiagnosticOverride> {
+        let now = util::now_unix_seconds();
+        let duration_secs = duration_secs.clamp(1, level.max_duration_secs());
+        let entry = {
+            let mut inner = self.lock();
+            inner.next_id += 1;
+            let entry = DiagnosticOverride {
+                id: inner.next_id,
+                module,
+                source_id,
+                level,
+                set_unix: now,
+                expires_unix: now + duration_secs,
+                actor_device_pk: actor_device_pk.to_string(),
+            };
+            inner.overrides.retain(|active| {
+                active.module != entry.module || active.source_id != entry.source_id
+            });
+            inner.overrides.push(entry.clone());
+            entry
+        };
+        self.reapply()?;
+        let this = self.clone();
+        let id = entry.id;
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(duration_secs)).await;
+            if this.remove(id) {
+                tracing::info!(id, "diagnostic override expired");
+            }
+        });
+        Ok(entry)
+    }
+
+    pub fn clear(&self, module: Option<&str>, source_id: Option<&str>) -> Result<bool> {
+        let removed = {
+            let mut inner = self.lock();
+            let before = inner.overrides.len();
+            inner.overrides.retain(|active| {
+                active.module.as_deref() != module || active.source_id.as_deref() != source_id
+            });
+            inner.overrides.len() != before
+        };
+        if removed {
+            self.reapply()?;
+        }
+        Ok(removed)
+    }
+
+    fn remove(&self, id: u64) -> bool {
+        let removed = {
+            let mut inner = self.lock();
+            let before = inner.overrides.len();
+            inner.overrides.retain(|active| active.id != id);
+            inner.overrides.len() != before
+        };
+        if removed && let Err(err) = self.reapply() {
+            tracing::warn!(error = %err, "restoring the log filter failed");
+        }
+        removed
+    }
+
+    pub fn captures(&self, source_id: &str) -> bool {
+        let now = util::now_unix_seconds();
+        self.lock().overrides.iter().any(|active| {
+            active.expires_unix > now && active.source_id.as_deref() == Some(source_id)
+        })
+    }
+
+    pub fn status(&self) -> DiagnosticsStatus {
+        let inner = self.lock();
+        DiagnosticsStatus {
+            base_filter: inner.base.clone(),
+            filter: filter(&inner),
+            overrides: inner.overrides.clone(),
+            max_duration_secs: MAX_DURATION_SECS,
+            max_trace_secs: MAX_TRACE_SECS,
+        }
+    }
+
+    fn reapply(&self) -> Result<()> {
+        let filter = filter(&self.lock());
+        match &self.apply {
+            Some(apply) => apply(&filter),
+            None => Ok(()),
+        }
+    }
+}
+
+fn filter(inner: &Inner) -> String {
+    std::iter::once(inner.base.clone())
+        .chain(
+            inner
+                .overrides
+                .iter()
+                .flat_map(DiagnosticOverride::directives),
+        )
+        .filter(|directive| !directive.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules_resolve_to_crate_targets() {
+        assert_eq!(
+            module_target("recording::worker").as_deref(),
+            Some("constitute_nvr::recording::worker")
+        );
+        assert_eq!(
+            module_target("constitute_nvr::swarm").as_deref(),
+            Some("constitute_nvr::swarm")
+        );
+        assert_eq!(module_target("swarm=trace"), None);
+        assert_eq!(module_target("recording::"), None);
+        assert_eq!(module_target(""), None);
+    }
+
+    #[tokio::test]
+    async fn overrides_build_on_the_base_filter_and_can_be_cleared() {
+        let applied = Arc::new(Mutex::new(Vec::<String>::new()));
+        let seen = Arc::clone(&applied);
+        let diagnostics = Diagnostics::new("info", move |filter| {
+            seen.lock().unwrap().push(filter.to_string());
+            Ok(())
+        });
+
+        let camera = diagnostics
+            .set(
+                None,
+                Some("cam-a".into()),
+                DiagnosticLevel::Info,
+                600,
+                "admin",
+            )
+            .unwrap();
+        assert_eq!(camera.expires_unix - camera.set_unix, 600);
+        assert!(diagnostics.captures("cam-a"));
+        assert!(!diagnostics.captures("cam-b"));
+        let module = Some("constitute_nvr::recording".to_string());
+        let trace = diagnostics
+            .set(
+                module.clone(),
+                None,
+                DiagnosticLevel::Trace,
+                u64::MAX,
+                "admin",
+            )
+            .unwrap();
+        assert_eq!(trace.expires_unix - trace.set_unix, MAX_TRACE_SECS);
+        assert_eq!(
+            diagnostics.status().filter,
+            "info,constitute_nvr::diagnostics=debug,constitute_nvr::recording=trace"
+        );
+
+        // Setting the same scope again replaces the override rather than stacking.
+        diagnostics
+            .set(module.clone(), None, DiagnosticLevel::Debug, 60, "admin")
+            .unwrap();
+        assert_eq!(diagnostics.status().overrides.len(), 2);
+
+        assert!(diagnostics.clear(None, Some("cam-a")).unwrap());
+        assert!(!diagnostics.captures("cam-a"));
+        assert!(diagnostics.clear(module.as_deref(), None).unwrap());
+        assert!(!diagnostics.clear(module.as_deref(), None).unwrap());
+        assert_eq!(applied.lock().unwrap().last().unwrap(), "info");
+    }
+}
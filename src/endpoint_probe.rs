@@ -0,0 +1,247 @@
+use crate::config::SharedConfig;
+use crate::interfaces::NetworkBindings;
+use crate::util;
+use base64::Engine;
+use rand::RngCore;
+use reqwest::StatusCode;
+use reqwest::header::{
+    CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant, MissedTickBehavior, interval_at};
+use tracing::{info, warn};
+
+const PROBE_TIMEOUT_SECS: u64 = 10;
+const PROBE_TICK_SECS: u64 = 30;
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeResult {
+    pub url: String,
+    pub reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub checked_unix: u64,
+    pub latency_ms: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UrlStatus {
+    Verified,
+    Unreachable,
+    Unverified,
+}
+
+impl UrlStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Verified => "verified",
+            Self::Unreachable => "unreachable",
+            Self::Unverified => "unverified",
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct EndpointProbe {
+    latest: Arc<std::sync::Mutex<Option<ProbeResult>>>,
+}
+
+impl EndpointProbe {
+    pub fn result_for(&self, url: &str) -> Option<ProbeResult> {
+        self.latest
+            .lock()
+            .expect("endpoint probe lock")
+            .clone()
+            .filter(|result| !url.is_empty() && result.url == url)
+    }
+
+    pub fn status(&self, url: &str) -> UrlStatus {
+        match self.result_for(url) {
+            Some(result) if result.reachable => UrlStatus::Verified,
+            Some(_) => UrlStatus::Unreachable,
+            None => UrlStatus::Unverified,
+        }
+    }
+
+    pub async fn check(&self, url: &str) -> ProbeResult {
+        let before = self.status(url);
+        let result = probe(url).await;
+        match (&result.reason, before) {
+            (None, UrlStatus::Verified) | (Some(_), UrlStatus::Unreachable) => {}
+            (None, _) => info!(url = %url, "public session URL accepted an upgrade"),
+            (Some(reason), _) => {
+                warn!(url = %url, reason = %reason, "public session URL is unreachable")
+            }
+        }
+        *self.latest.lock().expect("endpoint probe lock") = Some(result.clone());
+        result
+    }
+}
+
+pub async fn probe_loop(
+    live_cfg: SharedConfig,
+    bindings: NetworkBindings,
+    announce_now: Arc<Notify>,
+) {
+    let tick = Duration::from_secs(PROBE_TICK_SECS);
+    let mut ticker = interval_at(Instant::now() + tick, tick);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last: Option<(String, Instant)> = None;
+    loop {
+        ticker.tick().await;
+        let cfg = live_cfg.snapshot();
+        let every = cfg.api.endpoint_probe_interval_secs;
+        let url = bindings.public_ws_url(&cfg).0;
+        let due = match &last {
+            Some((probed, at)) => *probed != url || at.elapsed() >= Duration::from_secs(every),
+            None => true,
+        };
+        if every == 0 || url.is_empty() || !due {
+            continue;
+        }
+        let probe = bindings.probe();
+        let before = probe.status(&url);
+        probe.check(&url).await;
+        if probe.status(&url) != before {
+            announce_now.notify_one();
+        }
+        last = Some((url, Instant::now()));
+    }
+}
+
+pub async fn probe(url: &str) -> ProbeResult {
+    let started = Instant::now();
+    let outcome = upgrade(url).await;
+    ProbeResult {
+        url: url.to_string(),
+        reachable: outcome.is_ok(),
+        reason: outcome.err(),
+        checked_unix: util::now_unix_seconds(),
+        latency_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+    }
+}
+
+async fn upgrade(url: &str) -> Result<(), String> {
+    let mut target =
+        reqwest::Url::parse(url.trim()).map_err(|err| format!("invalid URL: {err}"))?;
+    let scheme = match target.scheme() {
+        "ws" => "http",
+        "wss" => "https",
+        other => return Err(format!("unsupported scheme {other}")),
+    };
+    target
+        .set_scheme(scheme)
+        .map_err(|()| format!("unsupported URL {url}"))?;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let key = base64::engine::general_purpose::STANDARD.encode(nonce);
+    let response = client
+        .get(target)
+        .header(CONNECTION, "Upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_VERSION, "13")
+        .header(SEC_WEBSOCKET_KEY, &key)
+        .send()
+        .await
+        .map_err(describe)?;
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(format!(
+            "answered HTTP {} instead of upgrading",
+            response.status().as_u16()
+        ));
+    }
+    let accept = response
+        .headers()
+        .get(SEC_WEBSOCKET_ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    if accept != Some(accept_key(&key).as_str()) {
+        return Err("upgraded without a matching Sec-WebSocket-Accept".to_string());
+    }
+    // Dropping the response closes the connection; the server sees a socket that never
+    // sent a hello, which costs no handshake failure.
+    Ok(())
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{key}{WS_GUID}").as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+fn describe(err: reqwest::Error) -> String {
+    if err.is_timeout() {
+        return format!("no answer within {PROBE_TIMEOUT_SECS}s");
+    }
+    let mut cause: &dyn std::error::Error = &err;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    if err.is_connect() {
+        format!("connect failed: {cause}")
+    } else {
+        cause.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::WebSocketUpgrade;
+    use axum::routing::get;
+
+    #[test]
+    fn accept_key_matches_the_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn only_an_upgraded_url_is_verified() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/session",
+            get(|ws: WebSocketUpgrade| async move { ws.on_upgrade(|_| async {}) }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let probe = EndpointProbe::default();
+        let url = format!("ws://{addr}/session");
+        assert_eq!(probe.status(&url), UrlStatus::Unverified);
+        let result = probe.check(&url).await;
+        assert!(result.reachable, "{result:?}");
+        assert_eq!(probe.status(&url), UrlStatus::Verified);
+        assert_eq!(
+            probe.status("ws://elsewhere/session"),
+            UrlStatus::Unverified
+        );
+
+        let wrong_path = probe.check(&format!("ws://{addr}/other")).await;
+        assert_eq!(
+            wrong_path.reason.as_deref(),
+            Some("answered HTTP 404 instead of upgrading")
+        );
+        assert_eq!(probe.status(&url), UrlStatus::Unverified);
+
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_url = format!("ws://{}/session", closed.local_addr().unwrap());
+        drop(closed);
+        let refused = probe.check(&closed_url).await;
+        assert!(refused.reason.unwrap().starts_with("connect failed"));
+        assert_eq!(probe.status(&closed_url), UrlStatus::Unreachable);
+        let scheme = probe.check("https://nvr.example/session").await;
+        assert_eq!(scheme.reason.as_deref(), Some("unsupported scheme https"));
+    }
+}
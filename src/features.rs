@@ -0,0 +1,320 @@
+use crate::config::{Config, UpdateMode};
+use crate::media::dependencies::MediaDependencies;
+use serde::Serialize;
+
+pub const SESSION_PROTOCOL_VERSION: u32 = 2;
+pub const SEGMENT_CHUNK_BYTES: usize = 48 * 1024;
+const MAX_CONCURRENT_TRANSFERS: usize = 1;
+
+const BUILTIN_FEATURES: &[&str] = &[
+    "segment_chunks",
+    "segment_binary_chunks",
+    "snapshots",
+    "privacy",
+    "purge_range",
+    "stats",
+    "session_options",
+    "source_drafts",
+    "protocol_schema",
+    "zone_sessions",
+    "maintenance_jobs",
+    "permissions",
+    "shares",
+    "self_check",
+    "source_bundles",
+    "job_progress",
+    "swarm_devices",
+    "dashboard_stream",
+    "session_timezone",
+];
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionLimits {
+    pub max_chunk_bytes: usize,
+    pub max_envelope_bytes: usize,
+    pub max_concurrent_transfers: usize,
+    pub max_cameras: usize,
+    pub protocol_versions: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CapabilityStatus {
+    Available,
+    DisabledByConfig,
+    Unavailable,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CapabilityReason {
+    Dependency { name: &'static str },
+    Config { key: &'static str },
+    Camera { capability: &'static str },
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capability {
+    pub feature: &'static str,
+    pub status: CapabilityStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<CapabilityReason>,
+}
+
+impl Capability {
+    fn gated<const N: usize>(
+        feature: &'static str,
+        blockers: [Option<CapabilityReason>; N],
+    ) -> Self {
+        let reason = blockers.into_iter().flatten().next();
+        let status = match reason {
+            None => CapabilityStatus::Available,
+            Some(CapabilityReason::Config { .. }) => CapabilityStatus::DisabledByConfig,
+            Some(_) => CapabilityStatus::Unavailable,
+        };
+        Self {
+            feature,
+            status,
+            reason,
+        }
+    }
+}
+
+fn missing(absent: bool, name: &'static str) -> Option<CapabilityReason> {
+    absent.then_some(CapabilityReason::Dependency { name })
+}
+
+fn switched_off(off: bool, key: &'static str) -> Option<CapabilityReason> {
+    off.then_some(CapabilityReason::Config { key })
+}
+
+pub fn capabilities(cfg: &Config, dependencies: &MediaDependencies) -> Vec<Capability> {
+    let ffmpeg = || missing(!dependencies.ffmpeg.available, "ffmpeg");
+    let ptz_cameras = cfg
+        .camera_devices
+        .iter()
+        .filter(|camera| camera.ptz_capable)
+        .collect::<Vec<_>>();
+    let ptz = if ptz_cameras.is_empty() {
+        Some(CapabilityReason::Camera { capability: "ptz" })
+    } else {
+        switched_off(
+            !ptz_cameras.iter().any(|camera| camera.enabled),
+            "camera_devices.enabled",
+        )
+    };
+    vec![
+        Capability::gated(
+            "recording",
+            [
+                ffmpeg(),
+                missing(!dependencies.segment_muxer, "ffmpeg_segment_muxer"),
+            ],
+        ),
+        Capability::gated("live_preview", [ffmpeg()]),
+        Capability::gated("media_stream", [ffmpeg()]),
+        Capability::gated(
+            "transcode",
+            [ffmpeg(), missing(!dependencies.libx264, "libx264")],
+        ),
+        Capability::gated(
+            "hwaccel",
+            [
+                ffmpeg(),
+                missing(dependencies.hwaccels.is_empty(), "hwaccel"),
+            ],
+        ),
+        Capability::gated(
+            "latest_frames",
+            [
+                switched_off(
+                    cfg.live_preview.latest_frame_interval_secs == 0,
+                    "live_preview.latest_frame_interval_secs",
+                ),
+                ffmpeg(),
+            ],
+        ),
+        Capability::gated("ptz", [ptz]),
+        Capability::gated(
+            "webhooks",
+            [switched_off(
+                cfg.notifications.webhooks.is_empty(),
+                "notifications.webhooks",
+            )],
+        ),
+        Capability::gated("mqtt", [switched_off(!cfg.mqtt.enabled, "mqtt.enabled")]),
+        Capability::gated(
+            "mqtt_commands",
+            [
+                switched_off(!cfg.mqtt.enabled, "mqtt.enabled"),
+                switched_off(!cfg.mqtt.allow_commands, "mqtt.allow_commands"),
+            ],
+        ),
+        Capability::gated(
+            "replication",
+            [switched_off(
+                cfg.replication.partner.trim().is_empty(),
+                "replication.partner",
+            )],
+        ),
+        Capability::gated(
+            "update_control",
+            [
+                switched_off(!cfg.update.enabled, "update.enabled"),
+                switched_off(
+                    cfg.update.mode != UpdateMode::ReleaseArtifact,
+                    "update.mode",
+                ),
+            ],
+        ),
+    ]
+}
+
+pub fn session_features(cfg: &Config, dependencies: &MediaDependencies) -> Vec<String> {
+    BUILTIN_FEATURES
+        .iter()
+        .copied()
+        .chain(
+            capabilities(cfg, dependencies)
+                .into_iter()
+                .filter(|capability| capability.status == CapabilityStatus::Available)
+                .map(|capability| capability.feature),
+        )
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn session_limits(cfg: &Config) -> SessionLimits {
+    SessionLimits {
+        max_chunk_bytes: SEGMENT_CHUNK_BYTES,
+        max_envelope_bytes: cfg.api.max_envelope_bytes,
+        max_concurrent_transfers: MAX_CONCURRENT_TRANSFERS,
+        max_cameras: cfg.api.max_cameras,
+        protocol_versions: (1..=SESSION_PROTOCOL_VERSION).collect(),
+    }
+}
+
+pub fn negotiate_protocol_version(requested: Option<u32>) -> u32 {
+    requested
+        .filter(|version| *version > 0)
+        .map_or(1, |version| version.min(SESSION_PROTOCOL_VERSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_follow_config_and_dependencies() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-features-test-{}.json",
+            std::process::id()
+        ));
+        let mut cfg = Config::load_or_create(&path).expect("create temp config").0;
+        let _ = std::fs::remove_file(&path);
+
+        let features = session_features(&cfg, &MediaDependencies::default());
+        assert!(features.iter().any(|feature| feature == "segment_chunks"));
+        assert!(!features.iter().any(|feature| feature == "recording"));
+        assert!(!features.iter().any(|feature| feature == "mqtt"));
+
+        cfg.mqtt.enabled = true;
+        let features = session_features(&cfg, &MediaDependencies::default());
+        assert!(features.iter().any(|feature| feature == "mqtt"));
+        assert!(!features.iter().any(|feature| feature == "mqtt_commands"));
+        assert!(features.iter().any(|feature| feature == "update_control"));
+
+        cfg.update.mode = UpdateMode::SourceBuild;
+        let features = session_features(&cfg, &MediaDependencies::default());
+        assert!(!features.iter().any(|feature| feature == "update_control"));
+        assert_eq!(session_limits(&cfg).max_chunk_bytes, SEGMENT_CHUNK_BYTES);
+        assert_eq!(negotiate_protocol_version(None), 1);
+        assert_eq!(negotiate_protocol_version(Some(0)), 1);
+        assert_eq!(
+            negotiate_protocol_version(Some(u32::MAX)),
+            SESSION_PROTOCOL_VERSION
+        );
+        // A new version needs its crypto test vectors too.
+        let vectors: serde_json::Value =
+            serde_json::from_str(include_str!("../docs/crypto-test-vectors.json")).unwrap();
+        assert_eq!(
+            vectors["protocolVersions"],
+            serde_json::json!(session_limits(&cfg).protocol_versions),
+            "regenerate docs/crypto-test-vectors.json with `constitute-nvr --dump-test-vectors`"
+        );
+    }
+
+    #[test]
+    fn capabilities_say_why_a_feature_is_missing() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-capabilities-test-{}.json",
+            std::process::id()
+        ));
+        let mut cfg = Config::load_or_create(&path).expect("create temp config").0;
+        let _ = std::fs::remove_file(&path);
+        let status = |cfg: &Config, dependencies: &MediaDependencies, feature: &str| {
+            let capability = capabilities(cfg, dependencies)
+                .into_iter()
+                .find(|capability| capability.feature == feature)
+                .unwrap();
+            (capability.status, capability.reason)
+        };
+
+        let mut dependencies = MediaDependencies::default();
+        assert_eq!(
+            status(&cfg, &dependencies, "transcode"),
+            (
+                CapabilityStatus::Unavailable,
+                Some(CapabilityReason::Dependency { name: "ffmpeg" })
+            )
+        );
+        dependencies.ffmpeg.available = true;
+        assert_eq!(
+            status(&cfg, &dependencies, "transcode").1,
+            Some(CapabilityReason::Dependency { name: "libx264" })
+        );
+        dependencies.libx264 = true;
+        assert_eq!(
+            status(&cfg, &dependencies, "transcode"),
+            (CapabilityStatus::Available, None)
+        );
+
+        assert_eq!(
+            status(&cfg, &dependencies, "mqtt_commands"),
+            (
+                CapabilityStatus::DisabledByConfig,
+                Some(CapabilityReason::Config {
+                    key: "mqtt.enabled"
+                })
+            )
+        );
+        cfg.mqtt.enabled = true;
+        assert_eq!(
+            status(&cfg, &dependencies, "mqtt_commands").1,
+            Some(CapabilityReason::Config {
+                key: "mqtt.allow_commands"
+            })
+        );
+        assert_eq!(
+            status(&cfg, &dependencies, "ptz"),
+            (
+                CapabilityStatus::Unavailable,
+                Some(CapabilityReason::Camera { capability: "ptz" })
+            )
+        );
+
+        let advertised = session_features(&cfg, &dependencies);
+        for capability in capabilities(&cfg, &dependencies) {
+            assert_eq!(
+                advertised
+                    .iter()
+                    .any(|feature| feature == capability.feature),
+                capability.status == CapabilityStatus::Available,
+                "{}",
+                capability.feature
+            );
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::ApiConfig;
+use crate::util;
+
+const FAILURE_DELAY: Duration = Duration::from_millis(200);
+const PENALTY_BASE: Duration = Duration::from_millis(250);
+const PENALTY_MAX: Duration = Duration::from_secs(30);
+const FAILURE_MEMORY: Duration = Duration::from_secs(15 * 60);
+const COOKIE_WINDOW_SECS: u64 = 300;
+const COOKIE_BYTES: usize = 16;
+const MAX_TRACKED_ADDRS: usize = 4096;
+const REJECTIONS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    GlobalLimit,
+    AddrLimit,
+    Timeout,
+    Oversized,
+    Malformed,
+    CookieRequired,
+    InvalidCookie,
+    AuthFailed,
+}
+
+impl Rejection {
+    const ALL: [Rejection; REJECTIONS] = [
+        Rejection::GlobalLimit,
+        Rejection::AddrLimit,
+        Rejection::Timeout,
+        Rejection::Oversized,
+        Rejection::Malformed,
+        Rejection::CookieRequired,
+        Rejection::InvalidCookie,
+        Rejection::AuthFailed,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rejection::GlobalLimit => "global_limit",
+            Rejection::AddrLimit => "addr_limit",
+            Rejection::Timeout => "timeout",
+            Rejection::Oversized => "oversized",
+            Rejection::Malformed => "malformed",
+            Rejection::CookieRequired => "cookie_required",
+            Rejection::InvalidCookie => "invalid_cookie",
+            Rejection::AuthFailed => "auth_failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HandshakeLimits {
+    pub max_hello_bytes: usize,
+    pub max_pending: usize,
+    pub max_pending_per_addr: usize,
+    pub cookie_after_failures: u32,
+}
+
+impl HandshakeLimits {
+    pub fn from_api(api: &ApiConfig) -> Self {
+        Self {
+            max_hello_bytes: api.max_hello_bytes.max(1),
+            max_pending: api.max_pending_handshakes.max(1),
+            max_pending_per_addr: api.max_pending_handshakes_per_addr.max(1),
+            cookie_after_failures: api.hello_cookie_after_failures,
+        }
+    }
+}
+
+#[derive(Default)]
+struct AddrState {
+    pending: usize,
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl AddrState {
+    fn failures(&self, now: Instant) -> u32 {
+        match self.last_failure {
+            Some(at) if now.saturating_duration_since(at) < FAILURE_MEMORY => self.failures,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct GuardState {
+    pending: usize,
+    addrs: HashMap<IpAddr, AddrState>,
+}
+
+struct Inner {
+    state: Mutex<GuardState>,
+    cookie_secret: [u8; 32],
+    rejections: [AtomicU64; REJECTIONS],
+}
+
+#[derive(Clone)]
+pub struct HandshakeGuard {
+    inner: Arc<Inner>,
+}
+
+impl Default for HandshakeGuard {
+    fn default() -> Self {
+        let mut cookie_secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut cookie_secret);
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(GuardState::default()),
+                cookie_secret,
+                rejections: Default::default(),
+            }),
+        }
+    }
+}
+
+pub struct HandshakePermit {
+    guard: HandshakeGuard,
+    addr: IpAddr,
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        let mut state = self.guard.lock();
+        state.pending = state.pending.saturating_sub(1);
+        if let Some(entry) = state.addrs.get_mut(&self.addr) {
+            entry.pending = entry.pending.saturating_sub(1);
+        }
+    }
+}
+
+impl HandshakeGuard {
+    fn lock(&self) -> std::sync::MutexGuard<'_, GuardState> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn record(&self, rejection: Rejection) {
+        self.inner.rejections[rejection as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn admit(
+        &self,
+        remote: IpAddr,
+        limits: HandshakeLimits,
+    ) -> Result<HandshakePermit, Rejection> {
+        let addr = addr_key(remote);
+        let mut state = self.lock();
+        let rejection = if state.pending >= limits.max_pending {
+            Some(Rejection::GlobalLimit)
+        } else if state
+            .addrs
+            .get(&addr)
+            .is_some_and(|entry| entry.pending >= limits.max_pending_per_addr)
+        {
+            Some(Rejection::AddrLimit)
+        } else {
+            None
+        };
+        if let Some(rejection) = rejection {
+            drop(state);
+            self.record(rejection);
+            return Err(rejection);
+        }
+        if state.addrs.len() >= MAX_TRACKED_ADDRS && !state.addrs.contains_key(&addr) {
+            let now = Instant::now();
+            state
+                .addrs
+                .retain(|_, entry| entry.pending > 0 || entry.failures(now) > 0);
+        }
+        state.pending += 1;
+        state.addrs.entry(addr).or_default().pending += 1;
+        drop(state);
+        Ok(HandshakePermit {
+            guard: self.clone(),
+            addr,
+        })
+    }
+
+    pub fn cookie_required(&self, permit: &HandshakePermit, limits: HandshakeLimits) -> bool {
+        limits.cookie_after_failures > 0
+            && self
+                .lock()
+                .addrs
+                .get(&permit.addr)
+                .is_some_and(|entry| entry.failures(Instant::now()) >= limits.cookie_after_failures)
+    }
+
+    pub fn cookie(&self, permit: &HandshakePermit) -> String {
+        self.cookie_for(permit.addr, util::now_unix_seconds() / COOKIE_WINDOW_SECS)
+    }
+
+    pub fn verify_cookie(&self, permit: &HandshakePermit, cookie: &str) -> bool {
+        let cookie = match hex::decode(cookie.trim()) {
+            Ok(cookie) if cookie.len() == COOKIE_BYTES => cookie,
+            _ => return false,
+        };
+        let window = util::now_unix_seconds() / COOKIE_WINDOW_SECS;
+        [window, window.saturating_sub(1)]
+            .into_iter()
+            .any(|window| {
+                self.cookie_mac(permit.addr, window)
+                    .verify_truncated_left(&cookie)
+                    .is_ok()
+            })
+    }
+
+    fn cookie_mac(&self, addr: IpAddr, window: u64) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.inner.cookie_secret)
+            .expect("hmac accepts any key length");
+        mac.update(&window.to_be_bytes());
+        mac.update(addr.to_string().as_bytes());
+        mac
+    }
+
+    fn cookie_for(&self, addr: IpAddr, window: u64) -> String {
+        let tag = self.cookie_mac(addr, window).finalize().into_bytes();
+        hex::encode(&tag[..COOKIE_BYTES])
+    }
+
+    pub fn failed(&self, permit: &HandshakePermit, rejection: Rejection) -> Duration {
+        self.record(rejection);
+        let now = Instant::now();
+        let mut state = self.lock();
+        let entry = state.addrs.entry(permit.addr).or_default();
+        entry.failures = entry.failures(now).saturating_add(1);
+        entry.last_failure = Some(now);
+        FAILURE_DELAY + penalty(entry.failures)
+    }
+
+    pub fn succeeded(&self, permit: &HandshakePermit) {
+        if let Some(entry) = self.lock().addrs.get_mut(&permit.addr) {
+            entry.failures = 0;
+            entry.last_failure = None;
+        }
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        let name = "constitute_nvr_handshakes_pending";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Session hellos admitted and not yet answered."
+        );
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {}", self.lock().pending);
+        let name = "constitute_nvr_handshake_rejections_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Session handshakes refused before a session opened."
+        );
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for rejection in Rejection::ALL {
+            let _ = writeln!(
+                out,
+                "{name}{{reason=\"{}\"}} {}",
+                rejection.as_str(),
+                self.inner.rejections[rejection as usize].load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
+
+fn penalty(failures: u32) -> Duration {
+    if failures <= 1 {
+        return Duration::ZERO;
+    }
+    PENALTY_BASE
+        .saturating_mul(1u32 << (failures - 2).min(16))
+        .min(PENALTY_MAX)
+}
+
+fn addr_key(remote: IpAddr) -> IpAddr {
+    match remote {
+        IpAddr::V4(_) => remote,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => {
+                let prefix = u128::from(v6) & !((1u128 << 64) - 1);
+                IpAddr::V6(prefix.into())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> HandshakeLimits {
+        HandshakeLimits {
+            max_hello_bytes: 1024,
+            max_pending: 3,
+            max_pending_per_addr: 2,
+            cookie_after_failures: 2,
+        }
+    }
+
+    #[test]
+    fn slots_are_capped_per_address_and_node_wide() {
+        let guard = HandshakeGuard::default();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "2001:db8::1".parse().unwrap();
+        let b_sibling: IpAddr = "2001:db8::ffff".parse().unwrap();
+
+        let first = guard.admit(a, limits()).unwrap();
+        let _second = guard.admit(a, limits()).unwrap();
+        assert_eq!(guard.admit(a, limits()).err(), Some(Rejection::AddrLimit));
+        let _third = guard.admit(b, limits()).unwrap();
+        assert_eq!(
+            guard.admit(b_sibling, limits()).err(),
+            Some(Rejection::GlobalLimit)
+        );
+
+        drop(first);
+        let _again = guard.admit(a, limits()).unwrap();
+        let metrics = guard.render_prometheus();
+        assert!(
+            metrics.contains("constitute_nvr_handshakes_pending 3"),
+            "{metrics}"
+        );
+        assert!(metrics.contains("{reason=\"addr_limit\"} 1"), "{metrics}");
+        assert!(metrics.contains("{reason=\"global_limit\"} 1"), "{metrics}");
+    }
+
+    #[test]
+    fn failures_escalate_to_penalties_and_cookies_until_a_success() {
+        let guard = HandshakeGuard::default();
+        let permit = guard.admit("10.0.0.2".parse().unwrap(), limits()).unwrap();
+        let other = guard.admit("10.0.0.3".parse().unwrap(), limits()).unwrap();
+
+        assert_eq!(guard.failed(&permit, Rejection::AuthFailed), FAILURE_DELAY);
+        assert!(!guard.cookie_required(&permit, limits()));
+        assert_eq!(
+            guard.failed(&permit, Rejection::AuthFailed),
+            FAILURE_DELAY + PENALTY_BASE
+        );
+        assert_eq!(
+            guard.failed(&permit, Rejection::AuthFailed),
+            FAILURE_DELAY + PENALTY_BASE * 2
+        );
+        assert_eq!(penalty(40), PENALTY_MAX);
+        assert!(guard.cookie_required(&permit, limits()));
+        assert!(!guard.cookie_required(&other, limits()));
+
+        let cookie = guard.cookie(&permit);
+        assert!(guard.verify_cookie(&permit, &cookie));
+        assert!(!guard.verify_cookie(&other, &cookie));
+        assert!(!guard.verify_cookie(&permit, &cookie[..8]));
+        assert!(!guard.verify_cookie(&permit, "not-hex"));
+
+        guard.succeeded(&permit);
+        assert!(!guard.cookie_required(&permit, limits()));
+        assert_eq!(guard.failed(&permit, Rejection::Malformed), FAILURE_DELAY);
+    }
+}
@@ -0,0 +1,137 @@
+use serde::Serialize;
+
+use crate::config::CameraDeviceConfig;
+use crate::stats::StatsRegistry;
+use crate::storage::DiskUsage;
+
+const CONFIDENT_AFTER_SECS: u64 = 3_600;
+const DAY_HOURS: f64 = 24.0;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceHeadroom {
+    pub source_id: String,
+    pub state: &'static str,
+    pub bytes_per_hour: Option<u64>,
+    pub observed_secs: u64,
+    pub low_confidence: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Headroom {
+    pub bytes_per_day: Option<u64>,
+    pub days_until_full: Option<f64>,
+    pub retention_horizon_days: Option<f64>,
+    pub low_confidence: bool,
+    pub sources: Vec<SourceHeadroom>,
+}
+
+pub fn estimate(
+    cameras: &[CameraDeviceConfig],
+    stats: &StatsRegistry,
+    usage: Option<DiskUsage>,
+) -> Headroom {
+    let sources = cameras
+        .iter()
+        .map(|camera| {
+            let rate = stats.ingest_rate(&camera.source_id);
+            let observed_secs = rate.map_or(0, |rate| rate.observed_secs);
+            let (state, bytes_per_hour) = match rate {
+                _ if !camera.is_capturing() => ("idle", Some(0)),
+                Some(rate) => ("recording", Some(rate.bytes_per_hour().round() as u64)),
+                None => ("unknown", None),
+            };
+            SourceHeadroom {
+                source_id: camera.source_id.clone(),
+                state,
+                bytes_per_hour,
+                observed_secs,
+                low_confidence: state != "idle" && observed_secs < CONFIDENT_AFTER_SECS,
+            }
+        })
+        .collect::<Vec<_>>();
+    let bytes_per_day = sources
+        .iter()
+        .any(|source| source.state != "unknown")
+        .then(|| {
+            let per_hour = sources
+                .iter()
+                .filter_map(|source| source.bytes_per_hour)
+                .sum::<u64>();
+            (per_hour as f64 * DAY_HOURS) as u64
+        });
+    let days = |bytes: u64| match bytes_per_day {
+        Some(per_day) if per_day > 0 => Some(round_tenth(bytes as f64 / per_day as f64)),
+        _ => None,
+    };
+    Headroom {
+        bytes_per_day,
+        days_until_full: usage.and_then(|usage| days(usage.available_bytes)),
+        retention_horizon_days: usage
+            .and_then(|usage| days(usage.used_bytes.saturating_add(usage.available_bytes))),
+        low_confidence: sources.iter().any(|source| source.low_confidence),
+        sources,
+    }
+}
+
+fn round_tenth(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+    use crate::stats::Counter;
+
+    fn camera(source_id: &str, enabled: bool) -> CameraDeviceConfig {
+        CameraDeviceConfig {
+            enabled,
+            ..test_support::camera(source_id)
+        }
+    }
+
+    #[test]
+    fn idle_and_new_sources_neither_add_rate_nor_report_infinity() {
+        let stats = StatsRegistry::default();
+        let usage = DiskUsage {
+            total_bytes: 1_000_000,
+            used_bytes: 200_000,
+            available_bytes: 800_000,
+            used_percent: 20.0,
+        };
+
+        let fresh = estimate(&[camera("cam-new", true)], &stats, Some(usage));
+        assert_eq!(fresh.bytes_per_day, None);
+        assert_eq!(fresh.days_until_full, None);
+        assert!(fresh.low_confidence);
+        assert_eq!(fresh.sources[0].state, "unknown");
+
+        stats.record("cam-a", Counter::CiphertextBytes, 5_000);
+        stats.record("cam-off", Counter::CiphertextBytes, 9_000);
+        let cameras = [camera("cam-a", true), camera("cam-off", false)];
+        let headroom = estimate(&cameras, &stats, Some(usage));
+        let rate = stats.ingest_rate("cam-a").unwrap();
+        assert_eq!(rate.bytes, 5_000);
+        let per_day = (rate.bytes_per_hour().round() * DAY_HOURS) as u64;
+        assert_eq!(headroom.bytes_per_day, Some(per_day));
+        assert_eq!(
+            headroom.days_until_full,
+            Some(round_tenth(800_000.0 / per_day as f64))
+        );
+        assert_eq!(
+            headroom.retention_horizon_days,
+            Some(round_tenth(1_000_000.0 / per_day as f64))
+        );
+        assert!(headroom.low_confidence);
+        assert_eq!(headroom.sources[1].state, "idle");
+        assert_eq!(headroom.sources[1].bytes_per_hour, Some(0));
+        assert!(!headroom.sources[1].low_confidence);
+
+        let stopped = estimate(&[camera("cam-off", false)], &stats, Some(usage));
+        assert_eq!(stopped.bytes_per_day, Some(0));
+        assert_eq!(stopped.days_until_full, None);
+        assert!(!stopped.low_confidence);
+    }
+}
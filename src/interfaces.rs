@@ -0,0 +1,352 @@
+use crate::config::Config;
+use crate::endpoint_probe::EndpointProbe;
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{Duration, MissedTickBehavior, interval};
+use tracing::{info, warn};
+
+pub const INTERFACE_POLL_SECS: u64 = 15;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostAddr {
+    pub interface: String,
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+pub fn host_addrs() -> Vec<HostAddr> {
+    match Command::new("ip").args(["-o", "addr", "show"]).output() {
+        Ok(output) if output.status.success() => {
+            parse_addrs(&String::from_utf8_lossy(&output.stdout))
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn parse_addrs(output: &str) -> Vec<HostAddr> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let interface = parts.get(1)?.split('@').next()?.trim_end_matches(':');
+            let idx = parts
+                .iter()
+                .position(|part| *part == "inet" || *part == "inet6")?;
+            let (ip, prefix) = parts.get(idx + 1)?.split_once('/')?;
+            let ip = ip.parse::<IpAddr>().ok()?;
+            if matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local()) {
+                return None;
+            }
+            Some(HostAddr {
+                interface: interface.to_string(),
+                ip,
+                prefix: prefix.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+pub fn select(selector: &str, addrs: &[HostAddr], current: Option<IpAddr>) -> Option<IpAddr> {
+    let selector = selector.trim();
+    let network = selector
+        .split_once('/')
+        .and_then(|(ip, prefix)| Some((ip.parse::<IpAddr>().ok()?, prefix.parse::<u8>().ok()?)));
+    let candidates = addrs
+        .iter()
+        .filter(|addr| match network {
+            Some((network, prefix)) => in_network(addr.ip, network, prefix),
+            None => addr.interface == selector,
+        })
+        .map(|addr| addr.ip)
+        .collect::<Vec<_>>();
+    if let Some(current) = current.filter(|ip| candidates.contains(ip)) {
+        return Some(current);
+    }
+    candidates
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or_else(|| candidates.first())
+        .copied()
+}
+
+fn resolve_bind(
+    key: &str,
+    bind: &str,
+    selector: &str,
+    addrs: &[HostAddr],
+    current: Option<IpAddr>,
+) -> Result<Option<SocketAddr>> {
+    if selector.trim().is_empty() {
+        return Ok(None);
+    }
+    let port = bind
+        .trim()
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse::<u16>().ok())
+        .with_context(|| format!("{key}.bind has no port: {bind}"))?;
+    match select(selector, addrs, current) {
+        Some(ip) => Ok(Some(SocketAddr::new(ip, port))),
+        None => bail!("{key}.interface {selector} has no usable address"),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Selected {
+    pub swarm: Option<SocketAddr>,
+    pub api: Option<SocketAddr>,
+}
+
+impl Selected {
+    fn resolve(cfg: &Config, addrs: &[HostAddr], current: Selected) -> Result<Self> {
+        Ok(Self {
+            swarm: resolve_bind(
+                "swarm",
+                &cfg.swarm.bind,
+                &cfg.swarm.interface,
+                addrs,
+                current.swarm.map(|addr| addr.ip()),
+            )?,
+            api: resolve_bind(
+                "api",
+                &cfg.api.bind,
+                &cfg.api.interface,
+                addrs,
+                current.api.map(|addr| addr.ip()),
+            )?,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct NetworkBindings {
+    selected: watch::Receiver<Selected>,
+    bound: Arc<std::sync::Mutex<Selected>>,
+    probe: EndpointProbe,
+}
+
+impl NetworkBindings {
+    pub fn start(cfg: &Config) -> Result<Self> {
+        let interfaces =
+            !cfg.swarm.interface.trim().is_empty() || !cfg.api.interface.trim().is_empty();
+        let initial = if interfaces {
+            Selected::resolve(cfg, &host_addrs(), Selected::default())?
+        } else {
+            Selected::default()
+        };
+        let (tx, selected) = watch::channel(initial);
+        if interfaces {
+            tokio::spawn(poll_loop(cfg.clone(), tx));
+        }
+        Ok(Self {
+            selected,
+            bound: Arc::default(),
+            probe: EndpointProbe::default(),
+        })
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Selected> {
+        self.selected.clone()
+    }
+
+    pub fn selected(&self) -> Selected {
+        *self.selected.borrow()
+    }
+
+    pub fn set_swarm_bound(&self, addr: SocketAddr) {
+        self.bound.lock().expect("bindings lock").swarm = Some(addr);
+    }
+
+    pub fn set_api_bound(&self, addr: SocketAddr) {
+        self.bound.lock().expect("bindings lock").api = Some(addr);
+    }
+
+    pub fn probe(&self) -> &EndpointProbe {
+        &self.probe
+    }
+
+    fn bound(&self) -> Selected {
+        *self.bound.lock().expect("bindings lock")
+    }
+
+    pub fn endpoint_hint(&self, cfg: &Config) -> (String, bool) {
+        let hint = cfg.swarm.endpoint_hint.trim();
+        if !hint.is_empty() {
+            return (hint.to_string(), false);
+        }
+        match self
+            .bound()
+            .swarm
+            .filter(|addr| !addr.ip().is_unspecified())
+        {
+            Some(addr) => (format!("udp://{addr}"), true),
+            None => (String::new(), false),
+        }
+    }
+
+    pub fn public_ws_url(&self, cfg: &Config) -> (String, bool) {
+        let url = cfg.api.public_ws_url.trim();
+        if !url.is_empty() {
+            return (url.to_string(), false);
+        }
+        match self.bound().api.filter(|addr| !addr.ip().is_unspecified()) {
+            Some(addr) => (format!("ws://{addr}/session"), true),
+            None => (String::new(), false),
+        }
+    }
+
+    pub fn apply_derived(&self, cfg: &mut Config) {
+        cfg.swarm.endpoint_hint = self.endpoint_hint(cfg).0;
+        cfg.api.public_ws_url = self.public_ws_url(cfg).0;
+    }
+
+    pub fn health(&self, cfg: &Config) -> Value {
+        let bound = self.bound();
+        let (endpoint_hint, hint_derived) = self.endpoint_hint(cfg);
+        let (public_ws_url, url_derived) = self.public_ws_url(cfg);
+        json!({
+            "swarm": {
+                "interface": cfg.swarm.interface,
+                "bound": bound.swarm.map(|addr| addr.to_string()),
+                "endpointHint": endpoint_hint,
+                "endpointHintDerived": hint_derived,
+            },
+            "api": {
+                "interface": cfg.api.interface,
+                "bound": bound.api.map(|addr| addr.to_string()),
+                "publicWsUrl": public_ws_url,
+                "publicWsUrlDerived": url_derived,
+                "publicWsUrlStatus": self.probe.status(&public_ws_url).as_str(),
+                "publicWsUrlProbe": self.probe.result_for(&public_ws_url),
+            },
+        })
+    }
+}
+
+async fn poll_loop(cfg: Config, tx: watch::Sender<Selected>) {
+    let mut ticker = interval(Duration::from_secs(INTERFACE_POLL_SECS));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut missing = false;
+    loop {
+        ticker.tick().await;
+        let addrs = tokio::task::spawn_blocking(host_addrs)
+            .await
+            .unwrap_or_default();
+        let current = *tx.borrow();
+        match Selected::resolve(&cfg, &addrs, current) {
+            Ok(next) => {
+                missing = false;
+                if next != current {
+                    info!(
+                        ?current,
+                        ?next,
+                        "bound interface address changed; rebinding"
+                    );
+                    tx.send_replace(next);
+                }
+            }
+            Err(err) => {
+                if !missing {
+                    warn!(error = %err, "bound interface lost its address; keeping the last one");
+                }
+                missing = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP_ADDR: &str = "\
+1: lo    inet 127.0.0.1/8 scope host lo\\       valid_lft forever preferred_lft forever
+2: eth0    inet 192.168.1.20/24 brd 192.168.1.255 scope global dynamic eth0\\  valid_lft 86000sec
+2: eth0    inet6 fe80::1/64 scope link \\       valid_lft forever preferred_lft forever
+3: wlan0    inet6 2001:db8::7/64 scope global dynamic \\       valid_lft 3000sec
+4: eth1@if9    inet 10.40.0.5/16 brd 10.40.255.255 scope global eth1\\       valid_lft forever
+4: eth1@if9    inet 10.40.0.6/16 brd 10.40.255.255 scope global secondary eth1\\  valid_lft forever
+";
+
+    #[test]
+    fn interfaces_and_cidrs_select_an_address() {
+        let addrs = parse_addrs(IP_ADDR);
+        assert_eq!(addrs.len(), 5);
+        assert!(addrs.iter().all(|addr| addr.ip.to_string() != "fe80::1"));
+        let ip = |raw: &str| raw.parse::<IpAddr>().unwrap();
+
+        assert_eq!(select("eth0", &addrs, None), Some(ip("192.168.1.20")));
+        assert_eq!(select("wlan0", &addrs, None), Some(ip("2001:db8::7")));
+        assert_eq!(select("10.40.0.0/16", &addrs, None), Some(ip("10.40.0.5")));
+        assert_eq!(
+            select("2001:db8::/32", &addrs, None),
+            Some(ip("2001:db8::7"))
+        );
+        assert_eq!(select("eth9", &addrs, None), None);
+        assert_eq!(select("172.16.0.0/12", &addrs, None), None);
+        // The address already bound stays chosen while the interface still has it.
+        assert_eq!(
+            select("eth1", &addrs, Some(ip("10.40.0.6"))),
+            Some(ip("10.40.0.6"))
+        );
+        assert_eq!(
+            select("eth1", &addrs, Some(ip("10.40.0.9"))),
+            Some(ip("10.40.0.5"))
+        );
+
+        assert_eq!(
+            resolve_bind("api", "0.0.0.0:8456", "eth0", &addrs, None).unwrap(),
+            Some("192.168.1.20:8456".parse().unwrap())
+        );
+        assert_eq!(
+            resolve_bind("api", "0.0.0.0:8456", " ", &addrs, None).unwrap(),
+            None
+        );
+        assert!(resolve_bind("api", "0.0.0.0:8456", "eth9", &addrs, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn blank_hints_are_derived_from_the_bound_address() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-interfaces-test-{}.json",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let mut cfg = Config::load_or_create(&path).expect("temp config").0;
+        let _ = std::fs::remove_file(&path);
+        let bindings = NetworkBindings::start(&cfg).unwrap();
+        assert_eq!(bindings.endpoint_hint(&cfg), (String::new(), false));
+
+        bindings.set_swarm_bound("0.0.0.0:4040".parse().unwrap());
+        bindings.set_api_bound("192.168.1.20:8456".parse().unwrap());
+        assert_eq!(bindings.endpoint_hint(&cfg), (String::new(), false));
+        assert_eq!(
+            bindings.public_ws_url(&cfg),
+            ("ws://192.168.1.20:8456/session".to_string(), true)
+        );
+
+        bindings.set_swarm_bound("[2001:db8::7]:4040".parse().unwrap());
+        cfg.api.public_ws_url = "wss://nvr.example/session".to_string();
+        let health = bindings.health(&cfg);
+        assert_eq!(health["swarm"]["endpointHint"], "udp://[2001:db8::7]:4040");
+        assert_eq!(health["swarm"]["endpointHintDerived"], true);
+        assert_eq!(health["api"]["publicWsUrl"], "wss://nvr.example/session");
+        assert_eq!(health["api"]["publicWsUrlDerived"], false);
+    }
+}
@@ -0,0 +1,409 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::CameraDeviceConfig;
+use crate::stats::escape_label;
+use crate::util;
+
+const WINDOW_SECS: u64 = 3_600;
+const MAX_SAMPLES: usize = 240;
+const MIN_SAMPLES: usize = 3;
+pub const MAX_NAME_SKEW_SECS: u64 = 120;
+const MAX_LEAD_MS: u64 = 2_000;
+const MAX_PIPELINE_MS: u64 = 86_400_000;
+const DEFAULT_THRESHOLD_SLACK_SECS: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Unreliable {
+    NoDuration,
+    ClockStep,
+    NameSkew,
+    Implausible,
+}
+
+impl Unreliable {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NoDuration => "no_duration",
+            Self::ClockStep => "clock_step",
+            Self::NameSkew => "name_skew",
+            Self::Implausible => "implausible",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    sealed_unix: u64,
+    pipeline_ms: Result<u64, Unreliable>,
+    encryptor_ms: u64,
+}
+
+#[derive(Default)]
+struct SourceSamples {
+    recent: VecDeque<Sample>,
+    total: u64,
+    unreliable_total: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Percentiles {
+    pub p50_secs: f64,
+    pub p95_secs: f64,
+    pub max_secs: f64,
+}
+
+impl Percentiles {
+    fn of(mut values: Vec<u64>) -> Option<Self> {
+        values.sort_unstable();
+        let max = *values.last()?;
+        let rank = |quantile: f64| {
+            let idx = (quantile * values.len() as f64).ceil() as usize;
+            values[idx.clamp(1, values.len()) - 1]
+        };
+        Some(Self {
+            p50_secs: rank(0.5) as f64 / 1000.0,
+            p95_secs: rank(0.95) as f64 / 1000.0,
+            max_secs: max as f64 / 1000.0,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceLatency {
+    pub source_id: String,
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    pub samples: usize,
+    pub unreliable: usize,
+    pub pipeline: Option<Percentiles>,
+    pub encryptor: Option<Percentiles>,
+    pub newest_sealed_unix: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlowSource {
+    pub source_id: String,
+    pub p95_secs: f64,
+    pub threshold_secs: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct LatencyTracker {
+    inner: Arc<Mutex<HashMap<String, SourceSamples>>>,
+}
+
+impl LatencyTracker {
+    pub fn record(
+        &self,
+        source_id: &str,
+        footage_end_ms: Result<u64, Unreliable>,
+        encryptor: Duration,
+    ) {
+        self.record_at(source_id, footage_end_ms, encryptor, util::now_ms());
+    }
+
+    fn record_at(
+        &self,
+        source_id: &str,
+        footage_end_ms: Result<u64, Unreliable>,
+        encryptor: Duration,
+        sealed_ms: u64,
+    ) {
+        let pipeline_ms = footage_end_ms.and_then(|end_ms| {
+            if end_ms > sealed_ms + MAX_LEAD_MS
+                || sealed_ms.saturating_sub(end_ms) > MAX_PIPELINE_MS
+            {
+                return Err(Unreliable::Implausible);
+            }
+            Ok(sealed_ms.saturating_sub(end_ms))
+        });
+        let sample = Sample {
+            sealed_unix: sealed_ms / 1000,
+            pipeline_ms,
+            encryptor_ms: u64::try_from(encryptor.as_millis()).unwrap_or(u64::MAX),
+        };
+        let mut guard = self.lock();
+        let source = guard.entry(source_id.to_string()).or_default();
+        source.total += 1;
+        source.unreliable_total += u64::from(pipeline_ms.is_err());
+        source.recent.push_back(sample);
+        while source.recent.len() > MAX_SAMPLES {
+            source.recent.pop_front();
+        }
+    }
+
+    pub fn views(&self, cameras: &[CameraDeviceConfig]) -> Vec<SourceLatency> {
+        self.views_at(cameras, util::now_unix_seconds())
+    }
+
+    fn views_at(&self, cameras: &[CameraDeviceConfig], now: u64) -> Vec<SourceLatency> {
+        let guard = self.lock();
+        cameras
+            .iter()
+            .map(|camera| {
+                let samples = guard
+                    .get(&camera.source_id)
+                    .map(|source| window(source, now))
+                    .unwrap_or_default();
+                let mut view = summarize(&camera.source_id, &samples);
+                if !camera.is_capturing() {
+                    view.state = "idle";
+                    view.reason = None;
+                    view.pipeline = None;
+                }
+                view
+            })
+            .collect()
+    }
+
+    pub fn slow_sources(
+        &self,
+        cameras: &[CameraDeviceConfig],
+        threshold_secs: u64,
+    ) -> Vec<SlowSource> {
+        slow_sources(cameras, &self.views(cameras), threshold_secs)
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let now = util::now_unix_seconds();
+        let guard = self.lock();
+        let mut sources = guard.iter().collect::<BTreeMap<_, _>>();
+        sources.retain(|_, source| source.total > 0);
+        let mut out = String::new();
+        let name = "constitute_nvr_recording_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Segment footage end to seal (pipeline) and encryptor wait, over the \
+             last hour; absent while unknown."
+        );
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (source_id, source) in &sources {
+            let view = summarize(source_id, &window(source, now));
+            for (stage, percentiles) in [("pipeline", view.pipeline), ("encryptor", view.encryptor)]
+            {
+                let Some(percentiles) = percentiles else {
+                    continue;
+                };
+                for (quantile, secs) in [
+                    ("0.5", percentiles.p50_secs),
+                    ("0.95", percentiles.p95_secs),
+                    ("1", percentiles.max_secs),
+                ] {
+                    let _ = writeln!(
+                        out,
+                        "{name}{{source_id=\"{}\",stage=\"{stage}\",quantile=\"{quantile}\"}} \
+                         {secs:.3}",
+                        escape_label(source_id)
+                    );
+                }
+            }
+        }
+        for (name, help, unreliable) in [
+            (
+                "constitute_nvr_recording_latency_samples_total",
+                "Sealed segments sampled for recording latency.",
+                false,
+            ),
+            (
+                "constitute_nvr_recording_latency_unreliable_total",
+                "Latency samples whose pipeline time could not be trusted.",
+                true,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (source_id, source) in &sources {
+                let value = match unreliable {
+                    true => source.unreliable_total,
+                    false => source.total,
+                };
+                let _ = writeln!(
+                    out,
+                    "{name}{{source_id=\"{}\"}} {value}",
+                    escape_label(source_id)
+                );
+            }
+        }
+        out
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, SourceSamples>> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn window(source: &SourceSamples, now: u64) -> Vec<Sample> {
+    let since = now.saturating_sub(WINDOW_SECS);
+    source
+        .recent
+        .iter()
+        .filter(|sample| sample.sealed_unix >= since)
+        .copied()
+        .collect()
+}
+
+fn summarize(source_id: &str, samples: &[Sample]) -> SourceLatency {
+    let reliable = samples
+        .iter()
+        .filter_map(|sample| sample.pipeline_ms.ok())
+        .collect::<Vec<_>>();
+    let mut reasons = BTreeMap::<Unreliable, usize>::new();
+    for reason in samples.iter().filter_map(|sample| sample.pipeline_ms.err()) {
+        *reasons.entry(reason).or_default() += 1;
+    }
+    let unreliable = samples.len() - reliable.len();
+    let reason = if samples.is_empty() {
+        Some("no_samples")
+    } else if unreliable * 2 > samples.len() {
+        reasons
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(reason, _)| reason.as_str())
+    } else if reliable.len() < MIN_SAMPLES {
+        Some("too_few_samples")
+    } else {
+        None
+    };
+    SourceLatency {
+        source_id: source_id.to_string(),
+        state: if reason.is_some() { "unknown" } else { "ok" },
+        reason,
+        samples: samples.len(),
+        unreliable,
+        pipeline: reason
+            .is_none()
+            .then(|| Percentiles::of(reliable))
+            .flatten(),
+        encryptor: Percentiles::of(samples.iter().map(|sample| sample.encryptor_ms).collect()),
+        newest_sealed_unix: samples.iter().map(|sample| sample.sealed_unix).max(),
+    }
+}
+
+fn slow_sources(
+    cameras: &[CameraDeviceConfig],
+    views: &[SourceLatency],
+    threshold_secs: u64,
+) -> Vec<SlowSource> {
+    cameras
+        .iter()
+        .zip(views)
+        .filter_map(|(camera, view)| {
+            let threshold_secs = match threshold_secs {
+                0 => camera
+                    .segment_secs
+                    .saturating_mul(2)
+                    .saturating_add(DEFAULT_THRESHOLD_SLACK_SECS),
+                secs => secs,
+            };
+            let p95_secs = view.pipeline?.p95_secs;
+            (p95_secs > threshold_secs as f64).then(|| SlowSource {
+                source_id: camera.source_id.clone(),
+                p95_secs,
+                threshold_secs,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    const NOW_MS: u64 = 1_710_000_000_000;
+
+    fn camera(source_id: &str, segment_secs: u64) -> CameraDeviceConfig {
+        CameraDeviceConfig {
+            segment_secs,
+            ..test_support::camera(source_id)
+        }
+    }
+
+    fn record(tracker: &LatencyTracker, source_id: &str, lag_ms: Result<u64, Unreliable>) {
+        let end = lag_ms.map(|lag_ms| NOW_MS - lag_ms);
+        tracker.record_at(source_id, end, Duration::from_millis(400), NOW_MS);
+    }
+
+    #[test]
+    fn percentiles_come_from_reliable_samples_in_the_window() {
+        let tracker = LatencyTracker::default();
+        for lag_ms in [3_000, 4_000, 5_000, 6_000, 30_000] {
+            record(&tracker, "front", Ok(lag_ms));
+        }
+        record(&tracker, "front", Err(Unreliable::NoDuration));
+        let cameras = [camera("front", 8), camera("back", 10)];
+        let views = tracker.views_at(&cameras, NOW_MS / 1000);
+
+        let front = &views[0];
+        assert_eq!((front.state, front.samples, front.unreliable), ("ok", 6, 1));
+        assert_eq!(
+            front.pipeline,
+            Some(Percentiles {
+                p50_secs: 5.0,
+                p95_secs: 30.0,
+                max_secs: 30.0
+            })
+        );
+        assert_eq!(front.encryptor.unwrap().p95_secs, 0.4);
+        assert_eq!(
+            (views[1].state, views[1].reason),
+            ("unknown", Some("no_samples"))
+        );
+        // An hour on, the window is empty again.
+        let later = tracker.views_at(&cameras, NOW_MS / 1000 + WINDOW_SECS + 1);
+        assert_eq!(later[0].reason, Some("no_samples"));
+
+        assert_eq!(
+            slow_sources(&cameras, &views, 0),
+            vec![SlowSource {
+                source_id: "front".to_string(),
+                p95_secs: 30.0,
+                threshold_secs: 26,
+            }]
+        );
+        assert!(slow_sources(&cameras, &views, 45).is_empty());
+    }
+
+    #[test]
+    fn unreliable_inputs_report_unknown_instead_of_a_number() {
+        let tracker = LatencyTracker::default();
+        record(&tracker, "front", Ok(2_000));
+        for _ in 0..3 {
+            record(&tracker, "front", Err(Unreliable::ClockStep));
+        }
+        // Footage that ends a minute after its seal, or a day and more before it.
+        tracker.record_at("front", Ok(NOW_MS + 60_000), Duration::ZERO, NOW_MS);
+        tracker.record_at("front", Ok(NOW_MS - 90_000_000), Duration::ZERO, NOW_MS);
+        // A lead within mtime rounding is a zero lag.
+        tracker.record_at("back", Ok(NOW_MS + 500), Duration::ZERO, NOW_MS);
+
+        let cameras = [camera("front", 10), camera("back", 10)];
+        let views = tracker.views_at(&cameras, NOW_MS / 1000);
+        assert_eq!(
+            (views[0].state, views[0].reason, views[0].unreliable),
+            ("unknown", Some("clock_step"), 5)
+        );
+        assert_eq!(views[0].pipeline, None);
+        assert!(views[0].encryptor.is_some());
+        assert_eq!(views[1].reason, Some("too_few_samples"));
+        assert!(slow_sources(&cameras, &views, 1).is_empty());
+
+        let metrics = tracker.render_prometheus();
+        assert!(
+            metrics.contains(
+                "constitute_nvr_recording_latency_unreliable_total{source_id=\"front\"} 5"
+            )
+        );
+        assert!(!metrics.contains("stage=\"pipeline\""));
+    }
+}
@@ -202,7 +202,8 @@ impl PreviewManager {
             .with_media_engine(media_engine)
             .with_setting_engine(setting_engine)
             .build();
-        let media_projection = MediaProjectionRuntime::new();
+        let media_projection =
+            MediaProjectionRuntime::new(cfg.live_preview.latest_frame_interval_secs);
         if !cfg!(test) {
             let warm_runtime = media_projection.clone();
             let warm_cfg = cfg.clone();
@@ -217,6 +218,10 @@ impl PreviewManager {
         })
     }
 
+    pub fn latest_frame(&self, source_id: &str) -> Option<crate::media_projection::LatestFrame> {
+        self.media_projection.latest_frame(source_id)
+    }
+
     pub async fn media_projection_health(
         &self,
         cfg: &Config,
@@ -224,6 +229,26 @@ impl PreviewManager {
         self.media_projection.health(cfg).await
     }
 
+    pub async fn suspend_source(&self, source_id: &str) {
+        let sessions = self
+            .sessions
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for session in sessions {
+            for (index, session_source) in session.source_ids.iter().enumerate() {
+                if session_source.trim() == source_id.trim()
+                    && let Some(stop) = session.stops.get(index)
+                {
+                    let _ = stop.send(true);
+                }
+            }
+        }
+        self.media_projection.stop_source(source_id).await;
+    }
+
     pub async fn handle_offer(
         &self,
         cfg: &Config,
@@ -405,7 +430,11 @@ pub fn resolve_control_camera(
     }
     cfg.camera_devices
         .iter()
-        .find(|camera| camera.enabled && camera.source_id.trim() == source_id.trim())
+        .find(|camera| {
+            camera.is_capturing()
+                && camera.has_onvif()
+                && camera.source_id.trim() == source_id.trim()
+        })
         .cloned()
         .ok_or_else(|| anyhow!("camera source is not available for control"))
 }
@@ -665,7 +694,7 @@ fn select_sources(
     let enabled = cfg
         .camera_devices
         .iter()
-        .filter(|camera| camera.enabled)
+        .filter(|camera| camera.is_capturing() && camera.has_rtsp())
         .cloned()
         .collect::<Vec<_>>();
     let allowed_ids = if token.owner || token.view_sources.is_empty() {
@@ -885,6 +914,13 @@ mod tests {
                 ..Default::default()
             },
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         });
         cfg
     }
@@ -1096,6 +1132,7 @@ mod tests {
         let args = ffmpeg::build_live_preview_ffmpeg_args(
             &planner::preview_pipeline_plan_for_codec(&camera, OutputCodec::Vp8),
             41000,
+            0,
         );
         let ff_idx = args
             .iter()
@@ -1113,9 +1150,34 @@ mod tests {
         let args = ffmpeg::build_live_preview_ffmpeg_args(
             &planner::preview_pipeline_plan_for_codec(&camera, OutputCodec::H264),
             41000,
+            0,
         );
         assert!(!args.iter().any(|arg| arg == "+genpts"));
         assert!(args.iter().any(|arg| arg == "copy"));
+        assert!(!args.iter().any(|arg| arg == "pipe:1"));
+    }
+
+    #[test]
+    fn live_preview_ffmpeg_args_add_latest_frame_output() {
+        let camera = sample_config().camera_devices.remove(0);
+        let args = ffmpeg::build_live_preview_ffmpeg_args(
+            &planner::preview_pipeline_plan_for_codec(&camera, OutputCodec::H264),
+            41000,
+            45,
+        );
+        let skip_idx = args
+            .iter()
+            .position(|arg| arg == "-skip_frame")
+            .expect("skip");
+        let input_idx = args.iter().position(|arg| arg == "-i").expect("input");
+        assert!(skip_idx < input_idx);
+        assert!(args.iter().any(|arg| arg == "fps=1/45"));
+        assert_eq!(args.last().map(String::as_str), Some("pipe:1"));
+        let rtp_idx = args
+            .iter()
+            .position(|arg| arg.starts_with("rtp://"))
+            .expect("rtp");
+        assert!(rtp_idx < args.len() - 1);
     }
 
     #[test]
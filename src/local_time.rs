@@ -0,0 +1,136 @@
+use chrono::{DateTime, Days, LocalResult, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DayBucket {
+    pub day: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub segments: usize,
+}
+
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.trim().parse().ok()
+}
+
+fn at(unix: u64, tz: Tz) -> DateTime<Tz> {
+    DateTime::from_timestamp(unix as i64, 0)
+        .unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+        .with_timezone(&tz)
+}
+
+pub fn local_label(unix: u64, tz: Tz) -> String {
+    at(unix, tz).to_rfc3339()
+}
+
+pub fn file_stamp(unix: u64, tz: Tz) -> String {
+    at(unix, tz)
+        .format(crate::util::clock::STAMP_FORMAT)
+        .to_string()
+}
+
+fn day_start(day: NaiveDate, tz: Tz) -> u64 {
+    let mut local = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+    for _ in 0..24 * 4 {
+        match tz.from_local_datetime(&local) {
+            LocalResult::Single(start) | LocalResult::Ambiguous(start, _) => {
+                return start.timestamp().max(0) as u64;
+            }
+            LocalResult::None => local += chrono::Duration::minutes(15),
+        }
+    }
+    day.and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .and_utc()
+        .timestamp()
+        .max(0) as u64
+}
+
+pub fn day_bounds(unix: u64, tz: Tz) -> (u64, u64) {
+    let day = at(unix, tz).date_naive();
+    let next = day.checked_add_days(Days::new(1)).unwrap_or(day);
+    (day_start(day, tz), day_start(next, tz).saturating_sub(1))
+}
+
+pub fn day_buckets(starts: impl IntoIterator<Item = u64>, tz: Tz) -> Vec<DayBucket> {
+    let mut days = std::collections::BTreeMap::<NaiveDate, DayBucket>::new();
+    for start in starts {
+        let day = at(start, tz).date_naive();
+        days.entry(day)
+            .or_insert_with(|| {
+                let (from_unix, to_unix) = day_bounds(start, tz);
+                DayBucket {
+                    day: day.format("%Y-%m-%d").to_string(),
+                    from_unix,
+                    to_unix,
+                    segments: 0,
+                }
+            })
+            .segments += 1;
+    }
+    days.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix(rfc3339: &str) -> u64 {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().timestamp() as u64
+    }
+
+    #[test]
+    fn day_boundaries_follow_dst_transitions() {
+        let new_york = parse_timezone(" America/New_York ").unwrap();
+        assert!(parse_timezone("Mars/Olympus_Mons").is_none());
+        assert!(parse_timezone("").is_none());
+
+        // Spring forward: 2024-03-10 has 23 hours.
+        let (from, to) = day_bounds(unix("2024-03-10T12:00:00-04:00"), new_york);
+        assert_eq!(from, unix("2024-03-10T00:00:00-05:00"));
+        assert_eq!(to + 1 - from, 23 * 3600);
+        // Fall back: 2024-11-03 has 25 hours.
+        let (from, to) = day_bounds(unix("2024-11-03T12:00:00-05:00"), new_york);
+        assert_eq!(from, unix("2024-11-03T00:00:00-04:00"));
+        assert_eq!(to + 1 - from, 25 * 3600);
+        // The repeated hour keeps its own offset in labels.
+        assert_eq!(
+            local_label(unix("2024-11-03T05:30:00Z"), new_york),
+            "2024-11-03T01:30:00-04:00"
+        );
+        assert_eq!(
+            local_label(unix("2024-11-03T06:30:00Z"), new_york),
+            "2024-11-03T01:30:00-05:00"
+        );
+        assert_eq!(
+            file_stamp(unix("2024-03-10T07:30:00Z"), new_york),
+            "20240310T033000"
+        );
+
+        // Midnight itself is skipped in Santiago's spring change; the day starts at 01:00.
+        let santiago = parse_timezone("America/Santiago").unwrap();
+        let (from, _) = day_bounds(unix("2024-09-08T12:00:00-03:00"), santiago);
+        assert_eq!(from, unix("2024-09-08T01:00:00-03:00"));
+
+        // Segments either side of UTC midnight land in one local day, and late evening
+        // segments in the local day rather than the UTC one.
+        let buckets = day_buckets(
+            [
+                unix("2024-03-09T23:30:00-05:00"),
+                unix("2024-03-10T00:30:00-05:00"),
+                unix("2024-03-10T23:59:59-04:00"),
+            ],
+            new_york,
+        );
+        assert_eq!(
+            buckets
+                .iter()
+                .map(|b| (b.day.as_str(), b.segments))
+                .collect::<Vec<_>>(),
+            [("2024-03-09", 1), ("2024-03-10", 2)]
+        );
+        assert_eq!(buckets[1].to_unix, unix("2024-03-10T23:59:59-04:00"));
+    }
+}
@@ -1,5 +1,5 @@
 use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -14,6 +14,8 @@ use tracing::debug;
 
 use crate::util;
 
+const RECENT_EVENTS_TAIL_BYTES: u64 = 4 * 1024 * 1024;
+
 #[derive(Debug, Deserialize)]
 pub struct LoggingEventsQuery {
     #[serde(default)]
@@ -119,6 +121,50 @@ pub fn read_events(query: LoggingEventsQuery) -> ProducerEventsResponse {
     }
 }
 
+pub fn read_recent_events(tag: &str, since_unix: u64) -> Vec<LogEventEnvelope> {
+    read_outbox_tail(|event| event.occurred_at >= since_unix && event.tags.iter().any(|t| t == tag))
+}
+
+pub fn read_source_events(
+    source_ids: &[String],
+    from_unix: u64,
+    to_unix: u64,
+) -> Vec<LogEventEnvelope> {
+    read_outbox_tail(|event| {
+        (from_unix..=to_unix).contains(&event.occurred_at)
+            && event.safe_facts["sourceId"]
+                .as_str()
+                .is_some_and(|source_id| {
+                    source_ids
+                        .iter()
+                        .any(|wanted| wanted.eq_ignore_ascii_case(source_id))
+                })
+    })
+}
+
+fn read_outbox_tail(keep: impl Fn(&LogEventEnvelope) -> bool) -> Vec<LogEventEnvelope> {
+    let Some(path) = outbox_path() else {
+        return Vec::new();
+    };
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return Vec::new();
+    };
+    let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+    let start = len.saturating_sub(RECENT_EVENTS_TAIL_BYTES);
+    if file.seek(SeekFrom::Start(start)).is_err() {
+        return Vec::new();
+    }
+    let mut lines = BufReader::new(file).lines().map_while(|line| line.ok());
+    if start > 0 {
+        // Most likely the middle of an event.
+        lines.next();
+    }
+    lines
+        .filter_map(|line| serde_json::from_str::<LogEventEnvelope>(&line).ok())
+        .filter(|event| keep(event))
+        .collect()
+}
+
 async fn submit_to_logging(event: &LogEventEnvelope) {
     let Some(base_url) = logging_url() else {
         return;
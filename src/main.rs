@@ -1,14 +1,35 @@
+mod access_token;
 mod api;
+mod bandwidth;
 mod camera_device;
 mod config;
 mod crypto;
+mod dashboard;
+mod diagnostics;
+mod endpoint_probe;
+mod features;
+mod handshake;
+mod headroom;
 mod hosted_registry;
+mod interfaces;
+mod latency;
 mod live;
+mod local_time;
 mod logging_surface;
 mod media;
 mod media_projection;
+mod mqtt;
 mod nostr;
+mod notifications;
+mod peer_clock;
+mod protocol;
+mod record_store;
 mod recording;
+mod replication;
+mod self_check;
+mod source_bundle;
+mod stats;
+mod status_page;
 mod storage;
 mod swarm;
 mod update;
@@ -31,7 +52,9 @@ use tracing::{info, warn};
     about = "Constitute NVR service (ONVIF ingest + swarm-native presence)"
 )]
 struct Args {
-    #[arg(long)]
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+    #[arg(long, global = true)]
     config: Option<PathBuf>,
     #[arg(long, default_value = "info")]
     log_level: String,
@@ -81,12 +104,56 @@ struct Args {
     apply_camera_device_source: Option<String>,
     #[arg(long)]
     apply_camera_device_desired_json: Option<PathBuf>,
+    #[arg(long)]
+    migrate_opaque_names: bool,
+    #[arg(long)]
+    migrate_day_layout: bool,
+    #[arg(long)]
+    decrypt_segment: Option<String>,
+    #[arg(long)]
+    decrypt_segment_out: Option<PathBuf>,
+    #[arg(long)]
+    validate_config: bool,
+    #[arg(long)]
+    print_protocol: bool,
+    /// Print the session crypto test vectors (`docs/crypto-test-vectors.json`).
+    #[arg(long, hide = true)]
+    dump_test_vectors: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CliCommand {
+    /// Camera bundles, read from and written to the config file directly.
+    Camera {
+        #[command(subcommand)]
+        action: CameraCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CameraCommand {
+    /// Write every configured camera as a source bundle (stdout without --out).
+    Export {
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Wrap the passwords under the passphrase in this file; omitted otherwise.
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+    },
+    /// Apply a source bundle to the config file; restart the service to pick it up.
+    Import {
+        bundle: PathBuf,
+        #[arg(long, value_enum, default_value = "skip")]
+        conflict_policy: source_bundle::ConflictPolicy,
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    init_logging(&args.log_level);
+    let diagnostics = init_logging(&args.log_level);
 
     if args.discover_onvif {
         let discovered = recording::discover_onvif(3).await?;
@@ -175,9 +242,35 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if args.print_protocol {
+        println!("{}", serde_json::to_string_pretty(&protocol::document())?);
+        return Ok(());
+    }
+
+    if args.dump_test_vectors {
+        let versions = (1..=features::SESSION_PROTOCOL_VERSION).collect::<Vec<_>>();
+        let vectors = crypto::testvectors::document(&versions);
+        println!("{}", serde_json::to_string_pretty(&vectors)?);
+        return Ok(());
+    }
+
     let cfg_path = args
         .config
         .unwrap_or_else(|| PathBuf::from("/etc/constitute-nvr/config.json"));
+
+    if args.validate_config {
+        let audit = Config::validate_file(&cfg_path)?;
+        println!("{}", serde_json::to_string_pretty(&audit)?);
+        if !audit.errors.is_empty() {
+            anyhow::bail!("config validation failed: {} error(s)", audit.errors.len());
+        }
+        return Ok(());
+    }
+
+    if let Some(CliCommand::Camera { action }) = &args.command {
+        return run_camera_command(&cfg_path, action);
+    }
+
     let (mut cfg, created) = Config::load_or_create(&cfg_path)?;
 
     if created {
@@ -197,6 +290,7 @@ async fn main() -> Result<()> {
     if let Err(err) = run_reolink_autoprovision(&mut cfg, &cfg_path).await {
         warn!(error = %err, "reolink auto-provision failed");
     }
+    cfg.record_derived_origins();
 
     if let Err(err) = hosted_registry::persist_hosted_service_manifest(&cfg) {
         warn!(error = %err, "failed writing hosted-service manifest");
@@ -276,15 +370,101 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    let stats_path = cfg.storage_root().join("stats.json");
+    let stats = stats::StatsRegistry::load(&stats_path);
     let storage =
-        storage::StorageManager::new(cfg.storage_root(), &cfg.storage.encryption_key_hex)?;
+        storage::StorageManager::new(cfg.storage_root(), &cfg.storage.encryption_key_hex)?
+            .with_opaque_names(cfg.storage.opaque_names)
+            .with_encrypt_workers(cfg.storage.encrypt_workers)
+            .with_verified_reads(cfg.storage.verify_segment_reads)
+            .with_snapshot_retention(storage::SnapshotRetention {
+                retention_days: cfg.storage.snapshot_retention_days,
+                max_bytes: cfg.storage.snapshot_max_bytes,
+            })
+            .with_pre_delete_hook(pre_delete_hook(&cfg))
+            .with_attachment_limits(storage::AttachmentLimits {
+                max_bytes: cfg.storage.attachment_max_bytes,
+                max_count: cfg.storage.attachment_max_count,
+                max_total_bytes: cfg.storage.attachment_max_total_bytes,
+            })
+            .with_exports(storage::ExportSettings {
+                spool: cfg.storage.export_spool,
+                ttl_secs: cfg.storage.export_ttl_hours.saturating_mul(3600),
+            })
+            .with_segment_cache(storage::SegmentCacheSettings {
+                max_entries: cfg.storage.segment_cache_entries,
+                max_bytes: cfg.storage.segment_cache_mb.saturating_mul(1024 * 1024),
+            })
+            .with_io_priority(io_priority(&cfg))
+            .with_stats(stats.clone());
     storage.ensure_dirs().await?;
-    storage.start_encryptor(cfg.storage.encrypt_interval_secs);
+    let reconciled = storage.reconcile().await?;
+    if reconciled != storage::ReconcileSummary::default() {
+        info!(
+            temp_files = reconciled.temp_files,
+            torn_segments = reconciled.torn_segments,
+            empty_segments = reconciled.empty_segments,
+            plaintext_segments = reconciled.plaintext_segments,
+            orphan_segments = reconciled.orphan_segments,
+            "reconciled the segment tree after the last shutdown"
+        );
+    }
+
+    if args.migrate_day_layout {
+        let report = storage.migrate_day_layout().await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if args.migrate_opaque_names {
+        let report = storage.migrate_opaque_names().await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
 
-    let recorder = RecorderManager::new();
+    if let Some(spec) = &args.decrypt_segment {
+        let (source_id, name) = spec.split_once('/').ok_or_else(|| {
+            anyhow::anyhow!("--decrypt-segment expects <sourceId>/<segment name>")
+        })?;
+        let plain = storage.read_segment(source_id, name).await?;
+        let out = args
+            .decrypt_segment_out
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(name).with_extension("mp4"));
+        fs::write(&out, plain.as_slice())?;
+        println!("{}", out.display());
+        return Ok(());
+    }
+
+    storage.start_io_canary();
+    storage.start_encryptor(cfg.storage.encrypt_interval_secs);
+    storage.set_retention_windows(api::retention_windows(&cfg));
+    match storage.load_incident_bookmarks().await {
+        Ok(0) => {}
+        Ok(incidents) => info!(incidents, "incident bookmarks restored"),
+        Err(err) => warn!(error = %err, "incident bookmarks not restored"),
+    }
+    storage.start_snapshot_retention();
+    storage.resume_reencrypt();
+    storage.schedule_backfill();
+    stats.start_persistence(stats_path);
+
+    let dependencies = media::dependencies::DependencyMonitor::probe().await;
+    let recorder =
+        RecorderManager::new(dependencies.clone(), stats.clone()).with_diagnostics(diagnostics);
     recorder.ensure_started(&cfg).await;
 
-    let swarm_handle = swarm::start(cfg.clone()).await?;
+    // Shared with the API so swarm announcements follow camera changes made over sessions.
+    let live_cfg = config::SharedConfig::new(cfg.clone());
+    // The API's self-check fills it; device records announce its health to peers.
+    let self_check = self_check::SelfCheck::default();
+    let swarm_handle = swarm::start(
+        live_cfg.clone(),
+        dependencies.clone(),
+        recorder.clone(),
+        self_check.clone(),
+    )
+    .await?;
 
     if args.once {
         let sources = storage.list_sources().await.unwrap_or_default();
@@ -294,6 +474,15 @@ async fn main() -> Result<()> {
         println!("identity_id: {}", cfg.api.identity_id);
         println!("storage_root: {}", cfg.storage.root);
         println!("sources: {}", sources.len());
+        let media = dependencies.current();
+        println!(
+            "ffmpeg: {}",
+            if media.ffmpeg.available {
+                media.ffmpeg.version.as_str()
+            } else {
+                "missing"
+            }
+        );
         println!(
             "swarm_confirmed_peers: {}",
             swarm_handle.confirmed_peers().await
@@ -301,7 +490,8 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    update::spawn_update_poller(cfg.clone());
+    let updates = update::spawn_update_poller(cfg.clone(), recorder.clone());
+    let replication = replication::spawn_replicator(live_cfg.clone(), storage.clone(), &cfg);
 
     info!(
         node_id = %cfg.node_id,
@@ -312,7 +502,106 @@ async fn main() -> Result<()> {
         "constitute-nvr starting"
     );
 
-    api::run(cfg, cfg_path, storage, recorder).await
+    let services = api::ApiServices {
+        storage,
+        recorder,
+        dependencies,
+        stats,
+        swarm: swarm_handle,
+        self_check,
+        updates,
+        replication,
+    };
+    api::run(live_cfg, cfg_path, services).await
+}
+
+fn run_camera_command(cfg_path: &Path, action: &CameraCommand) -> Result<()> {
+    if !cfg_path.exists() {
+        anyhow::bail!("config file {} does not exist", cfg_path.display());
+    }
+    let (mut cfg, _) = Config::load_or_create(cfg_path)?;
+    match action {
+        CameraCommand::Export {
+            out,
+            passphrase_file,
+        } => {
+            let passphrase = read_passphrase(passphrase_file.as_deref())?;
+            let bundle =
+                source_bundle::export_bundle(&cfg, passphrase.as_deref().map(String::as_str))?;
+            let raw = serde_json::to_string_pretty(&bundle)?;
+            match out {
+                Some(path) => fs::write(path, raw)?,
+                None => println!("{raw}"),
+            }
+        }
+        CameraCommand::Import {
+            bundle,
+            conflict_policy,
+            passphrase_file,
+        } => {
+            let passphrase = read_passphrase(passphrase_file.as_deref())?;
+            let raw = fs::read_to_string(bundle)?;
+            let bundle = serde_json::from_str(&raw)?;
+            let plan = source_bundle::plan_import(
+                &cfg,
+                bundle,
+                passphrase.as_deref().map(String::as_str),
+                *conflict_policy,
+            )?;
+            let applied = plan.is_valid();
+            if applied && !plan.applied.is_empty() {
+                api::check_camera_limit(&cfg, plan.cameras.len())?;
+                cfg.camera_devices = plan.cameras;
+                cfg.apply_defaults();
+                cfg.persist(cfg_path)?;
+                let _ = hosted_registry::persist_hosted_service_manifest(&cfg);
+            }
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "applied": applied,
+                    "results": plan.results,
+                }))?
+            );
+            if !applied {
+                anyhow::bail!("import refused: the bundle has invalid entries");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_passphrase(path: Option<&Path>) -> Result<Option<zeroize::Zeroizing<String>>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let raw = zeroize::Zeroizing::new(fs::read_to_string(path)?);
+    Ok(Some(zeroize::Zeroizing::new(
+        raw.trim_end_matches(['\r', '\n']).to_string(),
+    )))
+}
+
+fn pre_delete_hook(cfg: &Config) -> Option<storage::PreDeleteHook> {
+    let hook = &cfg.retention.pre_delete_hook;
+    (!hook.command.is_empty()).then(|| storage::PreDeleteHook {
+        command: hook.command.clone(),
+        timeout: std::time::Duration::from_secs(hook.timeout_secs.max(1)),
+        fail_open: hook.fail_open,
+    })
+}
+
+fn io_priority(cfg: &Config) -> storage::IoPrioritySettings {
+    let mbps = |value: u64| value.saturating_mul(125_000);
+    let storage = &cfg.storage;
+    storage::IoPrioritySettings {
+        canary_interval: std::time::Duration::from_secs(storage.io_canary_interval_secs),
+        latency_high: std::time::Duration::from_millis(storage.io_latency_high_ms),
+        latency_low: std::time::Duration::from_millis(storage.io_latency_low_ms),
+        background_max: mbps(storage.io_background_max_mbps),
+        background_min: mbps(storage.io_background_min_mbps),
+        serving_max: mbps(storage.io_serving_max_mbps),
+        serving_min: mbps(storage.io_serving_min_mbps),
+    }
 }
 
 fn warn_if_camera_network_not_ready(cfg: &Config) {
@@ -485,7 +774,7 @@ async fn run_reolink_autoprovision(cfg: &mut Config, cfg_path: &Path) -> Result<
 
         let mut found = false;
         for cam in &mut cfg.camera_devices {
-            if cam.source_id == source_id || cam.onvif_host == ip {
+            if cam.source_id == source_id || (cam.has_onvif() && cam.onvif_host == ip) {
                 cam.source_id = source_id.clone();
                 cam.name = source_name.clone();
                 cam.onvif_host = ip.clone();
@@ -525,6 +814,13 @@ async fn run_reolink_autoprovision(cfg: &mut Config, cfg_path: &Path) -> Result<
                     ..Default::default()
                 },
                 credentials: Default::default(),
+                privacy: false,
+                set_camera_time: false,
+                snapshot_interval_secs: 0,
+                zones: Vec::new(),
+                push: Default::default(),
+                source_type: Default::default(),
+                retention: Default::default(),
             });
             changed = true;
         }
@@ -559,14 +855,26 @@ fn reolink_source_id(uid: &str, ip: &str) -> String {
     format!("reolink-{}", sanitized.trim_matches('-'))
 }
 
-fn init_logging(level: &str) {
-    let env = tracing_subscriber::EnvFilter::try_new(level)
-        .or_else(|_| tracing_subscriber::EnvFilter::try_new("info"))
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+fn init_logging(level: &str) -> diagnostics::Diagnostics {
+    use tracing_subscriber::prelude::*;
 
-    tracing_subscriber::fmt()
-        .with_env_filter(env)
-        .with_target(false)
-        .compact()
+    let base = match tracing_subscriber::EnvFilter::try_new(level) {
+        Ok(_) => level,
+        Err(_) => "info",
+    };
+    let (filter, handle) =
+        tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new(base));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .compact(),
+        )
         .init();
+    diagnostics::Diagnostics::new(base, move |directives| {
+        let filter = tracing_subscriber::EnvFilter::try_new(directives)?;
+        handle.reload(filter)?;
+        Ok(())
+    })
 }
@@ -0,0 +1,35 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+
+use super::ffmpeg;
+
+const CLIP_TIMEOUT_SECS: u64 = 300;
+
+pub async fn concat_segments(list_path: &Path, output_path: &Path) -> Result<()> {
+    let status = timeout(
+        Duration::from_secs(CLIP_TIMEOUT_SECS),
+        Command::new("ffmpeg")
+            .args(ffmpeg::build_clip_concat_ffmpeg_args(
+                list_path,
+                output_path,
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status(),
+    )
+    .await
+    .map_err(|_| anyhow!("clip render timed out"))?
+    .context("failed to start ffmpeg for clip")?;
+    if !status.success() {
+        return Err(anyhow!(
+            "clip render failed: ffmpeg exited with code {:?}",
+            status.code()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use serde::Serialize;
+use std::process::Stdio;
+use std::sync::{Arc, RwLock};
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+use tracing::{info, warn};
+
+use crate::util;
+
+const FFMPEG_BIN: &str = "ffmpeg";
+const FFPROBE_BIN: &str = "ffprobe";
+const PROBE_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolStatus {
+    pub available: bool,
+    pub version: String,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaDependencies {
+    pub ffmpeg: ToolStatus,
+    pub ffprobe: ToolStatus,
+    pub segment_muxer: bool,
+    pub libx264: bool,
+    pub libvpx: bool,
+    pub hwaccels: Vec<String>,
+    pub checked_at: u64,
+}
+
+impl MediaDependencies {
+    pub fn can_record(&self) -> bool {
+        self.ffmpeg.available && self.segment_muxer
+    }
+
+    pub fn recording_blocker(&self) -> Option<String> {
+        if !self.ffmpeg.available {
+            let detail = if self.ffmpeg.error.is_empty() {
+                "not found in PATH".to_string()
+            } else {
+                self.ffmpeg.error.clone()
+            };
+            return Some(format!("ffmpeg unavailable: {detail}"));
+        }
+        if !self.segment_muxer {
+            return Some("ffmpeg build lacks the segment muxer".to_string());
+        }
+        None
+    }
+
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut out = vec!["camera".to_string()];
+        if self.can_record() {
+            out.push("recording".to_string());
+        }
+        if self.ffmpeg.available && self.libx264 {
+            out.push("transcode".to_string());
+        }
+        out
+    }
+}
+
+#[derive(Clone)]
+pub struct DependencyMonitor {
+    inner: Arc<RwLock<MediaDependencies>>,
+}
+
+impl DependencyMonitor {
+    pub async fn probe() -> Self {
+        let monitor = Self::from_report(MediaDependencies::default());
+        monitor.recheck().await;
+        monitor
+    }
+
+    pub fn from_report(report: MediaDependencies) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(report)),
+        }
+    }
+
+    pub fn current(&self) -> MediaDependencies {
+        self.inner
+            .read()
+            .map(|guard| guard.clone())
+            .unwrap_or_default()
+    }
+
+    pub async fn recheck(&self) -> MediaDependencies {
+        let report = probe_media_dependencies().await;
+        if let Some(blocker) = report.recording_blocker() {
+            warn!(reason = %blocker, "media dependencies incomplete; recorders will not start");
+        } else {
+            info!(
+                ffmpeg = %report.ffmpeg.version,
+                ffprobe = %report.ffprobe.version,
+                libx264 = report.libx264,
+                "media dependencies probed"
+            );
+        }
+        if let Ok(mut guard) = self.inner.write() {
+            *guard = report.clone();
+        }
+        report
+    }
+}
+
+async fn probe_media_dependencies() -> MediaDependencies {
+    let ffmpeg = probe_tool_version(FFMPEG_BIN).await;
+    let ffprobe = probe_tool_version(FFPROBE_BIN).await;
+    let mut report = MediaDependencies {
+        ffmpeg,
+        ffprobe,
+        checked_at: util::now_ms(),
+        ..MediaDependencies::default()
+    };
+    if !report.ffmpeg.available {
+        return report;
+    }
+
+    if let Ok(muxers) = run_tool(FFMPEG_BIN, &["-hide_banner", "-muxers"]).await {
+        report.segment_muxer = listing_contains(&muxers, "segment");
+    }
+    if let Ok(encoders) = run_tool(FFMPEG_BIN, &["-hide_banner", "-encoders"]).await {
+        report.libx264 = listing_contains(&encoders, "libx264");
+        report.libvpx = listing_contains(&encoders, "libvpx");
+    }
+    if let Ok(hwaccels) = run_tool(FFMPEG_BIN, &["-hide_banner", "-hwaccels"]).await {
+        report.hwaccels = parse_hwaccels(&hwaccels);
+    }
+    report
+}
+
+async fn probe_tool_version(program: &str) -> ToolStatus {
+    match run_tool(program, &["-version"]).await {
+        Ok(output) => ToolStatus {
+            available: true,
+            version: parse_version_line(&output).unwrap_or_default(),
+            error: String::new(),
+        },
+        Err(error) => ToolStatus {
+            available: false,
+            version: String::new(),
+            error,
+        },
+    }
+}
+
+async fn run_tool(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = timeout(
+        Duration::from_secs(PROBE_TIMEOUT_SECS),
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| format!("{program} probe timed out"))?
+    .map_err(|err| {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            format!("{program} not found in PATH")
+        } else {
+            format!("{program} failed to start: {err}")
+        }
+    })?;
+    if !output.status.success() {
+        return Err(format!(
+            "{program} exited with code {:?}",
+            output.status.code()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn parse_version_line(output: &str) -> Option<String> {
+    let line = output.lines().next()?.trim();
+    let rest = line.split_once(" version ")?.1;
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+fn listing_contains(output: &str, name: &str) -> bool {
+    output.lines().any(|line| {
+        let mut columns = line.split_whitespace();
+        let _flags = columns.next();
+        columns
+            .next()
+            .is_some_and(|entry| entry.split(',').any(|alias| alias == name))
+    })
+}
+
+fn parse_hwaccels(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| !line.ends_with(':'))
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_line_extracts_release() {
+        let output = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc";
+        assert_eq!(
+            parse_version_line(output).as_deref(),
+            Some("6.1.1-3ubuntu5")
+        );
+        assert_eq!(parse_version_line("garbage"), None);
+    }
+
+    #[test]
+    fn listings_match_name_column_only() {
+        let muxers =
+            " E  segment         segment\n E  stream_segment,ssegment streaming segment muxer\n";
+        assert!(listing_contains(muxers, "segment"));
+        assert!(listing_contains(muxers, "ssegment"));
+        let encoders = " V....D libx264              libx264 H.264 / AVC\n V....D libvpx               libvpx VP8\n";
+        assert!(listing_contains(encoders, "libx264"));
+        assert!(!listing_contains(encoders, "libx265"));
+    }
+
+    #[test]
+    fn hwaccels_skip_header() {
+        let output = "Hardware acceleration methods:\nvdpau\nvaapi\n\n";
+        assert_eq!(parse_hwaccels(output), vec!["vdpau", "vaapi"]);
+    }
+
+    #[test]
+    fn capabilities_follow_dependencies() {
+        let missing = MediaDependencies::default();
+        assert_eq!(missing.capabilities(), vec!["camera"]);
+        assert!(missing.recording_blocker().is_some());
+
+        let no_x264 = MediaDependencies {
+            ffmpeg: ToolStatus {
+                available: true,
+                ..ToolStatus::default()
+            },
+            segment_muxer: true,
+            ..MediaDependencies::default()
+        };
+        assert_eq!(no_x264.capabilities(), vec!["camera", "recording"]);
+        assert!(no_x264.recording_blocker().is_none());
+    }
+}
@@ -1,10 +1,18 @@
 use std::path::Path;
 
+use crate::config::PushProtocol;
+
 use super::types::{
     AudioPlanMode, OutputCodec, PreviewPipelinePlan, RecordingPipelinePlan, VideoPlanMode,
 };
 
-pub fn build_live_preview_ffmpeg_args(plan: &PreviewPipelinePlan, udp_port: u16) -> Vec<String> {
+const TEST_PATTERN_FPS: u32 = 15;
+
+pub fn build_live_preview_ffmpeg_args(
+    plan: &PreviewPipelinePlan,
+    udp_port: u16,
+    frame_interval_secs: u64,
+) -> Vec<String> {
     let mut args = vec![
         "-nostdin".to_string(),
         "-loglevel".to_string(),
@@ -16,6 +24,11 @@ pub fn build_live_preview_ffmpeg_args(plan: &PreviewPipelinePlan, udp_port: u16)
         args.push("-fflags".to_string());
         args.push("+genpts".to_string());
     }
+    if frame_interval_secs > 0 && plan.video.mode == VideoPlanMode::Copy {
+        // Only the JPEG output decodes; keyframes are enough for it.
+        args.push("-skip_frame".to_string());
+        args.push("nokey".to_string());
+    }
     args.push("-i".to_string());
     args.push(plan.input_url.clone());
     args.push("-an".to_string());
@@ -50,6 +63,9 @@ pub fn build_live_preview_ffmpeg_args(plan: &PreviewPipelinePlan, udp_port: u16)
         (VideoPlanMode::Transcode, OutputCodec::H264) => {
             args.extend(["-c:v".to_string(), "libx264".to_string()]);
         }
+        (VideoPlanMode::Transcode, OutputCodec::Mpeg4) => {
+            args.extend(["-c:v".to_string(), "mpeg4".to_string()]);
+        }
     }
 
     args.extend([
@@ -59,6 +75,21 @@ pub fn build_live_preview_ffmpeg_args(plan: &PreviewPipelinePlan, udp_port: u16)
         "96".to_string(),
         format!("rtp://127.0.0.1:{udp_port}?pkt_size=1200"),
     ]);
+    if frame_interval_secs > 0 {
+        args.extend([
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-vf".to_string(),
+            format!("fps=1/{frame_interval_secs}"),
+            "-c:v".to_string(),
+            "mjpeg".to_string(),
+            "-q:v".to_string(),
+            "5".to_string(),
+            "-f".to_string(),
+            "image2pipe".to_string(),
+            "pipe:1".to_string(),
+        ]);
+    }
     args
 }
 
@@ -70,11 +101,53 @@ pub fn build_recording_ffmpeg_args(
         "-hide_banner".to_string(),
         "-loglevel".to_string(),
         "warning".to_string(),
-        "-rtsp_transport".to_string(),
-        "tcp".to_string(),
-        "-i".to_string(),
-        plan.input_url.clone(),
     ];
+    if plan.test_pattern {
+        args.push("-re".to_string());
+        args.extend(test_pattern_video_args());
+        args.extend([
+            "-re".to_string(),
+            "-f".to_string(),
+            "lavfi".to_string(),
+            "-i".to_string(),
+            "sine=frequency=440:sample_rate=48000".to_string(),
+            "-map".to_string(),
+            "0:v:0".to_string(),
+            "-map".to_string(),
+            "1:a:0".to_string(),
+            "-c:v".to_string(),
+            "mpeg4".to_string(),
+            "-q:v".to_string(),
+            "5".to_string(),
+            "-g".to_string(),
+            TEST_PATTERN_FPS.to_string(),
+            "-c:a".to_string(),
+            "aac".to_string(),
+        ]);
+        args.extend(segment_output_args(plan.segment_secs, output_pattern));
+        return args;
+    }
+    if let Some(protocol) = plan.listen {
+        match protocol {
+            PushProtocol::Rtmp => args.extend([
+                "-f".to_string(),
+                "flv".to_string(),
+                "-listen".to_string(),
+                "1".to_string(),
+            ]),
+            PushProtocol::Srt => args.extend(["-f".to_string(), "mpegts".to_string()]),
+        }
+    } else if is_rtsp_input(&plan.input_url) {
+        args.extend(["-rtsp_transport".to_string(), "tcp".to_string()]);
+    } else {
+        // File-backed fixture sources are paced and looped so they behave like a live camera.
+        args.extend([
+            "-re".to_string(),
+            "-stream_loop".to_string(),
+            "-1".to_string(),
+        ]);
+    }
+    args.extend(["-i".to_string(), plan.input_url.clone()]);
 
     match plan.audio.mode {
         AudioPlanMode::Drop => {
@@ -90,16 +163,104 @@ pub fn build_recording_ffmpeg_args(
         }
     }
 
-    args.extend([
+    args.extend(segment_output_args(plan.segment_secs, output_pattern));
+    args
+}
+
+fn segment_output_args(segment_secs: u64, output_pattern: &Path) -> Vec<String> {
+    vec![
         "-f".to_string(),
         "segment".to_string(),
         "-segment_time".to_string(),
-        plan.segment_secs.to_string(),
+        segment_secs.to_string(),
         "-reset_timestamps".to_string(),
         "1".to_string(),
         "-strftime".to_string(),
         "1".to_string(),
         output_pattern.to_string_lossy().to_string(),
+    ]
+}
+
+fn test_pattern_video_args() -> Vec<String> {
+    vec![
+        "-f".to_string(),
+        "lavfi".to_string(),
+        "-i".to_string(),
+        format!("testsrc2=size=1280x720:rate={TEST_PATTERN_FPS}"),
+    ]
+}
+
+pub fn build_snapshot_ffmpeg_args(input_url: Option<&str>) -> Vec<String> {
+    let mut args = vec![
+        "-hide_banner".to_string(),
+        "-loglevel".to_string(),
+        "error".to_string(),
+    ];
+    match input_url {
+        Some(input_url) => {
+            if is_rtsp_input(input_url) {
+                args.extend(["-rtsp_transport".to_string(), "tcp".to_string()]);
+            }
+            args.extend(["-i".to_string(), input_url.to_string()]);
+        }
+        None => args.extend(test_pattern_video_args()),
+    }
+    args.extend([
+        "-frames:v".to_string(),
+        "1".to_string(),
+        "-q:v".to_string(),
+        "4".to_string(),
+        "-f".to_string(),
+        "image2".to_string(),
+        "-c:v".to_string(),
+        "mjpeg".to_string(),
+        "pipe:1".to_string(),
     ]);
     args
 }
+
+pub fn build_clip_concat_ffmpeg_args(list_path: &Path, output_path: &Path) -> Vec<String> {
+    vec![
+        "-hide_banner".to_string(),
+        "-loglevel".to_string(),
+        "error".to_string(),
+        "-f".to_string(),
+        "concat".to_string(),
+        "-safe".to_string(),
+        "0".to_string(),
+        "-i".to_string(),
+        list_path.to_string_lossy().to_string(),
+        "-c".to_string(),
+        "copy".to_string(),
+        "-movflags".to_string(),
+        "+faststart".to_string(),
+        "-y".to_string(),
+        output_path.to_string_lossy().to_string(),
+    ]
+}
+
+pub fn build_fragmented_mp4_ffmpeg_args(input_path: &Path) -> Vec<String> {
+    vec![
+        "-hide_banner".to_string(),
+        "-loglevel".to_string(),
+        "error".to_string(),
+        "-i".to_string(),
+        input_path.to_string_lossy().to_string(),
+        "-map".to_string(),
+        "0:v?".to_string(),
+        "-map".to_string(),
+        "0:a?".to_string(),
+        "-c".to_string(),
+        "copy".to_string(),
+        "-movflags".to_string(),
+        "frag_keyframe+empty_moov+default_base_moof".to_string(),
+        "-f".to_string(),
+        "mp4".to_string(),
+        "pipe:1".to_string(),
+    ]
+}
+
+fn is_rtsp_input(input_url: &str) -> bool {
+    let lowered = input_url.trim().to_ascii_lowercase();
+    lowered.starts_with("rtsp://") || lowered.starts_with("rtsps://")
+}
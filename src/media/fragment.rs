@@ -0,0 +1,393 @@
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, timeout};
+use zeroize::Zeroizing;
+
+use super::ffmpeg;
+
+const REMUX_IDLE_TIMEOUT_SECS: u64 = 60;
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+const FRAGMENTABLE: &[&str] = &[
+    "avc1", "avc3", "hvc1", "hev1", "av01", "vp09", "mp4a", "Opus", "fLaC",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum FragmentError {
+    #[error("ffmpeg is not installed")]
+    FfmpegMissing,
+    #[error("segment codec {} cannot be played as fragmented MP4 without transcoding", .0.join(", "))]
+    Unfragmentable(Vec<String>),
+    #[error("remux stalled")]
+    Timeout,
+    #[error("{0}")]
+    Failed(String),
+}
+
+pub fn track_codecs(mp4: &[u8]) -> Vec<String> {
+    let Some(moov) = child(mp4, b"moov") else {
+        return Vec::new();
+    };
+    boxes(moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .filter_map(|(_, trak)| {
+            let stsd = [b"mdia", b"minf", b"stbl", b"stsd"]
+                .iter()
+                .try_fold(trak, |data, kind| child(data, kind))?;
+            // Version, flags, and entry count precede the first sample entry.
+            let (kind, entry) = boxes(stsd.get(8..)?).next()?;
+            Some(codec_string(&kind, entry))
+        })
+        .collect()
+}
+
+pub fn check_fragmentable(codecs: &[String]) -> Result<(), FragmentError> {
+    let refused = codecs
+        .iter()
+        .filter(|codec| {
+            let fourcc = codec.split('.').next().unwrap_or_default();
+            !FRAGMENTABLE.contains(&fourcc)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if refused.is_empty() {
+        Ok(())
+    } else {
+        Err(FragmentError::Unfragmentable(refused))
+    }
+}
+
+pub fn mime_type(init: &[u8]) -> String {
+    let codecs = track_codecs(init);
+    if codecs.is_empty() {
+        return "video/mp4".to_string();
+    }
+    format!("video/mp4; codecs=\"{}\"", codecs.join(","))
+}
+
+fn codec_string(kind: &[u8; 4], entry: &[u8]) -> String {
+    let fourcc = String::from_utf8_lossy(kind).to_string();
+    match kind {
+        // Visual sample entries carry 78 bytes of fields before their child boxes.
+        b"avc1" | b"avc3" => entry
+            .get(78..)
+            .and_then(|children| child(children, b"avcC"))
+            .and_then(|avcc| avcc.get(1..4))
+            .map_or(fourcc.clone(), |profile| {
+                format!(
+                    "{fourcc}.{:02x}{:02x}{:02x}",
+                    profile[0], profile[1], profile[2]
+                )
+            }),
+        // Audio sample entries carry 28.
+        b"mp4a" => entry
+            .get(28..)
+            .and_then(|children| child(children, b"esds"))
+            .and_then(esds_codec)
+            .map_or(fourcc, |(object_type, audio_type)| match audio_type {
+                Some(audio_type) => format!("mp4a.{object_type:02x}.{audio_type}"),
+                None => format!("mp4a.{object_type:02x}"),
+            }),
+        _ => fourcc,
+    }
+}
+
+fn esds_codec(esds: &[u8]) -> Option<(u8, Option<u8>)> {
+    let (tag, es) = descriptor(esds.get(4..)?)?;
+    if tag != 3 {
+        return None;
+    }
+    let flags = *es.get(2)?;
+    let mut at = 3;
+    if flags & 0x80 != 0 {
+        at += 2;
+    }
+    if flags & 0x40 != 0 {
+        at += 1 + usize::from(*es.get(at)?);
+    }
+    if flags & 0x20 != 0 {
+        at += 2;
+    }
+    let (tag, config) = descriptor(es.get(at..)?)?;
+    if tag != 4 {
+        return None;
+    }
+    let object_type = *config.first()?;
+    let audio_type = config
+        .get(13..)
+        .and_then(descriptor)
+        .filter(|(tag, _)| *tag == 5)
+        .and_then(|(_, specific)| specific.first().map(|byte| byte >> 3));
+    Some((object_type, audio_type))
+}
+
+fn descriptor(data: &[u8]) -> Option<(u8, &[u8])> {
+    let tag = *data.first()?;
+    let mut len = 0usize;
+    let mut at = 1;
+    loop {
+        let byte = *data.get(at)?;
+        len = (len << 7) | usize::from(byte & 0x7f);
+        at += 1;
+        if byte & 0x80 == 0 || at == 5 {
+            break;
+        }
+    }
+    Some((tag, data.get(at..at.checked_add(len)?)?))
+}
+
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut offset = 0usize;
+    std::iter::from_fn(move || {
+        let (header, size) = box_header(data.get(offset..)?)?;
+        let kind = data[offset + 4..offset + 8].try_into().ok()?;
+        let payload = data.get(offset + header..offset.checked_add(size)?)?;
+        offset += size;
+        Some((kind, payload))
+    })
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(found, _)| found == kind)
+        .map(|(_, payload)| payload)
+}
+
+fn box_header(data: &[u8]) -> Option<(usize, usize)> {
+    let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+    data.get(4..8)?;
+    let (header, size) = match size {
+        0 => return None,
+        1 => (
+            16,
+            usize::try_from(u64::from_be_bytes(data.get(8..16)?.try_into().ok()?)).ok()?,
+        ),
+        size => (8, size as usize),
+    };
+    (size >= header).then_some((header, size))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Piece {
+    Init(Zeroizing<Vec<u8>>),
+    Fragment(Zeroizing<Vec<u8>>),
+}
+
+#[derive(Default)]
+pub struct FragmentSplitter {
+    pending: Zeroizing<Vec<u8>>,
+    piece: Zeroizing<Vec<u8>>,
+    init_sent: bool,
+}
+
+impl FragmentSplitter {
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Piece> {
+        self.pending.extend_from_slice(bytes);
+        let mut out = Vec::new();
+        let mut offset = 0;
+        while let Some((_, size)) = box_header(&self.pending[offset..]) {
+            let Some(end) = offset.checked_add(size) else {
+                break;
+            };
+            if end > self.pending.len() {
+                break;
+            }
+            let kind = &self.pending[offset + 4..offset + 8];
+            if !self.init_sent && kind == b"moof" {
+                out.push(Piece::Init(std::mem::take(&mut self.piece)));
+                self.init_sent = true;
+            }
+            let is_mdat = kind == b"mdat";
+            self.piece.extend_from_slice(&self.pending[offset..end]);
+            if self.init_sent && is_mdat {
+                out.push(Piece::Fragment(std::mem::take(&mut self.piece)));
+            }
+            offset = end;
+        }
+        self.pending.drain(..offset);
+        out
+    }
+}
+
+pub async fn remux(
+    input: &Path,
+    live: &mpsc::Sender<Zeroizing<Vec<u8>>>,
+) -> Result<Zeroizing<Vec<u8>>, FragmentError> {
+    let mut process = Command::new("ffmpeg")
+        // Input must be a file: segments keep their moov at the end, which ffmpeg cannot seek to
+        // on a pipe.
+        .args(ffmpeg::build_fragmented_mp4_ffmpeg_args(input))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                FragmentError::FfmpegMissing
+            } else {
+                FragmentError::Failed(format!("failed to start ffmpeg: {err}"))
+            }
+        })?;
+    let mut stdout = process.stdout.take().expect("stdout is piped");
+    let mut stderr = process.stderr.take().expect("stderr is piped");
+    let stderr = tokio::spawn(async move {
+        let mut text = Vec::new();
+        let _ = stderr.read_to_end(&mut text).await;
+        text
+    });
+    let mut output = Zeroizing::new(Vec::new());
+    let mut buf = Zeroizing::new(vec![0u8; READ_CHUNK_BYTES]);
+    loop {
+        let read = timeout(
+            Duration::from_secs(REMUX_IDLE_TIMEOUT_SECS),
+            stdout.read(&mut buf),
+        )
+        .await
+        .map_err(|_| FragmentError::Timeout)?
+        .map_err(|err| FragmentError::Failed(format!("read ffmpeg output: {err}")))?;
+        if read == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..read]);
+        // A reader that went away still leaves the remux to finish for the cache.
+        let _ = live.send(Zeroizing::new(buf[..read].to_vec())).await;
+    }
+    let status = timeout(Duration::from_secs(REMUX_IDLE_TIMEOUT_SECS), process.wait())
+        .await
+        .map_err(|_| FragmentError::Timeout)?
+        .map_err(|err| FragmentError::Failed(format!("wait for ffmpeg: {err}")))?;
+    let stderr = stderr.await.unwrap_or_default();
+    if !status.success() {
+        return Err(classify_failure(&stderr, status.code()));
+    }
+    Ok(output)
+}
+
+fn classify_failure(stderr: &[u8], code: Option<i32>) -> FragmentError {
+    let stderr = String::from_utf8_lossy(stderr);
+    if let Some((_, rest)) = stderr.split_once("Could not find tag for codec ") {
+        let codec = rest.split_whitespace().next().unwrap_or("unknown");
+        return FragmentError::Unfragmentable(vec![codec.to_string()]);
+    }
+    match stderr.lines().map(str::trim).rfind(|line| !line.is_empty()) {
+        Some(line) => FragmentError::Failed(format!("remux failed: {line}")),
+        None => FragmentError::Failed(format!("remux failed: ffmpeg exited with code {code:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn sample_entry(kind: &[u8; 4], fields: usize, config: Vec<u8>) -> Vec<u8> {
+        let mut payload = vec![0u8; fields];
+        payload.extend(config);
+        mp4_box(kind, &payload)
+    }
+
+    fn trak(entry: Vec<u8>) -> Vec<u8> {
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(entry);
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl)))
+    }
+
+    fn moov(entries: Vec<Vec<u8>>) -> Vec<u8> {
+        mp4_box(
+            b"moov",
+            &entries.into_iter().flat_map(trak).collect::<Vec<_>>(),
+        )
+    }
+
+    fn avc1() -> Vec<u8> {
+        sample_entry(b"avc1", 78, mp4_box(b"avcC", &[1, 0x64, 0x00, 0x1f, 0xff]))
+    }
+
+    fn mp4a() -> Vec<u8> {
+        // ES descriptor, then a decoder config for AAC (0x40) holding AAC-LC (2).
+        let specific = [5, 2, 0x12, 0x10];
+        let mut config = vec![4, 13 + specific.len() as u8, 0x40, 0x15];
+        config.extend([0; 11]);
+        config.extend(specific);
+        let mut es = vec![3, 3 + config.len() as u8, 0, 1, 0];
+        es.extend(config);
+        let mut esds = vec![0, 0, 0, 0];
+        esds.extend(es);
+        sample_entry(b"mp4a", 28, mp4_box(b"esds", &esds))
+    }
+
+    #[test]
+    fn codecs_are_read_from_sample_entries() {
+        let file = [mp4_box(b"ftyp", b"isom"), moov(vec![avc1(), mp4a()])].concat();
+        assert_eq!(track_codecs(&file), vec!["avc1.64001f", "mp4a.40.2"]);
+        assert_eq!(
+            mime_type(&file),
+            "video/mp4; codecs=\"avc1.64001f,mp4a.40.2\""
+        );
+        assert!(check_fragmentable(&track_codecs(&file)).is_ok());
+
+        // The test pattern records MPEG-4 Part 2, which browsers do not decode.
+        let test_pattern = moov(vec![sample_entry(b"mp4v", 78, Vec::new()), mp4a()]);
+        let err = check_fragmentable(&track_codecs(&test_pattern)).unwrap_err();
+        assert!(matches!(&err, FragmentError::Unfragmentable(codecs) if codecs == &["mp4v"]));
+        assert!(err.to_string().contains("mp4v"));
+        assert!(track_codecs(b"not an mp4").is_empty());
+    }
+
+    #[test]
+    fn splitter_separates_init_and_fragments_across_reads() {
+        let init = [mp4_box(b"ftyp", b"iso5"), moov(vec![avc1()])].concat();
+        let first = [mp4_box(b"moof", &[1; 20]), mp4_box(b"mdat", &[2; 300])].concat();
+        let second = [
+            mp4_box(b"styp", b"msdh"),
+            mp4_box(b"moof", &[3; 20]),
+            mp4_box(b"mdat", &[4; 10]),
+        ]
+        .concat();
+        let stream = [
+            init.clone(),
+            first.clone(),
+            second.clone(),
+            mp4_box(b"mfra", &[0; 16]),
+        ]
+        .concat();
+
+        let mut splitter = FragmentSplitter::default();
+        let mut pieces = Vec::new();
+        for chunk in stream.chunks(7) {
+            pieces.extend(splitter.push(chunk));
+        }
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Init(Zeroizing::new(init)),
+                Piece::Fragment(Zeroizing::new(first)),
+                Piece::Fragment(Zeroizing::new(second)),
+            ]
+        );
+    }
+
+    #[test]
+    fn muxer_refusals_read_as_unfragmentable() {
+        let stderr = b"[mp4 @ 0x1] Could not find tag for codec pcm_alaw in stream #1, \
+            codec not currently supported in container\n";
+        assert!(matches!(
+            classify_failure(stderr, Some(1)),
+            FragmentError::Unfragmentable(codecs) if codecs == ["pcm_alaw"]
+        ));
+        assert!(matches!(
+            classify_failure(b"\nmoov atom not found\n", Some(1)),
+            FragmentError::Failed(message) if message.ends_with("moov atom not found")
+        ));
+    }
+}
@@ -1,4 +1,8 @@
+pub mod clip;
+pub mod dependencies;
 pub mod ffmpeg;
+pub mod fragment;
 pub mod planner;
+pub mod snapshot;
 pub mod transcode;
 pub mod types;
@@ -2,7 +2,7 @@
 
 use anyhow::{Result, anyhow};
 
-use crate::config::CameraDeviceConfig;
+use crate::config::{CameraDeviceConfig, CameraSourceType, PushProtocol};
 
 use super::transcode::{preview_video_mode, xm_recording_audio_mode};
 use super::types::{
@@ -15,7 +15,7 @@ pub fn preview_pipeline_plan(
     offer_sdp: &str,
 ) -> Result<PreviewPipelinePlan> {
     let video_mode = preview_video_mode(&camera.driver_id);
-    let input_url = preview_rtsp_url(camera);
+    let input_url = camera.rtsp_input_url(&preview_rtsp_url(camera));
     let output_codec = match video_mode {
         VideoPlanMode::Copy => OutputCodec::H264,
         VideoPlanMode::Transcode => {
@@ -58,10 +58,10 @@ pub fn preview_pipeline_plan_for_codec(
     let is_xm = camera.driver_id.trim() == "xm_40e";
     let video_mode = match output_codec {
         OutputCodec::H264 => VideoPlanMode::Copy,
-        OutputCodec::Vp8 => VideoPlanMode::Transcode,
+        OutputCodec::Vp8 | OutputCodec::Mpeg4 => VideoPlanMode::Transcode,
     };
     PreviewPipelinePlan {
-        input_url: preview_rtsp_url(camera),
+        input_url: camera.rtsp_input_url(&preview_rtsp_url(camera)),
         video: VideoPlan {
             mode: video_mode.clone(),
             output_codec,
@@ -80,9 +80,47 @@ pub fn preview_pipeline_plan_for_codec(
 }
 
 pub fn recording_pipeline_plan(camera: &CameraDeviceConfig) -> RecordingPipelinePlan {
+    if camera.source_type == CameraSourceType::Test {
+        return RecordingPipelinePlan {
+            input_url: String::new(),
+            test_pattern: true,
+            listen: None,
+            video: VideoPlan {
+                mode: VideoPlanMode::Transcode,
+                output_codec: OutputCodec::Mpeg4,
+            },
+            audio: AudioPlan {
+                mode: AudioPlanMode::Transcode,
+            },
+            container: OutputContainer::SegmentMp4,
+            segment_secs: camera.segment_secs,
+            reason: "Test source encodes a generated pattern and tone into MP4 segments"
+                .to_string(),
+        };
+    }
+    if camera.source_type == CameraSourceType::Push {
+        return RecordingPipelinePlan {
+            input_url: push_listen_url(camera),
+            test_pattern: false,
+            listen: Some(camera.push.protocol),
+            video: VideoPlan {
+                mode: VideoPlanMode::Copy,
+                output_codec: OutputCodec::H264,
+            },
+            audio: AudioPlan {
+                mode: AudioPlanMode::Copy,
+            },
+            container: OutputContainer::SegmentMp4,
+            segment_secs: camera.segment_secs,
+            reason: "Push source copies whatever the sender publishes into MP4 segments"
+                .to_string(),
+        };
+    }
     let audio_mode = xm_recording_audio_mode(&camera.driver_id);
     RecordingPipelinePlan {
-        input_url: camera.rtsp_url.clone(),
+        input_url: camera.rtsp_input_url(&camera.rtsp_url),
+        test_pattern: false,
+        listen: None,
         video: VideoPlan {
             mode: VideoPlanMode::Copy,
             output_codec: OutputCodec::H264,
@@ -98,6 +136,25 @@ pub fn recording_pipeline_plan(camera: &CameraDeviceConfig) -> RecordingPipeline
     }
 }
 
+pub fn push_listen_url(camera: &CameraDeviceConfig) -> String {
+    push_url(camera, "0.0.0.0", "listener")
+}
+
+pub fn publish_url(camera: &CameraDeviceConfig, host: &str) -> String {
+    push_url(camera, host, "caller")
+}
+
+fn push_url(camera: &CameraDeviceConfig, host: &str, srt_mode: &str) -> String {
+    let push = &camera.push;
+    match push.protocol {
+        PushProtocol::Rtmp => format!("rtmp://{host}:{}/live/{}", push.port, push.stream_key),
+        PushProtocol::Srt => format!(
+            "srt://{host}:{}?mode={srt_mode}&passphrase={}",
+            push.port, push.stream_key
+        ),
+    }
+}
+
 pub fn preview_rtsp_url(camera: &CameraDeviceConfig) -> String {
     let mut url = camera.rtsp_url.clone();
     if url.contains("h264Preview_01_main") {
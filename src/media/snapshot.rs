@@ -0,0 +1,42 @@
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+
+use crate::camera_device::CameraError;
+use crate::config::{CameraDeviceConfig, CameraSourceType};
+
+use super::ffmpeg;
+
+const SNAPSHOT_TIMEOUT_SECS: u64 = 15;
+
+pub async fn capture_jpeg(camera: &CameraDeviceConfig) -> Result<Vec<u8>, CameraError> {
+    if !camera.is_capturing() {
+        return Err(CameraError::NoStream("camera source is not capturing"));
+    }
+    if camera.source_type == CameraSourceType::Push {
+        return Err(CameraError::NoStream(
+            "push sources have no stream to open; the recorder holds the listener",
+        ));
+    }
+    let input_url = (camera.source_type != CameraSourceType::Test)
+        .then(|| camera.rtsp_input_url(&camera.rtsp_url));
+    let output = timeout(
+        Duration::from_secs(SNAPSHOT_TIMEOUT_SECS),
+        Command::new("ffmpeg")
+            .args(ffmpeg::build_snapshot_ffmpeg_args(input_url.as_deref()))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| CameraError::Timeout)?
+    .map_err(CameraError::from_spawn)?;
+    // stderr is only classified: ffmpeg echoes the credential-bearing input URL there.
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(CameraError::from_ffmpeg_stderr(
+            &output.stderr,
+            output.status.code(),
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -2,6 +2,8 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::config::PushProtocol;
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceStreamDescriptor {
@@ -51,6 +53,7 @@ pub enum AudioPlanMode {
 pub enum OutputCodec {
     H264,
     Vp8,
+    Mpeg4,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -89,6 +92,8 @@ pub struct PreviewPipelinePlan {
 #[serde(rename_all = "camelCase")]
 pub struct RecordingPipelinePlan {
     pub input_url: String,
+    pub test_pattern: bool,
+    pub listen: Option<PushProtocol>,
     pub video: VideoPlan,
     pub audio: AudioPlan,
     pub container: OutputContainer,
@@ -1,20 +1,23 @@
 use crate::camera_device::registry::driver_is_xm;
 use crate::config::{CameraDeviceConfig, Config};
 use crate::media::{ffmpeg, planner, types::OutputCodec};
-use crate::recording::runtime::backoff_secs;
+use crate::recording::runtime::RESTART_BACKOFF;
+use crate::util::backoff::RetryState;
 use rtp::packet::Packet as RtpPacket;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::process::Stdio;
 use std::sync::Arc;
+use tokio::io::AsyncReadExt;
 use tokio::net::UdpSocket;
-use tokio::process::Command;
+use tokio::process::{ChildStdout, Command};
 use tokio::sync::{Mutex, broadcast, watch};
 use tokio::time::{Duration, sleep, timeout};
 use tracing::{debug, info, warn};
 use util::marshal::Unmarshal;
 
 const PROJECTION_RTP_BUFFER: usize = 512;
+const LATEST_FRAME_MAX_BYTES: usize = 8 * 1024 * 1024;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ProjectionCodec {
@@ -48,6 +51,7 @@ pub struct MediaProjectionSourceHealth {
     pub selected_stream: String,
     pub subscriber_count: usize,
     pub restart_attempt: u64,
+    pub next_retry_at: u64,
     pub last_packet_timestamp: Option<u64>,
     pub last_error: Option<String>,
     pub warmed_at: Option<u64>,
@@ -60,20 +64,61 @@ pub struct MediaProjectionHealth {
     pub sources: Vec<MediaProjectionSourceHealth>,
 }
 
+#[derive(Clone, Debug)]
+pub struct LatestFrame {
+    pub jpeg: Arc<Vec<u8>>,
+    pub captured_at: u64,
+}
+
+#[derive(Clone, Default)]
+struct LatestFrames {
+    interval_secs: u64,
+    frames: Arc<std::sync::Mutex<HashMap<String, LatestFrame>>>,
+}
+
+impl LatestFrames {
+    fn store(&self, source_id: &str, jpeg: Vec<u8>) {
+        let frame = LatestFrame {
+            jpeg: Arc::new(jpeg),
+            captured_at: crate::util::now_ms(),
+        };
+        self.lock().insert(source_id.trim().to_string(), frame);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, LatestFrame>> {
+        self.frames
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
 #[derive(Clone)]
 pub struct MediaProjectionRuntime {
     inner: Arc<Mutex<HashMap<ProjectionKey, Arc<ProjectionHandle>>>>,
+    latest: LatestFrames,
 }
 
 impl MediaProjectionRuntime {
-    pub fn new() -> Self {
+    pub fn new(latest_frame_interval_secs: u64) -> Self {
         Self {
             inner: Arc::new(Mutex::new(HashMap::new())),
+            latest: LatestFrames {
+                interval_secs: latest_frame_interval_secs,
+                ..LatestFrames::default()
+            },
         }
     }
 
+    pub fn latest_frame(&self, source_id: &str) -> Option<LatestFrame> {
+        self.latest.lock().get(source_id.trim()).cloned()
+    }
+
     pub async fn warm_enabled_previews(&self, cfg: &Config) {
-        for camera in cfg.camera_devices.iter().filter(|camera| camera.enabled) {
+        for camera in cfg
+            .camera_devices
+            .iter()
+            .filter(|camera| camera.is_capturing() && camera.has_rtsp())
+        {
             let codec = preferred_projection_codec(camera);
             self.ensure_projection(camera.clone(), codec).await;
         }
@@ -96,7 +141,11 @@ impl MediaProjectionRuntime {
         self.warm_enabled_previews(cfg).await;
         let handles = self.inner.lock().await;
         let mut sources = Vec::new();
-        for camera in cfg.camera_devices.iter().filter(|camera| camera.enabled) {
+        for camera in cfg
+            .camera_devices
+            .iter()
+            .filter(|camera| camera.is_capturing() && camera.has_rtsp())
+        {
             let codec = preferred_projection_codec(camera);
             let key = ProjectionKey::new(&camera.source_id, codec);
             if let Some(handle) = handles.get(&key) {
@@ -115,6 +164,7 @@ impl MediaProjectionRuntime {
                     selected_stream: "preview".to_string(),
                     subscriber_count: 0,
                     restart_attempt: 0,
+                    next_retry_at: 0,
                     last_packet_timestamp: None,
                     last_error: None,
                     warmed_at: None,
@@ -127,6 +177,19 @@ impl MediaProjectionRuntime {
         }
     }
 
+    pub async fn stop_source(&self, source_id: &str) {
+        self.latest.lock().remove(source_id.trim());
+        let mut handles = self.inner.lock().await;
+        handles.retain(|key, handle| {
+            if key.source_id == source_id.trim() {
+                let _ = handle.stop.send(true);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
     async fn ensure_projection(
         &self,
         camera: CameraDeviceConfig,
@@ -144,18 +207,25 @@ impl MediaProjectionRuntime {
         let handle = Arc::new(ProjectionHandle {
             key: key.clone(),
             sender: sender.clone(),
-            _stop: stop_tx,
+            stop: stop_tx,
             state: Arc::clone(&state),
         });
         handles.insert(key.clone(), Arc::clone(&handle));
-        tokio::spawn(run_projection_worker(camera, codec, sender, state, stop_rx));
+        tokio::spawn(run_projection_worker(
+            camera,
+            codec,
+            sender,
+            state,
+            stop_rx,
+            self.latest.clone(),
+        ));
         handle
     }
 }
 
 impl Default for MediaProjectionRuntime {
     fn default() -> Self {
-        Self::new()
+        Self::new(0)
     }
 }
 
@@ -183,7 +253,7 @@ impl ProjectionKey {
 struct ProjectionHandle {
     key: ProjectionKey,
     sender: broadcast::Sender<RtpPacket>,
-    _stop: watch::Sender<bool>,
+    stop: watch::Sender<bool>,
     state: Arc<Mutex<ProjectionState>>,
 }
 
@@ -192,7 +262,7 @@ struct ProjectionState {
     source_id: String,
     state: String,
     codec: ProjectionCodec,
-    restart_attempt: u64,
+    retry: RetryState,
     last_packet_timestamp: Option<u64>,
     last_error: Option<String>,
     warmed_at: Option<u64>,
@@ -204,7 +274,7 @@ impl ProjectionState {
             source_id: source_id.to_string(),
             state: "warming".to_string(),
             codec,
-            restart_attempt: 0,
+            retry: RetryState::default(),
             last_packet_timestamp: None,
             last_error: None,
             warmed_at: Some(crate::util::now_ms()),
@@ -226,7 +296,8 @@ impl ProjectionState {
             codec: self.codec.label().to_string(),
             selected_stream: "preview".to_string(),
             subscriber_count,
-            restart_attempt: self.restart_attempt,
+            restart_attempt: self.retry.attempt,
+            next_retry_at: self.retry.next_retry_at,
             last_packet_timestamp: self.last_packet_timestamp,
             last_error: self.last_error.clone(),
             warmed_at: self.warmed_at,
@@ -270,6 +341,7 @@ async fn run_projection_worker(
     sender: broadcast::Sender<RtpPacket>,
     state: Arc<Mutex<ProjectionState>>,
     mut stop_rx: watch::Receiver<bool>,
+    latest: LatestFrames,
 ) {
     let plan = planner::preview_pipeline_plan_for_codec(&camera, codec.output_codec());
     let std_socket = match std::net::UdpSocket::bind("127.0.0.1:0") {
@@ -320,9 +392,15 @@ async fn run_projection_worker(
         ffmpeg.args(ffmpeg::build_live_preview_ffmpeg_args(
             &plan,
             local_addr.port(),
+            latest.interval_secs,
         ));
+        let stdout = if latest.interval_secs > 0 {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
 
-        let mut child = match ffmpeg.stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
+        let mut child = match ffmpeg.stdout(stdout).stderr(Stdio::null()).spawn() {
             Ok(child) => child,
             Err(err) => {
                 let backoff =
@@ -331,10 +409,10 @@ async fn run_projection_worker(
                     source = %camera.source_id,
                     codec = codec.label(),
                     error = %err,
-                    backoff_secs = backoff,
+                    backoff_secs = backoff.as_secs(),
                     "media projection worker failed to start; will retry"
                 );
-                wait_or_stop(Duration::from_secs(backoff), &mut stop_rx).await;
+                wait_or_stop(backoff, &mut stop_rx).await;
                 continue;
             }
         };
@@ -344,6 +422,13 @@ async fn run_projection_worker(
             codec = codec.label(),
             "media projection worker started"
         );
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(read_latest_frames(
+                stdout,
+                camera.source_id.clone(),
+                latest.clone(),
+            ));
+        }
 
         let exit_reason = loop {
             tokio::select! {
@@ -371,7 +456,7 @@ async fn run_projection_worker(
                             {
                                 let mut current = state.lock().await;
                                 current.state = "ready".to_string();
-                                current.restart_attempt = 0;
+                                current.retry.succeeded();
                                 current.last_packet_timestamp = Some(crate::util::now_ms());
                                 current.last_error = None;
                             }
@@ -401,10 +486,10 @@ async fn run_projection_worker(
                     source = %camera.source_id,
                     codec = codec.label(),
                     message,
-                    backoff_secs = backoff,
+                    backoff_secs = backoff.as_secs(),
                     "media projection worker exited; restarting"
                 );
-                wait_or_stop(Duration::from_secs(backoff), &mut stop_rx).await;
+                wait_or_stop(backoff, &mut stop_rx).await;
             }
             ProjectionExitReason::NoPackets(timeout_secs) => {
                 let message = format!("no RTP packets for {timeout_secs}s");
@@ -413,10 +498,10 @@ async fn run_projection_worker(
                     source = %camera.source_id,
                     codec = codec.label(),
                     idle_timeout_secs = timeout_secs,
-                    backoff_secs = backoff,
+                    backoff_secs = backoff.as_secs(),
                     "media projection worker stalled; restarting"
                 );
-                wait_or_stop(Duration::from_secs(backoff), &mut stop_rx).await;
+                wait_or_stop(backoff, &mut stop_rx).await;
             }
         }
     }
@@ -428,6 +513,47 @@ async fn run_projection_worker(
     debug!(source = %camera.source_id, codec = codec.label(), "media projection worker stopped");
 }
 
+async fn read_latest_frames(mut stdout: ChildStdout, source_id: String, latest: LatestFrames) {
+    let mut pending = Vec::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        match stdout.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(len) => pending.extend_from_slice(&chunk[..len]),
+        }
+        if let Some(frame) = take_jpegs(&mut pending).pop() {
+            latest.store(&source_id, frame);
+        }
+        if pending.len() > LATEST_FRAME_MAX_BYTES {
+            pending.clear();
+        }
+    }
+}
+
+fn take_jpegs(pending: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut consumed = 0;
+    while let Some(start) = find_marker(&pending[consumed..], 0xD8).map(|at| consumed + at) {
+        let Some(end) = find_marker(&pending[start + 2..], 0xD9).map(|at| start + 2 + at + 2)
+        else {
+            consumed = start;
+            break;
+        };
+        frames.push(pending[start..end].to_vec());
+        consumed = end;
+    }
+    if frames.is_empty() && find_marker(pending, 0xD8).is_none() {
+        // A trailing FF may be the first half of the next SOI.
+        consumed = pending.len() - usize::from(pending.last() == Some(&0xFF));
+    }
+    pending.drain(..consumed);
+    frames
+}
+
+fn find_marker(data: &[u8], marker: u8) -> Option<usize> {
+    data.windows(2).position(|pair| pair == [0xFF, marker])
+}
+
 async fn wait_or_stop(duration: Duration, stop_rx: &mut watch::Receiver<bool>) {
     tokio::select! {
         _ = stop_rx.changed() => {}
@@ -441,12 +567,15 @@ async fn set_projection_error(state: &Arc<Mutex<ProjectionState>>, message: Stri
     current.last_error = Some(message);
 }
 
-async fn note_projection_backoff(state: &Arc<Mutex<ProjectionState>>, message: String) -> u64 {
+async fn note_projection_backoff(state: &Arc<Mutex<ProjectionState>>, message: String) -> Duration {
     let mut current = state.lock().await;
-    current.restart_attempt = current.restart_attempt.saturating_add(1);
+    let delay = current
+        .retry
+        .failed(&RESTART_BACKOFF, &message)
+        .unwrap_or(RESTART_BACKOFF.cap());
     current.state = "backoff".to_string();
     current.last_error = Some(message);
-    backoff_secs(current.restart_attempt)
+    delay
 }
 
 fn projection_no_packet_timeout_secs() -> u64 {
@@ -557,6 +686,7 @@ mod tests {
             selected_stream: "preview".to_string(),
             subscriber_count: 1,
             restart_attempt: 0,
+            next_retry_at: 0,
             last_packet_timestamp: Some(1),
             last_error: None,
             warmed_at: Some(1),
@@ -566,6 +696,25 @@ mod tests {
         assert_eq!(aggregate_projection_state(&[source]), "degraded");
     }
 
+    #[test]
+    fn latest_frames_split_on_jpeg_markers() {
+        let mut pending = vec![0x00, 0xFF, 0xD8, 0x01, 0xFF, 0xD9, 0xFF, 0xD8, 0x02];
+        let frames = take_jpegs(&mut pending);
+        assert_eq!(frames, vec![vec![0xFF, 0xD8, 0x01, 0xFF, 0xD9]]);
+        assert_eq!(pending, vec![0xFF, 0xD8, 0x02]);
+
+        pending.extend_from_slice(&[0xFF, 0xD9]);
+        assert_eq!(
+            take_jpegs(&mut pending),
+            vec![vec![0xFF, 0xD8, 0x02, 0xFF, 0xD9]]
+        );
+        assert!(pending.is_empty());
+
+        let mut noise = vec![0x10, 0x20];
+        assert!(take_jpegs(&mut noise).is_empty());
+        assert!(noise.is_empty());
+    }
+
     #[test]
     fn projection_no_packet_timeout_stays_short() {
         assert_eq!(projection_no_packet_timeout_secs(), 8);
@@ -615,6 +764,13 @@ mod tests {
             segment_secs: 10,
             desired: Default::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         }
     }
 }
@@ -0,0 +1,429 @@
+use crate::config::MqttConfig;
+use crate::notifications::{EventBus, OpsEvent};
+use crate::recording::RecorderManager;
+use crate::util;
+use crate::util::backoff::{Backoff, RetryState};
+use anyhow::{Context, Result, anyhow};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast, mpsc, oneshot};
+use tokio::time::{Duration, MissedTickBehavior, interval, sleep};
+use tracing::{debug, info, warn};
+
+const STATE_POLL_SECS: u64 = 2;
+const CLIENT_CHANNEL_CAPACITY: usize = 128;
+const COMMAND_CHANNEL_CAPACITY: usize = 16;
+const RECONNECT_BACKOFF: Backoff =
+    Backoff::new(Duration::from_secs(1), Duration::from_secs(60)).with_jitter(0.2);
+const ONLINE_PAYLOAD: &str = "online";
+const OFFLINE_PAYLOAD: &str = "offline";
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttStatus {
+    pub enabled: bool,
+    pub connected: bool,
+    pub broker: String,
+    pub commands_enabled: bool,
+    pub connects: u64,
+    pub published: u64,
+    pub dropped: u64,
+    pub commands_received: u64,
+    pub last_connected_at: Option<u64>,
+    pub last_error: String,
+    pub reconnect: RetryState,
+    #[serde(skip)]
+    epoch: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MqttAction {
+    Snapshot,
+    Privacy { enabled: bool },
+}
+
+pub struct MqttCommand {
+    pub topic_source: String,
+    pub action: MqttAction,
+    pub reply: oneshot::Sender<Result<Value, String>>,
+}
+
+#[derive(Clone, Default)]
+pub struct MqttBridge {
+    status: Arc<Mutex<MqttStatus>>,
+}
+
+impl MqttBridge {
+    pub async fn status(&self) -> MqttStatus {
+        self.status.lock().await.clone()
+    }
+
+    pub async fn spawn(
+        &self,
+        cfg: &MqttConfig,
+        node_id: &str,
+        recorder: RecorderManager,
+        bus: &EventBus,
+    ) -> Option<mpsc::Receiver<MqttCommand>> {
+        {
+            let mut status = self.status.lock().await;
+            status.enabled = cfg.enabled;
+            status.commands_enabled = cfg.enabled && cfg.allow_commands;
+        }
+        if !cfg.enabled {
+            return None;
+        }
+        let client_id = if cfg.client_id.trim().is_empty() {
+            node_id
+        } else {
+            cfg.client_id.trim()
+        };
+        let (options, broker) = match mqtt_options(cfg, client_id) {
+            Ok(options) => options,
+            Err(err) => {
+                warn!(error = %err, "mqtt bridge not started");
+                self.status.lock().await.last_error = err.to_string();
+                return None;
+            }
+        };
+        self.status.lock().await.broker = broker;
+
+        let (client, eventloop) = AsyncClient::new(options, CLIENT_CHANNEL_CAPACITY);
+        let (commands_tx, commands_rx) = if cfg.allow_commands {
+            let (tx, rx) = mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        tokio::spawn(self.clone().run_connection(
+            eventloop,
+            client.clone(),
+            cfg.base_topic.clone(),
+            commands_tx,
+        ));
+        tokio::spawn(self.clone().run_publisher(
+            client,
+            cfg.base_topic.clone(),
+            recorder,
+            bus.subscribe(),
+        ));
+        commands_rx
+    }
+
+    async fn run_connection(
+        self,
+        mut eventloop: EventLoop,
+        client: AsyncClient,
+        base: String,
+        commands: Option<mpsc::Sender<MqttCommand>>,
+    ) {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    {
+                        let mut status = self.status.lock().await;
+                        status.reconnect.succeeded();
+                        status.connected = true;
+                        status.connects += 1;
+                        status.epoch += 1;
+                        status.last_connected_at = Some(util::now_unix_seconds());
+                        status.last_error.clear();
+                    }
+                    info!("mqtt broker connected");
+                    let _ = client.try_publish(
+                        availability_topic(&base),
+                        QoS::AtLeastOnce,
+                        true,
+                        ONLINE_PAYLOAD,
+                    );
+                    if commands.is_some() {
+                        let _ = client.try_subscribe(format!("{base}/+/command"), QoS::AtLeastOnce);
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    if let Some(commands) = &commands {
+                        let (topic, payload) = (publish.topic.as_str(), &publish.payload[..]);
+                        self.handle_command(&client, &base, commands, topic, payload)
+                            .await;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    let (delay, was_connected) = {
+                        let mut status = self.status.lock().await;
+                        status.last_error = err.to_string();
+                        let delay = status
+                            .reconnect
+                            .failed(&RECONNECT_BACKOFF, &err)
+                            .unwrap_or(RECONNECT_BACKOFF.cap());
+                        (delay, std::mem::replace(&mut status.connected, false))
+                    };
+                    if was_connected {
+                        warn!(error = %err, "mqtt broker connection lost");
+                    } else {
+                        debug!(error = %err, delay_secs = delay.as_secs(), "mqtt connect failed");
+                    }
+                    sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    async fn handle_command(
+        &self,
+        client: &AsyncClient,
+        base: &str,
+        commands: &mpsc::Sender<MqttCommand>,
+        topic: &str,
+        payload: &[u8],
+    ) {
+        let Some(topic_source) = command_source(base, topic) else {
+            return;
+        };
+        self.status.lock().await.commands_received += 1;
+        let result_topic = format!("{base}/{topic_source}/command/result");
+        let Some(action) = parse_command(payload) else {
+            let body = json!({ "ok": false, "error": "unsupported command" });
+            let _ = client.try_publish(result_topic, QoS::AtLeastOnce, false, body.to_string());
+            return;
+        };
+        let (reply, response) = oneshot::channel();
+        let command = MqttCommand {
+            topic_source: topic_source.to_string(),
+            action,
+            reply,
+        };
+        if commands.try_send(command).is_err() {
+            let body = json!({ "ok": false, "command": action, "error": "command queue full" });
+            let _ = client.try_publish(result_topic, QoS::AtLeastOnce, false, body.to_string());
+            return;
+        }
+        let client = client.clone();
+        tokio::spawn(async move {
+            let body = match response.await {
+                Ok(Ok(result)) => json!({ "ok": true, "command": action, "result": result }),
+                Ok(Err(error)) => json!({ "ok": false, "command": action, "error": error }),
+                Err(_) => json!({ "ok": false, "command": action, "error": "command dropped" }),
+            };
+            let _ = client
+                .publish(result_topic, QoS::AtLeastOnce, false, body.to_string())
+                .await;
+        });
+    }
+
+    async fn run_publisher(
+        self,
+        client: AsyncClient,
+        base: String,
+        recorder: RecorderManager,
+        mut events: broadcast::Receiver<OpsEvent>,
+    ) {
+        let mut published = HashMap::<String, String>::new();
+        let mut epoch = 0u64;
+        let mut ticker = interval(Duration::from_secs(STATE_POLL_SECS));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let (connected, current_epoch) = {
+                        let status = self.status.lock().await;
+                        (status.connected, status.epoch)
+                    };
+                    if !connected {
+                        continue;
+                    }
+                    if current_epoch != epoch {
+                        published.clear();
+                        epoch = current_epoch;
+                    }
+                    let states = recorder.list_states().await;
+                    let removed = published
+                        .keys()
+                        .filter(|id| !states.iter().any(|entry| &entry.source_id == *id))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    for source_id in removed {
+                        // An empty retained payload clears the topic on the broker.
+                        let topic = state_topic(&base, &source_id);
+                        if self.publish(&client, topic, true, String::new()).await {
+                            published.remove(&source_id);
+                        }
+                    }
+                    for entry in states {
+                        if published.get(&entry.source_id) == Some(&entry.state) {
+                            continue;
+                        }
+                        let topic = state_topic(&base, &entry.source_id);
+                        let payload = serde_json::to_string(&entry).unwrap_or_default();
+                        if self.publish(&client, topic, true, payload).await {
+                            published.insert(entry.source_id, entry.state);
+                        }
+                    }
+                }
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        let connected = self.status.lock().await.connected;
+                        if !connected {
+                            self.status.lock().await.dropped += 1;
+                            continue;
+                        }
+                        let payload = serde_json::to_string(&event).unwrap_or_default();
+                        self.publish(&client, event_topic(&base, &event), false, payload).await;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        self.status.lock().await.dropped += skipped;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+    }
+
+    async fn publish(
+        &self,
+        client: &AsyncClient,
+        topic: String,
+        retain: bool,
+        payload: String,
+    ) -> bool {
+        let sent = client
+            .try_publish(topic, QoS::AtLeastOnce, retain, payload)
+            .is_ok();
+        let mut status = self.status.lock().await;
+        if sent {
+            status.published += 1;
+        } else {
+            status.dropped += 1;
+        }
+        sent
+    }
+}
+
+pub fn topic_segment(source_id: &str) -> String {
+    source_id.replace(['/', '+', '#'], "_")
+}
+
+fn availability_topic(base: &str) -> String {
+    format!("{base}/status")
+}
+
+fn state_topic(base: &str, source_id: &str) -> String {
+    format!("{base}/{}/state", topic_segment(source_id))
+}
+
+fn event_topic(base: &str, event: &OpsEvent) -> String {
+    match &event.source_id {
+        Some(source_id) => format!("{base}/{}/events", topic_segment(source_id)),
+        None => format!("{base}/events"),
+    }
+}
+
+fn command_source<'a>(base: &str, topic: &'a str) -> Option<&'a str> {
+    let source = topic
+        .strip_prefix(base)?
+        .strip_prefix('/')?
+        .strip_suffix("/command")?;
+    (!source.is_empty() && !source.contains('/')).then_some(source)
+}
+
+fn parse_command(payload: &[u8]) -> Option<MqttAction> {
+    let text = std::str::from_utf8(payload).ok()?.trim();
+    match text {
+        "snapshot" => Some(MqttAction::Snapshot),
+        "privacy_on" => Some(MqttAction::Privacy { enabled: true }),
+        "privacy_off" => Some(MqttAction::Privacy { enabled: false }),
+        _ => serde_json::from_str(text).ok(),
+    }
+}
+
+fn mqtt_options(cfg: &MqttConfig, client_id: &str) -> Result<(MqttOptions, String)> {
+    let url = reqwest::Url::parse(cfg.broker_url.trim()).context("parse mqtt.broker_url")?;
+    let tls = match url.scheme() {
+        "mqtt" | "tcp" => false,
+        "mqtts" | "ssl" => true,
+        other => return Err(anyhow!("unsupported mqtt.broker_url scheme: {other}")),
+    };
+    let host = url
+        .host_str()
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| anyhow!("mqtt.broker_url has no host"))?
+        .to_string();
+    let port = url.port().unwrap_or(if tls { 8883 } else { 1883 });
+
+    let mut options = MqttOptions::new(client_id, host.clone(), port);
+    options.set_keep_alive(Duration::from_secs(cfg.keep_alive_secs.max(5)));
+    options.set_last_will(LastWill::new(
+        availability_topic(&cfg.base_topic),
+        OFFLINE_PAYLOAD,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if !cfg.username.trim().is_empty() {
+        options.set_credentials(cfg.username.trim(), cfg.password.clone());
+    }
+    if tls {
+        let transport = if cfg.ca_cert_path.trim().is_empty() {
+            Transport::tls_with_default_config()
+        } else {
+            let ca_path = cfg.ca_cert_path.trim();
+            let ca = std::fs::read(ca_path)
+                .with_context(|| format!("read mqtt.ca_cert_path {ca_path}"))?;
+            Transport::tls(ca, None, None)
+        };
+        options.set_transport(transport);
+    }
+    Ok((options, format!("{host}:{port}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_command_topics_and_payloads() {
+        let base = "constitute-nvr/nvr-1";
+        assert_eq!(
+            command_source(base, "constitute-nvr/nvr-1/cam-a/command"),
+            Some("cam-a")
+        );
+        assert_eq!(
+            command_source(base, "constitute-nvr/nvr-1/cam-a/state"),
+            None
+        );
+        assert_eq!(
+            command_source(base, "constitute-nvr/nvr-1/a/b/command"),
+            None
+        );
+        assert_eq!(
+            state_topic(base, "cam/#1"),
+            "constitute-nvr/nvr-1/cam__1/state"
+        );
+
+        assert_eq!(parse_command(b"snapshot"), Some(MqttAction::Snapshot));
+        assert_eq!(
+            parse_command(br#"{"action":"privacy","enabled":true}"#),
+            Some(MqttAction::Privacy { enabled: true })
+        );
+        assert_eq!(parse_command(b"reboot"), None);
+        assert_eq!(RECONNECT_BACKOFF.nominal(1), Duration::from_secs(1));
+        assert_eq!(RECONNECT_BACKOFF.nominal(20), RECONNECT_BACKOFF.cap());
+    }
+
+    #[test]
+    fn rejects_unsupported_broker_urls() {
+        let mut cfg = MqttConfig {
+            enabled: true,
+            broker_url: "mqtt://broker.local".to_string(),
+            base_topic: "nvr".to_string(),
+            ..MqttConfig::default()
+        };
+        let (_, broker) = mqtt_options(&cfg, "nvr-1").unwrap();
+        assert_eq!(broker, "broker.local:1883");
+        cfg.broker_url = "http://broker.local".to_string();
+        assert!(mqtt_options(&cfg, "nvr-1").is_err());
+    }
+}
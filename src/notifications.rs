@@ -0,0 +1,273 @@
+use crate::config::{Config, NotificationSeverity, SharedConfig, WebhookTargetConfig};
+use crate::util;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast};
+use tokio::time::{Duration, sleep};
+use tracing::{debug, warn};
+
+const EVENT_BUS_CAPACITY: usize = 256;
+const WEBHOOK_ATTEMPTS: u32 = 4;
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const WEBHOOK_BACKOFF_BASE_SECS: u64 = 2;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpsEvent {
+    pub kind: String,
+    pub severity: NotificationSeverity,
+    pub source_id: Option<String>,
+    pub message: String,
+    pub ts: u64,
+    pub facts: Value,
+}
+
+impl OpsEvent {
+    pub fn new(kind: &str, severity: NotificationSeverity, message: String) -> Self {
+        Self {
+            kind: kind.to_string(),
+            severity,
+            source_id: None,
+            message,
+            ts: util::now_unix_seconds(),
+            facts: Value::Null,
+        }
+    }
+
+    pub fn with_source(mut self, source_id: &str) -> Self {
+        self.source_id = Some(source_id.to_string());
+        self
+    }
+
+    pub fn with_facts(mut self, facts: Value) -> Self {
+        self.facts = facts;
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<OpsEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: OpsEvent) {
+        debug!(kind = %event.kind, source = ?event.source_id, "ops event");
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OpsEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookStatus {
+    pub id: String,
+    pub delivered: u64,
+    pub failed: u64,
+    pub rate_limited: u64,
+    pub consecutive_failures: u32,
+    pub last_success_at: Option<u64>,
+    pub last_failure_at: Option<u64>,
+    pub last_error: String,
+}
+
+#[derive(Default)]
+struct TargetState {
+    status: WebhookStatus,
+    recent: VecDeque<u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct NotificationDispatcher {
+    targets: Arc<Mutex<HashMap<String, TargetState>>>,
+}
+
+impl NotificationDispatcher {
+    pub async fn status(&self, cfg: &Config) -> Vec<WebhookStatus> {
+        let guard = self.targets.lock().await;
+        cfg.notifications
+            .webhooks
+            .iter()
+            .map(|target| {
+                guard
+                    .get(&target.id)
+                    .map(|state| state.status.clone())
+                    .unwrap_or_else(|| WebhookStatus {
+                        id: target.id.clone(),
+                        ..WebhookStatus::default()
+                    })
+            })
+            .collect()
+    }
+
+    pub fn spawn(&self, cfg: SharedConfig, bus: &EventBus) {
+        let this = self.clone();
+        let mut events = bus.subscribe();
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "notification dispatcher lagged; events dropped");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let snapshot = cfg.snapshot();
+                for target in &snapshot.notifications.webhooks {
+                    if !target_accepts(target, &event) || !this.admit(target).await {
+                        continue;
+                    }
+                    let body = webhook_body(&snapshot, &event);
+                    tokio::spawn(this.clone().deliver(client.clone(), target.clone(), body));
+                }
+            }
+        });
+    }
+
+    async fn admit(&self, target: &WebhookTargetConfig) -> bool {
+        let now = util::now_unix_seconds();
+        let mut guard = self.targets.lock().await;
+        let state = guard.entry(target.id.clone()).or_default();
+        state.status.id = target.id.clone();
+        while state
+            .recent
+            .front()
+            .is_some_and(|ts| now.saturating_sub(*ts) >= 60)
+        {
+            state.recent.pop_front();
+        }
+        if state.recent.len() >= target.max_per_minute.max(1) as usize {
+            state.status.rate_limited += 1;
+            return false;
+        }
+        state.recent.push_back(now);
+        true
+    }
+
+    async fn deliver(self, client: reqwest::Client, target: WebhookTargetConfig, body: Value) {
+        let mut last_error = String::new();
+        for attempt in 0..WEBHOOK_ATTEMPTS {
+            if attempt > 0 {
+                sleep(Duration::from_secs(
+                    WEBHOOK_BACKOFF_BASE_SECS << (attempt - 1),
+                ))
+                .await;
+            }
+            match post_webhook(&client, &target, &body).await {
+                Ok(()) => {
+                    let mut guard = self.targets.lock().await;
+                    let status = &mut guard.entry(target.id.clone()).or_default().status;
+                    status.delivered += 1;
+                    status.consecutive_failures = 0;
+                    status.last_success_at = Some(util::now_unix_seconds());
+                    return;
+                }
+                Err(err) => last_error = err,
+            }
+        }
+        warn!(target = %target.id, error = %last_error, "webhook delivery failed");
+        let mut guard = self.targets.lock().await;
+        let status = &mut guard.entry(target.id.clone()).or_default().status;
+        status.failed += 1;
+        status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+        status.last_failure_at = Some(util::now_unix_seconds());
+        status.last_error = last_error;
+    }
+}
+
+fn target_accepts(target: &WebhookTargetConfig, event: &OpsEvent) -> bool {
+    event.severity >= target.min_severity
+        && (target.event_kinds.is_empty()
+            || target.event_kinds.iter().any(|kind| kind == &event.kind))
+}
+
+fn webhook_body(cfg: &Config, event: &OpsEvent) -> Value {
+    json!({
+        "text": format!("{}: {}", cfg.device_label, event.message),
+        "service": "nvr",
+        "nodeId": cfg.node_id,
+        "kind": event.kind,
+        "severity": event.severity,
+        "sourceId": event.source_id,
+        "message": event.message,
+        "ts": event.ts,
+        "facts": event.facts,
+    })
+}
+
+async fn post_webhook(
+    client: &reqwest::Client,
+    target: &WebhookTargetConfig,
+    body: &Value,
+) -> Result<(), String> {
+    let mut request = client
+        .post(&target.url)
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .json(body);
+    if !target.bearer_token.trim().is_empty() {
+        request = request.bearer_auth(target.bearer_token.trim());
+    }
+    for (name, value) in &target.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    match request.send().await {
+        Ok(resp) if resp.status().is_success() => Ok(()),
+        Ok(resp) => Err(format!("http status {}", resp.status().as_u16())),
+        Err(err) if err.is_timeout() => Err("timed out".to_string()),
+        Err(err) if err.is_connect() => Err("connection failed".to_string()),
+        Err(err) if err.is_builder() => Err("invalid webhook request".to_string()),
+        Err(_) => Err("request failed".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn filters_and_rate_limits_targets() {
+        let target = WebhookTargetConfig {
+            id: "ops".to_string(),
+            url: "https://example.invalid/hook".to_string(),
+            bearer_token: "secret".to_string(),
+            headers: BTreeMap::new(),
+            event_kinds: vec!["camera_down".to_string()],
+            min_severity: NotificationSeverity::Warning,
+            max_per_minute: 1,
+        };
+        let down = OpsEvent::new(
+            "camera_down",
+            NotificationSeverity::Warning,
+            "down".to_string(),
+        );
+        let up = OpsEvent::new("camera_up", NotificationSeverity::Info, "up".to_string());
+        assert!(target_accepts(&target, &down));
+        assert!(!target_accepts(&target, &up));
+        assert!(!format!("{target:?}").contains("secret"));
+
+        let dispatcher = NotificationDispatcher::default();
+        assert!(dispatcher.admit(&target).await);
+        assert!(!dispatcher.admit(&target).await);
+        let guard = dispatcher.targets.lock().await;
+        assert_eq!(guard["ops"].status.rate_limited, 1);
+    }
+}
@@ -0,0 +1,219 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+const SMOOTHING: f64 = 0.25;
+pub const MIN_SAMPLES: u64 = 3;
+const MIN_PEERS_FOR_OUTLIER: usize = 2;
+
+#[derive(Clone, Debug)]
+struct PeerClock {
+    node_id: String,
+    offset_ms: f64,
+    samples: u64,
+    last_sample_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerOffset {
+    pub device_pk: String,
+    pub node_id: String,
+    pub offset_ms: i64,
+    pub samples: u64,
+    pub last_sample_ms: u64,
+    pub skewed: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockAssessment {
+    pub threshold_ms: i64,
+    pub consensus_offset_ms: i64,
+    pub outlier: bool,
+    pub peers: Vec<PeerOffset>,
+}
+
+impl ClockAssessment {
+    pub fn own_skew_ms(&self) -> Option<i64> {
+        self.outlier.then_some(-self.consensus_offset_ms)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClockEstimator {
+    peers: HashMap<String, PeerClock>,
+}
+
+impl ClockEstimator {
+    pub fn observe(&mut self, device_pk: &str, node_id: &str, peer_ts_ms: u64, local_ms: u64) {
+        if device_pk.is_empty() || peer_ts_ms == 0 {
+            return;
+        }
+        let sample = peer_ts_ms as f64 - local_ms as f64;
+        let entry = self
+            .peers
+            .entry(device_pk.to_string())
+            .or_insert_with(|| PeerClock {
+                node_id: String::new(),
+                offset_ms: sample,
+                samples: 0,
+                last_sample_ms: local_ms,
+            });
+        if entry.samples > 0 {
+            entry.offset_ms += SMOOTHING * (sample - entry.offset_ms);
+        }
+        entry.node_id = node_id.to_string();
+        entry.samples += 1;
+        entry.last_sample_ms = local_ms;
+    }
+
+    pub fn forget_stale(&mut self, now_ms: u64, max_age_ms: u64) {
+        self.peers
+            .retain(|_, peer| now_ms.saturating_sub(peer.last_sample_ms) <= max_age_ms);
+    }
+
+    pub fn assess(&self, threshold_ms: i64) -> ClockAssessment {
+        let settled = self
+            .peers
+            .values()
+            .filter(|peer| peer.samples >= MIN_SAMPLES)
+            .map(|peer| peer.offset_ms.round() as i64)
+            .collect::<Vec<_>>();
+        let consensus_offset_ms = if settled.len() < MIN_PEERS_FOR_OUTLIER {
+            0
+        } else {
+            consensus(settled)
+        };
+        let threshold_ms = threshold_ms.max(1);
+        let mut peers = self
+            .peers
+            .iter()
+            .map(|(device_pk, peer)| {
+                let offset_ms = peer.offset_ms.round() as i64;
+                PeerOffset {
+                    device_pk: device_pk.clone(),
+                    node_id: peer.node_id.clone(),
+                    offset_ms,
+                    samples: peer.samples,
+                    last_sample_ms: peer.last_sample_ms,
+                    skewed: peer.samples >= MIN_SAMPLES
+                        && (offset_ms - consensus_offset_ms).abs() > threshold_ms,
+                }
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| a.device_pk.cmp(&b.device_pk));
+        ClockAssessment {
+            threshold_ms,
+            consensus_offset_ms,
+            outlier: consensus_offset_ms.abs() > threshold_ms,
+            peers,
+        }
+    }
+}
+
+fn consensus(mut offsets: Vec<i64>) -> i64 {
+    offsets.push(0);
+    offsets.sort_unstable();
+    let mid = offsets.len() / 2;
+    if offsets.len() % 2 == 1 {
+        return offsets[mid];
+    }
+    let (low, high) = (offsets[mid - 1], offsets[mid]);
+    if low.abs() <= high.abs() { low } else { high }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD_MS: i64 = 60_000;
+    const NOW_MS: u64 = 1_775_000_000_000;
+
+    fn estimator(peers: &[(&str, i64)], samples: u64) -> ClockEstimator {
+        let mut clocks = ClockEstimator::default();
+        for step in 0..samples {
+            let local = NOW_MS + step * 5_000;
+            for (device_pk, offset_ms) in peers {
+                let peer_ts = (local as i64 + offset_ms) as u64;
+                clocks.observe(device_pk, "node", peer_ts, local);
+            }
+        }
+        clocks
+    }
+
+    fn skewed(assessment: &ClockAssessment) -> Vec<&str> {
+        assessment
+            .peers
+            .iter()
+            .filter(|peer| peer.skewed)
+            .map(|peer| peer.device_pk.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn synchronized_swarm_flags_nothing() {
+        let assessment = estimator(&[("a", 400), ("b", -1_200), ("c", 30)], 5).assess(THRESHOLD_MS);
+        assert!(!assessment.outlier);
+        assert!(skewed(&assessment).is_empty());
+        assert_eq!(assessment.own_skew_ms(), None);
+    }
+
+    #[test]
+    fn a_broken_peer_is_flagged_not_this_node() {
+        let assessment =
+            estimator(&[("a", 200), ("b", -300), ("c", 3_600_000)], 5).assess(THRESHOLD_MS);
+        assert!(!assessment.outlier);
+        assert_eq!(skewed(&assessment), vec!["c"]);
+    }
+
+    #[test]
+    fn this_node_is_the_outlier_when_the_majority_agrees_against_it() {
+        // Our RTC runs ten minutes fast, so every peer reads ten minutes behind.
+        let assessment =
+            estimator(&[("a", -600_000), ("b", -601_000), ("c", -599_500)], 5).assess(THRESHOLD_MS);
+        assert!(assessment.outlier);
+        assert!(skewed(&assessment).is_empty());
+        let own = assessment.own_skew_ms().unwrap();
+        assert!((599_000..=601_000).contains(&own), "{own}");
+    }
+
+    #[test]
+    fn a_split_swarm_does_not_blame_this_node() {
+        let assessment = estimator(&[("a", 0), ("b", -600_000)], 5).assess(THRESHOLD_MS);
+        assert!(!assessment.outlier);
+        assert_eq!(skewed(&assessment), vec!["b"]);
+
+        let lone = estimator(&[("a", -600_000)], 5).assess(THRESHOLD_MS);
+        assert!(!lone.outlier);
+        assert_eq!(skewed(&lone), vec!["a"]);
+    }
+
+    #[test]
+    fn smoothing_absorbs_a_delayed_datagram_and_unsettled_peers_do_not_count() {
+        let mut clocks = estimator(&[("a", 0), ("b", 0)], 5);
+        // One hello held up for 40s in a queue.
+        clocks.observe("a", "node", NOW_MS, NOW_MS + 40_000);
+        let a = clocks.assess(THRESHOLD_MS).peers.remove(0);
+        assert_eq!(a.offset_ms, -10_000);
+        assert!(!a.skewed);
+
+        let mut fresh = estimator(&[("a", -600_000), ("b", -600_000)], MIN_SAMPLES - 1);
+        assert!(!fresh.assess(THRESHOLD_MS).outlier);
+        fresh.observe("a", "node", NOW_MS - 600_000 + 20_000, NOW_MS + 20_000);
+        fresh.observe("b", "node", NOW_MS - 600_000 + 20_000, NOW_MS + 20_000);
+        assert!(fresh.assess(THRESHOLD_MS).outlier);
+    }
+
+    #[test]
+    fn a_stepped_peer_clock_converges_and_stale_peers_are_forgotten() {
+        let mut clocks = estimator(&[("a", 600_000)], 5);
+        for step in 0..20 {
+            let local = NOW_MS + 100_000 + step * 5_000;
+            clocks.observe("a", "node", local, local);
+        }
+        assert!(clocks.assess(THRESHOLD_MS).peers[0].offset_ms.abs() < 2_000);
+
+        clocks.forget_stale(NOW_MS + 100_000 + 19 * 5_000 + 600_001, 600_000);
+        assert!(clocks.assess(THRESHOLD_MS).peers.is_empty());
+    }
+}
@@ -0,0 +1,2217 @@
+use crate::features::SESSION_PROTOCOL_VERSION;
+use serde_json::{Map, Value, json};
+
+pub const VIEWER: &str = "viewer";
+pub const ADMIN: &str = "admin";
+const VIEWER_METHODS: &[&str] = &[
+    "list_sources",
+    "list_source_stats",
+    "list_source_states",
+    "get_source_state_history",
+    "get_stats",
+    "list_segments",
+    "list_segments_page",
+    "get_segment",
+    "segment_ack",
+    "get_segment_range",
+    "get_media_stream",
+    "export_range",
+    "get_coverage",
+    "resume_job",
+    "ack_job_complete",
+    "get_snapshot",
+    "get_latest_frame",
+    "list_snapshots",
+    "get_snapshot_file",
+    "attachment_start",
+    "attachment_chunk",
+    "attachment_end",
+    "list_attachments",
+    "get_attachment",
+    "set_session_options",
+    "describe_protocol",
+    "get_permissions",
+    "get_capabilities",
+];
+const METHOD_SINCE: &[(&str, u32)] = &[
+    ("list_segments_page", 2),
+    ("export_range", 2),
+    ("resume_job", 2),
+    ("ack_job_complete", 2),
+    ("mint_token", 2),
+    ("inspect_token", 2),
+    ("revoke_token", 2),
+    ("rotate_camera_credentials", 2),
+    ("get_source_state_history", 2),
+    ("backfill_index", 2),
+    ("get_media_stream", 2),
+    ("get_capabilities", 2),
+    ("get_coverage", 2),
+    ("attachment_start", 2),
+    ("attachment_chunk", 2),
+    ("attachment_end", 2),
+    ("list_attachments", 2),
+    ("get_attachment", 2),
+    ("recheck_endpoint", 2),
+    ("set_diagnostics", 2),
+    ("get_diagnostics_status", 2),
+    ("create_incident", 2),
+    ("update_incident", 2),
+    ("list_incidents", 2),
+    ("get_incident", 2),
+    ("export_incident", 2),
+    ("close_incident", 2),
+    ("get_retention_status", 2),
+    ("list_source_stats", 2),
+    ("delete_segment", 2),
+    ("delete_segments", 2),
+    ("protect_segment", 2),
+    ("segment_ack", 2),
+    ("verify_segments", 2),
+    ("reindex_storage", 2),
+    ("get_storage_status", 2),
+    ("get_segment_range", 2),
+];
+const DEPRECATIONS: &[Deprecation] = &[Deprecation {
+    method: "list_segments",
+    since: 2,
+    replacement: "list_segments_page",
+}];
+
+pub struct Deprecation {
+    pub method: &'static str,
+    pub since: u32,
+    pub replacement: &'static str,
+}
+
+impl Deprecation {
+    pub fn notice(&self) -> Value {
+        json!({
+            "method": self.method,
+            "deprecatedSince": self.since,
+            "replacement": self.replacement,
+        })
+    }
+}
+
+pub fn document() -> Value {
+    json!({
+        "openrpc": "1.2.6",
+        "info": {
+            "title": "constitute-nvr session protocol",
+            "version": SESSION_PROTOCOL_VERSION.to_string(),
+            "description": concat!(
+                "Commands carried in cipher frames on the /session websocket. x-role is the role ",
+                "a session needs; sessions opened with the identity secret hold admin and ",
+                "sessions opened with a zone secret hold viewer for that zone's cameras."
+            ),
+        },
+        "x-framing": framing(),
+        "methods": methods(),
+        "components": { "errors": errors() },
+    })
+}
+
+pub fn role(method: &str) -> &'static str {
+    if VIEWER_METHODS.contains(&method) {
+        VIEWER
+    } else {
+        ADMIN
+    }
+}
+
+pub fn since(method: &str) -> u32 {
+    METHOD_SINCE
+        .iter()
+        .find(|(name, _)| *name == method)
+        .map_or(1, |(_, since)| *since)
+}
+
+pub fn deprecation(method: &str) -> Option<&'static Deprecation> {
+    DEPRECATIONS
+        .iter()
+        .find(|deprecation| deprecation.method == method)
+}
+
+pub fn method_index() -> Vec<(String, bool)> {
+    methods()
+        .iter()
+        .filter_map(|method| {
+            let name = method["name"].as_str()?.to_string();
+            let by_source = method["params"]
+                .as_array()
+                .is_some_and(|params| params.iter().any(|param| param["name"] == "sourceId"));
+            Some((name, by_source))
+        })
+        .collect()
+}
+
+fn framing() -> Value {
+    json!({
+        "transport": "websocket",
+        "path": "/session",
+        "hello": {
+            "schema": object(
+                &[
+                    ("type", json!({ "const": "hello" })),
+                    ("identityId", string()),
+                    ("devicePk", string()),
+                    ("clientKey", string()),
+                    ("ts", integer()),
+                    ("proof", string()),
+                    ("zone", string()),
+                    ("timezone", string()),
+                    ("cookie", string()),
+                    ("protocolVersion", integer()),
+                    ("token", string()),
+                ],
+                &["type", "identityId", "devicePk", "clientKey", "ts", "proof"],
+            ),
+            "clientKey": "base64 X25519 public key",
+            "ts": "unix seconds; refused when more than 300s from node time",
+            "proof": concat!(
+                "hex HMAC-SHA256 of identityId|devicePk|clientKey|ts keyed with the identity ",
+                "secret, with the zone secret when zone is set, or with the hex SHA-256 of the ",
+                "token when token is set"
+            ),
+            "token": concat!(
+                "signed access token from mint_token; opens a viewer session limited to the ",
+                "token's scope and operations. The device allowlist does not apply"
+            ),
+            "zone": "zone key; opens a viewer session limited to the cameras assigned to that zone",
+            "timezone": concat!(
+                "IANA zone name for display fields; an unknown name is refused with code ",
+                "invalid_argument and field timezone"
+            ),
+            "protocolVersion": concat!(
+                "highest session protocol version the client speaks; omitted means 1. The ",
+                "session uses the lower of it and the node's"
+            ),
+            "cookie": concat!(
+                "echo of the cookie from a code cookie_required refusal, which an address gets ",
+                "after repeated failed hellos"
+            ),
+        },
+        "helloAck": {
+            "schema": object(
+                &[
+                    ("type", json!({ "const": "hello_ack" })),
+                    ("sessionId", string()),
+                    ("serverKey", string()),
+                    ("ts", integer()),
+                    ("role", string()),
+                    ("zone", string()),
+                    ("timezone", string()),
+                    ("protocolVersion", integer()),
+                    ("tokenId", string()),
+                    ("features", array(string())),
+                    (
+                        "limits",
+                        object(
+                            &[
+                                ("maxChunkBytes", integer()),
+                                ("maxEnvelopeBytes", integer()),
+                                ("maxConcurrentTransfers", integer()),
+                                ("maxCameras", integer()),
+                                ("protocolVersions", array(integer())),
+                            ],
+                            &[],
+                        ),
+                    ),
+                ],
+                &["type", "sessionId", "serverKey", "ts"],
+            ),
+            "ts": "unix milliseconds",
+            "role": concat!(
+                "admin for identity-secret sessions, viewer for zone and token sessions; zone is ",
+                "only set on zone sessions and tokenId on token sessions"
+            ),
+            "protocolVersion": concat!(
+                "version negotiated for this session; methods with a higher x-since are refused ",
+                "with unsupported_version"
+            ),
+        },
+        "cipher": {
+            "schema": object(
+                &[
+                    ("type", json!({ "const": "cipher" })),
+                    ("nonce", string()),
+                    ("data", string()),
+                ],
+                &["type", "nonce", "data"],
+            ),
+            "nonce": "base64 of 24 random bytes, fresh per frame",
+            "aead": "XChaCha20-Poly1305",
+            "keyDerivation": concat!(
+                "X25519(server static secret, clientKey), then HKDF-SHA256 with the hello proof ",
+                "secret as salt and info constitute-nvr:<identityId>:<sessionId>"
+            ),
+            "plaintext": concat!(
+                "UTF-8 JSON; commands carry cmd plus their params by name, replies carry ok ",
+                "and cmd"
+            ),
+        },
+        "jobProgress": {
+            "schema": object(
+                &[
+                    ("cmd", json!({ "const": "job_progress" })),
+                    ("jobId", string()),
+                    ("kind", string()),
+                    ("state", string_enum(&["running", "completed", "failed", "cancelled"])),
+                    ("phase", string()),
+                    ("done", integer()),
+                    ("total", integer()),
+                    ("etaSecs", integer()),
+                ],
+                &["cmd", "jobId", "state", "phase", "done", "total"],
+            ),
+            "delivery": concat!(
+                "unsolicited cipher frame to the session that started or last queried the job, ",
+                "at most once a second per job, plus one when it finishes"
+            ),
+        },
+        "dashboard": {
+            "schema": object(
+                &[
+                    ("cmd", string_enum(&["dashboard_delta", "dashboard_snapshot"])),
+                    ("changed", any_object()),
+                    ("dashboard", any_object()),
+                ],
+                &["cmd"],
+            ),
+            "delivery": concat!(
+                "unsolicited cipher frames after subscribe_dashboard, at most once a second; ",
+                "dashboard_delta carries the changed top-level parts, dashboard_snapshot the ",
+                "whole view for a subscriber that fell behind"
+            ),
+        },
+        "exportStream": {
+            "schema": object(
+                &[
+                    ("cmd", string_enum(&["export_chunk", "export_end"])),
+                    ("jobId", string()),
+                    ("seq", integer()),
+                    ("offset", integer()),
+                    ("sha256", string()),
+                    ("data", string()),
+                    ("totalBytes", integer()),
+                    ("gaps", array(any_object())),
+                ],
+                &["cmd", "jobId"],
+            ),
+            "delivery": concat!(
+                "after the export_range or resume_job reply: export_chunk frames in order ",
+                "(base64 data, its byte offset in the archive, and the hex SHA-256 of the chunk), ",
+                "then export_end with the archive's totalBytes and sha256 and the range's ",
+                "labelled gaps"
+            ),
+        },
+        "errors": concat!(
+            "{ ok: false, error } arrives as a plaintext frame before the session key exists ",
+            "and inside a cipher frame afterwards"
+        ),
+        "deprecation": concat!(
+            "replies to methods marked deprecated carry deprecation: { method, deprecatedSince, ",
+            "replacement }; the call still succeeds"
+        ),
+    })
+}
+
+fn errors() -> Value {
+    json!({
+        "command_failed": {
+            "code": null,
+            "message": concat!(
+                "Any failure without a code; only ok: false and a human-readable error are ",
+                "set."
+            ),
+            "data": object(
+                &[("ok", json!({ "const": false })), ("error", string())],
+                &["ok", "error"],
+            ),
+        },
+        "permission_denied": {
+            "code": "permission_denied",
+            "message": "The session's role or zone does not cover the method or camera.",
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "permission_denied" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+        "limit_exceeded": {
+            "code": "limit_exceeded",
+            "message": "A size or count limit was hit; limit names it and max is the bound.",
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "limit_exceeded" })),
+                    ("error", string()),
+                    ("limit", string()),
+                    ("max", integer()),
+                ],
+                &["ok", "code", "error", "limit", "max"],
+            ),
+        },
+        "invalid_argument": {
+            "code": "invalid_argument",
+            "message": "A request field failed validation; field names it.",
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "invalid_argument" })),
+                    ("error", string()),
+                    ("field", string()),
+                ],
+                &["ok", "code", "error", "field"],
+            ),
+        },
+        "unsupported_version": {
+            "code": "unsupported_version",
+            "message": concat!(
+                "The method is newer than the session's protocol version; requiredVersion names ",
+                "the version it needs."
+            ),
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "unsupported_version" })),
+                    ("error", string()),
+                    ("requiredVersion", integer()),
+                ],
+                &["ok", "code", "error", "requiredVersion"],
+            ),
+        },
+        "unsupported": {
+            "code": "unsupported",
+            "message": concat!(
+                "The camera's source type has no control plane or no stream to open, or the ",
+                "updater is off."
+            ),
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "unsupported" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+        "not_found": {
+            "code": "not_found",
+            "message": "The segment is not on disk: it was never written or has been purged.",
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "not_found" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+        "segment_unreadable": {
+            "code": "segment_unreadable",
+            "message": concat!(
+                "The stored segment does not open: sealed under another storage key, ",
+                "altered, or truncated."
+            ),
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "segment_unreadable" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+        "range_not_satisfiable": {
+            "code": "range_not_satisfiable",
+            "message": concat!(
+                "The byte range starts at or past the end of the segment; size is the ",
+                "segment's length."
+            ),
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "range_not_satisfiable" })),
+                    ("error", string()),
+                    ("size", integer()),
+                ],
+                &["ok", "code", "error", "size"],
+            ),
+        },
+        "camera_unreachable": {
+            "code": "camera_unreachable",
+            "message": concat!(
+                "The camera did not answer: the connection was refused, had no route, or ",
+                "timed out."
+            ),
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "camera_unreachable" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+        "camera_auth_failed": {
+            "code": "camera_auth_failed",
+            "message": "The camera refused the configured credentials.",
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "camera_auth_failed" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+        "dependency_missing": {
+            "code": "dependency_missing",
+            "message": "A tool the command runs, such as ffmpeg, is not installed on the node.",
+            "data": object(
+                &[
+                    ("ok", json!({ "const": false })),
+                    ("code", json!({ "const": "dependency_missing" })),
+                    ("error", string()),
+                ],
+                &["ok", "code", "error"],
+            ),
+        },
+    })
+}
+
+fn methods() -> Vec<Value> {
+    vec![
+        method(
+            "list_sources",
+            "Configured cameras with their stored settings.",
+            vec![],
+            reply(
+                "list_sources",
+                &[
+                    ("sources", array(any_object())),
+                    (
+                        "mirrored",
+                        array(object(
+                            &[
+                                ("originNode", string()),
+                                ("sourceId", string()),
+                                ("readOnly", boolean()),
+                                ("segments", integer()),
+                                ("bytes", integer()),
+                                ("newestSegment", string()),
+                            ],
+                            &[],
+                        )),
+                    ),
+                    ("provenance", any_object()),
+                    ("policies", any_object()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "list_source_stats",
+            "Segment count, bytes, and oldest and newest segment per source directory.",
+            vec![],
+            reply(
+                "list_source_stats",
+                &[(
+                    "sources",
+                    array(object(
+                        &[
+                            ("sourceId", string()),
+                            ("segments", integer()),
+                            ("encryptedSegments", integer()),
+                            ("plaintextSegments", integer()),
+                            ("totalBytes", integer()),
+                            ("oldestUnix", integer()),
+                            ("newestUnix", integer()),
+                        ],
+                        &[
+                            "sourceId",
+                            "segments",
+                            "encryptedSegments",
+                            "plaintextSegments",
+                            "totalBytes",
+                        ],
+                    )),
+                )],
+            ),
+            &[],
+        ),
+        method(
+            "list_source_states",
+            "One page of recorder runtime states by sourceId; pass nextCursor back.",
+            vec![
+                param("limit", integer(), false),
+                param("cursor", string(), false),
+                param("availability", boolean(), false),
+            ],
+            reply(
+                "list_source_states",
+                &[
+                    ("states", array(any_object())),
+                    ("nextCursor", string()),
+                    ("availability", any_object()),
+                ],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "get_source_state_history",
+            "Recent recorder state transitions for one camera, newest first.",
+            vec![
+                param("sourceId", string(), true),
+                param("limit", integer(), false),
+            ],
+            reply(
+                "get_source_state_history",
+                &[
+                    ("sourceId", string()),
+                    (
+                        "transitions",
+                        array(object(
+                            &[
+                                ("at", integer()),
+                                ("from", string()),
+                                ("to", string()),
+                                ("attempt", integer()),
+                                ("error", string()),
+                            ],
+                            &[],
+                        )),
+                    ),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "get_stats",
+            "Rolling and lifetime segment counters; every source when sourceId is omitted.",
+            vec![param("sourceId", string(), false)],
+            reply(
+                "get_stats",
+                &[
+                    ("sources", array(any_object())),
+                    ("headroom", any_object()),
+                    ("latency", array(any_object())),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "get_notification_status",
+            "Delivery counters per webhook target.",
+            vec![],
+            reply(
+                "get_notification_status",
+                &[("targets", array(any_object()))],
+            ),
+            &[],
+        ),
+        method(
+            "get_problem_history",
+            "Open self-check problems and the latest raised/cleared transitions.",
+            vec![param("limit", integer(), false)],
+            reply(
+                "get_problem_history",
+                &[
+                    ("status", string()),
+                    ("checkedAt", integer()),
+                    ("problems", array(any_object())),
+                    ("history", array(any_object())),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "recheck_dependencies",
+            "Re-probe ffmpeg and ffprobe and resume recorders waiting on them.",
+            vec![],
+            reply(
+                "recheck_dependencies",
+                &[
+                    ("dependencies", any_object()),
+                    ("resumedSources", array(string())),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "recheck_endpoint",
+            "Probe the announced session URL now with a WebSocket upgrade.",
+            vec![],
+            reply(
+                "recheck_endpoint",
+                &[
+                    ("status", string()),
+                    (
+                        "probe",
+                        object(
+                            &[
+                                ("url", string()),
+                                ("reachable", boolean()),
+                                ("reason", string()),
+                                ("checkedUnix", integer()),
+                                ("latencyMs", integer()),
+                            ],
+                            &["url", "reachable", "checkedUnix", "latencyMs"],
+                        ),
+                    ),
+                ],
+            ),
+            &["unsupported", "unsupported_version"],
+        ),
+        method(
+            "check_camera_time",
+            "Run the camera clock check now.",
+            vec![
+                param("sourceId", string(), true),
+                param(
+                    "credentials",
+                    object(&[("username", string()), ("password", string())], &[]),
+                    false,
+                ),
+            ],
+            reply("check_camera_time", &[("clock", any_object())]),
+            &["unsupported"],
+        ),
+        method(
+            "discover_onvif",
+            "WS-Discovery probe for ONVIF devices.",
+            vec![],
+            reply("discover_onvif", &[("cameraDevices", array(any_object()))]),
+            &[],
+        ),
+        method(
+            "draft_source_from_discovery",
+            "Fill an upsert_source payload from a discovered ONVIF endpoint.",
+            vec![
+                param("endpoint", string(), true),
+                param("username", string(), false),
+                param("password", string(), false),
+                param(
+                    "credentials",
+                    object(&[("username", string()), ("password", string())], &[]),
+                    false,
+                ),
+            ],
+            reply(
+                "draft_source_from_discovery",
+                &[
+                    ("draft", source_upsert_schema()),
+                    ("device", any_object()),
+                    ("missing", array(string())),
+                    ("errors", array(string())),
+                    ("complete", boolean()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "discover_reolink",
+            "Broadcast discovery for Reolink cameras.",
+            vec![],
+            reply("discover_reolink", &[("devices", array(any_object()))]),
+            &[],
+        ),
+        method(
+            "probe_reolink",
+            "Identify a Reolink camera by address.",
+            vec![param("ip", string(), true)],
+            reply("probe_reolink", &[("result", any_object())]),
+            &[],
+        ),
+        method(
+            "read_reolink_state",
+            "Read a Reolink camera's network and feature state.",
+            vec![param(
+                "request",
+                object(
+                    &[
+                        ("ip", string()),
+                        ("username", string()),
+                        ("channel", integer()),
+                        ("password", string()),
+                    ],
+                    &["ip", "password"],
+                ),
+                true,
+            )],
+            reply("read_reolink_state", &[("result", any_object())]),
+            &[],
+        ),
+        method(
+            "apply_reolink_state",
+            "Apply network and feature state to a Reolink camera.",
+            vec![param(
+                "request",
+                object(
+                    &[
+                        ("ip", string()),
+                        ("username", string()),
+                        ("channel", integer()),
+                        ("password", string()),
+                        ("normal", any_object()),
+                        ("advanced", any_object()),
+                        ("p2p", any_object()),
+                        ("autoReboot", any_object()),
+                        ("ptz", any_object()),
+                        ("ptzPosition", any_object()),
+                        ("smartTrackTask", any_object()),
+                        ("smartTrackLimit", any_object()),
+                        ("signatureLogin", any_object()),
+                        ("userConfig", any_object()),
+                    ],
+                    &["ip", "password"],
+                ),
+                true,
+            )],
+            reply("apply_reolink_state", &[("result", any_object())]),
+            &[],
+        ),
+        method(
+            "setup_reolink",
+            "Harden a Reolink camera and upsert it as a source.",
+            vec![param(
+                "request",
+                object(
+                    &[
+                        ("ip", string()),
+                        ("username", string()),
+                        ("password", string()),
+                        ("desiredPassword", string()),
+                        ("generatePassword", boolean()),
+                        ("normal", any_object()),
+                        ("advanced", any_object()),
+                        ("p2p", any_object()),
+                    ],
+                    &["ip"],
+                ),
+                true,
+            )],
+            reply(
+                "setup_reolink",
+                &[("result", any_object()), ("source", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "bootstrap_reolink",
+            "Lease an address to a factory-fresh Reolink camera.",
+            vec![param(
+                "request",
+                object(
+                    &[
+                        ("serverIp", string()),
+                        ("leaseIp", string()),
+                        ("targetMac", string()),
+                        ("timeoutSecs", integer()),
+                        ("subnetMask", string()),
+                        ("routerIp", string()),
+                        ("dnsIp", string()),
+                    ],
+                    &["serverIp", "leaseIp"],
+                ),
+                true,
+            )],
+            reply("bootstrap_reolink", &[("result", any_object())]),
+            &[],
+        ),
+        method(
+            "upsert_source",
+            "Add or update a camera and restart its recorder.",
+            vec![param("source", source_upsert_schema(), true)],
+            reply("upsert_source", &[("source", any_object())]),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "remove_source",
+            "Remove a camera and stop its recorder.",
+            vec![param("sourceId", string(), true)],
+            reply(
+                "remove_source",
+                &[("sourceId", string()), ("removed", boolean())],
+            ),
+            &[],
+        ),
+        method(
+            "export_sources",
+            "Every camera as a versioned bundle; passwords omitted without a passphrase.",
+            vec![param("passphrase", string(), false)],
+            reply("export_sources", &[("bundle", any_object())]),
+            &[],
+        ),
+        method(
+            "import_sources",
+            "Apply a source bundle in one write, with a result per entry.",
+            vec![
+                param(
+                    "bundle",
+                    object(
+                        &[
+                            ("format", string()),
+                            ("version", integer()),
+                            ("exportedAt", integer()),
+                            ("nodeId", string()),
+                            ("passwords", string_enum(&["omitted", "wrapped"])),
+                            ("kdf", any_object()),
+                            ("sources", array(any_object())),
+                        ],
+                        &[
+                            "format",
+                            "version",
+                            "exportedAt",
+                            "nodeId",
+                            "passwords",
+                            "sources",
+                        ],
+                    ),
+                    true,
+                ),
+                param(
+                    "conflictPolicy",
+                    string_enum(&["skip", "overwrite", "rename"]),
+                    false,
+                ),
+                param("passphrase", string(), false),
+            ],
+            reply(
+                "import_sources",
+                &[("applied", boolean()), ("results", array(any_object()))],
+            ),
+            &["limit_exceeded"],
+        ),
+        method(
+            "get_source_history",
+            "A camera's config revisions, newest first; passwords only as a changed flag.",
+            vec![
+                param("sourceId", string(), true),
+                param("limit", integer(), false),
+            ],
+            reply(
+                "get_source_history",
+                &[("sourceId", string()), ("revisions", array(any_object()))],
+            ),
+            &[],
+        ),
+        method(
+            "rollback_source",
+            "Re-apply a stored config revision through upsert_source.",
+            vec![
+                param("sourceId", string(), true),
+                param("revision", integer(), true),
+            ],
+            reply(
+                "rollback_source",
+                &[
+                    ("sourceId", string()),
+                    ("revision", integer()),
+                    ("source", any_object()),
+                ],
+            ),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "rotate_camera_credentials",
+            "Move a camera to a new password, verified by a stream probe and rolled back on failure.",
+            vec![
+                param("sourceId", string(), true),
+                param("newPassword", string(), true),
+                param("applyToCamera", boolean(), false),
+            ],
+            reply(
+                "rotate_camera_credentials",
+                &[
+                    ("sourceId", string()),
+                    ("status", string_enum(&["rotated", "failed", "unsupported"])),
+                    ("appliedToCamera", boolean()),
+                    ("rolledBack", boolean()),
+                    (
+                        "failedStep",
+                        string_enum(&["set_user", "verify", "persist"]),
+                    ),
+                    ("error", string()),
+                ],
+            ),
+            &["limit_exceeded", "invalid_argument", "unsupported"],
+        ),
+        method(
+            "get_push_ingest",
+            "Listener port, stream key, and publish URL of a push source, for configuring its sender.",
+            vec![
+                param("sourceId", string(), true),
+                param("host", string(), false),
+            ],
+            reply(
+                "get_push_ingest",
+                &[
+                    ("sourceId", string()),
+                    ("protocol", string_enum(&["rtmp", "srt"])),
+                    ("port", integer()),
+                    ("streamKey", string()),
+                    ("url", string()),
+                ],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "list_segments",
+            "Recorded segments for a camera, newest first; superseded by list_segments_page.",
+            vec![
+                param("sourceId", string(), true),
+                param("limit", integer(), false),
+                param("fromUnix", integer(), false),
+                param("toUnix", integer(), false),
+                param("protectedOnly", boolean(), false),
+            ],
+            reply(
+                "list_segments",
+                &[
+                    ("sourceId", string()),
+                    ("segments", array(any_object())),
+                    ("timezone", string()),
+                    ("days", array(any_object())),
+                    ("deprecation", any_object()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "list_segments_page",
+            "One page of a camera's segments, newest first; pass nextCursor back for the next.",
+            vec![
+                param("sourceId", string(), true),
+                param("limit", integer(), false),
+                param("cursor", string(), false),
+                param("fromUnix", integer(), false),
+                param("toUnix", integer(), false),
+                param("protectedOnly", boolean(), false),
+            ],
+            reply(
+                "list_segments_page",
+                &[
+                    ("sourceId", string()),
+                    ("segments", array(any_object())),
+                    ("nextCursor", string()),
+                    ("timezone", string()),
+                    ("days", array(any_object())),
+                ],
+            ),
+            &["invalid_argument", "unsupported_version"],
+        ),
+        get_segment_method(),
+        method(
+            "segment_ack",
+            "Acknowledge a windowed get_segment's chunks through seq; only the transfer reads it.",
+            vec![param("seq", integer(), true)],
+            reply("segment_ack", &[]),
+            &["invalid_argument"],
+        ),
+        method(
+            "get_segment_range",
+            "One decrypted byte range of a segment; only the chunks it covers are opened.",
+            vec![
+                param("sourceId", string(), true),
+                param("name", string(), true),
+                param("offset", integer(), true),
+                param("length", integer(), true),
+            ],
+            reply(
+                "get_segment_range",
+                &[
+                    ("sourceId", string()),
+                    ("name", string()),
+                    ("offset", integer()),
+                    ("length", integer()),
+                    ("size", integer()),
+                    ("data", string()),
+                ],
+            ),
+            &[
+                "not_found",
+                "segment_unreadable",
+                "invalid_argument",
+                "limit_exceeded",
+                "range_not_satisfiable",
+                "unsupported_version",
+            ],
+        ),
+        get_media_stream_method(),
+        method(
+            "export_range",
+            "Start a job archiving a camera's segments over a range and stream it as export_chunk frames.",
+            vec![
+                param("sourceId", string(), true),
+                param("fromUnix", integer(), true),
+                param("toUnix", integer(), true),
+                param("reason", string(), false),
+            ],
+            reply(
+                "export_range",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "get_coverage",
+            "Gaps between a camera's segments over a range, labelled intentional or unexplained.",
+            vec![
+                param("sourceId", string(), true),
+                param("fromUnix", integer(), true),
+                param("toUnix", integer(), true),
+                param("minGapSecs", integer(), false),
+            ],
+            reply(
+                "get_coverage",
+                &[
+                    ("sourceId", string()),
+                    ("fromUnix", integer()),
+                    ("toUnix", integer()),
+                    ("minGapSecs", integer()),
+                    (
+                        "gaps",
+                        array(object(
+                            &[
+                                ("fromUnix", integer()),
+                                ("toUnix", integer()),
+                                ("label", string_enum(&["intentional", "unexplained"])),
+                                (
+                                    "reason",
+                                    string_enum(&["disabled", "privacy", "removed", "shutdown"]),
+                                ),
+                            ],
+                            &["fromUnix", "toUnix", "label"],
+                        )),
+                    ),
+                ],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "resume_job",
+            "Stream an export again from the chunk holding fromByte, e.g. after the session dropped.",
+            vec![
+                param("jobId", string(), true),
+                param("fromByte", integer(), false),
+            ],
+            reply(
+                "resume_job",
+                &[
+                    ("jobId", string()),
+                    ("offset", integer()),
+                    ("totalBytes", integer()),
+                    ("job", any_object()),
+                ],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "ack_job_complete",
+            "Delete a finished export's spool and manifest once the client has it all.",
+            vec![param("jobId", string(), true)],
+            reply(
+                "ack_job_complete",
+                &[("jobId", string()), ("removed", boolean())],
+            ),
+            &[],
+        ),
+        method(
+            "get_snapshot",
+            "Grab one JPEG frame from the camera, optionally storing it.",
+            vec![
+                param("sourceId", string(), true),
+                param("persist", boolean(), false),
+            ],
+            reply(
+                "get_snapshot",
+                &[
+                    ("sourceId", string()),
+                    ("contentType", string()),
+                    ("data", string()),
+                    ("snapshot", any_object()),
+                ],
+            ),
+            &[
+                "unsupported",
+                "camera_unreachable",
+                "camera_auth_failed",
+                "dependency_missing",
+            ],
+        ),
+        method(
+            "get_latest_frame",
+            "The camera's cached newest preview frame, answered without contacting it.",
+            vec![param("sourceId", string(), true)],
+            reply(
+                "get_latest_frame",
+                &[
+                    ("sourceId", string()),
+                    ("contentType", string()),
+                    ("data", string()),
+                    ("capturedAt", integer()),
+                    ("ageSecs", integer()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "list_snapshots",
+            "Stored snapshots for a camera, newest first.",
+            vec![
+                param("sourceId", string(), true),
+                param("limit", integer(), false),
+                param("fromUnix", integer(), false),
+                param("toUnix", integer(), false),
+            ],
+            reply(
+                "list_snapshots",
+                &[("sourceId", string()), ("snapshots", array(any_object()))],
+            ),
+            &[],
+        ),
+        method(
+            "get_snapshot_file",
+            "One stored snapshot as base64 JPEG.",
+            vec![
+                param("sourceId", string(), true),
+                param("name", string(), true),
+            ],
+            reply(
+                "get_snapshot_file",
+                &[
+                    ("sourceId", string()),
+                    ("name", string()),
+                    ("contentType", string()),
+                    ("data", string()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "attachment_start",
+            "Open an upload of a file to attach to a camera's footage; send it as attachment_chunk, then attachment_end.",
+            vec![
+                param("sourceId", string(), true),
+                param("atUnix", integer(), false),
+                param("name", string(), true),
+                param("contentType", string(), false),
+                param("bytes", integer(), true),
+                param("sha256", string(), true),
+            ],
+            reply(
+                "attachment_start",
+                &[
+                    ("uploadId", string()),
+                    ("chunkBytes", integer()),
+                    (
+                        "attachment",
+                        object(
+                            &[
+                                ("id", string()),
+                                ("sourceId", string()),
+                                ("atUnix", integer()),
+                                ("name", string()),
+                                ("contentType", string()),
+                                ("bytes", integer()),
+                                ("sha256", string()),
+                                ("uploadedBy", string()),
+                                ("createdUnix", integer()),
+                            ],
+                            &[
+                                "id",
+                                "sourceId",
+                                "atUnix",
+                                "name",
+                                "bytes",
+                                "sha256",
+                                "uploadedBy",
+                                "createdUnix",
+                            ],
+                        ),
+                    ),
+                ],
+            ),
+            &["limit_exceeded", "invalid_argument", "unsupported_version"],
+        ),
+        method(
+            "attachment_chunk",
+            "Append base64 bytes to an open upload at the offset received so far.",
+            vec![
+                param("uploadId", string(), true),
+                param("offset", integer(), true),
+                param("data", string(), true),
+            ],
+            reply(
+                "attachment_chunk",
+                &[("uploadId", string()), ("received", integer())],
+            ),
+            &["invalid_argument", "unsupported_version"],
+        ),
+        method(
+            "attachment_end",
+            "Check an upload's size and sha256 and store it encrypted.",
+            vec![param("uploadId", string(), true)],
+            reply(
+                "attachment_end",
+                &[
+                    ("uploadId", string()),
+                    (
+                        "attachment",
+                        object(
+                            &[
+                                ("id", string()),
+                                ("sourceId", string()),
+                                ("atUnix", integer()),
+                                ("name", string()),
+                                ("contentType", string()),
+                                ("bytes", integer()),
+                                ("sha256", string()),
+                                ("uploadedBy", string()),
+                                ("createdUnix", integer()),
+                            ],
+                            &[
+                                "id",
+                                "sourceId",
+                                "atUnix",
+                                "name",
+                                "bytes",
+                                "sha256",
+                                "uploadedBy",
+                                "createdUnix",
+                            ],
+                        ),
+                    ),
+                ],
+            ),
+            &["invalid_argument", "unsupported_version"],
+        ),
+        method(
+            "list_attachments",
+            "A camera's attachments, newest moment first.",
+            vec![
+                param("sourceId", string(), true),
+                param("limit", integer(), false),
+            ],
+            reply(
+                "list_attachments",
+                &[
+                    ("sourceId", string()),
+                    (
+                        "attachments",
+                        array(object(
+                            &[
+                                ("id", string()),
+                                ("sourceId", string()),
+                                ("atUnix", integer()),
+                                ("name", string()),
+                                ("contentType", string()),
+                                ("bytes", integer()),
+                                ("sha256", string()),
+                                ("uploadedBy", string()),
+                                ("createdUnix", integer()),
+                            ],
+                            &[
+                                "id",
+                                "sourceId",
+                                "atUnix",
+                                "name",
+                                "bytes",
+                                "sha256",
+                                "uploadedBy",
+                                "createdUnix",
+                            ],
+                        )),
+                    ),
+                ],
+            ),
+            &["unsupported_version"],
+        ),
+        get_attachment_method(),
+        method(
+            "set_privacy",
+            "Hold a camera in privacy mode, optionally purging the last minutes.",
+            vec![
+                param("sourceId", string(), true),
+                param("enabled", boolean(), true),
+                param("purgeLastMinutes", integer(), false),
+            ],
+            reply(
+                "set_privacy",
+                &[
+                    ("sourceId", string()),
+                    ("privacy", boolean()),
+                    ("purged", any_object()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "set_source_zones",
+            "Assign a camera to zones; replaces its current zone list.",
+            vec![
+                param("sourceId", string(), true),
+                param("zones", array(string()), true),
+            ],
+            reply(
+                "set_source_zones",
+                &[("sourceId", string()), ("zones", array(string()))],
+            ),
+            &[],
+        ),
+        method(
+            "purge_range",
+            "Start a job deleting segments overlapping a time range; signs the report.",
+            vec![
+                param("fromUnix", integer(), true),
+                param("toUnix", integer(), true),
+                param("sourceIds", array(string()), false),
+                param("includeBookmarked", boolean(), false),
+                param("confirm", boolean(), false),
+                param("dryRun", boolean(), false),
+            ],
+            reply("purge_range", &[("jobId", string()), ("job", any_object())]),
+            &[],
+        ),
+        method(
+            "delete_segment",
+            "Delete one segment by name; reports whether it existed and the bytes freed.",
+            vec![
+                param("sourceId", string(), true),
+                param("name", string(), true),
+                param("includeBookmarked", boolean(), false),
+            ],
+            reply(
+                "delete_segment",
+                &[
+                    ("sourceId", string()),
+                    ("name", string()),
+                    ("existed", boolean()),
+                    ("bookmarked", boolean()),
+                    ("bytes", integer()),
+                ],
+            ),
+            &["invalid_argument", "unsupported_version"],
+        ),
+        method(
+            "delete_segments",
+            "Delete a list of a camera's segments by name, answering for each.",
+            vec![
+                param("sourceId", string(), true),
+                param("names", array(string()), true),
+                param("includeBookmarked", boolean(), false),
+            ],
+            reply(
+                "delete_segments",
+                &[
+                    ("sourceId", string()),
+                    (
+                        "segments",
+                        array(object(
+                            &[
+                                ("name", string()),
+                                ("existed", boolean()),
+                                ("bookmarked", boolean()),
+                                ("bytes", integer()),
+                            ],
+                            &["name", "existed", "bookmarked", "bytes"],
+                        )),
+                    ),
+                    ("deleted", integer()),
+                    ("bytes", integer()),
+                ],
+            ),
+            &["invalid_argument", "limit_exceeded", "unsupported_version"],
+        ),
+        method(
+            "protect_segment",
+            "Protect a segment from retention and unforced deletion, or release it.",
+            vec![
+                param("sourceId", string(), true),
+                param("name", string(), true),
+                param("protected", boolean(), true),
+            ],
+            reply(
+                "protect_segment",
+                &[
+                    ("sourceId", string()),
+                    ("name", string()),
+                    ("protected", boolean()),
+                    ("changed", boolean()),
+                ],
+            ),
+            &["invalid_argument", "not_found", "unsupported_version"],
+        ),
+        method(
+            "verify_segments",
+            "Re-hash a camera's sealed segments, or a sample, against its integrity manifest.",
+            vec![
+                param("sourceId", string(), true),
+                param("sample", integer(), false),
+            ],
+            reply(
+                "verify_segments",
+                &[
+                    ("sourceId", string()),
+                    ("manifestIntact", boolean()),
+                    ("manifestBrokenAt", integer()),
+                    ("segments", integer()),
+                    ("checked", integer()),
+                    ("verified", integer()),
+                    (
+                        "mismatched",
+                        array(object(
+                            &[
+                                ("name", string()),
+                                ("expectedSha256", string()),
+                                ("actualSha256", string()),
+                            ],
+                            &["name", "expectedSha256", "actualSha256"],
+                        )),
+                    ),
+                    ("unrecorded", array(string())),
+                ],
+            ),
+            &["invalid_argument", "unsupported_version"],
+        ),
+        method(
+            "get_retention_status",
+            "Each camera's segments against its retention limits, and the last deletions.",
+            vec![param("sourceId", string(), false)],
+            reply(
+                "get_retention_status",
+                &[
+                    ("retention", any_object()),
+                    ("sources", array(any_object())),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "get_storage_status",
+            "Storage format, free space, encryptor health, and what startup reconciliation fixed.",
+            vec![],
+            reply(
+                "get_storage_status",
+                &[
+                    ("reconcile", any_object()),
+                    ("format", any_object()),
+                    ("usage", any_object()),
+                    ("diskSpace", any_object()),
+                    ("encryptor", any_object()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "migrate_opaque_names",
+            "Start a job renaming existing segments to opaque names.",
+            vec![],
+            reply(
+                "migrate_opaque_names",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "migrate_day_layout",
+            "Start a job moving flat legacy segments into day directories.",
+            vec![],
+            reply(
+                "migrate_day_layout",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "reindex_storage",
+            "Start a job rebuilding the segment index from the segment directories.",
+            vec![],
+            reply(
+                "reindex_storage",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "reencrypt_archive",
+            "Start a background job resealing older segments in a newer format.",
+            vec![
+                param("targetVersion", integer(), true),
+                param("sourceId", string(), false),
+                param("throttleMbps", integer(), false),
+            ],
+            reply(
+                "reencrypt_archive",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "backfill_index",
+            "Start a job recording index entries, sizes, and hashes for segments sealed before the index.",
+            vec![
+                param("sourceId", string(), false),
+                param("skipHashes", boolean(), false),
+                param("throttleMbps", integer(), false),
+            ],
+            reply(
+                "backfill_index",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "get_job_status",
+            "Status of a job, whose progress frames then come here; newest job if no jobId.",
+            vec![param("jobId", string(), false)],
+            reply("get_job_status", &[("job", any_object())]),
+            &[],
+        ),
+        method(
+            "cancel_job",
+            "Ask a running job to stop at its next checkpoint.",
+            vec![param("jobId", string(), true)],
+            reply(
+                "cancel_job",
+                &[("cancelled", boolean()), ("job", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "create_share",
+            "Start a job rendering a clip behind a time-limited guest download link.",
+            vec![
+                param("sourceId", string(), true),
+                param("fromUnix", integer(), true),
+                param("toUnix", integer(), true),
+                param("expiresInHours", integer(), true),
+                param("maxDownloads", integer(), false),
+                param("timezone", string(), false),
+                param("reason", string(), false),
+            ],
+            reply(
+                "create_share",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "list_shares",
+            "Outstanding share links, newest first.",
+            vec![],
+            reply("list_shares", &[("shares", array(any_object()))]),
+            &[],
+        ),
+        method(
+            "revoke_share",
+            "Delete a share link and its clip.",
+            vec![param("id", string(), true)],
+            reply("revoke_share", &[("id", string()), ("revoked", boolean())]),
+            &[],
+        ),
+        method(
+            "create_incident",
+            "Open an incident over cameras and a span, bookmarking its footage.",
+            vec![
+                param("title", string(), true),
+                param("fromUnix", integer(), true),
+                param("toUnix", integer(), true),
+                param("sourceIds", array(string()), true),
+                param("note", string(), false),
+            ],
+            reply("create_incident", &[("incident", any_object())]),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "update_incident",
+            "Append a note or attachments to an open incident.",
+            vec![
+                param("incidentId", string(), true),
+                param("note", string(), false),
+                param("attachments", array(any_object()), false),
+            ],
+            reply("update_incident", &[("incident", any_object())]),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "list_incidents",
+            "Incidents, newest first.",
+            vec![param("limit", integer(), false)],
+            reply("list_incidents", &[("incidents", array(any_object()))]),
+            &[],
+        ),
+        method(
+            "get_incident",
+            "An incident with its segments, snapshots, events, notes and attachments.",
+            vec![param("incidentId", string(), true)],
+            reply("get_incident", &[("incident", any_object())]),
+            &[],
+        ),
+        method(
+            "export_incident",
+            "Start a job archiving an incident with its timeline and signed manifest.",
+            vec![
+                param("incidentId", string(), true),
+                param("reason", string(), false),
+            ],
+            reply(
+                "export_incident",
+                &[("jobId", string()), ("job", any_object())],
+            ),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "close_incident",
+            "Close an incident; its bookmarks lapse after the grace period.",
+            vec![param("incidentId", string(), true)],
+            reply("close_incident", &[("incident", any_object())]),
+            &[],
+        ),
+        method(
+            "mint_token",
+            "Sign an access token limited to one segment, camera or zone and some operations.",
+            vec![
+                param("sourceId", string(), false),
+                param("name", string(), false),
+                param("zone", string(), false),
+                param(
+                    "ops",
+                    array(string_enum(&["download", "snapshot", "live"])),
+                    true,
+                ),
+                param("ttlSecs", integer(), true),
+                param("maxBytes", integer(), false),
+            ],
+            reply(
+                "mint_token",
+                &[("token", string()), ("claims", any_object())],
+            ),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "inspect_token",
+            "Check a token's signature and show its claims, revocation and bytes served.",
+            vec![param("token", string(), true)],
+            reply(
+                "inspect_token",
+                &[
+                    ("valid", boolean()),
+                    ("reason", string()),
+                    ("revoked", boolean()),
+                    ("bytesServed", integer()),
+                    ("claims", any_object()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "revoke_token",
+            "Deny a token id on every route and session until the token has expired.",
+            vec![param("tokenId", string(), true)],
+            reply(
+                "revoke_token",
+                &[("tokenId", string()), ("revoked", boolean())],
+            ),
+            &[],
+        ),
+        method(
+            "list_sessions",
+            "Open sessions with their egress counters.",
+            vec![],
+            reply(
+                "list_sessions",
+                &[
+                    ("sessionId", string()),
+                    ("sessions", array(any_object())),
+                    ("egress", any_object()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "set_session_options",
+            "Cap this session's archive transfer rate and set its display timezone.",
+            vec![
+                param("maxBytesPerSec", integer(), false),
+                param("timezone", string(), false),
+            ],
+            reply(
+                "set_session_options",
+                &[
+                    ("sessionId", string()),
+                    ("maxBytesPerSec", integer()),
+                    ("timezone", string()),
+                ],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "update_settings",
+            "Persist and apply runtime-adjustable settings.",
+            vec![
+                param("egressLimitBytesPerSec", integer(), false),
+                param("sessionEgressLimitBytesPerSec", integer(), false),
+            ],
+            reply("update_settings", &[("settings", any_object())]),
+            &[],
+        ),
+        method(
+            "rotate_zone_secret",
+            "Replace a zone's viewer secret, or clear it to turn zone sessions off.",
+            vec![
+                param("zone", string(), true),
+                param("revoke", boolean(), false),
+            ],
+            reply(
+                "rotate_zone_secret",
+                &[
+                    ("zone", string()),
+                    ("enabled", boolean()),
+                    ("zoneSecretHex", string()),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "get_update_status",
+            "In-process updater state, including a restart waiting on a segment boundary.",
+            vec![],
+            reply("get_update_status", &[("update", any_object())]),
+            &[],
+        ),
+        method(
+            "trigger_update",
+            "Check for an update now, or with immediate restart without waiting for a boundary.",
+            vec![param("immediate", boolean(), false)],
+            reply(
+                "trigger_update",
+                &[("immediate", boolean()), ("update", any_object())],
+            ),
+            &["unsupported"],
+        ),
+        method(
+            "set_diagnostics",
+            "Raise the log level of a module, the service, or one camera's capture for a while.",
+            vec![
+                param("module", string(), false),
+                param("sourceId", string(), false),
+                param(
+                    "level",
+                    string_enum(&["error", "warn", "info", "debug", "trace"]),
+                    true,
+                ),
+                param("durationSecs", integer(), true),
+            ],
+            reply(
+                "set_diagnostics",
+                &[("override", any_object()), ("diagnostics", any_object())],
+            ),
+            &["limit_exceeded", "invalid_argument"],
+        ),
+        method(
+            "get_diagnostics_status",
+            "Active diagnostic overrides, their expiry, and the log filter in force.",
+            vec![],
+            reply("get_diagnostics_status", &[("diagnostics", any_object())]),
+            &[],
+        ),
+        method(
+            "replicate_config",
+            "Replication origin pushes its redacted sources and retention; replaces its last push.",
+            vec![
+                param("originNode", string(), true),
+                param("sentUnix", integer(), true),
+                param("sources", array(any_object()), true),
+                param("retention", any_object(), false),
+                param("bookmarks", array(any_object()), false),
+            ],
+            reply(
+                "replicate_config",
+                &[("originNode", string()), ("sources", integer())],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "replicate_segment",
+            "Replication origin pushes one chunk of a sealed segment, in order from offset 0.",
+            vec![
+                param("originNode", string(), true),
+                param("sourceId", string(), true),
+                param("name", string(), true),
+                param("offset", integer(), true),
+                param("total", integer(), true),
+                param("data", string(), true),
+            ],
+            reply(
+                "replicate_segment",
+                &[
+                    ("originNode", string()),
+                    ("sourceId", string()),
+                    ("name", string()),
+                    ("received", integer()),
+                    ("complete", boolean()),
+                ],
+            ),
+            &["invalid_argument"],
+        ),
+        method(
+            "list_swarm_devices",
+            "Peers' latest swarm device records, with the health they announce and their clock offsets.",
+            vec![],
+            reply(
+                "list_swarm_devices",
+                &[("devices", array(any_object())), ("clock", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "subscribe_dashboard",
+            "Stream dashboard state: a snapshot now, then changes as they happen.",
+            vec![param("enabled", boolean(), false)],
+            reply(
+                "subscribe_dashboard",
+                &[("subscribed", boolean()), ("dashboard", any_object())],
+            ),
+            &[],
+        ),
+        method(
+            "describe_protocol",
+            "This document.",
+            vec![],
+            reply("describe_protocol", &[("protocol", any_object())]),
+            &[],
+        ),
+        method(
+            "get_permissions",
+            "Every method with whether this session may call it, and why not.",
+            vec![],
+            reply(
+                "get_permissions",
+                &[
+                    ("role", string()),
+                    ("zone", string()),
+                    (
+                        "permissions",
+                        array(object(
+                            &[
+                                ("method", string()),
+                                ("role", string()),
+                                ("allowed", boolean()),
+                                ("reason", string()),
+                                ("message", string()),
+                                ("sourceIds", array(string())),
+                            ],
+                            &["method", "role", "allowed"],
+                        )),
+                    ),
+                ],
+            ),
+            &[],
+        ),
+        method(
+            "get_capabilities",
+            "Every optional feature with whether it is available here, and why not.",
+            vec![],
+            reply(
+                "get_capabilities",
+                &[(
+                    "capabilities",
+                    array(object(
+                        &[
+                            ("feature", string()),
+                            (
+                                "status",
+                                string_enum(&["available", "disabled_by_config", "unavailable"]),
+                            ),
+                            (
+                                "reason",
+                                object(
+                                    &[
+                                        ("kind", string_enum(&["dependency", "config", "camera"])),
+                                        ("name", string()),
+                                        ("key", string()),
+                                        ("capability", string()),
+                                    ],
+                                    &["kind"],
+                                ),
+                            ),
+                        ],
+                        &["feature", "status"],
+                    )),
+                )],
+            ),
+            &[],
+        ),
+    ]
+}
+
+fn get_segment_method() -> Value {
+    let mut out = method(
+        "get_segment",
+        "Stream one decrypted segment: segment_start, then segment_chunk frames, then segment_end.",
+        vec![
+            param("sourceId", string(), true),
+            param("name", string(), true),
+            param("binary", boolean(), false),
+            param("window", integer(), false),
+            param("resumeFromSeq", integer(), false),
+        ],
+        reply(
+            "segment_start",
+            &[
+                ("sourceId", string()),
+                ("name", string()),
+                ("bytes", integer()),
+                ("sizeExact", boolean()),
+                ("durationMs", integer()),
+                ("binary", boolean()),
+                ("window", integer()),
+                ("resumeFromSeq", integer()),
+            ],
+        ),
+        &[
+            "not_found",
+            "segment_unreadable",
+            "invalid_argument",
+            "unsupported_version",
+        ],
+    );
+    out["x-frames"] = json!([
+        reply("segment_chunk", &[("seq", integer()), ("data", string())]),
+        reply("segment_end", &[("name", string())]),
+    ]);
+    out
+}
+
+fn get_media_stream_method() -> Value {
+    let mut out = method(
+        "get_media_stream",
+        "Stream one segment remuxed to fragmented MP4 for MSE: media_init, then media_fragment \
+         frames, then media_end.",
+        vec![
+            param("sourceId", string(), true),
+            param("name", string(), true),
+        ],
+        reply(
+            "media_init",
+            &[
+                ("sourceId", string()),
+                ("name", string()),
+                ("mimeType", string()),
+                ("data", string()),
+            ],
+        ),
+        &[
+            "not_found",
+            "segment_unreadable",
+            "unsupported",
+            "dependency_missing",
+            "unsupported_version",
+        ],
+    );
+    out["x-frames"] = json!([
+        reply(
+            "media_fragment",
+            &[
+                ("seq", integer()),
+                ("fragment", integer()),
+                ("fragmentEnd", boolean()),
+                ("data", string()),
+            ],
+        ),
+        reply(
+            "media_end",
+            &[
+                ("name", string()),
+                ("fragments", integer()),
+                ("bytes", integer()),
+            ],
+        ),
+    ]);
+    out
+}
+
+fn get_attachment_method() -> Value {
+    let mut out = method(
+        "get_attachment",
+        "Stream one attachment decrypted, in get_segment's segment_start, segment_chunk and \
+         segment_end frames.",
+        vec![
+            param("sourceId", string(), true),
+            param("attachmentId", string(), true),
+        ],
+        reply(
+            "segment_start",
+            &[
+                ("sourceId", string()),
+                ("attachmentId", string()),
+                ("name", string()),
+                ("contentType", string()),
+                ("bytes", integer()),
+                ("sizeExact", boolean()),
+                ("sha256", string()),
+            ],
+        ),
+        &[
+            "invalid_argument",
+            "segment_unreadable",
+            "unsupported_version",
+        ],
+    );
+    out["x-frames"] = json!([
+        reply("segment_chunk", &[("seq", integer()), ("data", string())]),
+        reply(
+            "segment_end",
+            &[("name", string()), ("attachmentId", string())]
+        ),
+    ]);
+    out
+}
+
+fn source_upsert_schema() -> Value {
+    object(
+        &[
+            ("sourceId", string()),
+            ("name", string()),
+            (
+                "sourceType",
+                json!({ "type": "string", "enum": ["onvif", "rtsp", "test", "push"] }),
+            ),
+            ("onvifHost", string()),
+            ("onvifPort", integer()),
+            ("rtspUrl", string()),
+            ("username", string()),
+            ("password", string()),
+            ("enabled", boolean()),
+            ("segmentSecs", integer()),
+            ("setCameraTime", boolean()),
+            (
+                "pushProtocol",
+                json!({ "type": "string", "enum": ["rtmp", "srt"] }),
+            ),
+            ("pushPort", integer()),
+            ("pushIdleTimeoutSecs", integer()),
+        ],
+        &["sourceId", "name"],
+    )
+}
+
+fn method(name: &str, summary: &str, params: Vec<Value>, result: Value, errors: &[&str]) -> Value {
+    let errors = ["command_failed", "permission_denied"]
+        .iter()
+        .chain(errors)
+        .map(|code| json!({ "$ref": format!("#/components/errors/{code}") }))
+        .collect::<Vec<_>>();
+    let mut method = json!({
+        "name": name,
+        "summary": summary,
+        "paramStructure": "by-name",
+        "params": params,
+        "result": { "name": "reply", "schema": result },
+        "errors": errors,
+        "x-role": role(name),
+        "x-since": since(name),
+    });
+    if let Some(deprecation) = deprecation(name) {
+        method["deprecated"] = json!(true);
+        method["x-deprecated-since"] = json!(deprecation.since);
+        method["x-replacement"] = json!(deprecation.replacement);
+    }
+    method
+}
+
+fn param(name: &str, schema: Value, required: bool) -> Value {
+    json!({ "name": name, "required": required, "schema": schema })
+}
+
+fn reply(cmd: &str, fields: &[(&str, Value)]) -> Value {
+    let mut schema = object(fields, &["ok", "cmd"]);
+    schema["properties"]["ok"] = json!({ "const": true });
+    schema["properties"]["cmd"] = json!({ "const": cmd });
+    schema
+}
+
+fn object(fields: &[(&str, Value)], required: &[&str]) -> Value {
+    let properties = fields
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect::<Map<_, _>>();
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+fn any_object() -> Value {
+    json!({ "type": "object" })
+}
+
+fn string_enum(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_in_document_is_current() {
+        let checked_in: Value = serde_json::from_str(include_str!("../docs/protocol.json"))
+            .expect("parse docs/protocol.json");
+        assert!(
+            checked_in == document(),
+            "docs/protocol.json is stale; regenerate it with `constitute-nvr --print-protocol`"
+        );
+    }
+
+    #[test]
+    fn method_versions_stay_within_the_session_protocol() {
+        let document = document();
+        let methods = document["methods"].as_array().unwrap();
+        for method in methods {
+            let name = method["name"].as_str().unwrap();
+            let since = method["x-since"].as_u64().unwrap() as u32;
+            assert!((1..=crate::features::SESSION_PROTOCOL_VERSION).contains(&since));
+            assert_eq!(
+                method.get("deprecated").is_some(),
+                deprecation(name).is_some()
+            );
+        }
+        for deprecated in DEPRECATIONS {
+            assert!(
+                since(deprecated.replacement) <= deprecated.since,
+                "{}",
+                deprecated.method
+            );
+            assert!(
+                methods
+                    .iter()
+                    .any(|method| method["name"] == json!(deprecated.replacement))
+            );
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug)]
+pub struct StoreLimits {
+    pub max_entries: usize,
+    pub max_per_pubkey: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredRecord<T> {
+    pub pubkey: String,
+    pub slot: String,
+    pub expires_at: u64,
+    pub value: T,
+    #[serde(skip)]
+    updated: u64,
+}
+
+pub struct RecordStore<T> {
+    limits: StoreLimits,
+    entries: HashMap<(String, String), StoredRecord<T>>,
+    clock: u64,
+    evictions: u64,
+}
+
+impl<T: Clone> RecordStore<T> {
+    pub fn new(limits: StoreLimits) -> Self {
+        Self {
+            limits,
+            entries: HashMap::new(),
+            clock: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn get(&self, pubkey: &str, slot: &str, now_ms: u64) -> Option<&T> {
+        self.entries
+            .get(&(pubkey.to_string(), slot.to_string()))
+            .filter(|entry| entry.expires_at > now_ms)
+            .map(|entry| &entry.value)
+    }
+
+    pub fn values(&self, now_ms: u64) -> impl Iterator<Item = &StoredRecord<T>> {
+        self.entries
+            .values()
+            .filter(move |entry| entry.expires_at > now_ms)
+    }
+
+    pub fn insert(
+        &mut self,
+        pubkey: &str,
+        slot: &str,
+        value: T,
+        expires_at: u64,
+        now_ms: u64,
+    ) -> bool {
+        if expires_at <= now_ms || self.limits.max_entries == 0 || self.limits.max_per_pubkey == 0 {
+            return false;
+        }
+        let key = (pubkey.to_string(), slot.to_string());
+        if !self.entries.contains_key(&key) {
+            let own = self
+                .entries
+                .values()
+                .filter(|entry| entry.pubkey == pubkey)
+                .count();
+            if own >= self.limits.max_per_pubkey {
+                self.evict(|entry| entry.pubkey == pubkey);
+            }
+            if self.entries.len() >= self.limits.max_entries {
+                self.sweep(now_ms);
+            }
+            if self.entries.len() >= self.limits.max_entries {
+                self.evict(|_| true);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            StoredRecord {
+                pubkey: pubkey.to_string(),
+                slot: slot.to_string(),
+                expires_at,
+                value,
+                updated: self.clock,
+            },
+        );
+        true
+    }
+
+    pub fn sweep(&mut self, now_ms: u64) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.expires_at > now_ms);
+        before - self.entries.len()
+    }
+
+    pub fn snapshot(&self, now_ms: u64) -> Vec<StoredRecord<T>> {
+        let mut out = self.values(now_ms).cloned().collect::<Vec<_>>();
+        out.sort_by_key(|entry| entry.updated);
+        out
+    }
+
+    pub fn restore(&mut self, records: Vec<StoredRecord<T>>, now_ms: u64) {
+        for record in records {
+            self.insert(
+                &record.pubkey,
+                &record.slot,
+                record.value,
+                record.expires_at,
+                now_ms,
+            );
+        }
+    }
+
+    fn evict(&mut self, candidate: impl Fn(&StoredRecord<T>) -> bool) {
+        let oldest = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| candidate(entry))
+            .min_by_key(|(_, entry)| entry.updated)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+            self.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn random_inserts_and_expiry_never_exceed_the_limits() {
+        let limits = StoreLimits {
+            max_entries: 16,
+            max_per_pubkey: 3,
+        };
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut store = RecordStore::new(limits);
+            let mut now = 1_000u64;
+            for step in 0..500u64 {
+                let pubkey = format!("pk{}", rng.gen_range(0..12));
+                let slot = ["device", "zone_presence", "sources", "control"][rng.gen_range(0..4)];
+                let expires_at = now + rng.gen_range(0..200);
+                store.insert(&pubkey, slot, step, expires_at, now);
+                now += rng.gen_range(0..20);
+                if rng.gen_ratio(1, 10) {
+                    store.sweep(now);
+                }
+
+                assert!(store.entry_count() <= limits.max_entries);
+                let mut per_pubkey = HashMap::<&str, usize>::new();
+                for entry in store.entries.values() {
+                    *per_pubkey.entry(entry.pubkey.as_str()).or_default() += 1;
+                }
+                assert!(
+                    per_pubkey
+                        .values()
+                        .all(|count| *count <= limits.max_per_pubkey)
+                );
+                assert!(store.values(now).all(|entry| entry.expires_at > now));
+                assert!(
+                    store
+                        .snapshot(now)
+                        .iter()
+                        .all(|entry| entry.expires_at > now)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn full_stores_evict_the_least_recently_updated_and_restore_in_order() {
+        let limits = StoreLimits {
+            max_entries: 2,
+            max_per_pubkey: 2,
+        };
+        let mut store = RecordStore::new(limits);
+        assert!(!store.insert("a", "device", 1, 100, 100), "already expired");
+        assert!(store.insert("a", "device", 1, 500, 100));
+        assert!(store.insert("b", "device", 2, 500, 100));
+        // Refreshing `a` leaves `b` as the oldest.
+        assert!(store.insert("a", "device", 3, 500, 110));
+        assert!(store.insert("c", "device", 4, 500, 120));
+        assert_eq!(store.evictions(), 1);
+        assert_eq!(store.get("b", "device", 120), None);
+        assert_eq!(store.get("a", "device", 120), Some(&3));
+        // Reads skip expired entries before any sweep removes them.
+        assert_eq!(store.get("a", "device", 500), None);
+        assert_eq!(store.values(500).count(), 0);
+
+        let snapshot = store.snapshot(130);
+        let mut restored = RecordStore::new(StoreLimits {
+            max_entries: 1,
+            max_per_pubkey: 1,
+        });
+        restored.restore(snapshot, 130);
+        assert_eq!(restored.get("c", "device", 130), Some(&4));
+        assert_eq!(restored.entry_count(), 1);
+        assert_eq!(store.sweep(600), 2);
+    }
+}
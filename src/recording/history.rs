@@ -0,0 +1,177 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+pub const STATE_HISTORY_LEN: usize = 200;
+const ERROR_SNIPPET_CHARS: usize = 200;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateTransition {
+    pub at: u64,
+    pub from: String,
+    pub to: String,
+    pub attempt: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+impl StateTransition {
+    pub fn new(at: u64, from: &str, to: &str, attempt: u64, error: &str) -> Self {
+        Self {
+            at,
+            from: from.to_string(),
+            to: to.to_string(),
+            attempt,
+            error: error.chars().take(ERROR_SNIPPET_CHARS).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StateHistory(Arc<Mutex<VecDeque<StateTransition>>>);
+
+impl StateHistory {
+    pub fn push(&self, transition: StateTransition) {
+        let mut ring = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if ring.len() == STATE_HISTORY_LEN {
+            ring.pop_front();
+        }
+        ring.push_back(transition);
+    }
+
+    pub fn newest(&self, limit: usize) -> Vec<StateTransition> {
+        let ring = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        ring.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Availability {
+    Up,
+    Down,
+    Idle,
+}
+
+pub fn availability_of(state: &str) -> Availability {
+    match state {
+        "running" | "receiving" => Availability::Up,
+        "stopped" | "privacy" | "waiting_for_publisher" | "" => Availability::Idle,
+        _ => Availability::Down,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailabilityWindow {
+    pub up_secs: u64,
+    pub down_secs: u64,
+}
+
+impl AvailabilityWindow {
+    pub fn percent(&self) -> Option<f64> {
+        let total = self.up_secs + self.down_secs;
+        (total > 0).then(|| self.up_secs as f64 * 100.0 / total as f64)
+    }
+}
+
+pub fn availability_window(
+    transitions: &[(u64, String, String)],
+    current: &str,
+    from_secs: u64,
+    now_secs: u64,
+) -> AvailabilityWindow {
+    let mut window = AvailabilityWindow::default();
+    let in_window = transitions
+        .iter()
+        .filter(|(at, _, _)| *at >= from_secs && *at <= now_secs)
+        .collect::<Vec<_>>();
+    let mut state = in_window
+        .first()
+        .map_or(availability_of(current), |(_, from, _)| {
+            availability_of(from)
+        });
+    let mut since = from_secs;
+    let mut add = |state: Availability, secs: u64| match state {
+        Availability::Up => window.up_secs += secs,
+        Availability::Down => window.down_secs += secs,
+        Availability::Idle => {}
+    };
+    for (at, _, to) in in_window {
+        add(state, at.saturating_sub(since));
+        state = availability_of(to);
+        since = *at;
+    }
+    add(state, now_secs.saturating_sub(since));
+    window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(at: u64, from: &str, to: &str) -> (u64, String, String) {
+        (at, from.to_string(), to.to_string())
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_transitions() {
+        let history = StateHistory::default();
+        for at in 0..(STATE_HISTORY_LEN as u64 + 5) {
+            history.push(StateTransition::new(
+                at,
+                "backoff",
+                "starting",
+                at,
+                &"x".repeat(500),
+            ));
+        }
+        let newest = history.newest(usize::MAX);
+        assert_eq!(newest.len(), STATE_HISTORY_LEN);
+        assert_eq!(newest[0].at, STATE_HISTORY_LEN as u64 + 4);
+        assert_eq!(newest.last().unwrap().at, 5);
+        assert_eq!(newest[0].error.len(), ERROR_SNIPPET_CHARS);
+        assert_eq!(history.clone().newest(2).len(), 2);
+    }
+
+    #[test]
+    fn availability_counts_outages_and_skips_idle_time() {
+        // Down from 02:13 to 02:41, in privacy for an hour later on.
+        let transitions = vec![
+            transition(7_980, "running", "backoff"),
+            transition(9_660, "starting", "running"),
+            transition(20_000, "running", "privacy"),
+            transition(23_600, "privacy", "running"),
+        ];
+        let window = availability_window(&transitions, "running", 0, 86_400);
+        assert_eq!(window.down_secs, 1_680);
+        assert_eq!(window.up_secs, 86_400 - 1_680 - 3_600);
+        let percent = window.percent().unwrap();
+        assert!((percent - 97.97).abs() < 0.01, "{percent}");
+    }
+
+    #[test]
+    fn availability_without_transitions_follows_the_current_state() {
+        assert_eq!(
+            availability_window(&[], "backoff", 0, 600),
+            AvailabilityWindow {
+                up_secs: 0,
+                down_secs: 600
+            }
+        );
+        assert_eq!(availability_window(&[], "stopped", 0, 600).percent(), None);
+        // Transitions before the window only matter through the first one inside it.
+        let transitions = vec![
+            transition(10, "stopped", "running"),
+            transition(500, "running", "failed"),
+        ];
+        let window = availability_window(&transitions, "failed", 100, 600);
+        assert_eq!((window.up_secs, window.down_secs), (400, 100));
+    }
+}
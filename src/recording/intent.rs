@@ -0,0 +1,331 @@
+use constitute_protocol::{LogCategory, LogOutcome, LogSeverity, LogSubjectRef};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+use tracing::warn;
+
+pub const RECORDING_INTENT_TAG: &str = "recording_intent";
+const MARKER_PREFIX: &str = ".stopped-";
+pub const MIN_GAP_SECS: u64 = 5;
+const GAP_SLACK_SECS: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    Disabled,
+    Privacy,
+    Removed,
+    Shutdown,
+}
+
+impl StopReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::Privacy => "privacy",
+            Self::Removed => "removed",
+            Self::Shutdown => "shutdown",
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        [Self::Disabled, Self::Privacy, Self::Removed, Self::Shutdown]
+            .into_iter()
+            .find(|reason| reason.as_str() == raw)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IntentMarker {
+    pub at: u64,
+    pub stop: Option<StopReason>,
+}
+
+impl IntentMarker {
+    pub fn from_event(facts: &serde_json::Value) -> Option<Self> {
+        let stop = match facts["action"].as_str()? {
+            "stopped" => Some(StopReason::parse(facts["reason"].as_str()?)?),
+            "started" => None,
+            _ => return None,
+        };
+        Some(Self {
+            at: facts["at"].as_u64()?,
+            stop,
+        })
+    }
+}
+
+fn marker_file_name(reason: StopReason, at: u64) -> String {
+    format!("{MARKER_PREFIX}{}-{at}", reason.as_str())
+}
+
+fn parse_marker_file(name: &str) -> Option<IntentMarker> {
+    let (reason, at) = name.strip_prefix(MARKER_PREFIX)?.rsplit_once('-')?;
+    Some(IntentMarker {
+        at: at.parse().ok()?,
+        stop: Some(StopReason::parse(reason)?),
+    })
+}
+
+pub async fn read_markers(source_dir: &Path) -> Vec<IntentMarker> {
+    let mut out = Vec::new();
+    let Ok(mut rd) = tokio::fs::read_dir(source_dir).await else {
+        return out;
+    };
+    while let Ok(Some(entry)) = rd.next_entry().await {
+        if let Some(marker) = parse_marker_file(&entry.file_name().to_string_lossy()) {
+            out.push(marker);
+        }
+    }
+    out.sort();
+    out
+}
+
+pub async fn mark_stopped(source_dir: &Path, source_id: &str, reason: StopReason) {
+    let existing = read_markers(source_dir).await;
+    if existing.iter().any(|marker| marker.stop == Some(reason)) {
+        return;
+    }
+    if !tokio::fs::try_exists(source_dir).await.unwrap_or(false) {
+        return;
+    }
+    remove_markers(source_dir, &existing).await;
+    let at = crate::util::now_unix_seconds();
+    let path = source_dir.join(marker_file_name(reason, at));
+    if let Err(err) = tokio::fs::write(&path, b"").await {
+        warn!(path = %path.display(), error = %err, "failed to write recording stop marker");
+    }
+    log_intent(source_id, at, "stopped", Some(reason)).await;
+}
+
+pub async fn mark_started(source_dir: &Path, source_id: &str) {
+    let existing = read_markers(source_dir).await;
+    if existing.is_empty() {
+        return;
+    }
+    remove_markers(source_dir, &existing).await;
+    log_intent(source_id, crate::util::now_unix_seconds(), "started", None).await;
+}
+
+async fn remove_markers(source_dir: &Path, markers: &[IntentMarker]) {
+    for marker in markers {
+        let Some(reason) = marker.stop else {
+            continue;
+        };
+        let path = source_dir.join(marker_file_name(reason, marker.at));
+        if let Err(err) = tokio::fs::remove_file(&path).await {
+            warn!(path = %path.display(), error = %err, "failed to remove recording stop marker");
+        }
+    }
+}
+
+async fn log_intent(source_id: &str, at: u64, action: &str, reason: Option<StopReason>) {
+    if cfg!(test) {
+        return;
+    }
+    crate::logging_surface::submit_safe_event(
+        "recorder",
+        LogCategory::ServiceAccess,
+        LogSeverity::Info,
+        LogOutcome::Observed,
+        LogSubjectRef {
+            kind: "camera".to_string(),
+            id: Some(source_id.to_string()),
+            display: None,
+        },
+        &["nvr", RECORDING_INTENT_TAG],
+        json!({
+            "sourceId": source_id,
+            "at": at,
+            "action": action,
+            "reason": reason.map(StopReason::as_str),
+        }),
+    )
+    .await;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapLabel {
+    Intentional,
+    Unexplained,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageGap {
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub label: GapLabel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<StopReason>,
+}
+
+pub fn coverage_gaps(
+    spans: &[(u64, u64)],
+    from: u64,
+    to: u64,
+    min_gap_secs: u64,
+    markers: &[IntentMarker],
+) -> Vec<CoverageGap> {
+    let min_gap_secs = min_gap_secs.max(MIN_GAP_SECS);
+    let mut spans = spans.to_vec();
+    spans.sort_unstable();
+    let mut markers = markers.to_vec();
+    markers.sort_unstable();
+    markers.dedup();
+    let stops = markers
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, marker)| {
+            // A stop for another reason before the start continues this one.
+            let until = markers[idx + 1..]
+                .iter()
+                .find(|next| next.stop.is_none())
+                .map_or(u64::MAX, |next| next.at);
+            Some((marker.at, until, marker.stop?))
+        })
+        .collect::<Vec<_>>();
+
+    let mut uncovered = Vec::new();
+    let mut cursor = from;
+    for (start, end) in spans {
+        if start > cursor {
+            uncovered.push((cursor, start.min(to)));
+        }
+        cursor = cursor.max(end);
+        if cursor >= to {
+            break;
+        }
+    }
+    if cursor < to {
+        uncovered.push((cursor, to));
+    }
+
+    uncovered
+        .into_iter()
+        .filter(|(start, end)| end.saturating_sub(*start) >= min_gap_secs)
+        .map(|(start, end)| {
+            let reason = stops
+                .iter()
+                .find(|(stopped, until, _)| {
+                    *stopped <= start.saturating_add(GAP_SLACK_SECS)
+                        && until.saturating_add(GAP_SLACK_SECS) >= end
+                })
+                .map(|(_, _, reason)| *reason);
+            CoverageGap {
+                from_unix: start,
+                to_unix: end,
+                label: if reason.is_some() {
+                    GapLabel::Intentional
+                } else {
+                    GapLabel::Unexplained
+                },
+                reason,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(at: u64, reason: StopReason) -> IntentMarker {
+        IntentMarker {
+            at,
+            stop: Some(reason),
+        }
+    }
+
+    fn start(at: u64) -> IntentMarker {
+        IntentMarker { at, stop: None }
+    }
+
+    #[test]
+    fn gaps_inside_a_stop_are_intentional() {
+        let spans = [
+            (1_000, 1_600),
+            (1_602, 2_000),
+            (5_010, 6_000),
+            (7_000, 8_000),
+        ];
+        let markers = [
+            stop(1_990, StopReason::Privacy),
+            start(5_000),
+            stop(9_000, StopReason::Disabled),
+        ];
+        let gaps = coverage_gaps(&spans, 500, 10_000, MIN_GAP_SECS, &markers);
+        let labels = gaps
+            .iter()
+            .map(|gap| (gap.from_unix, gap.to_unix, gap.label, gap.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            vec![
+                (500, 1_000, GapLabel::Unexplained, None),
+                (
+                    2_000,
+                    5_010,
+                    GapLabel::Intentional,
+                    Some(StopReason::Privacy)
+                ),
+                (6_000, 7_000, GapLabel::Unexplained, None),
+                (8_000, 10_000, GapLabel::Unexplained, None),
+            ]
+        );
+
+        // A recorder that failed before the stop leaves the stretch before it unexplained.
+        let stopped = [stop(8_100, StopReason::Disabled)];
+        let gaps = coverage_gaps(&spans, 8_000, 10_000, MIN_GAP_SECS, &stopped);
+        assert_eq!(gaps[0].label, GapLabel::Unexplained);
+        let stopped = [stop(8_010, StopReason::Disabled)];
+        let gaps = coverage_gaps(&spans, 8_000, 10_000, MIN_GAP_SECS, &stopped);
+        assert_eq!(gaps[0].reason, Some(StopReason::Disabled));
+
+        // A threshold keeps only the longer gaps, and never lets rollovers through.
+        let gaps = coverage_gaps(&spans, 500, 10_000, 1_500, &markers);
+        let kept = gaps.iter().map(|gap| gap.from_unix).collect::<Vec<_>>();
+        assert_eq!(kept, vec![2_000, 8_000]);
+        let gaps = coverage_gaps(&spans, 1_000, 2_000, 0, &[]);
+        assert!(gaps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn markers_last_until_the_recorder_starts() {
+        let dir = std::env::temp_dir().join(format!(
+            "constitute-nvr-intent-test-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        mark_stopped(&dir, "cam", StopReason::Privacy).await;
+        assert!(read_markers(&dir).await.is_empty());
+
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        mark_stopped(&dir, "cam", StopReason::Privacy).await;
+        let first = read_markers(&dir).await;
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].stop, Some(StopReason::Privacy));
+        mark_stopped(&dir, "cam", StopReason::Privacy).await;
+        assert_eq!(read_markers(&dir).await, first);
+        mark_stopped(&dir, "cam", StopReason::Removed).await;
+        let replaced = read_markers(&dir).await;
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(replaced[0].stop, Some(StopReason::Removed));
+
+        mark_started(&dir, "cam").await;
+        assert!(read_markers(&dir).await.is_empty());
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[test]
+    fn events_and_file_names_round_trip() {
+        let marker = stop(1_700_000_000, StopReason::Shutdown);
+        assert_eq!(
+            parse_marker_file(&marker_file_name(StopReason::Shutdown, marker.at)),
+            Some(marker)
+        );
+        assert_eq!(parse_marker_file(".stopped-nap-12"), None);
+        let event = json!({ "at": 12, "action": "started", "reason": null });
+        assert_eq!(IntentMarker::from_event(&event), Some(start(12)));
+    }
+}
@@ -1,5 +1,8 @@
+pub mod history;
+pub mod intent;
 pub mod runtime;
 pub mod segments;
+pub mod states;
 pub mod worker;
 
 pub use runtime::*;
@@ -1,14 +1,30 @@
+use super::history::{StateHistory, StateTransition, availability_of};
+use super::intent::{self, StopReason};
+use super::states::{SourceState, StateTable};
 use crate::config::{CameraDeviceConfig, Config};
+use crate::diagnostics::{CAPTURE_TARGET, Diagnostics};
+use crate::media::dependencies::DependencyMonitor;
+use crate::stats::StatsRegistry;
+use crate::util::backoff::Backoff;
+use crate::util::now_ms;
 use anyhow::Result;
+use constitute_protocol::{LogCategory, LogOutcome, LogSeverity, LogSubjectRef};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, watch};
 use tokio::time::{Duration, timeout};
 
+const STOP_GRACE_SECS: u64 = 10;
+pub const SOURCE_STATE_TAG: &str = "source_state";
+pub(crate) const RESTART_BACKOFF: Backoff =
+    Backoff::new(Duration::from_secs(2), Duration::from_secs(30)).with_jitter(0.2);
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DiscoveredCamera {
     pub endpoint: String,
@@ -22,27 +38,64 @@ pub struct SourceRuntimeState {
     pub state: String,
     pub restart_attempt: u64,
     pub backoff_secs: u64,
+    pub next_retry_at: u64,
     pub last_error: String,
     pub updated_at: u64,
+    pub grace_until: u64,
+    pub segment_started_at: u64,
+    #[serde(skip)]
+    pub history: StateHistory,
+    #[serde(skip)]
+    pub diagnostics: Diagnostics,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SegmentClock {
+    pub started_at: u64,
+    pub segment_secs: u64,
 }
 
 struct RuntimeEntry {
-    state: Arc<Mutex<SourceRuntimeState>>,
+    state: SourceState,
     handle: Option<tokio::task::JoinHandle<()>>,
+    stop: watch::Sender<bool>,
+    segment_secs: u64,
+    source_dir: PathBuf,
 }
 
 #[derive(Clone)]
 pub struct RecorderManager {
     inner: Arc<Mutex<HashMap<String, RuntimeEntry>>>,
+    states: StateTable,
+    dependencies: DependencyMonitor,
+    stats: StatsRegistry,
+    storage_paused: Arc<AtomicBool>,
+    disk_paused: Arc<AtomicBool>,
+    diagnostics: Diagnostics,
 }
 
 impl RecorderManager {
-    pub fn new() -> Self {
+    pub fn new(dependencies: DependencyMonitor, stats: StatsRegistry) -> Self {
         Self {
             inner: Arc::new(Mutex::new(HashMap::new())),
+            states: StateTable::default(),
+            dependencies,
+            stats,
+            storage_paused: Arc::default(),
+            disk_paused: Arc::default(),
+            diagnostics: Diagnostics::default(),
         }
     }
 
+    pub fn with_diagnostics(mut self, diagnostics: Diagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
     pub async fn ensure_started(&self, cfg: &Config) {
         let storage_root = cfg.storage_root();
         for cam in &cfg.camera_devices {
@@ -50,116 +103,368 @@ impl RecorderManager {
         }
     }
 
+    pub async fn resume_dependency_blocked(&self, cfg: &Config) -> usize {
+        let blocked = self
+            .list_states()
+            .await
+            .into_iter()
+            .filter(|state| state.state == "dependency_missing")
+            .map(|state| state.source_id)
+            .collect::<Vec<_>>();
+        let mut resumed = 0;
+        for cam in cfg
+            .camera_devices
+            .iter()
+            .filter(|cam| blocked.contains(&cam.source_id))
+        {
+            self.upsert_camera(cfg.storage_root(), cam.clone()).await;
+            resumed += 1;
+        }
+        resumed
+    }
+
+    pub async fn pause_for_storage(&self, reason: &str) {
+        self.storage_paused.store(true, Ordering::SeqCst);
+        self.stop_recorders("storage_unavailable", reason).await;
+    }
+
+    pub async fn resume_after_storage(&self, cfg: &Config) -> usize {
+        self.storage_paused.store(false, Ordering::SeqCst);
+        self.restart_parked(cfg, "storage_unavailable").await
+    }
+
+    pub async fn pause_for_disk_full(&self, reason: &str) {
+        self.disk_paused.store(true, Ordering::SeqCst);
+        self.stop_recorders("paused_disk_full", reason).await;
+    }
+
+    pub async fn resume_after_disk_full(&self, cfg: &Config) -> usize {
+        self.disk_paused.store(false, Ordering::SeqCst);
+        self.restart_parked(cfg, "paused_disk_full").await
+    }
+
+    async fn restart_parked(&self, cfg: &Config, parked: &str) -> usize {
+        let parked = self
+            .list_states()
+            .await
+            .into_iter()
+            .filter(|state| state.state == parked)
+            .map(|state| state.source_id)
+            .collect::<Vec<_>>();
+        let mut resumed = 0;
+        for cam in cfg
+            .camera_devices
+            .iter()
+            .filter(|cam| parked.contains(&cam.source_id))
+        {
+            self.upsert_camera(cfg.storage_root(), cam.clone()).await;
+            resumed += 1;
+        }
+        resumed
+    }
+
     pub async fn upsert_camera(&self, storage_root: PathBuf, cam: CameraDeviceConfig) {
-        self.remove_camera(&cam.source_id).await;
+        let history = self
+            .take_camera(&cam.source_id)
+            .await
+            .map(|(history, _)| history)
+            .unwrap_or_default();
+        let source_dir = storage_root
+            .join("segments")
+            .join(crate::util::source_dir_name(&cam.source_id));
 
-        let state = Arc::new(Mutex::new(SourceRuntimeState {
+        let invalid = if cam.is_capturing() {
+            cam.rtsp_url_error().or_else(|| cam.push_ingest_error())
+        } else {
+            None
+        };
+        if let Some(reason) = &invalid {
+            tracing::warn!(
+                source = %cam.source_id,
+                reason = %reason,
+                "camera config is invalid; recorder not started"
+            );
+        }
+        let parked = if !cam.is_capturing() || invalid.is_some() {
+            None
+        } else if self.storage_paused.load(Ordering::SeqCst) {
+            Some(("storage_unavailable", "storage root is unavailable"))
+        } else if self.disk_paused.load(Ordering::SeqCst) {
+            Some((
+                "paused_disk_full",
+                "storage volume is below its low-water mark",
+            ))
+        } else {
+            None
+        };
+        let blocker = if cam.is_capturing() && invalid.is_none() && parked.is_none() {
+            self.dependencies.current().recording_blocker()
+        } else {
+            None
+        };
+        let state = self.states.insert(SourceRuntimeState {
             source_id: cam.source_id.clone(),
-            state: if cam.enabled {
-                "starting".to_string()
-            } else {
-                "stopped".to_string()
-            },
+            state: "stopped".to_string(),
             restart_attempt: 0,
             backoff_secs: 0,
+            next_retry_at: 0,
             last_error: String::new(),
             updated_at: now_ms(),
-        }));
+            grace_until: 0,
+            segment_started_at: 0,
+            history,
+            diagnostics: self.diagnostics.clone(),
+        });
+        let initial = if !cam.enabled {
+            "stopped"
+        } else if cam.privacy {
+            "privacy"
+        } else if invalid.is_some() {
+            "config_invalid"
+        } else if let Some((parked, _)) = parked {
+            parked
+        } else if blocker.is_some() {
+            "dependency_missing"
+        } else {
+            "starting"
+        };
+        let reason = invalid
+            .clone()
+            .or_else(|| parked.map(|(_, reason)| reason.to_string()))
+            .or_else(|| blocker.clone())
+            .unwrap_or_default();
+        update_state(&state, initial, 0, reason, Some(0));
 
-        let handle = if cam.enabled {
+        let (stop, stop_rx) = watch::channel(false);
+        let spawn =
+            cam.is_capturing() && invalid.is_none() && parked.is_none() && blocker.is_none();
+        match initial {
+            "stopped" => {
+                intent::mark_stopped(&source_dir, &cam.source_id, StopReason::Disabled).await
+            }
+            "privacy" => {
+                intent::mark_stopped(&source_dir, &cam.source_id, StopReason::Privacy).await
+            }
+            _ if spawn => intent::mark_started(&source_dir, &cam.source_id).await,
+            _ => {}
+        }
+        let handle = if spawn {
             let source_id = cam.source_id.clone();
             let camera = cam.clone();
-            let state_ref = Arc::clone(&state);
+            let state_ref = state.clone();
+            let stats = self.stats.clone();
             Some(tokio::spawn(async move {
-                if let Err(err) =
-                    super::worker::record_loop(storage_root, camera, Arc::clone(&state_ref)).await
+                if let Err(err) = super::worker::record_loop(
+                    storage_root,
+                    camera,
+                    state_ref.clone(),
+                    stats,
+                    stop_rx,
+                )
+                .await
                 {
                     tracing::warn!(error = %err, source = %source_id, "camera recorder exited");
-                    update_state(&state_ref, "failed", 0, err.to_string(), None).await;
+                    update_state(&state_ref, "failed", 0, err.to_string(), None);
                 }
             }))
         } else {
             None
         };
 
+        let entry = RuntimeEntry {
+            state,
+            handle,
+            stop,
+            segment_secs: cam.segment_secs,
+            source_dir,
+        };
         let mut guard = self.inner.lock().await;
-        guard.insert(cam.source_id.clone(), RuntimeEntry { state, handle });
+        guard.insert(cam.source_id.clone(), entry);
     }
 
     pub async fn remove_camera(&self, source_id: &str) -> bool {
-        let source = source_id.to_string();
-        let entry = {
-            let mut guard = self.inner.lock().await;
-            guard.remove(&source)
+        let Some((_, source_dir)) = self.take_camera(source_id).await else {
+            return false;
+        };
+        intent::mark_stopped(&source_dir, source_id, StopReason::Removed).await;
+        true
+    }
+
+    async fn take_camera(&self, source_id: &str) -> Option<(StateHistory, PathBuf)> {
+        let mut entry = self.inner.lock().await.remove(source_id)?;
+        if let Some(handle) = entry.handle.take() {
+            handle.abort();
+        }
+        update_state(&entry.state, "stopped", 0, String::new(), None);
+        let history = entry.state.read(|state| state.history.clone());
+        self.states.remove(source_id);
+        Some((history.unwrap_or_default(), entry.source_dir))
+    }
+
+    pub async fn state_history(
+        &self,
+        source_id: &str,
+        limit: usize,
+    ) -> Option<Vec<StateTransition>> {
+        let history = self.states.get(source_id)?.history;
+        Some(history.newest(limit))
+    }
+
+    pub async fn hold_for_reboot(&self, source_id: &str, window: Duration) -> bool {
+        let state = {
+            let guard = self.inner.lock().await;
+            guard.get(source_id).map(|entry| entry.state.clone())
         };
+        state
+            .and_then(|state| {
+                state.update(|state| {
+                    state.grace_until = now_ms().saturating_add(window.as_millis() as u64);
+                })
+            })
+            .is_some()
+    }
 
-        if let Some(mut entry) = entry {
-            if let Some(handle) = entry.handle.take() {
+    pub async fn stop_all(&self) {
+        for (source_id, source_dir) in self.stop_recorders("stopped", "").await {
+            intent::mark_stopped(&source_dir, &source_id, StopReason::Shutdown).await;
+        }
+    }
+
+    async fn stop_recorders(&self, status: &str, reason: &str) -> Vec<(String, PathBuf)> {
+        let stopping = {
+            let mut guard = self.inner.lock().await;
+            guard
+                .iter_mut()
+                .filter_map(|(source_id, entry)| {
+                    let _ = entry.stop.send(true);
+                    entry.handle.take().map(|handle| {
+                        (
+                            handle,
+                            entry.state.clone(),
+                            (source_id.clone(), entry.source_dir.clone()),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(STOP_GRACE_SECS);
+        let mut stopped = Vec::with_capacity(stopping.len());
+        for (mut handle, state, source) in stopping {
+            if tokio::time::timeout_at(deadline, &mut handle)
+                .await
+                .is_err()
+            {
                 handle.abort();
             }
-            update_state(&entry.state, "stopped", 0, String::new(), None).await;
-            return true;
+            update_state(&state, status, 0, reason.to_string(), None);
+            stopped.push(source);
         }
-        false
+        stopped
     }
 
-    pub async fn list_states(&self) -> Vec<SourceRuntimeState> {
-        let entries: Vec<Arc<Mutex<SourceRuntimeState>>> = {
+    pub async fn segment_clocks(&self) -> Vec<SegmentClock> {
+        let entries = {
             let guard = self.inner.lock().await;
             guard
                 .values()
-                .map(|entry| Arc::clone(&entry.state))
-                .collect()
+                .filter(|entry| entry.handle.is_some())
+                .map(|entry| (entry.state.clone(), entry.segment_secs))
+                .collect::<Vec<_>>()
         };
+        entries
+            .into_iter()
+            .filter_map(|(state, segment_secs)| {
+                let started_at = state.read(|state| {
+                    (matches!(state.state.as_str(), "running" | "receiving")
+                        && state.segment_started_at > 0)
+                        .then_some(state.segment_started_at)
+                })??;
+                Some(SegmentClock {
+                    started_at,
+                    segment_secs,
+                })
+            })
+            .collect()
+    }
 
-        let mut out = Vec::with_capacity(entries.len());
-        for state in entries {
-            out.push(state.lock().await.clone());
-        }
-        out.sort_by(|left, right| left.source_id.cmp(&right.source_id));
-        out
+    pub async fn list_states(&self) -> Vec<SourceRuntimeState> {
+        self.states.list_where(|_| true)
     }
+
+    pub fn states(&self) -> &StateTable {
+        &self.states
+    }
+}
+
+pub(crate) fn update_state(
+    state: &SourceState,
+    status: &str,
+    restart_attempt: u64,
+    last_error: String,
+    next_retry_at: Option<u64>,
+) {
+    state.update(|guard| apply_state(guard, status, restart_attempt, last_error, next_retry_at));
 }
 
-pub(crate) async fn update_state(
-    state: &Arc<Mutex<SourceRuntimeState>>,
+fn apply_state(
+    guard: &mut SourceRuntimeState,
     status: &str,
     restart_attempt: u64,
     last_error: String,
-    backoff_secs: Option<u64>,
+    next_retry_at: Option<u64>,
 ) {
-    let mut guard = state.lock().await;
+    let now = now_ms();
+    if guard.state != status {
+        let transition =
+            StateTransition::new(now, &guard.state, status, restart_attempt, &last_error);
+        if guard.diagnostics.captures(&guard.source_id) {
+            tracing::debug!(
+                target: CAPTURE_TARGET,
+                source = %guard.source_id,
+                from = %guard.state,
+                to = status,
+                restart_attempt,
+                error = %last_error,
+                "recorder state transition"
+            );
+        }
+        if !cfg!(test) && availability_of(&guard.state) != availability_of(status) {
+            tokio::spawn(log_transition(guard.source_id.clone(), transition.clone()));
+        }
+        guard.history.push(transition);
+    }
     guard.state = status.to_string();
     guard.restart_attempt = restart_attempt;
-    guard.backoff_secs = backoff_secs.unwrap_or(guard.backoff_secs);
+    if let Some(next_retry_at) = next_retry_at {
+        guard.next_retry_at = next_retry_at;
+        guard.backoff_secs = next_retry_at.saturating_sub(now).div_ceil(1000);
+    }
     guard.last_error = last_error;
-    guard.updated_at = now_ms();
-}
-
-pub(crate) fn backoff_secs(attempt: u64) -> u64 {
-    let p = attempt.clamp(1, 6);
-    let secs = 2_u64.pow(p as u32);
-    secs.min(30)
+    guard.updated_at = now;
 }
 
-pub(crate) fn sanitize(value: &str) -> String {
-    value
-        .chars()
-        .map(|c| {
-            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
-                c
-            } else {
-                '_'
-            }
-        })
-        .collect()
-}
-
-pub(crate) fn now_ms() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_millis() as u64)
-        .unwrap_or(0)
+async fn log_transition(source_id: String, transition: StateTransition) {
+    crate::logging_surface::submit_safe_event(
+        "recorder",
+        LogCategory::ServiceAccess,
+        LogSeverity::Info,
+        LogOutcome::Observed,
+        LogSubjectRef {
+            kind: "camera".to_string(),
+            id: Some(source_id.clone()),
+            display: None,
+        },
+        &["nvr", SOURCE_STATE_TAG],
+        json!({
+            "sourceId": source_id,
+            "at": transition.at,
+            "from": transition.from,
+            "to": transition.to,
+            "attempt": transition.attempt,
+            "error": transition.error,
+        }),
+    )
+    .await;
 }
 
 pub async fn discover_onvif(timeout_secs: u64) -> Result<Vec<DiscoveredCamera>> {
@@ -234,6 +539,7 @@ fn extract_xaddrs(xml: &str) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::test_support;
     use crate::media::{ffmpeg, planner};
 
     #[test]
@@ -245,9 +551,132 @@ mod tests {
 
     #[test]
     fn backoff_bounds() {
-        assert_eq!(backoff_secs(1), 2);
-        assert_eq!(backoff_secs(2), 4);
-        assert_eq!(backoff_secs(8), 30);
+        let secs = |attempt| RESTART_BACKOFF.nominal(attempt).as_secs();
+        assert_eq!(secs(1), 2);
+        assert_eq!(secs(2), 4);
+        assert_eq!(secs(8), 30);
+    }
+
+    #[tokio::test]
+    async fn blocked_cameras_park_without_spawning() {
+        let recorder = RecorderManager::new(
+            DependencyMonitor::from_report(Default::default()),
+            StatsRegistry::default(),
+        );
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-runtime-test-{}",
+            uuid::Uuid::new_v4()
+        ));
+
+        let mut private = test_support::camera("private-cam");
+        private.privacy = true;
+        recorder.upsert_camera(root.clone(), private).await;
+        recorder
+            .upsert_camera(root, test_support::camera("no-ffmpeg-cam"))
+            .await;
+
+        let states = recorder.list_states().await;
+        assert_eq!(states[0].source_id, "no-ffmpeg-cam");
+        assert_eq!(states[0].state, "dependency_missing");
+        assert!(states[0].last_error.contains("ffmpeg"));
+        assert_eq!(states[1].source_id, "private-cam");
+        assert_eq!(states[1].state, "privacy");
+        assert!(
+            recorder
+                .inner
+                .lock()
+                .await
+                .values()
+                .all(|entry| entry.handle.is_none())
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_rtsp_urls_park_in_config_invalid() {
+        let recorder = RecorderManager::new(
+            DependencyMonitor::from_report(Default::default()),
+            StatsRegistry::default(),
+        );
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-runtime-test-{}",
+            uuid::Uuid::new_v4()
+        ));
+        let mut camera = test_support::camera("typo-cam");
+        camera.rtsp_url = "192.168.0.50:554/stream".to_string();
+        recorder.upsert_camera(root, camera).await;
+
+        let states = recorder.list_states().await;
+        assert_eq!(states[0].state, "config_invalid");
+        assert!(states[0].last_error.starts_with("rtsp_url "));
+        assert!(
+            recorder
+                .inner
+                .lock()
+                .await
+                .values()
+                .all(|entry| entry.handle.is_none())
+        );
+    }
+
+    #[tokio::test]
+    async fn storage_pauses_park_recorders_until_resumed() {
+        let recorder = RecorderManager::new(
+            DependencyMonitor::from_report(Default::default()),
+            StatsRegistry::default(),
+        );
+        let mut cfg = Config::default_generated();
+        cfg.camera_devices.push(test_support::camera("usb-cam"));
+        let mut private = test_support::camera("private-cam");
+        private.privacy = true;
+        cfg.camera_devices.push(private);
+        recorder.ensure_started(&cfg).await;
+
+        recorder
+            .pause_for_storage("storage root is unavailable")
+            .await;
+        // Cameras upserted while paused stay parked too.
+        recorder.ensure_started(&cfg).await;
+        let states = recorder.list_states().await;
+        assert_eq!(states[0].source_id, "private-cam");
+        assert_eq!(states[0].state, "privacy");
+        assert_eq!(states[1].state, "storage_unavailable");
+        assert_eq!(states[1].last_error, "storage root is unavailable");
+        assert!(
+            recorder
+                .inner
+                .lock()
+                .await
+                .values()
+                .all(|entry| entry.handle.is_none())
+        );
+
+        assert_eq!(recorder.resume_after_storage(&cfg).await, 1);
+        let states = recorder.list_states().await;
+        assert_eq!(states[1].state, "dependency_missing");
+    }
+
+    #[tokio::test]
+    async fn disk_full_pauses_outlast_a_storage_pause() {
+        let recorder = RecorderManager::new(
+            DependencyMonitor::from_report(Default::default()),
+            StatsRegistry::default(),
+        );
+        let mut cfg = Config::default_generated();
+        cfg.camera_devices.push(test_support::camera("usb-cam"));
+        recorder.ensure_started(&cfg).await;
+
+        recorder.pause_for_disk_full("storage volume is low").await;
+        recorder
+            .pause_for_storage("storage root is unavailable")
+            .await;
+        recorder.ensure_started(&cfg).await;
+        assert_eq!(recorder.list_states().await[0].state, "storage_unavailable");
+
+        // The root comes back while the volume is still low.
+        assert_eq!(recorder.resume_after_storage(&cfg).await, 1);
+        assert_eq!(recorder.list_states().await[0].state, "paused_disk_full");
+        assert_eq!(recorder.resume_after_disk_full(&cfg).await, 1);
+        assert_eq!(recorder.list_states().await[0].state, "dependency_missing");
     }
 
     #[test]
@@ -270,6 +699,13 @@ mod tests {
             segment_secs: 10,
             desired: Default::default(),
             credentials: Default::default(),
+            privacy: false,
+            set_camera_time: false,
+            snapshot_interval_secs: 0,
+            zones: Vec::new(),
+            push: Default::default(),
+            source_type: Default::default(),
+            retention: Default::default(),
         };
         let plan = planner::recording_pipeline_plan(&camera);
         let args = ffmpeg::build_recording_ffmpeg_args(
@@ -280,4 +716,25 @@ mod tests {
         assert!(args.windows(2).any(|pair| pair == ["-c:v", "copy"]));
         assert!(!args.windows(2).any(|pair| pair == ["-c", "copy"]));
     }
+
+    #[test]
+    fn test_source_records_a_generated_pattern() {
+        let mut camera = test_support::camera("test-1");
+        camera.source_type = crate::config::CameraSourceType::Test;
+        camera.rtsp_url.clear();
+        camera.onvif_host.clear();
+        let plan = planner::recording_pipeline_plan(&camera);
+        let args = ffmpeg::build_recording_ffmpeg_args(
+            &plan,
+            &PathBuf::from("/tmp/out-%Y%m%dT%H%M%S.mp4"),
+        );
+        assert!(
+            args.windows(2)
+                .any(|pair| pair == ["-i", "testsrc2=size=1280x720:rate=15"])
+        );
+        assert!(args.iter().any(|arg| arg.starts_with("sine=")));
+        assert!(args.windows(2).any(|pair| pair == ["-map", "1:a:0"]));
+        assert!(!args.iter().any(|arg| arg == "-rtsp_transport"));
+        assert!(args.windows(2).any(|pair| pair == ["-f", "segment"]));
+    }
 }
@@ -1,19 +1,91 @@
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub async fn count_segment_files(out_dir: &PathBuf) -> Result<u64> {
+pub fn dated_output_pattern(out_dir: &Path) -> PathBuf {
+    out_dir.join("%Y%m%d").join("%H%M%S.mp4")
+}
+
+pub async fn ensure_day_dirs(out_dir: &Path) -> Result<()> {
+    let today = chrono::Local::now();
+    for day in [today, today + chrono::Duration::days(1)] {
+        tokio::fs::create_dir_all(out_dir.join(day.format("%Y%m%d").to_string())).await?;
+    }
+    Ok(())
+}
+
+pub async fn count_segment_files(out_dir: &Path) -> Result<u64> {
+    Ok(recent_segment_names(out_dir).await?.len() as u64)
+}
+
+pub async fn scan_new_segments(
+    out_dir: &Path,
+    after: Option<&str>,
+) -> Result<(u64, Option<String>)> {
     let mut count = 0u64;
-    let mut reader = tokio::fs::read_dir(out_dir).await?;
-    while let Some(entry) = reader.next_entry().await? {
-        if entry
-            .path()
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| ext.eq_ignore_ascii_case("mp4"))
-            .unwrap_or(false)
+    let mut newest = after.map(str::to_string);
+    for name in recent_segment_names(out_dir).await? {
+        if after.is_some_and(|after| name.as_str() <= after) {
+            continue;
+        }
+        count = count.saturating_add(1);
+        if newest
+            .as_deref()
+            .is_none_or(|newest| name.as_str() > newest)
         {
-            count = count.saturating_add(1);
+            newest = Some(name);
+        }
+    }
+    Ok((count, newest))
+}
+
+pub async fn segment_len(out_dir: &Path, name: &str) -> Option<u64> {
+    let dated = name
+        .split_once('T')
+        .map(|(day, file)| out_dir.join(day).join(file));
+    for path in dated.into_iter().chain([out_dir.join(name)]) {
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            return Some(meta.len());
+        }
+    }
+    None
+}
+
+pub fn segment_start_ms(name: &str) -> Option<u64> {
+    crate::util::clock::local_stamp_unix(name).map(|secs| secs * 1000)
+}
+
+async fn recent_segment_names(out_dir: &Path) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    collect_mp4_names(out_dir, None, &mut out).await?;
+    let today = chrono::Local::now();
+    for day in [
+        today - chrono::Duration::days(1),
+        today,
+        today + chrono::Duration::days(1),
+    ] {
+        let day = day.format("%Y%m%d").to_string();
+        collect_mp4_names(&out_dir.join(&day), Some(&day), &mut out).await?;
+    }
+    Ok(out)
+}
+
+async fn collect_mp4_names(dir: &Path, day: Option<&str>, out: &mut Vec<String>) -> Result<()> {
+    let mut reader = match tokio::fs::read_dir(dir).await {
+        Ok(reader) => reader,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && day.is_some() => {
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = reader.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.to_ascii_lowercase().ends_with(".mp4") || !entry.file_type().await?.is_file() {
+            continue;
         }
+        out.push(match day {
+            Some(day) => format!("{day}T{name}"),
+            None => name,
+        });
     }
-    Ok(count)
+    Ok(())
 }
@@ -0,0 +1,184 @@
+use super::runtime::SourceRuntimeState;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+const SHARDS: usize = 16;
+
+struct Slot {
+    generation: u64,
+    state: SourceRuntimeState,
+}
+
+type Shard = RwLock<HashMap<String, Slot>>;
+
+#[derive(Clone, Default)]
+pub struct StateTable {
+    shards: Arc<[Shard; SHARDS]>,
+    generations: Arc<AtomicU64>,
+}
+
+impl StateTable {
+    fn shard(&self, source_id: &str) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        source_id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    fn read(shard: &Shard) -> RwLockReadGuard<'_, HashMap<String, Slot>> {
+        shard
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(shard: &Shard) -> RwLockWriteGuard<'_, HashMap<String, Slot>> {
+        shard
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn insert(&self, state: SourceRuntimeState) -> SourceState {
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed) + 1;
+        let source_id = state.source_id.clone();
+        Self::write(self.shard(&source_id)).insert(source_id.clone(), Slot { generation, state });
+        SourceState {
+            table: self.clone(),
+            source_id,
+            generation,
+        }
+    }
+
+    pub fn remove(&self, source_id: &str) {
+        Self::write(self.shard(source_id)).remove(source_id);
+    }
+
+    pub fn get(&self, source_id: &str) -> Option<SourceRuntimeState> {
+        Self::read(self.shard(source_id))
+            .get(source_id)
+            .map(|slot| slot.state.clone())
+    }
+
+    pub fn list_where(
+        &self,
+        keep: impl Fn(&SourceRuntimeState) -> bool,
+    ) -> Vec<SourceRuntimeState> {
+        let mut out = Vec::new();
+        for shard in self.shards.iter() {
+            out.extend(
+                Self::read(shard)
+                    .values()
+                    .filter(|slot| keep(&slot.state))
+                    .map(|slot| slot.state.clone()),
+            );
+        }
+        out.sort_by(|left, right| left.source_id.cmp(&right.source_id));
+        out
+    }
+
+    pub fn page_where(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+        keep: impl Fn(&SourceRuntimeState) -> bool,
+    ) -> (Vec<SourceRuntimeState>, bool) {
+        let mut out = self.list_where(|state| {
+            after.is_none_or(|after| state.source_id.as_str() > after) && keep(state)
+        });
+        let more = out.len() > limit;
+        out.truncate(limit);
+        (out, more)
+    }
+
+    pub fn count_by_state(&self) -> BTreeMap<String, usize> {
+        let mut out = BTreeMap::new();
+        for shard in self.shards.iter() {
+            for slot in Self::read(shard).values() {
+                *out.entry(slot.state.state.clone()).or_default() += 1;
+            }
+        }
+        out
+    }
+}
+
+#[derive(Clone)]
+pub struct SourceState {
+    table: StateTable,
+    source_id: String,
+    generation: u64,
+}
+
+impl SourceState {
+    pub fn read<T>(&self, read: impl FnOnce(&SourceRuntimeState) -> T) -> Option<T> {
+        StateTable::read(self.table.shard(&self.source_id))
+            .get(&self.source_id)
+            .filter(|slot| slot.generation == self.generation)
+            .map(|slot| read(&slot.state))
+    }
+
+    pub fn update<T>(&self, update: impl FnOnce(&mut SourceRuntimeState) -> T) -> Option<T> {
+        StateTable::write(self.table.shard(&self.source_id))
+            .get_mut(&self.source_id)
+            .filter(|slot| slot.generation == self.generation)
+            .map(|slot| update(&mut slot.state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime_state(source_id: &str, state: &str) -> SourceRuntimeState {
+        SourceRuntimeState {
+            source_id: source_id.to_string(),
+            state: state.to_string(),
+            restart_attempt: 0,
+            backoff_secs: 0,
+            next_retry_at: 0,
+            last_error: String::new(),
+            updated_at: 0,
+            grace_until: 0,
+            segment_started_at: 0,
+            history: Default::default(),
+            diagnostics: Default::default(),
+        }
+    }
+
+    #[test]
+    fn pages_walk_every_source_in_order() {
+        let table = StateTable::default();
+        for index in 0..50 {
+            table.insert(runtime_state(&format!("cam-{index:02}"), "running"));
+        }
+        let mut seen = Vec::new();
+        let mut after = None::<String>;
+        loop {
+            let (page, more) = table.page_where(after.as_deref(), 20, |_| true);
+            seen.extend(page.iter().map(|state| state.source_id.clone()));
+            after = page.last().map(|state| state.source_id.clone());
+            if !more {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 50);
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(table.count_by_state().get("running"), Some(&50));
+    }
+
+    #[test]
+    fn a_replaced_recorder_cannot_write_its_old_entry() {
+        let table = StateTable::default();
+        let old = table.insert(runtime_state("cam", "running"));
+        let new = table.insert(runtime_state("cam", "starting"));
+        assert_eq!(old.update(|state| state.state = "failed".into()), None);
+        assert_eq!(
+            new.read(|state| state.state.clone()).as_deref(),
+            Some("starting")
+        );
+
+        table.remove("cam");
+        assert_eq!(new.read(|state| state.state.clone()), None);
+        assert!(table.get("cam").is_none());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::config::{Config, ReplicationConfig, SharedConfig};
+use crate::storage::{Bookmark, StorageManager};
+use crate::{crypto, features, util};
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, interval, timeout};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+const MAX_SEGMENTS_PER_PASS: usize = 32;
+const SEGMENT_SCAN_LIMIT: usize = 1_000;
+const REPLY_TIMEOUT_SECS: u64 = 30;
+const CURSOR_FILE: &str = "replication.json";
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicationStatus {
+    pub state: &'static str,
+    pub partner: String,
+    pub last_attempt_unix: u64,
+    pub last_sync_unix: u64,
+    pub lag_secs: u64,
+    pub last_error: String,
+    pub pending_segments: usize,
+    pub segments_sent: u64,
+    pub bytes_sent: u64,
+}
+
+#[derive(Clone)]
+pub struct ReplicationHandle {
+    status: Arc<Mutex<ReplicationStatus>>,
+}
+
+impl ReplicationHandle {
+    fn new(partner: &str) -> Self {
+        let status = ReplicationStatus {
+            state: if partner.is_empty() {
+                "disabled"
+            } else {
+                "idle"
+            },
+            partner: partner.to_string(),
+            ..ReplicationStatus::default()
+        };
+        Self {
+            status: Arc::new(Mutex::new(status)),
+        }
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let mut status = self.status.lock().expect("replication status").clone();
+        if status.last_sync_unix > 0 {
+            status.lag_secs = util::now_unix_seconds().saturating_sub(status.last_sync_unix);
+        }
+        status
+    }
+
+    fn set(&self, apply: impl FnOnce(&mut ReplicationStatus)) {
+        apply(&mut self.status.lock().expect("replication status"));
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Cursor {
+    started_unix: u64,
+    #[serde(default)]
+    sources: BTreeMap<String, u64>,
+}
+
+impl Cursor {
+    fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+            .unwrap_or_else(|| Self {
+                started_unix: util::now_unix_seconds(),
+                sources: BTreeMap::new(),
+            })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("replace {}", path.display()))
+    }
+
+    fn pushed_until(&self, source_id: &str) -> u64 {
+        self.sources
+            .get(source_id)
+            .copied()
+            .unwrap_or(self.started_unix)
+    }
+}
+
+pub fn spawn_replicator(
+    live_cfg: SharedConfig,
+    storage: StorageManager,
+    cfg: &Config,
+) -> ReplicationHandle {
+    let partner = cfg.replication.partner.trim().to_string();
+    let handle = ReplicationHandle::new(&partner);
+    if partner.is_empty() {
+        return handle;
+    }
+    let replicator = handle.clone();
+    let cursor_path = PathBuf::from(&cfg.storage.root).join(CURSOR_FILE);
+    let period = Duration::from_secs(cfg.replication.interval_secs);
+    tokio::spawn(async move {
+        info!(partner = %partner, "replication to partner started");
+        let mut tick = interval(period);
+        loop {
+            tick.tick().await;
+            let cfg = live_cfg.snapshot();
+            replicator.set(|status| {
+                status.state = "syncing";
+                status.last_attempt_unix = util::now_unix_seconds();
+            });
+            match sync_once(&cfg, &storage, &cursor_path, &replicator).await {
+                Ok(pending) => replicator.set(|status| {
+                    status.state = "idle";
+                    status.pending_segments = pending;
+                    status.last_error.clear();
+                    if pending == 0 {
+                        status.last_sync_unix = util::now_unix_seconds();
+                    }
+                }),
+                Err(err) => {
+                    warn!(partner = %partner, error = %err, "replication pass failed");
+                    replicator.set(|status| {
+                        status.state = "failing";
+                        status.last_error = err.to_string();
+                    });
+                }
+            }
+        }
+    });
+    handle
+}
+
+async fn sync_once(
+    cfg: &Config,
+    storage: &StorageManager,
+    cursor_path: &Path,
+    handle: &ReplicationHandle,
+) -> Result<usize> {
+    let mut partner = PartnerSession::connect(cfg).await?;
+    partner
+        .request(&config_push(cfg, &storage.bookmarks()))
+        .await?;
+    if !cfg.replication.segments {
+        return Ok(0);
+    }
+
+    let mut cursor = Cursor::load(cursor_path);
+    let mut queue = Vec::new();
+    for source_id in storage.list_sources().await? {
+        let pushed_until = cursor.pushed_until(&source_id);
+        for segment in storage
+            .list_segments(&source_id, SEGMENT_SCAN_LIMIT)
+            .await?
+        {
+            if segment.name.ends_with(".cnv") && segment.start_unix > pushed_until {
+                queue.push((segment.start_unix, source_id.clone(), segment.name));
+            }
+        }
+    }
+    // Oldest first, so a cursor that has moved past a segment never skips an older one.
+    queue.sort();
+    let pending = queue.len().saturating_sub(MAX_SEGMENTS_PER_PASS);
+    let chunk_bytes = features::SEGMENT_CHUNK_BYTES
+        .min(cfg.api.max_envelope_bytes / 2)
+        .max(1);
+    for (start_unix, source_id, name) in queue.into_iter().take(MAX_SEGMENTS_PER_PASS) {
+        let sealed = storage.read_sealed_segment(&source_id, &name).await?;
+        let total = sealed.len();
+        let mut offset = 0;
+        // An empty segment still sends one chunk so the partner records it.
+        for chunk in sealed
+            .chunks(chunk_bytes)
+            .chain((total == 0).then_some(&[][..]))
+        {
+            partner
+                .request(&json!({
+                    "cmd": "replicate_segment",
+                    "originNode": cfg.node_id,
+                    "sourceId": source_id,
+                    "name": name,
+                    "offset": offset,
+                    "total": total,
+                    "data": base64::engine::general_purpose::STANDARD.encode(chunk),
+                }))
+                .await?;
+            offset += chunk.len();
+        }
+        cursor.sources.insert(source_id, start_unix);
+        cursor.save(cursor_path)?;
+        handle.set(|status| {
+            status.segments_sent += 1;
+            status.bytes_sent += total as u64;
+        });
+    }
+    Ok(pending)
+}
+
+fn config_push(cfg: &Config, bookmarks: &[Bookmark]) -> Value {
+    json!({
+        "cmd": "replicate_config",
+        "originNode": cfg.node_id,
+        "sentUnix": util::now_unix_seconds(),
+        "sources": cfg
+            .camera_devices
+            .iter()
+            .map(crate::api::history_config)
+            .collect::<Vec<_>>(),
+        "retention": {
+            "snapshotRetentionDays": cfg.storage.snapshot_retention_days,
+            "snapshotMaxBytes": cfg.storage.snapshot_max_bytes,
+        },
+        "bookmarks": bookmarks,
+    })
+}
+
+struct PartnerSession {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    key: Zeroizing<Vec<u8>>,
+}
+
+impl PartnerSession {
+    async fn connect(cfg: &Config) -> Result<Self> {
+        let replication: &ReplicationConfig = &cfg.replication;
+        let (mut socket, _) = timeout(
+            Duration::from_secs(REPLY_TIMEOUT_SECS),
+            tokio_tungstenite::connect_async(replication.partner.trim()),
+        )
+        .await
+        .context("timed out connecting to partner")?
+        .context("connect to partner")?;
+
+        let mut secret = Zeroizing::new([0u8; 32]);
+        rand::thread_rng().fill_bytes(&mut secret[..]);
+        let client_pub = PublicKey::from(&StaticSecret::from(*secret));
+        let client_key = base64::engine::general_purpose::STANDARD.encode(client_pub.as_bytes());
+        let ts = util::now_unix_seconds();
+        let proof = crypto::compute_hello_proof(
+            &replication.partner_identity_secret_hex,
+            &replication.partner_identity_id,
+            &cfg.nostr_pubkey,
+            &client_key,
+            ts,
+        )?;
+        let hello = json!({
+            "type": "hello",
+            "identityId": replication.partner_identity_id,
+            "devicePk": cfg.nostr_pubkey,
+            "clientKey": client_key,
+            "ts": ts,
+            "proof": proof,
+        });
+        socket.send(Message::Text(hello.to_string().into())).await?;
+
+        let ack = next_text(&mut socket).await?;
+        if ack.get("type").and_then(Value::as_str) != Some("hello_ack") {
+            return Err(anyhow!("partner refused the hello: {ack}"));
+        }
+        let field = |name: &str| {
+            ack.get(name)
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("partner hello_ack missing {name}"))
+        };
+        // X25519 is symmetric, so the service derivation doubles as the client derivation.
+        let context = format!(
+            "constitute-nvr:{}:{}",
+            replication.partner_identity_id,
+            field("sessionId")?
+        );
+        let (key, _) = crypto::derive_session_key(
+            &hex::encode(*secret),
+            &replication.partner_identity_secret_hex,
+            field("serverKey")?,
+            &context,
+        )?;
+        Ok(Self { socket, key })
+    }
+
+    async fn request(&mut self, command: &Value) -> Result<Value> {
+        let nonce = crypto::random_nonce_24();
+        let cipher = crypto::encrypt_payload(&self.key, &nonce, &serde_json::to_vec(command)?)?;
+        let frame = json!({
+            "type": "cipher",
+            "nonce": base64::engine::general_purpose::STANDARD.encode(nonce),
+            "data": base64::engine::general_purpose::STANDARD.encode(cipher),
+        });
+        self.socket
+            .send(Message::Text(frame.to_string().into()))
+            .await?;
+
+        let frame = next_text(&mut self.socket).await?;
+        let field = |name: &str| {
+            frame
+                .get(name)
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("partner frame missing {name}"))
+                .and_then(|value| Ok(base64::engine::general_purpose::STANDARD.decode(value)?))
+        };
+        let nonce: [u8; 24] = field("nonce")?
+            .try_into()
+            .map_err(|_| anyhow!("partner frame nonce length"))?;
+        let plain = crypto::decrypt_payload(&self.key, &nonce, &field("data")?)?;
+        let reply: Value = serde_json::from_slice(&plain)?;
+        if reply.get("ok").and_then(Value::as_bool) != Some(true) {
+            return Err(anyhow!(
+                "partner rejected {}: {}",
+                command
+                    .get("cmd")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                reply
+                    .get("error")
+                    .and_then(Value::as_str)
+                    .unwrap_or("no reason given")
+            ));
+        }
+        Ok(reply)
+    }
+}
+
+async fn next_text(socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> Result<Value> {
+    loop {
+        let frame = timeout(Duration::from_secs(REPLY_TIMEOUT_SECS), socket.next())
+            .await
+            .context("timed out waiting for partner")?
+            .ok_or_else(|| anyhow!("partner closed the session"))??;
+        match frame {
+            Message::Text(text) => return Ok(serde_json::from_str(text.as_str())?),
+            Message::Close(_) => return Err(anyhow!("partner closed the session")),
+            _ => continue,
+        }
+    }
+}
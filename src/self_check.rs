@@ -0,0 +1,223 @@
+use crate::config::NotificationSeverity;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub const HISTORY_LEN: usize = 200;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+    pub id: String,
+    pub check: String,
+    pub severity: NotificationSeverity,
+    pub source_id: Option<String>,
+    pub message: String,
+    pub since: u64,
+    pub facts: Value,
+}
+
+impl Problem {
+    pub fn new(check: &str, severity: NotificationSeverity, message: String) -> Self {
+        Self {
+            id: check.to_string(),
+            check: check.to_string(),
+            severity,
+            source_id: None,
+            message,
+            since: 0,
+            facts: Value::Null,
+        }
+    }
+
+    pub fn with_source(mut self, source_id: &str) -> Self {
+        self.id = format!("{}:{source_id}", self.check);
+        self.source_id = Some(source_id.to_string());
+        self
+    }
+
+    pub fn with_facts(mut self, facts: Value) -> Self {
+        self.facts = facts;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transition {
+    Raised,
+    Cleared,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProblemTransition {
+    pub ts: u64,
+    pub transition: Transition,
+    pub problem: Problem,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Starting,
+    Ok,
+    Degraded,
+    Failing,
+}
+
+impl HealthStatus {
+    pub fn is_ready(self) -> bool {
+        matches!(self, Self::Ok | Self::Degraded)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfCheckView {
+    pub status: HealthStatus,
+    pub checked_at: Option<u64>,
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Default)]
+struct Inner {
+    open: BTreeMap<String, Problem>,
+    history: VecDeque<ProblemTransition>,
+    checked_at: Option<u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct SelfCheck {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl SelfCheck {
+    pub async fn record(&self, found: Vec<Problem>, now: u64) -> Vec<ProblemTransition> {
+        let mut guard = self.inner.lock().await;
+        let mut next = BTreeMap::new();
+        let mut transitions = Vec::new();
+        for mut problem in found {
+            match guard.open.remove(&problem.id) {
+                Some(previous) if previous.severity == problem.severity => {
+                    problem.since = previous.since;
+                }
+                Some(previous) => {
+                    transitions.push(ProblemTransition {
+                        ts: now,
+                        transition: Transition::Cleared,
+                        problem: previous,
+                    });
+                    problem.since = now;
+                    transitions.push(ProblemTransition {
+                        ts: now,
+                        transition: Transition::Raised,
+                        problem: problem.clone(),
+                    });
+                }
+                None => {
+                    problem.since = now;
+                    transitions.push(ProblemTransition {
+                        ts: now,
+                        transition: Transition::Raised,
+                        problem: problem.clone(),
+                    });
+                }
+            }
+            next.insert(problem.id.clone(), problem);
+        }
+        for (_, problem) in std::mem::take(&mut guard.open) {
+            transitions.push(ProblemTransition {
+                ts: now,
+                transition: Transition::Cleared,
+                problem,
+            });
+        }
+        guard.open = next;
+        guard.checked_at = Some(now);
+        for transition in &transitions {
+            if guard.history.len() == HISTORY_LEN {
+                guard.history.pop_front();
+            }
+            guard.history.push_back(transition.clone());
+        }
+        transitions
+    }
+
+    pub async fn view(&self) -> SelfCheckView {
+        let guard = self.inner.lock().await;
+        let problems = guard.open.values().cloned().collect::<Vec<_>>();
+        let status = match guard.checked_at {
+            None => HealthStatus::Starting,
+            Some(_) if problems.is_empty() => HealthStatus::Ok,
+            Some(_)
+                if problems
+                    .iter()
+                    .any(|problem| problem.severity == NotificationSeverity::Critical) =>
+            {
+                HealthStatus::Failing
+            }
+            Some(_) => HealthStatus::Degraded,
+        };
+        SelfCheckView {
+            status,
+            checked_at: guard.checked_at,
+            problems,
+        }
+    }
+
+    pub async fn history(&self, limit: usize) -> Vec<ProblemTransition> {
+        let guard = self.inner.lock().await;
+        guard.history.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn transitions_follow_the_open_set() {
+        let checks = SelfCheck::default();
+        assert_eq!(checks.view().await.status, HealthStatus::Starting);
+
+        let stuck = Problem::new(
+            "recorder_stuck",
+            NotificationSeverity::Warning,
+            "stuck".to_string(),
+        )
+        .with_source("cam-a");
+        let raised = checks.record(vec![stuck.clone()], 100).await;
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].transition, Transition::Raised);
+        assert_eq!(raised[0].problem.id, "recorder_stuck:cam-a");
+        assert_eq!(checks.view().await.status, HealthStatus::Degraded);
+
+        assert!(checks.record(vec![stuck], 160).await.is_empty());
+        let view = checks.view().await;
+        assert_eq!(view.problems[0].since, 100);
+        assert_eq!(view.checked_at, Some(160));
+
+        let full = Problem::new(
+            "disk_full",
+            NotificationSeverity::Critical,
+            "full".to_string(),
+        );
+        let changed = checks.record(vec![full], 220).await;
+        assert_eq!(changed.len(), 2);
+        assert_eq!(changed[0].problem.id, "disk_full");
+        assert_eq!(changed[1].transition, Transition::Cleared);
+        assert_eq!(checks.view().await.status, HealthStatus::Failing);
+        assert!(!checks.view().await.status.is_ready());
+
+        checks.record(Vec::new(), 280).await;
+        assert_eq!(checks.view().await.status, HealthStatus::Ok);
+        let history = checks.history(10).await;
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[0].ts, 280);
+        assert_eq!(history[0].transition, Transition::Cleared);
+        assert_eq!(checks.history(1).await.len(), 1);
+    }
+}
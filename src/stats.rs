@@ -0,0 +1,507 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::time::{Duration, MissedTickBehavior, interval};
+use tracing::warn;
+
+use crate::latency::LatencyTracker;
+use crate::util;
+
+const SAMPLE_INTERVAL_SECS: u64 = 300;
+const DAY_SECS: u64 = 86_400;
+const HOUR_SECS: u64 = 3_600;
+const RING_LEN: usize = (DAY_SECS / SAMPLE_INTERVAL_SECS) as usize;
+const PERSIST_INTERVAL_SECS: u64 = 60;
+const STATS_FILE_VERSION: u32 = 1;
+const COUNTERS: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    SegmentsStarted,
+    SegmentsFinalized,
+    PlaintextBytes,
+    CiphertextBytes,
+    SegmentsDeleted,
+    BytesDeleted,
+    BytesServed,
+}
+
+impl Counter {
+    pub const ALL: [Counter; COUNTERS] = [
+        Counter::SegmentsStarted,
+        Counter::SegmentsFinalized,
+        Counter::PlaintextBytes,
+        Counter::CiphertextBytes,
+        Counter::SegmentsDeleted,
+        Counter::BytesDeleted,
+        Counter::BytesServed,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn metric_name(self) -> &'static str {
+        match self {
+            Counter::SegmentsStarted => "segments_started",
+            Counter::SegmentsFinalized => "segments_finalized",
+            Counter::PlaintextBytes => "plaintext_bytes",
+            Counter::CiphertextBytes => "ciphertext_bytes",
+            Counter::SegmentsDeleted => "segments_deleted",
+            Counter::BytesDeleted => "bytes_deleted",
+            Counter::BytesServed => "bytes_served",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Counter::SegmentsStarted => "Segments opened by the recorder.",
+            Counter::SegmentsFinalized => "Segments sealed by the encryptor.",
+            Counter::PlaintextBytes => "Plaintext segment bytes consumed by the encryptor.",
+            Counter::CiphertextBytes => "Encrypted segment bytes written by the encryptor.",
+            Counter::SegmentsDeleted => "Segments removed by retention or purge.",
+            Counter::BytesDeleted => "Segment bytes removed by retention or purge.",
+            Counter::BytesServed => "Decrypted media bytes served to clients.",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterValues {
+    pub segments_started: u64,
+    pub segments_finalized: u64,
+    pub plaintext_bytes: u64,
+    pub ciphertext_bytes: u64,
+    pub segments_deleted: u64,
+    pub bytes_deleted: u64,
+    pub bytes_served: u64,
+}
+
+impl CounterValues {
+    fn from_array(values: [u64; COUNTERS]) -> Self {
+        Self {
+            segments_started: values[Counter::SegmentsStarted.index()],
+            segments_finalized: values[Counter::SegmentsFinalized.index()],
+            plaintext_bytes: values[Counter::PlaintextBytes.index()],
+            ciphertext_bytes: values[Counter::CiphertextBytes.index()],
+            segments_deleted: values[Counter::SegmentsDeleted.index()],
+            bytes_deleted: values[Counter::BytesDeleted.index()],
+            bytes_served: values[Counter::BytesServed.index()],
+        }
+    }
+
+    fn accumulate(&mut self, other: &CounterValues) {
+        self.segments_started += other.segments_started;
+        self.segments_finalized += other.segments_finalized;
+        self.plaintext_bytes += other.plaintext_bytes;
+        self.ciphertext_bytes += other.ciphertext_bytes;
+        self.segments_deleted += other.segments_deleted;
+        self.bytes_deleted += other.bytes_deleted;
+        self.bytes_served += other.bytes_served;
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceStatsView {
+    pub source_id: String,
+    pub last_hour: CounterValues,
+    pub last_day: CounterValues,
+    pub totals: CounterValues,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsHeadline {
+    pub sources: usize,
+    pub last_hour: CounterValues,
+    pub last_day: CounterValues,
+    pub oversized_envelopes: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IngestRate {
+    pub bytes: u64,
+    pub observed_secs: u64,
+}
+
+impl IngestRate {
+    pub fn bytes_per_hour(self) -> f64 {
+        self.bytes as f64 * HOUR_SECS as f64 / self.observed_secs.max(1) as f64
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Sample {
+    start: u64,
+    values: [u64; COUNTERS],
+}
+
+#[derive(Default)]
+struct SourceStats {
+    totals: [AtomicU64; COUNTERS],
+    ring: Mutex<VecDeque<Sample>>,
+}
+
+impl SourceStats {
+    fn record(&self, counter: Counter, amount: u64, now: u64) {
+        self.totals[counter.index()].fetch_add(amount, Ordering::Relaxed);
+        let start = now - now % SAMPLE_INTERVAL_SECS;
+        let mut ring = self
+            .ring
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if ring.back().is_none_or(|sample| sample.start < start) {
+            ring.push_back(Sample {
+                start,
+                values: [0; COUNTERS],
+            });
+        }
+        while ring.len() > RING_LEN {
+            ring.pop_front();
+        }
+        if let Some(sample) = ring.back_mut() {
+            sample.values[counter.index()] += amount;
+        }
+    }
+
+    fn window(&self, now: u64, secs: u64) -> CounterValues {
+        let since = now.saturating_sub(secs);
+        let ring = self
+            .ring
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut values = [0u64; COUNTERS];
+        for sample in ring.iter().filter(|sample| sample.start >= since) {
+            for (total, value) in values.iter_mut().zip(sample.values) {
+                *total += value;
+            }
+        }
+        CounterValues::from_array(values)
+    }
+
+    fn ingest_rate(&self, now: u64) -> Option<IngestRate> {
+        let since = now.saturating_sub(DAY_SECS);
+        let ring = self
+            .ring
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut samples = ring
+            .iter()
+            .filter(|sample| sample.start >= since)
+            .peekable();
+        let oldest = samples.peek()?.start;
+        let bytes = samples
+            .map(|sample| sample.values[Counter::CiphertextBytes.index()])
+            .sum();
+        Some(IngestRate {
+            bytes,
+            observed_secs: now.saturating_sub(oldest).max(SAMPLE_INTERVAL_SECS),
+        })
+    }
+
+    fn totals(&self) -> [u64; COUNTERS] {
+        std::array::from_fn(|idx| self.totals[idx].load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedSource {
+    totals: [u64; COUNTERS],
+    samples: Vec<Sample>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedStats {
+    version: u32,
+    sources: HashMap<String, PersistedSource>,
+}
+
+#[derive(Clone, Default)]
+pub struct StatsRegistry {
+    inner: Arc<RwLock<HashMap<String, Arc<SourceStats>>>>,
+    oversized_envelopes: Arc<AtomicU64>,
+    deprecated_calls: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+    latency: LatencyTracker,
+}
+
+impl StatsRegistry {
+    pub fn record(&self, source_id: &str, counter: Counter, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        self.source(source_id)
+            .record(counter, amount, util::now_unix_seconds());
+    }
+
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.latency
+    }
+
+    pub fn record_oversized_envelope(&self) {
+        self.oversized_envelopes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_deprecated_call(&self, method: &'static str) {
+        *self
+            .deprecated_calls
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(method)
+            .or_default() += 1;
+    }
+
+    pub fn views(&self, source_id: Option<&str>) -> Vec<SourceStatsView> {
+        let now = util::now_unix_seconds();
+        let guard = self
+            .inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut out = guard
+            .iter()
+            .filter(|(id, _)| source_id.is_none_or(|wanted| wanted == id.as_str()))
+            .map(|(id, stats)| SourceStatsView {
+                source_id: id.clone(),
+                last_hour: stats.window(now, HOUR_SECS),
+                last_day: stats.window(now, DAY_SECS),
+                totals: CounterValues::from_array(stats.totals()),
+            })
+            .collect::<Vec<_>>();
+        out.sort_by(|left, right| left.source_id.cmp(&right.source_id));
+        out
+    }
+
+    pub fn ingest_rate(&self, source_id: &str) -> Option<IngestRate> {
+        let stats = self
+            .inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(source_id)
+            .cloned()?;
+        stats.ingest_rate(util::now_unix_seconds())
+    }
+
+    pub fn headline(&self) -> StatsHeadline {
+        let views = self.views(None);
+        let mut headline = StatsHeadline {
+            sources: views.len(),
+            oversized_envelopes: self.oversized_envelopes.load(Ordering::Relaxed),
+            ..StatsHeadline::default()
+        };
+        for view in &views {
+            headline.last_hour.accumulate(&view.last_hour);
+            headline.last_day.accumulate(&view.last_day);
+        }
+        headline
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let guard = self
+            .inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut sources = guard.iter().collect::<Vec<_>>();
+        sources.sort_by(|left, right| left.0.cmp(right.0));
+        let mut out = String::new();
+        for counter in Counter::ALL {
+            let name = format!("constitute_nvr_source_{}_total", counter.metric_name());
+            let _ = writeln!(out, "# HELP {name} {}", counter.help());
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (source_id, stats) in &sources {
+                let _ = writeln!(
+                    out,
+                    "{name}{{source_id=\"{}\"}} {}",
+                    escape_label(source_id),
+                    stats.totals[counter.index()].load(Ordering::Relaxed)
+                );
+            }
+        }
+        let name = "constitute_nvr_oversized_envelopes_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Session frames rejected for size before decryption."
+        );
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(
+            out,
+            "{name} {}",
+            self.oversized_envelopes.load(Ordering::Relaxed)
+        );
+        let name = "constitute_nvr_deprecated_calls_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Session commands that used a deprecated method."
+        );
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (method, calls) in self
+            .deprecated_calls
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+        {
+            let _ = writeln!(out, "{name}{{method=\"{method}\"}} {calls}");
+        }
+        out
+    }
+
+    pub fn load(path: &Path) -> Self {
+        let registry = Self::default();
+        let raw = match std::fs::read(path) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return registry,
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "failed to read persisted stats");
+                return registry;
+            }
+        };
+        let persisted = match serde_json::from_slice::<PersistedStats>(&raw) {
+            Ok(persisted) if persisted.version == STATS_FILE_VERSION => persisted,
+            Ok(_) => return registry,
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "ignoring unreadable persisted stats");
+                return registry;
+            }
+        };
+        let cutoff = util::now_unix_seconds().saturating_sub(DAY_SECS);
+        {
+            let mut guard = registry
+                .inner
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            for (source_id, source) in persisted.sources {
+                let stats = SourceStats {
+                    totals: source.totals.map(AtomicU64::new),
+                    ring: Mutex::new(
+                        source
+                            .samples
+                            .into_iter()
+                            .filter(|sample| sample.start >= cutoff)
+                            .collect(),
+                    ),
+                };
+                guard.insert(source_id, Arc::new(stats));
+            }
+        }
+        registry
+    }
+
+    pub fn persist(&self, path: &Path) -> Result<()> {
+        let persisted = {
+            let guard = self
+                .inner
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            PersistedStats {
+                version: STATS_FILE_VERSION,
+                sources: guard
+                    .iter()
+                    .map(|(id, stats)| {
+                        let samples = stats
+                            .ring
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .iter()
+                            .cloned()
+                            .collect();
+                        (
+                            id.clone(),
+                            PersistedSource {
+                                totals: stats.totals(),
+                                samples,
+                            },
+                        )
+                    })
+                    .collect(),
+            }
+        };
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&persisted)?)
+            .with_context(|| format!("write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("replace {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn start_persistence(&self, path: PathBuf) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut tick = interval(Duration::from_secs(PERSIST_INTERVAL_SECS));
+            tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                tick.tick().await;
+                let registry = this.clone();
+                let target = path.clone();
+                let result = tokio::task::spawn_blocking(move || registry.persist(&target)).await;
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => warn!(error = %err, "stats persistence failed"),
+                    Err(err) => warn!(error = %err, "stats persistence task failed"),
+                }
+            }
+        });
+    }
+
+    fn source(&self, source_id: &str) -> Arc<SourceStats> {
+        if let Some(stats) = self
+            .inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(source_id)
+        {
+            return Arc::clone(stats);
+        }
+        let mut guard = self
+            .inner
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(guard.entry(source_id.to_string()).or_default())
+    }
+}
+
+pub(crate) fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_roll_and_survive_persistence() {
+        let stats = SourceStats::default();
+        let now = 1_700_000_000;
+        stats.record(Counter::CiphertextBytes, 100, now - 2 * HOUR_SECS);
+        stats.record(Counter::CiphertextBytes, 40, now - 60);
+        stats.record(Counter::SegmentsFinalized, 1, now);
+        assert_eq!(stats.window(now, HOUR_SECS).ciphertext_bytes, 40);
+        assert_eq!(stats.window(now, DAY_SECS).ciphertext_bytes, 140);
+        assert_eq!(stats.window(now, DAY_SECS).segments_finalized, 1);
+
+        let registry = StatsRegistry::default();
+        registry.record("cam-a", Counter::BytesServed, 512);
+        registry.record("cam-a", Counter::BytesServed, 0);
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-stats-test-{}.json",
+            std::process::id()
+        ));
+        registry.persist(&path).unwrap();
+        let restored = StatsRegistry::load(&path);
+        let views = restored.views(Some("cam-a"));
+        assert_eq!(views.len(), 1);
+        assert_eq!(views[0].totals.bytes_served, 512);
+        assert_eq!(views[0].last_day.bytes_served, 512);
+        assert!(
+            restored
+                .render_prometheus()
+                .contains("constitute_nvr_source_bytes_served_total{source_id=\"cam-a\"} 512")
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,207 @@
+use serde_json::Value;
+use std::fmt::Write;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+td,th{border:1px solid #ccc;padding:4px 10px;text-align:left}\
+.bar{width:320px;height:14px;background:#eee;border:1px solid #aaa}\
+.fill{height:100%;background:#4a8}\
+.critical{color:#b00}.warning{color:#a60}";
+
+pub fn render(health: &Value, now_ms: u64) -> String {
+    let text = |key: &str| escape(health[key].as_str().unwrap_or(""));
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!doctype html><html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"30\">\
+         <title>constitute-nvr {node}</title><style>{STYLE}</style></head><body>\
+         <h1>constitute-nvr</h1><table>\
+         <tr><th>Status</th><td>{status}</td></tr>\
+         <tr><th>Node</th><td>{node}</td></tr>\
+         <tr><th>Role</th><td>{role}</td></tr>\
+         <tr><th>Provisioning</th><td>{provisioning}</td></tr>\
+         <tr><th>Version</th><td>{version} {build}</td></tr>\
+         <tr><th>Swarm peers</th><td>{peers}</td></tr></table>",
+        node = text("nodeId"),
+        status = text("status"),
+        role = text("nodeRole"),
+        provisioning = text("provisioning"),
+        version = text("version"),
+        build = text("buildHash"),
+        peers = health["swarmPeers"].as_u64().unwrap_or(0),
+    );
+
+    out.push_str("<h2>Storage</h2>");
+    match health["storageUsage"].as_object() {
+        Some(usage) => {
+            let percent = usage["usedPercent"]
+                .as_f64()
+                .unwrap_or(0.0)
+                .clamp(0.0, 100.0);
+            let _ = write!(
+                out,
+                "<div class=\"bar\"><div class=\"fill\" style=\"width:{percent:.1}%\"></div></div>\
+                 <p>{percent:.1}% used, {used} of {total}</p>",
+                used = gib(usage["usedBytes"].as_u64().unwrap_or(0)),
+                total = gib(usage["totalBytes"].as_u64().unwrap_or(0)),
+            );
+        }
+        None => out.push_str("<p>usage unavailable</p>"),
+    }
+
+    let summary = &health["sourceSummary"];
+    let _ = write!(
+        out,
+        "<h2>Cameras</h2><p>{configured} configured, {enabled} enabled, {problem} with a \
+         problem</p><table><tr><th>Source</th><th>State</th>",
+        configured = summary["configured"].as_u64().unwrap_or(0),
+        enabled = summary["enabled"].as_u64().unwrap_or(0),
+        problem = summary["problem"].as_u64().unwrap_or(0),
+    );
+    out.push_str("<th>Last segment</th><th>Error</th></tr>");
+    let runtime = health["problemSources"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    if runtime.is_empty() {
+        out.push_str("<tr><td colspan=\"4\">no camera has a problem</td></tr>");
+    }
+    for entry in &runtime {
+        let started = entry["segmentStartedAt"].as_u64().unwrap_or(0);
+        let age = if started == 0 {
+            "-".to_string()
+        } else {
+            format!("{} ago", duration(now_ms.saturating_sub(started) / 1000))
+        };
+        let _ = write!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{age}</td><td>{}</td></tr>",
+            escape(entry["sourceId"].as_str().unwrap_or("")),
+            escape(entry["state"].as_str().unwrap_or("")),
+            escape(entry["lastError"].as_str().unwrap_or("")),
+        );
+    }
+    out.push_str("</table>");
+
+    out.push_str("<h2>Features</h2><table><tr><th>Feature</th><th>Status</th>");
+    out.push_str("<th>Reason</th></tr>");
+    for capability in health["capabilities"].as_array().into_iter().flatten() {
+        let reason = &capability["reason"];
+        let detail = ["name", "key", "capability"]
+            .iter()
+            .find_map(|field| reason[*field].as_str())
+            .unwrap_or("");
+        let _ = write!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(capability["feature"].as_str().unwrap_or("")),
+            escape(capability["status"].as_str().unwrap_or("")),
+            match reason["kind"].as_str() {
+                Some(kind) => escape(&format!("{kind}: {detail}")),
+                None => String::new(),
+            },
+        );
+    }
+    out.push_str("</table>");
+
+    out.push_str("<h2>Problems</h2>");
+    let problems = health["problems"].as_array().cloned().unwrap_or_default();
+    if problems.is_empty() {
+        out.push_str("<p>none</p>");
+    } else {
+        out.push_str("<ul>");
+        for problem in &problems {
+            let severity = escape(problem["severity"].as_str().unwrap_or(""));
+            let _ = write!(
+                out,
+                "<li class=\"{severity}\">{severity}: {}</li>",
+                escape(problem["message"].as_str().unwrap_or("")),
+            );
+        }
+        out.push_str("</ul>");
+    }
+    out.push_str("<p><a href=\"/?format=json\">health JSON</a></p></body></html>");
+    out
+}
+
+fn escape(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+fn gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+}
+
+fn duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_health_fields_escaped() {
+        let health = json!({
+            "status": "degraded",
+            "nodeId": "nvr-<1>",
+            "nodeRole": "nvr",
+            "provisioning": "paired",
+            "version": "0.1.0",
+            "buildHash": "abc123",
+            "swarmPeers": 2,
+            "storageUsage": {
+                "usedPercent": 42.0,
+                "usedBytes": 1u64 << 30,
+                "totalBytes": 1u64 << 31,
+            },
+            "sourceSummary": { "configured": 2, "enabled": 2, "problem": 1 },
+            "problemSources": [{
+                "sourceId": "back",
+                "state": "backoff",
+                "lastError": "<refused>",
+                "segmentStartedAt": 1_000,
+            }],
+            "problems": [{ "severity": "warning", "message": "camera back is backoff" }],
+            "capabilities": [
+                { "feature": "recording", "status": "available" },
+                {
+                    "feature": "transcode",
+                    "status": "unavailable",
+                    "reason": { "kind": "dependency", "name": "libx264" },
+                },
+            ],
+        });
+        let page = render(&health, 91_000);
+        assert!(page.contains("nvr-&lt;1&gt;"));
+        assert!(!page.contains("nvr-<1>"));
+        assert!(page.contains("width:42.0%"));
+        assert!(page.contains("1.0 GiB of 2.0 GiB"));
+        assert!(page.contains("2 configured, 2 enabled, 1 with a problem"));
+        assert!(page.contains("<td>back</td><td>backoff</td><td>1m 30s ago</td>"));
+        assert!(page.contains("<td>&lt;refused&gt;</td>"));
+        assert!(page.contains("warning: camera back is backoff"));
+        assert!(page.contains("abc123"));
+        assert!(page.contains("<td>recording</td><td>available</td><td></td>"));
+        assert!(
+            page.contains("<td>transcode</td><td>unavailable</td><td>dependency: libx264</td>")
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use super::{StorageManager, decrypt_blob, seal_blob, snapshots::is_plain_component};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+const ATTACHMENTS_DIR: &str = "attachments";
+const UPLOADS_DIR: &str = ".uploads";
+const ATTACHMENT_FILE: &str = "attachment.json";
+const BLOB_FILE: &str = "blob.cnv";
+const PART_FILE: &str = "data.part";
+pub const UPLOAD_IDLE_SECS: u64 = 3600;
+pub const MAX_OPEN_UPLOADS: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AttachmentLimits {
+    pub max_bytes: u64,
+    pub max_count: usize,
+    pub max_total_bytes: u64,
+}
+
+impl Default for AttachmentLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            max_count: 1000,
+            max_total_bytes: 2 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentRequest {
+    pub source_id: String,
+    #[serde(default)]
+    pub at_unix: Option<u64>,
+    pub name: String,
+    #[serde(default)]
+    pub content_type: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub id: String,
+    pub source_id: String,
+    pub at_unix: u64,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content_type: String,
+    pub bytes: u64,
+    pub sha256: String,
+    pub uploaded_by: String,
+    pub created_unix: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("upload {0} is unknown or has expired")]
+    Unknown(String),
+    #[error("chunk at {offset} does not continue the {received} bytes received")]
+    OutOfOrder { offset: u64, received: u64 },
+    #[error("chunk ends at {end}, past the {bytes} bytes announced")]
+    Overrun { end: u64, bytes: u64 },
+    #[error("received {received} of the {bytes} bytes announced")]
+    Incomplete { received: u64, bytes: u64 },
+    #[error("sha256 does not match the uploaded bytes")]
+    HashMismatch,
+}
+
+impl UploadError {
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::Unknown(_) | Self::Incomplete { .. } => "uploadId",
+            Self::OutOfOrder { .. } => "offset",
+            Self::Overrun { .. } => "data",
+            Self::HashMismatch => "sha256",
+        }
+    }
+}
+
+impl StorageManager {
+    pub fn with_attachment_limits(mut self, limits: AttachmentLimits) -> Self {
+        self.attachment_limits = limits;
+        self
+    }
+
+    pub fn attachment_limits(&self) -> AttachmentLimits {
+        self.attachment_limits
+    }
+
+    pub async fn begin_attachment(
+        &self,
+        request: &AttachmentRequest,
+        uploaded_by: &str,
+    ) -> Result<Attachment> {
+        let now = crate::util::now_unix_seconds();
+        let attachment = Attachment {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            source_id: request.source_id.clone(),
+            at_unix: request.at_unix.unwrap_or(now),
+            name: request.name.clone(),
+            content_type: request.content_type.clone(),
+            bytes: request.bytes,
+            sha256: request.sha256.to_ascii_lowercase(),
+            uploaded_by: uploaded_by.to_string(),
+            created_unix: now,
+        };
+        let dir = self.uploads_dir().join(&attachment.id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+        tokio::fs::write(dir.join(PART_FILE), b"")
+            .await
+            .context("create upload part")?;
+        write_attachment_file(&dir, &attachment).await?;
+        Ok(attachment)
+    }
+
+    pub async fn open_uploads(&self, uploaded_by: &str) -> Result<usize> {
+        Ok(read_attachment_dirs(&self.uploads_dir())
+            .await?
+            .iter()
+            .filter(|(_, attachment)| attachment.uploaded_by == uploaded_by)
+            .count())
+    }
+
+    pub async fn write_attachment_chunk(
+        &self,
+        upload_id: &str,
+        uploaded_by: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u64> {
+        let (dir, attachment) = self.open_upload(upload_id, uploaded_by).await?;
+        let part = dir.join(PART_FILE);
+        let received = tokio::fs::metadata(&part)
+            .await
+            .with_context(|| format!("stat {}", part.display()))?
+            .len();
+        if offset != received {
+            return Err(UploadError::OutOfOrder { offset, received }.into());
+        }
+        let end = offset + data.len() as u64;
+        if end > attachment.bytes {
+            return Err(UploadError::Overrun {
+                end,
+                bytes: attachment.bytes,
+            }
+            .into());
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&part)
+            .await
+            .with_context(|| format!("open {}", part.display()))?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, data).await?;
+        tokio::io::AsyncWriteExt::flush(&mut file).await?;
+        Ok(end)
+    }
+
+    pub async fn finish_attachment(
+        &self,
+        upload_id: &str,
+        uploaded_by: &str,
+    ) -> Result<Attachment> {
+        let (dir, attachment) = self.open_upload(upload_id, uploaded_by).await?;
+        let plain = Zeroizing::new(
+            tokio::fs::read(dir.join(PART_FILE))
+                .await
+                .context("read upload part")?,
+        );
+        if plain.len() as u64 != attachment.bytes {
+            return Err(UploadError::Incomplete {
+                received: plain.len() as u64,
+                bytes: attachment.bytes,
+            }
+            .into());
+        }
+        if hex::encode(Sha256::digest(plain.as_slice())) != attachment.sha256 {
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+            return Err(UploadError::HashMismatch.into());
+        }
+
+        let _guard = self.attachment_lock.lock().await;
+        let target = self.attachments_dir().join(&attachment.id);
+        tokio::fs::create_dir_all(&target)
+            .await
+            .with_context(|| format!("create {}", target.display()))?;
+        let blob = seal_blob(&self.key, &plain)?;
+        tokio::fs::write(target.join(BLOB_FILE), &blob)
+            .await
+            .context("write attachment")?;
+        write_attachment_file(&target, &attachment).await?;
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        self.enforce_attachment_limits(&attachment.id).await?;
+        Ok(attachment)
+    }
+
+    pub async fn list_attachments(&self, source_id: &str, limit: usize) -> Result<Vec<Attachment>> {
+        let mut out = read_attachment_dirs(&self.attachments_dir())
+            .await?
+            .into_iter()
+            .map(|(_, attachment)| attachment)
+            .filter(|attachment| attachment.source_id.eq_ignore_ascii_case(source_id))
+            .collect::<Vec<_>>();
+        out.sort_by_key(|attachment| {
+            std::cmp::Reverse((attachment.at_unix, attachment.created_unix))
+        });
+        out.truncate(limit.max(1));
+        Ok(out)
+    }
+
+    pub async fn read_attachment(
+        &self,
+        source_id: &str,
+        id: &str,
+    ) -> Result<Option<(Attachment, Zeroizing<Vec<u8>>)>> {
+        let Some(attachment) = self.attachment(source_id, id).await else {
+            return Ok(None);
+        };
+        let dir = self.attachments_dir().join(id);
+        let blob = tokio::fs::read(dir.join(BLOB_FILE))
+            .await
+            .with_context(|| format!("read attachment {id}"))?;
+        Ok(Some((attachment, decrypt_blob(&self.key, &blob)?)))
+    }
+
+    pub(super) async fn attachment(&self, source_id: &str, id: &str) -> Option<Attachment> {
+        if !is_plain_component(id) || id.starts_with('.') {
+            return None;
+        }
+        read_attachment_file(&self.attachments_dir().join(id))
+            .await
+            .filter(|attachment| attachment.source_id.eq_ignore_ascii_case(source_id))
+    }
+
+    pub async fn collect_uploads(&self) -> Result<usize> {
+        let now = crate::util::now_unix_seconds();
+        let mut removed = 0;
+        for (dir, _) in read_attachment_dirs(&self.uploads_dir()).await? {
+            let touched = match tokio::fs::metadata(dir.join(PART_FILE)).await {
+                Ok(meta) => meta
+                    .modified()
+                    .map(crate::util::clock::unix_secs)
+                    .unwrap_or(0),
+                Err(_) => 0,
+            };
+            if now.saturating_sub(touched) >= UPLOAD_IDLE_SECS {
+                tokio::fs::remove_dir_all(&dir)
+                    .await
+                    .with_context(|| format!("remove upload {}", dir.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn enforce_attachment_limits(&self, keep: &str) -> Result<usize> {
+        let limits = self.attachment_limits;
+        let mut stored = read_attachment_dirs(&self.attachments_dir()).await?;
+        stored.sort_by_key(|(_, attachment)| attachment.created_unix);
+        let mut count = stored.len();
+        let mut total = stored.iter().map(|(_, a)| a.bytes).sum::<u64>();
+        let mut removed = 0;
+        for (dir, attachment) in stored {
+            let over_count = limits.max_count > 0 && count > limits.max_count;
+            let over_bytes = limits.max_total_bytes > 0 && total > limits.max_total_bytes;
+            if !(over_count || over_bytes) {
+                break;
+            }
+            if attachment.id == keep {
+                continue;
+            }
+            tokio::fs::remove_dir_all(&dir)
+                .await
+                .with_context(|| format!("remove attachment {}", dir.display()))?;
+            count -= 1;
+            total = total.saturating_sub(attachment.bytes);
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    async fn open_upload(
+        &self,
+        upload_id: &str,
+        uploaded_by: &str,
+    ) -> Result<(PathBuf, Attachment)> {
+        let unknown = || UploadError::Unknown(upload_id.to_string());
+        if !is_plain_component(upload_id) || upload_id.starts_with('.') {
+            return Err(unknown().into());
+        }
+        let dir = self.uploads_dir().join(upload_id);
+        match read_attachment_file(&dir).await {
+            // Another device's upload id reads as unknown.
+            Some(attachment) if attachment.uploaded_by == uploaded_by => Ok((dir, attachment)),
+            _ => Err(unknown().into()),
+        }
+    }
+
+    fn attachments_dir(&self) -> PathBuf {
+        self.root.join(ATTACHMENTS_DIR)
+    }
+
+    fn uploads_dir(&self) -> PathBuf {
+        self.attachments_dir().join(UPLOADS_DIR)
+    }
+}
+
+async fn read_attachment_file(dir: &Path) -> Option<Attachment> {
+    let raw = tokio::fs::read(dir.join(ATTACHMENT_FILE)).await.ok()?;
+    match serde_json::from_slice(&raw) {
+        Ok(attachment) => Some(attachment),
+        Err(err) => {
+            warn!(dir = %dir.display(), error = %err, "unreadable attachment.json");
+            None
+        }
+    }
+}
+
+async fn read_attachment_dirs(root: &Path) -> Result<Vec<(PathBuf, Attachment)>> {
+    let mut out = Vec::new();
+    let mut rd = match tokio::fs::read_dir(root).await {
+        Ok(rd) => rd,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+        Err(err) => return Err(err).with_context(|| format!("read_dir {}", root.display())),
+    };
+    while let Some(entry) = rd.next_entry().await? {
+        let dir = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if let Some(attachment) = read_attachment_file(&dir).await {
+            out.push((dir, attachment));
+        }
+    }
+    Ok(out)
+}
+
+async fn write_attachment_file(dir: &Path, attachment: &Attachment) -> Result<()> {
+    let tmp = dir.join(format!("{ATTACHMENT_FILE}.tmp"));
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(attachment)?)
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, dir.join(ATTACHMENT_FILE))
+        .await
+        .context("replace attachment.json")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::clock::TestClock;
+    use std::time::Duration;
+
+    fn request(data: &[u8]) -> AttachmentRequest {
+        AttachmentRequest {
+            source_id: "cam-a".to_string(),
+            at_unix: Some(1_700_000_000),
+            name: "scene.jpg".to_string(),
+            content_type: "image/jpeg".to_string(),
+            bytes: data.len() as u64,
+            sha256: hex::encode(Sha256::digest(data)),
+        }
+    }
+
+    async fn upload(storage: &StorageManager, data: &[u8]) -> Result<Attachment> {
+        let open = storage.begin_attachment(&request(data), "phone").await?;
+        for (idx, chunk) in data.chunks(4).enumerate() {
+            storage
+                .write_attachment_chunk(&open.id, "phone", idx as u64 * 4, chunk)
+                .await?;
+        }
+        storage.finish_attachment(&open.id, "phone").await
+    }
+
+    #[tokio::test]
+    async fn uploads_are_checked_sealed_and_capped() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-attachments-test-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let storage = StorageManager::new(root.clone(), &"33".repeat(32))
+            .unwrap()
+            .with_attachment_limits(AttachmentLimits {
+                max_bytes: 1024,
+                max_count: 2,
+                max_total_bytes: 0,
+            });
+        let clock = TestClock::at_secs(1_700_000_000);
+
+        let first = upload(&storage, b"first photo").await.unwrap();
+        let (stored, plain) = storage
+            .read_attachment("CAM-A", &first.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.sha256, first.sha256);
+        assert_eq!(plain.as_slice(), b"first photo");
+        let sealed = std::fs::read(root.join(ATTACHMENTS_DIR).join(&first.id).join(BLOB_FILE));
+        assert!(!sealed.unwrap().windows(5).any(|w| w == b"photo"));
+        assert!(
+            storage
+                .read_attachment("cam-b", &first.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Chunks must continue the upload, from the device that opened it.
+        let open = storage
+            .begin_attachment(&request(b"abcdef"), "phone")
+            .await
+            .unwrap();
+        let refused = |err: anyhow::Error| err.downcast::<UploadError>().unwrap().field();
+        let err = storage
+            .write_attachment_chunk(&open.id, "phone", 2, b"cd")
+            .await;
+        assert_eq!(refused(err.unwrap_err()), "offset");
+        let err = storage
+            .write_attachment_chunk(&open.id, "other", 0, b"ab")
+            .await;
+        assert_eq!(refused(err.unwrap_err()), "uploadId");
+        storage
+            .write_attachment_chunk(&open.id, "phone", 0, b"abc")
+            .await
+            .unwrap();
+        let err = storage.finish_attachment(&open.id, "phone").await;
+        assert_eq!(refused(err.unwrap_err()), "uploadId");
+        let err = storage
+            .write_attachment_chunk(&open.id, "phone", 3, b"defg")
+            .await;
+        assert_eq!(refused(err.unwrap_err()), "data");
+        storage
+            .write_attachment_chunk(&open.id, "phone", 3, b"xyz")
+            .await
+            .unwrap();
+        let err = storage.finish_attachment(&open.id, "phone").await;
+        assert_eq!(refused(err.unwrap_err()), "sha256");
+        assert_eq!(storage.open_uploads("phone").await.unwrap(), 0);
+
+        clock.advance(Duration::from_secs(1));
+        upload(&storage, b"second").await.unwrap();
+        clock.advance(Duration::from_secs(1));
+        let third = upload(&storage, b"third").await.unwrap();
+        let listed = storage.list_attachments("cam-a", 10).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().all(|attachment| attachment.id != first.id));
+        assert!(listed.iter().any(|attachment| attachment.id == third.id));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn idle_uploads_are_collected() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-attachments-idle-test-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let storage = StorageManager::new(root.clone(), &"33".repeat(32)).unwrap();
+        let open = storage
+            .begin_attachment(&request(b"abc"), "phone")
+            .await
+            .unwrap();
+        assert_eq!(storage.collect_uploads().await.unwrap(), 0);
+        assert_eq!(storage.open_uploads("phone").await.unwrap(), 1);
+
+        let later = crate::util::now_unix_seconds() + UPLOAD_IDLE_SECS;
+        let _clock = TestClock::at_secs(later);
+        assert_eq!(storage.collect_uploads().await.unwrap(), 1);
+        let err = storage
+            .finish_attachment(&open.id, "phone")
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<UploadError>().is_some());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
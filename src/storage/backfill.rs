@@ -0,0 +1,634 @@
+use super::day_index::{self, SegmentTime};
+use super::jobs::{JobHandle, JobProgress, JobStatus};
+use super::scan::{self, CancellationToken, ScanSpec};
+use super::{
+    IoClass, MAGIC, MAGIC_NAMED, StorageManager, child_dirs, chunked, layout, modified_unix,
+    name_map, open_blob,
+};
+use crate::bandwidth::RateLimiter;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+pub const BACKFILL_JOB: &str = "backfill_index";
+const CHECKPOINT_FILE: &str = "backfill_index.json";
+const DONE_FILE: &str = "backfill_index.done";
+const BATCH_SEGMENTS: u64 = 100;
+const AUTO_START_DELAY: Duration = Duration::from_secs(300);
+const AUTO_RETRY: Duration = Duration::from_secs(600);
+const AUTO_THROTTLE_MBPS: u64 = 40;
+const SEAL_OVERHEAD: u64 = 5 + 24 + 16;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackfillRequest {
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(default)]
+    pub skip_hashes: bool,
+    #[serde(default)]
+    pub throttle_mbps: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackfillReport {
+    pub scanned: u64,
+    pub indexed: u64,
+    pub hashed: u64,
+    pub already_indexed: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub resumed: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Checkpoint {
+    job_id: String,
+    request: BackfillRequest,
+    cursor: Option<(String, String)>,
+    report: BackfillReport,
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+    name: String,
+    source_dir: PathBuf,
+    path: PathBuf,
+    existing: Option<SegmentTime>,
+}
+
+impl Segment {
+    fn order(&self) -> (String, String) {
+        (self.name.clone(), super::source_dir_name(&self.source_dir))
+    }
+
+    fn complete(&self, skip_hashes: bool) -> bool {
+        self.existing
+            .as_ref()
+            .is_some_and(|time| skip_hashes || time.plaintext_sha256.is_some())
+    }
+}
+
+impl StorageManager {
+    pub fn start_backfill(&self, request: BackfillRequest) -> Result<JobStatus> {
+        let job = self.jobs.begin(BACKFILL_JOB)?;
+        let checkpoint = Checkpoint {
+            job_id: job.id().to_string(),
+            request,
+            cursor: None,
+            report: BackfillReport::default(),
+        };
+        Ok(self.spawn_backfill(job, checkpoint))
+    }
+
+    pub fn schedule_backfill(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            if !this.backfill_checkpoint_path().exists() {
+                tokio::time::sleep(AUTO_START_DELAY).await;
+            }
+            loop {
+                let pending = this.backfill_checkpoint_path().exists()
+                    || !this.root.join("jobs").join(DONE_FILE).exists();
+                if this.cancel.is_cancelled() || !pending {
+                    return;
+                }
+                match this.resume_or_start_backfill() {
+                    Ok(status) => {
+                        info!(job_id = %status.job_id, "backfilling the segment index");
+                        return;
+                    }
+                    Err(err) => debug!(error = %err, "index backfill not started yet"),
+                }
+                tokio::time::sleep(AUTO_RETRY).await;
+            }
+        });
+    }
+
+    fn resume_or_start_backfill(&self) -> Result<JobStatus> {
+        let path = self.backfill_checkpoint_path();
+        let checkpoint = match std::fs::read(&path) {
+            Ok(raw) => serde_json::from_slice::<Checkpoint>(&raw)
+                .inspect_err(|err| {
+                    warn!(
+                        path = %path.display(),
+                        error = %err,
+                        "discarding unreadable checkpoint"
+                    );
+                    let _ = std::fs::remove_file(&path);
+                })
+                .ok(),
+            Err(_) => None,
+        };
+        let Some(checkpoint) = checkpoint else {
+            return self.start_backfill(BackfillRequest {
+                source_id: None,
+                skip_hashes: false,
+                throttle_mbps: Some(AUTO_THROTTLE_MBPS),
+            });
+        };
+        let job = self
+            .jobs
+            .begin_with_id(BACKFILL_JOB, checkpoint.job_id.clone())?;
+        info!(
+            job_id = %checkpoint.job_id,
+            done = checkpoint.report.scanned,
+            "resuming index backfill"
+        );
+        Ok(self.spawn_backfill(job, checkpoint))
+    }
+
+    fn backfill_checkpoint_path(&self) -> PathBuf {
+        self.root.join("jobs").join(CHECKPOINT_FILE)
+    }
+
+    fn spawn_backfill(&self, job: JobHandle, checkpoint: Checkpoint) -> JobStatus {
+        let this = self.clone();
+        let everything = checkpoint.request.source_id.is_none();
+        job.spawn(move |progress| async move {
+            let result = this.run_backfill(&progress, checkpoint).await;
+            // A shutdown keeps the checkpoint so the next start resumes; `cancel_job` does not.
+            if !this.cancel.is_cancelled() {
+                let _ = tokio::fs::remove_file(this.backfill_checkpoint_path()).await;
+            }
+            if everything && result.is_ok() {
+                let done = this.root.join("jobs").join(DONE_FILE);
+                if let Err(err) = tokio::fs::write(&done, b"").await {
+                    warn!(path = %done.display(), error = %err, "backfill marker not written");
+                }
+            }
+            result
+        })
+    }
+
+    async fn run_backfill(
+        &self,
+        job: &JobProgress,
+        mut checkpoint: Checkpoint,
+    ) -> Result<BackfillReport> {
+        let root = self.root.join("segments");
+        let scope = checkpoint
+            .request
+            .source_id
+            .as_deref()
+            .map(|source_id| root.join(crate::util::source_dir_name(source_id)));
+        job.phase("scanning");
+        let (segments, skipped) = {
+            let key = self.key.clone();
+            let lock = Arc::clone(&self.name_map_lock);
+            let cancel = job.cancel_token().clone();
+            tokio::task::spawn_blocking(move || {
+                let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                collect_segments(&root, scope.as_deref(), &key, &cancel)
+            })
+            .await
+            .context("join index backfill scan")??
+            .ok_or_else(|| anyhow!("index backfill cancelled"))?
+        };
+        let total = segments.len() as u64;
+        let skip_hashes = checkpoint.request.skip_hashes;
+        let limiter = RateLimiter::new(
+            checkpoint
+                .request
+                .throttle_mbps
+                .unwrap_or(0)
+                .saturating_mul(125_000),
+        );
+        checkpoint.report.resumed = checkpoint.cursor.is_some();
+        checkpoint.report.skipped = skipped;
+        let checkpoint_path = self.backfill_checkpoint_path();
+        save_checkpoint(&checkpoint_path, &checkpoint).await?;
+        job.phase("indexing");
+        job.progress(checkpoint.report.scanned, total);
+
+        let mut pending = Vec::new();
+        for segment in segments {
+            if job.is_cancelled() {
+                self.write_batch(std::mem::take(&mut pending)).await?;
+                save_checkpoint(&checkpoint_path, &checkpoint).await?;
+                return Err(anyhow!("index backfill cancelled"));
+            }
+            let order = segment.order();
+            if checkpoint
+                .cursor
+                .as_ref()
+                .is_some_and(|cursor| order <= *cursor)
+            {
+                continue;
+            }
+            let report = &mut checkpoint.report;
+            if segment.complete(skip_hashes) {
+                report.already_indexed += 1;
+            } else {
+                let key = self.key.clone();
+                let hash = !skip_hashes;
+                let work = segment.clone();
+                let outcome =
+                    tokio::task::spawn_blocking(move || backfill_segment(&work, &key, hash))
+                        .await
+                        .context("join segment backfill")?;
+                match outcome {
+                    Ok(Some((time, read))) => {
+                        limiter.acquire(read).await;
+                        self.io.acquire(IoClass::Background, read).await;
+                        report.hashed += u64::from(hash);
+                        report.indexed += 1;
+                        pending.push((segment, time));
+                    }
+                    Ok(None) => report.skipped += 1,
+                    Err(err) => {
+                        warn!(segment = %segment.path.display(), error = %err, "segment not backfilled");
+                        report.failed += 1;
+                    }
+                }
+            }
+            report.scanned += 1;
+            checkpoint.cursor = Some(order);
+            job.progress(checkpoint.report.scanned, total);
+            if checkpoint.report.scanned.is_multiple_of(BATCH_SEGMENTS) {
+                self.write_batch(std::mem::take(&mut pending)).await?;
+                save_checkpoint(&checkpoint_path, &checkpoint).await?;
+            }
+        }
+        self.write_batch(pending).await?;
+        Ok(checkpoint.report)
+    }
+
+    async fn write_batch(&self, batch: Vec<(Segment, SegmentTime)>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut by_source = BTreeMap::<PathBuf, Vec<(String, SegmentTime)>>::new();
+        for (segment, time) in batch {
+            by_source
+                .entry(segment.source_dir)
+                .or_default()
+                .push((segment.name, time));
+        }
+        let key = self.key.clone();
+        let lock = Arc::clone(&self.name_map_lock);
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for (source_dir, records) in by_source {
+                write_records(&source_dir, &key, records)?;
+            }
+            Ok(())
+        })
+        .await
+        .context("join index backfill write")?
+    }
+
+    pub(super) async fn complete_record(&self, source_id: &str, name: &str, plain: &[u8]) {
+        let dir = self.segments_dir(source_id);
+        let record = {
+            let (dir, key, name) = (dir.clone(), self.key.clone(), name.to_string());
+            tokio::task::spawn_blocking(move || read_record(&dir, &key, &name)).await
+        };
+        let Ok(Ok(Some(mut time))) = record else {
+            return;
+        };
+        if !time.complete(plain) {
+            return;
+        }
+        let key = self.key.clone();
+        let lock = Arc::clone(&self.name_map_lock);
+        let records = vec![(name.to_string(), time)];
+        let written = tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            write_records(&dir, &key, records)
+        })
+        .await;
+        if let Ok(Err(err)) = written {
+            warn!(source_id, segment = name, error = %err, "segment record not completed");
+        }
+    }
+}
+
+fn collect_segments(
+    root: &Path,
+    scope: Option<&Path>,
+    key: &[u8],
+    cancel: &CancellationToken,
+) -> Result<Option<(Vec<Segment>, u64)>> {
+    let source_dirs = match scope {
+        Some(scope) if scope.is_dir() => vec![scope.to_path_buf()],
+        Some(_) => Vec::new(),
+        None if root.is_dir() => child_dirs(root)?,
+        None => Vec::new(),
+    };
+    let spec = ScanSpec {
+        max_depth: 2,
+        ..scan::SEGMENT_FILES
+    };
+    let mut out = Vec::new();
+    let mut skipped = 0;
+    for source_dir in source_dirs {
+        let Some(files) = scan::collect_files(&source_dir, spec, cancel) else {
+            return Ok(None);
+        };
+        let map = if name_map::has_map(&source_dir) {
+            Some(name_map::load(&source_dir, key)?)
+        } else {
+            None
+        };
+        let mut indexes = BTreeMap::new();
+        for path in files {
+            let Some((_, flat)) = layout::locate(&path) else {
+                continue;
+            };
+            let stem = flat.trim_end_matches(".cnv");
+            if let Some(entry) = map.as_ref().and_then(|map| map.get(stem)) {
+                out.push(Segment {
+                    name: entry.name.clone(),
+                    source_dir: source_dir.clone(),
+                    path,
+                    existing: entry.time.clone(),
+                });
+                continue;
+            }
+            let day_dir = path
+                .parent()
+                .filter(|parent| *parent != source_dir)
+                .map(Path::to_path_buf);
+            let migrated = map.as_ref().is_some_and(|map| map.contains_name(&flat));
+            let Some(day_dir) = day_dir.filter(|_| !migrated) else {
+                // Legacy flat files, unmapped opaque names, and originals an interrupted
+                // name migration left behind have no record to write.
+                skipped += 1;
+                continue;
+            };
+            let index = indexes
+                .entry(day_dir.clone())
+                .or_insert_with(|| day_index::load(&day_dir));
+            out.push(Segment {
+                existing: index.entries.get(&flat).cloned(),
+                name: flat,
+                source_dir: source_dir.clone(),
+                path,
+            });
+        }
+    }
+    out.sort_by_key(Segment::order);
+    Ok(Some((out, skipped)))
+}
+
+fn backfill_segment(
+    segment: &Segment,
+    key: &[u8],
+    hash: bool,
+) -> Result<Option<(SegmentTime, u64)>> {
+    let mut time = match &segment.existing {
+        Some(time) => time.clone(),
+        None => {
+            let len = std::fs::metadata(&segment.path)
+                .with_context(|| format!("stat {}", segment.path.display()))?
+                .len();
+            let plaintext_bytes = sealed_plaintext_len(&segment.path, len, &segment.name)?;
+            let mtime = modified_unix(&segment.path);
+            match SegmentTime::estimate(&segment.name, mtime, plaintext_bytes) {
+                Some(time) => time,
+                None => return Ok(None),
+            }
+        }
+    };
+    if !hash {
+        return Ok(Some((time, 0)));
+    }
+    let blob = std::fs::read(&segment.path)
+        .with_context(|| format!("read segment {}", segment.path.display()))?;
+    let (_, plain) = open_blob(key, &blob)?;
+    time.complete(&plain);
+    Ok(Some((time, blob.len() as u64)))
+}
+
+pub(super) fn sealed_plaintext_len(path: &Path, len: u64, name: &str) -> Result<Option<u64>> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut magic = [0u8; 5];
+    file.read_exact(&mut magic)
+        .with_context(|| format!("read header of {}", path.display()))?;
+    if chunked::is_chunked(&magic) {
+        let mut head = magic.to_vec();
+        file.take((chunked::MAX_PREFIX - magic.len()) as u64)
+            .read_to_end(&mut head)
+            .with_context(|| format!("read header of {}", path.display()))?;
+        return Ok(chunked::Layout::parse(&head)
+            .ok()
+            .and_then(|layout| layout.plaintext_len(len)));
+    }
+    let header = if magic.as_slice() == MAGIC {
+        0
+    } else if magic.as_slice() == MAGIC_NAMED {
+        2 + name.len() as u64
+    } else {
+        return Ok(None);
+    };
+    Ok(len.checked_sub(SEAL_OVERHEAD + header))
+}
+
+fn read_record(source_dir: &Path, key: &[u8], name: &str) -> Result<Option<SegmentTime>> {
+    if name_map::has_map(source_dir) {
+        let map = name_map::load(source_dir, key)?;
+        if let Some(entry) = map.entry_for(name) {
+            return Ok(entry.time.clone());
+        }
+    }
+    Ok(layout::split_name(name)
+        .and_then(|(day, _)| day_index::load(&source_dir.join(day)).entries.remove(name)))
+}
+
+fn write_records(source_dir: &Path, key: &[u8], records: Vec<(String, SegmentTime)>) -> Result<()> {
+    let mut map = if name_map::has_map(source_dir) {
+        Some(name_map::load(source_dir, key)?)
+    } else {
+        None
+    };
+    let mut map_changed = false;
+    let mut days = BTreeMap::<PathBuf, Vec<(String, SegmentTime)>>::new();
+    for (name, time) in records {
+        if let Some(map) = map.as_mut()
+            && let Some(opaque) = map.opaque_for(&name).map(str::to_string)
+        {
+            if source_dir.join(format!("{opaque}.cnv")).exists() && map.set_time(&opaque, time) {
+                map_changed = true;
+            }
+            continue;
+        }
+        if let Some(day_dir) = layout::dated_path(source_dir, &name)
+            .filter(|path| path.exists())
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        {
+            days.entry(day_dir).or_default().push((name, time));
+        }
+    }
+    if let Some(map) = map.filter(|_| map_changed) {
+        name_map::save(source_dir, key, &map)?;
+    }
+    for (day_dir, records) in days {
+        let mut index = day_index::load(&day_dir);
+        for (name, time) in records {
+            index.insert(name, time);
+        }
+        day_index::save(&day_dir, &index)?;
+    }
+    Ok(())
+}
+
+async fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(checkpoint)?)
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::jobs::JobState;
+    use crate::storage::name_map::{NameMap, NameMapEntry};
+    use crate::storage::{seal_blob, seal_named_blob};
+    use sha2::{Digest, Sha256};
+
+    async fn finished(storage: &StorageManager, job_id: &str) -> JobStatus {
+        loop {
+            let status = storage.jobs.get(job_id).unwrap();
+            if status.state != JobState::Running {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn backfill_resumes_and_completes_records_lazily() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-backfill-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let key_hex = "88".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        let day = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&day).unwrap();
+        // mvhd version 0: timescale 1000, duration 10s.
+        let mp4_box = |kind: &[u8; 4], payload: &[u8]| {
+            let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+            out.extend_from_slice(kind);
+            out.extend_from_slice(payload);
+            out
+        };
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&10_000u32.to_be_bytes());
+        let movie = mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd));
+        std::fs::write(day.join("000000.cnv"), seal_blob(&key, b"first").unwrap()).unwrap();
+        std::fs::write(day.join("000010.cnv"), seal_blob(&key, &movie).unwrap()).unwrap();
+        // An opaque segment mapped before the index, and a legacy flat one.
+        let opaque_dir = root.join("segments").join("cam-b");
+        std::fs::create_dir_all(&opaque_dir).unwrap();
+        let name = "20231231T235950.cnv";
+        std::fs::write(
+            opaque_dir.join("0a1b.cnv"),
+            seal_named_blob(&key, name, b"named").unwrap(),
+        )
+        .unwrap();
+        let mut map = NameMap::default();
+        map.insert(
+            "0a1b".to_string(),
+            NameMapEntry {
+                name: name.to_string(),
+                source_id: "cam-b".to_string(),
+                start_unix: None,
+                modified_unix: 0,
+                time: None,
+            },
+        );
+        name_map::save(&opaque_dir, &key, &map).unwrap();
+        std::fs::write(
+            opaque_dir.join("20231230T000000.cnv"),
+            seal_blob(&key, b"flat").unwrap(),
+        )
+        .unwrap();
+        let storage = StorageManager::new(root.clone(), &key_hex).unwrap();
+
+        // A run interrupted after its first segment.
+        save_checkpoint(
+            &storage.backfill_checkpoint_path(),
+            &Checkpoint {
+                job_id: "resumed-backfill".to_string(),
+                request: BackfillRequest::default(),
+                cursor: Some((name.to_string(), "cam-b".to_string())),
+                report: BackfillReport {
+                    scanned: 1,
+                    ..BackfillReport::default()
+                },
+            },
+        )
+        .await
+        .unwrap();
+        storage.resume_or_start_backfill().unwrap();
+        let status = finished(&storage, "resumed-backfill").await;
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(status.report["scanned"], 3);
+        assert_eq!(status.report["indexed"], 2);
+        assert_eq!(status.report["hashed"], 2);
+        assert_eq!(status.report["skipped"], 1);
+        assert_eq!(status.report["resumed"], true);
+        assert!(!storage.backfill_checkpoint_path().exists());
+        assert!(root.join("jobs").join(DONE_FILE).exists());
+        // The opaque segment sorted first and was done before the interruption.
+        assert!(read_record(&opaque_dir, &key, name).unwrap().is_none());
+
+        let index = day_index::load(&day);
+        let movie_time = &index.entries["20240101T000010.cnv"];
+        assert!(movie_time.backfilled);
+        assert_eq!(movie_time.duration_ms, Some(10_000));
+        assert_eq!(movie_time.end_unix, movie_time.start_unix + 10);
+        assert_eq!(movie_time.plaintext_bytes, Some(movie.len() as u64));
+        assert_eq!(
+            movie_time.plaintext_sha256.as_deref(),
+            Some(hex::encode(Sha256::digest(&movie)).as_str())
+        );
+        let first = &index.entries["20240101T000000.cnv"];
+        assert_eq!(first.start_unix + 10, movie_time.start_unix);
+
+        // Without hashes a record has its size from the blob length, and a read adds the hash.
+        let job = storage
+            .start_backfill(BackfillRequest {
+                source_id: Some("cam-b".to_string()),
+                skip_hashes: true,
+                throttle_mbps: None,
+            })
+            .unwrap();
+        let status = finished(&storage, &job.job_id).await;
+        assert_eq!(status.report["indexed"], 1);
+        assert_eq!(status.report["hashed"], 0);
+        let named = read_record(&opaque_dir, &key, name).unwrap().unwrap();
+        assert_eq!(named.plaintext_bytes, Some(5));
+        assert_eq!(named.plaintext_sha256, None);
+        storage.read_segment("cam-b", name).await.unwrap();
+        let named = read_record(&opaque_dir, &key, name).unwrap().unwrap();
+        assert_eq!(
+            named.plaintext_sha256.as_deref(),
+            Some(hex::encode(Sha256::digest(b"named")).as_str())
+        );
+        let listed = storage.list_segments("cam-a", 10).await.unwrap();
+        assert!(listed.iter().all(|entry| entry.plaintext_sha256.is_some()));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,74 @@
+use super::{SegmentEntry, StorageManager};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub owner: String,
+    pub source_id: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub release_unix: u64,
+}
+
+impl Bookmark {
+    fn active(&self, now: u64) -> bool {
+        self.release_unix == 0 || now < self.release_unix
+    }
+
+    fn holds(&self, source_id: &str, from_unix: u64, to_unix: u64, now: u64) -> bool {
+        self.active(now)
+            && self.source_id.eq_ignore_ascii_case(source_id)
+            && self.from_unix <= to_unix
+            && self.to_unix >= from_unix
+    }
+}
+
+impl StorageManager {
+    pub(super) fn set_bookmarks(&self, owner: &str, bookmarks: Vec<Bookmark>) {
+        let mut all = self
+            .bookmarks
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if bookmarks.is_empty() {
+            all.remove(owner);
+        } else {
+            all.insert(owner.to_string(), bookmarks);
+        }
+    }
+
+    pub(super) fn bookmarked(&self, source_id: &str, from_unix: u64, to_unix: u64) -> bool {
+        let now = crate::util::now_unix_seconds();
+        self.bookmarks
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+            .flatten()
+            .any(|bookmark| bookmark.holds(source_id, from_unix, to_unix, now))
+    }
+
+    pub(super) fn held(&self, source_id: &str, entry: &SegmentEntry) -> bool {
+        entry.protected || self.bookmarked(source_id, entry.start_unix, entry.end_unix)
+    }
+
+    pub fn bookmarks(&self) -> Vec<Bookmark> {
+        let now = crate::util::now_unix_seconds();
+        let mut out = self
+            .bookmarks
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+            .flatten()
+            .filter(|bookmark| bookmark.active(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        out.sort_by(|left, right| {
+            (&left.owner, left.from_unix, &left.source_id).cmp(&(
+                &right.owner,
+                right.from_unix,
+                &right.source_id,
+            ))
+        });
+        out
+    }
+}
@@ -0,0 +1,730 @@
+use super::{Plaintext, StorageError};
+use crate::crypto;
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use zeroize::Zeroizing;
+
+const MAGIC_V2: &[u8] = b"CNRV2";
+const MAGIC_C2: &[u8] = b"CNRC2";
+pub(super) const CHUNK_BYTES: usize = 1024 * 1024;
+const MAX_CHUNK_BYTES: u32 = 16 * 1024 * 1024;
+pub(super) const STREAM_CHUNK_BYTES: usize = 48 * 1024;
+const NONCE: usize = 24;
+const NONCE_PREFIX: usize = 16;
+const KEY_ID: usize = 8;
+const TAG: usize = 16;
+const LAST: u64 = 1 << 63;
+const KEY_ID_CONTEXT: &[u8] = b"constitute-nvr segment key id";
+const PREFIX_V2: usize = MAGIC_V2.len() + KEY_ID + 4 + NONCE_PREFIX + 2;
+const PREFIX_C2: usize = MAGIC_C2.len() + NONCE + 2;
+pub(super) const MAX_PREFIX: usize = PREFIX_V2;
+
+pub(super) fn is_chunked(blob: &[u8]) -> bool {
+    blob.starts_with(MAGIC_V2) || blob.starts_with(MAGIC_C2)
+}
+
+fn key_id(key: &[u8]) -> [u8; KEY_ID] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(KEY_ID_CONTEXT);
+    let mut id = [0u8; KEY_ID];
+    id.copy_from_slice(&mac.finalize().into_bytes()[..KEY_ID]);
+    id
+}
+
+fn message_nonce(base: &[u8; NONCE], counter: u64) -> [u8; NONCE] {
+    let mut nonce = *base;
+    for (byte, counter) in nonce[NONCE - 8..].iter_mut().zip(counter.to_be_bytes()) {
+        *byte ^= counter;
+    }
+    nonce
+}
+
+fn chunk_counter(index: u64, last: bool) -> u64 {
+    if last { (index + 1) | LAST } else { index + 1 }
+}
+
+pub(super) struct Layout {
+    aad: Vec<u8>,
+    key_id: Option<[u8; KEY_ID]>,
+    base: [u8; NONCE],
+    chunk_bytes: u64,
+    prefix_len: usize,
+    header_len: usize,
+}
+
+impl Layout {
+    pub(super) fn parse(head: &[u8]) -> Result<Self, StorageError> {
+        let corrupt = |reason: &str| StorageError::Corrupt(reason.to_string());
+        let short = || corrupt("chunked blob too short");
+        let header_len = |prefix_len: usize| {
+            let len = head.get(prefix_len - 2..prefix_len).ok_or_else(short)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            if len < 2 + TAG {
+                return Err(corrupt("chunked blob header truncated"));
+            }
+            Ok(len)
+        };
+        let mut base = [0u8; NONCE];
+        if head.starts_with(MAGIC_C2) {
+            let header_len = header_len(PREFIX_C2)?;
+            base.copy_from_slice(&head[MAGIC_C2.len()..MAGIC_C2.len() + NONCE]);
+            return Ok(Self {
+                aad: Vec::new(),
+                key_id: None,
+                base,
+                chunk_bytes: STREAM_CHUNK_BYTES as u64,
+                prefix_len: PREFIX_C2,
+                header_len,
+            });
+        }
+        if !head.starts_with(MAGIC_V2) {
+            return Err(corrupt("invalid chunked blob magic"));
+        }
+        let header_len = header_len(PREFIX_V2)?;
+        let mut at = MAGIC_V2.len();
+        let mut key_id = [0u8; KEY_ID];
+        key_id.copy_from_slice(&head[at..at + KEY_ID]);
+        at += KEY_ID;
+        let chunk_bytes = u32::from_be_bytes([head[at], head[at + 1], head[at + 2], head[at + 3]]);
+        if chunk_bytes == 0 || chunk_bytes > MAX_CHUNK_BYTES {
+            return Err(corrupt("chunked blob chunk size out of range"));
+        }
+        at += 4;
+        base[..NONCE_PREFIX].copy_from_slice(&head[at..at + NONCE_PREFIX]);
+        Ok(Self {
+            aad: head[..PREFIX_V2].to_vec(),
+            key_id: Some(key_id),
+            base,
+            chunk_bytes: u64::from(chunk_bytes),
+            prefix_len: PREFIX_V2,
+            header_len,
+        })
+    }
+
+    fn body_start(&self) -> u64 {
+        (self.prefix_len + self.header_len) as u64
+    }
+
+    fn sealed_chunk_bytes(&self) -> u64 {
+        self.chunk_bytes + TAG as u64
+    }
+
+    fn chunk_count(&self, body: u64) -> u64 {
+        body.div_ceil(self.sealed_chunk_bytes()).max(1)
+    }
+
+    pub(super) fn plaintext_len(&self, len: u64) -> Option<u64> {
+        let body = len.checked_sub(self.body_start())?;
+        body.checked_sub(self.chunk_count(body) * TAG as u64)
+    }
+
+    fn seal_message(&self, key: &[u8], counter: u64, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce = message_nonce(&self.base, counter);
+        Ok(crypto::encrypt_payload_with_aad(
+            key, &nonce, &self.aad, plain,
+        )?)
+    }
+
+    fn open_message(
+        &self,
+        key: &[u8],
+        counter: u64,
+        sealed: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+        let nonce = message_nonce(&self.base, counter);
+        Ok(Zeroizing::new(crypto::decrypt_payload_with_aad(
+            key, &nonce, &self.aad, sealed,
+        )?))
+    }
+
+    fn open_header(&self, key: &[u8], sealed: &[u8]) -> Result<Option<String>, StorageError> {
+        if self.key_id.is_some_and(|id| id != key_id(key)) {
+            return Err(StorageError::WrongKey);
+        }
+        let header = self.open_message(key, 0, sealed)?;
+        let corrupt = || StorageError::Corrupt("chunked blob header truncated".to_string());
+        let name_len = header.get(..2).ok_or_else(corrupt)?;
+        let name_len = u16::from_be_bytes([name_len[0], name_len[1]]) as usize;
+        let name = header.get(2..2 + name_len).ok_or_else(corrupt)?;
+        if name.is_empty() {
+            return Ok(None);
+        }
+        String::from_utf8(name.to_vec())
+            .map(Some)
+            .map_err(|_| StorageError::Corrupt("chunked blob name is not utf-8".to_string()))
+    }
+}
+
+pub(super) fn seal(key: &[u8], name: Option<&str>, plain: &[u8]) -> Result<Vec<u8>> {
+    seal_with_chunk_bytes(key, name, plain, CHUNK_BYTES)
+}
+
+fn seal_with_chunk_bytes(
+    key: &[u8],
+    name: Option<&str>,
+    plain: &[u8],
+    chunk_bytes: usize,
+) -> Result<Vec<u8>> {
+    let name = name.unwrap_or_default();
+    let name_len = u16::try_from(name.len()).map_err(|_| anyhow!("segment name too long"))?;
+    let header_len =
+        u16::try_from(2 + name.len() + TAG).map_err(|_| anyhow!("segment name too long"))?;
+    let nonce_prefix = crypto::random_nonce_24();
+    let mut prefix = Vec::with_capacity(PREFIX_V2);
+    prefix.extend_from_slice(MAGIC_V2);
+    prefix.extend_from_slice(&key_id(key));
+    prefix.extend_from_slice(&(chunk_bytes as u32).to_be_bytes());
+    prefix.extend_from_slice(&nonce_prefix[..NONCE_PREFIX]);
+    prefix.extend_from_slice(&header_len.to_be_bytes());
+    let layout = Layout::parse(&prefix)?;
+
+    let mut header = Vec::with_capacity(2 + name.len());
+    header.extend_from_slice(&name_len.to_be_bytes());
+    header.extend_from_slice(name.as_bytes());
+    let count = plain.len().div_ceil(chunk_bytes).max(1);
+    let mut out = Vec::with_capacity(PREFIX_V2 + header_len as usize + plain.len() + count * TAG);
+    out.extend_from_slice(&prefix);
+    out.extend_from_slice(&layout.seal_message(key, 0, &header)?);
+    for index in 0..count {
+        let from = (index * chunk_bytes).min(plain.len());
+        let to = (from + chunk_bytes).min(plain.len());
+        let counter = chunk_counter(index as u64, index + 1 == count);
+        out.extend_from_slice(&layout.seal_message(key, counter, &plain[from..to])?);
+    }
+    Ok(out)
+}
+
+pub(super) fn open(
+    key: &[u8],
+    blob: &[u8],
+) -> Result<(Option<String>, Zeroizing<Vec<u8>>), StorageError> {
+    let corrupt = |reason: &str| StorageError::Corrupt(reason.to_string());
+    let layout = Layout::parse(blob)?;
+    let body_start = layout.body_start() as usize;
+    let sealed = blob
+        .get(layout.prefix_len..body_start)
+        .ok_or_else(|| corrupt("chunked blob header truncated"))?;
+    let name = layout.open_header(key, sealed)?;
+    let len = layout
+        .plaintext_len(blob.len() as u64)
+        .ok_or_else(|| corrupt("chunked blob truncated"))?;
+    let mut plain = Zeroizing::new(Vec::with_capacity(len as usize));
+    let pieces = blob[body_start..]
+        .chunks(layout.sealed_chunk_bytes() as usize)
+        .collect::<Vec<_>>();
+    let count = pieces.len().max(1);
+    for index in 0..count {
+        let piece = pieces.get(index).copied().unwrap_or_default();
+        let counter = chunk_counter(index as u64, index + 1 == count);
+        plain.extend_from_slice(&layout.open_message(key, counter, piece)?);
+    }
+    Ok((name, plain))
+}
+
+pub(super) fn read_name(
+    file: &mut std::fs::File,
+    head: &[u8],
+    key: &[u8],
+) -> Result<Option<String>, StorageError> {
+    use std::io::{Read, Seek};
+
+    let layout = Layout::parse(head)?;
+    let mut sealed = vec![0u8; layout.header_len];
+    file.seek(std::io::SeekFrom::Start(layout.prefix_len as u64))
+        .and_then(|_| file.read_exact(&mut sealed))
+        .map_err(|err| StorageError::Io {
+            context: "read chunked blob header".to_string(),
+            source: err,
+        })?;
+    layout.open_header(key, &sealed)
+}
+
+pub(super) struct ChunkReader {
+    file: tokio::fs::File,
+    path: PathBuf,
+    key: Zeroizing<Vec<u8>>,
+    layout: Layout,
+    body_len: u64,
+    chunks: u64,
+    plaintext_len: u64,
+    position: u64,
+    file_chunk: u64,
+    open: Option<(u64, Zeroizing<Vec<u8>>)>,
+    done: bool,
+}
+
+impl ChunkReader {
+    pub(super) async fn open(
+        mut file: tokio::fs::File,
+        path: PathBuf,
+        key: Zeroizing<Vec<u8>>,
+    ) -> Result<Self, StorageError> {
+        let io = |err| StorageError::Io {
+            context: format!("read segment {}", path.display()),
+            source: err,
+        };
+        let len = file.metadata().await.map_err(io)?.len();
+        let mut magic = [0u8; 5];
+        file.read_exact(&mut magic).await.map_err(io)?;
+        let prefix_len = if magic.as_slice() == MAGIC_C2 {
+            PREFIX_C2
+        } else {
+            PREFIX_V2
+        };
+        let mut prefix = vec![0u8; prefix_len];
+        prefix[..magic.len()].copy_from_slice(&magic);
+        file.read_exact(&mut prefix[magic.len()..])
+            .await
+            .map_err(io)?;
+        let layout = Layout::parse(&prefix)?;
+        let mut sealed = vec![0u8; layout.header_len];
+        file.read_exact(&mut sealed).await.map_err(io)?;
+        layout.open_header(&key, &sealed)?;
+        let plaintext_len = layout
+            .plaintext_len(len)
+            .ok_or_else(|| StorageError::Corrupt("chunked blob truncated".to_string()))?;
+        let body_len = len - layout.body_start();
+        Ok(Self {
+            file,
+            path,
+            key,
+            chunks: layout.chunk_count(body_len),
+            layout,
+            body_len,
+            plaintext_len,
+            position: 0,
+            file_chunk: 0,
+            open: None,
+            done: false,
+        })
+    }
+
+    pub(super) fn plaintext_len(&self) -> u64 {
+        self.plaintext_len
+    }
+
+    async fn read_chunk(&mut self, index: u64) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+        let sealed_bytes = self.layout.sealed_chunk_bytes();
+        let from = index * sealed_bytes;
+        if self.file_chunk != index {
+            self.file
+                .seek(std::io::SeekFrom::Start(self.layout.body_start() + from))
+                .await
+                .map_err(|err| StorageError::Io {
+                    context: format!("seek segment {}", self.path.display()),
+                    source: err,
+                })?;
+        }
+        let take = sealed_bytes.min(self.body_len - from);
+        let mut sealed = vec![0u8; take as usize];
+        self.file
+            .read_exact(&mut sealed)
+            .await
+            .map_err(|err| StorageError::Io {
+                context: format!("read segment {}", self.path.display()),
+                source: err,
+            })?;
+        self.file_chunk = index + 1;
+        let counter = chunk_counter(index, index + 1 == self.chunks);
+        self.layout.open_message(&self.key, counter, &sealed)
+    }
+
+    pub(super) async fn next(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, StorageError> {
+        if self.done {
+            return Ok(None);
+        }
+        let end = (self.position + STREAM_CHUNK_BYTES as u64).min(self.plaintext_len);
+        let mut piece = Zeroizing::new(Vec::with_capacity((end - self.position) as usize));
+        loop {
+            let index = (self.position / self.layout.chunk_bytes).min(self.chunks - 1);
+            if self.open.as_ref().is_none_or(|(open, _)| *open != index) {
+                // Let go of the previous chunk before opening the next.
+                self.open = None;
+                self.open = Some((index, self.read_chunk(index).await?));
+            }
+            let media = &self.open.as_ref().expect("chunk opened above").1;
+            let from =
+                ((self.position - index * self.layout.chunk_bytes) as usize).min(media.len());
+            let take = (media.len() - from).min((end - self.position) as usize);
+            piece.extend_from_slice(&media[from..from + take]);
+            self.position += take as u64;
+            if self.position >= end || take == 0 {
+                break;
+            }
+        }
+        self.done = self.position >= self.plaintext_len;
+        Ok(Some(piece))
+    }
+
+    fn skip(&mut self, bytes: u64) {
+        self.position = self.position.saturating_add(bytes).min(self.plaintext_len);
+        self.done |= bytes > 0 && self.position >= self.plaintext_len;
+    }
+}
+
+pub struct SegmentStream {
+    inner: StreamInner,
+    trim: usize,
+    left: u64,
+}
+
+enum StreamInner {
+    Chunked(ChunkReader),
+    Plain {
+        file: tokio::fs::File,
+        path: PathBuf,
+        len: u64,
+        remaining: u64,
+    },
+    Buffered {
+        data: Plaintext,
+        offset: usize,
+    },
+}
+
+impl SegmentStream {
+    pub(super) fn chunked(reader: ChunkReader) -> Self {
+        Self {
+            inner: StreamInner::Chunked(reader),
+            trim: 0,
+            left: u64::MAX,
+        }
+    }
+
+    pub(super) fn plain(file: tokio::fs::File, path: PathBuf, len: u64) -> Self {
+        Self {
+            inner: StreamInner::Plain {
+                file,
+                path,
+                len,
+                remaining: len,
+            },
+            trim: 0,
+            left: u64::MAX,
+        }
+    }
+
+    pub(super) fn buffered(data: Plaintext) -> Self {
+        Self {
+            inner: StreamInner::Buffered { data, offset: 0 },
+            trim: 0,
+            left: u64::MAX,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.inner {
+            StreamInner::Chunked(reader) => reader.plaintext_len(),
+            StreamInner::Plain { len, .. } => *len,
+            StreamInner::Buffered { data, .. } => data.len() as u64,
+        }
+    }
+
+    pub async fn next(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, StorageError> {
+        while self.left > 0 {
+            let Some(mut chunk) = self.next_chunk().await? else {
+                return Ok(None);
+            };
+            let trim = self.trim.min(chunk.len());
+            chunk.drain(..trim);
+            self.trim -= trim;
+            let keep = self.left.min(chunk.len() as u64) as usize;
+            chunk.truncate(keep);
+            if !chunk.is_empty() {
+                self.left -= chunk.len() as u64;
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn next_chunk(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, StorageError> {
+        let chunk = match &mut self.inner {
+            StreamInner::Chunked(reader) => reader.next().await?,
+            StreamInner::Plain {
+                file,
+                path,
+                remaining,
+                ..
+            } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                let take = (*remaining).min(STREAM_CHUNK_BYTES as u64);
+                let mut chunk = Zeroizing::new(vec![0u8; take as usize]);
+                file.read_exact(&mut chunk)
+                    .await
+                    .map_err(|err| StorageError::Io {
+                        context: format!("read segment {}", path.display()),
+                        source: err,
+                    })?;
+                *remaining -= take;
+                Some(chunk)
+            }
+            StreamInner::Buffered { data, offset } => {
+                let take = (data.len() - *offset).min(STREAM_CHUNK_BYTES);
+                let chunk = Zeroizing::new(data[*offset..*offset + take].to_vec());
+                *offset += take;
+                Some(chunk)
+            }
+        };
+        Ok(chunk.filter(|chunk| !chunk.is_empty()))
+    }
+
+    pub async fn skip_chunks(&mut self, count: u64) -> Result<(), StorageError> {
+        let bytes = count.saturating_mul(STREAM_CHUNK_BYTES as u64);
+        match &mut self.inner {
+            StreamInner::Chunked(reader) => reader.skip(bytes),
+            StreamInner::Plain {
+                file,
+                path,
+                remaining,
+                ..
+            } => {
+                let take = bytes.min(*remaining);
+                file.seek(std::io::SeekFrom::Current(take as i64))
+                    .await
+                    .map_err(|err| StorageError::Io {
+                        context: format!("seek segment {}", path.display()),
+                        source: err,
+                    })?;
+                *remaining -= take;
+            }
+            StreamInner::Buffered { data, offset } => {
+                *offset += (bytes.min((data.len() - *offset) as u64)) as usize;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn restrict(&mut self, offset: u64, length: u64) -> Result<(), StorageError> {
+        if let StreamInner::Chunked(reader) = &mut self.inner {
+            // Sealed chunks need not line up with pieces, so start at the offset itself.
+            reader.skip(offset);
+        } else {
+            let chunk_bytes = STREAM_CHUNK_BYTES as u64;
+            self.skip_chunks(offset / chunk_bytes).await?;
+            self.trim = (offset % chunk_bytes) as usize;
+        }
+        self.left = length.min(self.len().saturating_sub(offset));
+        Ok(())
+    }
+
+    pub async fn read_range(
+        &mut self,
+        offset: u64,
+        length: u64,
+    ) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+        self.restrict(offset, length).await?;
+        let mut range = Zeroizing::new(Vec::with_capacity(self.left as usize));
+        while let Some(chunk) = self.next().await? {
+            range.extend_from_slice(&chunk);
+        }
+        Ok(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seal_c2(key: &[u8], plain: &[u8]) -> Vec<u8> {
+        let base = crypto::random_nonce_24();
+        let header = crypto::encrypt_payload(key, &message_nonce(&base, 0), &[0, 0]).unwrap();
+        let mut out = MAGIC_C2.to_vec();
+        out.extend_from_slice(&base);
+        out.extend_from_slice(&(header.len() as u16).to_be_bytes());
+        out.extend_from_slice(&header);
+        let pieces = plain.chunks(STREAM_CHUNK_BYTES).collect::<Vec<_>>();
+        let count = pieces.len().max(1);
+        for index in 0..count {
+            let nonce = message_nonce(&base, chunk_counter(index as u64, index + 1 == count));
+            let piece = pieces.get(index).copied().unwrap_or_default();
+            out.extend_from_slice(&crypto::encrypt_payload(key, &nonce, piece).unwrap());
+        }
+        out
+    }
+
+    fn media(len: usize) -> Vec<u8> {
+        (0..len).map(|idx| (idx % 251) as u8).collect()
+    }
+
+    async fn reader(path: &std::path::Path, key: &[u8]) -> ChunkReader {
+        let file = tokio::fs::File::open(path).await.unwrap();
+        ChunkReader::open(file, path.to_path_buf(), Zeroizing::new(key.to_vec()))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn chunked_blobs_roundtrip_and_refuse_tampering() {
+        let key = vec![9u8; 32];
+        for len in [0, 1, 4096, 2 * 4096 + 7] {
+            let plain = media(len);
+            let blob = seal_with_chunk_bytes(&key, None, &plain, 4096).unwrap();
+            let layout = Layout::parse(&blob).unwrap();
+            assert_eq!(layout.plaintext_len(blob.len() as u64), Some(len as u64));
+            let (name, opened) = open(&key, &blob).unwrap();
+            assert_eq!((name, opened.as_slice()), (None, plain.as_slice()));
+        }
+
+        let plain = media(CHUNK_BYTES + 7);
+        let blob = seal(&key, Some("20240101T000000.cnv"), &plain).unwrap();
+        assert!(blob.starts_with(MAGIC_V2));
+        let layout = Layout::parse(&blob).unwrap();
+        assert_eq!(layout.chunk_bytes, CHUNK_BYTES as u64);
+        assert_eq!(
+            layout.chunk_count(blob.len() as u64 - layout.body_start()),
+            2
+        );
+        let (name, opened) = open(&key, &blob).unwrap();
+        assert_eq!(name.as_deref(), Some("20240101T000000.cnv"));
+        assert_eq!(opened.as_slice(), plain.as_slice());
+        // Cut after a whole chunk: the new last chunk was not sealed as the last.
+        let cut = layout.body_start() as usize + CHUNK_BYTES + TAG;
+        assert!(matches!(
+            open(&key, &blob[..cut]),
+            Err(StorageError::WrongKey)
+        ));
+        // Another key is told apart by the key id.
+        assert_ne!(key_id(&key), key_id(&[1u8; 32]));
+        assert!(matches!(
+            open(&[1u8; 32], &blob),
+            Err(StorageError::WrongKey)
+        ));
+
+        // The earlier layout still opens.
+        let plain = media(2 * STREAM_CHUNK_BYTES + 7);
+        let blob = seal_c2(&key, &plain);
+        assert!(is_chunked(&blob));
+        let (name, opened) = open(&key, &blob).unwrap();
+        assert_eq!((name, opened.as_slice()), (None, plain.as_slice()));
+    }
+
+    #[test]
+    fn each_chunk_is_bound_to_its_place_in_the_blob() {
+        let key = vec![9u8; 32];
+        let chunk_bytes = 4096;
+        let plain = media(3 * chunk_bytes + 7);
+        let blob = seal_with_chunk_bytes(&key, None, &plain, chunk_bytes).unwrap();
+        let body = Layout::parse(&blob).unwrap().body_start() as usize;
+        let sealed = chunk_bytes + TAG;
+
+        // One flipped byte in any clear field, the sealed header, or any chunk, the short
+        // last one included.
+        let mut offsets = (0..PREFIX_V2).collect::<Vec<_>>();
+        offsets.push(PREFIX_V2);
+        offsets.extend((0..4).map(|index| body + index * sealed));
+        offsets.push(blob.len() - 1);
+        for offset in offsets {
+            let mut damaged = blob.clone();
+            damaged[offset] ^= 1;
+            assert!(
+                open(&key, &damaged).is_err(),
+                "flip at {offset} went unnoticed"
+            );
+        }
+
+        // Two whole chunks swapped: each is sealed under its own counter.
+        let mut swapped = blob.clone();
+        let (first, second) = swapped[body..body + 2 * sealed].split_at_mut(sealed);
+        first.swap_with_slice(second);
+        assert!(open(&key, &swapped).is_err());
+
+        // Cut inside a chunk, and with a whole middle chunk dropped.
+        assert!(open(&key, &blob[..body + sealed + 100]).is_err());
+        let mut dropped = blob[..body + sealed].to_vec();
+        dropped.extend_from_slice(&blob[body + 2 * sealed..]);
+        assert!(open(&key, &dropped).is_err());
+
+        // A header declaring no chunk size, or an outsized one, is refused unread.
+        for chunk_bytes in [0, MAX_CHUNK_BYTES + 1] {
+            let mut damaged = blob.clone();
+            let at = MAGIC_V2.len() + KEY_ID;
+            damaged[at..at + 4].copy_from_slice(&chunk_bytes.to_be_bytes());
+            assert!(matches!(
+                open(&key, &damaged),
+                Err(StorageError::Corrupt(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_skip_to_a_chunk_without_opening_the_ones_before() {
+        let key = vec![9u8; 32];
+        // Chunks of one and a half stream pieces, so some pieces span two chunks.
+        let chunk_bytes = STREAM_CHUNK_BYTES + STREAM_CHUNK_BYTES / 2;
+        let plain = media(5 * STREAM_CHUNK_BYTES + 7);
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-chunked-skip-test-{}.cnv",
+            std::process::id()
+        ));
+        let c2_path = path.with_extension("c2.cnv");
+        let mut blob = seal_with_chunk_bytes(&key, None, &plain, chunk_bytes).unwrap();
+        let mut c2 = seal_c2(&key, &plain);
+        // Damage the first chunk: skipping must not open it.
+        for blob in [&mut blob, &mut c2] {
+            let body = Layout::parse(blob).unwrap().body_start() as usize;
+            blob[body] ^= 1;
+        }
+        std::fs::write(&path, &blob).unwrap();
+        std::fs::write(&c2_path, &c2).unwrap();
+
+        let streams = [
+            SegmentStream::chunked(reader(&path, &key).await),
+            SegmentStream::chunked(reader(&c2_path, &key).await),
+            SegmentStream::buffered(std::sync::Arc::new(Zeroizing::new(plain.clone()))),
+        ];
+        for mut stream in streams {
+            assert_eq!(stream.len(), plain.len() as u64);
+            stream.skip_chunks(2).await.unwrap();
+            let mut rest = Vec::new();
+            while let Some(chunk) = stream.next().await.unwrap() {
+                // Whole pieces but for the last, so piece counts stay media offsets.
+                assert!(
+                    chunk.len() == STREAM_CHUNK_BYTES
+                        || rest.len() + chunk.len() == 3 * STREAM_CHUNK_BYTES + 7
+                );
+                rest.extend_from_slice(&chunk);
+            }
+            assert_eq!(rest, plain[2 * STREAM_CHUNK_BYTES..]);
+            stream.skip_chunks(5).await.unwrap();
+            assert!(stream.next().await.unwrap().is_none());
+        }
+
+        // A range opens only the chunks it covers, so one clear of the damaged chunk reads.
+        let start = chunk_bytes as u64 + 100;
+        for (offset, length) in [
+            (start, STREAM_CHUNK_BYTES as u64),
+            (start, chunk_bytes as u64),
+            (start, u64::MAX),
+        ] {
+            let end = offset.saturating_add(length).min(plain.len() as u64) as usize;
+            let range = SegmentStream::chunked(reader(&path, &key).await)
+                .read_range(offset, length)
+                .await
+                .unwrap();
+            assert_eq!(range.as_slice(), &plain[offset as usize..end]);
+        }
+        assert!(
+            SegmentStream::chunked(reader(&path, &key).await)
+                .read_range(10, 10)
+                .await
+                .is_err()
+        );
+        let mut buffered =
+            SegmentStream::buffered(std::sync::Arc::new(Zeroizing::new(plain.clone())));
+        assert!(
+            buffered
+                .read_range(plain.len() as u64, 1)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&c2_path);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::util::clock::unix_secs;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+const STEP_THRESHOLD_SECS: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockStep {
+    pub detected_unix: u64,
+    pub step_secs: i64,
+    pub since_unix: u64,
+}
+
+impl ClockStep {
+    fn before_unix(&self) -> u64 {
+        u64::try_from(self.detected_unix as i64 - self.step_secs).unwrap_or(0)
+    }
+
+    pub fn correct(&self, unix: u64) -> Option<u64> {
+        (self.step_secs > 0 && unix >= self.since_unix && unix < self.before_unix())
+            .then(|| unix + self.step_secs.unsigned_abs())
+    }
+}
+
+struct Inner {
+    since_unix: u64,
+    last: (SystemTime, Instant),
+    steps: Vec<ClockStep>,
+}
+
+#[derive(Clone)]
+pub struct ClockWatch {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for ClockWatch {
+    fn default() -> Self {
+        let now = SystemTime::now();
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                since_unix: unix_secs(now),
+                last: (now, Instant::now()),
+                steps: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl ClockWatch {
+    pub fn observe(&self) -> Option<ClockStep> {
+        self.observe_at(SystemTime::now(), Instant::now())
+    }
+
+    fn observe_at(&self, wall: SystemTime, mono: Instant) -> Option<ClockStep> {
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (last_wall, last_mono) = std::mem::replace(&mut inner.last, (wall, mono));
+        let wall_secs = match wall.duration_since(last_wall) {
+            Ok(elapsed) => elapsed.as_secs_f64(),
+            Err(err) => -err.duration().as_secs_f64(),
+        };
+        let skew = wall_secs - mono.saturating_duration_since(last_mono).as_secs_f64();
+        if skew.abs() < STEP_THRESHOLD_SECS {
+            return None;
+        }
+        let step = ClockStep {
+            detected_unix: unix_secs(wall),
+            step_secs: skew.round() as i64,
+            since_unix: inner.since_unix,
+        };
+        // Later steps compare against this run's start as the stepped clock reads it.
+        inner.since_unix = u64::try_from(inner.since_unix as i64 + step.step_secs).unwrap_or(0);
+        inner.steps.push(step);
+        Some(step)
+    }
+
+    pub fn correct(&self, unix: u64) -> (u64, i64) {
+        let inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let corrected = inner
+            .steps
+            .iter()
+            .fold(unix, |time, step| step.correct(time).unwrap_or(time));
+        (corrected, corrected as i64 - unix as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn forward_steps_correct_earlier_readings_of_this_run() {
+        let watch = ClockWatch::default();
+        let start = SystemTime::now();
+        let mono = Instant::now();
+        watch.observe_at(start, mono);
+        assert!(
+            watch
+                .observe_at(
+                    start + Duration::from_secs(10),
+                    mono + Duration::from_secs(10)
+                )
+                .is_none()
+        );
+
+        // NTP moves the clock an hour ahead between two ticks 10s apart.
+        let step = watch
+            .observe_at(
+                start + Duration::from_secs(3620),
+                mono + Duration::from_secs(20),
+            )
+            .unwrap();
+        assert_eq!(step.step_secs, 3600);
+        let since = step.since_unix;
+        assert!(since <= unix_secs(start));
+        assert_eq!(watch.correct(since + 15), (since + 3615, 3600));
+        // Readings taken after the step, or before this run started, are left alone.
+        assert_eq!(watch.correct(since + 3625), (since + 3625, 0));
+        assert_eq!(watch.correct(since - 60), (since - 60, 0));
+
+        let back = watch
+            .observe_at(
+                start + Duration::from_secs(3530),
+                mono + Duration::from_secs(30),
+            )
+            .unwrap();
+        assert_eq!(back.step_secs, -100);
+        assert_eq!(back.correct(since + 3600), None);
+    }
+}
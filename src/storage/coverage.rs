@@ -0,0 +1,93 @@
+use super::{SegmentEntry, StorageManager};
+use crate::recording::intent::{self, CoverageGap, IntentMarker, RECORDING_INTENT_TAG};
+use anyhow::Result;
+
+const UNINDEXED_MAX_SECS: u64 = 3_600;
+
+fn covered_span(entry: &SegmentEntry) -> (u64, u64) {
+    if entry.start_unix < entry.end_unix {
+        return (entry.start_unix, entry.end_unix);
+    }
+    match crate::util::clock::local_stamp_unix(&entry.name) {
+        Some(start) if start <= entry.end_unix && entry.end_unix - start <= UNINDEXED_MAX_SECS => {
+            (start, entry.end_unix)
+        }
+        _ => (entry.start_unix, entry.end_unix),
+    }
+}
+
+impl StorageManager {
+    pub async fn coverage_gaps(
+        &self,
+        source_id: &str,
+        from_unix: u64,
+        to_unix: u64,
+        min_gap_secs: u64,
+    ) -> Result<Vec<CoverageGap>> {
+        let spans = self
+            .list_segments(source_id, usize::MAX)
+            .await?
+            .iter()
+            .map(covered_span)
+            .filter(|(start, end)| *start <= to_unix && *end >= from_unix)
+            .collect::<Vec<_>>();
+        let mut markers = intent::read_markers(&self.segments_dir(source_id)).await;
+        let source = source_id.to_string();
+        let events = tokio::task::spawn_blocking(move || {
+            crate::logging_surface::read_recent_events(RECORDING_INTENT_TAG, 0)
+                .into_iter()
+                .filter(|event| event.safe_facts["sourceId"] == source.as_str())
+                .filter_map(|event| IntentMarker::from_event(&event.safe_facts))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        markers.extend(events);
+        let to_unix = to_unix.min(crate::util::now_unix_seconds());
+        Ok(intent::coverage_gaps(
+            &spans,
+            from_unix,
+            to_unix,
+            min_gap_secs,
+            &markers,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::clock::local_stamp;
+
+    fn entry(name: String, start_unix: u64, end_unix: u64) -> SegmentEntry {
+        SegmentEntry {
+            name,
+            bytes: 1,
+            modified_unix: end_unix,
+            start_unix,
+            end_unix,
+            plaintext_bytes: None,
+            duration_ms: None,
+            plaintext_sha256: None,
+            protected: false,
+        }
+    }
+
+    #[test]
+    fn unindexed_segments_run_from_their_named_start() {
+        let start = 1_800_000_000;
+        let name = |unix| format!("{}.mp4", local_stamp(unix).unwrap());
+
+        // Indexed times win over the name.
+        let indexed = entry(name(start), start + 5, start + 65);
+        assert_eq!(covered_span(&indexed), (start + 5, start + 65));
+        // A segment still being recorded spans its name to its last write.
+        let open = entry(name(start), start + 40, start + 40);
+        assert_eq!(covered_span(&open), (start, start + 40));
+        // A name after the mtime, or far before it, is a clock step: only the mtime counts.
+        let ahead = entry(name(start + 600), start + 40, start + 40);
+        assert_eq!(covered_span(&ahead), (start + 40, start + 40));
+        let stale = entry(name(start), start + 7_200, start + 7_200);
+        assert_eq!(covered_span(&stale), (start + 7_200, start + 7_200));
+    }
+}
@@ -0,0 +1,310 @@
+use super::clock::{ClockStep, ClockWatch};
+use crate::latency::{MAX_NAME_SKEW_SECS, Unreliable};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::warn;
+
+pub(super) const INDEX_FILE: &str = ".index.json";
+const MAX_ESTIMATED_SECS: u64 = 3600;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct DayIndex {
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub utc_offset_secs: i32,
+    #[serde(default)]
+    pub entries: BTreeMap<String, SegmentTime>,
+}
+
+impl DayIndex {
+    pub fn insert(&mut self, name: String, time: SegmentTime) {
+        self.timezone = local_timezone();
+        self.utc_offset_secs = time.utc_offset_secs;
+        self.entries.insert(name, time);
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct SegmentTime {
+    pub start_unix: u64,
+    pub end_unix: u64,
+    pub duration_ms: Option<u64>,
+    pub utc_offset_secs: i32,
+    pub indexed_unix: u64,
+    #[serde(default)]
+    pub clock_correction_secs: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plaintext_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plaintext_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backfilled: bool,
+}
+
+impl SegmentTime {
+    pub fn probe(mtime_unix: u64, plain: &[u8], clock: &ClockWatch) -> Self {
+        let (end_unix, clock_correction_secs) = clock.correct(mtime_unix);
+        let duration_ms = mp4_duration_ms(plain);
+        Self {
+            start_unix: end_unix.saturating_sub(duration_ms.unwrap_or(0) / 1000),
+            end_unix,
+            duration_ms,
+            utc_offset_secs: chrono::Local::now().offset().local_minus_utc(),
+            indexed_unix: crate::util::now_unix_seconds(),
+            clock_correction_secs,
+            plaintext_bytes: Some(plain.len() as u64),
+            plaintext_sha256: Some(plaintext_sha256(plain)),
+            backfilled: false,
+        }
+    }
+
+    pub fn estimate(name: &str, mtime_unix: u64, plaintext_bytes: Option<u64>) -> Option<Self> {
+        let named = crate::util::clock::parse_local_stamp(name)?;
+        let start_unix = u64::try_from(named.timestamp()).ok()?;
+        // The encryptor sealed the file within a pass of the segment closing; a later mtime
+        // means the file was copied or rewritten since, and says nothing about the end.
+        let end_unix = if (start_unix..=start_unix + MAX_ESTIMATED_SECS).contains(&mtime_unix) {
+            mtime_unix
+        } else {
+            start_unix
+        };
+        Some(Self {
+            start_unix,
+            end_unix,
+            duration_ms: None,
+            utc_offset_secs: named.offset().local_minus_utc(),
+            indexed_unix: crate::util::now_unix_seconds(),
+            clock_correction_secs: 0,
+            plaintext_bytes,
+            plaintext_sha256: None,
+            backfilled: true,
+        })
+    }
+
+    pub fn complete(&mut self, plain: &[u8]) -> bool {
+        let mut changed = false;
+        if self.plaintext_sha256.is_none() {
+            self.plaintext_sha256 = Some(plaintext_sha256(plain));
+            changed = true;
+        }
+        if self.plaintext_bytes.is_none() {
+            self.plaintext_bytes = Some(plain.len() as u64);
+            changed = true;
+        }
+        if self.backfilled
+            && self.duration_ms.is_none()
+            && let Some(duration_ms) = mp4_duration_ms(plain)
+        {
+            self.duration_ms = Some(duration_ms);
+            self.end_unix = self.start_unix + duration_ms / 1000;
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn apply_step(&mut self, step: &ClockStep) -> bool {
+        let Some(indexed_unix) = step.correct(self.indexed_unix) else {
+            return false;
+        };
+        let shift = step.step_secs.unsigned_abs();
+        self.start_unix += shift;
+        self.end_unix += shift;
+        self.indexed_unix = indexed_unix;
+        self.clock_correction_secs += step.step_secs;
+        true
+    }
+
+    pub fn footage_end_ms(&self, name: &str) -> Result<u64, Unreliable> {
+        let duration_ms = self.duration_ms.ok_or(Unreliable::NoDuration)?;
+        if self.clock_correction_secs != 0 {
+            return Err(Unreliable::ClockStep);
+        }
+        if self
+            .name_skew_secs(name)
+            .is_some_and(|skew| skew.unsigned_abs() > MAX_NAME_SKEW_SECS)
+        {
+            return Err(Unreliable::NameSkew);
+        }
+        Ok(self.start_unix * 1000 + duration_ms)
+    }
+
+    pub fn name_skew_secs(&self, name: &str) -> Option<i64> {
+        self.duration_ms?;
+        // The name read as UTC is the local time plus the offset it was written in.
+        let named = crate::util::clock::parse_stamp(name, &chrono::Utc)?.timestamp()
+            - i64::from(self.utc_offset_secs);
+        Some(self.start_unix as i64 - named)
+    }
+}
+
+pub(super) fn load(day_dir: &Path) -> DayIndex {
+    let path = day_dir.join(INDEX_FILE);
+    let Ok(raw) = std::fs::read(&path) else {
+        return DayIndex::default();
+    };
+    serde_json::from_slice(&raw).unwrap_or_else(|err| {
+        warn!(path = %path.display(), error = %err, "unreadable segment index; starting over");
+        DayIndex::default()
+    })
+}
+
+pub(super) fn save(day_dir: &Path, index: &DayIndex) -> Result<()> {
+    let tmp = day_dir.join(format!("{INDEX_FILE}.tmp"));
+    std::fs::write(&tmp, serde_json::to_vec_pretty(index)?)
+        .with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, day_dir.join(INDEX_FILE)).context("replace segment index")?;
+    Ok(())
+}
+
+pub(super) fn forget(source_dir: &Path, names: &[String]) -> Result<()> {
+    let mut by_day = BTreeMap::<&str, Vec<&String>>::new();
+    for name in names {
+        if let Some((day, _)) = super::layout::split_name(name) {
+            by_day.entry(day).or_default().push(name);
+        }
+    }
+    for (day, names) in by_day {
+        let day_dir = source_dir.join(day);
+        let mut index = load(&day_dir);
+        let before = index.entries.len();
+        for name in names {
+            index.entries.remove(name);
+        }
+        if index.entries.len() != before {
+            save(&day_dir, &index)?;
+        }
+    }
+    Ok(())
+}
+
+fn plaintext_sha256(plain: &[u8]) -> String {
+    hex::encode(Sha256::digest(plain))
+}
+
+pub fn mp4_duration_ms(data: &[u8]) -> Option<u64> {
+    let moov = find_box(data, b"moov")?;
+    let mvhd = find_box(moov, b"mvhd")?;
+    let (timescale, duration) = match mvhd.first()? {
+        1 => (be_u32(mvhd, 20)?, be_u64(mvhd, 24)?),
+        _ => (be_u32(mvhd, 12)?, u64::from(be_u32(mvhd, 16)?)),
+    };
+    (timescale > 0).then(|| duration.saturating_mul(1000) / u64::from(timescale))
+}
+
+fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 0usize;
+    while offset + 8 <= data.len() {
+        let size = be_u32(data, offset)? as usize;
+        let (header, size) = match size {
+            0 => (8, data.len() - offset),
+            1 => (16, usize::try_from(be_u64(data, offset + 8)?).ok()?),
+            size => (8, size),
+        };
+        if size < header || offset.checked_add(size)? > data.len() {
+            return None;
+        }
+        if &data[offset + 4..offset + 8] == kind {
+            return Some(&data[offset + header..offset + size]);
+        }
+        offset += size;
+    }
+    None
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn local_timezone() -> Option<String> {
+    if let Ok(tz) = std::env::var("TZ") {
+        let tz = tz.trim_start_matches(':').trim();
+        if !tz.is_empty() {
+            return Some(tz.to_string());
+        }
+    }
+    if let Ok(target) = std::fs::read_link("/etc/localtime") {
+        let target = target.to_string_lossy();
+        if let Some((_, zone)) = target.split_once("zoneinfo/") {
+            return Some(zone.to_string());
+        }
+    }
+    std::fs::read_to_string("/etc/timezone")
+        .ok()
+        .map(|zone| zone.trim().to_string())
+        .filter(|zone| !zone.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn duration_comes_from_the_movie_header() {
+        let mut mvhd = vec![0u8; 4 + 8];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&10_500u32.to_be_bytes());
+        mvhd.extend_from_slice(&[0u8; 80]);
+        let mut file = mp4_box(b"ftyp", b"isom");
+        file.extend(mp4_box(b"mdat", &[7u8; 64]));
+        file.extend(mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)));
+        assert_eq!(mp4_duration_ms(&file), Some(10_500));
+        assert_eq!(mp4_duration_ms(&file[..file.len() - 10]), None);
+        assert_eq!(mp4_duration_ms(b"not an mp4"), None);
+    }
+
+    #[test]
+    fn skew_reads_the_name_in_its_indexed_offset() {
+        let time = SegmentTime {
+            // 2026-03-29T01:59:50Z, named 03:59:50 in UTC+2.
+            start_unix: 1_774_749_590,
+            end_unix: 1_774_749_600,
+            duration_ms: Some(10_000),
+            utc_offset_secs: 7200,
+            indexed_unix: 1_774_749_605,
+            clock_correction_secs: 0,
+            plaintext_bytes: None,
+            plaintext_sha256: None,
+            backfilled: false,
+        };
+        assert_eq!(time.name_skew_secs("20260329T035950.cnv"), Some(0));
+        assert_eq!(
+            time.name_skew_secs("19700101T000512.cnv"),
+            Some(1_774_756_478)
+        );
+        assert_eq!(time.name_skew_secs("clip.cnv"), None);
+
+        let name = "20260329T035950.cnv";
+        assert_eq!(time.footage_end_ms(name), Ok(1_774_749_600_000));
+        assert_eq!(
+            time.footage_end_ms("20260329T034950.cnv"),
+            Err(Unreliable::NameSkew)
+        );
+        let stepped = SegmentTime {
+            clock_correction_secs: 3600,
+            ..time.clone()
+        };
+        assert_eq!(stepped.footage_end_ms(name), Err(Unreliable::ClockStep));
+        let unprobed = SegmentTime {
+            duration_ms: None,
+            ..time
+        };
+        assert_eq!(unprobed.footage_end_ms(name), Err(Unreliable::NoDuration));
+    }
+}
@@ -0,0 +1,180 @@
+use super::StorageManager;
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use tracing::{info, warn};
+
+const CANARY_FILE: &str = ".self-check";
+const ROOT_MARKER: &str = ".constitute-nvr-storage";
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskUsage {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+    pub used_percent: f64,
+}
+
+impl StorageManager {
+    pub async fn disk_usage(&self) -> Result<DiskUsage> {
+        let output = tokio::process::Command::new("df")
+            .arg("-Pk")
+            .arg(&self.root)
+            .output()
+            .await
+            .context("run df")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "df exited with code {:?} for {}",
+                output.status.code(),
+                self.root.display()
+            ));
+        }
+        parse_df_output(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    pub(super) async fn ensure_root_marker(&self) -> Result<()> {
+        let path = self.root.join(ROOT_MARKER);
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            tokio::fs::write(&path, b"constitute-nvr storage root\n")
+                .await
+                .with_context(|| format!("write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    pub async fn check_root(&self) -> std::result::Result<(), String> {
+        let reason = match tokio::fs::metadata(&self.root).await {
+            Err(err) => Some(format!(
+                "storage root {} is missing: {err}",
+                self.root.display()
+            )),
+            Ok(meta) if !meta.is_dir() => Some(format!(
+                "storage root {} is not a directory",
+                self.root.display()
+            )),
+            Ok(_) => match tokio::fs::try_exists(self.root.join(ROOT_MARKER)).await {
+                Ok(true) => None,
+                Ok(false) => Some(format!(
+                    "storage root {} has no {ROOT_MARKER}; its volume is not mounted",
+                    self.root.display()
+                )),
+                Err(err) => Some(format!(
+                    "storage root {} is unreadable: {err}",
+                    self.root.display()
+                )),
+            },
+        };
+        let mut lost = self
+            .root_lost
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match (lost.is_some(), &reason) {
+            (false, Some(reason)) => warn!(reason = %reason, "storage root unavailable"),
+            (true, None) => info!(root = %self.root.display(), "storage root is back"),
+            _ => {}
+        }
+        lost.clone_from(&reason);
+        reason.map_or(Ok(()), Err)
+    }
+
+    pub fn root_unavailable(&self) -> Option<String> {
+        self.root_lost
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub async fn probe_writable(&self) -> Result<()> {
+        let path = self.root.join(CANARY_FILE);
+        let payload = crate::util::now_unix_seconds().to_string();
+        tokio::fs::write(&path, &payload)
+            .await
+            .with_context(|| format!("write {}", path.display()))?;
+        let read = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("read {}", path.display()));
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("remove {}", path.display()))?;
+        if read? != payload.as_bytes() {
+            return Err(anyhow!("{} read back different bytes", path.display()));
+        }
+        Ok(())
+    }
+}
+
+fn parse_df_output(raw: &str) -> Result<DiskUsage> {
+    let line = raw
+        .lines()
+        .nth(1)
+        .ok_or_else(|| anyhow!("df reported no filesystem"))?;
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() < 6 {
+        return Err(anyhow!("unexpected df output: {line}"));
+    }
+    let kib = |idx: usize| -> Result<u64> {
+        fields[idx]
+            .parse::<u64>()
+            .map(|value| value * 1024)
+            .with_context(|| format!("parse df field {idx}"))
+    };
+    let total_bytes = kib(1)?;
+    let used_bytes = kib(2)?;
+    let available_bytes = kib(3)?;
+    let usable = used_bytes + available_bytes;
+    let used_percent = if usable == 0 {
+        0.0
+    } else {
+        used_bytes as f64 * 100.0 / usable as f64
+    };
+    Ok(DiskUsage {
+        total_bytes,
+        used_bytes,
+        available_bytes,
+        used_percent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn root_checks_notice_a_volume_swapped_for_an_empty_directory() {
+        let root = std::env::temp_dir().join(format!("constitute-nvr-root-{}", std::process::id()));
+        let away = root.with_extension("away");
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_dir_all(&away);
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        storage.ensure_dirs().await.unwrap();
+        assert_eq!(storage.check_root().await, Ok(()));
+
+        // The volume drops off and leaves its empty mountpoint behind.
+        std::fs::rename(&root, &away).unwrap();
+        std::fs::create_dir_all(&root).unwrap();
+        let reason = storage.check_root().await.unwrap_err();
+        assert!(reason.contains(ROOT_MARKER), "{reason}");
+        assert_eq!(storage.root_unavailable(), Some(reason));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(storage.check_root().await.unwrap_err().contains("missing"));
+
+        std::fs::rename(&away, &root).unwrap();
+        assert_eq!(storage.check_root().await, Ok(()));
+        assert_eq!(storage.root_unavailable(), None);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn parses_posix_df_output() {
+        let usage = parse_df_output(
+            "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+             /dev/sdb1         1000000    900000    100000      90% /mnt/nvr\n",
+        )
+        .unwrap();
+        assert_eq!(usage.total_bytes, 1_024_000_000);
+        assert_eq!(usage.available_bytes, 102_400_000);
+        assert!((usage.used_percent - 90.0).abs() < 0.01);
+        assert!(parse_df_output("Filesystem\n").is_err());
+    }
+}
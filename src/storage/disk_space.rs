@@ -0,0 +1,255 @@
+use super::pre_delete::DoomedFile;
+use super::zone_retention::SegmentRetentionSummary;
+use super::{StorageManager, resolve_segment_path};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use tracing::{info, warn};
+
+pub trait SpaceProvider: Send + Sync {
+    fn sample(&self, root: &Path) -> std::io::Result<SpaceSample>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceSample {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+}
+
+pub struct Statvfs;
+
+impl SpaceProvider for Statvfs {
+    fn sample(&self, root: &Path) -> std::io::Result<SpaceSample> {
+        let stat = rustix::fs::statvfs(root)?;
+        Ok(SpaceSample {
+            total_bytes: stat.f_blocks.saturating_mul(stat.f_frsize),
+            free_bytes: stat.f_bavail.saturating_mul(stat.f_frsize),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskGuardSettings {
+    pub low_water_bytes: u64,
+    pub prune: bool,
+}
+
+impl DiskGuardSettings {
+    pub fn resume_bytes(&self) -> u64 {
+        self.low_water_bytes
+            .saturating_add(self.low_water_bytes / 4)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskSpaceStatus {
+    pub checked_at: u64,
+    #[serde(flatten)]
+    pub sample: SpaceSample,
+    pub low_water_bytes: u64,
+    pub resume_bytes: u64,
+    pub low: bool,
+    pub last_prune_unix: Option<u64>,
+    pub last_prune_segments: usize,
+    pub last_prune_bytes: u64,
+}
+
+impl StorageManager {
+    pub async fn check_disk_space(&self, settings: DiskGuardSettings) -> Result<DiskSpaceStatus> {
+        let mut sample = self.sample_space()?;
+        let was_low = self.disk_space_status().low;
+        let resume = settings.resume_bytes();
+        let below = |sample: &SpaceSample, was_low: bool| {
+            settings.low_water_bytes > 0
+                && sample.free_bytes
+                    < if was_low {
+                        resume
+                    } else {
+                        settings.low_water_bytes
+                    }
+        };
+        let mut low = below(&sample, was_low);
+        let mut pruned = None;
+        if low && settings.prune {
+            let summary = self
+                .prune_for_space(resume.saturating_sub(sample.free_bytes))
+                .await?;
+            if summary.removed > 0 {
+                sample = self.sample_space()?;
+                low = below(&sample, true);
+                warn!(
+                    segments = summary.removed,
+                    bytes = summary.bytes,
+                    blocked = summary.blocked,
+                    free_bytes = sample.free_bytes,
+                    "storage volume is low on space; pruned the oldest footage"
+                );
+                pruned = Some(summary);
+            }
+        }
+        match (was_low, low) {
+            (false, true) => warn!(
+                free_bytes = sample.free_bytes,
+                low_water_bytes = settings.low_water_bytes,
+                "storage volume is below its low-water mark"
+            ),
+            (true, false) => info!(
+                free_bytes = sample.free_bytes,
+                "storage volume is back over its resume mark"
+            ),
+            _ => {}
+        }
+
+        let mut status = self
+            .disk_space
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        status.checked_at = crate::util::now_unix_seconds();
+        status.sample = sample;
+        status.low_water_bytes = settings.low_water_bytes;
+        status.resume_bytes = resume;
+        status.low = low;
+        if let Some(summary) = pruned {
+            status.last_prune_unix = Some(status.checked_at);
+            status.last_prune_segments = summary.removed;
+            status.last_prune_bytes = summary.bytes;
+        }
+        Ok(status.clone())
+    }
+
+    pub fn disk_space_status(&self) -> DiskSpaceStatus {
+        self.disk_space
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn sample_space(&self) -> Result<SpaceSample> {
+        self.space
+            .sample(&self.root)
+            .with_context(|| format!("statvfs {}", self.root.display()))
+    }
+
+    async fn prune_for_space(&self, needed: u64) -> Result<SegmentRetentionSummary> {
+        let now = crate::util::now_unix_seconds();
+        let mut candidates = Vec::new();
+        for source_id in self.list_sources().await? {
+            let window = self.retention_window(&source_id);
+            let dir = self.segments_dir(&source_id);
+            let map = self.load_name_map(&dir).await?;
+            // Newest first; the newest may still be recording.
+            let segments = self.list_segments(&source_id, usize::MAX).await?;
+            for entry in segments.into_iter().skip(1) {
+                if window.holds(entry.end_unix, now) || self.held(&source_id, &entry) {
+                    continue;
+                }
+                let path = resolve_segment_path(&dir, map.as_ref(), &entry.name);
+                let file = DoomedFile {
+                    source_id: source_id.clone(),
+                    name: entry.name.clone(),
+                    path: path.to_string_lossy().to_string(),
+                    bytes: entry.bytes,
+                };
+                candidates.push((file, entry));
+            }
+        }
+        candidates.sort_by_key(|(_, entry)| entry.end_unix);
+        let mut freed = 0u64;
+        let doomed = candidates
+            .into_iter()
+            .take_while(|(_, entry)| {
+                let take = freed < needed;
+                freed = freed.saturating_add(entry.bytes);
+                take
+            })
+            .collect::<Vec<_>>();
+        self.delete_doomed(doomed, now).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct FixedSpace(AtomicU64);
+
+    impl SpaceProvider for FixedSpace {
+        fn sample(&self, _root: &Path) -> std::io::Result<SpaceSample> {
+            Ok(SpaceSample {
+                total_bytes: 1000,
+                free_bytes: self.0.load(Ordering::SeqCst),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn low_space_latches_until_the_resume_mark_and_prunes_the_oldest_first() {
+        let root =
+            std::env::temp_dir().join(format!("constitute-nvr-disk-space-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut paths = Vec::new();
+        for (index, name) in ["20240101T000000", "20240101T000010", "20240101T000020"]
+            .iter()
+            .enumerate()
+        {
+            let path = dir.join(format!("{name}.cnv"));
+            std::fs::write(&path, [0u8; 10]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(
+                    std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000 + index as u64),
+                )
+                .unwrap();
+            paths.push(path);
+        }
+        let space = Arc::new(FixedSpace::default());
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32))
+            .unwrap()
+            .with_space_provider(space.clone());
+        let pause = DiskGuardSettings {
+            low_water_bytes: 100,
+            prune: false,
+        };
+
+        space.0.store(150, Ordering::SeqCst);
+        assert!(!storage.check_disk_space(pause).await.unwrap().low);
+        space.0.store(99, Ordering::SeqCst);
+        assert!(storage.check_disk_space(pause).await.unwrap().low);
+        // Back over the low-water mark but short of the resume mark: still low.
+        space.0.store(110, Ordering::SeqCst);
+        assert!(storage.check_disk_space(pause).await.unwrap().low);
+        assert!(paths.iter().all(|path| path.exists()));
+        space.0.store(125, Ordering::SeqCst);
+        assert!(!storage.check_disk_space(pause).await.unwrap().low);
+
+        // Pruning frees only what is needed, oldest first; the space stays short here, so
+        // the volume stays low and the newest segment is kept.
+        let prune = DiskGuardSettings {
+            prune: true,
+            ..pause
+        };
+        space.0.store(99, Ordering::SeqCst);
+        let status = storage.check_disk_space(prune).await.unwrap();
+        assert!(status.low);
+        assert_eq!(
+            (status.last_prune_segments, status.last_prune_bytes),
+            (2, 20)
+        );
+        assert!(!paths[0].exists() && !paths[1].exists());
+        assert!(paths[2].exists());
+
+        // Turning the watch off clears it.
+        let off = DiskGuardSettings::default();
+        assert!(!storage.check_disk_space(off).await.unwrap().low);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
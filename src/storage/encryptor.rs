@@ -0,0 +1,160 @@
+use super::StorageManager;
+use serde::Serialize;
+use std::fmt::Write as _;
+use tokio::time::{Duration, interval, sleep};
+use tracing::{error, warn};
+
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptorStatus {
+    pub running: bool,
+    pub restarts: u32,
+    pub failing: bool,
+    pub last_success_unix: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_error_unix: Option<u64>,
+    pub pending_segments: usize,
+    pub last_pass_backlog: usize,
+    pub last_pass_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct EncryptPass {
+    pub backlog: usize,
+    pub pending: usize,
+    pub elapsed: Duration,
+}
+
+impl StorageManager {
+    pub fn start_encryptor(&self, interval_secs: u64) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                this.update_encryptor(|status| status.running = true);
+                let ended = tokio::spawn(this.clone().run_encryptor(interval_secs)).await;
+                this.update_encryptor(|status| status.running = false);
+                if this.cancel.is_cancelled() {
+                    break;
+                }
+                let reason = match ended {
+                    Ok(()) => "encryptor task exited".to_string(),
+                    Err(err) if err.is_panic() => {
+                        let payload = err.into_panic();
+                        let message = payload
+                            .downcast_ref::<&str>()
+                            .map(|message| message.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_default();
+                        format!("encryptor task panicked: {message}")
+                    }
+                    Err(err) => format!("encryptor task failed: {err}"),
+                };
+                error!(error = %reason, "encryptor task stopped; restarting it");
+                this.update_encryptor(|status| {
+                    status.restarts += 1;
+                    status.failing = true;
+                    status.last_error = Some(reason);
+                    status.last_error_unix = Some(crate::util::now_unix_seconds());
+                });
+                sleep(RESTART_DELAY).await;
+            }
+        });
+    }
+
+    async fn run_encryptor(self, interval_secs: u64) {
+        let mut tick = interval(Duration::from_secs(interval_secs.max(2)));
+        loop {
+            tick.tick().await;
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            // Nothing to seal, and nowhere to put it, while the volume is gone.
+            if self.check_root().await.is_err() {
+                continue;
+            }
+            if let Some(step) = self.clock.observe() {
+                match self.apply_clock_step(step).await {
+                    Ok(corrected) => warn!(
+                        step_secs = step.step_secs,
+                        detected_unix = step.detected_unix,
+                        corrected,
+                        "wall clock stepped; corrected segment times indexed before it"
+                    ),
+                    Err(err) => warn!(error = %err, "segment index clock correction failed"),
+                }
+            }
+            if let Err(err) = self.encrypt_pending_once().await {
+                warn!(error = %err, "segment encryption pass failed");
+            }
+        }
+    }
+
+    pub fn encryptor_status(&self) -> EncryptorStatus {
+        self.encryptor
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub(super) fn record_encrypt_pass(
+        &self,
+        pass: Option<EncryptPass>,
+        error: Option<&anyhow::Error>,
+    ) {
+        let now = crate::util::now_unix_seconds();
+        self.update_encryptor(|status| {
+            status.failing = error.is_some();
+            match error {
+                Some(err) => {
+                    status.last_error = Some(format!("{err:#}"));
+                    status.last_error_unix = Some(now);
+                }
+                None => status.last_success_unix = Some(now),
+            }
+            if let Some(pass) = pass {
+                status.pending_segments = pass.pending;
+                status.last_pass_backlog = pass.backlog;
+                status.last_pass_ms = Some(pass.elapsed.as_millis() as u64);
+            }
+        });
+    }
+
+    pub fn render_encryptor_metrics(&self) -> String {
+        let status = self.encryptor_status();
+        let mut out = String::new();
+        let gauges = [
+            (
+                "constitute_nvr_encrypt_pass_seconds",
+                "Duration of the last segment encryption pass.",
+                status.last_pass_ms.unwrap_or_default() as f64 / 1000.0,
+            ),
+            (
+                "constitute_nvr_encrypt_backlog_segments",
+                "Plaintext segments the last encryption pass found ready to seal.",
+                status.last_pass_backlog as f64,
+            ),
+            (
+                "constitute_nvr_encrypt_pending_segments",
+                "Plaintext segments left after the last encryption pass.",
+                status.pending_segments as f64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        }
+        out
+    }
+
+    fn update_encryptor(&self, update: impl FnOnce(&mut EncryptorStatus)) {
+        update(
+            &mut self
+                .encryptor
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+    }
+}
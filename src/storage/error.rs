@@ -0,0 +1,46 @@
+use crate::crypto::CryptoError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("segment not found: {source_id}/{name}")]
+    NotFound { source_id: String, name: String },
+    #[error("{0}")]
+    Corrupt(String),
+    #[error("encrypted blob does not open with the storage key")]
+    WrongKey,
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl StorageError {
+    pub(super) fn read(
+        source_id: &str,
+        name: &str,
+        path: &std::path::Path,
+        err: std::io::Error,
+    ) -> Self {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            return Self::NotFound {
+                source_id: source_id.to_string(),
+                name: name.to_string(),
+            };
+        }
+        Self::Io {
+            context: format!("read segment {}", path.display()),
+            source: err,
+        }
+    }
+}
+
+impl From<CryptoError> for StorageError {
+    fn from(err: CryptoError) -> Self {
+        match err {
+            CryptoError::DecryptFailed | CryptoError::BadKeyLength { .. } => Self::WrongKey,
+            other => Self::Corrupt(other.to_string()),
+        }
+    }
+}
@@ -0,0 +1,875 @@
+use super::{IoClass, JobProgress, MAGIC, Plaintext, StorageManager, decrypt_blob, seal_blob};
+use crate::features::SEGMENT_CHUNK_BYTES;
+use crate::recording::intent::{self, CoverageGap};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Notify;
+use tracing::warn;
+use zeroize::Zeroizing;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SPOOL_FILE: &str = "spool.cnv";
+const MANIFEST_EVERY_CHUNKS: usize = 64;
+const SEALED_OVERHEAD: usize = MAGIC.len() + 24 + 16;
+const TAR_BLOCK: usize = 512;
+const CHUNK_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExportSettings {
+    pub spool: bool,
+    pub ttl_secs: u64,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            spool: true,
+            ttl_secs: 24 * 3600,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest {
+    pub source_id: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportChunk {
+    pub seq: u64,
+    pub offset: u64,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportSegment {
+    name: String,
+    start_unix: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub(super) enum ArchiveEntry {
+    Segment {
+        source_id: String,
+        name: String,
+        start_unix: u64,
+        path: String,
+    },
+    Snapshot {
+        source_id: String,
+        name: String,
+        taken_unix: u64,
+        path: String,
+    },
+    Attachment {
+        source_id: String,
+        id: String,
+        created_unix: u64,
+        path: String,
+    },
+    File {
+        path: String,
+        mtime: u64,
+        contents: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+    pub job_id: String,
+    pub source_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incident_id: Option<String>,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    segments: Vec<ExportSegment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    entries: Vec<ArchiveEntry>,
+    pub spooled: bool,
+    pub created_unix: u64,
+    pub expires_unix: u64,
+    pub complete: bool,
+    pub total_bytes: u64,
+    pub sha256: Option<String>,
+    pub error: Option<String>,
+    pub chunks: Vec<ExportChunk>,
+    #[serde(default)]
+    pub gaps: Vec<CoverageGap>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReport {
+    pub source_id: String,
+    pub segments: usize,
+    pub chunks: usize,
+    pub total_bytes: u64,
+    pub sha256: String,
+    pub spooled: bool,
+    pub expires_unix: u64,
+}
+
+pub(super) struct LiveExport {
+    manifest: Mutex<ExportManifest>,
+    grown: Notify,
+}
+
+impl LiveExport {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ExportManifest> {
+        self.manifest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct ExportPiece {
+    pub chunk: ExportChunk,
+    pub data: Zeroizing<Vec<u8>>,
+}
+
+enum ChunkState {
+    Known(ExportChunk),
+    Pending(Arc<LiveExport>),
+    End,
+}
+
+impl StorageManager {
+    pub async fn prepare_export(
+        &self,
+        request: &ExportRequest,
+        job_id: &str,
+    ) -> Result<ExportManifest> {
+        if request.from_unix > request.to_unix {
+            return Err(anyhow!("fromUnix must not be after toUnix"));
+        }
+        let mut segments = self
+            .list_segments(&request.source_id, usize::MAX)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.overlaps(request.from_unix, request.to_unix))
+            .map(|entry| ExportSegment {
+                name: entry.name,
+                start_unix: entry.start_unix,
+            })
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            return Err(anyhow!(
+                "no segments for {} in the requested range",
+                request.source_id
+            ));
+        }
+        segments.sort_by(|left, right| {
+            (left.start_unix, &left.name).cmp(&(right.start_unix, &right.name))
+        });
+        let gaps = self
+            .coverage_gaps(
+                &request.source_id,
+                request.from_unix,
+                request.to_unix,
+                intent::MIN_GAP_SECS,
+            )
+            .await?;
+        let mut manifest = self.new_manifest(job_id, request.from_unix, request.to_unix);
+        manifest.source_id = request.source_id.clone();
+        manifest.segments = segments;
+        manifest.gaps = gaps;
+        self.register_export(manifest).await
+    }
+
+    pub(super) async fn prepare_archive_export(
+        &self,
+        job_id: &str,
+        incident_id: &str,
+        from_unix: u64,
+        to_unix: u64,
+        entries: Vec<ArchiveEntry>,
+    ) -> Result<ExportManifest> {
+        let mut manifest = self.new_manifest(job_id, from_unix, to_unix);
+        manifest.incident_id = Some(incident_id.to_string());
+        manifest.entries = entries;
+        self.register_export(manifest).await
+    }
+
+    fn new_manifest(&self, job_id: &str, from_unix: u64, to_unix: u64) -> ExportManifest {
+        let now = crate::util::now_unix_seconds();
+        ExportManifest {
+            job_id: job_id.to_string(),
+            source_id: String::new(),
+            incident_id: None,
+            from_unix,
+            to_unix,
+            segments: Vec::new(),
+            entries: Vec::new(),
+            spooled: self.export_settings.spool,
+            created_unix: now,
+            expires_unix: now.saturating_add(self.export_settings.ttl_secs),
+            complete: false,
+            total_bytes: 0,
+            sha256: None,
+            error: None,
+            chunks: Vec::new(),
+            gaps: Vec::new(),
+        }
+    }
+
+    async fn register_export(&self, manifest: ExportManifest) -> Result<ExportManifest> {
+        let job_id = manifest.job_id.clone();
+        let dir = self.export_dir(&job_id)?;
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+        if manifest.spooled {
+            // Created here so readers opened before the producer starts find it.
+            tokio::fs::File::create(dir.join(SPOOL_FILE))
+                .await
+                .context("create export spool")?;
+        }
+        write_manifest(&dir, &manifest).await?;
+        self.live_exports().insert(
+            job_id,
+            Arc::new(LiveExport {
+                manifest: Mutex::new(manifest.clone()),
+                grown: Notify::new(),
+            }),
+        );
+        Ok(manifest)
+    }
+
+    pub async fn run_export(&self, job_id: &str, progress: &JobProgress) -> Result<ExportReport> {
+        let live = self
+            .live_exports()
+            .get(job_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("export {job_id} is not running"))?;
+        let dir = self.export_dir(job_id)?;
+        let result = self.produce_export(&live, &dir, progress).await;
+        let manifest = {
+            let mut manifest = live.lock();
+            match &result {
+                Ok(sha256) => {
+                    manifest.complete = true;
+                    manifest.sha256 = Some(sha256.clone());
+                }
+                Err(err) => manifest.error = Some(err.to_string()),
+            }
+            manifest.clone()
+        };
+        let written = write_manifest(&dir, &manifest).await;
+        self.live_exports().remove(job_id);
+        live.grown.notify_waiters();
+        let sha256 = result?;
+        written?;
+        Ok(ExportReport {
+            segments: manifest.segment_count(),
+            source_id: manifest.source_id,
+            chunks: manifest.chunks.len(),
+            total_bytes: manifest.total_bytes,
+            sha256,
+            spooled: manifest.spooled,
+            expires_unix: manifest.expires_unix,
+        })
+    }
+
+    async fn produce_export(
+        &self,
+        live: &LiveExport,
+        dir: &Path,
+        progress: &JobProgress,
+    ) -> Result<String> {
+        let (source_id, segments, entries, spooled) = {
+            let manifest = live.lock();
+            (
+                manifest.source_id.clone(),
+                manifest.segments.clone(),
+                manifest.entries.clone(),
+                manifest.spooled,
+            )
+        };
+        let total = (segments.len() + entries.len()) as u64;
+        let mut spool = match spooled {
+            true => Some(
+                tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(dir.join(SPOOL_FILE))
+                    .await
+                    .context("open export spool")?,
+            ),
+            false => None,
+        };
+        let mut archive = ArchiveChunks::new(self.clone(), source_id, segments, entries);
+        let mut hasher = Sha256::new();
+        let mut offset = 0u64;
+        progress.phase("archiving");
+        while let Some(data) = archive.next().await? {
+            if progress.is_cancelled() {
+                return Err(anyhow!("export cancelled"));
+            }
+            hasher.update(data.as_slice());
+            self.io.acquire(IoClass::Serving, data.len() as u64).await;
+            // Spooled before it is recorded, so a reader never finds a chunk it cannot read.
+            if let Some(spool) = spool.as_mut() {
+                let sealed = seal_blob(&self.key, &data)?;
+                self.io.acquire(IoClass::Serving, sealed.len() as u64).await;
+                spool.write_all(&sealed).await?;
+                spool.flush().await.context("write export spool")?;
+            }
+            let chunk = ExportChunk {
+                seq: offset / SEGMENT_CHUNK_BYTES as u64,
+                offset,
+                bytes: data.len() as u64,
+                sha256: hex::encode(Sha256::digest(data.as_slice())),
+            };
+            offset += chunk.bytes;
+            let checkpoint = {
+                let mut manifest = live.lock();
+                manifest.chunks.push(chunk);
+                manifest.total_bytes = offset;
+                manifest
+                    .chunks
+                    .len()
+                    .is_multiple_of(MANIFEST_EVERY_CHUNKS)
+                    .then(|| manifest.clone())
+            };
+            live.grown.notify_waiters();
+            if let Some(manifest) = checkpoint {
+                write_manifest(dir, &manifest).await?;
+            }
+            progress.progress(archive.files_read, total);
+        }
+        if let Some(spool) = spool {
+            spool.sync_all().await.context("sync export spool")?;
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    pub async fn export_manifest(&self, job_id: &str) -> Result<Option<ExportManifest>> {
+        if let Some(live) = self.live_exports().get(job_id) {
+            return Ok(Some(live.lock().clone()));
+        }
+        read_manifest(&self.export_dir(job_id)?).await
+    }
+
+    pub async fn open_export(&self, job_id: &str, from_byte: u64) -> Result<ExportReader> {
+        let manifest = self
+            .export_manifest(job_id)
+            .await?
+            .ok_or_else(|| anyhow!("unknown export {job_id}"))?;
+        let seq = from_byte / SEGMENT_CHUNK_BYTES as u64;
+        let mut reader = ExportReader {
+            storage: self.clone(),
+            job_id: job_id.to_string(),
+            seq,
+            spool: None,
+            rebuild: None,
+            settled: None,
+        };
+        if manifest.spooled {
+            let path = self.export_dir(job_id)?.join(SPOOL_FILE);
+            reader.spool = Some(
+                tokio::fs::File::open(&path)
+                    .await
+                    .with_context(|| format!("open {}", path.display()))?,
+            );
+        } else {
+            let mut archive = ArchiveChunks::new(
+                self.clone(),
+                manifest.source_id,
+                manifest.segments,
+                manifest.entries,
+            );
+            for _ in 0..seq {
+                if archive.next().await?.is_none() {
+                    break;
+                }
+            }
+            reader.rebuild = Some(archive);
+        }
+        Ok(reader)
+    }
+
+    pub async fn ack_export(&self, job_id: &str) -> Result<bool> {
+        if self.live_exports().contains_key(job_id) {
+            return Err(anyhow!(
+                "export {job_id} is still running; cancel it or wait for it to finish"
+            ));
+        }
+        let dir = self.export_dir(job_id)?;
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err).with_context(|| format!("remove export {}", dir.display())),
+        }
+    }
+
+    pub async fn collect_exports(&self) -> Result<usize> {
+        let root = self.root.join("exports");
+        let mut rd = match tokio::fs::read_dir(&root).await {
+            Ok(rd) => rd,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err).with_context(|| format!("read_dir {}", root.display())),
+        };
+        let now = crate::util::now_unix_seconds();
+        let mut removed = 0;
+        while let Some(entry) = rd.next_entry().await? {
+            let dir = entry.path();
+            let manifest = match read_manifest(&dir).await {
+                Ok(Some(manifest)) => manifest,
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!(dir = %dir.display(), error = %err, "unreadable export manifest");
+                    continue;
+                }
+            };
+            if now < manifest.expires_unix || self.live_exports().contains_key(&manifest.job_id) {
+                continue;
+            }
+            tokio::fs::remove_dir_all(&dir)
+                .await
+                .with_context(|| format!("remove export {}", dir.display()))?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn export_dir(&self, job_id: &str) -> Result<PathBuf> {
+        if job_id.is_empty() || !job_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+            return Err(anyhow!("invalid jobId"));
+        }
+        Ok(self.root.join("exports").join(job_id))
+    }
+
+    fn live_exports(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<LiveExport>>> {
+        self.exports
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct ExportReader {
+    storage: StorageManager,
+    job_id: String,
+    seq: u64,
+    spool: Option<tokio::fs::File>,
+    rebuild: Option<ArchiveChunks>,
+    settled: Option<ExportManifest>,
+}
+
+impl ExportReader {
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.seq * SEGMENT_CHUNK_BYTES as u64
+    }
+
+    pub async fn next(&mut self) -> Result<Option<ExportPiece>> {
+        if let Some(archive) = self.rebuild.as_mut() {
+            let Some(data) = archive.next().await? else {
+                return Ok(None);
+            };
+            let chunk = ExportChunk {
+                seq: self.seq,
+                offset: self.seq * SEGMENT_CHUNK_BYTES as u64,
+                bytes: data.len() as u64,
+                sha256: hex::encode(Sha256::digest(data.as_slice())),
+            };
+            // A rebuild does not need the producer, so only a recorded hash can stop it.
+            if let Ok(ChunkState::Known(recorded)) = self.chunk_state().await
+                && recorded.sha256 != chunk.sha256
+            {
+                return Err(anyhow!(
+                    "segments of export {} changed since it started; start a new export",
+                    self.job_id
+                ));
+            }
+            self.seq += 1;
+            return Ok(Some(ExportPiece { chunk, data }));
+        }
+        loop {
+            match self.chunk_state().await? {
+                ChunkState::End => return Ok(None),
+                ChunkState::Pending(live) => {
+                    let _ = tokio::time::timeout(CHUNK_WAIT, live.grown.notified()).await;
+                }
+                ChunkState::Known(chunk) => {
+                    let data = self.read_spooled(&chunk).await?;
+                    self.seq += 1;
+                    return Ok(Some(ExportPiece { chunk, data }));
+                }
+            }
+        }
+    }
+
+    async fn chunk_state(&mut self) -> Result<ChunkState> {
+        let seq = self.seq as usize;
+        let live = self.storage.live_exports().get(&self.job_id).cloned();
+        if let Some(live) = live {
+            let manifest = live.lock();
+            if let Some(chunk) = manifest.chunks.get(seq) {
+                return Ok(ChunkState::Known(chunk.clone()));
+            }
+            drop(manifest);
+            return Ok(ChunkState::Pending(live));
+        }
+        if self.settled.is_none() {
+            self.settled = self.storage.export_manifest(&self.job_id).await?;
+        }
+        let manifest = self
+            .settled
+            .as_ref()
+            .ok_or_else(|| anyhow!("export {} was removed", self.job_id))?;
+        if let Some(chunk) = manifest.chunks.get(seq) {
+            return Ok(ChunkState::Known(chunk.clone()));
+        }
+        if manifest.complete {
+            return Ok(ChunkState::End);
+        }
+        Err(match &manifest.error {
+            Some(error) => anyhow!("export {} failed: {error}", self.job_id),
+            None => anyhow!(
+                "export {} stopped at byte {} when the node restarted; start a new export",
+                self.job_id,
+                manifest.total_bytes
+            ),
+        })
+    }
+
+    async fn read_spooled(&mut self, chunk: &ExportChunk) -> Result<Zeroizing<Vec<u8>>> {
+        let spool = self
+            .spool
+            .as_mut()
+            .ok_or_else(|| anyhow!("export {} has no spool", self.job_id))?;
+        let position = chunk.seq * (SEGMENT_CHUNK_BYTES + SEALED_OVERHEAD) as u64;
+        let mut blob = vec![0u8; chunk.bytes as usize + SEALED_OVERHEAD];
+        spool.seek(SeekFrom::Start(position)).await?;
+        spool
+            .read_exact(&mut blob)
+            .await
+            .context("read export spool")?;
+        let data = decrypt_blob(&self.storage.key, &blob)?;
+        if hex::encode(Sha256::digest(data.as_slice())) != chunk.sha256 {
+            return Err(anyhow!("export spool chunk {} is corrupt", chunk.seq));
+        }
+        Ok(data)
+    }
+}
+
+struct ArchiveChunks {
+    storage: StorageManager,
+    source_id: String,
+    segments: VecDeque<ExportSegment>,
+    entries: VecDeque<ArchiveEntry>,
+    files_read: u64,
+    pending: Zeroizing<Vec<u8>>,
+    finished: bool,
+}
+
+impl ArchiveChunks {
+    fn new(
+        storage: StorageManager,
+        source_id: String,
+        segments: Vec<ExportSegment>,
+        entries: Vec<ArchiveEntry>,
+    ) -> Self {
+        Self {
+            storage,
+            source_id,
+            segments: segments.into(),
+            entries: entries.into(),
+            files_read: 0,
+            pending: Zeroizing::new(Vec::new()),
+            finished: false,
+        }
+    }
+
+    async fn next(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>> {
+        while self.pending.len() < SEGMENT_CHUNK_BYTES && !self.finished {
+            let (path, mtime, data) = if let Some(segment) = self.segments.pop_front() {
+                let plain = self
+                    .storage
+                    .read_segment(&self.source_id, &segment.name)
+                    .await?;
+                (archive_entry_name(&segment.name), segment.start_unix, plain)
+            } else if let Some(entry) = self.entries.pop_front() {
+                self.storage.read_archive_entry(entry).await?
+            } else {
+                // Two zero blocks end a tar archive.
+                self.pending.extend_from_slice(&[0; 2 * TAR_BLOCK]);
+                self.finished = true;
+                break;
+            };
+            let header = tar_header(&path, data.len() as u64, mtime)?;
+            self.pending.extend_from_slice(&header);
+            self.pending.extend_from_slice(&data);
+            let padded = self.pending.len().next_multiple_of(TAR_BLOCK);
+            self.pending.resize(padded, 0);
+            self.files_read += 1;
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let take = self.pending.len().min(SEGMENT_CHUNK_BYTES);
+        let rest = Zeroizing::new(self.pending[take..].to_vec());
+        let mut chunk = std::mem::replace(&mut self.pending, rest);
+        chunk.truncate(take);
+        Ok(Some(chunk))
+    }
+}
+
+impl StorageManager {
+    async fn read_archive_entry(&self, entry: ArchiveEntry) -> Result<(String, u64, Plaintext)> {
+        Ok(match entry {
+            ArchiveEntry::Segment {
+                source_id,
+                name,
+                start_unix,
+                path,
+            } => (
+                path,
+                start_unix,
+                self.read_segment(&source_id, &name).await?,
+            ),
+            ArchiveEntry::Snapshot {
+                source_id,
+                name,
+                taken_unix,
+                path,
+            } => (
+                path,
+                taken_unix,
+                Arc::new(self.read_snapshot(&source_id, &name).await?),
+            ),
+            ArchiveEntry::Attachment {
+                source_id,
+                id,
+                created_unix,
+                path,
+            } => {
+                let (_, data) = self
+                    .read_attachment(&source_id, &id)
+                    .await?
+                    .ok_or_else(|| anyhow!("attachment {id} is gone"))?;
+                (path, created_unix, Arc::new(data))
+            }
+            ArchiveEntry::File {
+                path,
+                mtime,
+                contents,
+            } => (path, mtime, Arc::new(Zeroizing::new(contents.into_bytes()))),
+        })
+    }
+}
+
+impl ExportManifest {
+    fn segment_count(&self) -> usize {
+        self.segments.len()
+            + self
+                .entries
+                .iter()
+                .filter(|entry| matches!(entry, ArchiveEntry::Segment { .. }))
+                .count()
+    }
+}
+
+pub(super) fn archive_entry_name(name: &str) -> String {
+    match name.strip_suffix(".cnv") {
+        Some(stem) => format!("{stem}.mp4"),
+        None => name.to_string(),
+    }
+}
+
+fn tar_header(path: &str, size: u64, mtime: u64) -> Result<[u8; TAR_BLOCK]> {
+    let (prefix, name) = split_tar_path(path)
+        .ok_or_else(|| anyhow!("archive path {path} is too long for the archive"))?;
+    if size >= 1 << 33 {
+        return Err(anyhow!("{path} is too large for the archive"));
+    }
+    let mut header = [0u8; TAR_BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime.min((1 << 33) - 1));
+    header[148..156].fill(b' ');
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let checksum = header.iter().map(|byte| u64::from(*byte)).sum();
+    write_octal(&mut header[148..155], checksum);
+    Ok(header)
+}
+
+fn split_tar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(at, _)| at)
+        .find(|&at| at <= 155 && path.len() - at - 1 <= 100)
+        .map(|at| (&path[..at], &path[at + 1..]))
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{value:0digits$o}");
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+}
+
+async fn read_manifest(dir: &Path) -> Result<Option<ExportManifest>> {
+    let path = dir.join(MANIFEST_FILE);
+    match tokio::fs::read(&path).await {
+        Ok(raw) => Ok(Some(
+            serde_json::from_slice(&raw).with_context(|| format!("parse {}", path.display()))?,
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+async fn write_manifest(dir: &Path, manifest: &ExportManifest) -> Result<()> {
+    let tmp = dir.join(format!("{MANIFEST_FILE}.tmp"));
+    tokio::fs::write(&tmp, serde_json::to_vec(manifest)?)
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, dir.join(MANIFEST_FILE))
+        .await
+        .context("replace export manifest")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    async fn storage_with_segments(name: &str, settings: ExportSettings) -> StorageManager {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-export-{name}-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"55".repeat(32))
+            .unwrap()
+            .with_exports(settings);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (idx, bytes) in [70_000usize, 3, 120_000].into_iter().enumerate() {
+            let mut plain = vec![0u8; bytes];
+            rand::thread_rng().fill_bytes(&mut plain);
+            let path = dir.join(format!("20240101T00000{idx}.cnv"));
+            std::fs::write(&path, seal_blob(&storage.key, &plain).unwrap()).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(1_000 + idx as u64))
+                .unwrap();
+        }
+        storage
+    }
+
+    async fn read_all(reader: &mut ExportReader, out: &mut Vec<u8>) {
+        out.truncate(reader.offset() as usize);
+        while let Some(piece) = reader.next().await.unwrap() {
+            assert_eq!(piece.chunk.offset, out.len() as u64);
+            out.extend_from_slice(&piece.data);
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_exports_resume_to_the_same_bytes() {
+        for spool in [true, false] {
+            let settings = ExportSettings {
+                spool,
+                ..ExportSettings::default()
+            };
+            let storage = storage_with_segments(&format!("resume-{spool}"), settings).await;
+            let request = ExportRequest {
+                source_id: "cam-a".to_string(),
+                from_unix: 0,
+                to_unix: 2_000,
+                reason: String::new(),
+            };
+
+            let straight = storage.jobs().begin_concurrent("export_range");
+            let straight_id = straight.id().to_string();
+            storage
+                .prepare_export(&request, straight.id())
+                .await
+                .unwrap();
+            let report = storage
+                .run_export(straight.id(), straight.progress())
+                .await
+                .unwrap();
+            straight.finish(&Ok(report.clone()));
+            let mut whole = Vec::new();
+            read_all(
+                &mut storage.open_export(&straight_id, 0).await.unwrap(),
+                &mut whole,
+            )
+            .await;
+            assert_eq!(hex::encode(Sha256::digest(&whole)), report.sha256);
+            assert_eq!(whole.len() as u64, report.total_bytes);
+            assert_eq!(whole.len() % TAR_BLOCK, 0);
+
+            // The client reads two chunks while the export runs, drops, and resumes
+            // from a byte inside the third.
+            let job = storage.jobs().begin_concurrent("export_range");
+            let job_id = job.id().to_string();
+            storage.prepare_export(&request, &job_id).await.unwrap();
+            let producer = {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    let report = storage.run_export(job.id(), job.progress()).await;
+                    job.finish(&report);
+                    report
+                })
+            };
+            let mut received = Vec::new();
+            let mut first = storage.open_export(&job_id, 0).await.unwrap();
+            for _ in 0..2 {
+                let piece = first.next().await.unwrap().unwrap();
+                received.extend_from_slice(&piece.data);
+            }
+            drop(first);
+            let resume_at = received.len() as u64 + 100;
+            let mut resumed = storage.open_export(&job_id, resume_at).await.unwrap();
+            assert!(resumed.offset() <= resume_at);
+            read_all(&mut resumed, &mut received).await;
+            let resumed_report = producer.await.unwrap().unwrap();
+            assert_eq!(hex::encode(Sha256::digest(&received)), report.sha256);
+            assert_eq!(resumed_report.sha256, report.sha256);
+
+            assert!(storage.ack_export(&job_id).await.unwrap());
+            assert!(storage.open_export(&job_id, 0).await.is_err());
+            assert!(!storage.ack_export(&job_id).await.unwrap());
+            assert_eq!(storage.collect_exports().await.unwrap(), 0);
+            let _ = std::fs::remove_dir_all(&storage.root);
+        }
+    }
+}
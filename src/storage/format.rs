@@ -0,0 +1,186 @@
+use super::StorageManager;
+use super::layout;
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use tracing::{info, warn};
+
+pub const STORAGE_FORMAT_VERSION: u32 = 2;
+const FORMAT_FILE: &str = "FORMAT";
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatStatus {
+    pub version: u32,
+    pub supported: u32,
+    pub compatibility: bool,
+}
+
+impl StorageManager {
+    pub(super) async fn check_format(&self) -> Result<()> {
+        let path = self.root.join(FORMAT_FILE);
+        let version = match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => raw
+                .trim()
+                .parse::<u32>()
+                .with_context(|| format!("parse {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let version = if self.list_sources().await?.is_empty() {
+                    STORAGE_FORMAT_VERSION
+                } else {
+                    1
+                };
+                write_format(&self.root, version).await?;
+                version
+            }
+            Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+        };
+        if version > STORAGE_FORMAT_VERSION {
+            return Err(anyhow!(
+                "storage format {version} in {} is newer than this build supports \
+                 ({STORAGE_FORMAT_VERSION}); upgrade constitute-nvr",
+                self.root.display()
+            ));
+        }
+        if version < STORAGE_FORMAT_VERSION {
+            warn!(
+                version,
+                supported = STORAGE_FORMAT_VERSION,
+                "storage format is older than this build; running in compatibility mode"
+            );
+        }
+        self.format.store(version, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn format_status(&self) -> FormatStatus {
+        let version = self.format.load(Ordering::Relaxed);
+        FormatStatus {
+            version,
+            supported: STORAGE_FORMAT_VERSION,
+            compatibility: version < STORAGE_FORMAT_VERSION,
+        }
+    }
+
+    pub(super) async fn advance_format_after_day_layout(&self) -> Result<u32> {
+        let current = self.format.load(Ordering::Relaxed);
+        if current >= 2 {
+            return Ok(current);
+        }
+        let root = self.root.join("segments");
+        let remaining = tokio::task::spawn_blocking(move || flat_segments_remain(&root))
+            .await
+            .context("join format verification")??;
+        if remaining {
+            return Ok(current);
+        }
+        write_format(&self.root, 2).await?;
+        self.format.store(2, Ordering::Relaxed);
+        info!(version = 2, "storage format advanced");
+        Ok(2)
+    }
+}
+
+fn flat_segments_remain(root: &Path) -> Result<bool> {
+    if !root.exists() {
+        return Ok(false);
+    }
+    let entries =
+        std::fs::read_dir(root).with_context(|| format!("read_dir {}", root.display()))?;
+    for entry in entries {
+        let source_dir = entry?.path();
+        if source_dir.is_dir() && layout::has_flat_segments(&source_dir)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+async fn write_format(root: &Path, version: u32) -> Result<()> {
+    let path = root.join(FORMAT_FILE);
+    let tmp = root.join(format!("{FORMAT_FILE}.tmp"));
+    tokio::fs::write(&tmp, format!("{version}\n"))
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    // Rename over the marker so a crash leaves the old or the new version, never a torn one.
+    tokio::fs::rename(&tmp, &path)
+        .await
+        .with_context(|| format!("replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::seal_blob;
+
+    #[tokio::test]
+    async fn interrupted_migration_keeps_the_old_marker_and_both_layouts_readable() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-format-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let key_hex = "44".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        // A root written before the dated layout: flat segments and no marker.
+        for source in ["cam-a", "cam-b"] {
+            let dir = root.join("segments").join(source);
+            std::fs::create_dir_all(&dir).unwrap();
+            for name in ["20240101T000000.cnv", "20240101T000010.cnv"] {
+                let blob = seal_blob(&key, format!("{source}/{name}").as_bytes()).unwrap();
+                std::fs::write(dir.join(name), blob).unwrap();
+            }
+        }
+        let storage = StorageManager::new(root.clone(), &key_hex).unwrap();
+        storage.ensure_dirs().await.unwrap();
+        assert_eq!(storage.format_status().version, 1);
+        assert!(storage.format_status().compatibility);
+
+        // The migration is interrupted after moving one source.
+        layout::move_flat_segments(&root.join("segments").join("cam-a")).unwrap();
+        assert!(root.join("segments/cam-a/20240101/000000.cnv").exists());
+        assert_eq!(
+            std::fs::read_to_string(root.join(FORMAT_FILE)).unwrap(),
+            "1\n"
+        );
+
+        // After a restart both layouts are listed and read.
+        let restarted = StorageManager::new(root.clone(), &key_hex).unwrap();
+        restarted.ensure_dirs().await.unwrap();
+        assert!(restarted.format_status().compatibility);
+        for source in ["cam-a", "cam-b"] {
+            assert_eq!(restarted.list_segments(source, 10).await.unwrap().len(), 2);
+            let plain = restarted
+                .read_segment(source, "20240101T000010.cnv")
+                .await
+                .unwrap();
+            assert_eq!(
+                plain.as_slice(),
+                format!("{source}/20240101T000010.cnv").as_bytes()
+            );
+        }
+
+        // Only a migration that leaves nothing flat advances the marker.
+        let report = restarted.migrate_day_layout().await.unwrap();
+        assert_eq!(report.segments, 2);
+        assert_eq!(report.format_version, Some(STORAGE_FORMAT_VERSION));
+        assert!(!restarted.format_status().compatibility);
+        assert_eq!(
+            std::fs::read_to_string(root.join(FORMAT_FILE)).unwrap(),
+            "2\n"
+        );
+
+        // A marker from a newer build stops startup.
+        std::fs::write(root.join(FORMAT_FILE), "9\n").unwrap();
+        let older_build = StorageManager::new(root.clone(), &key_hex).unwrap();
+        assert!(older_build.ensure_dirs().await.is_err());
+
+        let fresh = root.join("fresh");
+        let empty = StorageManager::new(fresh.clone(), &key_hex).unwrap();
+        empty.ensure_dirs().await.unwrap();
+        assert_eq!(empty.format_status().version, STORAGE_FORMAT_VERSION);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,64 @@
+use super::{Plaintext, StorageManager};
+use crate::bandwidth::EgressShaper;
+use crate::media::fragment;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
+use zeroize::Zeroizing;
+
+pub(super) const REMUX_DIR: &str = ".remux";
+
+impl StorageManager {
+    pub async fn fragmented_segment(
+        &self,
+        source_id: &str,
+        name: &str,
+        governor: &EgressShaper,
+        live: mpsc::Sender<Zeroizing<Vec<u8>>>,
+    ) -> Result<Plaintext> {
+        if !name.ends_with(".cnv") {
+            return self
+                .remux_segment(source_id, name, governor, live)
+                .await
+                .map(Arc::new);
+        }
+        let format = self.format.load(Ordering::Relaxed);
+        self.fragment_cache
+            .get_or_load(source_id, name, format, || {
+                self.remux_segment(source_id, name, governor, live)
+            })
+            .await
+    }
+
+    async fn remux_segment(
+        &self,
+        source_id: &str,
+        name: &str,
+        governor: &EgressShaper,
+        live: mpsc::Sender<Zeroizing<Vec<u8>>>,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let plain = self.read_segment(source_id, name).await?;
+        // Refused before ffmpeg runs; a codec it cannot place in fragments fails the remux.
+        fragment::check_fragmentable(&fragment::track_codecs(&plain))?;
+        let _slot = governor.remux_slot().await;
+        let dir = self.root.join(REMUX_DIR);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+        let input = ScratchFile(dir.join(format!("{}.mp4", uuid::Uuid::new_v4().simple())));
+        tokio::fs::write(&input.0, &**plain)
+            .await
+            .with_context(|| format!("write {}", input.0.display()))?;
+        Ok(fragment::remux(&input.0, &live).await?)
+    }
+}
+
+struct ScratchFile(PathBuf);
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
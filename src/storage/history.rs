@@ -0,0 +1,252 @@
+use super::StorageManager;
+use super::snapshots::is_plain_component;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+pub const MAX_REVISIONS: usize = 50;
+const HISTORY_DIR: &str = "config_history";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceRevision {
+    pub revision: u64,
+    pub at_unix: u64,
+    pub actor_device_pk: String,
+    pub action: String,
+    pub changes: Vec<FieldChange>,
+    pub password_changed: bool,
+    pub config: Option<Value>,
+}
+
+pub struct SourceChange<'a> {
+    pub source_id: &'a str,
+    pub actor_device_pk: &'a str,
+    pub action: &'a str,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub password_changed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryFile {
+    source_id: String,
+    revisions: Vec<SourceRevision>,
+}
+
+impl StorageManager {
+    pub async fn append_source_revision(
+        &self,
+        change: SourceChange<'_>,
+    ) -> Result<Option<SourceRevision>> {
+        let changes = field_changes(change.before.as_ref(), change.after.as_ref());
+        if changes.is_empty() && !change.password_changed {
+            return Ok(None);
+        }
+        let _guard = self.history_lock.lock().await;
+        let path = self.history_path(change.source_id)?;
+        let mut file = read_history(&path).await?.unwrap_or_else(|| HistoryFile {
+            source_id: change.source_id.to_string(),
+            revisions: Vec::new(),
+        });
+        let revision = SourceRevision {
+            revision: file.revisions.last().map_or(1, |last| last.revision + 1),
+            at_unix: crate::util::now_unix_seconds(),
+            actor_device_pk: change.actor_device_pk.to_string(),
+            action: change.action.to_string(),
+            changes,
+            password_changed: change.password_changed,
+            config: change.after,
+        };
+        file.revisions.push(revision.clone());
+        let excess = file.revisions.len().saturating_sub(MAX_REVISIONS);
+        file.revisions.drain(..excess);
+        write_history(&path, &file).await?;
+        Ok(Some(revision))
+    }
+
+    pub async fn source_history(
+        &self,
+        source_id: &str,
+        limit: usize,
+    ) -> Result<Vec<SourceRevision>> {
+        let _guard = self.history_lock.lock().await;
+        let path = self.history_path(source_id)?;
+        let revisions = read_history(&path)
+            .await?
+            .map(|file| file.revisions)
+            .unwrap_or_default();
+        Ok(revisions.into_iter().rev().take(limit).collect())
+    }
+
+    pub async fn source_revision(
+        &self,
+        source_id: &str,
+        revision: u64,
+    ) -> Result<Option<SourceRevision>> {
+        let _guard = self.history_lock.lock().await;
+        let path = self.history_path(source_id)?;
+        Ok(read_history(&path).await?.and_then(|file| {
+            file.revisions
+                .into_iter()
+                .find(|entry| entry.revision == revision)
+        }))
+    }
+
+    fn history_path(&self, source_id: &str) -> Result<PathBuf> {
+        if !is_plain_component(source_id) {
+            return Err(anyhow!("invalid sourceId"));
+        }
+        Ok(self
+            .root
+            .join(HISTORY_DIR)
+            .join(format!("{source_id}.json")))
+    }
+}
+
+fn field_changes(before: Option<&Value>, after: Option<&Value>) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+    let mut fields = before.keys().chain(after.keys()).collect::<Vec<_>>();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let from = before.get(field).cloned().unwrap_or(Value::Null);
+            let to = after.get(field).cloned().unwrap_or(Value::Null);
+            (from != to).then(|| FieldChange {
+                field: field.clone(),
+                from,
+                to,
+            })
+        })
+        .collect()
+}
+
+async fn read_history(path: &Path) -> Result<Option<HistoryFile>> {
+    let raw = match tokio::fs::read(path).await {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+    let file = serde_json::from_slice(&raw).with_context(|| format!("parse {}", path.display()))?;
+    Ok(Some(file))
+}
+
+async fn write_history(path: &Path, file: &HistoryFile) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(file)?)
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn history_records_changes_newest_first_and_keeps_a_bounded_count() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-history-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        let config = |url: &str| json!({ "sourceId": "front", "rtspUrl": url, "enabled": true });
+        let change = |before: Option<Value>, after: Option<Value>, password_changed| SourceChange {
+            source_id: "front",
+            actor_device_pk: "device",
+            action: "upsert",
+            before,
+            after,
+            password_changed,
+        };
+
+        let first = storage
+            .append_source_revision(change(None, Some(config("rtsp://a/1")), true))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.revision, 1);
+        assert_eq!(first.changes.len(), 3);
+        // Nothing changed: no revision.
+        let same = change(
+            Some(config("rtsp://a/1")),
+            Some(config("rtsp://a/1")),
+            false,
+        );
+        assert!(
+            storage
+                .append_source_revision(same)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let moved = change(
+            Some(config("rtsp://a/1")),
+            Some(config("rtsp://a/2")),
+            false,
+        );
+        let second = storage
+            .append_source_revision(moved)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            second.changes,
+            vec![FieldChange {
+                field: "rtspUrl".to_string(),
+                from: json!("rtsp://a/1"),
+                to: json!("rtsp://a/2"),
+            }]
+        );
+        assert!(!second.password_changed);
+        let history = storage.source_history("front", 10).await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| entry.revision)
+                .collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        let stored = storage.source_revision("front", 1).await.unwrap().unwrap();
+        assert_eq!(stored.config, Some(config("rtsp://a/1")));
+
+        for n in 3..=MAX_REVISIONS as u64 + 5 {
+            let before = config(&format!("rtsp://a/{}", n - 1));
+            let after = config(&format!("rtsp://a/{n}"));
+            storage
+                .append_source_revision(change(Some(before), Some(after), false))
+                .await
+                .unwrap();
+        }
+        let history = storage.source_history("front", usize::MAX).await.unwrap();
+        assert_eq!(history.len(), MAX_REVISIONS);
+        assert_eq!(history[0].revision, MAX_REVISIONS as u64 + 5);
+        assert!(storage.source_revision("front", 1).await.unwrap().is_none());
+        assert!(storage.source_history("../front", 1).await.is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
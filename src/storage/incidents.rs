@@ -0,0 +1,803 @@
+use super::exports::{ArchiveEntry, archive_entry_name};
+use super::{Attachment, Bookmark, ExportManifest, StorageManager, decrypt_blob, seal_blob};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use tracing::warn;
+
+const INCIDENTS_DIR: &str = "incidents";
+const SNAPSHOTS_PER_SOURCE: usize = 1_000;
+const EVENTS_MAX: usize = 1_000;
+const INCIDENT_NOTES_MAX: usize = 500;
+const INCIDENT_ATTACHMENTS_MAX: usize = 100;
+const TIMELINE_PATH: &str = "timeline.json";
+const MANIFEST_PATH: &str = "manifest.json";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentRequest {
+    pub title: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub source_ids: Vec<String>,
+    #[serde(default)]
+    pub note: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentUpdate {
+    pub incident_id: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub attachments: Vec<IncidentAttachmentRef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentAttachmentRef {
+    pub source_id: String,
+    pub attachment_id: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentSegment {
+    pub source_id: String,
+    pub name: String,
+    pub start_unix: u64,
+    pub end_unix: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentSnapshot {
+    pub source_id: String,
+    pub name: String,
+    pub taken_unix: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentEvent {
+    pub event_id: String,
+    pub occurred_unix: u64,
+    pub source_id: String,
+    pub tags: Vec<String>,
+    pub facts: Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentNote {
+    pub text: String,
+    pub author: String,
+    pub created_unix: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Incident {
+    pub id: String,
+    pub title: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub source_ids: Vec<String>,
+    pub created_unix: u64,
+    pub created_by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed_unix: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_unix: Option<u64>,
+    pub segments: Vec<IncidentSegment>,
+    pub snapshots: Vec<IncidentSnapshot>,
+    pub events: Vec<IncidentEvent>,
+    pub notes: Vec<IncidentNote>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl Incident {
+    fn owner(&self) -> String {
+        format!("incident:{}", self.id)
+    }
+
+    fn bookmarks(&self) -> Vec<Bookmark> {
+        self.source_ids
+            .iter()
+            .map(|source_id| Bookmark {
+                owner: self.owner(),
+                source_id: source_id.clone(),
+                from_unix: self.from_unix,
+                to_unix: self.to_unix,
+                release_unix: self.release_unix.unwrap_or(0),
+            })
+            .collect()
+    }
+
+    pub fn summary(&self) -> IncidentSummary {
+        IncidentSummary {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            from_unix: self.from_unix,
+            to_unix: self.to_unix,
+            source_ids: self.source_ids.clone(),
+            created_unix: self.created_unix,
+            closed_unix: self.closed_unix,
+            release_unix: self.release_unix,
+            segments: self.segments.len(),
+            snapshots: self.snapshots.len(),
+            events: self.events.len(),
+            notes: self.notes.len(),
+            attachments: self.attachments.len(),
+        }
+    }
+
+    fn timeline(&self) -> Value {
+        let mut items = Vec::new();
+        for segment in &self.segments {
+            items.push((
+                segment.start_unix,
+                json!({
+                    "atUnix": segment.start_unix,
+                    "kind": "segment",
+                    "sourceId": segment.source_id,
+                    "name": segment.name,
+                    "endUnix": segment.end_unix,
+                    "path": clip_path(segment),
+                }),
+            ));
+        }
+        for snapshot in &self.snapshots {
+            items.push((
+                snapshot.taken_unix,
+                json!({
+                    "atUnix": snapshot.taken_unix,
+                    "kind": "snapshot",
+                    "sourceId": snapshot.source_id,
+                    "name": snapshot.name,
+                    "path": snapshot_path(snapshot),
+                }),
+            ));
+        }
+        for event in &self.events {
+            items.push((
+                event.occurred_unix,
+                json!({
+                    "atUnix": event.occurred_unix,
+                    "kind": "event",
+                    "sourceId": event.source_id,
+                    "eventId": event.event_id,
+                    "tags": event.tags,
+                    "facts": event.facts,
+                }),
+            ));
+        }
+        for note in &self.notes {
+            items.push((
+                note.created_unix,
+                json!({
+                    "atUnix": note.created_unix,
+                    "kind": "note",
+                    "author": note.author,
+                    "text": note.text,
+                }),
+            ));
+        }
+        for attachment in &self.attachments {
+            items.push((
+                attachment.at_unix,
+                json!({
+                    "atUnix": attachment.at_unix,
+                    "kind": "attachment",
+                    "sourceId": attachment.source_id,
+                    "id": attachment.id,
+                    "name": attachment.name,
+                    "path": attachment_path(attachment),
+                }),
+            ));
+        }
+        items.sort_by_key(|(at_unix, _)| *at_unix);
+        json!({
+            "incidentId": self.id,
+            "title": self.title,
+            "fromUnix": self.from_unix,
+            "toUnix": self.to_unix,
+            "sourceIds": self.source_ids,
+            "createdUnix": self.created_unix,
+            "closedUnix": self.closed_unix,
+            "timeline": items.into_iter().map(|(_, item)| item).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncidentSummary {
+    pub id: String,
+    pub title: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub source_ids: Vec<String>,
+    pub created_unix: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_unix: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_unix: Option<u64>,
+    pub segments: usize,
+    pub snapshots: usize,
+    pub events: usize,
+    pub notes: usize,
+    pub attachments: usize,
+}
+
+impl StorageManager {
+    pub async fn create_incident(
+        &self,
+        request: &IncidentRequest,
+        actor: &str,
+    ) -> Result<Incident> {
+        if request.from_unix > request.to_unix {
+            return Err(anyhow!("fromUnix must not be after toUnix"));
+        }
+        let mut segments = Vec::new();
+        let mut snapshots = Vec::new();
+        for source_id in &request.source_ids {
+            segments.extend(
+                self.list_segments(source_id, usize::MAX)
+                    .await?
+                    .into_iter()
+                    .filter(|entry| entry.overlaps(request.from_unix, request.to_unix))
+                    .map(|entry| IncidentSegment {
+                        source_id: source_id.clone(),
+                        name: entry.name,
+                        start_unix: entry.start_unix,
+                        end_unix: entry.end_unix,
+                    }),
+            );
+            snapshots.extend(
+                self.list_snapshots(
+                    source_id,
+                    Some(request.from_unix),
+                    Some(request.to_unix),
+                    SNAPSHOTS_PER_SOURCE,
+                )
+                .await?
+                .into_iter()
+                .map(|entry| IncidentSnapshot {
+                    source_id: entry.source_id,
+                    name: entry.name,
+                    taken_unix: entry.taken_unix,
+                }),
+            );
+        }
+        segments.sort_by(|left, right| {
+            (left.start_unix, &left.source_id, &left.name).cmp(&(
+                right.start_unix,
+                &right.source_id,
+                &right.name,
+            ))
+        });
+        snapshots.sort_by_key(|snapshot| snapshot.taken_unix);
+        let events = {
+            let source_ids = request.source_ids.clone();
+            let (from_unix, to_unix) = (request.from_unix, request.to_unix);
+            tokio::task::spawn_blocking(move || {
+                crate::logging_surface::read_source_events(&source_ids, from_unix, to_unix)
+            })
+            .await
+            .unwrap_or_default()
+        };
+        let events = events
+            .into_iter()
+            .take(EVENTS_MAX)
+            .map(|event| IncidentEvent {
+                source_id: event.safe_facts["sourceId"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                event_id: event.event_id,
+                occurred_unix: event.occurred_at,
+                tags: event.tags,
+                facts: event.safe_facts,
+            })
+            .collect();
+
+        let now = crate::util::now_unix_seconds();
+        let note = request.note.trim();
+        let incident = Incident {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            title: request.title.trim().to_string(),
+            from_unix: request.from_unix,
+            to_unix: request.to_unix,
+            source_ids: request.source_ids.clone(),
+            created_unix: now,
+            created_by: actor.to_string(),
+            closed_unix: None,
+            closed_by: None,
+            release_unix: None,
+            segments,
+            snapshots,
+            events,
+            notes: (!note.is_empty())
+                .then(|| IncidentNote {
+                    text: note.to_string(),
+                    author: actor.to_string(),
+                    created_unix: now,
+                })
+                .into_iter()
+                .collect(),
+            attachments: Vec::new(),
+        };
+        let _guard = self.incident_lock.lock().await;
+        self.write_incident(&incident).await?;
+        self.set_bookmarks(&incident.owner(), incident.bookmarks());
+        Ok(incident)
+    }
+
+    pub async fn update_incident(
+        &self,
+        update: &IncidentUpdate,
+        actor: &str,
+    ) -> Result<Option<Incident>> {
+        let _guard = self.incident_lock.lock().await;
+        let Some(mut incident) = self.read_incident(&update.incident_id).await? else {
+            return Ok(None);
+        };
+        if incident.closed_unix.is_some() {
+            return Err(anyhow!("incident {} is closed", incident.id));
+        }
+        let note = update.note.trim();
+        if !note.is_empty() {
+            if incident.notes.len() >= INCIDENT_NOTES_MAX {
+                return Err(anyhow!(
+                    "incident {} already has {INCIDENT_NOTES_MAX} notes",
+                    incident.id
+                ));
+            }
+            incident.notes.push(IncidentNote {
+                text: note.to_string(),
+                author: actor.to_string(),
+                created_unix: crate::util::now_unix_seconds(),
+            });
+        }
+        for link in &update.attachments {
+            if incident
+                .attachments
+                .iter()
+                .any(|attachment| attachment.id == link.attachment_id)
+            {
+                continue;
+            }
+            if incident.attachments.len() >= INCIDENT_ATTACHMENTS_MAX {
+                return Err(anyhow!(
+                    "incident {} already has {INCIDENT_ATTACHMENTS_MAX} attachments",
+                    incident.id
+                ));
+            }
+            let attachment = self
+                .attachment(&link.source_id, &link.attachment_id)
+                .await
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no attachment {} for {}",
+                        link.attachment_id,
+                        link.source_id
+                    )
+                })?;
+            incident.attachments.push(attachment);
+        }
+        self.write_incident(&incident).await?;
+        Ok(Some(incident))
+    }
+
+    pub async fn close_incident(
+        &self,
+        id: &str,
+        grace_secs: u64,
+        actor: &str,
+    ) -> Result<Option<Incident>> {
+        let _guard = self.incident_lock.lock().await;
+        let Some(mut incident) = self.read_incident(id).await? else {
+            return Ok(None);
+        };
+        if incident.closed_unix.is_none() {
+            let now = crate::util::now_unix_seconds();
+            incident.closed_unix = Some(now);
+            incident.closed_by = Some(actor.to_string());
+            incident.release_unix = Some(now.saturating_add(grace_secs));
+            self.write_incident(&incident).await?;
+            self.set_bookmarks(&incident.owner(), incident.bookmarks());
+        }
+        Ok(Some(incident))
+    }
+
+    pub async fn get_incident(&self, id: &str) -> Result<Option<Incident>> {
+        let _guard = self.incident_lock.lock().await;
+        self.read_incident(id).await
+    }
+
+    pub async fn list_incidents(&self, limit: usize) -> Result<Vec<IncidentSummary>> {
+        let _guard = self.incident_lock.lock().await;
+        let mut out = self
+            .read_incidents()
+            .await?
+            .iter()
+            .map(Incident::summary)
+            .collect::<Vec<_>>();
+        out.sort_by_key(|summary| std::cmp::Reverse(summary.created_unix));
+        out.truncate(limit);
+        Ok(out)
+    }
+
+    pub async fn load_incident_bookmarks(&self) -> Result<usize> {
+        let _guard = self.incident_lock.lock().await;
+        let now = crate::util::now_unix_seconds();
+        let mut holding = 0;
+        for incident in self.read_incidents().await? {
+            if incident.release_unix.is_some_and(|release| release <= now) {
+                continue;
+            }
+            self.set_bookmarks(&incident.owner(), incident.bookmarks());
+            holding += 1;
+        }
+        Ok(holding)
+    }
+
+    pub async fn prepare_incident_export(
+        &self,
+        incident: &Incident,
+        job_id: &str,
+        sign: impl FnOnce(&Value) -> Result<String>,
+    ) -> Result<ExportManifest> {
+        let mut entries = Vec::new();
+        let mut files = Vec::new();
+        let mut missing = Vec::new();
+        let mut stored = HashMap::new();
+        for source_id in &incident.source_ids {
+            for entry in self.list_segments(source_id, usize::MAX).await? {
+                stored.insert((source_id.clone(), entry.name.clone()), entry);
+            }
+        }
+        for segment in &incident.segments {
+            let Some(entry) = stored.get(&(segment.source_id.clone(), segment.name.clone())) else {
+                missing.push(json!({
+                    "kind": "segment",
+                    "sourceId": segment.source_id,
+                    "name": segment.name,
+                }));
+                continue;
+            };
+            // Hashes come from the index; segments sealed before it may have none yet.
+            files.push(json!({
+                "path": clip_path(segment),
+                "bytes": entry.plaintext_bytes,
+                "sha256": entry.plaintext_sha256,
+            }));
+            entries.push(ArchiveEntry::Segment {
+                source_id: segment.source_id.clone(),
+                name: segment.name.clone(),
+                start_unix: segment.start_unix,
+                path: clip_path(segment),
+            });
+        }
+        let mut present = HashSet::new();
+        for source_id in &incident.source_ids {
+            for entry in self
+                .list_snapshots(
+                    source_id,
+                    Some(incident.from_unix),
+                    Some(incident.to_unix),
+                    usize::MAX,
+                )
+                .await?
+            {
+                present.insert((source_id.clone(), entry.name));
+            }
+        }
+        for snapshot in &incident.snapshots {
+            if !present.contains(&(snapshot.source_id.clone(), snapshot.name.clone())) {
+                missing.push(json!({
+                    "kind": "snapshot",
+                    "sourceId": snapshot.source_id,
+                    "name": snapshot.name,
+                }));
+                continue;
+            }
+            files.push(json!({ "path": snapshot_path(snapshot) }));
+            entries.push(ArchiveEntry::Snapshot {
+                source_id: snapshot.source_id.clone(),
+                name: snapshot.name.clone(),
+                taken_unix: snapshot.taken_unix,
+                path: snapshot_path(snapshot),
+            });
+        }
+
+        for attachment in &incident.attachments {
+            if self
+                .attachment(&attachment.source_id, &attachment.id)
+                .await
+                .is_none()
+            {
+                missing.push(json!({
+                    "kind": "attachment",
+                    "sourceId": attachment.source_id,
+                    "id": attachment.id,
+                }));
+                continue;
+            }
+            files.push(json!({
+                "path": attachment_path(attachment),
+                "bytes": attachment.bytes,
+                "sha256": attachment.sha256,
+            }));
+            entries.push(ArchiveEntry::Attachment {
+                source_id: attachment.source_id.clone(),
+                id: attachment.id.clone(),
+                created_unix: attachment.created_unix,
+                path: attachment_path(attachment),
+            });
+        }
+
+        let now = crate::util::now_unix_seconds();
+        let timeline = serde_json::to_string_pretty(&incident.timeline())?;
+        files.push(json!({
+            "path": TIMELINE_PATH,
+            "bytes": timeline.len(),
+            "sha256": hex::encode(Sha256::digest(timeline.as_bytes())),
+        }));
+        entries.push(ArchiveEntry::File {
+            path: TIMELINE_PATH.to_string(),
+            mtime: now,
+            contents: timeline,
+        });
+        let listing = json!({
+            "incidentId": incident.id,
+            "title": incident.title,
+            "fromUnix": incident.from_unix,
+            "toUnix": incident.to_unix,
+            "sourceIds": incident.source_ids,
+            "exportedUnix": now,
+            "files": files,
+            "missing": missing,
+        });
+        entries.push(ArchiveEntry::File {
+            path: MANIFEST_PATH.to_string(),
+            mtime: now,
+            contents: sign(&listing)?,
+        });
+        self.prepare_archive_export(
+            job_id,
+            &incident.id,
+            incident.from_unix,
+            incident.to_unix,
+            entries,
+        )
+        .await
+    }
+
+    fn incidents_dir(&self) -> PathBuf {
+        self.root.join(INCIDENTS_DIR)
+    }
+
+    fn incident_path(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("invalid incidentId"));
+        }
+        Ok(self.incidents_dir().join(format!("{id}.cnv")))
+    }
+
+    async fn read_incident(&self, id: &str) -> Result<Option<Incident>> {
+        let path = self.incident_path(id)?;
+        let blob = match tokio::fs::read(&path).await {
+            Ok(blob) => blob,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+        };
+        let plain = decrypt_blob(&self.key, &blob)?;
+        Ok(Some(
+            serde_json::from_slice(&plain).with_context(|| format!("parse {}", path.display()))?,
+        ))
+    }
+
+    async fn read_incidents(&self) -> Result<Vec<Incident>> {
+        let root = self.incidents_dir();
+        let mut out = Vec::new();
+        let mut rd = match tokio::fs::read_dir(&root).await {
+            Ok(rd) => rd,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+            Err(err) => return Err(err).with_context(|| format!("read_dir {}", root.display())),
+        };
+        while let Some(entry) = rd.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(id) = name.strip_suffix(".cnv") else {
+                continue;
+            };
+            match self.read_incident(id).await {
+                Ok(Some(incident)) => out.push(incident),
+                Ok(None) => {}
+                Err(err) => warn!(incident = id, error = %err, "unreadable incident record"),
+            }
+        }
+        Ok(out)
+    }
+
+    async fn write_incident(&self, incident: &Incident) -> Result<()> {
+        let path = self.incident_path(&incident.id)?;
+        tokio::fs::create_dir_all(self.incidents_dir())
+            .await
+            .context("create incidents dir")?;
+        let blob = seal_blob(&self.key, &serde_json::to_vec(incident)?)?;
+        let tmp = path.with_extension("cnv.tmp");
+        tokio::fs::write(&tmp, blob)
+            .await
+            .with_context(|| format!("write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .context("replace incident record")?;
+        Ok(())
+    }
+}
+
+fn clip_path(segment: &IncidentSegment) -> String {
+    format!(
+        "clips/{}/{}",
+        segment.source_id,
+        archive_entry_name(&segment.name)
+    )
+}
+
+fn snapshot_path(snapshot: &IncidentSnapshot) -> String {
+    let stem = snapshot.name.strip_suffix(".cnv").unwrap_or(&snapshot.name);
+    format!("snapshots/{}/{stem}.jpg", snapshot.source_id)
+}
+
+fn attachment_path(attachment: &Attachment) -> String {
+    let name = match attachment.name.len() <= 100 {
+        true => &attachment.name,
+        false => &attachment.id,
+    };
+    format!("attachments/{}/{name}", attachment.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{RetentionWindow, SnapshotRetention};
+
+    fn tar_paths(archive: &[u8]) -> Vec<String> {
+        let field = |bytes: &[u8]| {
+            let end = bytes
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(bytes.len());
+            String::from_utf8(bytes[..end].to_vec()).unwrap()
+        };
+        let mut paths = Vec::new();
+        let mut at = 0;
+        while archive[at] != 0 {
+            let header = &archive[at..at + 512];
+            let (prefix, name) = (field(&header[345..500]), field(&header[..100]));
+            paths.push(match prefix.is_empty() {
+                true => name,
+                false => format!("{prefix}/{name}"),
+            });
+            let size = u64::from_str_radix(&field(&header[124..135]), 8).unwrap() as usize;
+            at += 512 + size.next_multiple_of(512);
+        }
+        paths
+    }
+
+    #[tokio::test]
+    async fn incidents_hold_their_footage_until_closed_and_export_it() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-incidents-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"66".repeat(32))
+            .unwrap()
+            .with_snapshot_retention(SnapshotRetention {
+                retention_days: 1,
+                max_bytes: 0,
+            });
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        for idx in 0..2u64 {
+            let path = dir.join(format!("20200101T00000{idx}.cnv"));
+            std::fs::write(&path, seal_blob(&storage.key, b"media").unwrap()).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000 + idx))
+                .unwrap();
+        }
+        storage
+            .store_snapshot("cam-a", 1_500, b"jpeg")
+            .await
+            .unwrap();
+        storage.set_retention_windows(HashMap::from([(
+            "cam-a".to_string(),
+            RetentionWindow {
+                min_days: 0,
+                max_days: 1,
+                max_bytes: 0,
+                active: false,
+            },
+        )]));
+
+        let request = IncidentRequest {
+            title: "Break-in".to_string(),
+            from_unix: 0,
+            to_unix: 2_000,
+            source_ids: vec!["cam-a".to_string()],
+            note: "Side door".to_string(),
+        };
+        let incident = storage.create_incident(&request, "pk-admin").await.unwrap();
+        assert_eq!((incident.segments.len(), incident.snapshots.len()), (2, 1));
+        assert_eq!(incident.notes[0].author, "pk-admin");
+        assert_eq!(
+            storage.enforce_segment_retention().await.unwrap().removed,
+            0
+        );
+        assert_eq!(storage.enforce_snapshot_retention().await.unwrap().held, 1);
+
+        // A restarted node restores the hold from the sealed record.
+        let restarted = StorageManager::new(root.clone(), &"66".repeat(32)).unwrap();
+        assert_eq!(restarted.load_incident_bookmarks().await.unwrap(), 1);
+        assert_eq!(restarted.bookmarks().len(), 1);
+        assert_eq!(restarted.list_incidents(10).await.unwrap()[0].segments, 2);
+
+        let job = storage.jobs().begin_concurrent("export_incident");
+        let job_id = job.id().to_string();
+        storage
+            .prepare_incident_export(&incident, &job_id, |listing| Ok(listing.to_string()))
+            .await
+            .unwrap();
+        let report = storage.run_export(job.id(), job.progress()).await.unwrap();
+        assert_eq!(report.segments, 2);
+        let mut reader = storage.open_export(&job_id, 0).await.unwrap();
+        let mut archive = Vec::new();
+        while let Some(piece) = reader.next().await.unwrap() {
+            archive.extend_from_slice(&piece.data);
+        }
+        assert_eq!(
+            tar_paths(&archive),
+            [
+                "clips/cam-a/20200101T000000.mp4",
+                "clips/cam-a/20200101T000001.mp4",
+                &snapshot_path(&incident.snapshots[0]),
+                "timeline.json",
+                "manifest.json",
+            ]
+        );
+
+        let closed = storage
+            .close_incident(&incident.id, 0, "pk-admin")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(closed.release_unix.is_some());
+        let update = IncidentUpdate {
+            incident_id: incident.id.clone(),
+            note: "late".to_string(),
+            attachments: Vec::new(),
+        };
+        assert!(storage.update_incident(&update, "pk-admin").await.is_err());
+        assert_eq!(
+            storage.enforce_segment_retention().await.unwrap().removed,
+            2
+        );
+        assert_eq!(
+            storage.enforce_snapshot_retention().await.unwrap().removed,
+            1
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
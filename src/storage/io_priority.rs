@@ -0,0 +1,539 @@
+use crate::bandwidth::TokenBucket;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const CANARY_BYTES: usize = 64 * 1024;
+const CANARY_FILE: &str = ".io-canary";
+const SAMPLE_WINDOW: usize = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoClass {
+    Background,
+    Serving,
+}
+
+impl IoClass {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Background => "background",
+            Self::Serving => "serving",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IoPrioritySettings {
+    pub canary_interval: Duration,
+    pub latency_high: Duration,
+    pub latency_low: Duration,
+    pub background_max: u64,
+    pub background_min: u64,
+    pub serving_max: u64,
+    pub serving_min: u64,
+}
+
+impl Default for IoPrioritySettings {
+    fn default() -> Self {
+        Self {
+            canary_interval: Duration::ZERO,
+            latency_high: Duration::from_millis(250),
+            latency_low: Duration::from_millis(50),
+            background_max: 0,
+            background_min: 0,
+            serving_max: 0,
+            serving_min: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IoPriorityView {
+    pub background_bytes_per_sec: u64,
+    pub serving_bytes_per_sec: u64,
+}
+
+struct Budgets {
+    background: TokenBucket,
+    serving: TokenBucket,
+    samples: VecDeque<Duration>,
+}
+
+#[derive(Default)]
+struct Counters {
+    samples: AtomicU64,
+    failed_samples: AtomicU64,
+    background_wait_ms: AtomicU64,
+    serving_wait_ms: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct IoPriority {
+    settings: IoPrioritySettings,
+    budgets: Arc<Mutex<Budgets>>,
+    counters: Arc<Counters>,
+}
+
+impl Default for IoPriority {
+    fn default() -> Self {
+        Self::new(IoPrioritySettings::default())
+    }
+}
+
+impl IoPriority {
+    pub fn new(settings: IoPrioritySettings) -> Self {
+        let settings = IoPrioritySettings {
+            background_min: settings.background_min.min(settings.background_max),
+            serving_min: settings.serving_min.min(settings.serving_max),
+            ..settings
+        };
+        Self {
+            settings,
+            budgets: Arc::new(Mutex::new(Budgets {
+                background: TokenBucket::new(settings.background_max),
+                serving: TokenBucket::new(settings.serving_max),
+                samples: VecDeque::new(),
+            })),
+            counters: Arc::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Budgets> {
+        self.budgets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn charge(&self, class: IoClass, bytes: u64) -> Duration {
+        let wait = {
+            let mut budgets = self.lock();
+            let bucket = match class {
+                IoClass::Background => &mut budgets.background,
+                IoClass::Serving => &mut budgets.serving,
+            };
+            bucket.take(bytes, tokio::time::Instant::now())
+        };
+        let waited = match class {
+            IoClass::Background => &self.counters.background_wait_ms,
+            IoClass::Serving => &self.counters.serving_wait_ms,
+        };
+        waited.fetch_add(millis(wait), Ordering::Relaxed);
+        wait
+    }
+
+    pub async fn acquire(&self, class: IoClass, bytes: u64) {
+        let wait = self.charge(class, bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub fn observe(&self, latency: Duration) {
+        self.counters.samples.fetch_add(1, Ordering::Relaxed);
+        let settings = &self.settings;
+        let mut budgets = self.lock();
+        budgets.samples.push_back(latency);
+        if budgets.samples.len() > SAMPLE_WINDOW {
+            budgets.samples.pop_front();
+        }
+        let Budgets {
+            background,
+            serving,
+            ..
+        } = &mut *budgets;
+        if latency >= settings.latency_high {
+            if !shrink(serving, settings.serving_min) {
+                shrink(background, settings.background_min);
+            }
+        } else if latency <= settings.latency_low && !grow(background, settings.background_max) {
+            grow(serving, settings.serving_max);
+        }
+    }
+
+    pub fn view(&self) -> IoPriorityView {
+        let budgets = self.lock();
+        IoPriorityView {
+            background_bytes_per_sec: budgets.background.rate(),
+            serving_bytes_per_sec: budgets.serving.rate(),
+        }
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let (rates, samples) = {
+            let budgets = self.lock();
+            (
+                [
+                    (IoClass::Background, budgets.background.rate()),
+                    (IoClass::Serving, budgets.serving.rate()),
+                ],
+                budgets.samples.iter().copied().collect::<Vec<_>>(),
+            )
+        };
+        let mut out = String::new();
+        let name = "constitute_nvr_io_budget_bytes_per_second";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Disk budget per I/O class; 0 is unthrottled."
+        );
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (class, rate) in rates {
+            let _ = writeln!(out, "{name}{{class=\"{}\"}} {rate}", class.as_str());
+        }
+        let name = "constitute_nvr_io_throttled_seconds_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Time I/O was held back to stay within its class's budget."
+        );
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (class, waited) in [
+            (IoClass::Background, &self.counters.background_wait_ms),
+            (IoClass::Serving, &self.counters.serving_wait_ms),
+        ] {
+            let secs = waited.load(Ordering::Relaxed) as f64 / 1000.0;
+            let _ = writeln!(out, "{name}{{class=\"{}\"}} {secs:.3}", class.as_str());
+        }
+        let name = "constitute_nvr_recorder_write_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Canary write latency on the recording volume, over recent samples."
+        );
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let latest = samples.last().copied();
+        let max = samples.iter().max().copied();
+        for (stat, sample) in [("latest", latest), ("max", max)] {
+            if let Some(sample) = sample {
+                let secs = sample.as_secs_f64();
+                let _ = writeln!(out, "{name}{{stat=\"{stat}\"}} {secs:.6}");
+            }
+        }
+        for (name, help, value) in [
+            (
+                "constitute_nvr_io_canary_samples_total",
+                "Canary writes timed.",
+                &self.counters.samples,
+            ),
+            (
+                "constitute_nvr_io_canary_failures_total",
+                "Canary writes that failed and were not sampled.",
+                &self.counters.failed_samples,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
+        out
+    }
+
+    pub fn start_canary(&self, root: PathBuf) {
+        let every = self.settings.canary_interval;
+        if every.is_zero() {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(every);
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut slow = false;
+            loop {
+                tick.tick().await;
+                let path = root.join(CANARY_FILE);
+                match tokio::task::spawn_blocking(move || canary_write(&path)).await {
+                    Ok(Ok(latency)) => {
+                        this.observe(latency);
+                        let now_slow = latency >= this.settings.latency_high;
+                        if now_slow != slow {
+                            let view = this.view();
+                            match now_slow {
+                                true => info!(
+                                    latency_ms = millis(latency),
+                                    background = view.background_bytes_per_sec,
+                                    serving = view.serving_bytes_per_sec,
+                                    "recorder write latency high; shrinking I/O budgets"
+                                ),
+                                false => info!(
+                                    latency_ms = millis(latency),
+                                    "recorder write latency back under its threshold"
+                                ),
+                            }
+                            slow = now_slow;
+                        }
+                        debug!(latency_ms = millis(latency), "I/O canary");
+                    }
+                    Ok(Err(err)) => {
+                        this.counters.failed_samples.fetch_add(1, Ordering::Relaxed);
+                        warn!(error = %err, "I/O canary write failed");
+                    }
+                    Err(err) => warn!(error = %err, "I/O canary task failed"),
+                }
+            }
+        });
+    }
+}
+
+fn shrink(bucket: &mut TokenBucket, floor: u64) -> bool {
+    let rate = bucket.rate();
+    if rate == 0 || rate <= floor {
+        return false;
+    }
+    bucket.set_rate((rate / 2).max(floor));
+    true
+}
+
+fn grow(bucket: &mut TokenBucket, ceiling: u64) -> bool {
+    let rate = bucket.rate();
+    if rate == 0 || rate >= ceiling {
+        return false;
+    }
+    bucket.set_rate((rate + (ceiling / 16).max(1)).min(ceiling));
+    true
+}
+
+pub fn canary_write(path: &Path) -> std::io::Result<Duration> {
+    let data = [0x5au8; CANARY_BYTES];
+    let started = Instant::now();
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(&data)?;
+    file.sync_data()?;
+    Ok(started.elapsed())
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> IoPrioritySettings {
+        IoPrioritySettings {
+            canary_interval: Duration::from_secs(1),
+            latency_high: Duration::from_millis(100),
+            latency_low: Duration::from_millis(10),
+            background_max: 8_000,
+            background_min: 2_000,
+            serving_max: 4_000,
+            serving_min: 1_000,
+        }
+    }
+
+    fn rates(io: &IoPriority) -> (u64, u64) {
+        let view = io.view();
+        (view.background_bytes_per_sec, view.serving_bytes_per_sec)
+    }
+
+    #[test]
+    fn serving_is_shed_first_and_restored_last() {
+        let io = IoPriority::new(settings());
+        let slow = Duration::from_millis(200);
+        let fast = Duration::from_millis(1);
+
+        io.observe(slow);
+        assert_eq!(rates(&io), (8_000, 2_000));
+        io.observe(slow);
+        assert_eq!(rates(&io), (8_000, 1_000));
+        io.observe(slow);
+        assert_eq!(rates(&io), (4_000, 1_000));
+        io.observe(slow);
+        io.observe(slow);
+        assert_eq!(rates(&io), (2_000, 1_000));
+
+        // Between the thresholds nothing moves.
+        io.observe(Duration::from_millis(50));
+        assert_eq!(rates(&io), (2_000, 1_000));
+
+        for _ in 0..4 {
+            io.observe(fast);
+        }
+        assert_eq!(rates(&io), (4_000, 1_000));
+        for _ in 0..8 {
+            io.observe(fast);
+        }
+        assert_eq!(rates(&io), (8_000, 1_000));
+        io.observe(fast);
+        assert_eq!(rates(&io), (8_000, 1_250));
+        for _ in 0..20 {
+            io.observe(fast);
+        }
+        assert_eq!(rates(&io), (8_000, 4_000));
+
+        let metrics = io.render_prometheus();
+        for line in [
+            "constitute_nvr_io_budget_bytes_per_second{class=\"serving\"} 4000",
+            "constitute_nvr_recorder_write_latency_seconds{stat=\"latest\"} 0.001000",
+            "constitute_nvr_recorder_write_latency_seconds{stat=\"max\"} 0.200000",
+        ] {
+            assert!(metrics.contains(line), "{metrics}");
+        }
+        assert!(metrics.contains("constitute_nvr_io_canary_samples_total 39"));
+    }
+
+    #[test]
+    fn an_unlimited_class_is_left_alone() {
+        let io = IoPriority::default();
+        io.observe(Duration::from_secs(5));
+        assert_eq!(rates(&io), (0, 0));
+        assert_eq!(io.charge(IoClass::Background, u64::MAX), Duration::ZERO);
+
+        let io = IoPriority::new(IoPrioritySettings {
+            serving_max: 0,
+            serving_min: 0,
+            ..settings()
+        });
+        io.observe(Duration::from_secs(5));
+        assert_eq!(rates(&io), (4_000, 0));
+    }
+
+    #[test]
+    fn canary_times_a_synced_write() {
+        let dir = std::env::temp_dir().join(format!("cnvr-canary-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CANARY_FILE);
+        canary_write(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), CANARY_BYTES as u64);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+#[cfg(all(test, feature = "io-stress"))]
+mod stress {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use tokio::time::Instant;
+
+    const DISK_BYTES_PER_SEC: f64 = 32.0 * 1024.0 * 1024.0;
+    const SEEK: Duration = Duration::from_millis(2);
+    const RECORDER_WRITE: usize = 64 * 1024;
+    const RECORDER_PERIOD: Duration = Duration::from_millis(40);
+    const BULK_CHUNK: usize = 512 * 1024;
+    const CANARY_PERIOD: Duration = Duration::from_millis(100);
+    const WARMUP: Duration = Duration::from_secs(2);
+    const MEASURE: Duration = Duration::from_secs(3);
+
+    struct SlowDisk {
+        dir: PathBuf,
+        head: tokio::sync::Mutex<()>,
+    }
+
+    impl SlowDisk {
+        fn new() -> Arc<Self> {
+            let dir = std::env::temp_dir().join(format!("cnvr-io-stress-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Arc::new(Self {
+                dir,
+                head: tokio::sync::Mutex::new(()),
+            })
+        }
+
+        async fn write(&self, name: &str, bytes: usize) -> Duration {
+            let started = Instant::now();
+            let _head = self.head.lock().await;
+            let transfer = Duration::from_secs_f64(bytes as f64 / DISK_BYTES_PER_SEC);
+            tokio::time::sleep(SEEK + transfer).await;
+            std::fs::write(self.dir.join(name), vec![0u8; bytes]).unwrap();
+            started.elapsed()
+        }
+    }
+
+    impl Drop for SlowDisk {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    async fn recorder_latency(settings: IoPrioritySettings) -> (Vec<Duration>, IoPriority) {
+        let disk = SlowDisk::new();
+        let io = IoPriority::new(settings);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut tasks = Vec::new();
+
+        for (class, name) in [
+            (IoClass::Serving, "export"),
+            (IoClass::Background, "encryptor"),
+        ] {
+            let (disk, io, stop) = (Arc::clone(&disk), io.clone(), Arc::clone(&stop));
+            tasks.push(tokio::spawn(async move {
+                while !stop.load(Ordering::Relaxed) {
+                    io.acquire(class, BULK_CHUNK as u64).await;
+                    disk.write(name, BULK_CHUNK).await;
+                }
+            }));
+        }
+        {
+            let (disk, io, stop) = (Arc::clone(&disk), io.clone(), Arc::clone(&stop));
+            tasks.push(tokio::spawn(async move {
+                while !stop.load(Ordering::Relaxed) {
+                    io.observe(disk.write(CANARY_FILE, CANARY_BYTES).await);
+                    tokio::time::sleep(CANARY_PERIOD).await;
+                }
+            }));
+        }
+
+        let started = Instant::now();
+        let mut samples = Vec::new();
+        let mut tick = tokio::time::interval(RECORDER_PERIOD);
+        while started.elapsed() < WARMUP + MEASURE {
+            tick.tick().await;
+            let latency = disk.write("recorder", RECORDER_WRITE).await;
+            if started.elapsed() > WARMUP {
+                samples.push(latency);
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        samples.sort();
+        (samples, io)
+    }
+
+    fn percentile(samples: &[Duration], percent: usize) -> Duration {
+        samples[(samples.len() - 1) * percent / 100]
+    }
+
+    fn settings() -> IoPrioritySettings {
+        let max = DISK_BYTES_PER_SEC as u64;
+        IoPrioritySettings {
+            canary_interval: CANARY_PERIOD,
+            latency_high: Duration::from_millis(15),
+            latency_low: Duration::from_millis(8),
+            background_max: max,
+            background_min: 1024 * 1024,
+            serving_max: max,
+            serving_min: 512 * 1024,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recorder_latency_stays_bounded_under_export_and_backlog() {
+        let (control, _) = recorder_latency(IoPrioritySettings::default()).await;
+        let (scheduled, io) = recorder_latency(settings()).await;
+        let own = SEEK + Duration::from_secs_f64(RECORDER_WRITE as f64 / DISK_BYTES_PER_SEC);
+        let bulk = SEEK + Duration::from_secs_f64(BULK_CHUNK as f64 / DISK_BYTES_PER_SEC);
+        // Timers round each request up to the next millisecond.
+        let slack = Duration::from_millis(1);
+
+        // Unscheduled, the recorder usually queues behind a bulk request or two.
+        assert!(percentile(&control, 50) > bulk + own, "{control:?}");
+        // Scheduled, it usually finds the disk idle, rarely waits behind one bulk request,
+        // and never behind both streams at once.
+        assert!(percentile(&scheduled, 50) < own + slack, "{scheduled:?}");
+        assert!(
+            percentile(&scheduled, 95) < bulk + own + slack,
+            "{scheduled:?}"
+        );
+        assert!(
+            percentile(&scheduled, 100) < bulk * 2 + own,
+            "{scheduled:?}"
+        );
+        let view = io.view();
+        assert!(view.serving_bytes_per_sec < settings().serving_max);
+        assert!(view.background_bytes_per_sec < settings().background_max);
+    }
+}
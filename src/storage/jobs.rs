@@ -0,0 +1,449 @@
+use super::scan::CancellationToken;
+use crate::util;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+const FINISHED_RETENTION_SECS: u64 = 3600;
+const MAX_FINISHED_JOBS: usize = 50;
+const FRAME_INTERVAL: Duration = Duration::from_secs(1);
+const FRAME_BUFFER: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub job_id: String,
+    pub kind: String,
+    pub state: JobState,
+    pub phase: String,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub done: u64,
+    pub total: u64,
+    pub eta_secs: Option<u64>,
+    pub error: Option<String>,
+    pub report: Value,
+}
+
+impl JobStatus {
+    pub fn progress_frame(&self) -> Value {
+        json!({
+            "cmd": "job_progress",
+            "jobId": self.job_id,
+            "kind": self.kind,
+            "state": self.state,
+            "phase": self.phase,
+            "done": self.done,
+            "total": self.total,
+            "etaSecs": self.eta_secs,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JobFrame {
+    pub session_id: String,
+    pub status: JobStatus,
+}
+
+struct Entry {
+    status: JobStatus,
+    maintenance: bool,
+    started: Instant,
+    cancel: CancellationToken,
+    cancel_requested: bool,
+    follower: Option<String>,
+    last_frame: Option<Instant>,
+}
+
+#[derive(Clone)]
+pub struct JobRegistry {
+    entries: Arc<Mutex<Vec<Entry>>>,
+    frames: broadcast::Sender<JobFrame>,
+    shutdown: CancellationToken,
+}
+
+impl JobRegistry {
+    pub fn new(shutdown: CancellationToken) -> Self {
+        Self {
+            entries: Arc::default(),
+            frames: broadcast::channel(FRAME_BUFFER).0,
+            shutdown,
+        }
+    }
+
+    pub fn begin(&self, kind: &str) -> Result<JobHandle> {
+        self.begin_with_id(kind, uuid::Uuid::new_v4().to_string())
+    }
+
+    pub(super) fn begin_with_id(&self, kind: &str, job_id: String) -> Result<JobHandle> {
+        self.start(kind, job_id, true)
+    }
+
+    pub fn begin_concurrent(&self, kind: &str) -> JobHandle {
+        self.start(kind, uuid::Uuid::new_v4().to_string(), false)
+            .expect("jobs outside the maintenance slot are never refused")
+    }
+
+    fn start(&self, kind: &str, job_id: String, maintenance: bool) -> Result<JobHandle> {
+        let mut entries = self.lock();
+        prune(&mut entries, util::now_unix_seconds());
+        if maintenance
+            && let Some(running) = entries
+                .iter()
+                .find(|entry| entry.maintenance && entry.status.state == JobState::Running)
+        {
+            return Err(anyhow!(
+                "maintenance job {} ({}) is already running",
+                running.status.kind,
+                running.status.job_id
+            ));
+        }
+        let cancel = self.shutdown.child();
+        entries.push(Entry {
+            status: JobStatus {
+                job_id: job_id.clone(),
+                kind: kind.to_string(),
+                state: JobState::Running,
+                phase: "starting".to_string(),
+                started_at: util::now_unix_seconds(),
+                finished_at: None,
+                done: 0,
+                total: 0,
+                eta_secs: None,
+                error: None,
+                report: Value::Null,
+            },
+            maintenance,
+            started: Instant::now(),
+            cancel: cancel.clone(),
+            cancel_requested: false,
+            follower: None,
+            last_frame: None,
+        });
+        Ok(JobHandle {
+            progress: JobProgress {
+                jobs: self.clone(),
+                job_id,
+                cancel,
+            },
+            finished: false,
+        })
+    }
+
+    pub fn current(&self) -> Option<JobStatus> {
+        let entries = self.lock();
+        let running = entries
+            .iter()
+            .rev()
+            .find(|entry| entry.status.state == JobState::Running);
+        running
+            .or_else(|| entries.iter().max_by_key(|entry| entry.status.finished_at))
+            .map(|entry| entry.status.clone())
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<JobStatus> {
+        self.lock()
+            .iter()
+            .find(|entry| entry.status.job_id == job_id)
+            .map(|entry| entry.status.clone())
+    }
+
+    pub fn follow(&self, job_id: &str, session_id: &str) -> Option<JobStatus> {
+        let mut entries = self.lock();
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.status.job_id == job_id)?;
+        if entry.status.state == JobState::Running {
+            entry.follower = Some(session_id.to_string());
+        }
+        Some(entry.status.clone())
+    }
+
+    pub fn cancel(&self, job_id: &str) -> Option<(JobStatus, bool)> {
+        let mut entries = self.lock();
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.status.job_id == job_id)?;
+        let running = entry.status.state == JobState::Running;
+        if running {
+            entry.cancel_requested = true;
+            entry.cancel.cancel();
+        }
+        Some((entry.status.clone(), running))
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobFrame> {
+        self.frames.subscribe()
+    }
+
+    fn update(&self, job_id: &str, force: bool, change: impl FnOnce(&mut Entry)) {
+        let mut entries = self.lock();
+        let Some(entry) = entries
+            .iter_mut()
+            .find(|entry| entry.status.job_id == job_id)
+        else {
+            return;
+        };
+        if entry.status.state != JobState::Running {
+            return;
+        }
+        change(entry);
+        let Some(session_id) = entry.follower.clone() else {
+            return;
+        };
+        let due = entry
+            .last_frame
+            .is_none_or(|last| last.elapsed() >= FRAME_INTERVAL);
+        if force || due {
+            entry.last_frame = Some(Instant::now());
+            let _ = self.frames.send(JobFrame {
+                session_id,
+                status: entry.status.clone(),
+            });
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn prune(entries: &mut Vec<Entry>, now: u64) {
+    entries.retain(|entry| {
+        entry
+            .status
+            .finished_at
+            .is_none_or(|finished| now.saturating_sub(finished) < FINISHED_RETENTION_SECS)
+    });
+    let finished = entries
+        .iter()
+        .filter(|entry| entry.status.finished_at.is_some())
+        .count();
+    let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+    entries.retain(|entry| {
+        if excess > 0 && entry.status.finished_at.is_some() {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+}
+
+#[derive(Clone)]
+pub struct JobProgress {
+    jobs: JobRegistry,
+    job_id: String,
+    cancel: CancellationToken,
+}
+
+impl JobProgress {
+    pub fn id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn phase(&self, phase: &str) {
+        self.jobs.update(&self.job_id, false, |entry| {
+            entry.status.phase = phase.to_string();
+        });
+    }
+
+    pub fn progress(&self, done: u64, total: u64) {
+        self.jobs.update(&self.job_id, false, |entry| {
+            entry.status.done = done;
+            entry.status.total = total;
+            entry.status.eta_secs = eta_secs(entry.started.elapsed(), done, total);
+        });
+    }
+}
+
+fn eta_secs(elapsed: Duration, done: u64, total: u64) -> Option<u64> {
+    if done == 0 || total == 0 {
+        return None;
+    }
+    let left = total.saturating_sub(done) as f64;
+    Some((elapsed.as_secs_f64() / done as f64 * left).round() as u64)
+}
+
+pub struct JobHandle {
+    progress: JobProgress,
+    finished: bool,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> &str {
+        self.progress.id()
+    }
+
+    pub fn progress(&self) -> &JobProgress {
+        &self.progress
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.progress
+            .jobs
+            .get(self.id())
+            .expect("job status exists while its handle is held")
+    }
+
+    pub fn spawn<T, F, Fut>(self, work: F) -> JobStatus
+    where
+        F: FnOnce(JobProgress) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Serialize + Send + 'static,
+    {
+        let status = self.status();
+        let kind = status.kind.clone();
+        let run = work(self.progress.clone());
+        tokio::spawn(async move {
+            let result = run.await;
+            if let Err(err) = &result {
+                warn!(job_id = %self.id(), kind = %kind, error = %err, "job failed");
+            }
+            self.finish(&result);
+        });
+        status
+    }
+
+    pub fn finish<T: Serialize>(mut self, result: &Result<T>) {
+        match result {
+            Ok(report) => self.settle(
+                JobState::Completed,
+                None,
+                serde_json::to_value(report).unwrap_or(Value::Null),
+            ),
+            Err(err) => self.settle(JobState::Failed, Some(err.to_string()), Value::Null),
+        }
+    }
+
+    fn settle(&mut self, state: JobState, error: Option<String>, report: Value) {
+        self.finished = true;
+        let now = util::now_unix_seconds();
+        self.progress
+            .jobs
+            .update(self.progress.id(), true, |entry| {
+                entry.status.state = if entry.cancel_requested {
+                    JobState::Cancelled
+                } else {
+                    state
+                };
+                entry.status.phase = "finished".to_string();
+                entry.status.finished_at = Some(now);
+                entry.status.eta_secs = None;
+                entry.status.error = error;
+                entry.status.report = report;
+            });
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.settle(
+                JobState::Failed,
+                Some("job stopped before finishing".to_string()),
+                Value::Null,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_job_at_a_time_and_unfinished_handles_fail() {
+        let jobs = JobRegistry::new(CancellationToken::default());
+        let first = jobs.begin("migrate_day_layout").unwrap();
+        assert!(jobs.begin("reencrypt_archive").is_err());
+        first.progress().progress(3, 10);
+        assert_eq!(jobs.current().unwrap().done, 3);
+        first.finish(&Ok(serde_json::json!({ "segments": 3 })));
+        let done = jobs.current().unwrap();
+        assert_eq!(done.state, JobState::Completed);
+        assert_eq!(done.report["segments"], 3);
+
+        let second = jobs.begin("reencrypt_archive").unwrap();
+        let id = second.id().to_string();
+        drop(second);
+        let failed = jobs.get(&id).unwrap();
+        assert_eq!(failed.state, JobState::Failed);
+        assert!(jobs.begin("reencrypt_archive").is_ok());
+    }
+
+    #[test]
+    fn followers_get_throttled_frames_and_cancelled_jobs_say_so() {
+        let shutdown = CancellationToken::default();
+        let jobs = JobRegistry::new(shutdown.clone());
+        let mut frames = jobs.subscribe();
+        let purge = jobs.begin_concurrent("purge_range");
+        let migration = jobs.begin("migrate_opaque_names").unwrap();
+        assert!(jobs.follow(purge.id(), "session-a").is_some());
+
+        purge.progress().progress(1, 4);
+        purge.progress().progress(2, 4);
+        migration.progress().progress(1, 2);
+        let frame = frames.try_recv().unwrap();
+        assert_eq!(frame.session_id, "session-a");
+        assert_eq!(frame.status.done, 1);
+        assert_eq!(frame.status.progress_frame()["cmd"], "job_progress");
+        // The second update fell inside the same second, and nobody follows the migration.
+        assert!(frames.try_recv().is_err());
+
+        let (_, taken) = jobs.cancel(purge.id()).unwrap();
+        assert!(taken && purge.progress().is_cancelled());
+        assert!(!migration.progress().is_cancelled());
+        let id = purge.id().to_string();
+        purge.finish(&Ok(serde_json::json!({ "segments": 2 })));
+        let last = frames.try_recv().unwrap();
+        assert_eq!(last.status.state, JobState::Cancelled);
+        let cancelled = jobs.get(&id).unwrap();
+        assert_eq!(cancelled.report["segments"], 2);
+        assert!(!jobs.cancel(&id).unwrap().1);
+
+        shutdown.cancel();
+        assert!(migration.progress().is_cancelled());
+    }
+
+    #[test]
+    fn finished_jobs_are_kept_for_a_bounded_time() {
+        let jobs = JobRegistry::new(CancellationToken::default());
+        for _ in 0..MAX_FINISHED_JOBS + 5 {
+            jobs.begin_concurrent("create_share")
+                .finish(&Ok(Value::Null));
+        }
+        let mut entries = jobs.lock();
+        prune(&mut entries, util::now_unix_seconds());
+        assert_eq!(entries.len(), MAX_FINISHED_JOBS);
+        prune(
+            &mut entries,
+            util::now_unix_seconds() + FINISHED_RETENTION_SECS,
+        );
+        assert!(entries.is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+use super::day_index::INDEX_FILE;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+pub(super) struct SegmentFile {
+    pub name: String,
+    pub metadata: std::fs::Metadata,
+}
+
+pub(super) fn is_day_dir(name: &str) -> bool {
+    name.len() == 8 && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+pub(super) fn split_name(name: &str) -> Option<(&str, &str)> {
+    let (day, file) = name.split_once('T')?;
+    let (time, ext) = file.split_once('.')?;
+    let plain_ext = !ext.is_empty() && !ext.contains(['/', '\\']);
+    let valid_time = time.len() == 6 && time.bytes().all(|b| b.is_ascii_digit());
+    (is_day_dir(day) && valid_time && plain_ext).then_some((day, file))
+}
+
+pub(super) fn dated_path(source_dir: &Path, name: &str) -> Option<PathBuf> {
+    let (day, file) = split_name(name)?;
+    Some(source_dir.join(day).join(file))
+}
+
+pub(super) fn locate(path: &Path) -> Option<(PathBuf, String)> {
+    let file = path.file_name()?.to_string_lossy().to_string();
+    let parent = path.parent()?;
+    let parent_name = parent.file_name()?.to_string_lossy().to_string();
+    if is_day_dir(&parent_name) {
+        Some((
+            parent.parent()?.to_path_buf(),
+            format!("{parent_name}T{file}"),
+        ))
+    } else {
+        Some((parent.to_path_buf(), file))
+    }
+}
+
+pub(super) async fn segment_files(source_dir: &Path) -> Result<Vec<SegmentFile>> {
+    let mut out = Vec::new();
+    let mut rd = match tokio::fs::read_dir(source_dir).await {
+        Ok(rd) => rd,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = rd.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type().await?;
+        if file_type.is_dir() && is_day_dir(&name) {
+            let mut day = match tokio::fs::read_dir(entry.path()).await {
+                Ok(day) => day,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            while let Some(inner) = day.next_entry().await? {
+                let file = inner.file_name().to_string_lossy().to_string();
+                if !inner.file_type().await?.is_file() || !is_segment_file(&file) {
+                    continue;
+                }
+                out.push(SegmentFile {
+                    name: format!("{name}T{file}"),
+                    metadata: inner.metadata().await?,
+                });
+            }
+        } else if file_type.is_file() && is_segment_file(&name) {
+            out.push(SegmentFile {
+                metadata: entry.metadata().await?,
+                name,
+            });
+        }
+    }
+    Ok(out)
+}
+
+pub(super) fn move_flat_segments(source_dir: &Path) -> Result<usize> {
+    let mut files = std::fs::read_dir(source_dir)
+        .with_context(|| format!("read_dir {}", source_dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut moved = 0;
+    for path in files {
+        let Some(name) = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+        else {
+            continue;
+        };
+        if !is_segment_file(&name) {
+            continue;
+        }
+        let Some(target) = dated_path(source_dir, &name) else {
+            continue;
+        };
+        if target.exists() {
+            continue;
+        }
+        if let Some(day_dir) = target.parent() {
+            std::fs::create_dir_all(day_dir)
+                .with_context(|| format!("create {}", day_dir.display()))?;
+        }
+        std::fs::rename(&path, &target)
+            .with_context(|| format!("move {} to {}", path.display(), target.display()))?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+pub(super) fn has_flat_segments(source_dir: &Path) -> Result<bool> {
+    for entry in std::fs::read_dir(source_dir)
+        .with_context(|| format!("read_dir {}", source_dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && is_segment_file(&name) && split_name(&name).is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub(super) fn local_today() -> String {
+    chrono::Local::now().format("%Y%m%d").to_string()
+}
+
+pub(super) fn remove_if_empty(day_dir: &Path) -> Result<bool> {
+    let index_tmp = format!("{INDEX_FILE}.tmp");
+    let entries = match std::fs::read_dir(day_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err).with_context(|| format!("read_dir {}", day_dir.display())),
+    };
+    for entry in entries {
+        let name = entry?.file_name();
+        if name != INDEX_FILE && name != index_tmp.as_str() {
+            return Ok(false);
+        }
+    }
+    for file in [INDEX_FILE, &index_tmp] {
+        match std::fs::remove_file(day_dir.join(file)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("remove {file}")),
+        }
+    }
+    match std::fs::remove_dir(day_dir) {
+        Ok(()) => Ok(true),
+        // A segment landed in between; the index it needs is rewritten when it is sealed.
+        Err(err) if err.kind() == std::io::ErrorKind::DirectoryNotEmpty => Ok(false),
+        Err(err) => Err(err).with_context(|| format!("remove {}", day_dir.display())),
+    }
+}
+
+pub(super) fn remove_empty_day_dirs(source_dir: &Path, today: &str) -> Result<usize> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(source_dir)
+        .with_context(|| format!("read_dir {}", source_dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if is_day_dir(&name)
+            && name.as_str() < today
+            && entry.file_type()?.is_dir()
+            && remove_if_empty(&entry.path())?
+        {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+pub(super) fn is_segment_file(name: &str) -> bool {
+    name.ends_with(".cnv") || name.ends_with(".mp4")
+}
+
+pub fn is_segment_name(name: &str) -> bool {
+    super::snapshots::is_plain_component(name) && !name.starts_with('.') && is_segment_file(name)
+}
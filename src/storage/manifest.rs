@@ -0,0 +1,610 @@
+use super::{StorageError, StorageManager, layout, modified_unix, name_map, resolve_segment_path};
+use anyhow::{Context, Result, anyhow};
+use hmac::{Hmac, Mac};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub(super) const MANIFEST_FILE: &str = ".manifest.jsonl";
+const MAC_CONTEXT: &[u8] = b"constitute-nvr:segment-manifest:v1";
+const START_LINE: &str = "";
+const TAIL_BYTES: u64 = 4096;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ManifestLine {
+    pub name: String,
+    pub bytes: u64,
+    pub sha256: String,
+    pub created_unix: u64,
+    mac: String,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Manifest {
+    pub entries: HashMap<String, ManifestLine>,
+    pub started_unix: Option<u64>,
+    pub broken_at: Option<usize>,
+}
+
+pub(super) type ManifestCache = Arc<std::sync::Mutex<HashMap<PathBuf, CachedManifest>>>;
+
+#[derive(Debug)]
+pub(super) struct CachedManifest {
+    len: u64,
+    modified: SystemTime,
+    manifest: Arc<Manifest>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentMismatch {
+    pub name: String,
+    pub expected_sha256: String,
+    pub actual_sha256: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub source_id: String,
+    pub manifest_intact: bool,
+    pub manifest_broken_at: Option<usize>,
+    pub segments: usize,
+    pub checked: usize,
+    pub verified: usize,
+    pub mismatched: Vec<SegmentMismatch>,
+    pub unrecorded: Vec<String>,
+}
+
+fn line_mac(key: &[u8], prev: &str, line: &ManifestLine) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(MAC_CONTEXT);
+    let bytes = line.bytes.to_string();
+    let created = line.created_unix.to_string();
+    for field in [prev, &line.name, &bytes, &line.sha256, &created] {
+        mac.update(&(field.len() as u64).to_be_bytes());
+        mac.update(field.as_bytes());
+    }
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn sealed_line(key: &[u8], prev: &str, name: &str, bytes: u64, sha256: String) -> ManifestLine {
+    let mut line = ManifestLine {
+        name: name.to_string(),
+        bytes,
+        sha256,
+        created_unix: crate::util::now_unix_seconds(),
+        mac: String::new(),
+    };
+    line.mac = line_mac(key, prev, &line);
+    line
+}
+
+pub(super) fn record(source_dir: &Path, key: &[u8], name: &str, blob: &[u8]) -> Result<()> {
+    let path = source_dir.join(MANIFEST_FILE);
+    let mut file = std::fs::File::options()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open {}", path.display()))?;
+    let mut prev = last_mac(&mut file).with_context(|| format!("read {}", path.display()))?;
+    let mut out = Vec::new();
+    let empty = file
+        .metadata()
+        .with_context(|| format!("stat {}", path.display()))?
+        .len()
+        == 0;
+    if empty {
+        // Kept through deletions, so segments sealed since the start stay accounted for.
+        let start = sealed_line(key, &prev, START_LINE, 0, String::new());
+        prev = start.mac.clone();
+        out.extend(serde_json::to_vec(&start)?);
+        out.push(b'\n');
+    }
+    let sha256 = hex::encode(Sha256::digest(blob));
+    let line = sealed_line(key, &prev, name, blob.len() as u64, sha256);
+    out.extend(serde_json::to_vec(&line)?);
+    out.push(b'\n');
+    file.write_all(&out)
+        .and_then(|()| file.sync_data())
+        .with_context(|| format!("append to {}", path.display()))
+}
+
+pub(super) fn record_sealed(
+    path: &Path,
+    key: &[u8],
+    name: Option<&str>,
+    blob: &[u8],
+) -> Result<()> {
+    let (dir, listed) = layout::locate(path)
+        .ok_or_else(|| anyhow!("segment has no source dir: {}", path.display()))?;
+    record(&dir, key, name.unwrap_or(&listed), blob)
+}
+
+pub(super) fn record_if_missing(path: &Path, key: &[u8]) -> Result<()> {
+    let (dir, listed) = layout::locate(path)
+        .ok_or_else(|| anyhow!("segment has no source dir: {}", path.display()))?;
+    if load(&dir, key)?.entries.contains_key(&listed) {
+        return Ok(());
+    }
+    let blob = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    record(&dir, key, &listed, &blob)
+}
+
+pub(super) fn unrecorded(paths: &[PathBuf], key: &[u8]) -> Result<Vec<PathBuf>> {
+    let mut by_dir = HashMap::<PathBuf, Vec<(&PathBuf, String)>>::new();
+    for path in paths {
+        if let Some((dir, listed)) = layout::locate(path) {
+            by_dir.entry(dir).or_default().push((path, listed));
+        }
+    }
+    let mut out = Vec::new();
+    for (dir, files) in by_dir {
+        let manifest = load(&dir, key)?;
+        let since = manifest.started_unix;
+        let Some(since) = since.filter(|_| manifest.broken_at.is_none()) else {
+            continue;
+        };
+        let map = match name_map::has_map(&dir) {
+            true => Some(name_map::load(&dir, key)?),
+            false => None,
+        };
+        for (path, listed) in files {
+            let mapped = map
+                .as_ref()
+                .and_then(|map| map.get(listed.trim_end_matches(".cnv")));
+            let name = match mapped {
+                Some(entry) => entry.name.clone(),
+                None if name_map::segment_start_unix(&listed).is_some() => listed,
+                None => match name_map::embedded_name(path, key) {
+                    Ok(Some(name)) => name,
+                    // Unreadable, or holding no name: nothing to look it up by.
+                    _ => continue,
+                },
+            };
+            if !manifest.entries.contains_key(&name) && modified_unix(path) >= since {
+                out.push(path.clone());
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn last_mac(file: &mut std::fs::File) -> std::io::Result<String> {
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let whole = tail
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |at| at + 1);
+    if whole < tail.len() {
+        file.set_len(start + whole as u64)?;
+    }
+    let lines = &tail[..whole];
+    let last = lines[..lines.len().saturating_sub(1)]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(lines, |at| &lines[at + 1..]);
+    if last.is_empty() {
+        return Ok(String::new());
+    }
+    // A garbled last line is chained from nothing, so the break shows but sealing goes on.
+    Ok(serde_json::from_slice::<ManifestLine>(last)
+        .map(|line| line.mac)
+        .unwrap_or_default())
+}
+
+pub(super) fn load(source_dir: &Path, key: &[u8]) -> Result<Manifest> {
+    let path = source_dir.join(MANIFEST_FILE);
+    let raw = match std::fs::read(&path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Manifest::default()),
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+    // A last line without its newline is still being appended, or was torn by a crash.
+    let whole = raw
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |at| at + 1);
+    let mut manifest = Manifest::default();
+    let mut prev = String::new();
+    for (idx, raw_line) in raw[..whole].split(|byte| *byte == b'\n').enumerate() {
+        if raw_line.is_empty() {
+            continue;
+        }
+        let line = serde_json::from_slice::<ManifestLine>(raw_line)
+            .ok()
+            .filter(|line| line.mac == line_mac(key, &prev, line));
+        let Some(line) = line else {
+            manifest.broken_at = Some(idx + 1);
+            break;
+        };
+        prev = line.mac.clone();
+        if line.name == START_LINE {
+            manifest.started_unix.get_or_insert(line.created_unix);
+        } else {
+            manifest.entries.insert(line.name.clone(), line);
+        }
+    }
+    if manifest.started_unix.is_none() {
+        manifest.started_unix = manifest
+            .entries
+            .values()
+            .map(|line| line.created_unix)
+            .min();
+    }
+    Ok(manifest)
+}
+
+pub(super) fn forget(source_dir: &Path, key: &[u8], names: &[String]) -> Result<()> {
+    let path = source_dir.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let manifest = load(source_dir, key)?;
+    if names
+        .iter()
+        .all(|name| !manifest.entries.contains_key(name))
+    {
+        return Ok(());
+    }
+    let mut kept = manifest
+        .entries
+        .into_values()
+        .filter(|line| !names.contains(&line.name))
+        .collect::<Vec<_>>();
+    kept.sort_by(|a, b| (a.created_unix, &a.name).cmp(&(b.created_unix, &b.name)));
+    if let Some(started_unix) = manifest.started_unix {
+        kept.insert(
+            0,
+            ManifestLine {
+                name: START_LINE.to_string(),
+                bytes: 0,
+                sha256: String::new(),
+                created_unix: started_unix,
+                mac: String::new(),
+            },
+        );
+    }
+    let mut out = Vec::new();
+    let mut prev = String::new();
+    for line in kept {
+        let mut line = ManifestLine {
+            mac: String::new(),
+            ..line
+        };
+        line.mac = line_mac(key, &prev, &line);
+        prev = line.mac.clone();
+        out.extend(serde_json::to_vec(&line)?);
+        out.push(b'\n');
+    }
+    let tmp = source_dir.join(format!("{MANIFEST_FILE}.tmp"));
+    std::fs::write(&tmp, out).with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("replace {}", path.display()))
+}
+
+fn check_hash(
+    source_id: &str,
+    name: &str,
+    line: &ManifestLine,
+    actual: String,
+) -> Result<(), StorageError> {
+    if actual == line.sha256 {
+        return Ok(());
+    }
+    Err(StorageError::Corrupt(format!(
+        "segment {source_id}/{name} does not match its manifest hash"
+    )))
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            return Ok(hex::encode(hasher.finalize()));
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+impl StorageManager {
+    async fn cached_manifest(&self, source_dir: &Path) -> Result<Arc<Manifest>> {
+        let path = source_dir.join(MANIFEST_FILE);
+        let signature = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => Some((metadata.len(), metadata.modified()?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err).with_context(|| format!("stat {}", path.display())),
+        };
+        let Some((len, modified)) = signature else {
+            return Ok(Arc::default());
+        };
+        if let Some(cached) = self
+            .manifests
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(source_dir)
+            .filter(|cached| cached.len == len && cached.modified == modified)
+        {
+            return Ok(Arc::clone(&cached.manifest));
+        }
+        let dir = source_dir.to_path_buf();
+        let key = self.key.clone();
+        let manifest = tokio::task::spawn_blocking(move || load(&dir, &key))
+            .await
+            .context("join manifest read")??;
+        let manifest = Arc::new(manifest);
+        self.manifests
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                source_dir.to_path_buf(),
+                CachedManifest {
+                    len,
+                    modified,
+                    manifest: Arc::clone(&manifest),
+                },
+            );
+        Ok(manifest)
+    }
+
+    async fn read_check(
+        &self,
+        source_id: &str,
+        name: &str,
+    ) -> Result<Option<ManifestLine>, StorageError> {
+        if !self.verify_reads {
+            return Ok(None);
+        }
+        let manifest = self
+            .cached_manifest(&self.segments_dir(source_id))
+            .await
+            .map_err(|err| StorageError::Corrupt(format!("{err:#}")))?;
+        Ok(manifest.entries.get(name).cloned())
+    }
+
+    pub(super) async fn verify_blob(
+        &self,
+        source_id: &str,
+        name: &str,
+        blob: &[u8],
+    ) -> Result<(), StorageError> {
+        match self.read_check(source_id, name).await? {
+            Some(line) => check_hash(source_id, name, &line, hex::encode(Sha256::digest(blob))),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) async fn verify_file(
+        &self,
+        source_id: &str,
+        name: &str,
+        path: &Path,
+    ) -> Result<(), StorageError> {
+        let Some(line) = self.read_check(source_id, name).await? else {
+            return Ok(());
+        };
+        let owned = path.to_path_buf();
+        let actual = tokio::task::spawn_blocking(move || hash_file(&owned))
+            .await
+            .map_err(|err| StorageError::Corrupt(format!("join segment hash: {err}")))?
+            .map_err(|err| StorageError::read(source_id, name, path, err))?;
+        check_hash(source_id, name, &line, actual)
+    }
+
+    pub async fn verify_segments(
+        &self,
+        source_id: &str,
+        sample: Option<usize>,
+    ) -> Result<VerifyReport> {
+        let dir = self.segments_dir(source_id);
+        let mut names = self
+            .list_segments(source_id, usize::MAX)
+            .await?
+            .into_iter()
+            .map(|entry| entry.name)
+            .filter(|name| name.ends_with(".cnv"))
+            .collect::<Vec<_>>();
+        let segments = names.len();
+        if let Some(sample) = sample {
+            names.shuffle(&mut rand::thread_rng());
+            names.truncate(sample);
+            names.sort();
+        }
+        let manifest = self.cached_manifest(&dir).await?;
+        let map = self.load_name_map(&dir).await?;
+        let mut report = VerifyReport {
+            source_id: source_id.to_string(),
+            manifest_intact: manifest.broken_at.is_none(),
+            manifest_broken_at: manifest.broken_at,
+            segments,
+            ..VerifyReport::default()
+        };
+        for name in names {
+            let Some(line) = manifest.entries.get(&name) else {
+                report.checked += 1;
+                report.unrecorded.push(name);
+                continue;
+            };
+            let path = resolve_segment_path(&dir, map.as_ref(), &name);
+            let hashed = tokio::task::spawn_blocking(move || hash_file(&path))
+                .await
+                .context("join segment hash")?;
+            let actual = match hashed {
+                Ok(actual) => actual,
+                // Deleted since it was listed.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(anyhow!(err).context(format!("hash segment {name}"))),
+            };
+            self.io
+                .acquire(super::IoClass::Background, line.bytes)
+                .await;
+            report.checked += 1;
+            if actual == line.sha256 {
+                report.verified += 1;
+            } else {
+                report.mismatched.push(SegmentMismatch {
+                    name,
+                    expected_sha256: line.sha256.clone(),
+                    actual_sha256: actual,
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sealed_segments_are_recorded_and_tampering_is_found() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-manifest-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let source_dir = root.join("segments").join("cam-a");
+        let day = source_dir.join("20240101");
+        std::fs::create_dir_all(&day).unwrap();
+        let now = crate::util::now_unix_seconds();
+        for (name, age) in [("000000", 7200), ("000010", 3600), ("000020", 1800)] {
+            let path = day.join(format!("{name}.mp4"));
+            std::fs::write(&path, name).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(now - age))
+                .unwrap();
+        }
+        let storage = StorageManager::new(root.clone(), &"66".repeat(32))
+            .unwrap()
+            .with_verified_reads(true);
+        storage.encrypt_pending_once().await.unwrap();
+
+        let report = storage.verify_segments("cam-a", None).await.unwrap();
+        assert!(report.manifest_intact);
+        assert_eq!((report.segments, report.verified), (3, 3));
+        assert_eq!(
+            storage
+                .verify_segments("cam-a", Some(2))
+                .await
+                .unwrap()
+                .checked,
+            2
+        );
+
+        // Flip a bit in one blob: the check and verified reads both catch it.
+        let rotten = day.join("000010.cnv");
+        let mut blob = std::fs::read(&rotten).unwrap();
+        let last = blob.len() - 1;
+        blob[last] ^= 1;
+        std::fs::write(&rotten, &blob).unwrap();
+        let report = storage.verify_segments("cam-a", None).await.unwrap();
+        assert_eq!(report.verified, 2);
+        assert_eq!(report.mismatched.len(), 1);
+        assert_eq!(report.mismatched[0].name, "20240101T000010.cnv");
+        let read = storage.read_segment("cam-a", "20240101T000010.cnv").await;
+        assert!(matches!(read, Err(StorageError::Corrupt(_))));
+        assert!(
+            storage
+                .read_segment("cam-a", "20240101T000000.cnv")
+                .await
+                .is_ok()
+        );
+
+        // Deleting forgets the line and keeps the chain whole.
+        let names = ["20240101T000010.cnv".to_string()];
+        storage
+            .delete_segments("cam-a", &names, true)
+            .await
+            .unwrap();
+        let report = storage.verify_segments("cam-a", None).await.unwrap();
+        assert!(report.manifest_intact);
+        assert_eq!((report.segments, report.verified), (2, 2));
+
+        // A rewritten line breaks the chain from there on.
+        let path = source_dir.join(MANIFEST_FILE);
+        let raw = std::fs::read_to_string(&path).unwrap();
+        let forged = raw.replacen("\"bytes\":", "\"bytes\":1", 1);
+        std::fs::write(&path, forged).unwrap();
+        let report = storage.verify_segments("cam-a", None).await.unwrap();
+        assert!(!report.manifest_intact);
+        assert_eq!(report.manifest_broken_at, Some(1));
+        assert_eq!(report.unrecorded.len(), 2);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn the_start_outlives_deleted_lines_and_unmapped_opaque_files_are_counted() {
+        let dir = std::env::temp_dir()
+            .join(format!(
+                "constitute-nvr-storage-manifest-start-test-{}",
+                std::process::id()
+            ))
+            .join("cam-a");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = [5u8; 32];
+        record(&dir, &key, "20240101T000000.cnv", b"first").unwrap();
+        let started = load(&dir, &key).unwrap().started_unix;
+        assert!(started.is_some());
+
+        // Forgetting every segment keeps when the manifest began.
+        forget(&dir, &key, &["20240101T000000.cnv".to_string()]).unwrap();
+        let manifest = load(&dir, &key).unwrap();
+        assert!(manifest.entries.is_empty());
+        assert_eq!((manifest.started_unix, manifest.broken_at), (started, None));
+
+        // An opaque file the name map never learned of is looked up by its sealed name.
+        let blob = super::super::chunked::seal(&key, Some("20240101T000010.cnv"), b"x").unwrap();
+        let orphan = dir.join("0123456789abcdef.cnv");
+        std::fs::write(&orphan, &blob).unwrap();
+        let found = unrecorded(std::slice::from_ref(&orphan), &key).unwrap();
+        assert_eq!(found, std::slice::from_ref(&orphan));
+        record(&dir, &key, "20240101T000010.cnv", &blob).unwrap();
+        assert!(unrecorded(&[orphan], &key).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(dir.parent().unwrap());
+    }
+
+    #[test]
+    fn appends_cut_off_a_torn_last_line() {
+        let dir = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-manifest-torn-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = [3u8; 32];
+        record(&dir, &key, "a.cnv", b"first").unwrap();
+        let path = dir.join(MANIFEST_FILE);
+        let mut file = std::fs::File::options().append(true).open(&path).unwrap();
+        file.write_all(b"{\"name\":\"b.cn").unwrap();
+        record(&dir, &key, "c.cnv", b"third").unwrap();
+
+        let manifest = load(&dir, &key).unwrap();
+        assert_eq!(manifest.broken_at, None);
+        let mut names = manifest.entries.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.cnv", "c.cnv"]);
+        assert_eq!(
+            manifest.entries["c.cnv"].sha256,
+            hex::encode(Sha256::digest(b"third"))
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,2760 @@
+mod attachments;
+mod backfill;
+mod bookmarks;
+mod chunked;
+mod clock;
+mod coverage;
+mod day_index;
+mod disk;
+mod disk_space;
+mod encryptor;
+mod error;
+mod exports;
+mod format;
+mod fragments;
+mod history;
+mod incidents;
+mod io_priority;
+mod jobs;
+mod layout;
+mod manifest;
+mod name_map;
+mod pre_delete;
+mod protection;
+mod range_spool;
+mod reencrypt;
+mod replicas;
+mod scan;
+mod segment_cache;
+mod segment_db;
+mod shares;
+mod snapshots;
+mod source_stats;
+mod zone_retention;
+
+use crate::crypto;
+use crate::latency::Unreliable;
+use crate::stats::{Counter, StatsRegistry};
+use anyhow::{Context, Result, anyhow};
+pub use attachments::{
+    Attachment, AttachmentLimits, AttachmentRequest, MAX_OPEN_UPLOADS, UploadError,
+};
+pub use backfill::BackfillRequest;
+pub use bookmarks::Bookmark;
+pub use chunked::SegmentStream;
+use clock::{ClockStep, ClockWatch};
+use day_index::SegmentTime;
+pub use day_index::mp4_duration_ms;
+pub use disk::DiskUsage;
+pub use disk_space::DiskGuardSettings;
+use encryptor::EncryptPass;
+pub use encryptor::EncryptorStatus;
+pub use error::StorageError;
+use exports::LiveExport;
+pub use exports::{ExportManifest, ExportReader, ExportRequest, ExportSettings};
+use futures_util::StreamExt;
+pub use history::{SourceChange, SourceRevision};
+pub use incidents::{IncidentRequest, IncidentUpdate};
+pub use io_priority::{IoClass, IoPriority, IoPrioritySettings};
+use jobs::JobRegistry;
+pub use jobs::{JobProgress, JobStatus};
+pub use layout::is_segment_name;
+use name_map::{NameMap, NameMapEntry};
+pub use pre_delete::PreDeleteHook;
+pub use reencrypt::ReencryptRequest;
+pub use replicas::ReplicaConfig;
+use scan::{CancellationToken, ScanSpec};
+use segment_cache::SegmentCache;
+pub use segment_cache::{Plaintext, SegmentCacheSettings};
+use segment_db::IndexQuery;
+use serde::Serialize;
+pub use shares::{Share, ShareAccess, ShareRequest};
+use snapshots::RetentionStatus;
+pub use snapshots::SnapshotRetention;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
+pub use zone_retention::RetentionWindow;
+
+const MAGIC: &[u8] = b"CNRV1";
+const MIGRATE_OPAQUE_JOB: &str = "migrate_opaque_names";
+const MIGRATE_DAY_LAYOUT_JOB: &str = "migrate_day_layout";
+const IN_PROGRESS_QUIET_SECS: u64 = 60;
+const MAGIC_NAMED: &[u8] = b"CNRN1";
+
+#[derive(Clone)]
+pub struct StorageManager {
+    root: PathBuf,
+    key: Zeroizing<Vec<u8>>,
+    opaque_names: bool,
+    name_map_lock: Arc<std::sync::Mutex<()>>,
+    snapshot_retention: SnapshotRetention,
+    pre_delete_hook: Option<PreDeleteHook>,
+    retention_status: Arc<std::sync::Mutex<RetentionStatus>>,
+    retention_windows: Arc<std::sync::RwLock<HashMap<String, RetentionWindow>>>,
+    segment_prunes: Arc<std::sync::Mutex<HashMap<String, zone_retention::SegmentPrune>>>,
+    swept_day: Arc<std::sync::Mutex<String>>,
+    stats: StatsRegistry,
+    jobs: JobRegistry,
+    cancel: CancellationToken,
+    clock: ClockWatch,
+    share_lock: Arc<tokio::sync::Mutex<()>>,
+    bookmarks: Arc<std::sync::RwLock<HashMap<String, Vec<Bookmark>>>>,
+    incident_lock: Arc<tokio::sync::Mutex<()>>,
+    attachment_limits: AttachmentLimits,
+    attachment_lock: Arc<tokio::sync::Mutex<()>>,
+    export_settings: ExportSettings,
+    exports: Arc<std::sync::Mutex<HashMap<String, Arc<LiveExport>>>>,
+    history_lock: Arc<tokio::sync::Mutex<()>>,
+    root_lost: Arc<std::sync::Mutex<Option<String>>>,
+    format: Arc<AtomicU32>,
+    segment_cache: SegmentCache,
+    fragment_cache: SegmentCache,
+    io: IoPriority,
+    segment_db: Arc<segment_db::SegmentDb>,
+    source_stats: Arc<tokio::sync::Mutex<source_stats::SourceStatsCache>>,
+    verify_reads: bool,
+    manifests: manifest::ManifestCache,
+    space: Arc<dyn disk_space::SpaceProvider>,
+    disk_space: Arc<std::sync::Mutex<disk_space::DiskSpaceStatus>>,
+    last_reconcile: Arc<std::sync::Mutex<Option<ReconcileStatus>>>,
+    encryptor: Arc<std::sync::Mutex<EncryptorStatus>>,
+    encrypt_lock: Arc<tokio::sync::Mutex<()>>,
+    encrypt_workers: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SegmentEntry {
+    pub name: String,
+    pub bytes: u64,
+    pub modified_unix: u64,
+    pub start_unix: u64,
+    pub end_unix: u64,
+    pub plaintext_bytes: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub plaintext_sha256: Option<String>,
+    pub protected: bool,
+}
+
+impl SegmentEntry {
+    pub fn overlaps(&self, from_unix: u64, to_unix: u64) -> bool {
+        self.start_unix <= to_unix && self.end_unix >= from_unix
+    }
+
+    fn matches(&self, filter: &SegmentFilter) -> bool {
+        self.overlaps(
+            filter.from_unix.unwrap_or(0),
+            filter.to_unix.unwrap_or(u64::MAX),
+        ) && (self.protected || !filter.protected_only)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SegmentFilter {
+    pub from_unix: Option<u64>,
+    pub to_unix: Option<u64>,
+    pub protected_only: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SegmentMedia {
+    pub plaintext_bytes: Option<u64>,
+    pub duration_ms: Option<u64>,
+}
+
+impl SegmentMedia {
+    pub fn size(&self, len: usize) -> (u64, bool) {
+        let len = len as u64;
+        (len, self.plaintext_bytes == Some(len))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileSummary {
+    pub temp_files: usize,
+    pub torn_segments: usize,
+    pub empty_segments: usize,
+    pub plaintext_segments: usize,
+    pub orphan_segments: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileStatus {
+    pub at_unix: u64,
+    #[serde(flatten)]
+    pub summary: ReconcileSummary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockAnomaly {
+    pub source_id: String,
+    pub segments: usize,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub max_skew_secs: i64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptorBacklog {
+    pub segments: usize,
+    pub oldest_unix: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeSummary {
+    pub segments: usize,
+    pub bytes: u64,
+    pub names: Vec<String>,
+    pub bookmarked: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentDeletion {
+    pub name: String,
+    pub existed: bool,
+    pub bookmarked: bool,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourcePurge {
+    pub source_id: String,
+    pub segments: usize,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceMigration {
+    pub source_id: String,
+    pub segments: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationReport {
+    pub segments: usize,
+    pub sources: Vec<SourceMigration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format_version: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeReport {
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub dry_run: bool,
+    pub segments: usize,
+    pub bytes: u64,
+    pub bookmarked: usize,
+    pub sources: Vec<SourcePurge>,
+}
+
+impl StorageManager {
+    pub fn new(root: PathBuf, key_hex: &str) -> Result<Self> {
+        let key = crypto::parse_hex_exact(key_hex, 32)?;
+        let cancel = CancellationToken::default();
+        Ok(Self {
+            segment_db: Arc::new(segment_db::SegmentDb::new(&root)),
+            root,
+            key,
+            opaque_names: false,
+            name_map_lock: Arc::new(std::sync::Mutex::new(())),
+            snapshot_retention: SnapshotRetention::default(),
+            pre_delete_hook: None,
+            retention_status: Arc::default(),
+            retention_windows: Arc::default(),
+            segment_prunes: Arc::default(),
+            swept_day: Arc::default(),
+            stats: StatsRegistry::default(),
+            jobs: JobRegistry::new(cancel.clone()),
+            cancel,
+            clock: ClockWatch::default(),
+            share_lock: Arc::default(),
+            bookmarks: Arc::default(),
+            incident_lock: Arc::default(),
+            attachment_limits: AttachmentLimits::default(),
+            attachment_lock: Arc::default(),
+            export_settings: ExportSettings::default(),
+            exports: Arc::default(),
+            history_lock: Arc::default(),
+            root_lost: Arc::default(),
+            format: Arc::default(),
+            segment_cache: SegmentCache::default(),
+            fragment_cache: SegmentCache::default(),
+            io: IoPriority::default(),
+            source_stats: Arc::default(),
+            verify_reads: false,
+            manifests: Arc::default(),
+            space: Arc::new(disk_space::Statvfs),
+            disk_space: Arc::default(),
+            last_reconcile: Arc::default(),
+            encryptor: Arc::default(),
+            encrypt_lock: Arc::default(),
+            encrypt_workers: auto_encrypt_workers(),
+        })
+    }
+
+    pub fn with_opaque_names(mut self, enabled: bool) -> Self {
+        self.opaque_names = enabled;
+        self
+    }
+
+    pub fn with_verified_reads(mut self, enabled: bool) -> Self {
+        self.verify_reads = enabled;
+        self
+    }
+
+    pub fn with_exports(mut self, settings: ExportSettings) -> Self {
+        self.export_settings = settings;
+        self
+    }
+
+    pub fn with_segment_cache(mut self, settings: SegmentCacheSettings) -> Self {
+        self.segment_cache = SegmentCache::new(settings);
+        self.fragment_cache = SegmentCache::new(settings);
+        self
+    }
+
+    pub fn render_cache_metrics(&self) -> String {
+        self.segment_cache.render_prometheus()
+    }
+
+    pub fn with_encrypt_workers(mut self, workers: usize) -> Self {
+        self.encrypt_workers = match workers {
+            0 => auto_encrypt_workers(),
+            workers => workers,
+        };
+        self
+    }
+
+    pub fn with_io_priority(mut self, settings: IoPrioritySettings) -> Self {
+        self.io = IoPriority::new(settings);
+        self
+    }
+
+    pub fn io(&self) -> &IoPriority {
+        &self.io
+    }
+
+    pub fn start_io_canary(&self) {
+        self.io.start_canary(self.root.clone());
+    }
+
+    pub fn with_stats(mut self, stats: StatsRegistry) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_space_provider(mut self, space: Arc<dyn disk_space::SpaceProvider>) -> Self {
+        self.space = space;
+        self
+    }
+
+    pub async fn ensure_dirs(&self) -> Result<()> {
+        tokio::fs::create_dir_all(self.root.join("segments")).await?;
+        tokio::fs::create_dir_all(self.root.join("snapshots")).await?;
+        // Plaintext left behind by a remux the last run did not finish.
+        let _ = tokio::fs::remove_dir_all(self.root.join(fragments::REMUX_DIR)).await;
+        self.ensure_root_marker().await?;
+        self.check_format().await
+    }
+
+    pub async fn encrypt_pending_once(&self) -> Result<()> {
+        let _pass = self.encrypt_lock.lock().await;
+        let started = Instant::now();
+        let root = self.root.join("segments");
+        let cancel = self.cancel.clone();
+        let scanned = tokio::task::spawn_blocking(move || encrypt_candidates(&root, &cancel))
+            .await
+            .context("join encryptor scan");
+        let (ready, recording) = match scanned {
+            Ok(Some(found)) => found,
+            // Shutting down.
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                self.record_encrypt_pass(None, Some(&err));
+                return Err(err);
+            }
+        };
+        let backlog = ready.len();
+        let (sealed, result) = self.seal_all(ready).await;
+        let pass = EncryptPass {
+            backlog,
+            pending: recording + backlog - sealed,
+            elapsed: started.elapsed(),
+        };
+        self.record_encrypt_pass(Some(pass), result.as_ref().err());
+        result?;
+        if let Err(err) = self.sweep_empty_day_dirs().await {
+            warn!(error = %err, "empty day directory sweep failed");
+        }
+        self.refresh_index_after_pass().await;
+        Ok(())
+    }
+
+    async fn seal_all(&self, paths: Vec<PathBuf>) -> (usize, Result<()>) {
+        let context = Arc::new(SealContext {
+            key: self.key.clone(),
+            opaque_names: self.opaque_names,
+            name_map_lock: Arc::clone(&self.name_map_lock),
+            stats: self.stats.clone(),
+            clock: self.clock.clone(),
+            io: self.io.clone(),
+            found: Instant::now(),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut sealing = futures_util::stream::iter(paths)
+            .map(|path| {
+                let context = Arc::clone(&context);
+                let stop = Arc::clone(&stop);
+                let cancel = self.cancel.clone();
+                tokio::task::spawn_blocking(move || {
+                    if stop.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                        return Ok(false);
+                    }
+                    context.seal(&path).map(|()| true)
+                })
+            })
+            .buffer_unordered(self.encrypt_workers.max(1));
+        let (mut sealed, mut result) = (0, Ok(()));
+        while let Some(joined) = sealing.next().await {
+            match joined
+                .context("join segment seal")
+                .and_then(|sealing| sealing)
+            {
+                Ok(done) => sealed += usize::from(done),
+                Err(err) => {
+                    stop.store(true, Ordering::Relaxed);
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+        (sealed, result)
+    }
+
+    async fn sweep_empty_day_dirs(&self) -> Result<()> {
+        let today = layout::local_today();
+        {
+            let mut swept = self
+                .swept_day
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if *swept == today {
+                return Ok(());
+            }
+            *swept = today.clone();
+        }
+        let dirs = self
+            .list_sources()
+            .await?
+            .iter()
+            .map(|source_id| self.segments_dir(source_id))
+            .collect::<Vec<_>>();
+        let removed = tokio::task::spawn_blocking(move || -> Result<usize> {
+            let mut removed = 0;
+            for dir in dirs {
+                removed += layout::remove_empty_day_dirs(&dir, &today)?;
+            }
+            Ok(removed)
+        })
+        .await
+        .context("join day directory sweep")??;
+        if removed > 0 {
+            debug!(removed, "removed empty day directories");
+        }
+        Ok(())
+    }
+
+    pub async fn reconcile(&self) -> Result<ReconcileSummary> {
+        let root = self.root.join("segments");
+        let cancel = self.cancel.clone();
+        let key = self.key.clone();
+        let summary = tokio::task::spawn_blocking(move || {
+            let cancelled = || anyhow!("storage reconciliation cancelled");
+            let remove = |path: &Path| {
+                std::fs::remove_file(path).with_context(|| format!("remove {}", path.display()))
+            };
+            let mut summary = ReconcileSummary::default();
+            let temps =
+                scan::collect_files(&root, scan::TEMP_FILES, &cancel).ok_or_else(cancelled)?;
+            for tmp in temps {
+                remove(&tmp)?;
+                summary.temp_files += 1;
+            }
+            let mut sealed = Vec::new();
+            for path in
+                scan::collect_files(&root, scan::SEGMENT_FILES, &cancel).ok_or_else(cancelled)?
+            {
+                if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() == 0) {
+                    remove(&path)?;
+                    summary.empty_segments += 1;
+                } else {
+                    sealed.push(path);
+                }
+            }
+            let plain = scan::collect_files(&root, scan::PLAIN_SEGMENT_FILES, &cancel)
+                .ok_or_else(cancelled)?;
+            for path in plain {
+                let Ok(metadata) = std::fs::metadata(&path) else {
+                    continue;
+                };
+                if metadata.len() == 0 {
+                    remove(&path)?;
+                    summary.empty_segments += 1;
+                    continue;
+                }
+                summary.plaintext_segments += 1;
+                let enc_path = path.with_extension("cnv");
+                if enc_path.exists() && !sealed_whole(&enc_path, metadata.len()) {
+                    remove(&enc_path)?;
+                    summary.torn_segments += 1;
+                }
+            }
+            // One beside its plaintext is recorded when the encryptor finishes it.
+            sealed.retain(|path| !path.with_extension("mp4").exists());
+            for orphan in manifest::unrecorded(&sealed, &key)? {
+                warn!(path = %orphan.display(), "sealed segment missing from its manifest");
+                summary.orphan_segments += 1;
+            }
+            Ok::<_, anyhow::Error>(summary)
+        })
+        .await
+        .context("join storage reconciliation")??;
+        *self
+            .last_reconcile
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(ReconcileStatus {
+            at_unix: crate::util::now_unix_seconds(),
+            summary,
+        });
+        Ok(summary)
+    }
+
+    pub fn last_reconcile(&self) -> Option<ReconcileStatus> {
+        *self
+            .last_reconcile
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub async fn encryptor_backlog(&self, older_than_secs: u64) -> Result<EncryptorBacklog> {
+        let root = self.root.join("segments");
+        let cancel = self.cancel.clone();
+        let cutoff = crate::util::now_unix_seconds().saturating_sub(older_than_secs);
+        tokio::task::spawn_blocking(move || {
+            let files = scan::collect_files(&root, scan::PLAIN_SEGMENT_FILES, &cancel)
+                .ok_or_else(|| anyhow!("encryptor backlog scan cancelled"))?;
+            let mut backlog = EncryptorBacklog::default();
+            for modified in files.iter().map(|path| modified_unix(path)) {
+                if modified == 0 || modified >= cutoff {
+                    continue;
+                }
+                backlog.segments += 1;
+                backlog.oldest_unix = Some(backlog.oldest_unix.unwrap_or(modified).min(modified));
+            }
+            Ok(backlog)
+        })
+        .await
+        .context("join encryptor backlog scan")?
+    }
+
+    async fn apply_clock_step(&self, step: ClockStep) -> Result<usize> {
+        let root = self.root.join("segments");
+        let key = self.key.clone();
+        let lock = Arc::clone(&self.name_map_lock);
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            correct_indexes(&root, &key, &step)
+        })
+        .await
+        .context("join segment index correction")?
+    }
+
+    pub async fn clock_anomalies(&self, threshold_secs: u64) -> Result<Vec<ClockAnomaly>> {
+        let mut out = Vec::new();
+        for source_id in self.list_sources().await? {
+            let mut anomaly: Option<ClockAnomaly> = None;
+            for (entry, time) in self.indexed_segments(&source_id).await? {
+                let Some(skew) = time.and_then(|time| time.name_skew_secs(&entry.name)) else {
+                    continue;
+                };
+                if skew.unsigned_abs() <= threshold_secs {
+                    continue;
+                }
+                let anomaly = anomaly.get_or_insert_with(|| ClockAnomaly {
+                    source_id: source_id.clone(),
+                    segments: 0,
+                    from_unix: entry.start_unix,
+                    to_unix: entry.start_unix,
+                    max_skew_secs: 0,
+                });
+                anomaly.segments += 1;
+                anomaly.from_unix = anomaly.from_unix.min(entry.start_unix);
+                anomaly.to_unix = anomaly.to_unix.max(entry.start_unix);
+                if skew.abs() > anomaly.max_skew_secs.abs() {
+                    anomaly.max_skew_secs = skew;
+                }
+            }
+            out.extend(anomaly);
+        }
+        Ok(out)
+    }
+
+    pub async fn migrate_opaque_names(&self) -> Result<MigrationReport> {
+        let job = self.jobs.begin(MIGRATE_OPAQUE_JOB)?;
+        let result = self.run_migrate_opaque_names(job.progress().clone()).await;
+        job.finish(&result);
+        result
+    }
+
+    pub fn start_migrate_opaque_names(&self) -> Result<JobStatus> {
+        let job = self.jobs.begin(MIGRATE_OPAQUE_JOB)?;
+        let this = self.clone();
+        Ok(job.spawn(move |progress| async move { this.run_migrate_opaque_names(progress).await }))
+    }
+
+    async fn run_migrate_opaque_names(&self, progress: JobProgress) -> Result<MigrationReport> {
+        let root = self.root.join("segments");
+        let key = self.key.clone();
+        let lock = Arc::clone(&self.name_map_lock);
+        tokio::task::spawn_blocking(move || migrate_pass(&root, &key, &lock, &progress))
+            .await
+            .context("join name migration")?
+    }
+
+    pub async fn migrate_day_layout(&self) -> Result<MigrationReport> {
+        let job = self.jobs.begin(MIGRATE_DAY_LAYOUT_JOB)?;
+        let result = self.run_migrate_day_layout(job.progress().clone()).await;
+        job.finish(&result);
+        result
+    }
+
+    pub fn start_migrate_day_layout(&self) -> Result<JobStatus> {
+        let job = self.jobs.begin(MIGRATE_DAY_LAYOUT_JOB)?;
+        let this = self.clone();
+        Ok(job.spawn(move |progress| async move { this.run_migrate_day_layout(progress).await }))
+    }
+
+    async fn run_migrate_day_layout(&self, progress: JobProgress) -> Result<MigrationReport> {
+        let root = self.root.join("segments");
+        let lock = Arc::clone(&self.name_map_lock);
+        let mut report =
+            tokio::task::spawn_blocking(move || day_layout_pass(&root, &lock, &progress))
+                .await
+                .context("join day layout migration")??;
+        let version = self.advance_format_after_day_layout().await?;
+        report.format_version = (version >= 2).then_some(version);
+        Ok(report)
+    }
+
+    pub fn cancel_scans(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn jobs(&self) -> &JobRegistry {
+        &self.jobs
+    }
+
+    fn segments_dir(&self, source_id: &str) -> PathBuf {
+        self.root
+            .join("segments")
+            .join(crate::util::source_dir_name(source_id))
+    }
+
+    pub async fn list_sources(&self) -> Result<Vec<String>> {
+        let dir = self.root.join("segments");
+        let mut out = Vec::new();
+        let mut rd = tokio::fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("read_dir {}", dir.display()))?;
+        while let Some(entry) = rd.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                out.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        out.sort();
+        Ok(out)
+    }
+
+    pub async fn list_segments(&self, source_id: &str, limit: usize) -> Result<Vec<SegmentEntry>> {
+        self.list_segments_matching(source_id, SegmentFilter::default(), limit)
+            .await
+    }
+
+    pub async fn list_segments_matching(
+        &self,
+        source_id: &str,
+        filter: SegmentFilter,
+        limit: usize,
+    ) -> Result<Vec<SegmentEntry>> {
+        let narrow = IndexQuery {
+            from_unix: filter.from_unix,
+            to_unix: filter.to_unix,
+            after: None,
+            // Protection is applied after the index answers.
+            limit: (!filter.protected_only).then_some(limit.max(1)),
+        };
+        let mut out = self
+            .indexed_segments_narrowed(source_id, narrow)
+            .await?
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .filter(|entry| entry.matches(&filter))
+            .collect::<Vec<_>>();
+        out.sort_by_key(|segment| std::cmp::Reverse(segment.start_unix));
+        out.truncate(limit.max(1));
+        Ok(out)
+    }
+
+    pub async fn list_segments_page(
+        &self,
+        source_id: &str,
+        after: Option<(u64, &str)>,
+        filter: SegmentFilter,
+        limit: usize,
+    ) -> Result<(Vec<SegmentEntry>, bool)> {
+        let narrow = IndexQuery {
+            from_unix: filter.from_unix,
+            to_unix: filter.to_unix,
+            after: after.map(|(start, name)| (start, name.to_string())),
+            limit: (!filter.protected_only).then_some(limit.max(1) + 1),
+        };
+        let mut out = self
+            .indexed_segments_narrowed(source_id, narrow)
+            .await?
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .filter(|entry| {
+                entry.matches(&filter)
+                    && after.is_none_or(|(start, name)| {
+                        (entry.start_unix, entry.name.as_str()) < (start, name)
+                    })
+            })
+            .collect::<Vec<_>>();
+        out.sort_by(|left, right| {
+            (right.start_unix, &right.name).cmp(&(left.start_unix, &left.name))
+        });
+        let more = out.len() > limit.max(1);
+        out.truncate(limit.max(1));
+        Ok((out, more))
+    }
+
+    async fn indexed_segments(
+        &self,
+        source_id: &str,
+    ) -> Result<Vec<(SegmentEntry, Option<SegmentTime>)>> {
+        self.indexed_segments_narrowed(source_id, IndexQuery::default())
+            .await
+    }
+
+    async fn indexed_segments_narrowed(
+        &self,
+        source_id: &str,
+        narrow: IndexQuery,
+    ) -> Result<Vec<(SegmentEntry, Option<SegmentTime>)>> {
+        let mut out = match self.query_index(source_id, narrow).await {
+            Some(out) => out,
+            None => self.walk_segments(source_id).await?,
+        };
+        let dir = self.segments_dir(source_id);
+        let protected = tokio::task::spawn_blocking(move || protection::load(&dir))
+            .await
+            .context("join segment protection load")??;
+        for (entry, _) in &mut out {
+            entry.protected = protected.contains(&entry.name);
+        }
+        Ok(out)
+    }
+
+    async fn walk_segments(
+        &self,
+        source_id: &str,
+    ) -> Result<Vec<(SegmentEntry, Option<SegmentTime>)>> {
+        let dir = self.segments_dir(source_id);
+        let mut out = Vec::new();
+        let files = layout::segment_files(&dir).await?;
+        if files.is_empty() {
+            return Ok(out);
+        }
+        let map = self.load_name_map(&dir).await?;
+        let mapped_names = map
+            .as_ref()
+            .map(|map| {
+                map.entries()
+                    .values()
+                    .map(|entry| entry.name.clone())
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+
+        for file in files {
+            let name = file.name;
+            let md = file.metadata;
+            if let Some(map) = &map {
+                if let Some(mapped) = name.strip_suffix(".cnv").and_then(|stem| map.get(stem)) {
+                    out.push((
+                        SegmentEntry {
+                            name: mapped.name.clone(),
+                            bytes: md.len(),
+                            modified_unix: mapped.modified_unix,
+                            start_unix: mapped.modified_unix,
+                            end_unix: mapped.modified_unix,
+                            plaintext_bytes: None,
+                            duration_ms: None,
+                            plaintext_sha256: None,
+                            protected: false,
+                        },
+                        mapped.time.clone(),
+                    ));
+                    continue;
+                }
+                if mapped_names.contains(&name) {
+                    // Timestamp-named original left behind by an interrupted migration.
+                    continue;
+                }
+            }
+
+            let modified = md.modified().map_or(0, crate::util::clock::unix_secs);
+
+            out.push((
+                SegmentEntry {
+                    name,
+                    bytes: md.len(),
+                    modified_unix: modified,
+                    start_unix: modified,
+                    end_unix: modified,
+                    plaintext_bytes: None,
+                    duration_ms: None,
+                    plaintext_sha256: None,
+                    protected: false,
+                },
+                None,
+            ));
+        }
+
+        let days = out
+            .iter()
+            .filter(|(_, time)| time.is_none())
+            .filter_map(|(entry, _)| layout::split_name(&entry.name))
+            .map(|(day, _)| day.to_string())
+            .collect::<BTreeSet<_>>();
+        if !days.is_empty() {
+            let dir = dir.clone();
+            let indexes = tokio::task::spawn_blocking(move || {
+                days.into_iter()
+                    .map(|day| {
+                        let index = day_index::load(&dir.join(&day));
+                        (day, index)
+                    })
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .await
+            .context("join segment index load")?;
+            for (entry, time) in &mut out {
+                if time.is_none() {
+                    *time = layout::split_name(&entry.name)
+                        .and_then(|(day, _)| indexes.get(day))
+                        .and_then(|index| index.entries.get(&entry.name))
+                        .cloned();
+                }
+            }
+        }
+        for (entry, time) in &mut out {
+            if let Some(time) = time {
+                entry.start_unix = time.start_unix;
+                entry.end_unix = time.end_unix;
+                entry.plaintext_bytes = time.plaintext_bytes;
+                entry.duration_ms = time.duration_ms;
+                entry.plaintext_sha256 = time.plaintext_sha256.clone();
+            }
+        }
+        Ok(out)
+    }
+
+    pub async fn purge_segments(
+        &self,
+        source_id: &str,
+        from_unix: u64,
+        to_unix: u64,
+        include_bookmarked: bool,
+        dry_run: bool,
+    ) -> Result<PurgeSummary> {
+        let mut selected = self.list_segments(source_id, usize::MAX).await?;
+        selected.retain(|entry| entry.overlaps(from_unix, to_unix));
+        let before = selected.len();
+        if !include_bookmarked {
+            selected.retain(|entry| !self.held(source_id, entry));
+        }
+        let bookmarked = before - selected.len();
+        if dry_run {
+            return Ok(PurgeSummary {
+                segments: selected.len(),
+                bytes: selected.iter().map(|entry| entry.bytes).sum(),
+                names: selected.into_iter().map(|entry| entry.name).collect(),
+                bookmarked,
+            });
+        }
+        let mut summary = self.remove_segments(source_id, selected).await?;
+        summary.bookmarked = bookmarked;
+        Ok(summary)
+    }
+
+    pub async fn delete_segment(
+        &self,
+        source_id: &str,
+        name: &str,
+        include_bookmarked: bool,
+    ) -> Result<SegmentDeletion> {
+        let mut deleted = self
+            .delete_segments(source_id, &[name.to_string()], include_bookmarked)
+            .await?;
+        deleted
+            .pop()
+            .ok_or_else(|| anyhow!("segment deletion reported nothing"))
+    }
+
+    pub async fn delete_segments(
+        &self,
+        source_id: &str,
+        names: &[String],
+        include_bookmarked: bool,
+    ) -> Result<Vec<SegmentDeletion>> {
+        if let Some(name) = names.iter().find(|name| !is_segment_name(name)) {
+            return Err(anyhow!("not a segment name: {name:?}"));
+        }
+        let stored = self
+            .list_segments(source_id, usize::MAX)
+            .await?
+            .into_iter()
+            .map(|entry| (entry.name.clone(), entry))
+            .collect::<HashMap<_, _>>();
+        let mut seen = HashSet::new();
+        let mut out = Vec::with_capacity(names.len());
+        let mut doomed = Vec::new();
+        for name in names.iter().filter(|name| seen.insert(name.as_str())) {
+            let entry = stored.get(name);
+            let bookmarked =
+                entry.is_some_and(|entry| !include_bookmarked && self.held(source_id, entry));
+            out.push(SegmentDeletion {
+                name: name.clone(),
+                existed: entry.is_some(),
+                bookmarked,
+                bytes: 0,
+            });
+            doomed.extend(entry.filter(|_| !bookmarked).cloned());
+        }
+        let summary = self.remove_segments(source_id, doomed).await?;
+        let removed = summary.names.iter().collect::<HashSet<_>>();
+        for deletion in out
+            .iter_mut()
+            .filter(|deletion| removed.contains(&deletion.name))
+        {
+            deletion.bytes = stored[&deletion.name].bytes;
+        }
+        Ok(out)
+    }
+
+    async fn remove_segments(
+        &self,
+        source_id: &str,
+        entries: Vec<SegmentEntry>,
+    ) -> Result<PurgeSummary> {
+        let mut summary = PurgeSummary::default();
+        let dir = self.segments_dir(source_id);
+        let map = self.load_name_map(&dir).await?;
+        for entry in entries {
+            let path = resolve_segment_path(&dir, map.as_ref(), &entry.name);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("remove segment {}", path.display()));
+                }
+            }
+            summary.segments += 1;
+            summary.bytes += entry.bytes;
+            summary.names.push(entry.name);
+        }
+        self.segment_cache.forget(source_id, &summary.names);
+        self.fragment_cache.forget(source_id, &summary.names);
+        self.forget_range_spools(source_id, &summary.names).await?;
+        let deleted = summary.segments as u64;
+        self.stats
+            .record(source_id, Counter::SegmentsDeleted, deleted);
+        self.stats
+            .record(source_id, Counter::BytesDeleted, summary.bytes);
+
+        if !summary.names.is_empty() {
+            let dir = dir.clone();
+            let key = self.key.clone();
+            let lock = Arc::clone(&self.name_map_lock);
+            let names = summary.names.clone();
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                day_index::forget(&dir, &names)?;
+                protection::forget(&dir, &names)?;
+                manifest::forget(&dir, &key, &names)?;
+                let today = layout::local_today();
+                let emptied = names
+                    .iter()
+                    .filter_map(|name| layout::split_name(name))
+                    .map(|(day, _)| day)
+                    .filter(|day| *day < today.as_str())
+                    .collect::<BTreeSet<_>>();
+                for day in emptied {
+                    layout::remove_if_empty(&dir.join(day))?;
+                }
+                Ok(())
+            })
+            .await
+            .context("join segment index update")??;
+        }
+
+        if map.is_some() && !summary.names.is_empty() {
+            let key = self.key.clone();
+            let lock = Arc::clone(&self.name_map_lock);
+            let names = summary.names.clone();
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let mut map = name_map::load(&dir, &key)?;
+                for name in &names {
+                    map.remove_name(name);
+                }
+                name_map::save(&dir, &key, &map)
+            })
+            .await
+            .context("join name map update")??;
+        }
+        Ok(summary)
+    }
+
+    pub async fn purge_range(
+        &self,
+        from_unix: u64,
+        to_unix: u64,
+        source_ids: &[String],
+        include_bookmarked: bool,
+        dry_run: bool,
+        progress: Option<&JobProgress>,
+    ) -> Result<PurgeReport> {
+        let sources = if source_ids.is_empty() {
+            self.list_sources().await?
+        } else {
+            source_ids.to_vec()
+        };
+        let mut report = PurgeReport {
+            from_unix,
+            to_unix,
+            dry_run,
+            ..PurgeReport::default()
+        };
+        let total = sources.len() as u64;
+        if let Some(progress) = progress {
+            progress.phase(if dry_run { "previewing" } else { "deleting" });
+            progress.progress(0, total);
+        }
+        for source_id in sources {
+            // A cancelled purge stops between sources; re-running deletes the rest.
+            if progress.is_some_and(JobProgress::is_cancelled) {
+                break;
+            }
+            let summary = self
+                .purge_segments(&source_id, from_unix, to_unix, include_bookmarked, dry_run)
+                .await?;
+            report.segments += summary.segments;
+            report.bytes += summary.bytes;
+            report.bookmarked += summary.bookmarked;
+            report.sources.push(SourcePurge {
+                source_id,
+                segments: summary.segments,
+                bytes: summary.bytes,
+            });
+            if let Some(progress) = progress {
+                progress.progress(report.sources.len() as u64, total);
+            }
+        }
+        Ok(report)
+    }
+
+    pub async fn read_segment(
+        &self,
+        source_id: &str,
+        name: &str,
+    ) -> Result<Plaintext, StorageError> {
+        if !name.ends_with(".cnv") {
+            // Still being recorded; the next read may find more.
+            return self.read_segment_file(source_id, name).await.map(Arc::new);
+        }
+        let format = self.format.load(Ordering::Relaxed);
+        self.segment_cache
+            .get_or_load(source_id, name, format, || {
+                self.read_segment_file(source_id, name)
+            })
+            .await
+    }
+
+    pub async fn read_segment_stream(
+        &self,
+        source_id: &str,
+        name: &str,
+    ) -> Result<SegmentStream, StorageError> {
+        self.open_segment_stream(source_id, name, false).await
+    }
+
+    pub async fn read_segment_ranged(
+        &self,
+        source_id: &str,
+        name: &str,
+    ) -> Result<SegmentStream, StorageError> {
+        self.open_segment_stream(source_id, name, true).await
+    }
+
+    async fn open_segment_stream(
+        &self,
+        source_id: &str,
+        name: &str,
+        spool: bool,
+    ) -> Result<SegmentStream, StorageError> {
+        let sealed = name.ends_with(".cnv");
+        let format = self.format.load(Ordering::Relaxed);
+        if let Some(plain) = sealed
+            .then(|| self.segment_cache.cached(source_id, name, format))
+            .flatten()
+        {
+            return Ok(SegmentStream::buffered(plain));
+        }
+        let dir = self.segments_dir(source_id);
+        let map = self.load_segment_name_map(&dir).await?;
+        let path = resolve_segment_path(&dir, map.as_ref(), name);
+        let read = |err| StorageError::read(source_id, name, &path, err);
+        let mut file = tokio::fs::File::open(&path).await.map_err(read)?;
+        if !sealed {
+            let len = file.metadata().await.map_err(read)?.len();
+            return Ok(SegmentStream::plain(file, path, len));
+        }
+        let mut magic = [0u8; 5];
+        let chunked = match file.read_exact(&mut magic).await {
+            Ok(_) => chunked::is_chunked(&magic),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(read(err)),
+        };
+        if !chunked && spool {
+            return self.spooled_stream(source_id, name, &path).await;
+        }
+        if !chunked {
+            return self
+                .read_segment(source_id, name)
+                .await
+                .map(SegmentStream::buffered);
+        }
+        self.verify_file(source_id, name, &path).await?;
+        file.rewind().await.map_err(read)?;
+        let reader = chunked::ChunkReader::open(file, path, self.key.clone()).await?;
+        Ok(SegmentStream::chunked(reader))
+    }
+
+    async fn read_segment_file(
+        &self,
+        source_id: &str,
+        name: &str,
+    ) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+        let dir = self.segments_dir(source_id);
+        let map = self.load_segment_name_map(&dir).await?;
+        let path = resolve_segment_path(&dir, map.as_ref(), name);
+        let bytes = tokio::fs::read(&path)
+            .await
+            .map_err(|err| StorageError::read(source_id, name, &path, err))?;
+
+        if !name.ends_with(".cnv") {
+            return Ok(Zeroizing::new(bytes));
+        }
+        self.verify_blob(source_id, name, &bytes).await?;
+        let plain = decrypt_blob(&self.key, &bytes)?;
+        self.complete_record(source_id, name, &plain).await;
+        Ok(plain)
+    }
+
+    pub async fn segment_media(&self, source_id: &str, name: &str) -> Result<SegmentMedia> {
+        let dir = self.segments_dir(source_id);
+        let map = self.load_name_map(&dir).await?;
+        let mapped = map.as_ref().and_then(|map| map.entry_for(name));
+        let time = match mapped {
+            Some(entry) => entry.time.clone(),
+            None => match layout::split_name(name) {
+                Some((day, _)) => {
+                    let (day_dir, name) = (dir.join(day), name.to_string());
+                    tokio::task::spawn_blocking(move || day_index::load(&day_dir))
+                        .await
+                        .context("join segment index load")?
+                        .entries
+                        .remove(&name)
+                }
+                None => None,
+            },
+        };
+        Ok(
+            time.map_or_else(SegmentMedia::default, |time| SegmentMedia {
+                plaintext_bytes: time.plaintext_bytes,
+                duration_ms: time.duration_ms,
+            }),
+        )
+    }
+
+    async fn load_segment_name_map(&self, dir: &Path) -> Result<Option<NameMap>, StorageError> {
+        self.load_name_map(dir)
+            .await
+            .map_err(|err| StorageError::Corrupt(format!("segment name map: {err:#}")))
+    }
+
+    async fn load_name_map(&self, dir: &Path) -> Result<Option<NameMap>> {
+        if !self.opaque_names && !name_map::has_map(dir) {
+            return Ok(None);
+        }
+        let dir = dir.to_path_buf();
+        let key = self.key.clone();
+        let lock = Arc::clone(&self.name_map_lock);
+        let map = tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            name_map::load(&dir, &key)
+        })
+        .await
+        .context("join name map load")??;
+        Ok(Some(map))
+    }
+}
+
+fn resolve_segment_path(dir: &Path, map: Option<&NameMap>, name: &str) -> PathBuf {
+    if let Some(opaque) = map.and_then(|map| map.opaque_for(name)) {
+        return dir.join(format!("{opaque}.cnv"));
+    }
+    layout::dated_path(dir, name)
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| dir.join(name))
+}
+
+fn auto_encrypt_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |cpus| cpus.get().min(4))
+}
+
+fn encrypt_candidates(root: &Path, cancel: &CancellationToken) -> Option<(Vec<PathBuf>, usize)> {
+    let files = scan::collect_files(root, scan::PLAIN_SEGMENT_FILES, cancel)?;
+    let now = crate::util::now_unix_seconds();
+    let mut recording = 0;
+    let ready = files
+        .into_iter()
+        .filter(|path| {
+            let busy = still_recording(path, now);
+            if busy {
+                debug!(path = %path.display(), "segment still recording; sealing it later");
+                recording += 1;
+            }
+            !busy
+        })
+        .collect();
+    Some((ready, recording))
+}
+
+fn still_recording(path: &Path, now: u64) -> bool {
+    let modified = modified_unix(path);
+    // Unreadable metadata, most likely a file just removed; the next pass looks again.
+    if modified == 0 {
+        return true;
+    }
+    modified + IN_PROGRESS_QUIET_SECS > now && !later_segment_started(path)
+}
+
+fn later_segment_started(path: &Path) -> bool {
+    fn later(dir: &Path, after: &std::ffi::OsStr, matches: &dyn Fn(&Path) -> bool) -> bool {
+        std::fs::read_dir(dir).is_ok_and(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .any(|entry| entry.file_name().as_os_str() > after && matches(&entry.path()))
+        })
+    }
+    fn plain(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "mp4")
+    }
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    if later(dir, name, &plain) {
+        return true;
+    }
+    match (dir.parent(), dir.file_name()) {
+        (Some(source_dir), Some(day)) if layout::is_day_dir(&day.to_string_lossy()) => {
+            later(source_dir, day, &|day_dir| {
+                day_dir.is_dir() && later(day_dir, std::ffi::OsStr::new(""), &plain)
+            })
+        }
+        _ => false,
+    }
+}
+
+struct SealContext {
+    key: Zeroizing<Vec<u8>>,
+    opaque_names: bool,
+    name_map_lock: Arc<std::sync::Mutex<()>>,
+    stats: StatsRegistry,
+    clock: ClockWatch,
+    io: IoPriority,
+    found: Instant,
+}
+
+impl SealContext {
+    fn seal(&self, path: &Path) -> Result<()> {
+        let bytes = if self.opaque_names && !path.with_extension("cnv").exists() {
+            self.seal_opaque(path)?
+        } else {
+            self.seal_beside(path)?
+        };
+        let wait = self.io.charge(IoClass::Background, bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.name_map_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn seal_beside(&self, path: &Path) -> Result<u64> {
+        let enc_path = path.with_extension("cnv");
+        let raw = Zeroizing::new(
+            std::fs::read(path)
+                .with_context(|| format!("read plain segment {}", path.display()))?,
+        );
+        if raw.is_empty() {
+            return Ok(0);
+        }
+
+        let time = SegmentTime::probe(modified_unix(path), &raw, &self.clock);
+        // A crash after the rename but before the plaintext was removed leaves both; a blob
+        // that holds all of the plaintext is kept, and anything else is sealed again.
+        let sealed = if sealed_whole(&enc_path, raw.len() as u64) {
+            debug!(path = %enc_path.display(), "finishing a segment sealed before a restart");
+            None
+        } else {
+            let blob = chunked::seal(&self.key, None, &raw)?;
+            Some((write_sealed_temp(&enc_path, &blob)?, blob))
+        };
+        let footage_end_ms = footage_end_ms(path, &time);
+
+        let guard = self.lock();
+        // Purged while it was being sealed.
+        if !path.exists() {
+            if let Some((tmp, _)) = sealed {
+                let _ = std::fs::remove_file(tmp);
+            }
+            return Ok(0);
+        }
+        let sealed_bytes = match sealed {
+            Some((tmp, blob)) => {
+                rename_sealed(&tmp, &enc_path)?;
+                manifest::record_sealed(&enc_path, &self.key, None, &blob)?;
+                blob.len()
+            }
+            None => {
+                // The crash may have come before the blob was recorded, too.
+                manifest::record_if_missing(&enc_path, &self.key)?;
+                0
+            }
+        };
+        index_segment(&enc_path, time)?;
+        std::fs::remove_file(path)
+            .with_context(|| format!("remove plain segment {}", path.display()))?;
+        drop(guard);
+        if sealed_bytes > 0 {
+            record_finalized(&self.stats, path, raw.len(), sealed_bytes);
+            record_latency(&self.stats, path, footage_end_ms, self.found);
+            debug!(path = %enc_path.display(), "encrypted segment");
+        }
+        Ok((raw.len() + sealed_bytes) as u64)
+    }
+
+    fn seal_opaque(&self, path: &Path) -> Result<u64> {
+        let (dir, plain_name) = layout::locate(path)
+            .ok_or_else(|| anyhow!("segment has no source dir: {}", path.display()))?;
+        let name = match plain_name.strip_suffix(".mp4") {
+            Some(stem) => format!("{stem}.cnv"),
+            None => plain_name,
+        };
+        let raw = Zeroizing::new(
+            std::fs::read(path)
+                .with_context(|| format!("read plain segment {}", path.display()))?,
+        );
+        if raw.is_empty() {
+            return Ok(0);
+        }
+        let opaque = uuid::Uuid::new_v4().simple().to_string();
+        let enc_path = dir.join(format!("{opaque}.cnv"));
+        let blob = chunked::seal(&self.key, Some(&name), &raw)?;
+        let tmp = write_sealed_temp(&enc_path, &blob)?;
+        let time = SegmentTime::probe(modified_unix(path), &raw, &self.clock);
+        let footage_end_ms = footage_end_ms(path, &time);
+
+        let guard = self.lock();
+        let mut map = name_map::load(&dir, &self.key)?;
+        // Purged while it was being sealed, or mapped by a pass cut short before removing it.
+        if !path.exists() || map.contains_name(&name) {
+            let _ = std::fs::remove_file(&tmp);
+            if path.exists() {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("remove plain segment {}", path.display()))?;
+            }
+            return Ok(0);
+        }
+        rename_sealed(&tmp, &enc_path)?;
+        manifest::record(&dir, &self.key, &name, &blob)?;
+        map.insert(
+            opaque,
+            NameMapEntry {
+                start_unix: name_map::segment_start_unix(&name),
+                source_id: source_dir_name(&dir),
+                modified_unix: modified_unix(path),
+                time: Some(time),
+                name,
+            },
+        );
+        name_map::save(&dir, &self.key, &map)?;
+        std::fs::remove_file(path)
+            .with_context(|| format!("remove plain segment {}", path.display()))?;
+        drop(guard);
+        record_finalized(&self.stats, path, raw.len(), blob.len());
+        record_latency(&self.stats, path, footage_end_ms, self.found);
+        debug!(path = %enc_path.display(), "encrypted segment under opaque name");
+        Ok((raw.len() + blob.len()) as u64)
+    }
+}
+
+fn write_sealed_temp(path: &Path, blob: &[u8]) -> Result<PathBuf> {
+    use std::io::Write;
+
+    let tmp = path.with_extension("cnv.tmp");
+    let mut file = std::fs::File::create(&tmp)
+        .with_context(|| format!("create encrypted segment {}", tmp.display()))?;
+    file.write_all(blob)
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("write encrypted segment {}", tmp.display()))?;
+    Ok(tmp)
+}
+
+fn rename_sealed(tmp: &Path, path: &Path) -> Result<()> {
+    std::fs::rename(tmp, path).with_context(|| format!("rename {}", tmp.display()))?;
+    // Sync the directory so the rename survives a crash as the blob's contents do.
+    if let Some(dir) = path.parent() {
+        std::fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("sync directory {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+fn sealed_whole(enc_path: &Path, plain_len: u64) -> bool {
+    let Ok(metadata) = std::fs::metadata(enc_path) else {
+        return false;
+    };
+    let name = enc_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    backfill::sealed_plaintext_len(enc_path, metadata.len(), &name)
+        .ok()
+        .flatten()
+        == Some(plain_len)
+}
+
+fn index_segment(enc_path: &Path, time: SegmentTime) -> Result<()> {
+    let Some(day_dir) = enc_path.parent().filter(|dir| {
+        dir.file_name()
+            .is_some_and(|name| layout::is_day_dir(&name.to_string_lossy()))
+    }) else {
+        return Ok(());
+    };
+    let Some((_, name)) = layout::locate(enc_path) else {
+        return Ok(());
+    };
+    let mut index = day_index::load(day_dir);
+    index.insert(name, time);
+    day_index::save(day_dir, &index)
+}
+
+fn record_finalized(stats: &StatsRegistry, path: &Path, plaintext: usize, ciphertext: usize) {
+    let Some((dir, _)) = layout::locate(path) else {
+        return;
+    };
+    let source_id = source_dir_name(&dir);
+    stats.record(&source_id, Counter::SegmentsFinalized, 1);
+    stats.record(&source_id, Counter::PlaintextBytes, plaintext as u64);
+    stats.record(&source_id, Counter::CiphertextBytes, ciphertext as u64);
+}
+
+fn footage_end_ms(path: &Path, time: &SegmentTime) -> Result<u64, Unreliable> {
+    let name = layout::locate(path)
+        .map(|(_, name)| name)
+        .unwrap_or_default();
+    time.footage_end_ms(&name)
+}
+
+fn record_latency(
+    stats: &StatsRegistry,
+    path: &Path,
+    footage_end_ms: Result<u64, Unreliable>,
+    found: Instant,
+) {
+    let Some((dir, _)) = layout::locate(path) else {
+        return;
+    };
+    stats
+        .latency()
+        .record(&source_dir_name(&dir), footage_end_ms, found.elapsed());
+}
+
+fn migrate_pass(
+    root: &Path,
+    key: &[u8],
+    name_map_lock: &std::sync::Mutex<()>,
+    progress: &JobProgress,
+) -> Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+    if !root.exists() {
+        return Ok(report);
+    }
+    let _guard = name_map_lock
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let mut dirs = std::fs::read_dir(root)
+        .with_context(|| format!("read_dir {}", root.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    let total = dirs.len() as u64;
+    progress.phase("migrating");
+    progress.progress(0, total);
+
+    for (idx, dir) in dirs.into_iter().enumerate() {
+        let mut map = name_map::load(&dir, key)?;
+        // Cancelled migrations stop between sources; re-running picks up the rest.
+        let spec = ScanSpec {
+            max_depth: 2,
+            ..scan::SEGMENT_FILES
+        };
+        let Some(files) = scan::collect_files(&dir, spec, progress.cancel_token()) else {
+            break;
+        };
+
+        let mut migrated = 0;
+        for path in files {
+            let Some((_, name)) = layout::locate(&path) else {
+                continue;
+            };
+            let Some(stem) = name.strip_suffix(".cnv") else {
+                continue;
+            };
+            if map.get(stem).is_some() {
+                continue;
+            }
+            if map.contains_name(&name) {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("remove migrated segment {}", path.display()))?;
+                continue;
+            }
+            let blob =
+                std::fs::read(&path).with_context(|| format!("read segment {}", path.display()))?;
+            if !(blob.starts_with(MAGIC) || chunked::is_chunked(&blob)) {
+                continue;
+            }
+            let (embedded, plain) = open_blob(key, &blob)
+                .with_context(|| format!("decrypt segment {}", path.display()))?;
+            if embedded.is_some() {
+                continue;
+            }
+
+            let opaque = uuid::Uuid::new_v4().simple().to_string();
+            let enc_path = dir.join(format!("{opaque}.cnv"));
+            let sealed = chunked::seal(key, Some(&name), &plain)?;
+            // Written whole before it takes the new name, as the encryptor does; the
+            // name-map lock is held for the whole pass.
+            let tmp = write_sealed_temp(&enc_path, &sealed)?;
+            rename_sealed(&tmp, &enc_path)?;
+            manifest::record(&dir, key, &name, &sealed)?;
+            let time = layout::split_name(&name)
+                .and_then(|(day, _)| day_index::load(&dir.join(day)).entries.remove(&name));
+            map.insert(
+                opaque,
+                NameMapEntry {
+                    start_unix: name_map::segment_start_unix(&name),
+                    source_id: source_dir_name(&dir),
+                    modified_unix: modified_unix(&path),
+                    time,
+                    name,
+                },
+            );
+            name_map::save(&dir, key, &map)?;
+            std::fs::remove_file(&path)
+                .with_context(|| format!("remove migrated segment {}", path.display()))?;
+            migrated += 1;
+        }
+
+        report.segments += migrated;
+        report.sources.push(SourceMigration {
+            source_id: source_dir_name(&dir),
+            segments: migrated,
+        });
+        progress.progress(idx as u64 + 1, total);
+    }
+    Ok(report)
+}
+
+fn day_layout_pass(
+    root: &Path,
+    name_map_lock: &std::sync::Mutex<()>,
+    progress: &JobProgress,
+) -> Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+    if !root.exists() {
+        return Ok(report);
+    }
+    let _guard = name_map_lock
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut dirs = std::fs::read_dir(root)
+        .with_context(|| format!("read_dir {}", root.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    let total = dirs.len() as u64;
+    progress.phase("migrating");
+    progress.progress(0, total);
+    for (idx, dir) in dirs.into_iter().enumerate() {
+        if progress.is_cancelled() {
+            break;
+        }
+        let moved = layout::move_flat_segments(&dir)?;
+        report.segments += moved;
+        report.sources.push(SourceMigration {
+            source_id: source_dir_name(&dir),
+            segments: moved,
+        });
+        progress.progress(idx as u64 + 1, total);
+    }
+    Ok(report)
+}
+
+fn correct_indexes(root: &Path, key: &[u8], step: &ClockStep) -> Result<usize> {
+    let mut corrected = 0;
+    if step.step_secs <= 0 || !root.exists() {
+        return Ok(corrected);
+    }
+    for source_dir in child_dirs(root)? {
+        if name_map::has_map(&source_dir) {
+            let mut map = name_map::load(&source_dir, key)?;
+            let stepped = map
+                .times_mut()
+                .map(|time| time.apply_step(step))
+                .filter(|stepped| *stepped)
+                .count();
+            if stepped > 0 {
+                name_map::save(&source_dir, key, &map)?;
+                corrected += stepped;
+            }
+        }
+        for day_dir in child_dirs(&source_dir)? {
+            if !day_dir.join(day_index::INDEX_FILE).exists() {
+                continue;
+            }
+            let mut index = day_index::load(&day_dir);
+            let stepped = index
+                .entries
+                .values_mut()
+                .map(|time| time.apply_step(step))
+                .filter(|stepped| *stepped)
+                .count();
+            if stepped > 0 {
+                day_index::save(&day_dir, &index)?;
+                corrected += stepped;
+            }
+        }
+    }
+    Ok(corrected)
+}
+
+fn child_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(std::fs::read_dir(dir)
+        .with_context(|| format!("read_dir {}", dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect())
+}
+
+fn source_dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn modified_unix(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|md| md.modified())
+        .map_or(0, crate::util::clock::unix_secs)
+}
+
+fn seal_blob(key: &[u8], plain: &[u8]) -> Result<Vec<u8>> {
+    seal_with_magic(key, MAGIC, plain)
+}
+
+#[cfg(test)]
+fn seal_named_blob(key: &[u8], name: &str, plain: &[u8]) -> Result<Vec<u8>> {
+    let name_len = u16::try_from(name.len()).map_err(|_| anyhow!("segment name too long"))?;
+    let mut body = Zeroizing::new(Vec::with_capacity(2 + name.len() + plain.len()));
+    body.extend_from_slice(&name_len.to_be_bytes());
+    body.extend_from_slice(name.as_bytes());
+    body.extend_from_slice(plain);
+    seal_with_magic(key, MAGIC_NAMED, &body)
+}
+
+fn seal_with_magic(key: &[u8], magic: &[u8], plain: &[u8]) -> Result<Vec<u8>> {
+    let nonce = crypto::random_nonce_24();
+    let cipher = crypto::encrypt_payload(key, &nonce, plain)?;
+    let mut out = Vec::with_capacity(magic.len() + nonce.len() + cipher.len());
+    out.extend_from_slice(magic);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&cipher);
+    Ok(out)
+}
+
+fn open_blob(
+    key: &[u8],
+    blob: &[u8],
+) -> Result<(Option<String>, Zeroizing<Vec<u8>>), StorageError> {
+    if chunked::is_chunked(blob) {
+        return chunked::open(key, blob);
+    }
+    let corrupt = |reason: &str| StorageError::Corrupt(reason.to_string());
+    if blob.len() < MAGIC.len() + 24 {
+        return Err(corrupt("encrypted blob too short"));
+    }
+    let magic = &blob[..MAGIC.len()];
+    if magic != MAGIC && magic != MAGIC_NAMED {
+        return Err(corrupt("invalid encrypted blob magic"));
+    }
+    let nonce: [u8; 24] = blob[MAGIC.len()..MAGIC.len() + 24]
+        .try_into()
+        .map_err(|_| corrupt("nonce decode"))?;
+    let plain = Zeroizing::new(crypto::decrypt_payload(
+        key,
+        &nonce,
+        &blob[MAGIC.len() + 24..],
+    )?);
+    if magic == MAGIC {
+        return Ok((None, plain));
+    }
+
+    if plain.len() < 2 {
+        return Err(corrupt("named blob header truncated"));
+    }
+    let name_len = u16::from_be_bytes([plain[0], plain[1]]) as usize;
+    if plain.len() < 2 + name_len {
+        return Err(corrupt("named blob header truncated"));
+    }
+    let name = String::from_utf8(plain[2..2 + name_len].to_vec())
+        .map_err(|_| corrupt("named blob header is not utf-8"))?;
+    Ok((Some(name), Zeroizing::new(plain[2 + name_len..].to_vec())))
+}
+
+fn decrypt_blob(key: &[u8], blob: &[u8]) -> Result<Zeroizing<Vec<u8>>, StorageError> {
+    Ok(open_blob(key, blob)?.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunked::STREAM_CHUNK_BYTES;
+
+    fn write_finished(path: &Path, contents: &[u8]) {
+        write_modified(path, contents, crate::util::now_unix_seconds() - 3_600);
+    }
+
+    fn write_modified(path: &Path, contents: &[u8], modified_unix: u64) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified_unix))
+            .unwrap();
+    }
+
+    #[test]
+    fn encrypt_decrypt_blob_roundtrip() {
+        let key = vec![42u8; 32];
+        let plain = b"abc123";
+        let nonce = crypto::random_nonce_24();
+        let enc = crypto::encrypt_payload(&key, &nonce, plain).unwrap();
+        let mut blob = Vec::new();
+        blob.extend_from_slice(MAGIC);
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&enc);
+
+        let dec = decrypt_blob(&key, &blob).unwrap();
+        assert_eq!(dec.as_slice(), plain);
+    }
+
+    #[tokio::test]
+    async fn purge_range_is_dry_runnable_and_idempotent() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-purge-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("20200101T000000.cnv");
+        std::fs::write(&old, b"old").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000))
+            .unwrap();
+        std::fs::write(dir.join("20990101T000000.cnv"), b"recent").unwrap();
+
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        let preview = storage
+            .purge_range(0, 2_000, &[], false, true, None)
+            .await
+            .unwrap();
+        assert_eq!(preview.segments, 1);
+        assert_eq!(preview.bytes, 3);
+        assert!(old.exists());
+
+        let purged = storage
+            .purge_range(0, 2_000, &[], false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(purged.segments, 1);
+        assert_eq!(purged.sources[0].source_id, "cam-a");
+        assert!(!old.exists());
+
+        let rerun = storage
+            .purge_range(0, 2_000, &[], false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(rerun.segments, 0);
+        assert_eq!(storage.list_segments("cam-a", 10).await.unwrap().len(), 1);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn emptied_and_unused_past_day_directories_are_removed() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-day-sweep-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        let today = layout::local_today();
+        for day in ["20200101", "20200102", today.as_str()] {
+            std::fs::create_dir_all(dir.join(day)).unwrap();
+        }
+        write_modified(&dir.join("20200101").join("000000.cnv"), b"old", 1_000);
+        std::fs::write(dir.join("20200101").join(day_index::INDEX_FILE), b"{}").unwrap();
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+
+        // Deleting a day's last segment removes its directory.
+        let purged = storage
+            .purge_range(0, 2_000, &[], false, false, None)
+            .await
+            .unwrap();
+        assert_eq!(purged.segments, 1);
+        assert!(!dir.join("20200101").exists());
+        assert!(dir.join("20200102").exists());
+
+        // The encryptor sweeps days nothing was recorded on, but not today's.
+        storage.encrypt_pending_once().await.unwrap();
+        assert!(!dir.join("20200102").exists());
+        assert!(dir.join(&today).exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn segment_pages_resume_after_ties_and_purged_cursors() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-page-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, modified) in [
+            ("20200101T000000.cnv", 3_000),
+            ("20200101T000010.cnv", 2_000),
+            ("20200101T000020.cnv", 2_000),
+            ("20200101T000030.cnv", 1_000),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, b"x").unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified))
+                .unwrap();
+        }
+
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        let (first, more) = storage
+            .list_segments_page("cam-a", None, SegmentFilter::default(), 2)
+            .await
+            .unwrap();
+        assert!(more);
+        let last = first.last().unwrap().clone();
+        let (second, more) = storage
+            .list_segments_page(
+                "cam-a",
+                Some((last.start_unix, &last.name)),
+                SegmentFilter::default(),
+                2,
+            )
+            .await
+            .unwrap();
+        assert!(!more);
+        let mut names = first
+            .iter()
+            .chain(&second)
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 4);
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 4);
+        assert!(first.iter().chain(&second).is_sorted_by(|left, right| {
+            (left.start_unix, &left.name) >= (right.start_unix, &right.name)
+        }));
+
+        storage
+            .purge_segments("cam-a", last.start_unix, last.start_unix, false, false)
+            .await
+            .unwrap();
+        let (after_purge, _) = storage
+            .list_segments_page(
+                "cam-a",
+                Some((last.start_unix, &last.name)),
+                SegmentFilter::default(),
+                10,
+            )
+            .await
+            .unwrap();
+        assert_eq!(after_purge.len(), 1);
+        assert_eq!(after_purge[0].name, second.last().unwrap().name);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn segment_lists_narrow_to_a_time_range() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-range-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, modified) in [
+            ("20200101T000000.cnv", 1_000),
+            ("20200101T000010.cnv", 2_000),
+            ("20200101T000020.cnv", 2_500),
+            ("20200101T000030.cnv", 3_000),
+        ] {
+            write_modified(&dir.join(name), b"x", modified);
+        }
+
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        let range = |from_unix, to_unix| SegmentFilter {
+            from_unix,
+            to_unix,
+            protected_only: false,
+        };
+        let names = |entries: Vec<SegmentEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>()
+        };
+        let between = storage
+            .list_segments_matching("cam-a", range(Some(1_500), Some(2_500)), 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            names(between),
+            ["20200101T000020.cnv", "20200101T000010.cnv"]
+        );
+        let since = storage
+            .list_segments_matching("cam-a", range(Some(2_600), None), 10)
+            .await
+            .unwrap();
+        assert_eq!(names(since), ["20200101T000030.cnv"]);
+        let newest_until = storage
+            .list_segments_matching("cam-a", range(None, Some(2_400)), 1)
+            .await
+            .unwrap();
+        assert_eq!(names(newest_until), ["20200101T000010.cnv"]);
+
+        let (page, more) = storage
+            .list_segments_page("cam-a", None, range(Some(1_000), Some(2_000)), 1)
+            .await
+            .unwrap();
+        assert!(more);
+        assert_eq!(page[0].start_unix, 2_000);
+        let (rest, more) = storage
+            .list_segments_page(
+                "cam-a",
+                Some((page[0].start_unix, &page[0].name)),
+                range(Some(1_000), Some(2_000)),
+                1,
+            )
+            .await
+            .unwrap();
+        assert!(!more);
+        assert_eq!(names(rest), ["20200101T000000.cnv"]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn encryptor_status_follows_failed_and_successful_passes() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-encryptor-status-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        write_finished(&dir.join("000000.mp4"), b"test clip");
+        // A directory where the sealed blob's temp file goes makes its write fail.
+        std::fs::create_dir(dir.join("000000.cnv.tmp")).unwrap();
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        assert!(!storage.encryptor_status().failing);
+
+        assert!(storage.encrypt_pending_once().await.is_err());
+        let status = storage.encryptor_status();
+        assert!(status.failing);
+        assert!(
+            status
+                .last_error
+                .as_deref()
+                .unwrap()
+                .contains("000000.cnv.tmp")
+        );
+        assert!(status.last_error_unix.is_some() && status.last_success_unix.is_none());
+        assert_eq!(status.pending_segments, 1);
+
+        std::fs::remove_dir(dir.join("000000.cnv.tmp")).unwrap();
+        let now = crate::util::now_unix_seconds();
+        write_modified(&dir.join("000010.mp4"), b"still recording", now);
+        storage.encrypt_pending_once().await.unwrap();
+        let status = storage.encryptor_status();
+        assert!(!status.failing);
+        assert!(status.last_success_unix.is_some() && status.last_error.is_some());
+        assert_eq!(status.pending_segments, 1);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn parallel_passes_seal_each_finished_segment_once() {
+        let now = crate::util::now_unix_seconds();
+        for opaque_names in [false, true] {
+            let root = std::env::temp_dir().join(format!(
+                "constitute-nvr-storage-parallel-seal-test-{opaque_names}-{}",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&root);
+            for source_id in ["cam-a", "cam-b"] {
+                let dir = root.join("segments").join(source_id).join("20240101");
+                std::fs::create_dir_all(&dir).unwrap();
+                for index in 0..6 {
+                    let contents = format!("{source_id} clip {index}");
+                    write_finished(&dir.join(format!("0000{index}0.mp4")), contents.as_bytes());
+                }
+                // The newest segment of each source is still being recorded.
+                write_modified(&dir.join("000100.mp4"), b"recording", now);
+            }
+            let storage = StorageManager::new(root.clone(), &"11".repeat(32))
+                .unwrap()
+                .with_opaque_names(opaque_names)
+                .with_encrypt_workers(3);
+
+            // Two passes at once: the second waits, then finds nothing left to seal.
+            let (first, second) = tokio::join!(
+                storage.encrypt_pending_once(),
+                storage.encrypt_pending_once()
+            );
+            first.unwrap();
+            second.unwrap();
+            let status = storage.encryptor_status();
+            assert_eq!((status.pending_segments, status.failing), (2, false));
+            assert_eq!(status.last_pass_backlog, 0);
+            assert!(
+                storage
+                    .render_encryptor_metrics()
+                    .contains("constitute_nvr_encrypt_pending_segments 2")
+            );
+            for source_id in ["cam-a", "cam-b"] {
+                let segments = storage.list_segments(source_id, 100).await.unwrap();
+                let sealed = segments
+                    .iter()
+                    .filter(|entry| entry.name.ends_with(".cnv"))
+                    .count();
+                assert_eq!(sealed, 6, "{source_id}");
+                for index in 0..6 {
+                    let name = format!("20240101T0000{index}0.cnv");
+                    let plain = storage.read_segment(source_id, &name).await.unwrap();
+                    let expected = format!("{source_id} clip {index}");
+                    assert_eq!(plain.as_slice(), expected.as_bytes());
+                }
+            }
+            let _ = std::fs::remove_dir_all(&root);
+        }
+    }
+
+    #[tokio::test]
+    async fn named_segments_are_deleted_unless_bookmarked_or_unsafe() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-delete-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = crate::util::now_unix_seconds();
+        write_modified(&dir.join("000000.mp4"), b"test clip", now - 7200);
+        write_modified(&dir.join("000010.mp4"), b"kept for an incident", now - 3600);
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        storage.encrypt_pending_once().await.unwrap();
+        let held = storage
+            .list_segments("cam-a", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.name == "20240101T000010.cnv")
+            .unwrap();
+        storage.set_bookmarks(
+            "incident:test",
+            vec![Bookmark {
+                owner: "incident:test".to_string(),
+                source_id: "cam-a".to_string(),
+                from_unix: held.start_unix,
+                to_unix: held.end_unix,
+                release_unix: 0,
+            }],
+        );
+
+        for unsafe_name in [
+            "../cam-b/20240101T000000.cnv",
+            ".names.cnvm",
+            "notes.txt",
+            "..",
+        ] {
+            let names = ["20240101T000000.cnv".to_string(), unsafe_name.to_string()];
+            assert!(
+                storage
+                    .delete_segments("cam-a", &names, false)
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(storage.list_segments("cam-a", 10).await.unwrap().len(), 2);
+
+        let sealed_len = std::fs::metadata(dir.join("000000.cnv")).unwrap().len();
+        let names = [
+            "20240101T000000.cnv",
+            "20240101T000010.cnv",
+            "20240101T000000.cnv",
+            "20240101T000020.cnv",
+        ]
+        .map(String::from);
+        let deleted = storage
+            .delete_segments("cam-a", &names, false)
+            .await
+            .unwrap();
+        let summary = deleted
+            .iter()
+            .map(|deletion| {
+                (
+                    deletion.name.as_str(),
+                    deletion.existed,
+                    deletion.bookmarked,
+                    deletion.bytes,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("20240101T000000.cnv", true, false, sealed_len),
+                ("20240101T000010.cnv", true, true, 0),
+                ("20240101T000020.cnv", false, false, 0),
+            ]
+        );
+        assert!(!dir.join("000000.cnv").exists());
+        assert!(
+            !day_index::load(&dir)
+                .entries
+                .contains_key("20240101T000000.cnv")
+        );
+
+        let forced = storage
+            .delete_segment("cam-a", "20240101T000010.cnv", true)
+            .await
+            .unwrap();
+        assert!(forced.existed && !forced.bookmarked && forced.bytes > 0);
+        assert!(storage.list_segments("cam-a", 10).await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn the_segment_being_recorded_is_sealed_only_once_finished() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-in-progress-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let segments = root.join("segments");
+        let now = crate::util::now_unix_seconds();
+        let day = |source: &str, day: &str| {
+            let dir = segments.join(source).join(day);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        };
+        // Just closed, and the one ffmpeg moved on to.
+        let recording = day("cam-a", "20240101");
+        write_modified(&recording.join("000000.mp4"), b"closed", now);
+        write_modified(&recording.join("000010.mp4"), b"open", now);
+        day("cam-a", "20240102");
+        // The last segment of a day, with the recorder now in the next day's directory.
+        let (evening, morning) = (day("cam-b", "20240101"), day("cam-b", "20240102"));
+        write_modified(&evening.join("235950.mp4"), b"closed", now);
+        write_modified(&morning.join("000000.mp4"), b"open", now);
+        // A recorder that stopped: its last segment has gone quiet.
+        let stopped = day("cam-c", "20240101");
+        write_modified(&stopped.join("000000.mp4"), b"last", now - 120);
+
+        let storage = StorageManager::new(root.clone(), &"77".repeat(32)).unwrap();
+        storage.encrypt_pending_once().await.unwrap();
+        assert!(recording.join("000000.cnv").exists());
+        assert!(recording.join("000010.mp4").exists());
+        assert!(!recording.join("000010.cnv").exists());
+        assert!(evening.join("235950.cnv").exists());
+        assert!(morning.join("000000.mp4").exists());
+        assert!(!morning.join("000000.cnv").exists());
+        assert!(stopped.join("000000.cnv").exists());
+
+        // Once a newer segment starts, the one left behind is sealed whole.
+        std::fs::write(recording.join("000010.mp4"), b"open, now closed").unwrap();
+        std::fs::write(recording.join("000020.mp4"), b"open").unwrap();
+        storage.encrypt_pending_once().await.unwrap();
+        assert_eq!(
+            storage
+                .read_segment("cam-a", "20240101T000010.cnv")
+                .await
+                .unwrap()
+                .as_slice(),
+            b"open, now closed"
+        );
+        assert!(!recording.join("000020.cnv").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn a_crash_between_sealing_and_removing_the_plaintext_is_recovered() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-torn-write-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = [0x33; 32];
+        // Renamed into place, but the plaintext was never removed.
+        write_finished(&dir.join("000000.mp4"), b"sealed before the crash");
+        let whole = chunked::seal(&key, None, b"sealed before the crash").unwrap();
+        std::fs::write(dir.join("000000.cnv"), &whole).unwrap();
+        // Cut short mid-write, with a temp file nobody renamed.
+        write_finished(&dir.join("000010.mp4"), b"torn by the crash");
+        let torn = chunked::seal(&key, None, b"torn by the crash").unwrap();
+        std::fs::write(dir.join("000010.cnv"), &torn[..torn.len() - 4]).unwrap();
+        std::fs::write(dir.join("000010.cnv.tmp"), &torn[..8]).unwrap();
+        // Zero-length leftovers of a recorder and of an encryption that never wrote.
+        std::fs::write(dir.join("000020.mp4"), b"").unwrap();
+        std::fs::write(dir.join("000030.cnv"), b"").unwrap();
+
+        let storage = StorageManager::new(root.clone(), &"33".repeat(32)).unwrap();
+        let summary = storage.reconcile().await.unwrap();
+        assert_eq!(
+            summary,
+            ReconcileSummary {
+                temp_files: 1,
+                torn_segments: 1,
+                empty_segments: 2,
+                plaintext_segments: 2,
+                orphan_segments: 0,
+            }
+        );
+        assert_eq!(storage.last_reconcile().unwrap().summary, summary);
+        assert!(!dir.join("000020.mp4").exists());
+        assert!(!dir.join("000030.cnv").exists());
+        assert!(!dir.join("000010.cnv.tmp").exists());
+        assert!(!dir.join("000010.cnv").exists());
+        assert!(dir.join("000000.cnv").exists());
+
+        storage.encrypt_pending_once().await.unwrap();
+        assert!(!dir.join("000000.mp4").exists());
+        assert!(!dir.join("000010.mp4").exists());
+        assert_eq!(std::fs::read(dir.join("000000.cnv")).unwrap(), whole);
+        for (name, plain) in [
+            ("20240101T000000.cnv", &b"sealed before the crash"[..]),
+            ("20240101T000010.cnv", &b"torn by the crash"[..]),
+        ] {
+            let read = storage.read_segment("cam-a", name).await.unwrap();
+            assert_eq!(read.as_slice(), plain);
+        }
+        // The blob kept from before the crash is recorded when it is finished.
+        let report = storage.verify_segments("cam-a", None).await.unwrap();
+        assert_eq!((report.verified, report.unrecorded.len()), (2, 0));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn sealed_segments_missing_from_the_manifest_are_reported() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-orphan-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        let bare = root.join("segments").join("cam-b").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = [0x44; 32];
+        let storage = StorageManager::new(root.clone(), &"44".repeat(32)).unwrap();
+        write_finished(&dir.join("000000.mp4"), b"recorded");
+        storage.encrypt_pending_once().await.unwrap();
+        // Sealed after the manifest's first line, but never recorded in it.
+        let orphan = chunked::seal(&key, None, b"orphan").unwrap();
+        std::fs::write(dir.join("000010.cnv"), &orphan).unwrap();
+        // Sealed before the manifest existed, and a source with no manifest at all.
+        let old = chunked::seal(&key, None, b"old").unwrap();
+        write_modified(&dir.join("000020.cnv"), &old, 1_000);
+        std::fs::create_dir_all(&bare).unwrap();
+        std::fs::write(bare.join("000000.cnv"), &old).unwrap();
+
+        let summary = storage.reconcile().await.unwrap();
+        assert_eq!(
+            summary,
+            ReconcileSummary {
+                orphan_segments: 1,
+                ..ReconcileSummary::default()
+            }
+        );
+        assert!(dir.join("000010.cnv").exists());
+        let report = storage.verify_segments("cam-a", None).await.unwrap();
+        assert_eq!(
+            report.unrecorded,
+            ["20240101T000010.cnv", "20240101T000020.cnv"]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn dated_and_flat_layouts_read_side_by_side() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-layout-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(dir.join("20240102")).unwrap();
+        write_finished(&dir.join("20240102").join("080000.mp4"), b"dated");
+        write_finished(&dir.join("20240101T230000.mp4"), b"flat");
+
+        let storage = StorageManager::new(root.clone(), &"44".repeat(32)).unwrap();
+        storage.encrypt_pending_once().await.unwrap();
+        assert!(dir.join("20240102").join("080000.cnv").exists());
+        let mut names = storage
+            .list_segments("cam-a", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["20240101T230000.cnv", "20240102T080000.cnv"]);
+        assert_eq!(
+            storage
+                .read_segment("cam-a", "20240102T080000.cnv")
+                .await
+                .unwrap()
+                .as_slice(),
+            b"dated"
+        );
+
+        let report = storage.migrate_day_layout().await.unwrap();
+        assert_eq!(report.segments, 1);
+        assert!(dir.join("20240101").join("230000.cnv").exists());
+        assert_eq!(
+            storage
+                .read_segment("cam-a", "20240101T230000.cnv")
+                .await
+                .unwrap()
+                .as_slice(),
+            b"flat"
+        );
+        assert_eq!(storage.migrate_day_layout().await.unwrap().segments, 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn opaque_names_survive_map_loss_and_migration() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-opaque-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_hex = "22".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        std::fs::write(
+            dir.join("20240101T000000.cnv"),
+            seal_blob(&key, b"legacy").unwrap(),
+        )
+        .unwrap();
+        write_finished(&dir.join("20240101T000010.mp4"), b"fresh");
+
+        let storage = StorageManager::new(root.clone(), &key_hex)
+            .unwrap()
+            .with_opaque_names(true);
+        storage.encrypt_pending_once().await.unwrap();
+        assert!(!dir.join("20240101T000010.cnv").exists());
+        assert_eq!(
+            storage
+                .read_segment("cam-a", "20240101T000010.cnv")
+                .await
+                .unwrap()
+                .as_slice(),
+            b"fresh"
+        );
+
+        let report = storage.migrate_opaque_names().await.unwrap();
+        assert_eq!(report.segments, 1);
+        assert!(!dir.join("20240101T000000.cnv").exists());
+        // Sealed beside its new name and renamed into place, with nothing left over.
+        assert!(std::fs::read_dir(&dir).unwrap().all(|entry| {
+            !entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".tmp")
+        }));
+        assert_eq!(storage.migrate_opaque_names().await.unwrap().segments, 0);
+
+        std::fs::remove_file(dir.join(name_map::MAP_FILE)).unwrap();
+        let mut names = storage
+            .list_segments("cam-a", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["20240101T000000.cnv", "20240101T000010.cnv"]);
+        assert_eq!(
+            storage
+                .read_segment("cam-a", "20240101T000000.cnv")
+                .await
+                .unwrap()
+                .as_slice(),
+            b"legacy"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn indexed_start_orders_segments_and_flags_misnamed_ones() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-index-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let day = root.join("segments").join("cam-a").join("20240102");
+        std::fs::create_dir_all(&day).unwrap();
+        fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+            let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+            out.extend_from_slice(kind);
+            out.extend_from_slice(payload);
+            out
+        }
+        // mvhd version 0: timescale 1000, duration 10s.
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&10_000u32.to_be_bytes());
+        let movie = mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd));
+        // Named 08:00 on 2024-01-02 but written in 2001 by a clock that was never set.
+        let plain = day.join("080000.mp4");
+        std::fs::write(&plain, &movie).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&plain)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_010))
+            .unwrap();
+
+        let storage = StorageManager::new(root.clone(), &"66".repeat(32)).unwrap();
+        storage.encrypt_pending_once().await.unwrap();
+        assert!(day.join(day_index::INDEX_FILE).exists());
+        let listed = storage.list_segments("cam-a", 10).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].start_unix, 1_000_000_000);
+        assert_eq!(listed[0].end_unix, 1_000_000_010);
+        assert_eq!(listed[0].plaintext_bytes, Some(movie.len() as u64));
+        assert_eq!(listed[0].duration_ms, Some(10_000));
+        let media = storage
+            .segment_media("cam-a", &listed[0].name)
+            .await
+            .unwrap();
+        assert_eq!(media.size(movie.len()), (movie.len() as u64, true));
+        assert_eq!(media.duration_ms, Some(10_000));
+        // Segments sealed before sizes were recorded announce the decrypted length.
+        assert_eq!(SegmentMedia::default().size(7), (7, false));
+
+        let anomalies = storage.clock_anomalies(120).await.unwrap();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].source_id, "cam-a");
+        assert_eq!(anomalies[0].segments, 1);
+        assert_eq!(anomalies[0].from_unix, 1_000_000_000);
+        assert!(anomalies[0].max_skew_secs < 0);
+
+        // A cached read must not outlive the purge.
+        let read = storage
+            .read_segment("cam-a", &listed[0].name)
+            .await
+            .unwrap();
+        assert_eq!(read.as_slice(), movie.as_slice());
+        let rekeyed = StorageManager::new(root.clone(), &"77".repeat(32)).unwrap();
+        assert!(matches!(
+            rekeyed.read_segment("cam-a", &listed[0].name).await,
+            Err(StorageError::WrongKey)
+        ));
+        let purged = storage
+            .purge_segments("cam-a", 1_000_000_005, 1_000_000_005, false, false)
+            .await
+            .unwrap();
+        assert_eq!(purged.segments, 1);
+        assert!(day_index::load(&day).entries.is_empty());
+        assert!(matches!(
+            storage.read_segment("cam-a", &listed[0].name).await,
+            Err(StorageError::NotFound { .. })
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn downloads_stream_large_segments_a_chunk_at_a_time() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-stream-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_hex = "55".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        let media = (0..5 * 1024 * 1024 + 123)
+            .map(|idx: usize| (idx * 31 % 251) as u8)
+            .collect::<Vec<_>>();
+        write_finished(&dir.join("000000.mp4"), &media);
+        std::fs::write(
+            dir.join("000010.cnv"),
+            seal_blob(&key, b"single message").unwrap(),
+        )
+        .unwrap();
+        let storage = StorageManager::new(root.clone(), &key_hex).unwrap();
+
+        // Still recording: the unsealed file is read as it lies.
+        let mut stream = storage
+            .read_segment_stream("cam-a", "20240101T000000.mp4")
+            .await
+            .unwrap();
+        assert_eq!(stream.len(), media.len() as u64);
+        assert_eq!(
+            stream.next().await.unwrap().unwrap().as_slice(),
+            &media[..STREAM_CHUNK_BYTES]
+        );
+
+        storage.encrypt_pending_once().await.unwrap();
+        let sealed = std::fs::read(dir.join("000000.cnv")).unwrap();
+        assert!(sealed.starts_with(b"CNRV2"));
+        let mut stream = storage
+            .read_segment_stream("cam-a", "20240101T000000.cnv")
+            .await
+            .unwrap();
+        assert_eq!(stream.len(), media.len() as u64);
+        let mut received = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = stream.next().await.unwrap() {
+            assert!(chunk.len() <= STREAM_CHUNK_BYTES);
+            received.extend_from_slice(&chunk);
+            chunks += 1;
+        }
+        assert_eq!(received, media);
+        assert_eq!(chunks, media.len().div_ceil(STREAM_CHUNK_BYTES));
+        // Nothing was decrypted whole on the way.
+        let format = storage.format.load(Ordering::Relaxed);
+        assert!(
+            storage
+                .segment_cache
+                .cached("cam-a", "20240101T000000.cnv", format)
+                .is_none()
+        );
+
+        // A single-message blob still downloads, from its decrypted buffer.
+        let mut stream = storage
+            .read_segment_stream("cam-a", "20240101T000010.cnv")
+            .await
+            .unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap().as_slice(),
+            b"single message"
+        );
+        assert!(stream.next().await.unwrap().is_none());
+        assert!(matches!(
+            storage
+                .read_segment_stream("cam-a", "20240101T000020.cnv")
+                .await,
+            Err(StorageError::NotFound { .. })
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn ranges_of_single_message_blobs_are_served_from_a_chunked_spool() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-spool-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_hex = "56".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        let media = (0..3 * STREAM_CHUNK_BYTES + 11)
+            .map(|idx| (idx * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(dir.join("000000.cnv"), seal_blob(&key, &media).unwrap()).unwrap();
+        let storage = StorageManager::new(root.clone(), &key_hex).unwrap();
+        let spools = || std::fs::read_dir(root.join("range-spool")).unwrap().count();
+
+        let offset = STREAM_CHUNK_BYTES as u64 + 5;
+        for _ in 0..2 {
+            let mut stream = storage
+                .read_segment_ranged("cam-a", "20240101T000000.cnv")
+                .await
+                .unwrap();
+            assert_eq!(stream.len(), media.len() as u64);
+            let range = stream.read_range(offset, 100).await.unwrap();
+            assert_eq!(
+                range.as_slice(),
+                &media[offset as usize..offset as usize + 100]
+            );
+            storage
+                .segment_cache
+                .forget("cam-a", &["20240101T000000.cnv".to_string()]);
+        }
+        assert_eq!(spools(), 1);
+
+        // A spool left unopened past the idle limit is collected.
+        assert_eq!(storage.collect_range_spools().await.unwrap(), 0);
+        let spool_dir = std::fs::read_dir(root.join("range-spool"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let spool = std::fs::read_dir(&spool_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let idle = std::time::SystemTime::now()
+            - std::time::Duration::from_secs(range_spool::SPOOL_IDLE_SECS + 60);
+        std::fs::File::options()
+            .write(true)
+            .open(&spool)
+            .unwrap()
+            .set_modified(idle)
+            .unwrap();
+        assert_eq!(storage.collect_range_spools().await.unwrap(), 1);
+        assert_eq!(spools(), 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_segment_removes_its_range_spool() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-spool-delete-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_hex = "57".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        for name in ["000000.cnv", "000100.cnv"] {
+            std::fs::write(dir.join(name), seal_blob(&key, b"footage").unwrap()).unwrap();
+        }
+        let storage = StorageManager::new(root.clone(), &key_hex).unwrap();
+        for name in ["20240101T000000.cnv", "20240101T000100.cnv"] {
+            let mut stream = storage.read_segment_ranged("cam-a", name).await.unwrap();
+            stream.read_range(0, 4).await.unwrap();
+        }
+        let spools = || std::fs::read_dir(root.join("range-spool")).unwrap().count();
+        assert_eq!(spools(), 2);
+
+        let deleted = storage
+            .delete_segment("cam-a", "20240101T000000.cnv", true)
+            .await
+            .unwrap();
+        assert!(deleted.existed);
+        assert_eq!(spools(), 1);
+        let summary = storage
+            .purge_segments("cam-a", 0, u64::MAX, true, false)
+            .await
+            .unwrap();
+        assert_eq!(summary.segments, 1);
+        assert_eq!(spools(), 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
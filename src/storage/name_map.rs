@@ -0,0 +1,282 @@
+use crate::crypto;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use super::day_index::SegmentTime;
+use super::{MAGIC_NAMED, StorageError, chunked, open_blob};
+
+const MAP_MAGIC: &[u8] = b"CNRM1";
+pub(super) const MAP_FILE: &str = ".names.cnvm";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "StoredNameMap")]
+pub(super) struct NameMap {
+    entries: BTreeMap<String, NameMapEntry>,
+    #[serde(skip)]
+    by_name: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct StoredNameMap {
+    entries: BTreeMap<String, NameMapEntry>,
+}
+
+impl From<StoredNameMap> for NameMap {
+    fn from(stored: StoredNameMap) -> Self {
+        let mut map = Self::default();
+        for (opaque, entry) in stored.entries {
+            map.insert(opaque, entry);
+        }
+        map
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct NameMapEntry {
+    pub name: String,
+    pub source_id: String,
+    #[serde(default)]
+    pub start_unix: Option<u64>,
+    pub modified_unix: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<SegmentTime>,
+}
+
+impl NameMap {
+    pub fn entries(&self) -> &BTreeMap<String, NameMapEntry> {
+        &self.entries
+    }
+
+    pub fn get(&self, opaque: &str) -> Option<&NameMapEntry> {
+        self.entries.get(opaque)
+    }
+
+    pub fn opaque_for(&self, name: &str) -> Option<&str> {
+        self.by_name.get(name).map(String::as_str)
+    }
+
+    pub fn entry_for(&self, name: &str) -> Option<&NameMapEntry> {
+        self.entries.get(self.opaque_for(name)?)
+    }
+
+    pub fn contains_name(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    pub fn insert(&mut self, opaque: String, entry: NameMapEntry) {
+        let name = entry.name.clone();
+        if let Some(old) = self.entries.insert(opaque.clone(), entry)
+            && old.name != name
+            && self.by_name.get(&old.name) == Some(&opaque)
+        {
+            self.by_name.remove(&old.name);
+        }
+        self.by_name.entry(name).or_insert(opaque);
+    }
+
+    pub fn remove_name(&mut self, name: &str) -> Option<String> {
+        let opaque = self.by_name.remove(name)?;
+        self.entries.remove(&opaque);
+        Some(opaque)
+    }
+
+    pub fn set_time(&mut self, opaque: &str, time: SegmentTime) -> bool {
+        match self.entries.get_mut(opaque) {
+            Some(entry) => {
+                entry.time = Some(time);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn times_mut(&mut self) -> impl Iterator<Item = &mut SegmentTime> {
+        self.entries
+            .values_mut()
+            .filter_map(|entry| entry.time.as_mut())
+    }
+}
+
+pub(super) fn map_path(dir: &Path) -> PathBuf {
+    dir.join(MAP_FILE)
+}
+
+pub(super) fn has_map(dir: &Path) -> bool {
+    map_path(dir).exists()
+}
+
+pub(super) fn load(dir: &Path, key: &[u8]) -> Result<NameMap> {
+    let path = map_path(dir);
+    let raw = match std::fs::read(&path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return rebuild(dir, key),
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+    match open_map(key, &raw) {
+        Ok(map) => Ok(map),
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "segment name map unreadable; rebuilding from headers");
+            let map = rebuild(dir, key)?;
+            save(dir, key, &map)?;
+            Ok(map)
+        }
+    }
+}
+
+pub(super) fn save(dir: &Path, key: &[u8], map: &NameMap) -> Result<()> {
+    let plain = serde_json::to_vec(map)?;
+    let nonce = crypto::random_nonce_24();
+    let cipher = crypto::encrypt_payload(key, &nonce, &plain)?;
+    let mut out = Vec::with_capacity(MAP_MAGIC.len() + nonce.len() + cipher.len());
+    out.extend_from_slice(MAP_MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&cipher);
+
+    let path = map_path(dir);
+    let tmp = dir.join(format!("{MAP_FILE}.tmp"));
+    std::fs::write(&tmp, out).with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("replace {}", path.display()))?;
+    Ok(())
+}
+
+pub(super) fn rebuild(dir: &Path, key: &[u8]) -> Result<NameMap> {
+    let mut map = NameMap::default();
+    let source_id = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(map),
+        Err(err) => return Err(err).with_context(|| format!("read_dir {}", dir.display())),
+    };
+    for entry in read_dir.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("cnv") {
+            continue;
+        }
+        let name = match embedded_name(&path, key) {
+            Ok(Some(name)) => name,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!(path = %path.display(), error = %err, "skipping unreadable opaque segment");
+                continue;
+            }
+        };
+        let Some(opaque) = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+        else {
+            continue;
+        };
+        if map.contains_name(&name) {
+            continue;
+        }
+        let modified_unix = entry
+            .metadata()
+            .and_then(|md| md.modified())
+            .map_or(0, crate::util::clock::unix_secs);
+        map.insert(
+            opaque,
+            NameMapEntry {
+                start_unix: segment_start_unix(&name),
+                name,
+                source_id: source_id.clone(),
+                modified_unix,
+                time: None,
+            },
+        );
+    }
+    Ok(map)
+}
+
+pub(super) fn embedded_name(path: &Path, key: &[u8]) -> Result<Option<String>, StorageError> {
+    use std::io::Read;
+
+    let io = |err| StorageError::Io {
+        context: format!("read segment {}", path.display()),
+        source: err,
+    };
+    let mut file = std::fs::File::open(path).map_err(io)?;
+    let mut head = Vec::new();
+    (&mut file)
+        .take(chunked::MAX_PREFIX as u64)
+        .read_to_end(&mut head)
+        .map_err(io)?;
+    if chunked::is_chunked(&head) {
+        return chunked::read_name(&mut file, &head, key);
+    }
+    if !head.starts_with(MAGIC_NAMED) {
+        return Ok(None);
+    }
+    file.read_to_end(&mut head).map_err(io)?;
+    open_blob(key, &head).map(|(name, _)| name)
+}
+
+pub(super) fn segment_start_unix(name: &str) -> Option<u64> {
+    crate::util::clock::local_stamp_unix(name)
+}
+
+fn open_map(key: &[u8], raw: &[u8]) -> Result<NameMap> {
+    if raw.len() < MAP_MAGIC.len() + 24 || &raw[..MAP_MAGIC.len()] != MAP_MAGIC {
+        return Err(anyhow!("invalid name map header"));
+    }
+    let nonce: [u8; 24] = raw[MAP_MAGIC.len()..MAP_MAGIC.len() + 24]
+        .try_into()
+        .map_err(|_| anyhow!("nonce decode"))?;
+    let plain = crypto::decrypt_payload(key, &nonce, &raw[MAP_MAGIC.len() + 24..])?;
+    Ok(serde_json::from_slice(&plain)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> NameMapEntry {
+        NameMapEntry {
+            name: name.to_string(),
+            source_id: "cam-a".to_string(),
+            start_unix: None,
+            modified_unix: 0,
+            time: None,
+        }
+    }
+
+    #[test]
+    fn names_stay_indexed_through_saves_and_changes() {
+        let dir = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-name-map-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = [7u8; 32];
+        let mut map = NameMap::default();
+        map.insert("aa".to_string(), entry("20240101T000000.cnv"));
+        map.insert("bb".to_string(), entry("20240101T000010.cnv"));
+        save(&dir, &key, &map).unwrap();
+
+        let mut map = load(&dir, &key).unwrap();
+        assert_eq!(map.opaque_for("20240101T000010.cnv"), Some("bb"));
+        assert_eq!(
+            map.remove_name("20240101T000000.cnv").as_deref(),
+            Some("aa")
+        );
+        assert!(!map.contains_name("20240101T000000.cnv"));
+        assert!(map.get("aa").is_none());
+
+        // Remapping a file under another name drops its old one.
+        map.insert("bb".to_string(), entry("20240101T000020.cnv"));
+        assert!(!map.contains_name("20240101T000010.cnv"));
+        assert_eq!(
+            map.entry_for("20240101T000020.cnv")
+                .map(|entry| &entry.name),
+            Some(&"20240101T000020.cnv".to_string())
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
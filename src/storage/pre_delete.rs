@@ -0,0 +1,167 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{Duration, timeout};
+
+#[derive(Clone, Debug)]
+pub struct PreDeleteHook {
+    pub command: Vec<String>,
+    pub timeout: Duration,
+    pub fail_open: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DoomedFile {
+    pub source_id: String,
+    pub name: String,
+    pub path: String,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HookOutcome {
+    pub result: &'static str,
+    pub offered: usize,
+    pub approved: usize,
+    pub blocked: usize,
+    pub error: Option<String>,
+    pub ts: u64,
+}
+
+#[derive(Deserialize)]
+struct HookReply {
+    #[serde(default)]
+    acknowledged: Vec<String>,
+}
+
+impl PreDeleteHook {
+    pub async fn approve(&self, doomed: &[DoomedFile]) -> (HashSet<String>, HookOutcome) {
+        let (result, approved, error) = match timeout(self.timeout, self.run(doomed)).await {
+            Ok(Ok(acknowledged)) => {
+                let approved = doomed
+                    .iter()
+                    .filter(|file| acknowledged.contains(&file.path))
+                    .map(|file| file.path.clone())
+                    .collect::<HashSet<_>>();
+                ("acknowledged", approved, None)
+            }
+            Ok(Err(err)) => ("failed", self.fallback(doomed), Some(err.to_string())),
+            Err(_) => (
+                "timed_out",
+                self.fallback(doomed),
+                Some(format!("no reply within {}s", self.timeout.as_secs())),
+            ),
+        };
+        let outcome = HookOutcome {
+            result,
+            offered: doomed.len(),
+            approved: approved.len(),
+            blocked: doomed.len() - approved.len(),
+            error,
+            ts: crate::util::now_unix_seconds(),
+        };
+        (approved, outcome)
+    }
+
+    fn fallback(&self, doomed: &[DoomedFile]) -> HashSet<String> {
+        if self.fail_open {
+            doomed.iter().map(|file| file.path.clone()).collect()
+        } else {
+            HashSet::new()
+        }
+    }
+
+    async fn run(&self, doomed: &[DoomedFile]) -> Result<HashSet<String>> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow!("pre-delete hook command is empty"))?;
+        // Dropping the child on timeout kills it, so a hung hook does not linger.
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("spawn pre-delete hook {program}"))?;
+        let input = serde_json::to_vec(&json!({ "files": doomed }))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(&input)
+                .await
+                .context("write pre-delete hook stdin")?;
+        }
+        let output = child
+            .wait_with_output()
+            .await
+            .context("wait for pre-delete hook")?;
+        if !output.status.success() {
+            return Err(anyhow!("pre-delete hook exited with {}", output.status));
+        }
+        let reply: HookReply =
+            serde_json::from_slice(&output.stdout).context("parse pre-delete hook reply")?;
+        Ok(reply.acknowledged.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doomed(path: &str) -> DoomedFile {
+        DoomedFile {
+            source_id: "cam-a".to_string(),
+            name: path.to_string(),
+            path: path.to_string(),
+            bytes: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_acknowledged_files_are_approved() {
+        let hook = PreDeleteHook {
+            command: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                r#"cat >/dev/null; echo '{"acknowledged":["/a.cnv","/elsewhere.cnv"]}'"#
+                    .to_string(),
+            ],
+            timeout: Duration::from_secs(10),
+            fail_open: false,
+        };
+        let (approved, outcome) = hook.approve(&[doomed("/a.cnv"), doomed("/b.cnv")]).await;
+        assert_eq!(approved, HashSet::from(["/a.cnv".to_string()]));
+        assert_eq!(outcome.result, "acknowledged");
+        assert_eq!(outcome.blocked, 1);
+    }
+
+    #[tokio::test]
+    async fn timeouts_follow_the_fail_policy() {
+        let mut hook = PreDeleteHook {
+            command: vec!["sleep".to_string(), "5".to_string()],
+            timeout: Duration::from_millis(100),
+            fail_open: false,
+        };
+        let files = [doomed("/a.cnv")];
+        let (approved, outcome) = hook.approve(&files).await;
+        assert!(approved.is_empty());
+        assert_eq!(outcome.result, "timed_out");
+
+        hook.fail_open = true;
+        let (approved, outcome) = hook.approve(&files).await;
+        assert_eq!(approved.len(), 1);
+        assert_eq!(outcome.blocked, 0);
+
+        hook.command = vec!["false".to_string()];
+        hook.fail_open = false;
+        let (_, outcome) = hook.approve(&files).await;
+        assert_eq!(outcome.result, "failed");
+    }
+}
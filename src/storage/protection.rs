@@ -0,0 +1,199 @@
+use super::{StorageError, StorageManager, is_segment_name};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+
+pub(super) const PROTECTED_FILE: &str = ".protected.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct Protected {
+    pub segments: BTreeSet<String>,
+}
+
+impl Protected {
+    pub(super) fn contains(&self, name: &str) -> bool {
+        self.segments.contains(stem(name))
+    }
+}
+
+fn stem(name: &str) -> &str {
+    name.strip_suffix(".cnv")
+        .or_else(|| name.strip_suffix(".mp4"))
+        .unwrap_or(name)
+}
+
+pub(super) fn load(source_dir: &Path) -> Result<Protected> {
+    let path = source_dir.join(PROTECTED_FILE);
+    match std::fs::read(&path) {
+        Ok(raw) => {
+            serde_json::from_slice(&raw).with_context(|| format!("parse {}", path.display()))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Protected::default()),
+        Err(err) => Err(err).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+fn save(source_dir: &Path, protected: &Protected) -> Result<()> {
+    let path = source_dir.join(PROTECTED_FILE);
+    if protected.segments.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let tmp = source_dir.join(format!("{PROTECTED_FILE}.tmp"));
+    std::fs::write(&tmp, serde_json::to_vec_pretty(protected)?)
+        .with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("replace {}", path.display()))
+}
+
+pub(super) fn forget(source_dir: &Path, names: &[String]) -> Result<()> {
+    if !source_dir.join(PROTECTED_FILE).exists() {
+        return Ok(());
+    }
+    let mut protected = load(source_dir)?;
+    let before = protected.segments.len();
+    for name in names {
+        protected.segments.remove(stem(name));
+    }
+    if protected.segments.len() != before {
+        save(source_dir, &protected)?;
+    }
+    Ok(())
+}
+
+impl StorageManager {
+    pub async fn protect_segment(
+        &self,
+        source_id: &str,
+        name: &str,
+        protect: bool,
+    ) -> Result<bool> {
+        if !is_segment_name(name) {
+            return Err(anyhow!("not a segment name: {name:?}"));
+        }
+        let listed = self.list_segments(source_id, usize::MAX).await?;
+        if !listed.iter().any(|entry| entry.name == name) {
+            return Err(StorageError::NotFound {
+                source_id: source_id.to_string(),
+                name: name.to_string(),
+            }
+            .into());
+        }
+        let dir = self.segments_dir(source_id);
+        let lock = Arc::clone(&self.name_map_lock);
+        let stem = stem(name).to_string();
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut protected = load(&dir)?;
+            let changed = if protect {
+                protected.segments.insert(stem)
+            } else {
+                protected.segments.remove(&stem)
+            };
+            if changed {
+                save(&dir, &protected)?;
+            }
+            Ok(changed)
+        })
+        .await
+        .context("join segment protection update")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{RetentionWindow, SegmentFilter};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn protection_outlives_sealing_and_holds_off_retention() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-protection-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let source_dir = root.join("segments").join("cam-a");
+        let day = source_dir.join("20240101");
+        std::fs::create_dir_all(&day).unwrap();
+        let now = crate::util::now_unix_seconds();
+        for (name, age) in [("000000", 7200), ("000010", 3600), ("000020", 1800)] {
+            let path = day.join(format!("{name}.mp4"));
+            std::fs::write(&path, name).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(now - age))
+                .unwrap();
+        }
+        let storage = StorageManager::new(root.clone(), &"55".repeat(32)).unwrap();
+
+        assert!(
+            storage
+                .protect_segment("cam-a", "../x.mp4", true)
+                .await
+                .is_err()
+        );
+        let missing = storage
+            .protect_segment("cam-a", "20240101T000030.mp4", true)
+            .await
+            .unwrap_err();
+        assert!(missing.downcast_ref::<StorageError>().is_some());
+        // Protected while still plaintext.
+        assert!(
+            storage
+                .protect_segment("cam-a", "20240101T000000.mp4", true)
+                .await
+                .unwrap()
+        );
+        storage.encrypt_pending_once().await.unwrap();
+        let only_protected = SegmentFilter {
+            protected_only: true,
+            ..SegmentFilter::default()
+        };
+        let listed = storage
+            .list_segments_matching("cam-a", only_protected, 10)
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "20240101T000000.cnv");
+        assert!(listed[0].protected);
+
+        let window = RetentionWindow {
+            min_days: 0,
+            max_days: 0,
+            max_bytes: 1,
+            active: false,
+        };
+        storage.set_retention_windows(HashMap::from([("cam-a".to_string(), window)]));
+        let summary = storage.enforce_segment_retention().await.unwrap();
+        assert_eq!(summary.removed, 2);
+        assert!(day.join("000000.cnv").exists());
+        let names = ["20240101T000000.cnv".to_string()];
+        let kept = storage
+            .delete_segments("cam-a", &names, false)
+            .await
+            .unwrap();
+        assert!(kept[0].bookmarked);
+
+        // Released, it is an ordinary segment again.
+        assert!(
+            storage
+                .protect_segment("cam-a", "20240101T000000.cnv", false)
+                .await
+                .unwrap()
+        );
+        assert!(!source_dir.join(PROTECTED_FILE).exists());
+        assert_eq!(
+            storage.enforce_segment_retention().await.unwrap().removed,
+            1
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
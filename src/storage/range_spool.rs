@@ -0,0 +1,179 @@
+use super::{SegmentStream, StorageError, StorageManager, chunked};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const SPOOL_DIR: &str = "range-spool";
+pub const SPOOL_IDLE_SECS: u64 = 3600;
+
+impl StorageManager {
+    pub(super) async fn spooled_stream(
+        &self,
+        source_id: &str,
+        name: &str,
+        path: &Path,
+    ) -> Result<SegmentStream, StorageError> {
+        let read = |err| StorageError::read(source_id, name, path, err);
+        let meta = tokio::fs::metadata(path).await.map_err(read)?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_nanos());
+        let id =
+            crate::util::sha256_b64url(&format!("{}\n{}\n{modified}", path.display(), meta.len()));
+        let dir = self.segment_spool_dir(source_id, name);
+        let spool = dir.join(format!("{id}.cnv"));
+        let spool_io = |context: &str, err| StorageError::Io {
+            context: format!("{context} range spool {}", spool.display()),
+            source: err,
+        };
+        let file = match tokio::fs::File::open(&spool).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let plain = self.read_segment(source_id, name).await?;
+                let blob = chunked::seal(&self.key, None, &plain)
+                    .map_err(|err| StorageError::Corrupt(err.to_string()))?;
+                write_spool(&spool, &id, &blob)
+                    .await
+                    .map_err(|err| spool_io("write", err))?;
+                remove_stale_spools(&dir, &spool).await;
+                tokio::fs::File::open(&spool)
+                    .await
+                    .map_err(|err| spool_io("open", err))?
+            }
+            Err(err) => return Err(spool_io("open", err)),
+        };
+        // Opening counts as use, so a spool being served is not collected as idle.
+        let file = file.into_std().await;
+        let _ = file.set_modified(SystemTime::now());
+        let file = tokio::fs::File::from_std(file);
+        let reader = chunked::ChunkReader::open(file, spool, self.key.clone()).await?;
+        Ok(SegmentStream::chunked(reader))
+    }
+
+    pub(super) async fn forget_range_spools(
+        &self,
+        source_id: &str,
+        names: &[String],
+    ) -> Result<()> {
+        for name in names {
+            let dir = self.segment_spool_dir(source_id, name);
+            match tokio::fs::remove_dir_all(&dir).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("remove range spools {}", dir.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn collect_range_spools(&self) -> Result<usize> {
+        let dir = self.range_spool_dir();
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err).with_context(|| format!("read {}", dir.display())),
+        };
+        let now = crate::util::now_unix_seconds();
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if !entry.file_type().await?.is_dir() {
+                removed += remove_if_idle(&path, now).await?;
+                continue;
+            }
+            // A segment delete may remove the directory while this pass walks it.
+            let mut spools = match tokio::fs::read_dir(&path).await {
+                Ok(spools) => spools,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+            };
+            let mut kept = 0;
+            while let Some(spool) = spools.next_entry().await? {
+                match remove_if_idle(&spool.path(), now).await? {
+                    0 => kept += 1,
+                    n => removed += n,
+                }
+            }
+            if kept == 0 {
+                let _ = tokio::fs::remove_dir(&path).await;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn range_spool_dir(&self) -> PathBuf {
+        self.root.join(SPOOL_DIR)
+    }
+
+    fn segment_spool_dir(&self, source_id: &str, name: &str) -> PathBuf {
+        self.range_spool_dir()
+            .join(crate::util::sha256_b64url(&format!("{source_id}\n{name}")))
+    }
+}
+
+async fn write_spool(spool: &Path, id: &str, blob: &[u8]) -> std::io::Result<()> {
+    let dir = spool.parent().unwrap_or(Path::new("."));
+    tokio::fs::create_dir_all(dir).await?;
+    let tmp = dir.join(format!("{id}.{}.tmp", uuid::Uuid::new_v4().simple()));
+    if let Err(err) = tokio::fs::write(&tmp, blob).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(err);
+    }
+    tokio::fs::rename(&tmp, spool).await
+}
+
+async fn remove_if_idle(path: &Path, now: u64) -> Result<usize> {
+    let touched = match tokio::fs::metadata(path).await {
+        Ok(meta) => meta
+            .modified()
+            .map(crate::util::clock::unix_secs)
+            .unwrap_or(0),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err).with_context(|| format!("stat {}", path.display())),
+    };
+    if now.saturating_sub(touched) < SPOOL_IDLE_SECS {
+        return Ok(0);
+    }
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(1),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err).with_context(|| format!("remove range spool {}", path.display())),
+    }
+}
+
+async fn remove_stale_spools(dir: &Path, current: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path != current && path.extension().is_some_and(|ext| ext == "cnv") {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spools_already_removed_are_skipped() {
+        let dir = std::env::temp_dir().join(format!(
+            "constitute-nvr-range-spool-test-{}",
+            uuid::Uuid::new_v4()
+        ));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let spool = dir.join("gone.cnv");
+        tokio::fs::write(&spool, b"spool").await.unwrap();
+        let later = crate::util::now_unix_seconds() + SPOOL_IDLE_SECS;
+        assert_eq!(remove_if_idle(&spool, later).await.unwrap(), 1);
+        assert_eq!(remove_if_idle(&spool, later).await.unwrap(), 0);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
@@ -0,0 +1,377 @@
+use super::jobs::{JobHandle, JobProgress, JobStatus};
+use super::scan::{self, CancellationToken};
+use super::{IoClass, MAGIC, MAGIC_NAMED, StorageManager, chunked, manifest, open_blob};
+use crate::bandwidth::RateLimiter;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+pub const ARCHIVE_FORMAT_VERSION: u32 = 2;
+pub const REENCRYPT_JOB: &str = "reencrypt_archive";
+const CHECKPOINT_FILE: &str = "reencrypt.json";
+const CHECKPOINT_EVERY_FILES: u64 = 50;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReencryptRequest {
+    pub target_version: u32,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(default)]
+    pub throttle_mbps: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReencryptReport {
+    pub target_version: u32,
+    pub scanned: u64,
+    pub rewritten: u64,
+    pub already_current: u64,
+    pub failed: u64,
+    pub resumed: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Checkpoint {
+    job_id: String,
+    request: ReencryptRequest,
+    cursor: Option<String>,
+    done: u64,
+    rewritten: u64,
+    already_current: u64,
+    failed: u64,
+}
+
+impl StorageManager {
+    pub fn start_reencrypt(&self, request: ReencryptRequest) -> Result<JobStatus> {
+        if request.target_version == 0 || request.target_version > ARCHIVE_FORMAT_VERSION {
+            return Err(anyhow!(
+                "unsupported targetVersion {}; newest format is {ARCHIVE_FORMAT_VERSION}",
+                request.target_version
+            ));
+        }
+        let job = self.jobs.begin(REENCRYPT_JOB)?;
+        let checkpoint = Checkpoint {
+            job_id: job.id().to_string(),
+            request,
+            cursor: None,
+            done: 0,
+            rewritten: 0,
+            already_current: 0,
+            failed: 0,
+        };
+        Ok(self.spawn_reencrypt(job, checkpoint))
+    }
+
+    pub fn resume_reencrypt(&self) {
+        let path = self.checkpoint_path();
+        let checkpoint = match std::fs::read(&path) {
+            Ok(raw) => match serde_json::from_slice::<Checkpoint>(&raw) {
+                Ok(checkpoint) => checkpoint,
+                Err(err) => {
+                    warn!(
+                        path = %path.display(),
+                        error = %err,
+                        "discarding unreadable checkpoint"
+                    );
+                    let _ = std::fs::remove_file(&path);
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+        match self
+            .jobs
+            .begin_with_id(REENCRYPT_JOB, checkpoint.job_id.clone())
+        {
+            Ok(job) => {
+                info!(
+                    job_id = %checkpoint.job_id,
+                    done = checkpoint.done,
+                    "resuming re-encryption"
+                );
+                self.spawn_reencrypt(job, checkpoint);
+            }
+            Err(err) => warn!(error = %err, "re-encryption checkpoint not resumed"),
+        }
+    }
+
+    fn checkpoint_path(&self) -> PathBuf {
+        self.root.join("jobs").join(CHECKPOINT_FILE)
+    }
+
+    fn spawn_reencrypt(&self, job: JobHandle, checkpoint: Checkpoint) -> JobStatus {
+        let this = self.clone();
+        job.spawn(move |progress| async move {
+            let result = this.run_reencrypt(&progress, checkpoint).await;
+            // A shutdown keeps the checkpoint so the next start resumes; `cancel_job` does not.
+            if !this.cancel.is_cancelled() {
+                let _ = tokio::fs::remove_file(this.checkpoint_path()).await;
+            }
+            result
+        })
+    }
+
+    async fn run_reencrypt(
+        &self,
+        job: &JobProgress,
+        mut checkpoint: Checkpoint,
+    ) -> Result<ReencryptReport> {
+        let root = self.root.join("segments");
+        let scope = match &checkpoint.request.source_id {
+            Some(source_id) => root.join(crate::util::source_dir_name(source_id)),
+            None => root.clone(),
+        };
+        job.phase("scanning");
+        let files = {
+            let root = root.clone();
+            let cancel = job.cancel_token().clone();
+            tokio::task::spawn_blocking(move || archive_files(&root, &scope, &cancel))
+                .await
+                .context("join archive scan")?
+                .ok_or_else(|| anyhow!("re-encryption cancelled"))?
+        };
+        let total = files.len() as u64;
+        let target = checkpoint.request.target_version;
+        let resumed = checkpoint.cursor.is_some();
+        let limiter = RateLimiter::new(
+            checkpoint
+                .request
+                .throttle_mbps
+                .unwrap_or(0)
+                .saturating_mul(125_000),
+        );
+        let checkpoint_path = self.checkpoint_path();
+        save_checkpoint(&checkpoint_path, &checkpoint).await?;
+        job.phase("rewriting");
+        job.progress(checkpoint.done, total);
+
+        for relative in files {
+            if job.is_cancelled() {
+                save_checkpoint(&checkpoint_path, &checkpoint).await?;
+                return Err(anyhow!("re-encryption cancelled"));
+            }
+            if checkpoint
+                .cursor
+                .as_ref()
+                .is_some_and(|cursor| relative <= *cursor)
+            {
+                continue;
+            }
+            let path = root.join(&relative);
+            let key = self.key.clone();
+            let lock = Arc::clone(&self.name_map_lock);
+            let outcome = tokio::task::spawn_blocking(move || {
+                let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                reencrypt_file(&path, &key, target)
+            })
+            .await
+            .context("join segment re-encryption")?;
+            match outcome {
+                Ok(Rewrite { bytes, rewritten }) => {
+                    limiter.acquire(bytes).await;
+                    self.io.acquire(IoClass::Background, bytes).await;
+                    if rewritten {
+                        // The first path component is the source directory.
+                        let dir = relative.split('/').next().unwrap_or_default();
+                        self.segment_cache.forget_where(|source_id, _| {
+                            crate::util::source_dir_name(source_id) == dir
+                        });
+                        checkpoint.rewritten += 1;
+                    } else {
+                        checkpoint.already_current += 1;
+                    }
+                }
+                Err(err) => {
+                    warn!(segment = %relative, error = %err, "segment not re-encrypted");
+                    checkpoint.failed += 1;
+                }
+            }
+            checkpoint.done += 1;
+            checkpoint.cursor = Some(relative);
+            job.progress(checkpoint.done, total);
+            if checkpoint.done.is_multiple_of(CHECKPOINT_EVERY_FILES) {
+                save_checkpoint(&checkpoint_path, &checkpoint).await?;
+            }
+        }
+
+        Ok(ReencryptReport {
+            target_version: target,
+            scanned: checkpoint.done,
+            rewritten: checkpoint.rewritten,
+            already_current: checkpoint.already_current,
+            failed: checkpoint.failed,
+            resumed,
+        })
+    }
+}
+
+struct Rewrite {
+    bytes: u64,
+    rewritten: bool,
+}
+
+fn blob_version(blob: &[u8]) -> Option<u32> {
+    if chunked::is_chunked(blob) {
+        return Some(2);
+    }
+    (blob.starts_with(MAGIC) || blob.starts_with(MAGIC_NAMED)).then_some(1)
+}
+
+fn reencrypt_file(path: &Path, key: &[u8], target: u32) -> Result<Rewrite> {
+    let blob = std::fs::read(path).with_context(|| format!("read segment {}", path.display()))?;
+    let bytes = blob.len() as u64;
+    let version = blob_version(&blob).ok_or_else(|| anyhow!("unrecognised blob header"))?;
+    if version >= target {
+        return Ok(Rewrite {
+            bytes,
+            rewritten: false,
+        });
+    }
+    let (name, sealed) = reseal(key, &blob)?;
+    let tmp = path.with_extension("cnv.tmp");
+    std::fs::write(&tmp, &sealed).with_context(|| format!("write {}", tmp.display()))?;
+    // A purge may have removed the segment meanwhile; renaming would bring it back.
+    if !path.exists() {
+        let _ = std::fs::remove_file(&tmp);
+        return Ok(Rewrite {
+            bytes,
+            rewritten: false,
+        });
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("replace {}", path.display()))?;
+    manifest::record_sealed(path, key, name.as_deref(), &sealed)?;
+    Ok(Rewrite {
+        bytes,
+        rewritten: true,
+    })
+}
+
+fn reseal(key: &[u8], blob: &[u8]) -> Result<(Option<String>, Vec<u8>)> {
+    let (name, plain) = open_blob(key, blob)?;
+    let sealed = chunked::seal(key, name.as_deref(), &plain)?;
+    Ok((name, sealed))
+}
+
+fn archive_files(root: &Path, scope: &Path, cancel: &CancellationToken) -> Option<Vec<String>> {
+    let mut files = scan::collect_files(scope, scan::SEGMENT_FILES, cancel)?
+        .into_iter()
+        .filter_map(|path| {
+            path.strip_prefix(root)
+                .ok()
+                .map(|relative| relative.to_string_lossy().to_string())
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Some(files)
+}
+
+async fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(checkpoint)?)
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::jobs::JobState;
+    use crate::storage::{seal_blob, seal_named_blob};
+
+    #[test]
+    fn reseal_keeps_layout_and_plaintext() {
+        let key = vec![7u8; 32];
+        let named = seal_named_blob(&key, "20240101T000000.cnv", b"media").unwrap();
+        let (name, resealed) = reseal(&key, &named).unwrap();
+        assert_eq!(name.as_deref(), Some("20240101T000000.cnv"));
+        assert_ne!(resealed, named);
+        let (name, plain) = open_blob(&key, &resealed).unwrap();
+        assert_eq!(name.as_deref(), Some("20240101T000000.cnv"));
+        assert_eq!(plain.as_slice(), b"media");
+        assert_eq!(blob_version(&named), Some(1));
+        assert_eq!(blob_version(&resealed), Some(ARCHIVE_FORMAT_VERSION));
+        assert_eq!(blob_version(b"garbage"), None);
+
+        let (_, plain) = reseal(&key, &seal_blob(&key, b"media").unwrap()).unwrap();
+        assert!(plain.starts_with(b"CNRV2"));
+        assert_eq!(open_blob(&key, &plain).unwrap().0, None);
+    }
+
+    #[tokio::test]
+    async fn reencrypt_job_resumes_from_its_checkpoint() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-reencrypt-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("segments").join("cam-a").join("20240101");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_hex = "33".repeat(32);
+        let key = hex::decode(&key_hex).unwrap();
+        for time in ["000000", "000010", "000020"] {
+            let blob = seal_blob(&key, time.as_bytes()).unwrap();
+            std::fs::write(dir.join(format!("{time}.cnv")), blob).unwrap();
+        }
+        let storage = StorageManager::new(root.clone(), &key_hex).unwrap();
+
+        let too_new = ReencryptRequest {
+            target_version: ARCHIVE_FORMAT_VERSION + 1,
+            source_id: None,
+            throttle_mbps: None,
+        };
+        assert!(storage.start_reencrypt(too_new).is_err());
+
+        // A checkpoint left by an interrupted run: the first file is already done.
+        save_checkpoint(
+            &storage.checkpoint_path(),
+            &Checkpoint {
+                job_id: "resumed-job".to_string(),
+                request: ReencryptRequest {
+                    target_version: 1,
+                    source_id: Some("cam-a".to_string()),
+                    throttle_mbps: Some(100),
+                },
+                cursor: Some("cam-a/20240101/000000.cnv".to_string()),
+                done: 1,
+                rewritten: 0,
+                already_current: 1,
+                failed: 0,
+            },
+        )
+        .await
+        .unwrap();
+        let before = std::fs::read(dir.join("000010.cnv")).unwrap();
+        storage.resume_reencrypt();
+        assert!(storage.migrate_day_layout().await.is_err());
+
+        let status = loop {
+            let status = storage.jobs.get("resumed-job").unwrap();
+            if status.state != JobState::Running {
+                break status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(status.done, 3);
+        assert_eq!(status.report["alreadyCurrent"], 3);
+        assert_eq!(status.report["resumed"], true);
+        // Every blob is already version 1, so nothing was rewritten.
+        assert_eq!(std::fs::read(dir.join("000010.cnv")).unwrap(), before);
+        assert!(!storage.checkpoint_path().exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,342 @@
+use super::snapshots::is_plain_component;
+use super::{StorageError, StorageManager, resolve_segment_path};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+pub const MAX_REPLICA_SEGMENT_BYTES: u64 = 512 * 1024 * 1024;
+const REPLICAS_DIR: &str = "replicas";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicaConfig {
+    pub origin_node: String,
+    pub origin_device_pk: String,
+    pub sent_unix: u64,
+    pub received_unix: u64,
+    pub sources: Vec<Value>,
+    pub retention: Value,
+    pub bookmarks: Vec<Value>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MirroredSource {
+    pub origin_node: String,
+    pub source_id: String,
+    pub read_only: bool,
+    pub segments: usize,
+    pub bytes: u64,
+    pub newest_segment: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicaStatus {
+    pub origin_node: String,
+    pub last_sync_unix: u64,
+    pub lag_secs: u64,
+    pub sources: usize,
+    pub segments: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkReceipt {
+    pub received: u64,
+    pub complete: bool,
+}
+
+impl StorageManager {
+    pub async fn store_replica_config(&self, config: &ReplicaConfig) -> Result<()> {
+        let path = self.replica_config_path(&config.origin_node)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(config)?)
+            .await
+            .with_context(|| format!("write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .with_context(|| format!("replace {}", path.display()))?;
+        Ok(())
+    }
+
+    pub async fn replica_configs(&self) -> Result<Vec<ReplicaConfig>> {
+        let dir = self.root.join(REPLICAS_DIR);
+        let mut rd = match tokio::fs::read_dir(&dir).await {
+            Ok(rd) => rd,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).with_context(|| format!("read_dir {}", dir.display())),
+        };
+        let mut out = Vec::new();
+        while let Some(entry) = rd.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let raw = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("read {}", path.display()))?;
+            out.push(
+                serde_json::from_slice(&raw)
+                    .with_context(|| format!("parse {}", path.display()))?,
+            );
+        }
+        out.sort_by(|a: &ReplicaConfig, b| a.origin_node.cmp(&b.origin_node));
+        Ok(out)
+    }
+
+    pub async fn write_replica_chunk(
+        &self,
+        origin_node: &str,
+        source_id: &str,
+        name: &str,
+        offset: u64,
+        total: u64,
+        data: &[u8],
+    ) -> Result<ChunkReceipt> {
+        if !name.ends_with(".cnv") || !is_plain_component(name) {
+            return Err(anyhow!("mirrored segments must be sealed .cnv files"));
+        }
+        if total > MAX_REPLICA_SEGMENT_BYTES {
+            return Err(anyhow!(
+                "segment of {total} bytes exceeds {MAX_REPLICA_SEGMENT_BYTES}"
+            ));
+        }
+        let dir = self.replica_source_dir(origin_node, source_id)?;
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("create {}", dir.display()))?;
+        let part = dir.join(format!("{name}.part"));
+        let received = match tokio::fs::metadata(&part).await {
+            Ok(meta) if offset > 0 => meta.len(),
+            _ => 0,
+        };
+        if offset != received {
+            return Err(anyhow!(
+                "chunk at {offset} does not continue the {received} bytes received"
+            ));
+        }
+        let end = offset + data.len() as u64;
+        if end > total {
+            return Err(anyhow!(
+                "chunk ends at {end}, past the {total} byte segment"
+            ));
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&part)
+            .await
+            .with_context(|| format!("open {}", part.display()))?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, data).await?;
+        tokio::io::AsyncWriteExt::flush(&mut file).await?;
+        let complete = end == total;
+        if complete {
+            tokio::fs::rename(&part, dir.join(name))
+                .await
+                .with_context(|| format!("finish {}", part.display()))?;
+        }
+        Ok(ChunkReceipt {
+            received: end,
+            complete,
+        })
+    }
+
+    pub async fn mirrored_sources(&self) -> Result<Vec<MirroredSource>> {
+        let root = self.root.join(REPLICAS_DIR).join("segments");
+        tokio::task::spawn_blocking(move || mirrored_sources(&root))
+            .await
+            .context("join mirror listing")?
+    }
+
+    pub async fn replica_status(&self) -> Result<Vec<ReplicaStatus>> {
+        let now = crate::util::now_unix_seconds();
+        let mirrored = self.mirrored_sources().await?;
+        Ok(self
+            .replica_configs()
+            .await?
+            .into_iter()
+            .map(|config| ReplicaStatus {
+                last_sync_unix: config.received_unix,
+                lag_secs: now.saturating_sub(config.received_unix),
+                sources: config.sources.len(),
+                segments: mirrored
+                    .iter()
+                    .filter(|source| source.origin_node == config.origin_node)
+                    .map(|source| source.segments)
+                    .sum(),
+                origin_node: config.origin_node,
+            })
+            .collect())
+    }
+
+    pub async fn read_sealed_segment(
+        &self,
+        source_id: &str,
+        name: &str,
+    ) -> Result<Vec<u8>, StorageError> {
+        if !name.ends_with(".cnv") {
+            return Err(StorageError::NotFound {
+                source_id: source_id.to_string(),
+                name: name.to_string(),
+            });
+        }
+        let dir = self.segments_dir(source_id);
+        let map = self.load_segment_name_map(&dir).await?;
+        let path = resolve_segment_path(&dir, map.as_ref(), name);
+        tokio::fs::read(&path)
+            .await
+            .map_err(|err| StorageError::read(source_id, name, &path, err))
+    }
+
+    fn replica_config_path(&self, origin_node: &str) -> Result<PathBuf> {
+        if !is_plain_component(origin_node) {
+            return Err(anyhow!("invalid originNode"));
+        }
+        Ok(self
+            .root
+            .join(REPLICAS_DIR)
+            .join(format!("{origin_node}.json")))
+    }
+
+    fn replica_source_dir(&self, origin_node: &str, source_id: &str) -> Result<PathBuf> {
+        if !is_plain_component(origin_node) {
+            return Err(anyhow!("invalid originNode"));
+        }
+        if !is_plain_component(source_id) {
+            return Err(anyhow!("invalid sourceId"));
+        }
+        Ok(self
+            .root
+            .join(REPLICAS_DIR)
+            .join("segments")
+            .join(origin_node)
+            .join(crate::util::source_dir_name(source_id)))
+    }
+}
+
+fn mirrored_sources(root: &Path) -> Result<Vec<MirroredSource>> {
+    let mut out = Vec::new();
+    for origin in sorted_dirs(root)? {
+        let origin_node = origin.file_name().to_string_lossy().to_string();
+        for source in sorted_dirs(&origin.path())? {
+            let mut mirrored = MirroredSource {
+                origin_node: origin_node.clone(),
+                source_id: source.file_name().to_string_lossy().to_string(),
+                read_only: true,
+                segments: 0,
+                bytes: 0,
+                newest_segment: None,
+            };
+            for entry in std::fs::read_dir(source.path())? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !name.ends_with(".cnv") {
+                    continue;
+                }
+                mirrored.segments += 1;
+                mirrored.bytes += entry.metadata()?.len();
+                if mirrored
+                    .newest_segment
+                    .as_ref()
+                    .is_none_or(|newest| name > *newest)
+                {
+                    mirrored.newest_segment = Some(name);
+                }
+            }
+            out.push(mirrored);
+        }
+    }
+    Ok(out)
+}
+
+fn sorted_dirs(dir: &Path) -> Result<Vec<std::fs::DirEntry>> {
+    let rd = match std::fs::read_dir(dir) {
+        Ok(rd) => rd,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("read_dir {}", dir.display())),
+    };
+    let mut out = rd
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .collect::<Vec<_>>();
+    out.sort_by_key(|entry| entry.file_name());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn chunks_assemble_in_order_and_stay_outside_local_segments() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-replicas-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"22".repeat(32)).unwrap();
+        storage.ensure_dirs().await.unwrap();
+        let name = "20240101T000000.cnv";
+
+        let first = storage
+            .write_replica_chunk("origin", "front", name, 0, 6, b"abc")
+            .await
+            .unwrap();
+        assert!(!first.complete);
+        // A gap or a replay past what was received is refused.
+        assert!(
+            storage
+                .write_replica_chunk("origin", "front", name, 4, 6, b"ef")
+                .await
+                .is_err()
+        );
+        let done = storage
+            .write_replica_chunk("origin", "front", name, 3, 6, b"def")
+            .await
+            .unwrap();
+        assert!(done.complete);
+        let mirrored = root.join("replicas/segments/origin/front").join(name);
+        assert_eq!(std::fs::read(&mirrored).unwrap(), b"abcdef");
+        assert!(storage.list_sources().await.unwrap().is_empty());
+
+        let listed = storage.mirrored_sources().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].segments, listed[0].bytes), (1, 6));
+        assert_eq!(listed[0].newest_segment.as_deref(), Some(name));
+
+        for (origin, source, name) in [
+            ("../origin", "front", name),
+            ("origin", "..", name),
+            ("origin", "front", "20240101T000000.mp4"),
+        ] {
+            assert!(
+                storage
+                    .write_replica_chunk(origin, source, name, 0, 1, b"x")
+                    .await
+                    .is_err()
+            );
+        }
+        let config = ReplicaConfig {
+            origin_node: "origin".to_string(),
+            origin_device_pk: "device".to_string(),
+            sent_unix: 10,
+            received_unix: crate::util::now_unix_seconds(),
+            sources: vec![serde_json::json!({ "sourceId": "front" })],
+            retention: Value::Null,
+            bookmarks: Vec::new(),
+        };
+        storage.store_replica_config(&config).await.unwrap();
+        let status = storage.replica_status().await.unwrap();
+        assert_eq!((status[0].sources, status[0].segments), (1, 1));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
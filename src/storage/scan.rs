@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use walkdir::WalkDir;
+
+pub(super) const SCAN_BATCH_ENTRIES: usize = 500;
+
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    own: Arc<AtomicBool>,
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl CancellationToken {
+    pub fn child(&self) -> Self {
+        Self {
+            own: Arc::default(),
+            parent: Some(Arc::clone(&self.own)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.own.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.own.load(Ordering::Relaxed)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct ScanSpec {
+    pub max_depth: usize,
+    pub extensions: &'static [&'static str],
+}
+
+pub(super) const SEGMENT_FILES: ScanSpec = ScanSpec {
+    max_depth: 3,
+    extensions: &["cnv"],
+};
+
+pub(super) const PLAIN_SEGMENT_FILES: ScanSpec = ScanSpec {
+    max_depth: 3,
+    extensions: &["mp4"],
+};
+
+pub(super) const STORED_SEGMENT_FILES: ScanSpec = ScanSpec {
+    max_depth: 3,
+    extensions: &["cnv", "mp4"],
+};
+
+pub(super) const TEMP_FILES: ScanSpec = ScanSpec {
+    max_depth: 3,
+    extensions: &["tmp"],
+};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct ScanProgress {
+    pub entries: u64,
+    pub matched: u64,
+    pub cancelled: bool,
+}
+
+pub(super) fn scan_files(
+    root: &Path,
+    spec: ScanSpec,
+    cancel: &CancellationToken,
+    mut on_batch: impl FnMut(Vec<PathBuf>, ScanProgress) -> bool,
+) -> ScanProgress {
+    let mut progress = ScanProgress::default();
+    if !root.exists() {
+        return progress;
+    }
+    let mut walker = WalkDir::new(root)
+        .max_depth(spec.max_depth)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.path()));
+    loop {
+        if cancel.is_cancelled() {
+            progress.cancelled = true;
+            return progress;
+        }
+        let mut batch = Vec::new();
+        let mut seen = 0;
+        for entry in walker.by_ref().filter_map(Result::ok) {
+            seen += 1;
+            if entry.file_type().is_file() && has_extension(entry.path(), spec.extensions) {
+                batch.push(entry.into_path());
+            }
+            if seen == SCAN_BATCH_ENTRIES {
+                break;
+            }
+        }
+        progress.entries += seen as u64;
+        progress.matched += batch.len() as u64;
+        if seen == 0 {
+            return progress;
+        }
+        if !batch.is_empty() && !on_batch(batch, progress) {
+            return progress;
+        }
+    }
+}
+
+pub(super) fn collect_files(
+    root: &Path,
+    spec: ScanSpec,
+    cancel: &CancellationToken,
+) -> Option<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let progress = scan_files(root, spec, cancel, |batch, _| {
+        files.extend(batch);
+        true
+    });
+    (!progress.cancelled).then_some(files)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext))
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_in_batches_and_stops_when_cancelled() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-scan-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let day = root.join("cam-a").join("20240101");
+        std::fs::create_dir_all(&day).unwrap();
+        std::fs::create_dir_all(root.join(".hidden")).unwrap();
+        std::fs::write(root.join(".hidden").join("skipped.cnv"), b"x").unwrap();
+        std::fs::write(day.join("notes.txt"), b"x").unwrap();
+        for idx in 0..SCAN_BATCH_ENTRIES + 10 {
+            std::fs::write(day.join(format!("{idx:06}.cnv")), b"x").unwrap();
+        }
+
+        let cancel = CancellationToken::default();
+        let mut batches = Vec::new();
+        let progress = scan_files(&root, SEGMENT_FILES, &cancel, |batch, _| {
+            batches.push(batch.len());
+            true
+        });
+        assert!(batches.len() > 1);
+        assert_eq!(progress.matched as usize, SCAN_BATCH_ENTRIES + 10);
+        assert!(!progress.cancelled);
+
+        let progress = scan_files(&root, SEGMENT_FILES, &cancel, |_, _| {
+            cancel.cancel();
+            true
+        });
+        assert!(progress.cancelled);
+        assert!(progress.matched < (SCAN_BATCH_ENTRIES + 10) as u64);
+        assert!(collect_files(&root, SEGMENT_FILES, &cancel).is_none());
+
+        let parent = CancellationToken::default();
+        let job = parent.child();
+        job.cancel();
+        assert!(job.is_cancelled() && !parent.is_cancelled());
+        let job = parent.child();
+        parent.cancel();
+        assert!(job.is_cancelled());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
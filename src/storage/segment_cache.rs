@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::OnceCell;
+use zeroize::Zeroizing;
+
+pub type Plaintext = Arc<Zeroizing<Vec<u8>>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SegmentCacheSettings {
+    pub max_entries: usize,
+    pub max_bytes: u64,
+}
+
+impl Default for SegmentCacheSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 32,
+            max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    source_id: String,
+    name: String,
+    format: u32,
+}
+
+struct Entry {
+    cell: Arc<OnceCell<Plaintext>>,
+    bytes: u64,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, Entry>,
+    bytes: u64,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+#[derive(Clone, Default)]
+pub struct SegmentCache {
+    settings: SegmentCacheSettings,
+    state: Arc<Mutex<CacheState>>,
+    counters: Arc<Counters>,
+}
+
+impl SegmentCache {
+    pub fn new(settings: SegmentCacheSettings) -> Self {
+        Self {
+            settings,
+            ..Self::default()
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.settings.max_entries > 0 && self.settings.max_bytes > 0
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub async fn get_or_load<F, Fut, E>(
+        &self,
+        source_id: &str,
+        name: &str,
+        format: u32,
+        load: F,
+    ) -> Result<Plaintext, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Zeroizing<Vec<u8>>, E>>,
+    {
+        if !self.enabled() {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+            return load().await.map(Arc::new);
+        }
+        let key = CacheKey {
+            source_id: source_id.to_string(),
+            name: name.to_string(),
+            format,
+        };
+        let cell = {
+            let mut state = self.lock();
+            state.tick += 1;
+            let tick = state.tick;
+            let entry = state.entries.entry(key.clone()).or_insert_with(|| Entry {
+                cell: Arc::default(),
+                bytes: 0,
+                last_used: tick,
+            });
+            entry.last_used = tick;
+            Arc::clone(&entry.cell)
+        };
+        let mut loaded = false;
+        let plain = cell
+            .get_or_try_init(|| async {
+                loaded = true;
+                load().await.map(Arc::new)
+            })
+            .await;
+        let plain = match plain {
+            Ok(plain) => Arc::clone(plain),
+            Err(err) => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                self.discard_if_empty(&key, &cell);
+                return Err(err);
+            }
+        };
+        if loaded {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+            self.admit(&key, &cell, plain.len() as u64);
+        } else {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(plain)
+    }
+
+    pub fn cached(&self, source_id: &str, name: &str, format: u32) -> Option<Plaintext> {
+        if !self.enabled() {
+            return None;
+        }
+        let key = CacheKey {
+            source_id: source_id.to_string(),
+            name: name.to_string(),
+            format,
+        };
+        let mut state = self.lock();
+        state.tick += 1;
+        let tick = state.tick;
+        let entry = state.entries.get_mut(&key)?;
+        let plain = Arc::clone(entry.cell.get()?);
+        entry.last_used = tick;
+        self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        Some(plain)
+    }
+
+    fn admit(&self, key: &CacheKey, cell: &Arc<OnceCell<Plaintext>>, bytes: u64) {
+        let mut state = self.lock();
+        let Some(entry) = state.entries.get_mut(key) else {
+            return;
+        };
+        if !Arc::ptr_eq(&entry.cell, cell) {
+            return;
+        }
+        entry.bytes = bytes;
+        state.bytes += bytes;
+        if bytes > self.settings.max_bytes {
+            self.remove(&mut state, key);
+            return;
+        }
+        while state.entries.len() > self.settings.max_entries
+            || state.bytes > self.settings.max_bytes
+        {
+            // Segments still being decrypted are never the victim; their readers hold them.
+            let victim = state
+                .entries
+                .iter()
+                .filter(|(candidate, entry)| *candidate != key && entry.cell.initialized())
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(candidate, _)| candidate.clone());
+            let Some(victim) = victim else {
+                break;
+            };
+            self.remove(&mut state, &victim);
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn discard_if_empty(&self, key: &CacheKey, cell: &Arc<OnceCell<Plaintext>>) {
+        let mut state = self.lock();
+        if state
+            .entries
+            .get(key)
+            .is_some_and(|entry| Arc::ptr_eq(&entry.cell, cell) && !entry.cell.initialized())
+        {
+            state.entries.remove(key);
+        }
+    }
+
+    fn remove(&self, state: &mut CacheState, key: &CacheKey) {
+        if let Some(entry) = state.entries.remove(key) {
+            state.bytes = state.bytes.saturating_sub(entry.bytes);
+        }
+    }
+
+    pub fn forget(&self, source_id: &str, names: &[String]) {
+        self.forget_where(|source, name| source == source_id && names.iter().any(|n| n == name));
+    }
+
+    pub fn forget_where(&self, matches: impl Fn(&str, &str) -> bool) {
+        let mut state = self.lock();
+        let doomed = state
+            .entries
+            .keys()
+            .filter(|key| matches(&key.source_id, &key.name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in doomed {
+            self.remove(&mut state, &key);
+        }
+    }
+
+    pub fn render_prometheus(&self) -> String {
+        let (entries, bytes) = {
+            let state = self.lock();
+            (state.entries.len(), state.bytes)
+        };
+        let mut out = String::new();
+        let counters = [
+            (
+                "constitute_nvr_segment_cache_hits_total",
+                "Segment reads answered from the decrypted segment cache.",
+                &self.counters.hits,
+            ),
+            (
+                "constitute_nvr_segment_cache_misses_total",
+                "Segment reads that had to decrypt the segment.",
+                &self.counters.misses,
+            ),
+            (
+                "constitute_nvr_segment_cache_evictions_total",
+                "Decrypted segments dropped to stay within the cache limits.",
+                &self.counters.evictions,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
+        let name = "constitute_nvr_segment_cache_bytes";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Plaintext bytes held by the segment cache."
+        );
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {bytes}");
+        let name = "constitute_nvr_segment_cache_entries";
+        let _ = writeln!(out, "# HELP {name} Segments held by the segment cache.");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {entries}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn cache(max_entries: usize, max_bytes: u64) -> SegmentCache {
+        SegmentCache::new(SegmentCacheSettings {
+            max_entries,
+            max_bytes,
+        })
+    }
+
+    async fn read(cache: &SegmentCache, name: &str, len: usize, loads: &AtomicUsize) -> Plaintext {
+        cache
+            .get_or_load("cam-a", name, 2, || async {
+                loads.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, anyhow::Error>(Zeroizing::new(vec![7; len]))
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn repeated_reads_decrypt_once_and_evict_least_recently_used() {
+        let cache = cache(2, 1_000);
+        let loads = AtomicUsize::new(0);
+        read(&cache, "a.cnv", 100, &loads).await;
+        read(&cache, "b.cnv", 100, &loads).await;
+        read(&cache, "a.cnv", 100, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+        // `b` is the least recently used, so `c` pushes it out.
+        read(&cache, "c.cnv", 100, &loads).await;
+        read(&cache, "a.cnv", 100, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+        read(&cache, "b.cnv", 100, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+
+        let metrics = cache.render_prometheus();
+        assert!(metrics.contains("constitute_nvr_segment_cache_hits_total 2\n"));
+        assert!(metrics.contains("constitute_nvr_segment_cache_misses_total 4\n"));
+        assert!(metrics.contains("constitute_nvr_segment_cache_evictions_total 2\n"));
+        assert!(metrics.contains("constitute_nvr_segment_cache_bytes 200\n"));
+    }
+
+    #[tokio::test]
+    async fn byte_limit_evicts_and_oversized_segments_are_not_kept() {
+        let cache = cache(10, 250);
+        let loads = AtomicUsize::new(0);
+        read(&cache, "a.cnv", 100, &loads).await;
+        read(&cache, "b.cnv", 100, &loads).await;
+        read(&cache, "c.cnv", 100, &loads).await;
+        read(&cache, "c.cnv", 100, &loads).await;
+        read(&cache, "a.cnv", 100, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+
+        let big = read(&cache, "big.cnv", 300, &loads).await;
+        assert_eq!(big.len(), 300);
+        read(&cache, "big.cnv", 300, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 6);
+        assert!(cache.lock().bytes <= 250);
+    }
+
+    #[tokio::test]
+    async fn concurrent_readers_share_one_decryption() {
+        let cache = cache(4, 1_000);
+        let loads = Arc::new(AtomicUsize::new(0));
+        let (release, wait) = tokio::sync::watch::channel(false);
+        let readers = (0..8)
+            .map(|_| {
+                let (cache, loads, mut wait) = (cache.clone(), Arc::clone(&loads), wait.clone());
+                tokio::spawn(async move {
+                    cache
+                        .get_or_load("cam-a", "a.cnv", 2, || async move {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            let _ = wait.wait_for(|released| *released).await;
+                            Ok::<_, anyhow::Error>(Zeroizing::new(vec![1; 10]))
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        tokio::task::yield_now().await;
+        release.send(true).unwrap();
+        for reader in readers {
+            assert_eq!(reader.await.unwrap().len(), 10);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_are_retried_and_invalidation_forces_a_fresh_read() {
+        let cache = cache(4, 1_000);
+        let failed = cache
+            .get_or_load("cam-a", "a.cnv", 2, || async {
+                Err(anyhow::anyhow!("gone"))
+            })
+            .await;
+        assert!(failed.is_err());
+        assert!(cache.lock().entries.is_empty());
+
+        let loads = AtomicUsize::new(0);
+        read(&cache, "a.cnv", 10, &loads).await;
+        read(&cache, "b.cnv", 10, &loads).await;
+        cache.forget("cam-a", &["a.cnv".to_string()]);
+        read(&cache, "a.cnv", 10, &loads).await;
+        read(&cache, "b.cnv", 10, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+
+        cache.forget_where(|source, _| source == "cam-a");
+        assert_eq!(cache.lock().bytes, 0);
+        // A different format version is a different entry.
+        let loads_before = loads.load(Ordering::SeqCst);
+        read(&cache, "a.cnv", 10, &loads).await;
+        cache
+            .get_or_load("cam-a", "a.cnv", 1, || async {
+                loads.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, anyhow::Error>(Zeroizing::new(vec![0; 10]))
+            })
+            .await
+            .unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), loads_before + 2);
+    }
+
+    #[tokio::test]
+    async fn disabled_cache_always_loads() {
+        let cache = cache(0, 1_000);
+        let loads = AtomicUsize::new(0);
+        read(&cache, "a.cnv", 10, &loads).await;
+        read(&cache, "a.cnv", 10, &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,720 @@
+use super::day_index::{self, DayIndex, SegmentTime};
+use super::name_map::{self, NameMap};
+use super::source_stats::SourceStats;
+use super::{JobProgress, JobStatus, SegmentEntry, StorageManager, layout};
+use anyhow::{Context, Result, anyhow};
+use rusqlite::types::Value;
+use rusqlite::{Connection, Transaction, params, params_from_iter};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::os::unix::fs::DirEntryExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+pub(super) const INDEX_DB_FILE: &str = "segments.db";
+const REINDEX_JOB: &str = "reindex_storage";
+const SCHEMA_VERSION: i64 = 1;
+const SETTLE_NS: i64 = 2_000_000_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS dirs (
+    source TEXT NOT NULL,
+    dir TEXT NOT NULL,
+    mtime_ns INTEGER,
+    PRIMARY KEY (source, dir)
+);
+CREATE TABLE IF NOT EXISTS segments (
+    source TEXT NOT NULL,
+    dir TEXT NOT NULL,
+    file TEXT NOT NULL,
+    inode INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    opaque INTEGER NOT NULL,
+    encrypted INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    modified_unix INTEGER NOT NULL,
+    start_unix INTEGER NOT NULL,
+    end_unix INTEGER NOT NULL,
+    plaintext_sha256 TEXT,
+    time TEXT,
+    PRIMARY KEY (source, dir, file)
+);
+CREATE INDEX IF NOT EXISTS segments_by_start ON segments (source, start_unix, name);
+CREATE INDEX IF NOT EXISTS segments_opaque ON segments (source, opaque, name);
+";
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct IndexQuery {
+    pub from_unix: Option<u64>,
+    pub to_unix: Option<u64>,
+    pub after: Option<(u64, String)>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReindexReport {
+    pub sources: usize,
+    pub segments: usize,
+}
+
+pub(super) struct SegmentDb {
+    segments_root: PathBuf,
+    path: PathBuf,
+    conn: Mutex<Option<Connection>>,
+}
+
+struct Row {
+    file: String,
+    inode: u64,
+    name: String,
+    opaque: bool,
+    bytes: u64,
+    modified_unix: u64,
+    time: Option<SegmentTime>,
+}
+
+struct Known {
+    inode: u64,
+    bytes: u64,
+    modified_unix: u64,
+}
+
+impl SegmentDb {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            segments_root: root.join("segments"),
+            path: root.join(INDEX_DB_FILE),
+            conn: Mutex::new(None),
+        }
+    }
+
+    fn open(&self) -> Result<Connection> {
+        let conn = Connection::open(&self.path)
+            .with_context(|| format!("open {}", self.path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            conn.execute_batch("DROP TABLE IF EXISTS dirs; DROP TABLE IF EXISTS segments;")?;
+        }
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(conn)
+    }
+
+    fn remove_files(&self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn with<T>(&self, work: impl FnOnce(&mut Connection) -> Result<T>) -> Result<T> {
+        let mut conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if conn.is_none() {
+            // Without it the volume is not mounted; leave the mountpoint alone.
+            if !self.segments_root.is_dir() {
+                return Err(anyhow!("{} is missing", self.segments_root.display()));
+            }
+            let opened = self.open().or_else(|err| {
+                warn!(
+                    path = %self.path.display(),
+                    error = %format!("{err:#}"),
+                    "segment index unreadable; rebuilding it"
+                );
+                self.remove_files();
+                self.open()
+            })?;
+            *conn = Some(opened);
+        }
+        let result = work(conn.as_mut().expect("opened above"));
+        if result.is_err() {
+            *conn = None;
+        }
+        result
+    }
+
+    fn reset(&self) {
+        let mut conn = self
+            .conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *conn = None;
+        self.remove_files();
+    }
+}
+
+fn sql_int(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| {
+            i64::try_from(since.as_nanos()).unwrap_or(i64::MAX)
+        })
+}
+
+fn dir_mtime_ns(dir: &Path) -> Result<Option<i64>> {
+    match std::fs::metadata(dir) {
+        Ok(metadata) => Ok(Some(
+            metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| {
+                    i64::try_from(since.as_nanos()).unwrap_or(i64::MAX)
+                }),
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("stat {}", dir.display())),
+    }
+}
+
+fn file_facts(
+    entry: &std::fs::DirEntry,
+    file: &str,
+    known: &HashMap<String, Known>,
+) -> Result<Option<(u64, u64)>> {
+    if !file.ends_with(".mp4")
+        && let Some(known) = known.get(file).filter(|known| known.inode == entry.ino())
+    {
+        return Ok(Some((known.bytes, known.modified_unix)));
+    }
+    match entry.metadata() {
+        Ok(metadata) => Ok(Some((
+            metadata.len(),
+            metadata.modified().map_or(0, crate::util::clock::unix_secs),
+        ))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("stat {}", entry.path().display())),
+    }
+}
+
+fn scan_day(day_dir: &Path, day: &str, known: &HashMap<String, Known>) -> Result<(Vec<Row>, bool)> {
+    let index = day_index::load(day_dir);
+    let mut rows = Vec::new();
+    let mut settled = true;
+    for entry in
+        std::fs::read_dir(day_dir).with_context(|| format!("read_dir {}", day_dir.display()))?
+    {
+        let entry = entry?;
+        let file = entry.file_name().to_string_lossy().to_string();
+        if !layout::is_segment_file(&file) || !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Some((bytes, modified_unix)) = file_facts(&entry, &file, known)? else {
+            continue;
+        };
+        settled &= !file.ends_with(".mp4");
+        let name = format!("{day}T{file}");
+        rows.push(Row {
+            time: index.entries.get(&name).cloned(),
+            inode: entry.ino(),
+            file,
+            name,
+            opaque: false,
+            bytes,
+            modified_unix,
+        });
+    }
+    Ok((rows, settled))
+}
+
+fn scan_source(
+    source_dir: &Path,
+    map: Option<&NameMap>,
+    known: &HashMap<String, Known>,
+) -> Result<(Vec<Row>, Vec<String>, bool)> {
+    let mut rows = Vec::new();
+    let mut days = Vec::new();
+    let mut settled = true;
+    let mut indexes = HashMap::<String, DayIndex>::new();
+    for entry in std::fs::read_dir(source_dir)
+        .with_context(|| format!("read_dir {}", source_dir.display()))?
+    {
+        let entry = entry?;
+        let file = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() && layout::is_day_dir(&file) {
+            days.push(file);
+            continue;
+        }
+        if !file_type.is_file() || !layout::is_segment_file(&file) {
+            continue;
+        }
+        let Some((bytes, modified_unix)) = file_facts(&entry, &file, known)? else {
+            continue;
+        };
+        let mapped = map.and_then(|map| file.strip_suffix(".cnv").and_then(|stem| map.get(stem)));
+        let row = match mapped {
+            Some(mapped) => Row {
+                inode: entry.ino(),
+                file,
+                name: mapped.name.clone(),
+                opaque: true,
+                bytes,
+                modified_unix: mapped.modified_unix,
+                time: mapped.time.clone(),
+            },
+            None => {
+                let time = layout::split_name(&file).and_then(|(day, _)| {
+                    settled = false;
+                    indexes
+                        .entry(day.to_string())
+                        .or_insert_with(|| day_index::load(&source_dir.join(day)))
+                        .entries
+                        .get(&file)
+                        .cloned()
+                });
+                settled &= !file.ends_with(".mp4");
+                Row {
+                    inode: entry.ino(),
+                    name: file.clone(),
+                    file,
+                    opaque: false,
+                    bytes,
+                    modified_unix,
+                    time,
+                }
+            }
+        };
+        rows.push(row);
+    }
+    Ok((rows, days, settled))
+}
+
+fn cached_dirs(tx: &Transaction, source: &str) -> Result<HashMap<String, Option<i64>>> {
+    let mut stmt = tx.prepare_cached("SELECT dir, mtime_ns FROM dirs WHERE source = ?1")?;
+    let dirs = stmt
+        .query_map(params![source], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(dirs)
+}
+
+fn known_files(tx: &Transaction, source: &str, dir: &str) -> Result<HashMap<String, Known>> {
+    let mut stmt = tx.prepare_cached(
+        "SELECT file, inode, bytes, modified_unix FROM segments WHERE source = ?1 AND dir = ?2",
+    )?;
+    let known = stmt
+        .query_map(params![source, dir], |row| {
+            Ok((
+                row.get(0)?,
+                Known {
+                    inode: row.get::<_, i64>(1)? as u64,
+                    bytes: row.get::<_, i64>(2)? as u64,
+                    modified_unix: row.get::<_, i64>(3)? as u64,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(known)
+}
+
+fn forget_dir(tx: &Transaction, source: &str, dir: &str) -> Result<()> {
+    tx.execute(
+        "DELETE FROM segments WHERE source = ?1 AND dir = ?2",
+        params![source, dir],
+    )?;
+    tx.execute(
+        "DELETE FROM dirs WHERE source = ?1 AND dir = ?2",
+        params![source, dir],
+    )?;
+    Ok(())
+}
+
+fn store_dir(
+    tx: &Transaction,
+    source: &str,
+    dir: &str,
+    rows: &[Row],
+    mtime_ns: Option<i64>,
+) -> Result<()> {
+    tx.execute(
+        "DELETE FROM segments WHERE source = ?1 AND dir = ?2",
+        params![source, dir],
+    )?;
+    let mut insert = tx.prepare_cached(
+        "INSERT INTO segments (source, dir, file, inode, name, opaque, encrypted, bytes,
+             modified_unix, start_unix, end_unix, plaintext_sha256, time)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+    )?;
+    for row in rows {
+        let time = row.time.as_ref();
+        insert.execute(params![
+            source,
+            dir,
+            row.file,
+            row.inode as i64,
+            row.name,
+            row.opaque,
+            row.file.ends_with(".cnv"),
+            sql_int(row.bytes),
+            sql_int(row.modified_unix),
+            sql_int(time.map_or(row.modified_unix, |time| time.start_unix)),
+            sql_int(time.map_or(row.modified_unix, |time| time.end_unix)),
+            time.and_then(|time| time.plaintext_sha256.as_deref()),
+            time.map(serde_json::to_string).transpose()?,
+        ])?;
+    }
+    tx.execute(
+        "INSERT INTO dirs (source, dir, mtime_ns) VALUES (?1, ?2, ?3)
+         ON CONFLICT (source, dir) DO UPDATE SET mtime_ns = excluded.mtime_ns",
+        params![source, dir, mtime_ns],
+    )?;
+    Ok(())
+}
+
+fn refresh_source(
+    conn: &mut Connection,
+    source: &str,
+    source_dir: &Path,
+    load_map: &dyn Fn() -> Result<Option<NameMap>>,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    let settle_before = now_ns() - SETTLE_NS;
+    let trusted = |mtime: i64, settled: bool| (settled && mtime < settle_before).then_some(mtime);
+    let cached = cached_dirs(&tx, source)?;
+    let Some(source_mtime) = dir_mtime_ns(source_dir)? else {
+        tx.execute("DELETE FROM segments WHERE source = ?1", params![source])?;
+        tx.execute("DELETE FROM dirs WHERE source = ?1", params![source])?;
+        return Ok(tx.commit()?);
+    };
+    let days = if cached.get("") == Some(&Some(source_mtime)) {
+        cached
+            .keys()
+            .filter(|dir| !dir.is_empty())
+            .cloned()
+            .collect()
+    } else {
+        let map = load_map()?;
+        let known = known_files(&tx, source, "")?;
+        let (rows, days, settled) = scan_source(source_dir, map.as_ref(), &known)?;
+        store_dir(&tx, source, "", &rows, trusted(source_mtime, settled))?;
+        for gone in cached
+            .keys()
+            .filter(|dir| !dir.is_empty() && !days.contains(dir))
+        {
+            forget_dir(&tx, source, gone)?;
+        }
+        days
+    };
+    for day in days {
+        let day_dir = source_dir.join(&day);
+        let Some(mtime) = dir_mtime_ns(&day_dir)? else {
+            forget_dir(&tx, source, &day)?;
+            continue;
+        };
+        if cached.get(&day) == Some(&Some(mtime)) {
+            continue;
+        }
+        let known = known_files(&tx, source, &day)?;
+        let (rows, settled) = scan_day(&day_dir, &day, &known)?;
+        store_dir(&tx, source, &day, &rows, trusted(mtime, settled))?;
+    }
+    Ok(tx.commit()?)
+}
+
+fn query_rows(
+    conn: &Connection,
+    source: &str,
+    query: &IndexQuery,
+) -> Result<Vec<(SegmentEntry, Option<SegmentTime>)>> {
+    // A timestamp-named original that an interrupted migration left beside its opaque
+    // copy is not listed.
+    let mut sql = String::from(
+        "SELECT name, bytes, modified_unix, start_unix, end_unix, time FROM segments
+         WHERE source = ?1 AND (opaque = 1
+             OR name NOT IN (SELECT name FROM segments WHERE source = ?1 AND opaque = 1))",
+    );
+    let mut args = vec![Value::Text(source.to_string())];
+    if let Some(from_unix) = query.from_unix {
+        args.push(Value::Integer(sql_int(from_unix)));
+        sql.push_str(&format!(" AND end_unix >= ?{}", args.len()));
+    }
+    if let Some(to_unix) = query.to_unix {
+        args.push(Value::Integer(sql_int(to_unix)));
+        sql.push_str(&format!(" AND start_unix <= ?{}", args.len()));
+    }
+    if let Some((start, name)) = &query.after {
+        args.push(Value::Integer(sql_int(*start)));
+        args.push(Value::Text(name.clone()));
+        let (start, name) = (args.len() - 1, args.len());
+        sql.push_str(&format!(" AND (start_unix, name) < (?{start}, ?{name})"));
+    }
+    sql.push_str(" ORDER BY start_unix DESC, name DESC");
+    if let Some(limit) = query.limit {
+        args.push(Value::Integer(sql_int(limit as u64)));
+        sql.push_str(&format!(" LIMIT ?{}", args.len()));
+    }
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let rows = stmt
+        .query_map(params_from_iter(args), |row| {
+            let time = row
+                .get::<_, Option<String>>(5)?
+                .and_then(|time| serde_json::from_str::<SegmentTime>(&time).ok());
+            let entry = SegmentEntry {
+                name: row.get(0)?,
+                bytes: row.get::<_, i64>(1)? as u64,
+                modified_unix: row.get::<_, i64>(2)? as u64,
+                start_unix: row.get::<_, i64>(3)? as u64,
+                end_unix: row.get::<_, i64>(4)? as u64,
+                plaintext_bytes: time.as_ref().and_then(|time| time.plaintext_bytes),
+                duration_ms: time.as_ref().and_then(|time| time.duration_ms),
+                plaintext_sha256: time.as_ref().and_then(|time| time.plaintext_sha256.clone()),
+                protected: false,
+            };
+            Ok((entry, time))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+fn query_totals(conn: &Connection) -> Result<Vec<SourceStats>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT source, COUNT(*), SUM(encrypted), SUM(bytes), MIN(modified_unix),
+             MAX(modified_unix)
+         FROM segments GROUP BY source ORDER BY source",
+    )?;
+    let stats = stmt
+        .query_map([], |row| {
+            let segments = row.get::<_, i64>(1)? as usize;
+            let encrypted_segments = row.get::<_, i64>(2)? as usize;
+            Ok(SourceStats {
+                source_id: row.get(0)?,
+                segments,
+                encrypted_segments,
+                plaintext_segments: segments - encrypted_segments,
+                total_bytes: row.get::<_, i64>(3)? as u64,
+                oldest_unix: row.get::<_, Option<i64>>(4)?.map(|unix| unix as u64),
+                newest_unix: row.get::<_, Option<i64>>(5)?.map(|unix| unix as u64),
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(stats)
+}
+
+impl StorageManager {
+    fn index_refresher(
+        &self,
+        source_id: &str,
+    ) -> (
+        String,
+        PathBuf,
+        impl Fn() -> Result<Option<NameMap>> + Send + 'static,
+    ) {
+        let source = crate::util::source_dir_name(source_id);
+        let dir = self.segments_dir(source_id);
+        let key = self.key.clone();
+        let lock = Arc::clone(&self.name_map_lock);
+        let opaque_names = self.opaque_names;
+        let map_dir = dir.clone();
+        let load_map = move || {
+            if !opaque_names && !name_map::has_map(&map_dir) {
+                return Ok(None);
+            }
+            let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            name_map::load(&map_dir, &key).map(Some)
+        };
+        (source, dir, load_map)
+    }
+
+    pub(super) async fn query_index(
+        &self,
+        source_id: &str,
+        query: IndexQuery,
+    ) -> Option<Vec<(SegmentEntry, Option<SegmentTime>)>> {
+        let db = Arc::clone(&self.segment_db);
+        let (source, dir, load_map) = self.index_refresher(source_id);
+        let result = tokio::task::spawn_blocking(move || {
+            db.with(|conn| {
+                refresh_source(conn, &source, &dir, &load_map)?;
+                query_rows(conn, &source, &query)
+            })
+        })
+        .await
+        .context("join segment index query")
+        .and_then(|result| result);
+        result
+            .inspect_err(|err| {
+                warn!(
+                    source_id,
+                    error = %format!("{err:#}"),
+                    "segment index unavailable; walking the source instead"
+                );
+            })
+            .ok()
+    }
+
+    async fn refresh_index(&self, progress: Option<&JobProgress>) -> Result<ReindexReport> {
+        let sources = self.list_sources().await?;
+        let total = sources.len() as u64;
+        for (idx, source_id) in sources.iter().enumerate() {
+            if progress.is_some_and(JobProgress::is_cancelled) {
+                return Err(anyhow!("reindex cancelled"));
+            }
+            let db = Arc::clone(&self.segment_db);
+            let (source, dir, load_map) = self.index_refresher(source_id);
+            tokio::task::spawn_blocking(move || {
+                db.with(|conn| refresh_source(conn, &source, &dir, &load_map))
+            })
+            .await
+            .context("join segment index refresh")??;
+            if let Some(progress) = progress {
+                progress.progress(idx as u64 + 1, total);
+            }
+        }
+        let db = Arc::clone(&self.segment_db);
+        let segments = tokio::task::spawn_blocking(move || {
+            db.with(|conn| {
+                let count: i64 =
+                    conn.query_row("SELECT COUNT(*) FROM segments", [], |row| row.get(0))?;
+                Ok(count as usize)
+            })
+        })
+        .await
+        .context("join segment index count")??;
+        Ok(ReindexReport {
+            sources: sources.len(),
+            segments,
+        })
+    }
+
+    pub(super) async fn refresh_index_after_pass(&self) {
+        match self.refresh_index(None).await {
+            Ok(report) => debug!(segments = report.segments, "segment index refreshed"),
+            Err(err) => warn!(error = %format!("{err:#}"), "segment index refresh failed"),
+        }
+    }
+
+    pub(super) async fn index_source_stats(&self) -> Option<Vec<SourceStats>> {
+        let result = async {
+            self.refresh_index(None).await?;
+            let db = Arc::clone(&self.segment_db);
+            tokio::task::spawn_blocking(move || db.with(|conn| query_totals(conn)))
+                .await
+                .context("join segment index totals")?
+        }
+        .await;
+        result
+            .inspect_err(|err| {
+                warn!(error = %format!("{err:#}"), "segment index unavailable for source stats");
+            })
+            .ok()
+    }
+
+    pub fn start_reindex_storage(&self) -> Result<JobStatus> {
+        let job = self.jobs.begin(REINDEX_JOB)?;
+        let this = self.clone();
+        Ok(job.spawn(move |progress| async move {
+            progress.phase("indexing");
+            let db = Arc::clone(&this.segment_db);
+            tokio::task::spawn_blocking(move || db.reset())
+                .await
+                .context("join segment index reset")?;
+            this.refresh_index(Some(&progress)).await
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::jobs::JobState;
+
+    fn set_mtime(path: &Path, unix: u64) {
+        std::fs::File::open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(unix))
+            .unwrap();
+    }
+
+    fn names(entries: &[(SegmentEntry, Option<SegmentTime>)]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|(entry, _)| entry.name.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn listings_come_from_the_index_and_follow_changed_directories() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-segment-db-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let source_dir = root.join("segments").join("cam-a");
+        let day = source_dir.join("20240101");
+        std::fs::create_dir_all(&day).unwrap();
+        for (file, unix) in [("000000.cnv", 1_704_067_260), ("000100.cnv", 1_704_067_320)] {
+            std::fs::write(day.join(file), b"sealed").unwrap();
+            set_mtime(&day.join(file), unix);
+        }
+        set_mtime(&day, 1_704_100_000);
+        set_mtime(&source_dir, 1_704_100_000);
+        // A damaged index is rebuilt rather than trusted.
+        std::fs::write(root.join(INDEX_DB_FILE), b"not a database").unwrap();
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+
+        let all = storage
+            .query_index("cam-a", IndexQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&all), ["20240101T000100.cnv", "20240101T000000.cnv"]);
+        let mut walked = names(&storage.walk_segments("cam-a").await.unwrap());
+        walked.sort_unstable_by(|left, right| right.cmp(left));
+        assert_eq!(names(&all), walked);
+
+        // A settled directory is not read again while its mtime stands.
+        std::fs::remove_file(day.join("000000.cnv")).unwrap();
+        set_mtime(&day, 1_704_100_000);
+        let cached = storage
+            .query_index("cam-a", IndexQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(cached.len(), 2);
+
+        // Once it changes, it is; a new day directory is found through its source directory.
+        set_mtime(&day, 1_704_100_060);
+        let next = source_dir.join("20240102");
+        std::fs::create_dir_all(&next).unwrap();
+        std::fs::write(next.join("000000.cnv"), b"sealed").unwrap();
+        set_mtime(&next.join("000000.cnv"), 1_704_153_660);
+        let query = IndexQuery {
+            from_unix: Some(1_704_067_300),
+            limit: Some(1),
+            ..IndexQuery::default()
+        };
+        let newest = storage.query_index("cam-a", query).await.unwrap();
+        assert_eq!(names(&newest), ["20240102T000000.cnv"]);
+        let query = IndexQuery {
+            after: Some((1_704_153_660, "20240102T000000.cnv".to_string())),
+            ..IndexQuery::default()
+        };
+        let older = storage.query_index("cam-a", query).await.unwrap();
+        assert_eq!(names(&older), ["20240101T000100.cnv"]);
+
+        let stats = storage.index_source_stats().await.unwrap();
+        assert_eq!(stats[0].segments, 2);
+        assert_eq!(stats[0].encrypted_segments, 2);
+
+        // A rebuild reads everything again.
+        let job = storage.start_reindex_storage().unwrap();
+        let status = loop {
+            let status = storage.jobs().get(&job.job_id).unwrap();
+            if status.state != JobState::Running {
+                break status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(status.report["segments"], 2);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
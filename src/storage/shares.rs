@@ -0,0 +1,429 @@
+use super::{
+    JobProgress, SegmentEntry, SegmentStream, StorageManager, chunked, decrypt_blob,
+    mp4_duration_ms,
+};
+use anyhow::{Context, Result, anyhow};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+const SHARE_FILE: &str = "share.json";
+const CLIP_FILE: &str = "clip.cnv";
+const RENDER_DIR: &str = ".render";
+const CONTINUATION_SECS: u64 = 15 * 60;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareRequest {
+    pub source_id: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub expires_in_hours: u64,
+    #[serde(default)]
+    pub max_downloads: u64,
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Share {
+    pub id: String,
+    pub source_id: String,
+    pub from_unix: u64,
+    pub to_unix: u64,
+    pub created_unix: u64,
+    pub expires_unix: u64,
+    pub max_downloads: u64,
+    pub downloads: u64,
+    pub segments: usize,
+    pub bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub timezone: String,
+}
+
+impl Share {
+    fn exhausted(&self) -> bool {
+        self.max_downloads > 0 && self.downloads >= self.max_downloads
+    }
+
+    fn expired(&self, now: u64) -> bool {
+        now >= self.expires_unix
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareFile {
+    #[serde(flatten)]
+    share: Share,
+    token_hash: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    counted: Vec<CountedOpen>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CountedOpen {
+    remote: IpAddr,
+    unix: u64,
+}
+
+pub enum ShareAccess {
+    Missing,
+    Expired(Share),
+    Exhausted(Share),
+    Ready {
+        share: Share,
+        clip: Box<SegmentStream>,
+        counted: bool,
+    },
+}
+
+impl StorageManager {
+    pub async fn create_share(
+        &self,
+        request: &ShareRequest,
+        progress: &JobProgress,
+    ) -> Result<(Share, String)> {
+        if request.from_unix > request.to_unix {
+            return Err(anyhow!("fromUnix must not be after toUnix"));
+        }
+        let mut segments = self
+            .list_segments(&request.source_id, usize::MAX)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.overlaps(request.from_unix, request.to_unix))
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            return Err(anyhow!(
+                "no segments for {} in the requested range",
+                request.source_id
+            ));
+        }
+        segments.sort_by_key(|entry| entry.start_unix);
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        let token = hex::encode(token);
+        let dir = self.shares_dir().join(&id);
+        let render = dir.join(RENDER_DIR);
+        tokio::fs::create_dir_all(&render)
+            .await
+            .with_context(|| format!("create {}", render.display()))?;
+        let rendered = self
+            .render_clip(&request.source_id, &segments, &render, progress)
+            .await;
+        let _ = tokio::fs::remove_dir_all(&render).await;
+        // Chunked, so a range request opens only the chunks it covers.
+        let sealed = rendered.and_then(|clip| {
+            let blob = chunked::seal(&self.key, None, &clip)?;
+            Ok((clip.len() as u64, mp4_duration_ms(&clip), blob))
+        });
+        let (bytes, duration_ms, blob) = match sealed {
+            Ok(sealed) => sealed,
+            Err(err) => {
+                let _ = tokio::fs::remove_dir_all(&dir).await;
+                return Err(err);
+            }
+        };
+        tokio::fs::write(dir.join(CLIP_FILE), &blob)
+            .await
+            .context("write share clip")?;
+
+        let now = crate::util::now_unix_seconds();
+        let share = Share {
+            id,
+            source_id: request.source_id.clone(),
+            from_unix: request.from_unix,
+            to_unix: request.to_unix,
+            created_unix: now,
+            expires_unix: now + request.expires_in_hours * 3600,
+            max_downloads: request.max_downloads,
+            downloads: 0,
+            segments: segments.len(),
+            bytes,
+            duration_ms,
+            timezone: request.timezone.clone(),
+        };
+        let file = ShareFile {
+            share: share.clone(),
+            token_hash: crate::util::sha256_b64url(&token),
+            counted: Vec::new(),
+        };
+        write_share_file(&dir, &file).await?;
+        Ok((share, token))
+    }
+
+    pub async fn open_share(
+        &self,
+        token: &str,
+        fresh: bool,
+        remote: IpAddr,
+    ) -> Result<ShareAccess> {
+        let _guard = self.share_lock.lock().await;
+        let token_hash = crate::util::sha256_b64url(token);
+        let Some((dir, mut file)) = self
+            .read_shares()
+            .await?
+            .into_iter()
+            .find(|(_, file)| file.token_hash == token_hash)
+        else {
+            return Ok(ShareAccess::Missing);
+        };
+        let now = crate::util::now_unix_seconds();
+        if file.share.expired(now) {
+            return Ok(ShareAccess::Expired(file.share));
+        }
+        file.counted
+            .retain(|open| now.saturating_sub(open.unix) < CONTINUATION_SECS);
+        let count = fresh || !file.counted.iter().any(|open| open.remote == remote);
+        if count && file.share.exhausted() {
+            return Ok(ShareAccess::Exhausted(file.share));
+        }
+        let clip = self
+            .open_clip(&dir.join(CLIP_FILE), &mut file.share)
+            .await?;
+        if count {
+            file.share.downloads += 1;
+            file.counted.push(CountedOpen { remote, unix: now });
+            write_share_file(&dir, &file).await?;
+        }
+        Ok(ShareAccess::Ready {
+            share: file.share,
+            clip: Box::new(clip),
+            counted: count,
+        })
+    }
+
+    pub async fn list_shares(&self) -> Result<Vec<Share>> {
+        let _guard = self.share_lock.lock().await;
+        let mut shares = self
+            .read_shares()
+            .await?
+            .into_iter()
+            .map(|(_, file)| file.share)
+            .collect::<Vec<_>>();
+        shares.sort_by_key(|share| std::cmp::Reverse(share.created_unix));
+        Ok(shares)
+    }
+
+    pub async fn revoke_share(&self, id: &str) -> Result<bool> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("invalid share id"));
+        }
+        let _guard = self.share_lock.lock().await;
+        let dir = self.shares_dir().join(id);
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err).with_context(|| format!("remove share {}", dir.display())),
+        }
+    }
+
+    pub async fn collect_shares(&self) -> Result<usize> {
+        let _guard = self.share_lock.lock().await;
+        let now = crate::util::now_unix_seconds();
+        let mut removed = 0;
+        for (dir, file) in self.read_shares().await? {
+            let continued = file
+                .counted
+                .iter()
+                .any(|open| now.saturating_sub(open.unix) < CONTINUATION_SECS);
+            if file.share.expired(now) || (file.share.exhausted() && !continued) {
+                tokio::fs::remove_dir_all(&dir)
+                    .await
+                    .with_context(|| format!("remove share {}", dir.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn shares_dir(&self) -> PathBuf {
+        self.root.join("shares")
+    }
+
+    async fn open_clip(&self, path: &Path, share: &mut Share) -> Result<SegmentStream> {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .context("open share clip")?;
+        let mut magic = [0u8; 5];
+        let chunked = match file.read_exact(&mut magic).await {
+            Ok(_) => chunked::is_chunked(&magic),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(err).context("read share clip"),
+        };
+        if !chunked {
+            let blob = tokio::fs::read(path).await.context("read share clip")?;
+            let clip = decrypt_blob(&self.key, &blob)?;
+            share.duration_ms = share.duration_ms.or_else(|| mp4_duration_ms(&clip));
+            return Ok(SegmentStream::buffered(Arc::new(clip)));
+        }
+        file.rewind().await.context("read share clip")?;
+        let reader = chunked::ChunkReader::open(file, path.to_path_buf(), self.key.clone()).await?;
+        Ok(SegmentStream::chunked(reader))
+    }
+
+    async fn render_clip(
+        &self,
+        source_id: &str,
+        segments: &[SegmentEntry],
+        render: &Path,
+        progress: &JobProgress,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let total = segments.len() as u64;
+        progress.phase("decrypting");
+        let mut list = String::new();
+        for (idx, entry) in segments.iter().enumerate() {
+            if progress.is_cancelled() {
+                return Err(anyhow!("share render cancelled"));
+            }
+            progress.progress(idx as u64, total);
+            let plain = self.read_segment(source_id, &entry.name).await?;
+            let path = render.join(format!("{idx:06}.mp4"));
+            tokio::fs::write(&path, &*plain)
+                .await
+                .with_context(|| format!("write {}", path.display()))?;
+            list.push_str(&format!("file '{}'\n", path.display()));
+        }
+        let list_path = render.join("segments.txt");
+        tokio::fs::write(&list_path, list).await?;
+        let output = render.join("clip.mp4");
+        progress.phase("rendering");
+        progress.progress(total, total);
+        crate::media::clip::concat_segments(&list_path, &output).await?;
+        let clip = tokio::fs::read(&output)
+            .await
+            .context("read rendered clip")?;
+        Ok(Zeroizing::new(clip))
+    }
+
+    async fn read_shares(&self) -> Result<Vec<(PathBuf, ShareFile)>> {
+        let root = self.shares_dir();
+        let mut out = Vec::new();
+        let mut rd = match tokio::fs::read_dir(&root).await {
+            Ok(rd) => rd,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+            Err(err) => return Err(err).with_context(|| format!("read_dir {}", root.display())),
+        };
+        while let Some(entry) = rd.next_entry().await? {
+            let dir = entry.path();
+            let Ok(raw) = tokio::fs::read(dir.join(SHARE_FILE)).await else {
+                continue;
+            };
+            match serde_json::from_slice::<ShareFile>(&raw) {
+                Ok(file) => out.push((dir, file)),
+                Err(err) => warn!(dir = %dir.display(), error = %err, "unreadable share.json"),
+            }
+        }
+        Ok(out)
+    }
+}
+
+async fn write_share_file(dir: &Path, file: &ShareFile) -> Result<()> {
+    let tmp = dir.join(format!("{SHARE_FILE}.tmp"));
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(file)?)
+        .await
+        .with_context(|| format!("write {}", tmp.display()))?;
+    tokio::fs::rename(&tmp, dir.join(SHARE_FILE))
+        .await
+        .context("replace share.json")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::seal_blob;
+
+    #[tokio::test]
+    async fn tokens_resolve_until_downloads_run_out_and_shares_are_collected() {
+        let root =
+            std::env::temp_dir().join(format!("constitute-nvr-shares-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        let dir = storage.shares_dir().join("abc123");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(CLIP_FILE),
+            seal_blob(&storage.key, b"clip").unwrap(),
+        )
+        .unwrap();
+        let now = crate::util::now_unix_seconds();
+        let share = Share {
+            id: "abc123".to_string(),
+            source_id: "cam-a".to_string(),
+            from_unix: now - 60,
+            to_unix: now,
+            created_unix: now,
+            expires_unix: now + 3600,
+            max_downloads: 1,
+            downloads: 0,
+            segments: 1,
+            bytes: 4,
+            duration_ms: None,
+            timezone: String::new(),
+        };
+        let file = ShareFile {
+            share,
+            token_hash: crate::util::sha256_b64url("token"),
+            counted: Vec::new(),
+        };
+        write_share_file(&dir, &file).await.unwrap();
+        let guest: IpAddr = "192.0.2.7".parse().unwrap();
+        let other: IpAddr = "192.0.2.8".parse().unwrap();
+
+        assert!(matches!(
+            storage.open_share("other", true, guest).await.unwrap(),
+            ShareAccess::Missing
+        ));
+        let ShareAccess::Ready {
+            mut clip,
+            share,
+            counted,
+        } = storage.open_share("token", true, guest).await.unwrap()
+        else {
+            panic!("share should open");
+        };
+        // Clips rendered before chunking still open.
+        assert_eq!(clip.read_range(0, 4).await.unwrap().as_slice(), b"clip");
+        assert_eq!(share.downloads, 1);
+        assert!(counted);
+        // Continuations of that download are not counted and still resolve.
+        assert!(matches!(
+            storage.open_share("token", false, guest).await.unwrap(),
+            ShareAccess::Ready { counted: false, .. }
+        ));
+        // A new download, or a range from an address that did not start one, is refused.
+        assert!(matches!(
+            storage.open_share("token", true, guest).await.unwrap(),
+            ShareAccess::Exhausted(_)
+        ));
+        assert!(matches!(
+            storage.open_share("token", false, other).await.unwrap(),
+            ShareAccess::Exhausted(_)
+        ));
+        assert_eq!(storage.list_shares().await.unwrap().len(), 1);
+
+        // A used-up share stays while its last download can be continued.
+        assert_eq!(storage.collect_shares().await.unwrap(), 0);
+        let mut file = storage.read_shares().await.unwrap().remove(0).1;
+        file.counted[0].unix -= CONTINUATION_SECS;
+        write_share_file(&dir, &file).await.unwrap();
+        assert_eq!(storage.collect_shares().await.unwrap(), 1);
+        assert!(storage.list_shares().await.unwrap().is_empty());
+        assert!(!storage.revoke_share("abc123").await.unwrap());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,463 @@
+use super::pre_delete::{DoomedFile, HookOutcome, PreDeleteHook};
+use super::{StorageManager, decrypt_blob, name_map, seal_blob};
+use anyhow::{Context, Result, anyhow};
+use constitute_protocol::{LogCategory, LogOutcome, LogSeverity, LogSubjectRef};
+use serde::Serialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tokio::time::{Duration, interval};
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
+
+const SNAPSHOT_RETENTION_INTERVAL_SECS: u64 = 300;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SnapshotRetention {
+    pub retention_days: u64,
+    pub max_bytes: u64,
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            retention_days: 30,
+            max_bytes: 2 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotEntry {
+    pub source_id: String,
+    pub name: String,
+    pub bytes: u64,
+    pub taken_unix: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotRetentionSummary {
+    pub removed: usize,
+    pub bytes: u64,
+    pub blocked: usize,
+    pub held: usize,
+    pub over_quota_bytes: u64,
+    pub hook: Option<HookOutcome>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionStatus {
+    pub pre_delete_hook: bool,
+    pub blocked: usize,
+    pub blocked_segments: usize,
+    pub held: usize,
+    pub over_quota_bytes: u64,
+    pub last_hook: Option<HookOutcome>,
+    pub segment_pass_unix: Option<u64>,
+}
+
+impl StorageManager {
+    pub fn with_snapshot_retention(mut self, retention: SnapshotRetention) -> Self {
+        self.snapshot_retention = retention;
+        self
+    }
+
+    pub fn with_pre_delete_hook(mut self, hook: Option<PreDeleteHook>) -> Self {
+        self.pre_delete_hook = hook;
+        self
+    }
+
+    pub fn retention_status(&self) -> RetentionStatus {
+        let mut status = self
+            .retention_status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        status.pre_delete_hook = self.pre_delete_hook.is_some();
+        status
+    }
+
+    pub fn start_snapshot_retention(&self) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut tick = interval(Duration::from_secs(SNAPSHOT_RETENTION_INTERVAL_SECS));
+            loop {
+                tick.tick().await;
+                if this.cancel.is_cancelled() {
+                    break;
+                }
+                match this.enforce_snapshot_retention().await {
+                    Ok(summary) => {
+                        if summary.removed > 0 || summary.blocked > 0 {
+                            debug!(
+                                removed = summary.removed,
+                                bytes = summary.bytes,
+                                blocked = summary.blocked,
+                                "snapshot retention pass"
+                            );
+                        }
+                        if let Some(outcome) = &summary.hook {
+                            record_hook_outcome(outcome, "snapshots").await;
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "snapshot retention pass failed"),
+                }
+                match this.enforce_segment_retention().await {
+                    Ok(summary) => {
+                        if summary.removed > 0 || summary.blocked > 0 {
+                            debug!(
+                                removed = summary.removed,
+                                bytes = summary.bytes,
+                                blocked = summary.blocked,
+                                "segment retention pass"
+                            );
+                        }
+                        if let Some(outcome) = &summary.hook {
+                            record_hook_outcome(outcome, "segments").await;
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "segment retention pass failed"),
+                }
+                match this.collect_shares().await {
+                    Ok(0) => {}
+                    Ok(removed) => debug!(removed, "expired shares removed"),
+                    Err(err) => warn!(error = %err, "share cleanup failed"),
+                }
+                match this.collect_range_spools().await {
+                    Ok(0) => {}
+                    Ok(removed) => debug!(removed, "idle range spools removed"),
+                    Err(err) => warn!(error = %err, "range spool cleanup failed"),
+                }
+                match this.collect_exports().await {
+                    Ok(0) => {}
+                    Ok(removed) => debug!(removed, "expired exports removed"),
+                    Err(err) => warn!(error = %err, "export cleanup failed"),
+                }
+                match this.collect_uploads().await {
+                    Ok(0) => {}
+                    Ok(removed) => debug!(removed, "idle attachment uploads removed"),
+                    Err(err) => warn!(error = %err, "attachment upload cleanup failed"),
+                }
+            }
+        });
+    }
+
+    pub async fn store_snapshot(
+        &self,
+        source_id: &str,
+        taken_unix: u64,
+        jpeg: &[u8],
+    ) -> Result<SnapshotEntry> {
+        let dir = self.snapshot_dir(source_id)?;
+        tokio::fs::create_dir_all(&dir).await?;
+        let name = snapshot_name(taken_unix)?;
+        let path = dir.join(&name);
+        let blob = seal_blob(&self.key, jpeg)?;
+        let tmp = dir.join(format!("{name}.tmp"));
+        tokio::fs::write(&tmp, &blob)
+            .await
+            .with_context(|| format!("write snapshot {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .with_context(|| format!("replace snapshot {}", path.display()))?;
+        Ok(SnapshotEntry {
+            source_id: source_id.to_string(),
+            name,
+            bytes: blob.len() as u64,
+            taken_unix,
+        })
+    }
+
+    pub async fn list_snapshots(
+        &self,
+        source_id: &str,
+        from_unix: Option<u64>,
+        to_unix: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<SnapshotEntry>> {
+        let dir = self.snapshot_dir(source_id)?;
+        let mut out = read_snapshot_dir(&dir, source_id).await?;
+        out.retain(|entry| {
+            from_unix.is_none_or(|from| entry.taken_unix >= from)
+                && to_unix.is_none_or(|to| entry.taken_unix <= to)
+        });
+        out.sort_by_key(|entry| std::cmp::Reverse(entry.taken_unix));
+        out.truncate(limit.max(1));
+        Ok(out)
+    }
+
+    pub async fn read_snapshot(&self, source_id: &str, name: &str) -> Result<Zeroizing<Vec<u8>>> {
+        if !is_plain_component(name) || !name.ends_with(".cnv") {
+            return Err(anyhow!("invalid snapshot name"));
+        }
+        let path = self.snapshot_dir(source_id)?.join(name);
+        let blob = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("read snapshot {}", path.display()))?;
+        Ok(decrypt_blob(&self.key, &blob)?)
+    }
+
+    pub async fn enforce_snapshot_retention(&self) -> Result<SnapshotRetentionSummary> {
+        let root = self.root.join("snapshots");
+        let mut entries = Vec::new();
+        let mut rd = match tokio::fs::read_dir(&root).await {
+            Ok(rd) => rd,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(SnapshotRetentionSummary::default());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        while let Some(entry) = rd.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                let source_id = entry.file_name().to_string_lossy().to_string();
+                entries.extend(read_snapshot_dir(&entry.path(), &source_id).await?);
+            }
+        }
+        entries.sort_by_key(|entry| entry.taken_unix);
+
+        let policy = self.snapshot_retention;
+        let now = crate::util::now_unix_seconds();
+        let cutoff =
+            (policy.retention_days > 0).then(|| now.saturating_sub(policy.retention_days * 86_400));
+        let mut total = entries.iter().map(|entry| entry.bytes).sum::<u64>();
+        let mut doomed = Vec::new();
+        let mut summary = SnapshotRetentionSummary::default();
+        for entry in entries {
+            let window = self.retention_window(&entry.source_id);
+            let expired = cutoff.is_some_and(|cutoff| entry.taken_unix < cutoff)
+                || window.expires(entry.taken_unix, now);
+            let over_quota = policy.max_bytes > 0 && total > policy.max_bytes;
+            if !expired && !over_quota {
+                continue;
+            }
+            if window.holds(entry.taken_unix, now)
+                || self.bookmarked(&entry.source_id, entry.taken_unix, entry.taken_unix)
+            {
+                summary.held += 1;
+                continue;
+            }
+            total = total.saturating_sub(entry.bytes);
+            doomed.push(DoomedFile {
+                path: root
+                    .join(&entry.source_id)
+                    .join(&entry.name)
+                    .to_string_lossy()
+                    .to_string(),
+                source_id: entry.source_id,
+                name: entry.name,
+                bytes: entry.bytes,
+            });
+        }
+
+        if policy.max_bytes > 0 {
+            summary.over_quota_bytes = total.saturating_sub(policy.max_bytes);
+        }
+        let approved = match &self.pre_delete_hook {
+            Some(hook) if !doomed.is_empty() => {
+                let (approved, outcome) = hook.approve(&doomed).await;
+                summary.hook = Some(outcome);
+                Some(approved)
+            }
+            _ => None,
+        };
+        for file in doomed {
+            if approved
+                .as_ref()
+                .is_some_and(|approved| !approved.contains(&file.path))
+            {
+                summary.blocked += 1;
+                continue;
+            }
+            let path = PathBuf::from(&file.path);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err).with_context(|| format!("remove snapshot {}", path.display()));
+                }
+            }
+            summary.removed += 1;
+            summary.bytes += file.bytes;
+        }
+
+        let mut status = self
+            .retention_status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        status.blocked = summary.blocked;
+        status.held = summary.held;
+        status.over_quota_bytes = summary.over_quota_bytes;
+        if summary.hook.is_some() {
+            status.last_hook = summary.hook.clone();
+        }
+        Ok(summary)
+    }
+
+    fn snapshot_dir(&self, source_id: &str) -> Result<PathBuf> {
+        if !is_plain_component(source_id) {
+            return Err(anyhow!("invalid sourceId"));
+        }
+        Ok(self.root.join("snapshots").join(source_id))
+    }
+}
+
+async fn record_hook_outcome(outcome: &HookOutcome, tree: &str) {
+    if outcome.result != "acknowledged" {
+        warn!(
+            result = outcome.result,
+            blocked = outcome.blocked,
+            error = ?outcome.error,
+            "pre-delete hook did not answer"
+        );
+    }
+    crate::logging_surface::submit_safe_event(
+        "storage",
+        LogCategory::ServiceAccess,
+        LogSeverity::Info,
+        LogOutcome::Observed,
+        LogSubjectRef {
+            kind: "retention".to_string(),
+            id: Some(tree.to_string()),
+            display: None,
+        },
+        &["nvr", "retention", "pre_delete_hook"],
+        json!(outcome),
+    )
+    .await;
+}
+
+async fn read_snapshot_dir(dir: &Path, source_id: &str) -> Result<Vec<SnapshotEntry>> {
+    let mut out = Vec::new();
+    let mut rd = match tokio::fs::read_dir(dir).await {
+        Ok(rd) => rd,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = rd.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".cnv") {
+            continue;
+        }
+        let Some(taken_unix) = name_map::segment_start_unix(&name) else {
+            continue;
+        };
+        out.push(SnapshotEntry {
+            source_id: source_id.to_string(),
+            bytes: entry.metadata().await?.len(),
+            name,
+            taken_unix,
+        });
+    }
+    Ok(out)
+}
+
+fn snapshot_name(taken_unix: u64) -> Result<String> {
+    let stamp = crate::util::clock::local_stamp(taken_unix)
+        .ok_or_else(|| anyhow!("snapshot timestamp out of range"))?;
+    Ok(format!("{stamp}.cnv"))
+}
+
+pub(super) fn is_plain_component(value: &str) -> bool {
+    !value.is_empty() && value != "." && value != ".." && !value.contains(['/', '\\'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn snapshots_roundtrip_and_respect_byte_quota() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-snapshot-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"33".repeat(32))
+            .unwrap()
+            .with_snapshot_retention(SnapshotRetention {
+                retention_days: 0,
+                max_bytes: 1,
+            });
+
+        let first = storage
+            .store_snapshot("cam-a", 1_700_000_000, b"jpeg-one")
+            .await
+            .unwrap();
+        storage
+            .store_snapshot("cam-a", 1_700_000_060, b"jpeg-two")
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .read_snapshot("cam-a", &first.name)
+                .await
+                .unwrap()
+                .as_slice(),
+            b"jpeg-one"
+        );
+        let recent = storage
+            .list_snapshots("cam-a", Some(1_700_000_030), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].taken_unix, 1_700_000_060);
+        assert!(storage.read_snapshot("cam-a", "../x.cnv").await.is_err());
+
+        let summary = storage.enforce_snapshot_retention().await.unwrap();
+        assert_eq!(summary.removed, 2);
+        assert!(
+            storage
+                .list_snapshots("cam-a", None, None, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_snapshots_wait_for_the_pre_delete_hook() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-pre-delete-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = StorageManager::new(root.clone(), &"33".repeat(32))
+            .unwrap()
+            .with_snapshot_retention(SnapshotRetention {
+                retention_days: 0,
+                max_bytes: 1,
+            })
+            .with_pre_delete_hook(Some(PreDeleteHook {
+                command: vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "cat >/dev/null; echo '{}'".to_string(),
+                ],
+                timeout: Duration::from_secs(10),
+                fail_open: false,
+            }));
+        storage
+            .store_snapshot("cam-a", 1_700_000_000, b"jpeg-one")
+            .await
+            .unwrap();
+
+        let summary = storage.enforce_snapshot_retention().await.unwrap();
+        assert_eq!((summary.removed, summary.blocked), (0, 1));
+        let status = storage.retention_status();
+        assert!(status.pre_delete_hook);
+        assert_eq!(status.blocked, 1);
+        assert_eq!(status.last_hook.unwrap().result, "acknowledged");
+        assert_eq!(
+            storage
+                .list_snapshots("cam-a", None, None, 10)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
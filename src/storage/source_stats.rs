@@ -0,0 +1,140 @@
+use super::{StorageManager, modified_unix, scan};
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub(super) const SOURCE_STATS_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceStats {
+    pub source_id: String,
+    pub segments: usize,
+    pub encrypted_segments: usize,
+    pub plaintext_segments: usize,
+    pub total_bytes: u64,
+    pub oldest_unix: Option<u64>,
+    pub newest_unix: Option<u64>,
+}
+
+pub(super) type SourceStatsCache = Option<(Instant, Vec<SourceStats>)>;
+
+impl StorageManager {
+    pub async fn list_source_stats(&self) -> Result<Vec<SourceStats>> {
+        let mut cache = self.source_stats.lock().await;
+        if let Some((walked, stats)) = cache.as_ref()
+            && walked.elapsed() < SOURCE_STATS_TTL
+        {
+            return Ok(stats.clone());
+        }
+        if let Some(stats) = self.index_source_stats().await {
+            *cache = Some((Instant::now(), stats.clone()));
+            return Ok(stats);
+        }
+        let root = self.root.join("segments");
+        let cancel = self.cancel.clone();
+        let stats = tokio::task::spawn_blocking(move || {
+            let files = scan::collect_files(&root, scan::STORED_SEGMENT_FILES, &cancel)
+                .ok_or_else(|| anyhow!("source stats walk cancelled"))?;
+            Ok::<_, anyhow::Error>(tally(&root, &files))
+        })
+        .await
+        .context("join source stats walk")??;
+        *cache = Some((Instant::now(), stats.clone()));
+        Ok(stats)
+    }
+}
+
+fn tally(root: &Path, files: &[std::path::PathBuf]) -> Vec<SourceStats> {
+    let mut by_source = BTreeMap::<String, SourceStats>::new();
+    for path in files {
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let mut components = relative.components();
+        let (Some(source), Some(_)) = (components.next(), components.next()) else {
+            continue;
+        };
+        let Ok(metadata) = std::fs::metadata(path) else {
+            continue;
+        };
+        let source_id = source.as_os_str().to_string_lossy().to_string();
+        let modified = modified_unix(path);
+        let stats = by_source
+            .entry(source_id.clone())
+            .or_insert_with(|| SourceStats {
+                source_id,
+                ..SourceStats::default()
+            });
+        stats.segments += 1;
+        if path.extension().is_some_and(|ext| ext == "mp4") {
+            stats.plaintext_segments += 1;
+        } else {
+            stats.encrypted_segments += 1;
+        }
+        stats.total_bytes += metadata.len();
+        stats.oldest_unix = Some(stats.oldest_unix.map_or(modified, |old| old.min(modified)));
+        stats.newest_unix = Some(stats.newest_unix.map_or(modified, |new| new.max(modified)));
+    }
+    by_source.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stats_count_both_layouts_and_are_reused_until_they_expire() {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-storage-source-stats-test-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let segments = root.join("segments");
+        let day = segments.join("cam-a").join("20240101");
+        std::fs::create_dir_all(&day).unwrap();
+        std::fs::create_dir_all(segments.join("cam-b")).unwrap();
+        let write = |path: std::path::PathBuf, contents: &[u8], modified: u64| {
+            std::fs::write(&path, contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(modified))
+                .unwrap();
+        };
+        write(day.join("000000.cnv"), b"sealed", 2_000);
+        write(day.join("000010.mp4"), b"open", 3_000);
+        write(day.join(".index.json"), b"{}", 4_000);
+        write(segments.join("cam-a").join("legacy.cnv"), b"flat", 1_000);
+
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+        let stats = storage.list_source_stats().await.unwrap();
+        assert_eq!(
+            stats,
+            [SourceStats {
+                source_id: "cam-a".to_string(),
+                segments: 3,
+                encrypted_segments: 2,
+                plaintext_segments: 1,
+                total_bytes: 14,
+                oldest_unix: Some(1_000),
+                newest_unix: Some(3_000),
+            }]
+        );
+
+        // A new segment shows up only once the cached walk has expired.
+        write(segments.join("cam-b").join("legacy.cnv"), b"late", 5_000);
+        assert_eq!(storage.list_source_stats().await.unwrap(), stats);
+        if let Some((walked, _)) = storage.source_stats.lock().await.as_mut() {
+            *walked -= SOURCE_STATS_TTL;
+        }
+        let stats = storage.list_source_stats().await.unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[1].source_id, "cam-b");
+        assert_eq!(stats[1].newest_unix, Some(5_000));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,375 @@
+use super::pre_delete::{DoomedFile, HookOutcome};
+use super::{SegmentEntry, StorageManager, resolve_segment_path};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+
+const DAY_SECS: u64 = 86_400;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionWindow {
+    pub min_days: u64,
+    pub max_days: u64,
+    pub max_bytes: u64,
+    pub active: bool,
+}
+
+impl RetentionWindow {
+    pub fn holds(&self, taken_unix: u64, now: u64) -> bool {
+        self.min_days > 0 && taken_unix >= now.saturating_sub(self.min_days * DAY_SECS)
+    }
+
+    pub fn bounded(&self) -> bool {
+        self.min_days > 0 || self.max_days > 0 || self.max_bytes > 0
+    }
+
+    pub fn expires(&self, taken_unix: u64, now: u64) -> bool {
+        self.max_days > 0 && taken_unix < now.saturating_sub(self.max_days * DAY_SECS)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentRetentionSummary {
+    pub removed: usize,
+    pub bytes: u64,
+    pub blocked: usize,
+    pub hook: Option<HookOutcome>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct SegmentPrune {
+    at_unix: u64,
+    segments: usize,
+    bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceRetentionStatus {
+    pub source_id: String,
+    #[serde(flatten)]
+    pub window: RetentionWindow,
+    pub segments: usize,
+    pub total_bytes: u64,
+    pub oldest_segment_unix: Option<u64>,
+    pub last_prune_unix: Option<u64>,
+    pub last_prune_segments: usize,
+    pub last_prune_bytes: u64,
+}
+
+impl StorageManager {
+    pub fn set_retention_windows(&self, windows: HashMap<String, RetentionWindow>) {
+        *self
+            .retention_windows
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = windows;
+    }
+
+    pub(super) fn retention_window(&self, source_id: &str) -> RetentionWindow {
+        self.retention_windows
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(source_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub async fn enforce_segment_retention(&self) -> Result<SegmentRetentionSummary> {
+        let windows = self
+            .retention_windows
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        let now = crate::util::now_unix_seconds();
+        let mut doomed = Vec::new();
+        for (source_id, window) in windows {
+            if window.max_days == 0 && window.max_bytes == 0 {
+                continue;
+            }
+            let dir = self.segments_dir(&source_id);
+            if !dir.is_dir() {
+                continue;
+            }
+            let map = self.load_name_map(&dir).await?;
+            // Newest first; an active source keeps its newest and the rest are walked from
+            // the oldest.
+            let segments = self.list_segments(&source_id, usize::MAX).await?;
+            let mut total = segments.iter().map(|entry| entry.bytes).sum::<u64>();
+            for entry in segments.into_iter().skip(usize::from(window.active)).rev() {
+                let over_budget = window.max_bytes > 0 && total > window.max_bytes;
+                if !(over_budget || window.expires(entry.end_unix, now))
+                    || window.holds(entry.end_unix, now)
+                    || self.held(&source_id, &entry)
+                {
+                    continue;
+                }
+                total -= entry.bytes;
+                let path = resolve_segment_path(&dir, map.as_ref(), &entry.name);
+                doomed.push((
+                    DoomedFile {
+                        source_id: source_id.clone(),
+                        name: entry.name.clone(),
+                        path: path.to_string_lossy().to_string(),
+                        bytes: entry.bytes,
+                    },
+                    entry,
+                ));
+            }
+        }
+
+        let summary = self.delete_doomed(doomed, now).await?;
+
+        let mut status = self
+            .retention_status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        status.blocked_segments = summary.blocked;
+        status.segment_pass_unix = Some(now);
+        if summary.hook.is_some() {
+            status.last_hook = summary.hook.clone();
+        }
+        Ok(summary)
+    }
+
+    pub(super) async fn delete_doomed(
+        &self,
+        doomed: Vec<(DoomedFile, SegmentEntry)>,
+        now: u64,
+    ) -> Result<SegmentRetentionSummary> {
+        let mut summary = SegmentRetentionSummary::default();
+        let approved = match &self.pre_delete_hook {
+            Some(hook) if !doomed.is_empty() => {
+                let files = doomed
+                    .iter()
+                    .map(|(file, _)| file.clone())
+                    .collect::<Vec<_>>();
+                let (approved, outcome) = hook.approve(&files).await;
+                summary.hook = Some(outcome);
+                Some(approved)
+            }
+            _ => None,
+        };
+        let mut by_source = HashMap::<String, Vec<_>>::new();
+        for (file, entry) in doomed {
+            if approved
+                .as_ref()
+                .is_some_and(|approved| !approved.contains(&file.path))
+            {
+                summary.blocked += 1;
+                continue;
+            }
+            by_source.entry(file.source_id).or_default().push(entry);
+        }
+        for (source_id, entries) in by_source {
+            let removed = self.remove_segments(&source_id, entries).await?;
+            summary.removed += removed.segments;
+            summary.bytes += removed.bytes;
+            if removed.segments > 0 {
+                self.segment_prunes
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .insert(
+                        source_id,
+                        SegmentPrune {
+                            at_unix: now,
+                            segments: removed.segments,
+                            bytes: removed.bytes,
+                        },
+                    );
+            }
+        }
+        Ok(summary)
+    }
+
+    pub async fn segment_retention_status(
+        &self,
+        source_ids: &[String],
+    ) -> Result<Vec<SourceRetentionStatus>> {
+        let mut out = Vec::new();
+        for source_id in source_ids {
+            let segments = self.list_segments(source_id, usize::MAX).await?;
+            let prune = self
+                .segment_prunes
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get(source_id)
+                .copied();
+            out.push(SourceRetentionStatus {
+                source_id: source_id.clone(),
+                window: self.retention_window(source_id),
+                segments: segments.len(),
+                total_bytes: segments.iter().map(|entry| entry.bytes).sum(),
+                oldest_segment_unix: segments.iter().map(|entry| entry.end_unix).min(),
+                last_prune_unix: prune.map(|prune| prune.at_unix),
+                last_prune_segments: prune.map(|prune| prune.segments).unwrap_or_default(),
+                last_prune_bytes: prune.map(|prune| prune.bytes).unwrap_or_default(),
+            });
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SnapshotRetention;
+
+    fn temp_root(name: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "constitute-nvr-zone-retention-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[tokio::test]
+    async fn segments_past_the_maximum_are_deleted() {
+        let root = temp_root("segments");
+        let dir = root.join("segments").join("cam-a");
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("20200101T000000.cnv");
+        std::fs::write(&old, b"old").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000))
+            .unwrap();
+        let recent = dir.join("20990101T000000.cnv");
+        std::fs::write(&recent, b"recent").unwrap();
+        let storage = StorageManager::new(root.clone(), &"11".repeat(32)).unwrap();
+
+        // Without a maximum, nothing ages out.
+        let summary = storage.enforce_segment_retention().await.unwrap();
+        assert_eq!(summary.removed, 0);
+        assert!(old.exists());
+
+        let window = RetentionWindow {
+            min_days: 7,
+            max_days: 30,
+            max_bytes: 0,
+            active: false,
+        };
+        storage.set_retention_windows(HashMap::from([("cam-a".to_string(), window)]));
+        let summary = storage.enforce_segment_retention().await.unwrap();
+        assert_eq!((summary.removed, summary.bytes, summary.blocked), (1, 3, 0));
+        assert!(!old.exists());
+        assert!(recent.exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn segments_over_the_byte_budget_go_oldest_first_but_never_the_newest() {
+        let root = temp_root("budget");
+        let dir = root.join("segments").join("cam-b");
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = crate::util::now_unix_seconds();
+        let mut paths = Vec::new();
+        for (index, name) in ["20240101T000000", "20240101T000010", "20240101T000020"]
+            .iter()
+            .enumerate()
+        {
+            let path = dir.join(format!("{name}.cnv"));
+            std::fs::write(&path, [0u8; 10]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(
+                    std::time::UNIX_EPOCH
+                        + std::time::Duration::from_secs(now - 300 + index as u64 * 10),
+                )
+                .unwrap();
+            paths.push(path);
+        }
+        let storage = StorageManager::new(root.clone(), &"44".repeat(32)).unwrap();
+        let window = RetentionWindow {
+            min_days: 0,
+            max_days: 0,
+            max_bytes: 15,
+            active: true,
+        };
+        storage.set_retention_windows(HashMap::from([("cam-b".to_string(), window)]));
+
+        let summary = storage.enforce_segment_retention().await.unwrap();
+        assert_eq!((summary.removed, summary.bytes), (2, 20));
+        assert!(!paths[0].exists() && !paths[1].exists());
+        assert!(paths[2].exists());
+
+        // The newest stays even when it alone is over the budget.
+        let window = RetentionWindow {
+            max_bytes: 1,
+            ..window
+        };
+        storage.set_retention_windows(HashMap::from([("cam-b".to_string(), window)]));
+        let summary = storage.enforce_segment_retention().await.unwrap();
+        assert_eq!(summary.removed, 0);
+        assert!(paths[2].exists());
+
+        let status = storage
+            .segment_retention_status(&["cam-b".to_string(), "cam-none".to_string()])
+            .await
+            .unwrap();
+        assert_eq!((status[0].segments, status[0].total_bytes), (1, 10));
+        assert_eq!(status[0].oldest_segment_unix, Some(now - 280));
+        assert_eq!(status[0].window.max_bytes, 1);
+        assert_eq!(
+            (status[0].last_prune_segments, status[0].last_prune_bytes),
+            (2, 20)
+        );
+        assert!(status[0].last_prune_unix.is_some());
+        assert_eq!((status[1].segments, status[1].last_prune_unix), (0, None));
+        assert!(storage.retention_status().segment_pass_unix.is_some());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn snapshots_inside_the_minimum_outlast_the_quota() {
+        let root = temp_root("snapshots");
+        let storage = StorageManager::new(root.clone(), &"33".repeat(32))
+            .unwrap()
+            .with_snapshot_retention(SnapshotRetention {
+                retention_days: 0,
+                max_bytes: 1,
+            });
+        let now = crate::util::now_unix_seconds();
+        storage
+            .store_snapshot("held", now - 60, b"kept")
+            .await
+            .unwrap();
+        storage
+            .store_snapshot("free", now - 30, b"gone")
+            .await
+            .unwrap();
+        storage.set_retention_windows(HashMap::from([(
+            "held".to_string(),
+            RetentionWindow {
+                min_days: 1,
+                max_days: 0,
+                max_bytes: 0,
+                active: false,
+            },
+        )]));
+
+        let summary = storage.enforce_snapshot_retention().await.unwrap();
+        assert_eq!((summary.removed, summary.held), (1, 1));
+        assert!(summary.over_quota_bytes > 0);
+        let status = storage.retention_status();
+        assert_eq!(status.held, 1);
+        assert_eq!(status.over_quota_bytes, summary.over_quota_bytes);
+        let kept = storage
+            .list_snapshots("held", None, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(kept.len(), 1);
+        let freed = storage
+            .list_snapshots("free", None, None, 10)
+            .await
+            .unwrap();
+        assert!(freed.is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -1,20 +1,43 @@
-use crate::config::Config;
+use crate::config::{Config, SharedConfig};
+use crate::endpoint_probe;
+use crate::features;
+use crate::interfaces::NetworkBindings;
+use crate::media::dependencies::DependencyMonitor;
 use crate::nostr::{self, NostrEvent};
+use crate::peer_clock::{ClockAssessment, ClockEstimator};
+use crate::record_store::{RecordStore, StoreLimits, StoredRecord};
+use crate::recording::RecorderManager;
+use crate::self_check::{HealthStatus, SelfCheck, SelfCheckView};
 use crate::util;
+use crate::util::backoff::Backoff;
 use anyhow::{Context, Result};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::future::Future;
+use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::net::{UdpSocket, lookup_host};
-use tokio::sync::Mutex;
-use tokio::time::{Duration, Instant, interval};
+use tokio::sync::{Mutex, Notify, watch};
+use tokio::time::{Duration, Instant, interval, sleep, sleep_until};
 use tracing::{debug, info, warn};
 
 const PROTOCOL_VERSION: u8 = 1;
 const RECORD_KIND: u32 = 30078;
 const APP_KIND: u32 = 1;
+const HEALTH_VERSION: u8 = 1;
+const MAX_HEALTH_PROBLEMS: usize = 8;
+const PEER_DEVICE_TTL_SECS: u64 = 600;
+const ANNOUNCE_STEADY_MAX_SECS: u64 = PEER_DEVICE_TTL_SECS / 2;
+const ANNOUNCE_MIN_SECS: u64 = 5;
+const ANNOUNCE_DECAY_AFTER: u32 = 3;
+const DEVICE_SLOT: &str = "device";
+const ANNOUNCE_CERT_TYPE: &str = "announce_key";
+const DEMOTE_AFTER_SEND_FAILURES: u32 = 3;
+const RECV_ERROR_PAUSE_MAX_MS: u64 = 1_000;
+const LOOP_RESTART_BACKOFF: Backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "kind", rename_all = "lowercase")]
@@ -46,6 +69,158 @@ enum UdpMessage {
 struct PeerState {
     last_seen: Instant,
     confirmed: bool,
+    send_failures: u32,
+}
+
+impl PeerState {
+    fn heard() -> Self {
+        Self {
+            last_seen: Instant::now(),
+            confirmed: true,
+            send_failures: 0,
+        }
+    }
+
+    fn live(&self) -> bool {
+        self.confirmed
+            && self.send_failures < DEMOTE_AFTER_SEND_FAILURES
+            && self.last_seen.elapsed() < Duration::from_secs(PEER_DEVICE_TTL_SECS)
+    }
+}
+
+type PeerTable = Arc<Mutex<HashMap<SocketAddr, PeerState>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnounceReset {
+    Start,
+    LocalChange,
+    Health,
+    RecordChanged,
+    NewPeer,
+}
+
+#[derive(Clone, Debug)]
+struct AnnounceCadence {
+    fast: Duration,
+    steady: Duration,
+    interval: Duration,
+    unchanged: u32,
+    next_at: Instant,
+    reset: AnnounceReset,
+    reset_unix: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnounceStatus {
+    pub interval_secs: u64,
+    pub fast_secs: u64,
+    pub steady_secs: u64,
+    pub unchanged: u32,
+    pub last_reset: AnnounceReset,
+    pub last_reset_unix: u64,
+}
+
+impl AnnounceCadence {
+    fn new(cfg: &Config, now: Instant, now_unix: u64) -> Self {
+        let steady = announce_steady_secs(cfg);
+        let fast = cfg
+            .swarm
+            .announce_fast_secs
+            .clamp(ANNOUNCE_MIN_SECS, steady);
+        Self {
+            fast: Duration::from_secs(fast),
+            steady: Duration::from_secs(steady),
+            interval: Duration::from_secs(fast),
+            unchanged: 0,
+            next_at: now,
+            reset: AnnounceReset::Start,
+            reset_unix: now_unix,
+        }
+    }
+
+    fn next_at(&self) -> Instant {
+        self.next_at
+    }
+
+    fn announced(&mut self, now: Instant, now_unix: u64, reset: Option<AnnounceReset>) {
+        match reset {
+            Some(reason) => {
+                self.interval = self.fast;
+                self.unchanged = 0;
+                self.reset = reason;
+                self.reset_unix = now_unix;
+            }
+            None => {
+                self.unchanged += 1;
+                if self.unchanged >= ANNOUNCE_DECAY_AFTER && self.interval < self.steady {
+                    self.interval = (self.interval * 2).min(self.steady);
+                    self.unchanged = 0;
+                }
+            }
+        }
+        self.next_at = now + self.interval;
+    }
+
+    fn status(&self) -> AnnounceStatus {
+        AnnounceStatus {
+            interval_secs: self.interval.as_secs(),
+            fast_secs: self.fast.as_secs(),
+            steady_secs: self.steady.as_secs(),
+            unchanged: self.unchanged,
+            last_reset: self.reset,
+            last_reset_unix: self.reset_unix,
+        }
+    }
+}
+
+fn announce_steady_secs(cfg: &Config) -> u64 {
+    cfg.swarm
+        .announce_interval_secs
+        .clamp(ANNOUNCE_MIN_SECS, ANNOUNCE_STEADY_MAX_SECS)
+}
+
+#[derive(Default)]
+struct TransportCounters {
+    send_errors: AtomicU64,
+    recv_errors: AtomicU64,
+    recv_restarts: AtomicU64,
+    announce_restarts: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransportStats {
+    pub send_errors: u64,
+    pub recv_errors: u64,
+    pub recv_restarts: u64,
+    pub announce_restarts: u64,
+}
+
+trait Datagrams: Send + Sync + 'static {
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+    fn send_to(&self, buf: &[u8], to: SocketAddr)
+    -> impl Future<Output = io::Result<usize>> + Send;
+}
+
+impl Datagrams for UdpSocket {
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn send_to(
+        &self,
+        buf: &[u8],
+        to: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send {
+        UdpSocket::send_to(self, buf, to)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -63,17 +238,86 @@ struct DeviceRecordPayload {
     service_version: String,
     ingest_protocols: Vec<String>,
     capabilities: Vec<String>,
+    #[serde(default)]
+    features: Vec<String>,
     ui_repo: String,
     #[serde(rename = "uiRef")]
     ui_ref: String,
-    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     ui_manifest_url: String,
     ui_entry: String,
-    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     session_ws_url: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    session_ws_url_status: String,
     #[serde(default)]
     allow_unsigned_debug_hello: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    privacy_sources: Vec<String>,
     metrics: DeviceMetricsPayload,
+    #[serde(default)]
+    announce_pk: String,
+    #[serde(default)]
+    announce_cert: Option<NostrEvent>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnnounceCertPayload {
+    #[serde(rename = "type")]
+    kind: String,
+    device_pk: String,
+    announce_pk: String,
+    issued_at: u64,
+}
+
+#[derive(Clone)]
+struct AnnounceKey {
+    sk_hex: String,
+    pubkey: String,
+    cert: NostrEvent,
+}
+
+impl AnnounceKey {
+    fn certify(cfg: &Config) -> Result<Self> {
+        let sk_hex = cfg.swarm.announce_sk_hex.trim().to_string();
+        let pubkey = nostr::pubkey_from_sk_hex(&sk_hex).context("invalid swarm.announce_sk_hex")?;
+        let payload = AnnounceCertPayload {
+            kind: ANNOUNCE_CERT_TYPE.to_string(),
+            device_pk: cfg.nostr_pubkey.clone(),
+            announce_pk: pubkey.clone(),
+            issued_at: util::now_ms(),
+        };
+        let tags = vec![
+            vec!["t".to_string(), "swarm_discovery".to_string()],
+            vec!["type".to_string(), ANNOUNCE_CERT_TYPE.to_string()],
+            vec!["p".to_string(), pubkey.clone()],
+        ];
+        let unsigned = nostr::build_unsigned_event(
+            &cfg.nostr_pubkey,
+            RECORD_KIND,
+            tags,
+            serde_json::to_string(&payload)?,
+            util::now_unix_seconds(),
+        );
+        let cert = nostr::sign_event(&unsigned, &cfg.nostr_sk_hex)?;
+        Ok(Self {
+            sk_hex,
+            pubkey,
+            cert,
+        })
+    }
+
+    fn sign(&self, kind: u32, tags: Vec<Vec<String>>, content: String) -> Result<NostrEvent> {
+        let unsigned = nostr::build_unsigned_event(
+            &self.pubkey,
+            kind,
+            tags,
+            content,
+            util::now_unix_seconds(),
+        );
+        nostr::sign_event(&unsigned, &self.sk_hex)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -84,6 +328,80 @@ struct DeviceMetricsPayload {
     peers_confirmed: u64,
     cameras_total: u64,
     cameras_enabled: u64,
+    #[serde(default)]
+    cameras_privacy: u64,
+    #[serde(default)]
+    health_v: u8,
+    #[serde(default)]
+    health: SwarmHealth,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwarmHealth {
+    #[default]
+    Ok,
+    Degraded,
+    Failing,
+}
+
+impl SwarmHealth {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Degraded => "degraded",
+            Self::Failing => "failing",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PeerRecordPayload {
+    device_pk: String,
+    announce_cert: Option<NostrEvent>,
+    device_label: String,
+    role: String,
+    service: String,
+    service_version: String,
+    updated_at: u64,
+    expires_at: u64,
+    metrics: PeerMetricsPayload,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PeerMetricsPayload {
+    uptime_sec: u64,
+    cameras_total: u64,
+    cameras_enabled: u64,
+    health_v: u8,
+    health: String,
+    problems: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SwarmDevice {
+    pub device_pk: String,
+    pub device_label: String,
+    pub role: String,
+    pub service: String,
+    pub service_version: String,
+    pub zones: Vec<String>,
+    pub health: String,
+    pub problems: Vec<String>,
+    pub cameras_total: u64,
+    pub cameras_enabled: u64,
+    pub uptime_sec: u64,
+    pub updated_at: u64,
+    pub last_seen_at: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub announce_pk: String,
+    #[serde(default)]
+    pub announce_cert_issued_at: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -99,56 +417,298 @@ struct ZonePresencePayload {
     service_version: String,
     ts: u64,
     ttl: u64,
+    announce_pk: String,
+    announce_cert: NostrEvent,
+}
+
+type DeviceTable = Arc<Mutex<RecordStore<SwarmDevice>>>;
+type PeerClocks = Arc<Mutex<ClockEstimator>>;
+
+#[derive(Clone)]
+struct SwarmCtx {
+    peers: Arc<Mutex<Vec<SocketAddr>>>,
+    table: PeerTable,
+    devices: DeviceTable,
+    clocks: PeerClocks,
+    counters: Arc<TransportCounters>,
+    peer_joined: Arc<Notify>,
+}
+
+#[derive(Clone)]
+struct AnnounceSources {
+    live_cfg: SharedConfig,
+    announce_key: AnnounceKey,
+    bindings: NetworkBindings,
+    dependencies: DependencyMonitor,
+    recorder: RecorderManager,
+    self_check: SelfCheck,
 }
 
 #[derive(Clone)]
 pub struct SwarmHandle {
-    peers: Arc<Mutex<HashMap<SocketAddr, PeerState>>>,
+    peers: PeerTable,
+    devices: DeviceTable,
+    clocks: PeerClocks,
+    skew_threshold_ms: i64,
+    announce_now: Arc<Notify>,
+    announce_status: watch::Receiver<AnnounceStatus>,
+    counters: Arc<TransportCounters>,
+    bindings: NetworkBindings,
+    started: Instant,
 }
 
 impl SwarmHandle {
     pub async fn confirmed_peers(&self) -> usize {
         let guard = self.peers.lock().await;
-        guard.values().filter(|p| p.confirmed).count()
+        guard.values().filter(|p| p.live()).count()
     }
-}
 
-pub async fn start(cfg: Config) -> Result<SwarmHandle> {
-    let bind: SocketAddr = cfg
-        .swarm
-        .bind
-        .parse()
-        .with_context(|| format!("invalid swarm.bind: {}", cfg.swarm.bind))?;
+    pub async fn silent_for(&self) -> Duration {
+        let guard = self.peers.lock().await;
+        guard
+            .values()
+            .filter(|peer| peer.confirmed)
+            .map(|peer| peer.last_seen)
+            .max()
+            .unwrap_or(self.started)
+            .elapsed()
+    }
 
-    let socket = Arc::new(UdpSocket::bind(bind).await?);
-    let peers = Arc::new(Mutex::new(resolve_peers(&cfg.swarm.peers).await));
-    let table = Arc::new(Mutex::new(HashMap::<SocketAddr, PeerState>::new()));
+    pub fn announce_now(&self) {
+        self.announce_now.notify_one();
+    }
 
-    let recv_socket = Arc::clone(&socket);
-    let recv_peers = Arc::clone(&peers);
-    let recv_table = Arc::clone(&table);
-    let recv_cfg = cfg.clone();
+    pub fn announce_status(&self) -> AnnounceStatus {
+        self.announce_status.borrow().clone()
+    }
 
-    tokio::spawn(async move {
-        if let Err(err) = recv_loop(recv_socket, recv_peers, recv_table, recv_cfg).await {
-            warn!(error = %err, "swarm recv loop exited");
+    pub fn bindings(&self) -> &NetworkBindings {
+        &self.bindings
+    }
+
+    pub async fn devices(&self) -> Vec<SwarmDevice> {
+        let guard = self.devices.lock().await;
+        let mut out = guard
+            .values(util::now_ms())
+            .map(|entry| entry.value.clone())
+            .collect::<Vec<_>>();
+        out.sort_by(|a, b| {
+            (a.device_label.as_str(), a.device_pk.as_str())
+                .cmp(&(b.device_label.as_str(), b.device_pk.as_str()))
+        });
+        out
+    }
+
+    pub async fn clock_assessment(&self) -> ClockAssessment {
+        let mut guard = self.clocks.lock().await;
+        guard.forget_stale(util::now_ms(), PEER_DEVICE_TTL_SECS * 1000);
+        guard.assess(self.skew_threshold_ms)
+    }
+
+    pub async fn record_store_stats(&self) -> (usize, u64) {
+        let guard = self.devices.lock().await;
+        (guard.entry_count(), guard.evictions())
+    }
+
+    pub fn transport_stats(&self) -> TransportStats {
+        let counters = &self.counters;
+        TransportStats {
+            send_errors: counters.send_errors.load(Ordering::Relaxed),
+            recv_errors: counters.recv_errors.load(Ordering::Relaxed),
+            recv_restarts: counters.recv_restarts.load(Ordering::Relaxed),
+            announce_restarts: counters.announce_restarts.load(Ordering::Relaxed),
         }
-    });
+    }
+}
 
-    let tx_socket = Arc::clone(&socket);
-    let tx_peers = Arc::clone(&peers);
-    let tx_table = Arc::clone(&table);
-    let tx_cfg = cfg.clone();
+pub async fn start(
+    live_cfg: SharedConfig,
+    dependencies: DependencyMonitor,
+    recorder: RecorderManager,
+    self_check: SelfCheck,
+) -> Result<SwarmHandle> {
+    let cfg = Config::clone(&live_cfg.snapshot());
+    let bindings = NetworkBindings::start(&cfg)?;
+    let bind: SocketAddr = match bindings.selected().swarm {
+        Some(bind) => bind,
+        None => cfg
+            .swarm
+            .bind
+            .parse()
+            .with_context(|| format!("invalid swarm.bind: {}", cfg.swarm.bind))?,
+    };
 
-    tokio::spawn(async move {
-        if let Err(err) = announce_loop(tx_socket, tx_peers, tx_table, tx_cfg).await {
-            warn!(error = %err, "swarm announce loop exited");
+    let announce_key = AnnounceKey::certify(&cfg)?;
+    let socket = UdpSocket::bind(bind).await?;
+    bindings.set_swarm_bound(socket.local_addr()?);
+    let (socket_tx, sockets) = watch::channel(Arc::new(socket));
+    let peers = Arc::new(Mutex::new(resolve_peers(&cfg.swarm.peers).await));
+    let table = PeerTable::default();
+    let devices = Arc::new(Mutex::new(RecordStore::new(StoreLimits {
+        max_entries: cfg.swarm.max_records,
+        max_per_pubkey: cfg.swarm.max_records_per_device,
+    })));
+    let snapshot_path = cfg.swarm.record_snapshot_path.trim().to_string();
+    if !snapshot_path.is_empty() {
+        match load_record_snapshot(&snapshot_path).await {
+            Ok(records) => devices.lock().await.restore(records, util::now_ms()),
+            Err(err) => warn!(path = %snapshot_path, error = %err, "swarm record snapshot ignored"),
         }
+    }
+    let sweep_devices = Arc::clone(&devices);
+    let sweep_secs = cfg.swarm.record_sweep_secs.max(1);
+    tokio::spawn(async move {
+        sweep_loop(sweep_devices, sweep_secs, snapshot_path).await;
     });
 
+    let ctx = SwarmCtx {
+        peers,
+        table,
+        devices,
+        clocks: PeerClocks::default(),
+        counters: Arc::new(TransportCounters::default()),
+        peer_joined: Arc::new(Notify::new()),
+    };
+    {
+        let (sockets, ctx, cfg) = (sockets.clone(), ctx.clone(), cfg.clone());
+        tokio::spawn(supervise(
+            "recv",
+            Arc::clone(&ctx.counters),
+            |counters| &counters.recv_restarts,
+            move || recv_rebinding(sockets.clone(), ctx.clone(), cfg.clone()),
+        ));
+    }
+
+    let announce_now = Arc::new(Notify::new());
+    let (status_tx, announce_status) = watch::channel(
+        AnnounceCadence::new(&cfg, Instant::now(), util::now_unix_seconds()).status(),
+    );
+    let status_tx = Arc::new(status_tx);
+    tokio::spawn(endpoint_probe::probe_loop(
+        live_cfg.clone(),
+        bindings.clone(),
+        Arc::clone(&announce_now),
+    ));
+    tokio::spawn(rebind_loop(
+        bindings.clone(),
+        socket_tx,
+        Arc::clone(&announce_now),
+    ));
+    {
+        let (ctx, announce_now) = (ctx.clone(), Arc::clone(&announce_now));
+        let sources = AnnounceSources {
+            live_cfg,
+            announce_key,
+            bindings: bindings.clone(),
+            dependencies,
+            recorder,
+            self_check,
+        };
+        tokio::spawn(supervise(
+            "announce",
+            Arc::clone(&ctx.counters),
+            |counters| &counters.announce_restarts,
+            move || {
+                announce_loop(
+                    sockets.clone(),
+                    ctx.clone(),
+                    sources.clone(),
+                    Arc::clone(&announce_now),
+                    Arc::clone(&status_tx),
+                )
+            },
+        ));
+    }
+
     info!(bind = %bind, "swarm udp runtime started");
 
-    Ok(SwarmHandle { peers: table })
+    Ok(SwarmHandle {
+        peers: ctx.table,
+        devices: ctx.devices,
+        clocks: ctx.clocks,
+        skew_threshold_ms: (cfg.swarm.clock_skew_threshold_secs.max(1) * 1000) as i64,
+        announce_now,
+        announce_status,
+        counters: ctx.counters,
+        bindings,
+        started: Instant::now(),
+    })
+}
+
+async fn rebind_loop(
+    bindings: NetworkBindings,
+    socket_tx: watch::Sender<Arc<UdpSocket>>,
+    announce_now: Arc<Notify>,
+) {
+    let mut selected = bindings.subscribe();
+    let mut last = *selected.borrow_and_update();
+    while selected.changed().await.is_ok() {
+        let next = *selected.borrow_and_update();
+        if let Some(bind) = next.swarm.filter(|bind| Some(*bind) != last.swarm) {
+            match UdpSocket::bind(bind).await {
+                Ok(socket) => {
+                    bindings.set_swarm_bound(socket.local_addr().unwrap_or(bind));
+                    socket_tx.send_replace(Arc::new(socket));
+                    info!(bind = %bind, "swarm socket rebound");
+                }
+                Err(err) => warn!(bind = %bind, error = %err, "swarm rebind failed"),
+            }
+        }
+        last = next;
+        announce_now.notify_one();
+    }
+}
+
+async fn recv_rebinding(
+    mut sockets: watch::Receiver<Arc<UdpSocket>>,
+    ctx: SwarmCtx,
+    cfg: Config,
+) -> Result<()> {
+    loop {
+        let socket = Arc::clone(&sockets.borrow_and_update());
+        let received = recv_loop(socket, ctx.clone(), cfg.clone());
+        let rebound = async {
+            if sockets.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::select! {
+            result = received => return result,
+            _ = rebound => {}
+        }
+    }
+}
+
+async fn supervise<F, Fut>(
+    name: &'static str,
+    counters: Arc<TransportCounters>,
+    restarts: fn(&TransportCounters) -> &AtomicU64,
+    mut run: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut delays = LOOP_RESTART_BACKOFF.iter();
+    loop {
+        let started = Instant::now();
+        match tokio::spawn(run()).await {
+            Ok(Ok(())) => warn!(loop_name = name, "swarm loop ended"),
+            Ok(Err(err)) => warn!(loop_name = name, error = %err, "swarm loop exited"),
+            Err(err) => warn!(loop_name = name, error = %err, "swarm loop panicked"),
+        }
+        restarts(&counters).fetch_add(1, Ordering::Relaxed);
+        // A loop that ran for a while before exiting starts the backoff over.
+        if started.elapsed() >= LOOP_RESTART_BACKOFF.cap() {
+            delays = LOOP_RESTART_BACKOFF.iter();
+        }
+        let backoff = delays.next().unwrap_or(LOOP_RESTART_BACKOFF.cap());
+        info!(
+            loop_name = name,
+            after_secs = backoff.as_secs(),
+            "restarting swarm loop"
+        );
+        sleep(backoff).await;
+    }
 }
 
 async fn resolve_peers(raw: &[String]) -> Vec<SocketAddr> {
@@ -165,20 +725,28 @@ async fn resolve_peers(raw: &[String]) -> Vec<SocketAddr> {
 }
 
 async fn announce_loop(
-    socket: Arc<UdpSocket>,
-    peers: Arc<Mutex<Vec<SocketAddr>>>,
-    table: Arc<Mutex<HashMap<SocketAddr, PeerState>>>,
-    cfg: Config,
+    sockets: watch::Receiver<Arc<UdpSocket>>,
+    ctx: SwarmCtx,
+    sources: AnnounceSources,
+    announce_now: Arc<Notify>,
+    status: Arc<watch::Sender<AnnounceStatus>>,
 ) -> Result<()> {
+    let SwarmCtx {
+        peers,
+        table,
+        counters,
+        peer_joined,
+        ..
+    } = &ctx;
+    let live_cfg = &sources.live_cfg;
+    // Identity, pairing, and timer settings are fixed at start; zones and cameras are live.
+    let cfg = live_cfg.snapshot();
     let started_at = Instant::now();
     let mut hello_tick = interval(Duration::from_secs(5));
-    let mut announce_tick = interval(Duration::from_secs(cfg.swarm.announce_interval_secs.max(5)));
-    let zones = cfg
-        .swarm
-        .zones
-        .iter()
-        .map(|z| z.key.clone())
-        .collect::<Vec<_>>();
+    let mut cadence = AnnounceCadence::new(&cfg, Instant::now(), util::now_unix_seconds());
+    status.send_replace(cadence.status());
+    let mut last_health: Option<SwarmHealth> = None;
+    let mut last_fingerprint: Option<String> = None;
 
     let pair_identity_label = cfg.pair_identity_label.trim().to_string();
     let pair_code = cfg.pair_code.trim().to_string();
@@ -211,48 +779,54 @@ async fn announce_loop(
                     v: PROTOCOL_VERSION,
                     node_id: cfg.node_id.clone(),
                     device_pk: cfg.nostr_pubkey.clone(),
-                    zones: zones.clone(),
+                    zones: zone_keys(&live_cfg.snapshot()),
                     ts: util::now_ms(),
                 };
-                broadcast_json(&socket, &peers, &hello).await;
+                let socket = Arc::clone(&sockets.borrow());
+                broadcast_json(&*socket, peers, table, counters, &hello).await;
             }
-            _ = announce_tick.tick() => {
+            woken = async {
+                tokio::select! {
+                    _ = sleep_until(cadence.next_at()) => None,
+                    _ = announce_now.notified() => Some(AnnounceReset::LocalChange),
+                    _ = peer_joined.notified() => Some(AnnounceReset::NewPeer),
+                }
+            } => {
+                if let Some(reason) = woken {
+                    debug!(reason = ?reason, "announcing out of cycle");
+                }
                 let peers_known = peers.lock().await.len() as u64;
-                let peers_confirmed = table.lock().await.values().filter(|p| p.confirmed).count() as u64;
-                let cameras_total = cfg.camera_devices.len() as u64;
-                let cameras_enabled = cfg.camera_devices.iter().filter(|c| c.enabled).count() as u64;
-                let metrics = DeviceMetricsPayload {
-                    uptime_sec: started_at.elapsed().as_secs(),
+                let peers_confirmed = table.lock().await.values().filter(|p| p.live()).count() as u64;
+                let (messages, health, fingerprint) = announcements(
+                    &sources,
+                    started_at.elapsed().as_secs(),
                     peers_known,
                     peers_confirmed,
-                    cameras_total,
-                    cameras_enabled,
-                };
-
-                for zone in &zones {
-                    if let Ok(ev) = build_device_record(&cfg, &metrics) {
-                        let msg = UdpMessage::Record {
-                            v: PROTOCOL_VERSION,
-                            zone: zone.clone(),
-                            record_type: "device".to_string(),
-                            event: ev,
-                            ts: util::now_ms(),
-                        };
-                        broadcast_json(&socket, &peers, &msg).await;
-                    }
-                    if let Ok(ev) = build_zone_presence(&cfg, zone) {
-                        let msg = UdpMessage::Record {
-                            v: PROTOCOL_VERSION,
-                            zone: zone.clone(),
-                            record_type: "zone_presence".to_string(),
-                            event: ev,
-                            ts: util::now_ms(),
-                        };
-                        broadcast_json(&socket, &peers, &msg).await;
-                    }
+                )
+                .await;
+                let socket = Arc::clone(&sockets.borrow());
+                for msg in &messages {
+                    broadcast_json(&*socket, peers, table, counters, msg).await;
                 }
+                let reset = if last_health.is_some_and(|last| last != health) {
+                    info!(health = health.as_str(), "node health changed; announcing faster");
+                    Some(AnnounceReset::Health)
+                } else if last_fingerprint.as_ref().is_some_and(|last| *last != fingerprint) {
+                    Some(AnnounceReset::RecordChanged)
+                } else {
+                    None
+                };
+                cadence.announced(
+                    Instant::now(),
+                    util::now_unix_seconds(),
+                    woken.or(reset),
+                );
+                status.send_replace(cadence.status());
+                last_health = Some(health);
+                last_fingerprint = Some(fingerprint);
             }
             _ = pair_tick.tick(), if pair_enabled && pair_attempts_remaining > 0 => {
+                let zones = zone_keys(&live_cfg.snapshot());
                 for zone in &zones {
                     match build_pair_request_event(&cfg, zone, &pair_identity_label, &pair_code, &pair_code_hash) {
                         Ok(ev) => {
@@ -263,7 +837,8 @@ async fn announce_loop(
                                 event: ev,
                                 ts: util::now_ms(),
                             };
-                            broadcast_json(&socket, &peers, &msg).await;
+                            let socket = Arc::clone(&sockets.borrow());
+                            broadcast_json(&*socket, peers, table, counters, &msg).await;
                         }
                         Err(err) => {
                             warn!(error = %err, zone = %zone, "failed building pair_request enrollment signal");
@@ -278,15 +853,297 @@ async fn announce_loop(
     }
 }
 
-async fn recv_loop(
-    socket: Arc<UdpSocket>,
-    peers: Arc<Mutex<Vec<SocketAddr>>>,
-    table: Arc<Mutex<HashMap<SocketAddr, PeerState>>>,
-    cfg: Config,
-) -> Result<()> {
+fn zone_keys(cfg: &Config) -> Vec<String> {
+    cfg.swarm.zones.iter().map(|z| z.key.clone()).collect()
+}
+
+async fn announcements(
+    sources: &AnnounceSources,
+    uptime_sec: u64,
+    peers_known: u64,
+    peers_confirmed: u64,
+) -> (Vec<UdpMessage>, SwarmHealth, String) {
+    let AnnounceSources {
+        live_cfg,
+        announce_key,
+        bindings,
+        dependencies,
+        recorder,
+        self_check,
+    } = sources;
+    let mut cfg = Config::clone(&live_cfg.snapshot());
+    bindings.apply_derived(&mut cfg);
+    let media = &dependencies.current();
+    let privacy_sources = recorder
+        .list_states()
+        .await
+        .into_iter()
+        .filter(|state| state.state == "privacy")
+        .map(|state| state.source_id)
+        .collect::<Vec<_>>();
+    let (health, problems) = health_summary(&self_check.view().await, &cfg);
+    let metrics = DeviceMetricsPayload {
+        uptime_sec,
+        peers_known,
+        peers_confirmed,
+        cameras_total: cfg.camera_devices.len() as u64,
+        cameras_enabled: cfg.camera_devices.iter().filter(|c| c.enabled).count() as u64,
+        cameras_privacy: privacy_sources.len() as u64,
+        health_v: HEALTH_VERSION,
+        health,
+        problems,
+    };
+    let capabilities = media.capabilities();
+    let features = features::session_features(&cfg, media);
+    let url_status = bindings.probe().status(&cfg.api.public_ws_url);
+    let fingerprint = util::sha256_b64url(
+        &serde_json::json!([
+            zone_keys(&cfg),
+            cfg.device_label,
+            cfg.api.public_ws_url,
+            cfg.swarm.endpoint_hint,
+            metrics.cameras_total,
+            metrics.cameras_enabled,
+            privacy_sources,
+            metrics.health,
+            metrics.problems,
+            capabilities,
+            features,
+            url_status.as_str(),
+        ])
+        .to_string(),
+    );
+
+    let mut out = Vec::new();
+    for zone in zone_keys(&cfg) {
+        if let Ok(ev) = build_device_record(
+            &cfg,
+            announce_key,
+            &metrics,
+            &capabilities,
+            &features,
+            &privacy_sources,
+            url_status.as_str(),
+        ) {
+            out.push(UdpMessage::Record {
+                v: PROTOCOL_VERSION,
+                zone: zone.clone(),
+                record_type: "device".to_string(),
+                event: ev,
+                ts: util::now_ms(),
+            });
+        }
+        if let Ok(ev) = build_zone_presence(&cfg, announce_key, &zone) {
+            out.push(UdpMessage::Record {
+                v: PROTOCOL_VERSION,
+                zone,
+                record_type: "zone_presence".to_string(),
+                event: ev,
+                ts: util::now_ms(),
+            });
+        }
+    }
+    (out, health, fingerprint)
+}
+
+fn health_summary(view: &SelfCheckView, cfg: &Config) -> (SwarmHealth, Vec<String>) {
+    let health = match view.status {
+        HealthStatus::Failing => SwarmHealth::Failing,
+        HealthStatus::Degraded => SwarmHealth::Degraded,
+        HealthStatus::Ok | HealthStatus::Starting => SwarmHealth::Ok,
+    };
+    let mut problems = view
+        .problems
+        .iter()
+        .map(|problem| match problem.check.as_str() {
+            "recorder_stuck" => "cameras_failing".to_string(),
+            "camera_clock_drift" | "clock_anomaly" => "camera_clocks".to_string(),
+            check => check.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if cfg.provisioning_state() != "paired" {
+        problems.push("unprovisioned".to_string());
+    }
+    problems.sort();
+    problems.dedup();
+    problems.truncate(MAX_HEALTH_PROBLEMS);
+    (health, problems)
+}
+
+async fn remember_device(devices: &DeviceTable, cfg: &Config, zone: &str, event: &NostrEvent) {
+    let Ok(payload) = serde_json::from_str::<PeerRecordPayload>(&event.content) else {
+        return;
+    };
+    let Some((device_pk, issued_at)) =
+        record_identity(event, &payload.device_pk, payload.announce_cert.as_ref())
+    else {
+        debug!(signer = %event.pubkey, "swarm record rejected: announce key not certified");
+        return;
+    };
+    if device_pk == cfg.nostr_pubkey {
+        return;
+    }
+    let now = util::now_ms();
+    let mut expires_at = now + PEER_DEVICE_TTL_SECS * 1000;
+    if payload.expires_at > 0 {
+        expires_at = expires_at.min(payload.expires_at);
+    }
+    let mut guard = devices.lock().await;
+    let previous = guard.get(&device_pk, DEVICE_SLOT, now);
+    // Once a rotated key's certificate is seen, the key it replaced is refused.
+    let (announce_pk, announce_cert_issued_at) = match (issued_at, previous) {
+        (None, previous) => previous.map_or_else(Default::default, |previous| {
+            (
+                previous.announce_pk.clone(),
+                previous.announce_cert_issued_at,
+            )
+        }),
+        (Some(issued_at), Some(previous))
+            if issued_at < previous.announce_cert_issued_at
+                || (issued_at == previous.announce_cert_issued_at
+                    && event.pubkey != previous.announce_pk) =>
+        {
+            debug!(signer = %event.pubkey, "swarm record rejected: announce key superseded");
+            return;
+        }
+        (Some(issued_at), _) => (event.pubkey.clone(), issued_at),
+    };
+    let mut zones = previous
+        .map(|previous| previous.zones.clone())
+        .unwrap_or_default();
+    if !zones.iter().any(|known| known == zone) {
+        zones.push(zone.to_string());
+        zones.sort();
+    }
+    let metrics = payload.metrics;
+    let health = if metrics.health_v == 0 || metrics.health.is_empty() {
+        "unknown".to_string()
+    } else {
+        metrics.health
+    };
+    let device = SwarmDevice {
+        device_pk: device_pk.clone(),
+        device_label: payload.device_label,
+        role: payload.role,
+        service: payload.service,
+        service_version: payload.service_version,
+        zones,
+        health,
+        problems: metrics
+            .problems
+            .into_iter()
+            .take(MAX_HEALTH_PROBLEMS)
+            .collect(),
+        cameras_total: metrics.cameras_total,
+        cameras_enabled: metrics.cameras_enabled,
+        uptime_sec: metrics.uptime_sec,
+        updated_at: payload.updated_at,
+        last_seen_at: now,
+        announce_pk,
+        announce_cert_issued_at,
+    };
+    guard.insert(&device_pk, DEVICE_SLOT, device, expires_at, now);
+}
+
+fn record_identity(
+    event: &NostrEvent,
+    device_pk: &str,
+    cert: Option<&NostrEvent>,
+) -> Option<(String, Option<u64>)> {
+    if device_pk.is_empty() || device_pk == event.pubkey {
+        return Some((event.pubkey.clone(), None));
+    }
+    let cert = cert?;
+    if cert.kind != RECORD_KIND
+        || cert.pubkey != device_pk
+        || !nostr::verify_event(cert).unwrap_or(false)
+    {
+        return None;
+    }
+    let payload = serde_json::from_str::<AnnounceCertPayload>(&cert.content).ok()?;
+    (payload.kind == ANNOUNCE_CERT_TYPE
+        && payload.device_pk == device_pk
+        && payload.announce_pk == event.pubkey)
+        .then(|| (device_pk.to_string(), Some(payload.issued_at)))
+}
+
+async fn sweep_loop(devices: DeviceTable, every_secs: u64, snapshot_path: String) {
+    let mut tick = interval(Duration::from_secs(every_secs));
+    loop {
+        tick.tick().await;
+        let now = util::now_ms();
+        let snapshot = {
+            let mut guard = devices.lock().await;
+            let swept = guard.sweep(now);
+            if swept > 0 {
+                debug!(swept, held = guard.entry_count(), "swarm records swept");
+            }
+            guard.snapshot(now)
+        };
+        if snapshot_path.is_empty() {
+            continue;
+        }
+        if let Err(err) = write_record_snapshot(&snapshot_path, &snapshot).await {
+            warn!(path = %snapshot_path, error = %err, "failed writing swarm record snapshot");
+        }
+    }
+}
+
+async fn load_record_snapshot(path: &str) -> Result<Vec<StoredRecord<SwarmDevice>>> {
+    let raw = match tokio::fs::read(path).await {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("read {path}")),
+    };
+    serde_json::from_slice(&raw).with_context(|| format!("parse {path}"))
+}
+
+async fn write_record_snapshot(path: &str, records: &[StoredRecord<SwarmDevice>]) -> Result<()> {
+    let tmp = format!("{path}.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(records)?)
+        .await
+        .with_context(|| format!("write {tmp}"))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("replace {path}"))?;
+    Ok(())
+}
+
+async fn recv_loop<S: Datagrams>(socket: Arc<S>, ctx: SwarmCtx, cfg: Config) -> Result<()> {
+    let SwarmCtx {
+        peers,
+        table,
+        devices,
+        clocks,
+        counters,
+        peer_joined,
+    } = ctx;
     let mut buf = vec![0u8; 65_535];
+    let mut failing = 0u64;
     loop {
-        let (len, from) = socket.recv_from(&mut buf).await?;
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => {
+                failing = 0;
+                received
+            }
+            Err(err) if !recoverable_recv_error(&err) => {
+                return Err(err).context("swarm recv_from");
+            }
+            Err(err) => {
+                counters.recv_errors.fetch_add(1, Ordering::Relaxed);
+                failing += 1;
+                if failing == 1 {
+                    warn!(error = %err, "swarm recv failed; continuing");
+                } else {
+                    debug!(error = %err, failing, "swarm recv failed again");
+                }
+                sleep(Duration::from_millis(
+                    (failing * 50).min(RECV_ERROR_PAUSE_MAX_MS),
+                ))
+                .await;
+                continue;
+            }
+        };
         let raw = &buf[..len];
         let msg: UdpMessage = match serde_json::from_slice(raw) {
             Ok(v) => v,
@@ -299,20 +1156,14 @@ async fn recv_loop(
                 node_id,
                 device_pk,
                 zones,
-                ..
+                ts,
             } => {
                 if v != PROTOCOL_VERSION {
                     continue;
                 }
-                {
-                    let mut guard = table.lock().await;
-                    guard.insert(
-                        from,
-                        PeerState {
-                            last_seen: Instant::now(),
-                            confirmed: true,
-                        },
-                    );
+                observe_clock(&clocks, &cfg, &device_pk, &node_id, ts).await;
+                if heard_from(&table, from).await {
+                    peer_joined.notify_one();
                 }
 
                 let ack = UdpMessage::Ack {
@@ -322,29 +1173,24 @@ async fn recv_loop(
                     zones: cfg.swarm.zones.iter().map(|z| z.key.clone()).collect(),
                     ts: util::now_ms(),
                 };
-                send_json(&socket, from, &ack).await;
+                send_json(&*socket, &table, &counters, from, &ack).await;
 
                 add_peer(peers.clone(), from).await;
                 debug!(from = %from, node_id = %node_id, device_pk = %device_pk, zones = ?zones, "swarm hello received");
             }
             UdpMessage::Ack {
                 v,
+                node_id,
                 device_pk,
                 zones,
-                ..
+                ts,
             } => {
                 if v != PROTOCOL_VERSION {
                     continue;
                 }
-                {
-                    let mut guard = table.lock().await;
-                    guard.insert(
-                        from,
-                        PeerState {
-                            last_seen: Instant::now(),
-                            confirmed: true,
-                        },
-                    );
+                observe_clock(&clocks, &cfg, &device_pk, &node_id, ts).await;
+                if heard_from(&table, from).await {
+                    peer_joined.notify_one();
                 }
                 add_peer(peers.clone(), from).await;
                 debug!(from = %from, device_pk = %device_pk, zones = ?zones, "swarm ack received");
@@ -362,9 +1208,14 @@ async fn recv_loop(
                 match nostr::verify_event(&event) {
                     Ok(true) => {
                         debug!(from = %from, zone = %zone, record_type = %record_type, "swarm record received");
-                        let mut guard = table.lock().await;
-                        if let Some(entry) = guard.get_mut(&from) {
-                            entry.last_seen = Instant::now();
+                        {
+                            let mut guard = table.lock().await;
+                            if let Some(entry) = guard.get_mut(&from) {
+                                entry.last_seen = Instant::now();
+                            }
+                        }
+                        if record_type == "device" && event.kind == RECORD_KIND {
+                            remember_device(&devices, &cfg, &zone, &event).await;
                         }
                     }
                     Ok(false) => {
@@ -379,6 +1230,23 @@ async fn recv_loop(
     }
 }
 
+async fn observe_clock(clocks: &PeerClocks, cfg: &Config, device_pk: &str, node_id: &str, ts: u64) {
+    if device_pk == cfg.nostr_pubkey {
+        return;
+    }
+    // Hellos are unsigned, so the estimate is only reported and bounds the session window;
+    // it never adjusts the clock.
+    clocks
+        .lock()
+        .await
+        .observe(device_pk, node_id, ts, util::now_ms());
+}
+
+async fn heard_from(table: &PeerTable, peer: SocketAddr) -> bool {
+    let previous = table.lock().await.insert(peer, PeerState::heard());
+    !previous.is_some_and(|state| state.live())
+}
+
 async fn add_peer(peers: Arc<Mutex<Vec<SocketAddr>>>, addr: SocketAddr) {
     let mut guard = peers.lock().await;
     if !guard.contains(&addr) {
@@ -386,24 +1254,102 @@ async fn add_peer(peers: Arc<Mutex<Vec<SocketAddr>>>, addr: SocketAddr) {
     }
 }
 
-async fn broadcast_json(socket: &UdpSocket, peers: &Arc<Mutex<Vec<SocketAddr>>>, msg: &UdpMessage) {
+async fn broadcast_json<S: Datagrams>(
+    socket: &S,
+    peers: &Arc<Mutex<Vec<SocketAddr>>>,
+    table: &PeerTable,
+    counters: &TransportCounters,
+    msg: &UdpMessage,
+) {
     let payload = match serde_json::to_vec(msg) {
         Ok(v) => v,
         Err(_) => return,
     };
     let list = peers.lock().await.clone();
     for peer in list {
-        let _ = socket.send_to(&payload, peer).await;
+        let sent = socket.send_to(&payload, peer).await;
+        note_send(table, counters, peer, sent).await;
     }
 }
 
-async fn send_json(socket: &UdpSocket, to: SocketAddr, msg: &UdpMessage) {
+async fn send_json<S: Datagrams>(
+    socket: &S,
+    table: &PeerTable,
+    counters: &TransportCounters,
+    to: SocketAddr,
+    msg: &UdpMessage,
+) {
     if let Ok(payload) = serde_json::to_vec(msg) {
-        let _ = socket.send_to(&payload, to).await;
+        let sent = socket.send_to(&payload, to).await;
+        note_send(table, counters, to, sent).await;
     }
 }
 
-fn build_device_record(cfg: &Config, metrics: &DeviceMetricsPayload) -> Result<NostrEvent> {
+async fn note_send(
+    table: &PeerTable,
+    counters: &TransportCounters,
+    peer: SocketAddr,
+    sent: io::Result<usize>,
+) {
+    let mut guard = table.lock().await;
+    match sent {
+        Ok(_) => {
+            if let Some(state) = guard.get_mut(&peer) {
+                state.send_failures = 0;
+            }
+        }
+        Err(err) => {
+            counters.send_errors.fetch_add(1, Ordering::Relaxed);
+            let state = guard.entry(peer).or_insert_with(|| PeerState {
+                last_seen: Instant::now(),
+                confirmed: false,
+                send_failures: 0,
+            });
+            state.send_failures += 1;
+            if state.confirmed && state.send_failures == DEMOTE_AFTER_SEND_FAILURES {
+                warn!(peer = %peer, error = %err, "swarm peer unreachable; no longer confirmed");
+            } else {
+                debug!(peer = %peer, error = %err, "swarm send failed");
+            }
+        }
+    }
+}
+
+fn recoverable_recv_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::OutOfMemory
+    )
+}
+
+fn ingest_protocols(cfg: &Config) -> Vec<String> {
+    let mut protocols = Vec::new();
+    if cfg.camera_devices.iter().any(|camera| camera.has_onvif()) {
+        protocols.push("onvif".to_string());
+    }
+    protocols.push("rtsp".to_string());
+    protocols
+}
+
+fn build_device_record(
+    cfg: &Config,
+    announce_key: &AnnounceKey,
+    metrics: &DeviceMetricsPayload,
+    capabilities: &[String],
+    features: &[String],
+    privacy_sources: &[String],
+    session_ws_url_status: &str,
+) -> Result<NostrEvent> {
     let now = util::now_ms();
     let payload = DeviceRecordPayload {
         device_pk: cfg.nostr_pubkey.clone(),
@@ -411,58 +1357,75 @@ fn build_device_record(cfg: &Config, metrics: &DeviceMetricsPayload) -> Result<N
         device_label: cfg.device_label.clone(),
         updated_at: now,
         expires_at: now + 24 * 60 * 60 * 1000,
-        role: cfg.node_role.clone(),
+        role: cfg.node_role.to_string(),
         device_kind: "service".to_string(),
         service: "nvr".to_string(),
         host_gateway_pk: cfg.gateway.host_gateway_pk.clone(),
         service_version: cfg.service_version.clone(),
-        ingest_protocols: vec!["onvif".to_string(), "rtsp".to_string()],
-        capabilities: vec!["camera".to_string()],
+        ingest_protocols: ingest_protocols(cfg),
+        capabilities: capabilities.to_vec(),
+        features: features.to_vec(),
         ui_repo: cfg.ui.repo.clone(),
         ui_ref: cfg.ui.repo_ref.clone(),
         ui_manifest_url: cfg.ui.manifest_url.clone(),
         ui_entry: cfg.ui.entry.clone(),
         session_ws_url: cfg.api.public_ws_url.clone(),
+        session_ws_url_status: if cfg.api.public_ws_url.is_empty() {
+            String::new()
+        } else {
+            session_ws_url_status.to_string()
+        },
         allow_unsigned_debug_hello: cfg.api.allow_unsigned_debug_hello,
+        privacy_sources: privacy_sources.to_vec(),
         metrics: metrics.clone(),
+        announce_pk: announce_key.pubkey.clone(),
+        announce_cert: Some(announce_key.cert.clone()),
     };
     let content = serde_json::to_string(&payload)?;
-    let tags = vec![
+    let mut tags = vec![
         vec!["t".to_string(), "swarm_discovery".to_string()],
         vec!["type".to_string(), "device".to_string()],
-        vec!["role".to_string(), cfg.node_role.clone()],
+        vec!["role".to_string(), cfg.node_role.to_string()],
         vec!["service".to_string(), "nvr".to_string()],
-        vec!["cap".to_string(), "camera".to_string()],
-        vec![
-            "hello".to_string(),
-            if cfg.api.allow_unsigned_debug_hello {
-                "unsigned-debug".to_string()
-            } else {
-                "signed".to_string()
-            },
-        ],
     ];
-    let unsigned = nostr::build_unsigned_event(
-        &cfg.nostr_pubkey,
-        RECORD_KIND,
-        tags,
-        content,
-        util::now_unix_seconds(),
+    tags.extend(
+        capabilities
+            .iter()
+            .map(|capability| vec!["cap".to_string(), capability.clone()]),
     );
-    nostr::sign_event(&unsigned, &cfg.nostr_sk_hex)
+    tags.extend(
+        features
+            .iter()
+            .map(|feature| vec!["feature".to_string(), feature.clone()]),
+    );
+    tags.push(vec![
+        "hello".to_string(),
+        if cfg.api.allow_unsigned_debug_hello {
+            "unsigned-debug".to_string()
+        } else {
+            "signed".to_string()
+        },
+    ]);
+    announce_key.sign(RECORD_KIND, tags, content)
 }
 
-fn build_zone_presence(cfg: &Config, zone: &str) -> Result<NostrEvent> {
+fn presence_ttl_secs(cfg: &Config) -> u64 {
+    (announce_steady_secs(cfg) * 2).max(120)
+}
+
+fn build_zone_presence(cfg: &Config, announce_key: &AnnounceKey, zone: &str) -> Result<NostrEvent> {
     let payload = ZonePresencePayload {
         kind: "zone_presence".to_string(),
         zone: zone.to_string(),
         device_pk: cfg.nostr_pubkey.clone(),
         swarm: cfg.swarm.endpoint_hint.clone(),
-        role: cfg.node_role.clone(),
+        role: cfg.node_role.to_string(),
         service: "nvr".to_string(),
         service_version: cfg.service_version.clone(),
         ts: util::now_ms(),
-        ttl: 120,
+        ttl: presence_ttl_secs(cfg),
+        announce_pk: announce_key.pubkey.clone(),
+        announce_cert: announce_key.cert.clone(),
     };
 
     let content = serde_json::to_string(&payload)?;
@@ -470,15 +1433,7 @@ fn build_zone_presence(cfg: &Config, zone: &str) -> Result<NostrEvent> {
         vec!["t".to_string(), "constitute".to_string()],
         vec!["z".to_string(), zone.to_string()],
     ];
-
-    let unsigned = nostr::build_unsigned_event(
-        &cfg.nostr_pubkey,
-        APP_KIND,
-        tags,
-        content,
-        util::now_unix_seconds(),
-    );
-    nostr::sign_event(&unsigned, &cfg.nostr_sk_hex)
+    announce_key.sign(APP_KIND, tags, content)
 }
 
 fn build_pair_request_event(
@@ -524,6 +1479,177 @@ fn build_pair_request_event(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::media::dependencies::MediaDependencies;
+    use crate::self_check::Problem;
+
+    struct FlakySocket {
+        inner: UdpSocket,
+        failures: AtomicU64,
+    }
+
+    impl Datagrams for FlakySocket {
+        async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            }
+            self.inner.recv_from(buf).await
+        }
+
+        fn send_to(
+            &self,
+            buf: &[u8],
+            to: SocketAddr,
+        ) -> impl Future<Output = io::Result<usize>> + Send {
+            self.inner.send_to(buf, to)
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_loop_survives_transient_socket_errors() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-swarm-recv-test-{}.json",
+            std::process::id()
+        ));
+        let cfg = crate::config::Config::load_or_create(&path)
+            .expect("create temp config")
+            .0;
+        let _ = std::fs::remove_file(&path);
+        let node = FlakySocket {
+            inner: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            failures: AtomicU64::new(3),
+        };
+        let node_addr = node.inner.local_addr().unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let table = PeerTable::default();
+        let counters = Arc::new(TransportCounters::default());
+        let devices = Arc::new(Mutex::new(RecordStore::new(StoreLimits {
+            max_entries: 16,
+            max_per_pubkey: 4,
+        })));
+        let ctx = SwarmCtx {
+            peers: Arc::default(),
+            table: Arc::clone(&table),
+            devices,
+            clocks: PeerClocks::default(),
+            counters: Arc::clone(&counters),
+            peer_joined: Arc::default(),
+        };
+        let task = tokio::spawn(recv_loop(Arc::new(node), ctx, cfg));
+
+        let hello = UdpMessage::Hello {
+            v: PROTOCOL_VERSION,
+            node_id: "peer".to_string(),
+            device_pk: "peer-pk".to_string(),
+            zones: Vec::new(),
+            ts: util::now_ms(),
+        };
+        peer.send_to(&serde_json::to_vec(&hello).unwrap(), node_addr)
+            .await
+            .unwrap();
+        let mut buf = vec![0u8; 65_535];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), peer.recv_from(&mut buf))
+            .await
+            .expect("ack after the errors")
+            .unwrap();
+        let ack = serde_json::from_slice::<UdpMessage>(&buf[..len]).unwrap();
+        assert!(matches!(ack, UdpMessage::Ack { .. }));
+        assert_eq!(counters.recv_errors.load(Ordering::SeqCst), 3);
+        assert!(table.lock().await[&peer.local_addr().unwrap()].live());
+        assert!(!task.is_finished());
+        task.abort();
+
+        assert!(recoverable_recv_error(
+            &io::ErrorKind::PermissionDenied.into()
+        ));
+        assert!(!recoverable_recv_error(&io::Error::other("bad descriptor")));
+    }
+
+    #[test]
+    fn announce_interval_decays_to_steady_and_snaps_back_on_change() {
+        let mut cfg = Config::default_generated();
+        cfg.swarm.announce_fast_secs = 10;
+        cfg.swarm.announce_interval_secs = 120;
+        let mut now = Instant::now();
+        let mut cadence = AnnounceCadence::new(&cfg, now, 1_000);
+        assert_eq!(cadence.next_at(), now);
+
+        let mut intervals = Vec::new();
+        for _ in 0..15 {
+            now = cadence.next_at();
+            cadence.announced(now, 1_000, None);
+            intervals.push((cadence.next_at() - now).as_secs());
+        }
+        assert_eq!(
+            intervals,
+            [
+                10, 10, 20, 20, 20, 40, 40, 40, 80, 80, 80, 120, 120, 120, 120
+            ]
+        );
+        assert_eq!(cadence.status().last_reset, AnnounceReset::Start);
+
+        now += Duration::from_secs(30);
+        cadence.announced(now, 2_000, Some(AnnounceReset::NewPeer));
+        assert_eq!(cadence.next_at(), now + Duration::from_secs(10));
+        let status = cadence.status();
+        assert_eq!((status.interval_secs, status.unchanged), (10, 0));
+        assert_eq!(status.last_reset, AnnounceReset::NewPeer);
+        assert_eq!(status.last_reset_unix, 2_000);
+
+        // Out-of-range settings are pulled back inside the bounds.
+        cfg.swarm.announce_fast_secs = 1;
+        cfg.swarm.announce_interval_secs = 3_600;
+        let status = AnnounceCadence::new(&cfg, now, 0).status();
+        assert_eq!((status.fast_secs, status.steady_secs), (5, 300));
+        cfg.swarm.announce_fast_secs = 60;
+        cfg.swarm.announce_interval_secs = 30;
+        let status = AnnounceCadence::new(&cfg, now, 0).status();
+        assert_eq!((status.fast_secs, status.steady_secs), (30, 30));
+        assert_eq!(presence_ttl_secs(&cfg), 120);
+    }
+
+    #[tokio::test]
+    async fn only_peers_that_were_not_live_count_as_joining() {
+        let table = PeerTable::default();
+        let counters = TransportCounters::default();
+        let peer: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        assert!(heard_from(&table, peer).await);
+        assert!(!heard_from(&table, peer).await);
+        for _ in 0..DEMOTE_AFTER_SEND_FAILURES {
+            let refused = Err(io::ErrorKind::ConnectionRefused.into());
+            note_send(&table, &counters, peer, refused).await;
+        }
+        assert!(heard_from(&table, peer).await);
+    }
+
+    #[tokio::test]
+    async fn unreachable_peers_are_demoted_until_a_send_gets_through() {
+        let table = PeerTable::default();
+        let counters = TransportCounters::default();
+        let peer: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        table.lock().await.insert(peer, PeerState::heard());
+        for _ in 0..DEMOTE_AFTER_SEND_FAILURES {
+            assert!(table.lock().await[&peer].live());
+            let refused = Err(io::ErrorKind::ConnectionRefused.into());
+            note_send(&table, &counters, peer, refused).await;
+        }
+        assert!(!table.lock().await[&peer].live());
+        assert_eq!(counters.send_errors.load(Ordering::SeqCst), 3);
+
+        note_send(&table, &counters, peer, Ok(10)).await;
+        assert!(table.lock().await[&peer].live());
+
+        // Configured peers never heard from are tracked, but never confirmed.
+        let silent: SocketAddr = "127.0.0.1:10".parse().unwrap();
+        note_send(
+            &table,
+            &counters,
+            silent,
+            Err(io::ErrorKind::ConnectionRefused.into()),
+        )
+        .await;
+        assert!(!table.lock().await[&silent].live());
+    }
 
     #[test]
     fn pair_request_event_contains_required_tags_and_payload() {
@@ -566,4 +1692,305 @@ mod tests {
             Some("hash123")
         );
     }
+
+    #[test]
+    fn device_record_advertises_only_given_capabilities() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-swarm-caps-test-{}.json",
+            std::process::id()
+        ));
+        let cfg = crate::config::Config::load_or_create(&path)
+            .expect("create temp config")
+            .0;
+        let _ = std::fs::remove_file(&path);
+        let metrics = DeviceMetricsPayload {
+            uptime_sec: 1,
+            peers_known: 0,
+            peers_confirmed: 0,
+            cameras_total: 0,
+            cameras_enabled: 0,
+            cameras_privacy: 0,
+            health_v: HEALTH_VERSION,
+            health: SwarmHealth::Ok,
+            problems: Vec::new(),
+        };
+
+        let capabilities = vec!["camera".to_string(), "recording".to_string()];
+        let features = vec!["snapshots".to_string()];
+        let key = AnnounceKey::certify(&cfg).expect("announce key");
+        let ev = build_device_record(&cfg, &key, &metrics, &capabilities, &features, &[], "")
+            .expect("device record");
+        let caps = ev
+            .tags
+            .iter()
+            .filter(|t| t.first().map(String::as_str) == Some("cap"))
+            .filter_map(|t| t.get(1).cloned())
+            .collect::<Vec<_>>();
+        assert_eq!(caps, capabilities);
+
+        let payload: DeviceRecordPayload = serde_json::from_str(&ev.content).expect("json payload");
+        assert!(!payload.capabilities.iter().any(|cap| cap == "transcode"));
+        assert_eq!(payload.features, features);
+        assert!(ev.tags.iter().any(|t| t == &["feature", "snapshots"]));
+        assert_eq!(payload.ingest_protocols, vec!["rtsp".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn announcements_follow_cameras_added_after_start() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-swarm-live-test-{}.json",
+            std::process::id()
+        ));
+        let cfg = crate::config::Config::load_or_create(&path)
+            .expect("create temp config")
+            .0;
+        let _ = std::fs::remove_file(&path);
+        let dependencies = DependencyMonitor::from_report(MediaDependencies::default());
+        let sources = AnnounceSources {
+            recorder: RecorderManager::new(
+                dependencies.clone(),
+                crate::stats::StatsRegistry::default(),
+            ),
+            announce_key: AnnounceKey::certify(&cfg).expect("announce key"),
+            bindings: NetworkBindings::start(&cfg).expect("bindings"),
+            live_cfg: SharedConfig::new(cfg),
+            dependencies,
+            self_check: SelfCheck::default(),
+        };
+        let cameras = |messages: Vec<UdpMessage>| {
+            messages
+                .into_iter()
+                .find_map(|msg| match msg {
+                    UdpMessage::Record {
+                        record_type, event, ..
+                    } if record_type == "device" => Some(event),
+                    _ => None,
+                })
+                .map(|event| {
+                    let payload: DeviceRecordPayload =
+                        serde_json::from_str(&event.content).expect("json payload");
+                    (
+                        payload.metrics.cameras_total,
+                        payload.metrics.cameras_enabled,
+                    )
+                })
+                .expect("device record")
+        };
+
+        let (before, _, fingerprint) = announcements(&sources, 1, 0, 0).await;
+        assert_eq!(cameras(before), (0, 0));
+        // Uptime and peer counts change every time and are not a change worth announcing.
+        let (_, _, later) = announcements(&sources, 9, 3, 1).await;
+        assert_eq!(later, fingerprint);
+
+        sources
+            .live_cfg
+            .lock()
+            .await
+            .camera_devices
+            .push(crate::config::CameraDeviceConfig {
+                source_id: "porch".to_string(),
+                name: "Porch".to_string(),
+                onvif_host: "porch.local".to_string(),
+                onvif_port: 80,
+                rtsp_url: "rtsp://porch.local/stream".to_string(),
+                username: String::new(),
+                password: String::new(),
+                driver_id: String::new(),
+                vendor: String::new(),
+                model: String::new(),
+                mac_address: String::new(),
+                rtsp_port: 554,
+                ptz_capable: false,
+                enabled: true,
+                segment_secs: 10,
+                desired: Default::default(),
+                credentials: Default::default(),
+                privacy: false,
+                set_camera_time: false,
+                snapshot_interval_secs: 0,
+                zones: Vec::new(),
+                push: Default::default(),
+                source_type: Default::default(),
+                retention: Default::default(),
+            });
+        let (after, _, changed) = announcements(&sources, 2, 0, 0).await;
+        assert_eq!(cameras(after), (1, 1));
+        assert_ne!(changed, fingerprint);
+    }
+
+    #[tokio::test]
+    async fn health_follows_problems_and_reaches_peers() {
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-swarm-health-test-{}.json",
+            std::process::id()
+        ));
+        let mut cfg = crate::config::Config::load_or_create(&path)
+            .expect("create temp config")
+            .0;
+        let _ = std::fs::remove_file(&path);
+        cfg.gateway.host_gateway_pk = "gateway".to_string();
+        let checks = SelfCheck::default();
+        assert_eq!(
+            health_summary(&checks.view().await, &cfg),
+            (SwarmHealth::Ok, Vec::new())
+        );
+
+        let warning = crate::config::NotificationSeverity::Warning;
+        let stuck = |source_id: &str| {
+            Problem::new("recorder_stuck", warning, String::new()).with_source(source_id)
+        };
+        checks
+            .record(
+                vec![
+                    stuck("front"),
+                    stuck("back"),
+                    Problem::new("encryptor_backlog", warning, String::new()),
+                ],
+                1,
+            )
+            .await;
+        let (health, problems) = health_summary(&checks.view().await, &cfg);
+        assert_eq!(health, SwarmHealth::Degraded);
+        assert_eq!(problems, ["cameras_failing", "encryptor_backlog"]);
+        cfg.gateway.host_gateway_pk.clear();
+        let critical = crate::config::NotificationSeverity::Critical;
+        checks
+            .record(vec![Problem::new("disk_full", critical, String::new())], 2)
+            .await;
+        let (health, problems) = health_summary(&checks.view().await, &cfg);
+        assert_eq!(health, SwarmHealth::Failing);
+        assert_eq!(problems, ["disk_full", "unprovisioned"]);
+
+        let metrics = DeviceMetricsPayload {
+            uptime_sec: 5,
+            peers_known: 1,
+            peers_confirmed: 1,
+            cameras_total: 2,
+            cameras_enabled: 2,
+            cameras_privacy: 0,
+            health_v: HEALTH_VERSION,
+            health,
+            problems,
+        };
+        let key = AnnounceKey::certify(&cfg).expect("announce key");
+        let record = build_device_record(&cfg, &key, &metrics, &[], &[], &[], "unverified")
+            .expect("device record");
+        let devices = Arc::new(Mutex::new(RecordStore::new(StoreLimits {
+            max_entries: cfg.swarm.max_records,
+            max_per_pubkey: cfg.swarm.max_records_per_device,
+        })));
+        remember_device(&devices, &cfg, "zone-a", &record).await;
+        assert_eq!(
+            devices.lock().await.entry_count(),
+            0,
+            "own records are not peers"
+        );
+
+        let mut peer = cfg.clone();
+        peer.nostr_sk_hex = "11".repeat(32);
+        peer.nostr_pubkey = nostr::pubkey_from_sk_hex(&peer.nostr_sk_hex).expect("pubkey");
+        peer.swarm.announce_sk_hex = "22".repeat(32);
+        let peer_key = AnnounceKey::certify(&peer).expect("announce key");
+        let record = build_device_record(&peer, &peer_key, &metrics, &[], &[], &[], "unverified")
+            .expect("device record");
+        remember_device(&devices, &cfg, "zone-b", &record).await;
+        remember_device(&devices, &cfg, "zone-a", &record).await;
+        let guard = devices.lock().await;
+        let device = guard
+            .get(&peer.nostr_pubkey, DEVICE_SLOT, util::now_ms())
+            .expect("peer device");
+        assert_eq!(device.health, "failing");
+        assert_eq!(device.problems, ["disk_full", "unprovisioned"]);
+        assert_eq!(device.zones, ["zone-a", "zone-b"]);
+        assert_eq!(device.cameras_total, 2);
+    }
+
+    #[tokio::test]
+    async fn records_are_signed_by_an_announce_key_the_identity_certifies() {
+        let clock = crate::util::clock::TestClock::at_secs(1_700_000_000);
+        let mut cfg = crate::config::Config::default_generated();
+        cfg.nostr_sk_hex = "11".repeat(32);
+        cfg.nostr_pubkey = nostr::pubkey_from_sk_hex(&cfg.nostr_sk_hex).expect("pubkey");
+        cfg.swarm.announce_sk_hex = "22".repeat(32);
+        let key = AnnounceKey::certify(&cfg).expect("announce key");
+        let presence = build_zone_presence(&cfg, &key, "zone-a").expect("zone presence");
+        assert_eq!(presence.pubkey, key.pubkey);
+        assert_ne!(presence.pubkey, cfg.nostr_pubkey);
+        assert!(nostr::verify_event(&presence).expect("verify"));
+        let identity = |event: &NostrEvent, cert: Option<&NostrEvent>| {
+            record_identity(event, &cfg.nostr_pubkey, cert).map(|(device_pk, _)| device_pk)
+        };
+        assert_eq!(
+            identity(&presence, Some(&key.cert)),
+            Some(cfg.nostr_pubkey.clone())
+        );
+        assert_eq!(identity(&presence, None), None);
+
+        // A rotated announce key keeps the identity; a cert for another key does not carry over.
+        clock.advance(Duration::from_secs(60));
+        let previous = cfg.clone();
+        cfg.swarm.announce_sk_hex = "33".repeat(32);
+        let rotated = AnnounceKey::certify(&cfg).expect("announce key");
+        let presence = build_zone_presence(&cfg, &rotated, "zone-a").expect("zone presence");
+        assert_eq!(
+            identity(&presence, Some(&rotated.cert)),
+            Some(cfg.nostr_pubkey.clone())
+        );
+        assert_eq!(identity(&presence, Some(&key.cert)), None);
+
+        // A key certifying itself under someone else's identity is refused.
+        let mut forged = cfg.clone();
+        forged.nostr_sk_hex = "44".repeat(32);
+        let forged_key = AnnounceKey::certify(&forged).expect("announce key");
+        assert_eq!(identity(&presence, Some(&forged_key.cert)), None);
+        // Records signed by the identity itself, as older builds send them, still resolve.
+        let legacy = nostr::sign_event(
+            &nostr::build_unsigned_event(&cfg.nostr_pubkey, APP_KIND, Vec::new(), "{}".into(), 1),
+            &cfg.nostr_sk_hex,
+        )
+        .expect("sign");
+        assert_eq!(identity(&legacy, None), Some(cfg.nostr_pubkey.clone()));
+
+        // Once the rotated certificate has been seen, the key it replaced is refused.
+        let mut node = crate::config::Config::default_generated();
+        node.nostr_sk_hex = "55".repeat(32);
+        node.nostr_pubkey = nostr::pubkey_from_sk_hex(&node.nostr_sk_hex).expect("pubkey");
+        let devices: DeviceTable = Arc::new(Mutex::new(RecordStore::new(StoreLimits {
+            max_entries: node.swarm.max_records,
+            max_per_pubkey: node.swarm.max_records_per_device,
+        })));
+        let metrics = DeviceMetricsPayload {
+            uptime_sec: 5,
+            peers_known: 0,
+            peers_confirmed: 0,
+            cameras_total: 1,
+            cameras_enabled: 1,
+            cameras_privacy: 0,
+            health_v: HEALTH_VERSION,
+            health: SwarmHealth::Ok,
+            problems: Vec::new(),
+        };
+        let record = |cfg: &Config, key: &AnnounceKey, uptime_sec| {
+            let metrics = DeviceMetricsPayload {
+                uptime_sec,
+                ..metrics.clone()
+            };
+            build_device_record(cfg, key, &metrics, &[], &[], &[], "unverified")
+                .expect("device record")
+        };
+        let uptime = || async {
+            devices
+                .lock()
+                .await
+                .get(&cfg.nostr_pubkey, DEVICE_SLOT, util::now_ms())
+                .map(|device| device.uptime_sec)
+        };
+        remember_device(&devices, &node, "zone-a", &record(&previous, &key, 1)).await;
+        assert_eq!(uptime().await, Some(1));
+        remember_device(&devices, &node, "zone-a", &record(&cfg, &rotated, 2)).await;
+        assert_eq!(uptime().await, Some(2));
+        remember_device(&devices, &node, "zone-a", &record(&previous, &key, 3)).await;
+        assert_eq!(uptime().await, Some(2));
+    }
 }
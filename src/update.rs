@@ -1,12 +1,183 @@
-use crate::config::{Config, UpdateMode};
+use crate::config::{Config, UpdateConfig, UpdateMode};
+use crate::recording::{RecorderManager, SegmentClock};
+use crate::util::backoff::{Backoff, RetryState};
+use crate::util::{now_ms, now_unix_seconds};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::process::Command;
-use tokio::time::{Duration, interval};
-use tracing::{debug, info, warn};
+use tokio::sync::Notify;
+use tokio::time::{Duration, interval, sleep};
+use tracing::{debug, error, info, warn};
 
-pub fn spawn_update_poller(cfg: Config) {
+const RESTART_NEEDED_EXIT: i32 = 10;
+const ROLLOVER_WINDOW_MS: u64 = 2_000;
+const ROLLOVER_POLL_MS: u64 = 500;
+const RETRY_BACKOFF: Backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(3_600))
+    .with_multiplier(3.0)
+    .with_jitter(0.2)
+    .with_max_attempts(4);
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStatus {
+    pub state: &'static str,
+    pub last_check_at: u64,
+    pub last_result: &'static str,
+    pub last_error: String,
+    pub restart_pending: bool,
+    pub restart_pending_since: u64,
+    pub restart_deadline: u64,
+    pub restart_in_secs: u64,
+    pub retry: RetryState,
+}
+
+#[derive(Clone)]
+pub struct UpdateHandle {
+    inner: Arc<UpdateInner>,
+}
+
+struct UpdateInner {
+    running: bool,
+    status: Mutex<UpdateStatus>,
+    wake: Notify,
+    immediate: AtomicBool,
+}
+
+impl UpdateHandle {
+    fn new(running: bool) -> Self {
+        let status = UpdateStatus {
+            state: if running { "idle" } else { "disabled" },
+            ..UpdateStatus::default()
+        };
+        Self {
+            inner: Arc::new(UpdateInner {
+                running,
+                status: Mutex::new(status),
+                wake: Notify::new(),
+                immediate: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn status(&self) -> UpdateStatus {
+        let mut status = self.inner.status.lock().expect("update status").clone();
+        if status.restart_pending {
+            status.restart_in_secs = status.restart_deadline.saturating_sub(now_unix_seconds());
+        }
+        status
+    }
+
+    pub fn trigger(&self, immediate: bool) -> bool {
+        if !self.inner.running {
+            return false;
+        }
+        if immediate {
+            self.inner.immediate.store(true, Ordering::SeqCst);
+        }
+        self.inner.wake.notify_one();
+        true
+    }
+
+    fn set(&self, apply: impl FnOnce(&mut UpdateStatus)) {
+        apply(&mut self.inner.status.lock().expect("update status"));
+    }
+
+    async fn check(&self, update: &UpdateConfig) -> bool {
+        self.set(|status| status.state = "checking");
+        let mut cmd = release_script(update);
+        cmd.env("DEFER_RESTART", "1").arg("--try-restart");
+        let (result, last_error) = match cmd.status().await {
+            Ok(status) if status.code() == Some(RESTART_NEEDED_EXIT) => {
+                ("installed", String::new())
+            }
+            Ok(status) if status.success() => {
+                debug!("update poll executed successfully");
+                ("up_to_date", String::new())
+            }
+            Ok(status) => {
+                warn!(code = ?status.code(), "update poll script returned non-zero");
+                (
+                    "failed",
+                    format!("update script exited with code {:?}", status.code()),
+                )
+            }
+            Err(err) => {
+                warn!(error = %err, script = %update.script_path, "update poll script failed");
+                ("failed", err.to_string())
+            }
+        };
+        self.set(|status| {
+            status.state = "idle";
+            status.last_check_at = now_unix_seconds();
+            status.last_result = result;
+            match result {
+                "failed" => {
+                    status.retry.failed(&RETRY_BACKOFF, &last_error);
+                }
+                _ => status.retry.succeeded(),
+            }
+            status.last_error = last_error;
+        });
+        let installed = result == "installed";
+        if !installed {
+            self.inner.immediate.store(false, Ordering::SeqCst);
+        }
+        installed
+    }
+
+    async fn restart_at_boundary(&self, update: &UpdateConfig, recorder: &RecorderManager) {
+        let since = now_unix_seconds();
+        let deadline = since.saturating_add(update.restart_max_delay_secs);
+        self.set(|status| {
+            status.state = "restart_pending";
+            status.restart_pending = true;
+            status.restart_pending_since = since;
+            status.restart_deadline = deadline;
+        });
+        info!(
+            max_delay_secs = update.restart_max_delay_secs,
+            "update installed; restart waits for a segment boundary"
+        );
+        let reason = loop {
+            if self.inner.immediate.swap(false, Ordering::SeqCst) {
+                break "immediate";
+            }
+            if at_rollover(now_ms(), &recorder.segment_clocks().await) {
+                break "segment_boundary";
+            }
+            if now_unix_seconds() >= deadline {
+                break "max_delay";
+            }
+            tokio::select! {
+                _ = sleep(Duration::from_millis(ROLLOVER_POLL_MS)) => {}
+                _ = self.inner.wake.notified() => {}
+            }
+        };
+        info!(reason, "stopping recorders for the update restart");
+        self.set(|status| status.state = "restarting");
+        recorder.stop_all().await;
+
+        let mut cmd = release_script(update);
+        cmd.arg("--restart-only");
+        match cmd.status().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                error!(code = ?status.code(), "update restart failed; exiting for systemd");
+                std::process::exit(1);
+            }
+            Err(err) => {
+                error!(error = %err, "update restart failed; exiting for systemd");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+pub fn spawn_update_poller(cfg: Config, recorder: RecorderManager) -> UpdateHandle {
     if !cfg.update.enabled {
         info!("update poller disabled by config");
-        return;
+        return UpdateHandle::new(false);
     }
 
     if cfg.update.mode == UpdateMode::SourceBuild {
@@ -15,44 +186,77 @@ pub fn spawn_update_poller(cfg: Config) {
             source_dir = %cfg.update.source_dir,
             "skipping in-process source-build updater; rely on constitute-nvr-update.timer or an external operator"
         );
-        return;
+        return UpdateHandle::new(false);
     }
 
+    let updates = UpdateHandle::new(true);
+    let handle = updates.clone();
     tokio::spawn(async move {
-        let script = cfg.update.script_path.clone();
         let mut tick = interval(Duration::from_secs(cfg.update.interval_secs.max(60)));
         info!(
             interval_secs = cfg.update.interval_secs,
-            script = %script,
+            script = %cfg.update.script_path,
             "update poller started"
         );
 
         loop {
-            tick.tick().await;
-            let mut cmd = Command::new(&script);
-            cmd.arg("--mode").arg("release_artifact");
-            if !cfg.update.build_user.trim().is_empty() {
-                cmd.arg("--build-user").arg(&cfg.update.build_user);
-            }
-            cmd.arg("--source-dir")
-                .arg(&cfg.update.source_dir)
-                .arg("--branch")
-                .arg(&cfg.update.branch)
-                .arg("--service-name")
-                .arg("constitute-nvr")
-                .arg("--try-restart");
-
-            match cmd.status().await {
-                Ok(status) if status.success() => {
-                    debug!("update poll executed successfully");
-                }
-                Ok(status) => {
-                    warn!(code = ?status.code(), "update poll script returned non-zero");
-                }
-                Err(err) => {
-                    warn!(error = %err, script = %script, "update poll script failed");
-                }
+            let retry_in = handle.status().retry.remaining(now_ms());
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = sleep(retry_in.unwrap_or_default()), if retry_in.is_some() => {}
+                _ = handle.inner.wake.notified() => {}
+            }
+            if handle.check(&cfg.update).await {
+                handle.restart_at_boundary(&cfg.update, &recorder).await;
             }
         }
     });
+    updates
+}
+
+fn release_script(update: &UpdateConfig) -> Command {
+    let mut cmd = Command::new(&update.script_path);
+    cmd.arg("--mode").arg("release_artifact");
+    if !update.build_user.trim().is_empty() {
+        cmd.arg("--build-user").arg(&update.build_user);
+    }
+    cmd.arg("--source-dir")
+        .arg(&update.source_dir)
+        .arg("--branch")
+        .arg(&update.branch)
+        .arg("--service-name")
+        .arg("constitute-nvr");
+    cmd
+}
+
+fn at_rollover(now_ms: u64, clocks: &[SegmentClock]) -> bool {
+    clocks.iter().all(|clock| {
+        let len = clock.segment_secs.max(1).saturating_mul(1000);
+        let elapsed = now_ms.saturating_sub(clock.started_at);
+        len - elapsed % len <= ROLLOVER_WINDOW_MS
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollover_needs_every_recorder_near_its_boundary() {
+        let clock = |started_at, segment_secs| SegmentClock {
+            started_at,
+            segment_secs,
+        };
+        assert!(at_rollover(1_000_000, &[]));
+        assert!(at_rollover(1_059_000, &[clock(1_000_000, 60)]));
+        assert!(!at_rollover(1_030_000, &[clock(1_000_000, 60)]));
+        // A missed boundary is measured against the next one.
+        assert!(at_rollover(1_119_500, &[clock(1_000_000, 60)]));
+        let mixed = [clock(1_000_000, 60), clock(1_010_000, 60)];
+        assert!(!at_rollover(1_059_000, &mixed));
+        assert!(at_rollover(
+            1_069_000,
+            &[clock(1_000_000, 10), clock(1_010_000, 60)]
+        ));
+    }
 }
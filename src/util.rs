@@ -1,18 +1,7 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+pub mod backoff;
+pub mod clock;
 
-pub fn now_unix_seconds() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0)
-}
-
-pub fn now_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis() as u64)
-        .unwrap_or(0)
-}
+pub use clock::{now_ms, now_unix_seconds};
 
 pub fn sha256_b64url(input: &str) -> String {
     use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
@@ -23,3 +12,38 @@ pub fn sha256_b64url(input: &str) -> String {
     let digest = hasher.finalize();
     URL_SAFE_NO_PAD.encode(digest)
 }
+
+pub fn source_dir_name(source_id: &str) -> String {
+    source_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn normalize_source_id(source_id: &str) -> String {
+    source_id.trim().to_ascii_lowercase()
+}
+
+pub fn is_host_name_or_ip(host: &str) -> bool {
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return true;
+    }
+    let name = host.strip_suffix('.').unwrap_or(host);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
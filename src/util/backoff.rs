@@ -0,0 +1,206 @@
+use super::clock::now_ms;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::fmt::Display;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    base: Duration,
+    multiplier: f64,
+    cap: Duration,
+    jitter: f64,
+    max_attempts: Option<u64>,
+}
+
+impl Backoff {
+    pub const fn new(base: Duration, cap: Duration) -> Self {
+        Self {
+            base,
+            multiplier: 2.0,
+            cap,
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+
+    pub const fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub const fn with_jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction;
+        self
+    }
+
+    pub const fn with_max_attempts(mut self, attempts: u64) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    pub fn cap(&self) -> Duration {
+        self.cap
+    }
+
+    pub fn nominal(&self, attempt: u64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u64) as i32;
+        let secs = self.base.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        Duration::try_from_secs_f64(secs)
+            .unwrap_or(self.cap)
+            .min(self.cap)
+    }
+
+    pub fn delay(&self, attempt: u64, rng: &mut impl Rng) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        let nominal = self.nominal(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return Some(nominal);
+        }
+        Some(nominal.mul_f64(1.0 - jitter * rng.r#gen::<f64>()))
+    }
+
+    pub fn iter(&self) -> BackoffIter<StdRng> {
+        self.iter_with(StdRng::from_entropy())
+    }
+
+    pub fn iter_with<R: Rng>(&self, rng: R) -> BackoffIter<R> {
+        BackoffIter {
+            policy: *self,
+            attempt: 0,
+            rng,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BackoffIter<R> {
+    policy: Backoff,
+    attempt: u64,
+    rng: R,
+}
+
+impl<R: Rng> Iterator for BackoffIter<R> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        self.attempt = self.attempt.saturating_add(1);
+        self.policy.delay(self.attempt, &mut self.rng)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryState {
+    pub attempt: u64,
+    pub next_retry_at: u64,
+    pub last_error: String,
+}
+
+impl RetryState {
+    pub fn failed(&mut self, policy: &Backoff, error: impl Display) -> Option<Duration> {
+        self.failed_at(policy, error, now_ms(), &mut rand::thread_rng())
+    }
+
+    fn failed_at(
+        &mut self,
+        policy: &Backoff,
+        error: impl Display,
+        now: u64,
+        rng: &mut impl Rng,
+    ) -> Option<Duration> {
+        self.attempt = self.attempt.saturating_add(1);
+        self.last_error = error.to_string();
+        let delay = policy.delay(self.attempt, rng);
+        self.next_retry_at = delay.map_or(0, |delay| {
+            now.saturating_add(u64::try_from(delay.as_millis()).unwrap_or(u64::MAX))
+        });
+        delay
+    }
+
+    pub fn succeeded(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn remaining(&self, now: u64) -> Option<Duration> {
+        (self.next_retry_at > 0)
+            .then(|| Duration::from_millis(self.next_retry_at.saturating_sub(now)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn delays_grow_to_the_cap_and_stop_at_the_attempt_limit() {
+        let policy = Backoff::new(secs(1), secs(10));
+        let delays = policy
+            .iter_with(StdRng::seed_from_u64(1))
+            .take(6)
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10].map(secs));
+        assert_eq!(policy.nominal(0), secs(1));
+        assert_eq!(policy.nominal(u64::MAX), secs(10));
+
+        let policy = Backoff::new(secs(5), secs(100))
+            .with_multiplier(3.0)
+            .with_max_attempts(3);
+        let delays = policy
+            .iter_with(StdRng::seed_from_u64(1))
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [5, 15, 45].map(secs));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction_and_follows_the_seed() {
+        let policy = Backoff::new(secs(8), secs(8)).with_jitter(0.25);
+        let delays = policy
+            .iter_with(StdRng::seed_from_u64(0x5eed))
+            .take(64)
+            .collect::<Vec<_>>();
+        assert!(
+            delays
+                .iter()
+                .all(|delay| (secs(6)..=secs(8)).contains(delay))
+        );
+        assert!(delays.windows(2).any(|pair| pair[0] != pair[1]));
+        let again = policy
+            .iter_with(StdRng::seed_from_u64(0x5eed))
+            .take(64)
+            .collect::<Vec<_>>();
+        assert_eq!(delays, again);
+    }
+
+    #[test]
+    fn retry_state_schedules_the_next_try_and_resets_on_success() {
+        let policy = Backoff::new(secs(2), secs(30)).with_max_attempts(2);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut retry = RetryState::default();
+        assert_eq!(retry.remaining(1_000), None);
+
+        let delay = retry.failed_at(&policy, "refused", 1_000, &mut rng);
+        assert_eq!(delay, Some(secs(2)));
+        assert_eq!((retry.attempt, retry.next_retry_at), (1, 3_000));
+        assert_eq!(retry.last_error, "refused");
+        assert_eq!(retry.remaining(2_500), Some(Duration::from_millis(500)));
+        assert_eq!(retry.remaining(9_000), Some(Duration::ZERO));
+
+        retry.failed_at(&policy, "refused", 3_000, &mut rng);
+        assert_eq!(retry.next_retry_at, 7_000);
+        assert_eq!(retry.failed_at(&policy, "timed out", 7_000, &mut rng), None);
+        assert_eq!((retry.attempt, retry.next_retry_at), (3, 0));
+        assert_eq!(retry.last_error, "timed out");
+
+        retry.succeeded();
+        assert_eq!(retry, RetryState::default());
+    }
+}
@@ -0,0 +1,223 @@
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+pub const STAMP_FORMAT: &str = "%Y%m%dT%H%M%S";
+const BACKWARD_STEP_MS: u64 = 2_000;
+
+static LATEST_MS: AtomicU64 = AtomicU64::new(0);
+static BEFORE_EPOCH_LOGGED: AtomicBool = AtomicBool::new(false);
+static STEP_BACK_LOGGED: AtomicBool = AtomicBool::new(false);
+
+pub fn now_ms() -> u64 {
+    #[cfg(test)]
+    if let Some(pinned) = TestClock::pinned() {
+        return pinned;
+    }
+    let ms = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+        Err(err) => {
+            if !BEFORE_EPOCH_LOGGED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    behind_secs = err.duration().as_secs(),
+                    "system clock is before the unix epoch; timestamps read as 0"
+                );
+            }
+            return 0;
+        }
+    };
+    let latest = LATEST_MS.fetch_max(ms, Ordering::Relaxed);
+    if ms.saturating_add(BACKWARD_STEP_MS) < latest
+        && !STEP_BACK_LOGGED.swap(true, Ordering::Relaxed)
+    {
+        warn!(
+            step_ms = latest - ms,
+            "system clock stepped backwards; timestamps may repeat or go out of order"
+        );
+    }
+    ms
+}
+
+pub fn now_unix_seconds() -> u64 {
+    now_ms() / 1000
+}
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn rfc3339_utc(unix: u64) -> String {
+    DateTime::from_timestamp(i64::try_from(unix).unwrap_or(i64::MAX), 0)
+        .unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn format_stamp<Tz: TimeZone>(unix: u64, tz: &Tz) -> Option<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let at = DateTime::from_timestamp(i64::try_from(unix).ok()?, 0)?;
+    Some(at.with_timezone(tz).format(STAMP_FORMAT).to_string())
+}
+
+pub fn parse_stamp<Tz: TimeZone>(name: &str, tz: &Tz) -> Option<DateTime<Tz>> {
+    let stem = name.split('.').next()?;
+    // chrono takes fields with fewer digits than the layout writes; names never have them.
+    let shape = stem.bytes().enumerate().all(|(idx, byte)| match idx {
+        8 => byte == b'T',
+        _ => byte.is_ascii_digit(),
+    });
+    if stem.len() != 15 || !shape {
+        return None;
+    }
+    let naive = NaiveDateTime::parse_from_str(stem, STAMP_FORMAT).ok()?;
+    tz.from_local_datetime(&naive).earliest()
+}
+
+pub fn local_stamp(unix: u64) -> Option<String> {
+    format_stamp(unix, &chrono::Local)
+}
+
+pub fn parse_local_stamp(name: &str) -> Option<DateTime<chrono::Local>> {
+    parse_stamp(name, &chrono::Local)
+}
+
+pub fn local_stamp_unix(name: &str) -> Option<u64> {
+    u64::try_from(parse_local_stamp(name)?.timestamp()).ok()
+}
+
+#[cfg(test)]
+thread_local! {
+    static PINNED_MS: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(test)]
+pub struct TestClock(());
+
+#[cfg(test)]
+impl TestClock {
+    pub fn at_ms(ms: u64) -> Self {
+        PINNED_MS.with(|pinned| pinned.set(Some(ms)));
+        Self(())
+    }
+
+    pub fn at_secs(secs: u64) -> Self {
+        Self::at_ms(secs * 1000)
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        PINNED_MS.with(|pinned| {
+            let now = pinned.get().unwrap_or_default();
+            pinned.set(Some(now + by.as_millis() as u64));
+        });
+    }
+
+    fn pinned() -> Option<u64> {
+        PINNED_MS.with(std::cell::Cell::get)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestClock {
+    fn drop(&mut self) {
+        PINNED_MS.with(|pinned| pinned.set(None));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use std::time::Duration;
+
+    const ZONES: &[&str] = &[
+        "America/New_York",
+        "Europe/Berlin",
+        "Australia/Sydney",
+        "Asia/Kolkata",
+        "UTC",
+    ];
+
+    fn assert_round_trips(from: u64, secs: u64, step: usize) {
+        for zone in ZONES {
+            let tz: chrono_tz::Tz = zone.parse().unwrap();
+            for unix in (from..from + secs).step_by(step) {
+                let name = format!("{}.mp4", format_stamp(unix, &tz).unwrap());
+                let parsed = parse_stamp(&name, &tz).unwrap().timestamp() as u64;
+                assert!(
+                    parsed <= unix && unix - parsed <= 3600,
+                    "{zone} {unix} {name}"
+                );
+                if parsed != unix {
+                    assert_eq!(format_stamp(parsed, &tz).unwrap(), name[..15], "{zone}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn names_round_trip_across_dst_changes() {
+        // US and EU spring forward and fall back, and the southern-hemisphere changes.
+        for day_start in [
+            1_710_028_800, // 2024-03-10
+            1_711_843_200, // 2024-03-31
+            1_730_592_000, // 2024-11-03
+            1_729_987_200, // 2024-10-27
+            1_712_361_600, // 2024-04-06
+            1_728_086_400, // 2024-10-05
+        ] {
+            assert_round_trips(day_start, 86_400 * 2, 13);
+        }
+        let tz: chrono_tz::Tz = "America/New_York".parse().unwrap();
+        // 02:30 does not exist on the spring-forward day; 01:30 happens twice in the fall.
+        assert!(parse_stamp("20240310T023000.mp4", &tz).is_none());
+        let repeated = parse_stamp("20241103T013000.cnv", &tz).unwrap();
+        assert_eq!(repeated.timestamp(), 1_730_611_800);
+    }
+
+    #[test]
+    fn names_round_trip_across_year_rollovers() {
+        for new_year in [
+            1_704_067_200, // 2024-01-01, into a leap year
+            1_735_689_600, // 2025-01-01
+            4_102_444_800, // 2100-01-01, a century that is not a leap year
+        ] {
+            assert_round_trips(new_year - 86_400, 86_400 * 2, 13);
+        }
+    }
+
+    #[test]
+    fn random_instants_round_trip() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        for _ in 0..2_000 {
+            let unix = rng.gen_range(0..4_102_444_800u64);
+            assert_round_trips(unix, 1, 1);
+        }
+        assert!(parse_stamp("2024013T000000.mp4", &Utc).is_none());
+        assert!(parse_stamp("20240230T000000.mp4", &Utc).is_none());
+        assert!(parse_stamp("snapshot.cnv", &Utc).is_none());
+    }
+
+    #[test]
+    fn rfc3339_is_utc_to_the_second() {
+        assert_eq!(rfc3339_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339_utc(1_710_055_800), "2024-03-10T07:30:00Z");
+        assert_eq!(rfc3339_utc(4_107_542_400), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_clock_pins_and_advances_this_thread() {
+        {
+            let clock = TestClock::at_secs(1_000);
+            assert_eq!(now_ms(), 1_000_000);
+            clock.advance(Duration::from_millis(1_500));
+            assert_eq!(now_unix_seconds(), 1_001);
+            let elsewhere = std::thread::spawn(now_unix_seconds).join().unwrap();
+            assert!(elsewhere > 1_600_000_000);
+        }
+        assert!(now_unix_seconds() > 1_600_000_000);
+    }
+}
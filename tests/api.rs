@@ -0,0 +1,353 @@
+mod common;
+
+use common::NvrHarness;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::io::{Read, Write};
+use tokio_tungstenite::tungstenite::Message;
+
+const SOURCE_ID: &str = "range-cam";
+
+fn garbage_envelope(bytes: usize) -> String {
+    json!({ "type": "cipher", "nonce": "!", "data": "!".repeat(bytes) }).to_string()
+}
+
+#[tokio::test]
+async fn oversized_envelopes_are_refused_and_far_larger_frames_close_the_session() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let mut client = harness.connect().await.expect("session");
+
+    // Just past the 1 MiB default: read, answered with the limit, and the session goes on.
+    client
+        .send_raw(garbage_envelope(1024 * 1024))
+        .await
+        .expect("send envelope");
+    let refused = client.recv().await.expect("limit reply");
+    assert_eq!(refused["code"], json!("limit_exceeded"), "{refused}");
+    assert_eq!(refused["limit"], json!("envelope_bytes"), "{refused}");
+    assert_eq!(refused["max"], json!(1024 * 1024), "{refused}");
+    let listed = client
+        .request(&json!({ "cmd": "list_sources" }))
+        .await
+        .expect("list_sources");
+    assert_eq!(listed.get("ok").and_then(Value::as_bool), Some(true));
+
+    // Twice the limit is refused as it arrives. The server may drop the connection while
+    // the frame is still going out.
+    let _ = client.send_raw(garbage_envelope(2 * 1024 * 1024)).await;
+    // Closed or reset, depending on how much of the frame was still unread.
+    client.recv().await.expect_err("session should close");
+
+    // The node keeps serving new sessions.
+    let mut client = harness.connect().await.expect("second session");
+    let listed = client
+        .request(&json!({ "cmd": "list_sources" }))
+        .await
+        .expect("list_sources");
+    assert_eq!(listed.get("ok").and_then(Value::as_bool), Some(true));
+
+    let metrics = reqwest::get(harness.http_url("/metrics"))
+        .await
+        .expect("metrics")
+        .text()
+        .await
+        .expect("metrics body");
+    assert!(
+        metrics
+            .lines()
+            .any(|line| line == "constitute_nvr_oversized_envelopes_total 2"),
+        "{metrics}"
+    );
+}
+
+#[tokio::test]
+async fn oversized_hellos_are_refused_before_they_are_buffered() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let (mut socket, _) = tokio_tungstenite::connect_async(harness.session_url())
+        .await
+        .expect("connect");
+    let hello = json!({ "type": "hello", "proof": "!".repeat(1024 * 1024) });
+    let _ = socket.send(Message::Text(hello.to_string().into())).await;
+    // Whatever arrives, the socket ends without a session opening.
+    while let Some(Ok(frame)) = socket.next().await {
+        if let Message::Text(text) = frame {
+            assert!(!text.contains("hello_ack"), "{text}");
+        }
+    }
+
+    let metrics = reqwest::get(harness.http_url("/metrics"))
+        .await
+        .expect("metrics")
+        .text()
+        .await
+        .expect("metrics body");
+    assert!(
+        metrics.lines().any(|line| line
+            == "constitute_nvr_handshake_rejections_total{reason=\"oversized\"} 1"),
+        "{metrics}"
+    );
+}
+
+async fn segment_with_token(harness: &NvrHarness, media: &[u8]) -> String {
+    let mut client = harness.connect().await.expect("session");
+    let upserted = client
+        .request(&json!({
+            "cmd": "upsert_source",
+            "source": {
+                "sourceId": SOURCE_ID,
+                "name": "Range Camera",
+                "sourceType": "test",
+                "enabled": false,
+            },
+        }))
+        .await
+        .expect("upsert_source");
+    assert_eq!(upserted["ok"], json!(true), "{upserted}");
+    let day = harness
+        .storage_root()
+        .join("segments")
+        .join(SOURCE_ID)
+        .join("20240101");
+    std::fs::create_dir_all(&day).expect("create segment dir");
+    let sealed =
+        common::seal_chunked_segment(&harness.storage_key_hex, media).expect("seal segment");
+    std::fs::write(day.join("000000.cnv"), sealed).expect("write segment");
+    let minted = client
+        .request(&json!({
+            "cmd": "mint_token",
+            "sourceId": SOURCE_ID,
+            "ops": ["download"],
+            "ttlSecs": 600,
+        }))
+        .await
+        .expect("mint_token");
+    minted["token"].as_str().expect("token").to_string()
+}
+
+#[tokio::test]
+async fn token_downloads_serve_single_byte_ranges() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let media = (0..300 * 1024)
+        .map(|idx: usize| (idx * 13 % 251) as u8)
+        .collect::<Vec<_>>();
+    let token = segment_with_token(&harness, &media).await;
+    let url = harness.http_url(&format!("/download/{SOURCE_ID}/20240101T000000.cnv"));
+    let http = reqwest::Client::new();
+    let fetch = |range: Option<&str>| {
+        let mut request = http.get(&url).bearer_auth(&token);
+        if let Some(range) = range {
+            request = request.header("range", range);
+        }
+        async move { request.send().await.expect("download") }
+    };
+    let len = media.len();
+
+    let whole = fetch(None).await;
+    assert_eq!(whole.status(), 200);
+    assert_eq!(whole.content_length(), Some(len as u64));
+    assert_eq!(
+        whole.bytes().await.expect("body").as_ref(),
+        media.as_slice()
+    );
+    // The download went through the node-wide egress shaper.
+    let metrics = reqwest::get(harness.http_url("/metrics"))
+        .await
+        .expect("metrics")
+        .text()
+        .await
+        .expect("metrics body");
+    let egress = metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("constitute_nvr_egress_bytes_total "))
+        .and_then(|total| total.parse::<usize>().ok());
+    assert_eq!(egress, Some(len), "{metrics}");
+
+    // Across a chunk boundary, a suffix, and an open end.
+    for (range, from, to) in [
+        ("bytes=49000-100000", 49_000, 100_001),
+        ("bytes=-1000", len - 1000, len),
+        ("bytes=200000-", 200_000, len),
+    ] {
+        let part = fetch(Some(range)).await;
+        assert_eq!(part.status(), 206, "{range}");
+        let content_range = format!("bytes {from}-{}/{len}", to - 1);
+        assert_eq!(part.headers()["content-range"], content_range.as_str());
+        assert_eq!(part.content_length(), Some((to - from) as u64));
+        assert_eq!(part.bytes().await.expect("body").as_ref(), &media[from..to]);
+    }
+
+    let past_end = fetch(Some(&format!("bytes={len}-"))).await;
+    assert_eq!(past_end.status(), 416);
+    assert_eq!(
+        past_end.headers()["content-range"],
+        format!("bytes */{len}").as_str()
+    );
+
+    // Headers this server does not serve piecewise get the whole segment.
+    for ignored in ["bytes=0-1,5-9", "bytes=9-2", "lines=0-10", "bytes=abc"] {
+        let response = fetch(Some(ignored)).await;
+        assert_eq!(response.status(), 200, "{ignored}");
+        assert_eq!(response.bytes().await.expect("body").len(), len);
+    }
+}
+
+fn planted_share(harness: &NvrHarness, clip: &[u8]) -> String {
+    use base64::Engine;
+    use sha2::{Digest, Sha256};
+
+    let token = "5a".repeat(32);
+    let id = "0123abcd";
+    let dir = harness.storage_root().join("shares").join(id);
+    std::fs::create_dir_all(&dir).expect("create share dir");
+    let sealed = common::seal_chunked_segment(&harness.storage_key_hex, clip).expect("seal clip");
+    std::fs::write(dir.join("clip.cnv"), sealed).expect("write clip");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("clock")
+        .as_secs();
+    let share = json!({
+        "id": id,
+        "sourceId": SOURCE_ID,
+        "fromUnix": now - 60,
+        "toUnix": now,
+        "createdUnix": now,
+        "expiresUnix": now + 3600,
+        "maxDownloads": 1,
+        "downloads": 0,
+        "segments": 1,
+        "bytes": clip.len(),
+        "tokenHash": base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(token.as_bytes())),
+    });
+    std::fs::write(dir.join("share.json"), share.to_string()).expect("write share");
+    token
+}
+
+#[tokio::test]
+async fn used_up_shares_refuse_ranges_that_continue_no_counted_download() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let clip = (0..100 * 1024)
+        .map(|idx: usize| (idx * 7 % 251) as u8)
+        .collect::<Vec<_>>();
+    let token = planted_share(&harness, &clip);
+    let url = harness.http_url(&format!("/share/{token}"));
+    let guest = reqwest::Client::new();
+    // Another loopback address reaches the listener as a second guest.
+    let other = reqwest::Client::builder()
+        .local_address("127.0.0.2".parse::<std::net::IpAddr>().expect("address"))
+        .build()
+        .expect("client");
+    let fetch = |client: &reqwest::Client, range: &str| {
+        let request = client.get(&url).header("range", range);
+        async move { request.send().await.expect("share download") }
+    };
+
+    // The one download, and a range continuing it.
+    assert_eq!(fetch(&guest, "bytes=0-").await.status(), 206);
+    let continued = fetch(&guest, "bytes=1-").await;
+    assert_eq!(continued.status(), 206);
+    assert_eq!(continued.bytes().await.expect("body").as_ref(), &clip[1..]);
+
+    // Ranges that skip the first byte do not get around the limit.
+    for range in ["bytes=1-", "bytes=-1000"] {
+        assert_eq!(fetch(&other, range).await.status(), 410, "{range}");
+    }
+    assert_eq!(fetch(&guest, "bytes=0-").await.status(), 410);
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).expect("gzip");
+    encoder.finish().expect("gzip")
+}
+
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).expect("deflate");
+    encoder.finish().expect("deflate")
+}
+
+fn relabeled_config(harness: &NvrHarness, label: &str) -> Vec<u8> {
+    let raw = std::fs::read(harness.config_path()).expect("read config");
+    let mut config: Value = serde_json::from_slice(&raw).expect("config json");
+    config["device_label"] = json!(label);
+    serde_json::to_vec_pretty(&config).expect("config json")
+}
+
+fn stored_label(harness: &NvrHarness) -> Value {
+    let raw = std::fs::read(harness.config_path()).expect("read config");
+    serde_json::from_slice::<Value>(&raw).expect("config json")["device_label"].clone()
+}
+
+#[tokio::test]
+async fn admin_socket_takes_compressed_configs_within_the_decompressed_limit() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    type Encoder = fn(&[u8]) -> Vec<u8>;
+    for (encoding, encode) in [("gzip", gzip as Encoder), ("deflate", deflate)] {
+        let label = format!("{encoding} label");
+        let body = encode(&relabeled_config(&harness, &label));
+        let reply = harness
+            .admin_request(
+                "/replace_config",
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Content-Encoding", encoding),
+                ],
+                &body,
+            )
+            .await
+            .expect("replace_config");
+        assert_eq!(
+            reply.status,
+            200,
+            "{encoding}: {}",
+            String::from_utf8_lossy(&reply.body)
+        );
+        let reply: Value = serde_json::from_slice(&reply.body).expect("reply json");
+        assert_eq!(reply["ok"], json!(true), "{reply}");
+        assert!(
+            reply["changed"]
+                .as_array()
+                .is_some_and(|changed| changed.contains(&json!("device_label"))),
+            "{reply}"
+        );
+        assert_eq!(stored_label(&harness), json!(label));
+    }
+
+    // Well under the limit compressed, past it once inflated.
+    let mut padded = relabeled_config(&harness, "padded label");
+    padded.extend(std::iter::repeat_n(b' ', common::ADMIN_MAX_BODY_BYTES));
+    let body = gzip(&padded);
+    assert!(body.len() < common::ADMIN_MAX_BODY_BYTES / 10);
+    let reply = harness
+        .admin_request("/replace_config", &[("Content-Encoding", "gzip")], &body)
+        .await
+        .expect("replace_config");
+    assert_eq!(reply.status, 413);
+    assert_eq!(stored_label(&harness), json!("deflate label"));
+}
+
+#[tokio::test]
+async fn admin_socket_compresses_replies_for_clients_that_accept_it() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let body = relabeled_config(&harness, "compressed reply");
+    let reply = harness
+        .admin_request("/replace_config", &[("Accept-Encoding", "gzip")], &body)
+        .await
+        .expect("replace_config");
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.header("content-encoding"), Some("gzip"));
+    let mut json = String::new();
+    flate2::read::GzDecoder::new(reply.body.as_slice())
+        .read_to_string(&mut json)
+        .expect("gunzip reply");
+    let reply: Value = serde_json::from_str(&json).expect("reply json");
+    assert_eq!(reply["ok"], json!(true), "{reply}");
+
+    let plain = harness
+        .admin_request("/replace_config", &[], &body)
+        .await
+        .expect("replace_config");
+    assert_eq!(plain.status, 200);
+    assert_eq!(plain.header("content-encoding"), None);
+}
@@ -0,0 +1,620 @@
+#![allow(dead_code)]
+
+#[path = "../../src/crypto.rs"]
+pub mod crypto;
+
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use rand::RngCore;
+use serde_json::{Value, json};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+pub const IDENTITY_ID: &str = "harness-identity";
+pub const DEVICE_PK: &str = "harness-device";
+pub const ADMIN_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+pub fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(label: &str) -> Self {
+        let mut suffix = [0u8; 6];
+        rand::thread_rng().fill_bytes(&mut suffix);
+        let path = std::env::temp_dir().join(format!(
+            "constitute-nvr-{label}-{}-{}",
+            std::process::id(),
+            hex::encode(suffix)
+        ));
+        std::fs::create_dir_all(&path).expect("create temp dir");
+        Self { path }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+pub struct NvrHarness {
+    pub dir: TempDir,
+    pub api_port: u16,
+    pub identity_secret_hex: String,
+    pub storage_key_hex: String,
+    child: Child,
+}
+
+impl NvrHarness {
+    pub async fn start() -> Result<Self> {
+        let dir = TempDir::new("harness");
+        let api_port = free_tcp_port()?;
+        let identity_secret_hex = random_hex(32);
+        let storage_key_hex = random_hex(32);
+        let config_path = dir.path.join("config.json");
+        let config = json!({
+            "node_id": "nvr-harness",
+            "service_version": "harness",
+            "nostr_pubkey": "",
+            "nostr_sk_hex": "",
+            "swarm": {
+                "bind": "127.0.0.1:0",
+                "zones": [{ "key": "harness-zone", "name": "Harness Zone" }],
+            },
+            "api": {
+                "bind": format!("127.0.0.1:{api_port}"),
+                "identity_id": IDENTITY_ID,
+                "identity_secret_hex": identity_secret_hex,
+                "server_secret_hex": random_hex(32),
+                "admin_socket_path": dir.path.join("admin.sock").to_string_lossy(),
+                "admin_max_body_bytes": ADMIN_MAX_BODY_BYTES,
+            },
+            "storage": {
+                "root": dir.path.join("storage").to_string_lossy(),
+                "encryption_key_hex": storage_key_hex,
+                "encrypt_interval_secs": 2,
+            },
+            "update": { "enabled": false },
+            "camera_devices": [],
+        });
+        std::fs::write(&config_path, serde_json::to_vec_pretty(&config)?)?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_constitute-nvr"))
+            .arg("--config")
+            .arg(&config_path)
+            .arg("--log-level")
+            .arg("warn")
+            .env(
+                "CONSTITUTE_NVR_HOSTED_SERVICE_MANIFEST",
+                dir.path.join("hosted-service.json"),
+            )
+            .env(
+                "CONSTITUTE_NVR_LOG_OUTBOX",
+                dir.path.join("log-events.jsonl"),
+            )
+            .env_remove("CONSTITUTE_LOGGING_URL")
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .context("spawn constitute-nvr")?;
+
+        let harness = Self {
+            dir,
+            api_port,
+            identity_secret_hex,
+            storage_key_hex,
+            child,
+        };
+        harness.wait_ready(Duration::from_secs(20)).await?;
+        Ok(harness)
+    }
+
+    pub fn session_url(&self) -> String {
+        format!("ws://127.0.0.1:{}/session", self.api_port)
+    }
+
+    pub fn http_url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{path}", self.api_port)
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.dir.path.join("config.json")
+    }
+
+    pub async fn admin_request(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<AdminReply> {
+        let socket = self.dir.path.join("admin.sock");
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let mut stream = loop {
+            match UnixStream::connect(&socket).await {
+                Ok(stream) => break stream,
+                Err(_) if tokio::time::Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                Err(err) => return Err(err).context("connect admin socket"),
+            }
+        };
+        let mut request = format!(
+            "POST {path} HTTP/1.0\r\nHost: localhost\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(body).await?;
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).await?;
+        let split = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| anyhow!("admin reply has no header end"))?;
+        let head = std::str::from_utf8(&raw[..split])?;
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .ok_or_else(|| anyhow!("admin reply has no status line"))?
+            .parse()?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        Ok(AdminReply {
+            status,
+            headers,
+            body: raw[split + 4..].to_vec(),
+        })
+    }
+
+    pub fn storage_root(&self) -> PathBuf {
+        self.dir.path.join("storage")
+    }
+
+    pub fn peak_rss_kib(&self) -> Option<u64> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.child.id())).ok()?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    pub async fn connect(&self) -> Result<SessionClient> {
+        SessionClient::connect(&self.session_url(), IDENTITY_ID, &self.identity_secret_hex).await
+    }
+
+    async fn wait_ready(&self, limit: Duration) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + limit;
+        while tokio::time::Instant::now() < deadline {
+            if TcpStream::connect(("127.0.0.1", self.api_port))
+                .await
+                .is_ok()
+            {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Err(anyhow!("api listener did not become ready"))
+    }
+}
+
+pub struct AdminReply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl AdminReply {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Drop for NvrHarness {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct SessionClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    pub session_id: String,
+    key: Zeroizing<Vec<u8>>,
+}
+
+impl SessionClient {
+    pub async fn connect(url: &str, identity_id: &str, identity_secret_hex: &str) -> Result<Self> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .context("connect session websocket")?;
+
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let client_pub = PublicKey::from(&StaticSecret::from(secret));
+        let client_key = base64::engine::general_purpose::STANDARD.encode(client_pub.as_bytes());
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let proof = crypto::compute_hello_proof(
+            identity_secret_hex,
+            identity_id,
+            DEVICE_PK,
+            &client_key,
+            ts,
+        )?;
+        let hello = json!({
+            "type": "hello",
+            "identityId": identity_id,
+            "devicePk": DEVICE_PK,
+            "clientKey": client_key,
+            "ts": ts,
+            "proof": proof,
+            "protocolVersion": 2,
+        });
+        socket.send(Message::Text(hello.to_string().into())).await?;
+
+        let ack = next_text(&mut socket).await?;
+        if ack.get("type").and_then(Value::as_str) != Some("hello_ack") {
+            return Err(anyhow!("unexpected hello response: {ack}"));
+        }
+        let session_id = ack
+            .get("sessionId")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("hello_ack missing sessionId"))?
+            .to_string();
+        let server_key = ack
+            .get("serverKey")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("hello_ack missing serverKey"))?;
+
+        // X25519 is symmetric, so the service derivation doubles as the client derivation.
+        let context = format!("constitute-nvr:{identity_id}:{session_id}");
+        let (key, _) = crypto::derive_session_key(
+            &hex::encode(secret),
+            identity_secret_hex,
+            server_key,
+            &context,
+        )?;
+
+        Ok(Self {
+            socket,
+            session_id,
+            key,
+        })
+    }
+
+    pub async fn send(&mut self, command: &Value) -> Result<()> {
+        let plain = serde_json::to_vec(command)?;
+        let nonce = crypto::random_nonce_24();
+        let cipher = crypto::encrypt_payload(&self.key, &nonce, &plain)?;
+        let frame = json!({
+            "type": "cipher",
+            "nonce": base64::engine::general_purpose::STANDARD.encode(nonce),
+            "data": base64::engine::general_purpose::STANDARD.encode(cipher),
+        });
+        self.socket
+            .send(Message::Text(frame.to_string().into()))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn send_raw(&mut self, text: String) -> Result<()> {
+        self.socket.send(Message::Text(text.into())).await?;
+        Ok(())
+    }
+
+    pub async fn recv(&mut self) -> Result<Value> {
+        let frame = next_text(&mut self.socket).await?;
+        self.open_envelope(&frame)
+    }
+
+    fn open_envelope(&self, frame: &Value) -> Result<Value> {
+        let nonce = base64::engine::general_purpose::STANDARD.decode(
+            frame
+                .get("nonce")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("cipher frame missing nonce"))?,
+        )?;
+        let nonce: [u8; 24] = nonce
+            .try_into()
+            .map_err(|_| anyhow!("cipher frame nonce length"))?;
+        let data = base64::engine::general_purpose::STANDARD.decode(
+            frame
+                .get("data")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("cipher frame missing data"))?,
+        )?;
+        let plain = crypto::decrypt_payload(&self.key, &nonce, &data)?;
+        Ok(serde_json::from_slice(&plain)?)
+    }
+
+    pub async fn recv_within(&mut self, wait: Duration) -> Result<Option<Value>> {
+        match tokio::time::timeout(wait, self.recv()).await {
+            Ok(reply) => reply.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub async fn request(&mut self, command: &Value) -> Result<Value> {
+        self.send(command).await?;
+        self.recv().await
+    }
+
+    pub async fn fetch_segment(&mut self, source_id: &str, name: &str) -> Result<Vec<u8>> {
+        self.fetch_segment_as(source_id, name, false).await
+    }
+
+    pub async fn fetch_segment_as(
+        &mut self,
+        source_id: &str,
+        name: &str,
+        binary: bool,
+    ) -> Result<Vec<u8>> {
+        self.send(&json!({
+            "cmd": "get_segment",
+            "sourceId": source_id,
+            "name": name,
+            "binary": binary,
+        }))
+        .await?;
+        let start = self.recv().await?;
+        if start.get("cmd").and_then(Value::as_str) != Some("segment_start") {
+            return Err(anyhow!("unexpected get_segment response: {start}"));
+        }
+        let expected = start.get("bytes").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let mut out = Vec::with_capacity(expected);
+        let mut next_seq = 0u32;
+        loop {
+            let frame = match self.recv_frame().await? {
+                SessionFrame::Binary(frame) if binary => {
+                    let (seq, chunk) = self.open_binary_chunk(&frame)?;
+                    if seq != next_seq {
+                        return Err(anyhow!("chunk {seq} arrived, expected {next_seq}"));
+                    }
+                    next_seq += 1;
+                    out.extend_from_slice(&chunk);
+                    continue;
+                }
+                SessionFrame::Binary(_) => return Err(anyhow!("binary frame on a JSON download")),
+                SessionFrame::Json(frame) => frame,
+            };
+            match frame.get("cmd").and_then(Value::as_str) {
+                Some("segment_chunk") if !binary => {
+                    let chunk = base64::engine::general_purpose::STANDARD.decode(
+                        frame
+                            .get("data")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default(),
+                    )?;
+                    out.extend_from_slice(&chunk);
+                }
+                Some("segment_end") => break,
+                _ => return Err(anyhow!("unexpected segment frame: {frame}")),
+            }
+        }
+        if out.len() != expected {
+            return Err(anyhow!(
+                "segment length mismatch: expected {expected}, got {}",
+                out.len()
+            ));
+        }
+        Ok(out)
+    }
+
+    async fn recv_frame(&mut self) -> Result<SessionFrame> {
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(30), self.socket.next())
+                .await
+                .context("timed out waiting for session frame")?
+                .ok_or_else(|| anyhow!("session closed"))??;
+            match frame {
+                Message::Binary(data) => return Ok(SessionFrame::Binary(data.to_vec())),
+                Message::Text(text) => {
+                    let envelope: Value = serde_json::from_str(text.as_str())?;
+                    return Ok(SessionFrame::Json(self.open_envelope(&envelope)?));
+                }
+                Message::Close(_) => return Err(anyhow!("session closed")),
+                _ => continue,
+            }
+        }
+    }
+
+    fn open_binary_chunk(&self, frame: &[u8]) -> Result<(u32, Vec<u8>)> {
+        if frame.len() < 5 + 24 || frame[0] != 0x01 {
+            return Err(anyhow!("malformed binary chunk frame"));
+        }
+        let (header, rest) = frame.split_at(5);
+        let seq = u32::from_be_bytes(header[1..].try_into()?);
+        let (nonce, cipher) = rest.split_at(24);
+        let nonce: [u8; 24] = nonce.try_into()?;
+        let chunk = crypto::decrypt_payload_with_aad(&self.key, &nonce, header, cipher)?;
+        Ok((seq, chunk))
+    }
+}
+
+enum SessionFrame {
+    Json(Value),
+    Binary(Vec<u8>),
+}
+
+async fn next_text(socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> Result<Value> {
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(30), socket.next())
+            .await
+            .context("timed out waiting for session frame")?
+            .ok_or_else(|| anyhow!("session closed"))??;
+        match frame {
+            Message::Text(text) => return Ok(serde_json::from_str(text.as_str())?),
+            Message::Close(_) => return Err(anyhow!("session closed")),
+            _ => continue,
+        }
+    }
+}
+
+const SEALED_CHUNK_BYTES: usize = 48 * 1024;
+const TAG: usize = 16;
+
+fn chunk_nonce(base: &[u8], counter: u64) -> [u8; 24] {
+    let mut nonce: [u8; 24] = base.try_into().expect("24-byte base nonce");
+    for (byte, counter) in nonce[16..].iter_mut().zip(counter.to_be_bytes()) {
+        *byte ^= counter;
+    }
+    nonce
+}
+
+pub fn seal_chunked_segment(key_hex: &str, plain: &[u8]) -> Result<Vec<u8>> {
+    let key = crypto::parse_hex_exact(key_hex, 32)?;
+    let base = crypto::random_nonce_24();
+    let header = crypto::encrypt_payload(&key, &chunk_nonce(&base, 0), &[0, 0])?;
+    let mut out = b"CNRC2".to_vec();
+    out.extend_from_slice(&base);
+    out.extend_from_slice(&(header.len() as u16).to_be_bytes());
+    out.extend_from_slice(&header);
+    let count = plain.len().div_ceil(SEALED_CHUNK_BYTES).max(1);
+    for index in 0..count {
+        let piece =
+            &plain[index * SEALED_CHUNK_BYTES..plain.len().min((index + 1) * SEALED_CHUNK_BYTES)];
+        let counter = (index as u64 + 1) | if index + 1 == count { 1 << 63 } else { 0 };
+        out.extend_from_slice(&crypto::encrypt_payload(
+            &key,
+            &chunk_nonce(&base, counter),
+            piece,
+        )?);
+    }
+    Ok(out)
+}
+
+pub fn decrypt_segment_file(key_hex: &str, path: &Path) -> Result<Vec<u8>> {
+    const MAGIC: &[u8] = b"CNRV1";
+    let key = crypto::parse_hex_exact(key_hex, 32)?;
+    let blob = std::fs::read(path)?;
+    if blob.starts_with(b"CNRV2") {
+        return open_chunked_v2(&key, &blob);
+    }
+    if blob.starts_with(b"CNRC2") {
+        return open_chunked(&key, &blob);
+    }
+    if blob.len() < MAGIC.len() + 24 || &blob[..MAGIC.len()] != MAGIC {
+        return Err(anyhow!(
+            "not a CNRV1, CNRC2 or CNRV2 blob: {}",
+            path.display()
+        ));
+    }
+    let nonce: [u8; 24] = blob[MAGIC.len()..MAGIC.len() + 24]
+        .try_into()
+        .map_err(|_| anyhow!("nonce decode"))?;
+    Ok(crypto::decrypt_payload(
+        &key,
+        &nonce,
+        &blob[MAGIC.len() + 24..],
+    )?)
+}
+
+fn open_chunked_v2(key: &[u8], blob: &[u8]) -> Result<Vec<u8>> {
+    const PREFIX: usize = 5 + 8 + 4 + 16 + 2;
+    let aad = blob
+        .get(..PREFIX)
+        .ok_or_else(|| anyhow!("CNRV2 blob too short"))?;
+    let chunk_bytes = u32::from_be_bytes(aad[13..17].try_into()?) as usize;
+    let header_len = u16::from_be_bytes([aad[33], aad[34]]) as usize;
+    let body = blob
+        .get(PREFIX + header_len..)
+        .ok_or_else(|| anyhow!("CNRV2 header truncated"))?;
+    let nonce = |counter: u64| {
+        let mut nonce = [0u8; 24];
+        nonce[..16].copy_from_slice(&aad[17..33]);
+        nonce[16..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    };
+    let pieces = body.chunks(chunk_bytes + TAG).collect::<Vec<_>>();
+    let mut out = Vec::new();
+    for (index, piece) in pieces.iter().enumerate() {
+        let last = if index + 1 == pieces.len() {
+            1 << 63
+        } else {
+            0
+        };
+        out.extend(crypto::decrypt_payload_with_aad(
+            key,
+            &nonce((index as u64 + 1) | last),
+            aad,
+            piece,
+        )?);
+    }
+    Ok(out)
+}
+
+fn open_chunked(key: &[u8], blob: &[u8]) -> Result<Vec<u8>> {
+    let base = blob
+        .get(5..29)
+        .ok_or_else(|| anyhow!("CNRC2 blob too short"))?;
+    let header_len = u16::from_be_bytes([blob[29], blob[30]]) as usize;
+    let body = blob
+        .get(31 + header_len..)
+        .ok_or_else(|| anyhow!("CNRC2 header truncated"))?;
+    let pieces = body.chunks(SEALED_CHUNK_BYTES + TAG).collect::<Vec<_>>();
+    let mut out = Vec::new();
+    for (index, piece) in pieces.iter().enumerate() {
+        let counter = (index as u64 + 1)
+            | if index + 1 == pieces.len() {
+                1 << 63
+            } else {
+                0
+            };
+        out.extend(crypto::decrypt_payload(
+            key,
+            &chunk_nonce(base, counter),
+            piece,
+        )?);
+    }
+    Ok(out)
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(bytes))
+}
+
+fn free_tcp_port() -> Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
@@ -0,0 +1,254 @@
+mod common;
+
+use base64::Engine;
+use common::NvrHarness;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+const SOURCE_ID: &str = "test-cam";
+
+#[tokio::test]
+async fn recorder_to_encrypted_segment_roundtrip() {
+    if !common::ffmpeg_available() {
+        eprintln!("skipping pipeline harness: ffmpeg not found in PATH");
+        return;
+    }
+
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let mut client = harness.connect().await.expect("session");
+
+    let upserted = client
+        .request(&json!({
+            "cmd": "upsert_source",
+            "source": {
+                "sourceId": SOURCE_ID,
+                "name": "Test Pattern",
+                "sourceType": "test",
+                "segmentSecs": 2,
+            },
+        }))
+        .await
+        .expect("upsert_source");
+    assert_eq!(upserted.get("ok").and_then(Value::as_bool), Some(true));
+
+    let segments = wait_for_encrypted_segments(&mut client, 2, Duration::from_secs(60)).await;
+    let name = segments[0]
+        .get("name")
+        .and_then(Value::as_str)
+        .expect("segment name")
+        .to_string();
+
+    let fetched = client
+        .fetch_segment(SOURCE_ID, &name)
+        .await
+        .expect("get_segment");
+    // Segments are stored as `<YYYYMMDD>/<HHMMSS>.cnv` but addressed as `<YYYYMMDD>T<HHMMSS>.cnv`.
+    let (day, file) = name.split_once('T').expect("timestamp segment name");
+    let on_disk = common::decrypt_segment_file(
+        &harness.storage_key_hex,
+        &harness
+            .storage_root()
+            .join("segments")
+            .join(SOURCE_ID)
+            .join(day)
+            .join(file),
+    )
+    .expect("decrypt on-disk segment");
+    assert!(!fetched.is_empty());
+    assert_eq!(common::sha256_hex(&fetched), common::sha256_hex(&on_disk));
+}
+
+#[tokio::test]
+async fn large_segment_downloads_without_holding_it_whole() {
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let media = (0..12 * 1024 * 1024)
+        .map(|idx: usize| (idx * 31 % 251) as u8)
+        .collect::<Vec<_>>();
+    let day = harness
+        .storage_root()
+        .join("segments")
+        .join(SOURCE_ID)
+        .join("20240101");
+    std::fs::create_dir_all(&day).expect("create segment dir");
+    let sealed =
+        common::seal_chunked_segment(&harness.storage_key_hex, &media).expect("seal segment");
+    std::fs::write(day.join("000000.cnv"), sealed).expect("write segment");
+    let mut client = harness.connect().await.expect("session");
+    let before = harness.peak_rss_kib();
+
+    let fetched = client
+        .fetch_segment(SOURCE_ID, "20240101T000000.cnv")
+        .await
+        .expect("get_segment");
+    assert_eq!(common::sha256_hex(&fetched), common::sha256_hex(&media));
+    let binary = client
+        .fetch_segment_as(SOURCE_ID, "20240101T000000.cnv", true)
+        .await
+        .expect("get_segment with binary chunks");
+    assert_eq!(common::sha256_hex(&binary), common::sha256_hex(&media));
+    // Reading the segment whole would take its sealed and plain copies, 24 MiB, at once.
+    if let (Some(before), Some(after)) = (before, harness.peak_rss_kib()) {
+        assert!(
+            after - before < 8 * 1024,
+            "peak RSS grew by {} KiB during the download",
+            after - before
+        );
+    }
+}
+
+#[tokio::test]
+async fn windowed_downloads_wait_for_acks_and_resume_after_a_disconnect() {
+    const CHUNK: usize = 48 * 1024;
+    let harness = NvrHarness::start().await.expect("start nvr");
+    let media = (0..6 * CHUNK + 100)
+        .map(|idx: usize| (idx * 7 % 253) as u8)
+        .collect::<Vec<_>>();
+    let day = harness
+        .storage_root()
+        .join("segments")
+        .join(SOURCE_ID)
+        .join("20240101");
+    std::fs::create_dir_all(&day).expect("create segment dir");
+    let sealed =
+        common::seal_chunked_segment(&harness.storage_key_hex, &media).expect("seal segment");
+    std::fs::write(day.join("000000.cnv"), sealed).expect("write segment");
+    let name = "20240101T000000.cnv";
+    let chunk = |frame: &Value, seq: u64| {
+        assert_eq!(frame["cmd"], "segment_chunk", "{frame}");
+        assert_eq!(frame["seq"], seq);
+        base64::engine::general_purpose::STANDARD
+            .decode(frame["data"].as_str().unwrap())
+            .unwrap()
+    };
+
+    // A client that stalls after its first window gets nothing more until it acks, and
+    // its other commands wait for the transfer.
+    let mut client = harness.connect().await.expect("session");
+    client
+        .send(&json!({ "cmd": "get_segment", "sourceId": SOURCE_ID, "name": name, "window": 2 }))
+        .await
+        .expect("get_segment");
+    let start = client.recv().await.expect("segment_start");
+    assert_eq!(start["window"], 2);
+    let mut fetched = Vec::new();
+    for seq in 0..2 {
+        fetched.extend(chunk(&client.recv().await.expect("chunk"), seq));
+    }
+    client
+        .send(&json!({ "cmd": "get_permissions" }))
+        .await
+        .expect("get_permissions");
+    let stalled = client
+        .recv_within(Duration::from_secs(1))
+        .await
+        .expect("stalled session");
+    assert!(stalled.is_none(), "sent past the window: {stalled:?}");
+    for (ack, seqs) in [(1, 2..4), (3, 4..6), (5, 6..7)] {
+        client
+            .send(&json!({ "cmd": "segment_ack", "seq": ack }))
+            .await
+            .expect("segment_ack");
+        for seq in seqs {
+            fetched.extend(chunk(&client.recv().await.expect("chunk"), seq));
+        }
+    }
+    let pending = client
+        .recv_within(Duration::from_millis(500))
+        .await
+        .expect("waiting for the last ack");
+    assert!(pending.is_none(), "ended before the last ack: {pending:?}");
+    client
+        .send(&json!({ "cmd": "segment_ack", "seq": 6 }))
+        .await
+        .expect("segment_ack");
+    assert_eq!(
+        client.recv().await.expect("segment_end")["cmd"],
+        "segment_end"
+    );
+    assert_eq!(
+        client.recv().await.expect("deferred")["cmd"],
+        "get_permissions"
+    );
+    assert_eq!(fetched, media);
+
+    // Cut off after three chunks, a new session picks up from the fourth.
+    let mut client = harness.connect().await.expect("session");
+    client
+        .send(&json!({ "cmd": "get_segment", "sourceId": SOURCE_ID, "name": name, "window": 4 }))
+        .await
+        .expect("get_segment");
+    client.recv().await.expect("segment_start");
+    let mut fetched = Vec::new();
+    for seq in 0..3 {
+        fetched.extend(chunk(&client.recv().await.expect("chunk"), seq));
+    }
+    drop(client);
+    let mut client = harness.connect().await.expect("session");
+    client
+        .send(&json!({
+            "cmd": "get_segment",
+            "sourceId": SOURCE_ID,
+            "name": name,
+            "resumeFromSeq": 3,
+        }))
+        .await
+        .expect("get_segment");
+    let start = client.recv().await.expect("segment_start");
+    assert_eq!(start["bytes"], media.len());
+    for seq in 3..7 {
+        fetched.extend(chunk(&client.recv().await.expect("chunk"), seq));
+    }
+    assert_eq!(
+        client.recv().await.expect("segment_end")["cmd"],
+        "segment_end"
+    );
+    assert_eq!(fetched, media);
+
+    let beyond = client
+        .request(&json!({
+            "cmd": "get_segment",
+            "sourceId": SOURCE_ID,
+            "name": name,
+            "resumeFromSeq": 8,
+        }))
+        .await
+        .expect("get_segment");
+    assert_eq!(beyond["field"], "resumeFromSeq");
+}
+
+async fn wait_for_encrypted_segments(
+    client: &mut common::SessionClient,
+    min: usize,
+    limit: Duration,
+) -> Vec<Value> {
+    let deadline = tokio::time::Instant::now() + limit;
+    loop {
+        let listed = client
+            .request(&json!({ "cmd": "list_segments", "sourceId": SOURCE_ID, "limit": 50 }))
+            .await
+            .expect("list_segments");
+        let encrypted = listed
+            .get("segments")
+            .and_then(Value::as_array)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter(|item| {
+                        item.get("name")
+                            .and_then(Value::as_str)
+                            .is_some_and(|name| name.ends_with(".cnv"))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if encrypted.len() >= min {
+            return encrypted;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "timed out waiting for encrypted segments: {listed}"
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}